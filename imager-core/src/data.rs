@@ -0,0 +1,698 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+/// `WEBP_MAX_DIMENSION` of libwebp.
+pub const WEBP_MAX_DIMENSION: u32 = 16383;
+
+///////////////////////////////////////////////////////////////////////////////
+// OUTPUT-FORMAT
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Jpeg,
+    Png,
+    Webp,
+    /// Lossless (Deflate), or CCITT G4 for bilevel outputs.
+    Tiff,
+    /// AV1 in HEIF.
+    Avif,
+    /// JPEG XL.
+    Jxl,
+}
+
+impl OutputFormat {
+    /// The largest width or height the encoder accepts.
+    pub fn max_dimension(&self) -> u32 {
+        match self {
+            Self::Jpeg => 65_500,
+            Self::Png => i32::MAX as u32,
+            Self::Webp => WEBP_MAX_DIMENSION - 1,
+            Self::Tiff => u16::MAX as u32,
+            // OF AV1 FRAMES
+            Self::Avif => 65_536,
+            Self::Jxl => 1_073_741_823,
+        }
+    }
+    /// Of output file names, e.g. `jpeg`.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpeg",
+            Self::Png => "png",
+            Self::Webp => "webp",
+            Self::Tiff => "tiff",
+            Self::Avif => "avif",
+            Self::Jxl => "jxl",
+        }
+    }
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+            Self::Webp => "image/webp",
+            Self::Tiff => "image/tiff",
+            Self::Avif => "image/avif",
+            Self::Jxl => "image/jxl",
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "jpeg" => Ok(Self::Jpeg),
+            "jpg" => Ok(Self::Jpeg),
+            "png" => Ok(Self::Png),
+            "webp" => Ok(Self::Webp),
+            "tiff" | "tif" => Ok(Self::Tiff),
+            "avif" => Ok(Self::Avif),
+            "jxl" => Ok(Self::Jxl),
+            _ => Err(format!("Unknown or unsupported output format {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputFormats(pub Vec<OutputFormat>);
+
+impl Default for OutputFormats {
+    fn default() -> Self {
+        OutputFormats(vec![OutputFormat::Jpeg, OutputFormat::Webp])
+    }
+}
+
+impl FromStr for OutputFormats {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut invalids = Vec::new();
+        let results = s
+            .split_whitespace()
+            .filter_map(|x| match OutputFormat::from_str(x) {
+                Ok(x) => Some(x),
+                Err(e) => {
+                    invalids.push(e);
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        if invalids.is_empty() {
+            Ok(Self(results))
+        } else {
+            Err(invalids.join(", "))
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// RESOLUTION
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Eq)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Resolution {
+    #[must_use] pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height }
+    }
+}
+
+impl core::fmt::Display for Resolution {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+impl FromStr for Resolution {
+    type Err = String;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let ix = input.find("x").ok_or("invalid")?;
+        let (width, height) = input.split_at(ix);
+        let height = height.trim_start_matches("x");
+        let width = u32::from_str(width).map_err(|_| "invalid")?;
+        let height = u32::from_str(height).map_err(|_| "invalid")?;
+        Ok(Self { width, height })
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// UPSCALING
+///////////////////////////////////////////////////////////////////////////////
+
+/// Sources are never enlarged by more than this factor (per side).
+pub const MAX_UPSCALE: u32 = 4;
+
+/// How sources smaller than the `max_size` are enlarged, where upscaling
+/// is allowed.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Upscaler {
+    #[default]
+    Lanczos,
+    /// Real-ESRGAN (via the `realesrgan-ncnn-vulkan` executable); requires
+    /// the `esrgan` feature of imager.
+    Esrgan,
+}
+
+impl FromStr for Upscaler {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "lanczos" => Ok(Self::Lanczos),
+            "esrgan" => Ok(Self::Esrgan),
+            _ => Err(format!("Unknown upscaler {}", s)),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// RESIZING
+///////////////////////////////////////////////////////////////////////////////
+
+/// How sources are resized to the `max_size` (the box), as in sharp.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Fit {
+    /// Keep the aspect ratio, padding (centered) to the box.
+    Contain,
+    /// Keep the aspect ratio, cropping (centered) to the box.
+    Cover,
+    /// Stretch to the box.
+    Fill,
+    /// Keep the aspect ratio, as large as fits in the box.
+    #[default]
+    Inside,
+    /// Keep the aspect ratio, as small as covers the box.
+    Outside,
+}
+
+impl FromStr for Fit {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "contain" => Ok(Self::Contain),
+            "cover" => Ok(Self::Cover),
+            "fill" => Ok(Self::Fill),
+            "inside" => Ok(Self::Inside),
+            "outside" => Ok(Self::Outside),
+            _ => Err(format!("Unknown fit {}", s)),
+        }
+    }
+}
+
+impl core::fmt::Display for Fit {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Contain => write!(f, "contain"),
+            Self::Cover => write!(f, "cover"),
+            Self::Fill => write!(f, "fill"),
+            Self::Inside => write!(f, "inside"),
+            Self::Outside => write!(f, "outside"),
+        }
+    }
+}
+
+/// Where `cover` resizes crop (see `imager::crop`).
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Crop {
+    #[default]
+    Centre,
+    /// Keep the busiest (highest entropy) part.
+    Entropy,
+    /// Keep the most salient part: detail, skin tones and saturated colors.
+    Attention,
+}
+
+impl FromStr for Crop {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "centre" | "center" => Ok(Self::Centre),
+            "entropy" => Ok(Self::Entropy),
+            "attention" => Ok(Self::Attention),
+            _ => Err(format!("Unknown crop {}", s)),
+        }
+    }
+}
+
+impl core::fmt::Display for Crop {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Centre => write!(f, "centre"),
+            Self::Entropy => write!(f, "entropy"),
+            Self::Attention => write!(f, "attention"),
+        }
+    }
+}
+
+/// The resampling filter of resizes.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ResizeFilter {
+    /// The sharpest, at the cost of ringing around hard edges.
+    #[default]
+    Lanczos3,
+    /// Bicubic: a little softer than Lanczos3, with less ringing.
+    CatmullRom,
+    /// Bilinear: the softest, and fastest.
+    Triangle,
+}
+
+impl FromStr for ResizeFilter {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "lanczos3" => Ok(Self::Lanczos3),
+            "catmull-rom" | "catmullrom" => Ok(Self::CatmullRom),
+            "triangle" => Ok(Self::Triangle),
+            _ => Err(format!("Unknown resize filter {}", s)),
+        }
+    }
+}
+
+impl core::fmt::Display for ResizeFilter {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Lanczos3 => write!(f, "Lanczos3"),
+            Self::CatmullRom => write!(f, "Catmull-Rom"),
+            Self::Triangle => write!(f, "triangle"),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// COLOR MODE
+///////////////////////////////////////////////////////////////////////////////
+
+/// Grayscale and bilevel (1 bit) outputs, e.g. for document scans: PNG and
+/// TIFF store them at 8 and 1 bits per pixel; JPEG and WebP only in 8 bit
+/// RGB (grayscale only).
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ColorMode {
+    #[default]
+    Color,
+    Grayscale,
+    /// Black and white, split at the `Threshold`.
+    Bilevel,
+}
+
+impl FromStr for ColorMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "color" => Ok(Self::Color),
+            "grayscale" | "gray" => Ok(Self::Grayscale),
+            "bilevel" => Ok(Self::Bilevel),
+            _ => Err(format!("Unknown color mode {}", s)),
+        }
+    }
+}
+
+/// The luma at and above which bilevel pixels are white.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Threshold {
+    /// Per image, by Otsu’s method (the split that best separates the
+    /// histogram into two classes); suits scans with uneven exposure.
+    #[default]
+    Otsu,
+    Fixed(u8),
+}
+
+impl FromStr for Threshold {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "otsu" => Ok(Self::Otsu),
+            x => x.parse::<u8>().map(Self::Fixed).map_err(|_| format!("Unknown threshold {}", s)),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// OUTPUT-SIZE
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum OutputSize {
+    /// Output image resolution. Akin to the 'px' CSS unit.
+    Px(Resolution),
+    /// Retain the original resolution. Akin to the '100%' CSS value.
+    #[default]
+    Full,
+}
+
+impl core::fmt::Display for OutputSize {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Px(px) => write!(f, "{}", px),
+            Self::Full => write!(f, "full"),
+        }
+    }
+}
+
+impl FromStr for OutputSize {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "full" { Ok(Self::Full) } else {
+            let val: Resolution = Resolution::from_str(s)?;
+            Ok(Self::Px(val))
+        }
+    }
+}
+
+impl Serialize for OutputSize {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for OutputSize {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// BRAND PALETTE
+///////////////////////////////////////////////////////////////////////////////
+
+/// A fixed set of RGBA colors (e.g. design-system tokens) to snap outputs
+/// to, written as `#RRGGBB` or `#RRGGBBAA` (in JSON, a list of these).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BrandPalette(pub Vec<[u8; 4]>);
+
+fn parse_hex_color(s: &str) -> Result<[u8; 4], String> {
+    let hex = s.trim_start_matches('#');
+    let channel = |ix: usize| {
+        hex.get(ix * 2..ix * 2 + 2)
+            .and_then(|x| u8::from_str_radix(x, 16).ok())
+            .ok_or_else(|| format!("Invalid color {}", s))
+    };
+    match hex.len() {
+        6 => Ok([channel(0)?, channel(1)?, channel(2)?, 255]),
+        8 => Ok([channel(0)?, channel(1)?, channel(2)?, channel(3)?]),
+        _ => Err(format!("Invalid color {}", s)),
+    }
+}
+
+fn hex_color([r, g, b, a]: [u8; 4]) -> String {
+    if a == 255 {
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    } else {
+        format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+    }
+}
+
+impl FromStr for BrandPalette {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let colors = s
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|x| !x.is_empty())
+            .map(parse_hex_color)
+            .collect::<Result<Vec<_>, _>>()?;
+        if colors.is_empty() || colors.len() > 256 {
+            return Err(String::from("A palette needs 1 to 256 colors"));
+        }
+        Ok(BrandPalette(colors))
+    }
+}
+
+impl Serialize for BrandPalette {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_seq(self.0.iter().map(|x| hex_color(*x)))
+    }
+}
+
+impl<'de> Deserialize<'de> for BrandPalette {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let colors = Vec::<String>::deserialize(deserializer)?;
+        colors
+            .iter()
+            .map(|x| parse_hex_color(x))
+            .collect::<Result<Vec<_>, _>>()
+            .map(BrandPalette)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// MATTE
+///////////////////////////////////////////////////////////////////////////////
+
+/// An opaque color to composite transparency onto, written as `#RRGGBB`;
+/// by default, white.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Matte(pub [u8; 3]);
+
+impl Default for Matte {
+    fn default() -> Self {
+        Matte([255, 255, 255])
+    }
+}
+
+impl core::fmt::Display for Matte {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let [r, g, b] = self.0;
+        write!(f, "{}", hex_color([r, g, b, 255]))
+    }
+}
+
+impl FromStr for Matte {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match parse_hex_color(s)? {
+            [r, g, b, 255] if s.trim_start_matches('#').len() == 6 => Ok(Matte([r, g, b])),
+            _ => Err(format!("A matte is an opaque color, not {}", s)),
+        }
+    }
+}
+
+impl Serialize for Matte {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Matte {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// SEED
+///////////////////////////////////////////////////////////////////////////////
+
+/// Seeds every stage that uses randomness, so a job with the same inputs,
+/// options and seed always produces the same outputs (e.g. for tests and
+/// caching). Each stage draws from its own stream (see `Seed::stage`), so
+/// adding randomness to one stage doesn’t shift another’s.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Seed(pub u64);
+
+impl Seed {
+    /// The seed of the named stage’s stream; FNV-1a of the name, mixed
+    /// into the job seed (i.e. stable across builds and platforms).
+    pub fn stage(&self, name: &str) -> u64 {
+        let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        });
+        self.0 ^ hash
+    }
+}
+
+impl FromStr for Seed {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim()
+            .parse::<u64>()
+            .map(Seed)
+            .map_err(|_| format!("Invalid seed {}", s))
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// TUNING
+///////////////////////////////////////////////////////////////////////////////
+
+/// The qualities a quality search may pick (inclusive); sources that pass
+/// below the `min` are encoded at the `min`, and those that never pass at
+/// the `max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QualityRange {
+    pub min: u8,
+    pub max: u8,
+}
+
+impl QualityRange {
+    pub fn clamp(&self, quality: u8) -> u8 {
+        quality.clamp(self.min, self.max)
+    }
+}
+
+/// Per format VMAF targets (0 to 100), in place of the class (and size)
+/// dependent thresholds of the VMAF searches, e.g. a lower one for AVIF
+/// than for WebP, since AVIF degrades more gracefully. Written as
+/// `webp=92,avif=90`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VmafTargets {
+    pub jpeg: Option<f64>,
+    pub webp: Option<f64>,
+    pub avif: Option<f64>,
+}
+
+impl VmafTargets {
+    /// Of the format, if it has a VMAF search.
+    pub fn get(&self, format: &OutputFormat) -> Option<f64> {
+        match format {
+            OutputFormat::Jpeg => self.jpeg,
+            OutputFormat::Webp => self.webp,
+            OutputFormat::Avif => self.avif,
+            _ => None,
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.jpeg.is_none() && self.webp.is_none() && self.avif.is_none()
+    }
+}
+
+impl FromStr for VmafTargets {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut targets = VmafTargets::default();
+        for pair in s.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let (format, target) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid VMAF target {}; write it as FORMAT=SCORE", pair))?;
+            let target = target
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| format!("Invalid VMAF score {}", target))?;
+            match format.trim().parse::<OutputFormat>()? {
+                OutputFormat::Jpeg => targets.jpeg = Some(target),
+                OutputFormat::Webp => targets.webp = Some(target),
+                OutputFormat::Avif => targets.avif = Some(target),
+                format => return Err(format!("{:?} has no VMAF search", format)),
+            }
+        }
+        Ok(targets)
+    }
+}
+
+/// The chroma resolution of JPEG outputs: 4:2:0 halves it both ways, which
+/// suits photos; 4:4:4 keeps it, for sharp colored edges (e.g. of
+/// graphics), at higher qualities’ sizes.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Subsampling {
+    #[default]
+    Yuv420,
+    Yuv444,
+}
+
+impl FromStr for Subsampling {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "4:2:0" | "420" | "yuv420" => Ok(Self::Yuv420),
+            "4:4:4" | "444" | "yuv444" => Ok(Self::Yuv444),
+            _ => Err(format!("Unknown chroma subsampling {}", s)),
+        }
+    }
+}
+
+/// libwebp’s highest effort (`method`).
+pub const MAX_WEBP_EFFORT: u8 = 6;
+
+/// Encoder settings tailored to a site’s content (see `imager tune`); the
+/// defaults are the full ranges of the searches, 4:2:0 JPEG outputs and
+/// libwebp’s highest effort.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tuning {
+    pub jpeg: QualityRange,
+    pub webp: QualityRange,
+    pub avif: QualityRange,
+    /// Of the VMAF searches’ outputs, as the `webp_effort` is; those of a
+    /// `max_distance` encode at the defaults.
+    pub jpeg_subsampling: Subsampling,
+    /// libwebp’s `method` (0 to `MAX_WEBP_EFFORT`): lower efforts encode
+    /// faster, and larger.
+    pub webp_effort: u8,
+    /// The max butteraugli distance of lossy outputs (about 1 where the
+    /// differences start to be noticeable), in place of the VMAF searches:
+    /// every format gets the lowest quality (within its range) whose output
+    /// is within it, else the top of its range (with a `DistanceExceeded`
+    /// warning). JPEG XL searches its full range.
+    pub max_distance: Option<f64>,
+    /// Per format targets of the VMAF searches (see `VmafTargets`).
+    pub vmaf: VmafTargets,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Tuning {
+            jpeg: QualityRange { min: 0, max: 98 },
+            webp: QualityRange { min: 0, max: 100 },
+            avif: QualityRange { min: 0, max: 100 },
+            jpeg_subsampling: Subsampling::default(),
+            webp_effort: MAX_WEBP_EFFORT,
+            max_distance: None,
+            vmaf: VmafTargets::default(),
+        }
+    }
+}
+
+impl Tuning {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, range) in [("jpeg", self.jpeg), ("webp", self.webp), ("avif", self.avif)] {
+            if range.min > range.max || range.max > 100 {
+                return Err(format!("invalid {} quality range {}-{}", name, range.min, range.max));
+            }
+        }
+        for (name, target) in [("jpeg", self.vmaf.jpeg), ("webp", self.vmaf.webp), ("avif", self.vmaf.avif)] {
+            match target {
+                Some(x) if !(0.0..=100.0).contains(&x) => return Err(format!("invalid {} VMAF target {}", name, x)),
+                _ => (),
+            }
+        }
+        if self.webp_effort > MAX_WEBP_EFFORT {
+            return Err(format!("invalid WebP effort {}", self.webp_effort));
+        }
+        match self.max_distance {
+            Some(x) if !(x.is_finite() && x > 0.0) => Err(format!("invalid max distance {}", x)),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+use crate::data::{Fit, Resolution};
+
+///////////////////////////////////////////////////////////////////////////////
+// DECODERS
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Decoder {
+    /// Imager’s standard decoders: the `image` crate, libwebp for WebP, and
+    /// libjpeg DCT scaling for downscaled JPEGs. Rejects damaged files.
+    Image,
+    /// libjpeg(-turbo) for JPEGs, which recovers from damage `image` can’t.
+    Turbo,
+    /// An external `ffmpeg` executable (must be on the `PATH`).
+    Ffmpeg,
+}
+
+impl FromStr for Decoder {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "image" => Ok(Self::Image),
+            "turbo" => Ok(Self::Turbo),
+            "ffmpeg" => Ok(Self::Ffmpeg),
+            _ => Err(format!("Unknown decoder {}", s)),
+        }
+    }
+}
+
+impl core::fmt::Display for Decoder {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Image => write!(f, "image"),
+            Self::Turbo => write!(f, "turbo"),
+            Self::Ffmpeg => write!(f, "ffmpeg"),
+        }
+    }
+}
+
+/// Decoders to try in order, until one succeeds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecoderChain(pub Vec<Decoder>);
+
+impl Default for DecoderChain {
+    fn default() -> Self {
+        DecoderChain(vec![Decoder::Image, Decoder::Turbo, Decoder::Ffmpeg])
+    }
+}
+
+impl FromStr for DecoderChain {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut invalids = Vec::new();
+        let results = s
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|x| !x.is_empty())
+            .filter_map(|x| match Decoder::from_str(x) {
+                Ok(x) => Some(x),
+                Err(e) => {
+                    invalids.push(e);
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        if invalids.is_empty() {
+            Ok(Self(results))
+        } else {
+            Err(invalids.join(", "))
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// OPTIONS
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    pub chain: DecoderChain,
+    /// Accept truncated or partially corrupt files (where the decoder
+    /// supports it) instead of moving on to the next decoder.
+    pub tolerate_truncated: bool,
+    /// Downscale while decoding when the source is considerably larger.
+    pub max_size: Option<Resolution>,
+    /// How the source will be fit to the `max_size`, i.e. how far it can be
+    /// downscaled while decoding.
+    pub fit: Fit,
+    /// Apply the source’s EXIF orientation to the pixels, rather than
+    /// carrying the tag into outputs.
+    pub auto_orient: bool,
+}
@@ -0,0 +1,37 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+use alloc::format;
+use alloc::string::String;
+use core::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+/// Files at or above this size are memory-mapped in `ReadMode::Auto`.
+pub const MMAP_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// How input files are brought into memory.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ReadMode {
+    /// Memory-map large files, read small files into the heap.
+    #[default]
+    Auto,
+    /// Always memory-map (falls back to the heap if mapping fails).
+    Mmap,
+    /// Always read into a heap buffer.
+    ///
+    /// Use this for network filesystems, or anywhere the file may be
+    /// truncated while imager is still reading it.
+    Heap,
+}
+
+impl FromStr for ReadMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "mmap" => Ok(Self::Mmap),
+            "heap" => Ok(Self::Heap),
+            _ => Err(format!("Unknown read mode {}", s)),
+        }
+    }
+}
@@ -0,0 +1,23 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! The pure parts of imager: parsers for the CLI/config types, job
+//! profiles and report types. Builds without `std` (but with `alloc`),
+//! so configs can be validated in constrained environments before a job
+//! is ever submitted.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod data;
+pub mod decode;
+pub mod digest;
+pub mod input;
+pub mod meta;
+pub mod pipeline;
+pub mod profile;
+pub mod report;
+pub mod sharp;
+pub mod validate;
+pub mod vp8;
+pub mod vp8l;
@@ -0,0 +1,230 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Which source metadata may be carried into outputs.
+use alloc::format;
+use alloc::string::String;
+use core::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+///////////////////////////////////////////////////////////////////////////////
+// EXIF TAGS
+///////////////////////////////////////////////////////////////////////////////
+
+/// The EXIF directories a tag may belong to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExifIfd {
+    /// IFD0, i.e. the TIFF tags of the main image.
+    Primary,
+    /// The Exif sub-IFD.
+    Exif,
+}
+
+// (IFD, TAG) ALLOW-LISTS, PER GROUP
+const BASIC_TAGS: &[(ExifIfd, u16)] = &[
+    (ExifIfd::Primary, 0x0112), // Orientation
+    (ExifIfd::Primary, 0x011A), // XResolution
+    (ExifIfd::Primary, 0x011B), // YResolution
+    (ExifIfd::Primary, 0x0128), // ResolutionUnit
+    (ExifIfd::Exif, 0x9000),    // ExifVersion
+    (ExifIfd::Exif, 0xA001),    // ColorSpace
+];
+const CAMERA_TAGS: &[(ExifIfd, u16)] = &[
+    (ExifIfd::Primary, 0x010F), // Make
+    (ExifIfd::Primary, 0x0110), // Model
+    (ExifIfd::Exif, 0xA432),    // LensSpecification
+    (ExifIfd::Exif, 0xA433),    // LensMake
+    (ExifIfd::Exif, 0xA434),    // LensModel
+];
+const EXPOSURE_TAGS: &[(ExifIfd, u16)] = &[
+    (ExifIfd::Exif, 0x829A), // ExposureTime
+    (ExifIfd::Exif, 0x829D), // FNumber
+    (ExifIfd::Exif, 0x8822), // ExposureProgram
+    (ExifIfd::Exif, 0x8827), // PhotographicSensitivity (ISO)
+    (ExifIfd::Exif, 0x8830), // SensitivityType
+    (ExifIfd::Exif, 0x9201), // ShutterSpeedValue
+    (ExifIfd::Exif, 0x9202), // ApertureValue
+    (ExifIfd::Exif, 0x9204), // ExposureBiasValue
+    (ExifIfd::Exif, 0x9205), // MaxApertureValue
+    (ExifIfd::Exif, 0x9207), // MeteringMode
+    (ExifIfd::Exif, 0x9209), // Flash
+    (ExifIfd::Exif, 0x920A), // FocalLength
+    (ExifIfd::Exif, 0xA402), // ExposureMode
+    (ExifIfd::Exif, 0xA403), // WhiteBalance
+    (ExifIfd::Exif, 0xA405), // FocalLengthIn35mmFilm
+    (ExifIfd::Exif, 0xA406), // SceneCaptureType
+];
+const CAPTURE_TIME_TAGS: &[(ExifIfd, u16)] = &[
+    (ExifIfd::Primary, 0x0132), // DateTime
+    (ExifIfd::Exif, 0x9003),    // DateTimeOriginal
+    (ExifIfd::Exif, 0x9004),    // DateTimeDigitized
+    (ExifIfd::Exif, 0x9010),    // OffsetTime
+    (ExifIfd::Exif, 0x9011),    // OffsetTimeOriginal
+    (ExifIfd::Exif, 0x9012),    // OffsetTimeDigitized
+    (ExifIfd::Exif, 0x9290),    // SubSecTime
+    (ExifIfd::Exif, 0x9291),    // SubSecTimeOriginal
+    (ExifIfd::Exif, 0x9292),    // SubSecTimeDigitized
+];
+const COPYRIGHT_TAGS: &[(ExifIfd, u16)] = &[
+    (ExifIfd::Primary, 0x013B), // Artist
+    (ExifIfd::Primary, 0x8298), // Copyright
+];
+
+///////////////////////////////////////////////////////////////////////////////
+// PRIVACY POLICY
+///////////////////////////////////////////////////////////////////////////////
+
+/// What metadata (EXIF tags, the ICC profile and the XMP packet) may be
+/// copied from the source into outputs.
+///
+/// This is an allow-list: GPS data, serial numbers, owner names, unique
+/// image IDs, maker notes and any tag not listed in one of the groups
+/// below are dropped under every policy. The default strips everything,
+/// but a non-default EXIF orientation (which the pixels don’t have
+/// applied, unless auto-oriented, so outputs would display rotated without
+/// it).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrivacyPolicy {
+    /// Keep EXIF at all: orientation, resolution and color space.
+    pub keep_exif: bool,
+    /// Camera and lens make and model.
+    pub keep_camera: bool,
+    /// Exposure settings (shutter, aperture, ISO, focal length, ...).
+    pub keep_exposure: bool,
+    /// Capture and modification timestamps.
+    pub keep_capture_time: bool,
+    /// Artist and copyright.
+    pub keep_copyright: bool,
+    /// The ICC profile, so the colors of wide gamut sources stay right.
+    pub keep_icc: bool,
+    /// The XMP packet, as is (so with whatever it carries, e.g. GPS data),
+    /// unless an attribution replaces it.
+    pub keep_xmp: bool,
+}
+
+impl PrivacyPolicy {
+    pub fn allows(&self, ifd: ExifIfd, tag: u16) -> bool {
+        if !self.keep_exif {
+            return false;
+        }
+        let groups = [
+            (true, BASIC_TAGS),
+            (self.keep_camera, CAMERA_TAGS),
+            (self.keep_exposure, EXPOSURE_TAGS),
+            (self.keep_capture_time, CAPTURE_TIME_TAGS),
+            (self.keep_copyright, COPYRIGHT_TAGS),
+        ];
+        groups
+            .iter()
+            .filter(|(enabled, _)| *enabled)
+            .any(|(_, tags)| tags.contains(&(ifd, tag)))
+    }
+}
+
+/// `strip`, or a list of groups to keep: `basic`, `camera`, `exposure`,
+/// `capture-time` and `copyright` (any of which implies `basic`) of the
+/// EXIF, `icc` and `xmp`.
+impl FromStr for PrivacyPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policy = PrivacyPolicy::default();
+        let groups = s
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|x| !x.is_empty());
+        for group in groups {
+            match group.to_lowercase().as_str() {
+                "strip" | "none" => {}
+                "basic" => policy.keep_exif = true,
+                "camera" => policy.keep_camera = true,
+                "exposure" => policy.keep_exposure = true,
+                "capture-time" => policy.keep_capture_time = true,
+                "copyright" => policy.keep_copyright = true,
+                "icc" => policy.keep_icc = true,
+                "xmp" => policy.keep_xmp = true,
+                _ => return Err(format!("Unknown metadata group {}", group)),
+            }
+        }
+        policy.keep_exif |= policy.keep_camera
+            || policy.keep_exposure
+            || policy.keep_capture_time
+            || policy.keep_copyright;
+        Ok(policy)
+    }
+}
+
+/// The `FromStr` form, e.g. `basic,camera`.
+impl core::fmt::Display for PrivacyPolicy {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if !(self.keep_exif || self.keep_icc || self.keep_xmp) {
+            return write!(f, "strip");
+        }
+        let groups = [
+            (self.keep_exif, "basic"),
+            (self.keep_camera, "camera"),
+            (self.keep_exposure, "exposure"),
+            (self.keep_capture_time, "capture-time"),
+            (self.keep_copyright, "copyright"),
+            (self.keep_icc, "icc"),
+            (self.keep_xmp, "xmp"),
+        ];
+        let mut first = true;
+        for (_, name) in groups.iter().filter(|(enabled, _)| *enabled) {
+            if !first {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", name)?;
+            first = false;
+        }
+        Ok(())
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ATTRIBUTION
+///////////////////////////////////////////////////////////////////////////////
+
+/// Attribution written into every output (as XMP, using the IPTC Core
+/// properties), whether or not the source carried any.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Attribution {
+    /// The creator, i.e. `dc:creator`.
+    pub creator: Option<String>,
+    /// The copyright notice, i.e. `dc:rights`.
+    pub copyright: Option<String>,
+    /// The license, i.e. `xmpRights:WebStatement` and `cc:license`.
+    pub license_url: Option<String>,
+}
+
+impl Attribution {
+    pub fn is_empty(&self) -> bool {
+        self.creator.is_none() && self.copyright.is_none() && self.license_url.is_none()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_privacy_policy() {
+        let policy = PrivacyPolicy::from_str("camera").expect("parse policy");
+        assert!(policy.allows(ExifIfd::Primary, 0x0110));
+        assert!(policy.allows(ExifIfd::Primary, 0x0112));
+        assert!(!policy.allows(ExifIfd::Exif, 0x829A));
+        // SERIAL NUMBERS: NEVER
+        let everything = PrivacyPolicy::from_str("camera exposure capture-time copyright")
+            .expect("parse policy");
+        assert!(!everything.allows(ExifIfd::Exif, 0xA431));
+        assert!(!everything.allows(ExifIfd::Exif, 0xA435));
+        assert!(!PrivacyPolicy::default().allows(ExifIfd::Primary, 0x0112));
+        assert_eq!(policy.to_string(), "basic,camera");
+        assert_eq!(PrivacyPolicy::default().to_string(), "strip");
+        // ICC AND XMP DON’T IMPLY EXIF
+        let icc = PrivacyPolicy::from_str("icc").expect("parse policy");
+        assert!(icc.keep_icc && !icc.keep_exif && !icc.allows(ExifIfd::Primary, 0x0112));
+        assert_eq!(PrivacyPolicy::from_str("xmp,copyright").expect("parse policy").to_string(), "basic,copyright,xmp");
+    }
+}
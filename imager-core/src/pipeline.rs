@@ -0,0 +1,334 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Pipelines as data: a whole job (stages and their options) as JSON,
+//! e.g. for web UIs or config services that construct jobs declaratively.
+//!
+//! Stages run in imager’s fixed order (that of `Stage::ORDER`); a
+//! pipeline lists the ones it uses, in that order, each at most once.
+//! Only `encode` is required. See `schemas/pipeline.v1.json`; the
+//! versioning rules are those of `crate::report`.
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::data::{
+    BrandPalette, ColorMode, Crop, Fit, OutputFormat, ResizeFilter, Resolution, Seed, Threshold, Tuning, Upscaler,
+};
+use crate::decode::{DecodeOptions, Decoder, DecoderChain};
+use crate::meta::{Attribution, PrivacyPolicy};
+use crate::report::{check_schema_version, unversioned_schema};
+use crate::validate::Options;
+
+pub const PIPELINE_SCHEMA_VERSION: u32 = 1;
+
+/// The JSON Schema (draft 7) of the current pipeline version.
+pub const PIPELINE_JSON_SCHEMA: &str = include_str!("../schemas/pipeline.v1.json");
+
+fn default_decoders() -> Vec<Decoder> {
+    DecoderChain::default().0
+}
+
+fn yes() -> bool {
+    true
+}
+
+/// Omitted, so pipelines (and the cache validators of their outputs) are
+/// unchanged by options at their defaults.
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "stage", rename_all = "kebab-case")]
+pub enum Stage {
+    Decode {
+        #[serde(default = "default_decoders")]
+        decoders: Vec<Decoder>,
+        #[serde(default)]
+        tolerate_truncated: bool,
+        /// Apply the EXIF orientation to the pixels.
+        #[serde(default)]
+        auto_orient: bool,
+    },
+    /// Fit to `max_size` (within it, by default); sources are only
+    /// enlarged given an `upscaler`.
+    Resize {
+        max_size: Resolution,
+        #[serde(default)]
+        upscaler: Option<Upscaler>,
+        #[serde(default, skip_serializing_if = "is_default")]
+        fit: Fit,
+        #[serde(default, skip_serializing_if = "is_default")]
+        filter: ResizeFilter,
+        /// Of `cover` fits.
+        #[serde(default, skip_serializing_if = "is_default")]
+        crop: Crop,
+    },
+    /// `model` is a path, as for `--remove-background`.
+    RemoveBackground { model: String },
+    Watermark { id: u32 },
+    Palette {
+        palette: BrandPalette,
+        #[serde(default = "yes")]
+        dither: bool,
+    },
+    Encode {
+        format: OutputFormat,
+        /// The formats to try in order when the `format`’s encoder fails.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        fallbacks: Vec<OutputFormat>,
+        #[serde(default)]
+        extreme: bool,
+        #[serde(default)]
+        color_mode: ColorMode,
+        #[serde(default)]
+        threshold: Threshold,
+        /// See `OptProfile::text_protect`.
+        #[serde(default)]
+        text_protect: bool,
+        #[serde(default)]
+        tuning: Tuning,
+    },
+    Metadata {
+        #[serde(default)]
+        privacy: PrivacyPolicy,
+        #[serde(default)]
+        attribution: Attribution,
+        /// Embed a thumbnail of the output in its EXIF (JPEG outputs only).
+        #[serde(default)]
+        exif_thumbnail: bool,
+    },
+}
+
+impl Stage {
+    /// Every stage name, in the order stages run.
+    pub const ORDER: [&'static str; 7] = [
+        "decode",
+        "resize",
+        "remove-background",
+        "watermark",
+        "palette",
+        "encode",
+        "metadata",
+    ];
+    /// The (JSON) name of the stage.
+    pub fn name(&self) -> &'static str {
+        Self::ORDER[self.position()]
+    }
+    fn position(&self) -> usize {
+        match self {
+            Stage::Decode { .. } => 0,
+            Stage::Resize { .. } => 1,
+            Stage::RemoveBackground { .. } => 2,
+            Stage::Watermark { .. } => 3,
+            Stage::Palette { .. } => 4,
+            Stage::Encode { .. } => 5,
+            Stage::Metadata { .. } => 6,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pipeline {
+    #[serde(default = "unversioned_schema")]
+    pub schema_version: u32,
+    /// Seeds stages that use randomness, for reproducible outputs.
+    #[serde(default)]
+    pub seed: Seed,
+    pub stages: Vec<Stage>,
+}
+
+impl Pipeline {
+    pub fn new(stages: Vec<Stage>) -> Self {
+        Pipeline {
+            schema_version: PIPELINE_SCHEMA_VERSION,
+            seed: Seed::default(),
+            stages,
+        }
+    }
+    /// Parses and validates a pipeline.
+    pub fn from_json(source: &str) -> Result<Self, String> {
+        let value: serde_json::Value =
+            serde_json::from_str(source).map_err(|e| e.to_string())?;
+        check_schema_version(&value, PIPELINE_SCHEMA_VERSION)?;
+        let pipeline: Pipeline = serde_json::from_value(value).map_err(|e| e.to_string())?;
+        pipeline.validate()?;
+        Ok(pipeline)
+    }
+    #[cfg(feature = "std")]
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Pipeline::from_json(&source)
+    }
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("pipelines are serializable")
+    }
+    /// Rejects pipelines that would fail every job, or that imager
+    /// can’t run as given (i.e. out of order).
+    pub fn validate(&self) -> Result<(), String> {
+        for pair in self.stages.windows(2) {
+            if pair[0].position() >= pair[1].position() {
+                return Err(format!(
+                    "stage {} can’t follow {} (stages run in the order {})",
+                    pair[1].name(),
+                    pair[0].name(),
+                    Stage::ORDER.join(", ")
+                ));
+            }
+        }
+        let format = self.format().ok_or("a pipeline needs an encode stage")?;
+        let default_tuning = Tuning::default();
+        let mut options = Options {
+            formats: core::slice::from_ref(format),
+            max_size: None,
+            allow_upscale: false,
+            upscaler: Upscaler::default(),
+            remove_background: false,
+            palette: false,
+            color_mode: ColorMode::default(),
+            exif_thumbnail: false,
+            text_protect: false,
+            tuning: &default_tuning,
+        };
+        for stage in &self.stages {
+            match stage {
+                Stage::Decode { decoders, .. } if decoders.is_empty() => {
+                    return Err(String::from("no decoders given"));
+                }
+                Stage::Resize { max_size, upscaler, .. } => {
+                    options.max_size = Some(max_size);
+                    options.allow_upscale = upscaler.is_some();
+                    options.upscaler = upscaler.unwrap_or_default();
+                }
+                Stage::Palette { .. } => options.palette = true,
+                Stage::Encode { fallbacks, .. } if fallbacks.contains(format) => {
+                    return Err(format!("{:?} can’t be its own fallback", format));
+                }
+                Stage::Encode {
+                    color_mode,
+                    text_protect,
+                    tuning,
+                    ..
+                } => {
+                    options.color_mode = *color_mode;
+                    options.text_protect = *text_protect;
+                    options.tuning = tuning;
+                }
+                Stage::RemoveBackground { model } if model.is_empty() => {
+                    return Err(String::from("no background removal model given"));
+                }
+                Stage::RemoveBackground { .. } => options.remove_background = true,
+                Stage::Metadata { exif_thumbnail, .. } => options.exif_thumbnail = *exif_thumbnail,
+                _ => (),
+            }
+        }
+        options.validate()
+    }
+    pub fn stage(&self, name: &str) -> Option<&Stage> {
+        self.stages.iter().find(|x| x.name() == name)
+    }
+    /// The format of the `encode` stage.
+    pub fn format(&self) -> Option<&OutputFormat> {
+        self.stages.iter().find_map(|x| match x {
+            Stage::Encode { format, .. } => Some(format),
+            _ => None,
+        })
+    }
+    /// The fallback formats of the `encode` stage, in order.
+    pub fn fallbacks(&self) -> &[OutputFormat] {
+        self.stages
+            .iter()
+            .find_map(|x| match x {
+                Stage::Encode { fallbacks, .. } => Some(fallbacks.as_slice()),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+    pub fn max_size(&self) -> Option<&Resolution> {
+        self.stages.iter().find_map(|x| match x {
+            Stage::Resize { max_size, .. } => Some(max_size),
+            _ => None,
+        })
+    }
+    pub fn decode_options(&self) -> DecodeOptions {
+        let (chain, tolerate_truncated, auto_orient) = match self.stage("decode") {
+            Some(Stage::Decode {
+                decoders,
+                tolerate_truncated,
+                auto_orient,
+            }) => (DecoderChain(decoders.clone()), *tolerate_truncated, *auto_orient),
+            _ => (DecoderChain::default(), false, false),
+        };
+        let fit = match self.stage("resize") {
+            Some(Stage::Resize { fit, .. }) => *fit,
+            _ => Fit::default(),
+        };
+        DecodeOptions {
+            chain,
+            tolerate_truncated,
+            auto_orient,
+            max_size: self.max_size().cloned(),
+            fit,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    #[test]
+    fn test_pipeline_json() {
+        let schema: serde_json::Value = serde_json::from_str(PIPELINE_JSON_SCHEMA).expect("schema");
+        let stages = &schema["definitions"]["stage"]["oneOf"];
+        let names = stages
+            .as_array()
+            .expect("stage variants")
+            .iter()
+            .map(|x| x["properties"]["stage"]["const"].as_str().expect("stage name").to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, Stage::ORDER);
+        let source = r##"{
+            "schema_version": 1,
+            "seed": 7,
+            "stages": [
+                {"stage": "resize", "max_size": {"width": 800, "height": 600}},
+                {"stage": "palette", "palette": ["#000000", "#ffffff"]},
+                {"stage": "encode", "format": "Webp"}
+            ]
+        }"##;
+        let pipeline = Pipeline::from_json(source).expect("parse pipeline");
+        assert_eq!(pipeline.seed, Seed(7));
+        assert_eq!(pipeline.format(), Some(&OutputFormat::Webp));
+        assert_eq!(pipeline.decode_options().max_size, Some(Resolution::new(800, 600)));
+        assert!(matches!(pipeline.stage("palette"), Some(Stage::Palette { dither: true, .. })));
+        // DEFAULT FITS AND FILTERS ARE OMITTED
+        assert!(!pipeline.to_json().contains("fit"));
+        assert_eq!(Pipeline::from_json(&pipeline.to_json()), Ok(pipeline));
+        let cover = source.replace("\"height\": 600}", "\"height\": 600}, \"fit\": \"Cover\"");
+        assert_eq!(Pipeline::from_json(&cover).expect("parse pipeline").decode_options().fit, Fit::Cover);
+        // OUT OF ORDER, AND WITHOUT AN ENCODE STAGE
+        let swapped = Pipeline::new(vec![
+            Stage::Encode {
+                format: OutputFormat::Png,
+                fallbacks: Vec::new(),
+                extreme: false,
+                color_mode: ColorMode::Bilevel,
+                threshold: Threshold::Otsu,
+                text_protect: false,
+                tuning: Tuning::default(),
+            },
+            Stage::Watermark { id: 1 },
+        ]);
+        assert!(swapped.validate().is_err());
+        let fallback = r#"{"stages": [{"stage": "encode", "format": "Avif", "fallbacks": ["Webp", "Avif"]}]}"#;
+        assert!(Pipeline::from_json(fallback).is_err());
+        let fallback = Pipeline::from_json(&fallback.replace(", \"Avif\"]", "]")).expect("parse pipeline");
+        assert_eq!(fallback.fallbacks(), [OutputFormat::Webp]);
+        assert!(Pipeline::new(vec![Stage::Watermark { id: 1 }]).validate().is_err());
+    }
+}
@@ -0,0 +1,348 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Optimization profiles: reusable, versioned (JSON or TOML) option sets.
+//!
+//! See `schemas/opt-profile.v1.json`, which TOML profiles (e.g. the
+//! `imager.toml` of `imager tune`) follow as well; the versioning rules are
+//! those of `crate::report`.
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+use crate::data::{
+    BrandPalette, ColorMode, Crop, Fit, OutputFormat, OutputFormats, QualityRange, ResizeFilter, Resolution, Seed,
+    Threshold, Tuning, Upscaler,
+};
+use crate::decode::{DecodeOptions, Decoder, DecoderChain};
+use crate::input::ReadMode;
+use crate::meta::{Attribution, PrivacyPolicy};
+use crate::report::{check_schema_version, unversioned_schema};
+use crate::validate::Options;
+
+pub const OPT_PROFILE_SCHEMA_VERSION: u32 = 1;
+
+/// The JSON Schema (draft 7) of the current profile version.
+pub const OPT_PROFILE_JSON_SCHEMA: &str = include_str!("../schemas/opt-profile.v1.json");
+
+/// Every field is optional in JSON; missing fields take the CLI defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OptProfile {
+    #[serde(default = "unversioned_schema")]
+    pub schema_version: u32,
+    pub formats: Vec<OutputFormat>,
+    /// The formats to try in order when an encoder fails, after the later
+    /// `formats` (the server’s preference list).
+    pub fallbacks: Vec<OutputFormat>,
+    pub max_size: Option<Resolution>,
+    /// How sources are resized to the `max_size`.
+    pub fit: Fit,
+    pub resize_filter: ResizeFilter,
+    /// Where `cover` fits crop.
+    pub crop: Crop,
+    /// Enlarge sources smaller than the `max_size` to fit it.
+    pub allow_upscale: bool,
+    pub upscaler: Upscaler,
+    pub decoders: Vec<Decoder>,
+    pub tolerate_truncated: bool,
+    /// Apply the EXIF orientation to the pixels.
+    pub auto_orient: bool,
+    pub read_mode: ReadMode,
+    pub extreme: bool,
+    /// E.g. grayscale or bilevel, for document scans.
+    pub color_mode: ColorMode,
+    /// Of bilevel outputs.
+    pub threshold: Threshold,
+    /// Keep the text of UI screenshots crisp (JPEG outputs only).
+    pub text_protect: bool,
+    /// The quality ranges of the searches, e.g. as written by `imager tune`.
+    pub tuning: Tuning,
+    /// Source metadata to carry into outputs.
+    pub privacy: PrivacyPolicy,
+    /// Embed a thumbnail of JPEG outputs in their EXIF.
+    pub exif_thumbnail: bool,
+    /// Colors to snap outputs to, dithered unless `dither` is off.
+    pub palette: Option<BrandPalette>,
+    pub dither: bool,
+    /// Seeds stages that use randomness, for reproducible outputs.
+    pub seed: Seed,
+    /// Written into every output.
+    pub attribution: Attribution,
+}
+
+impl Default for OptProfile {
+    fn default() -> Self {
+        OptProfile {
+            schema_version: OPT_PROFILE_SCHEMA_VERSION,
+            formats: OutputFormats::default().0,
+            fallbacks: Vec::new(),
+            max_size: None,
+            fit: Fit::default(),
+            resize_filter: ResizeFilter::default(),
+            crop: Crop::default(),
+            allow_upscale: false,
+            upscaler: Upscaler::default(),
+            decoders: DecoderChain::default().0,
+            tolerate_truncated: false,
+            auto_orient: false,
+            read_mode: ReadMode::default(),
+            extreme: false,
+            color_mode: ColorMode::default(),
+            threshold: Threshold::default(),
+            text_protect: false,
+            tuning: Tuning::default(),
+            privacy: PrivacyPolicy::default(),
+            exif_thumbnail: false,
+            palette: None,
+            dither: true,
+            seed: Seed::default(),
+            attribution: Attribution::default(),
+        }
+    }
+}
+
+impl OptProfile {
+    /// Parses and validates a profile.
+    pub fn from_json(source: &str) -> Result<Self, String> {
+        let value: serde_json::Value =
+            serde_json::from_str(source).map_err(|e| e.to_string())?;
+        OptProfile::from_value(value)
+    }
+    /// Like `from_json`.
+    #[cfg(feature = "std")]
+    pub fn from_toml(source: &str) -> Result<Self, String> {
+        let value: toml::Value = toml::from_str(source).map_err(|e| e.to_string())?;
+        OptProfile::from_value(serde_json::to_value(value).map_err(|e| e.to_string())?)
+    }
+    fn from_value(value: serde_json::Value) -> Result<Self, String> {
+        check_schema_version(&value, OPT_PROFILE_SCHEMA_VERSION)?;
+        let profile: OptProfile = serde_json::from_value(value).map_err(|e| e.to_string())?;
+        profile.validate()?;
+        Ok(profile)
+    }
+    #[cfg(feature = "std")]
+    pub fn to_toml(&self) -> Result<String, String> {
+        toml::to_string_pretty(self).map_err(|e| e.to_string())
+    }
+    /// TOML if the extension is `.toml`, else JSON.
+    #[cfg(feature = "std")]
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path.as_ref()).map_err(|e| e.to_string())?;
+        match is_toml(path.as_ref()) {
+            true => OptProfile::from_toml(&source),
+            false => OptProfile::from_json(&source),
+        }
+    }
+    /// As `open` reads it back.
+    #[cfg(feature = "std")]
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), String> {
+        let source = match is_toml(path.as_ref()) {
+            true => self.to_toml()?,
+            false => serde_json::to_string_pretty(self).map_err(|e| e.to_string())?,
+        };
+        std::fs::write(path, source).map_err(|e| e.to_string())
+    }
+    /// Rejects profiles that would fail every job.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.decoders.is_empty() {
+            return Err(String::from("no decoders given"));
+        }
+        Options {
+            formats: &self.formats,
+            max_size: self.max_size.as_ref(),
+            allow_upscale: self.allow_upscale,
+            upscaler: self.upscaler,
+            remove_background: false,
+            palette: self.palette.is_some(),
+            color_mode: self.color_mode,
+            exif_thumbnail: self.exif_thumbnail,
+            text_protect: self.text_protect,
+            tuning: &self.tuning,
+        }
+        .validate()
+    }
+    pub fn decode_options(&self) -> DecodeOptions {
+        DecodeOptions {
+            chain: DecoderChain(self.decoders.clone()),
+            tolerate_truncated: self.tolerate_truncated,
+            max_size: self.max_size.clone(),
+            fit: self.fit,
+            auto_orient: self.auto_orient,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+fn is_toml(path: &std::path::Path) -> bool {
+    path.extension().is_some_and(|x| x.eq_ignore_ascii_case("toml"))
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PRESETS
+///////////////////////////////////////////////////////////////////////////////
+
+/// The max quality of `data-saver` outputs, of every format.
+pub const DATA_SAVER_QUALITY: u8 = 55;
+/// The max butteraugli distance of `data-saver` outputs; visibly lossy at
+/// 1:1, though not on the small, dense screens it’s for.
+pub const DATA_SAVER_DISTANCE: f64 = 2.5;
+/// The max width and height of `data-saver` outputs.
+pub const DATA_SAVER_MAX_DIMENSION: u32 = 1280;
+
+/// Built-in profiles, applied over a base one (e.g. the server’s
+/// `--profile`), per request or job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Preset {
+    /// For low-bandwidth traffic: lower qualities, a looser perceptual
+    /// target, and smaller outputs. Text isn’t protected, so its chroma is
+    /// subsampled as the rest is.
+    DataSaver,
+}
+
+impl FromStr for Preset {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "data-saver" => Ok(Self::DataSaver),
+            _ => Err(format!("Unknown preset {}", s)),
+        }
+    }
+}
+
+impl Preset {
+    pub fn apply(&self, base: &OptProfile) -> OptProfile {
+        match self {
+            Preset::DataSaver => OptProfile {
+                max_size: self.max_size(base.max_size.clone()),
+                text_protect: false,
+                tuning: self.tuning(base.tuning),
+                ..base.clone()
+            },
+        }
+    }
+    /// Of a max size (`None` is the full size).
+    pub fn max_size(&self, max_size: Option<Resolution>) -> Option<Resolution> {
+        match self {
+            Preset::DataSaver => {
+                let (width, height) = max_size.map_or((u32::MAX, u32::MAX), |x| (x.width, x.height));
+                Some(Resolution::new(
+                    width.min(DATA_SAVER_MAX_DIMENSION),
+                    height.min(DATA_SAVER_MAX_DIMENSION),
+                ))
+            }
+        }
+    }
+    pub fn tuning(&self, tuning: Tuning) -> Tuning {
+        match self {
+            Preset::DataSaver => {
+                let cap = |range: QualityRange| QualityRange {
+                    min: range.min.min(DATA_SAVER_QUALITY),
+                    max: range.max.min(DATA_SAVER_QUALITY),
+                };
+                Tuning {
+                    jpeg: cap(tuning.jpeg),
+                    webp: cap(tuning.webp),
+                    avif: cap(tuning.avif),
+                    max_distance: Some(tuning.max_distance.map_or(DATA_SAVER_DISTANCE, |x| x.max(DATA_SAVER_DISTANCE))),
+                    ..tuning
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_profile_compat() {
+        let schema: serde_json::Value =
+            serde_json::from_str(OPT_PROFILE_JSON_SCHEMA).expect("schema");
+        let json = serde_json::to_value(OptProfile::default()).expect("to json");
+        for key in json.as_object().expect("object").keys() {
+            assert!(schema["properties"].get(key).is_some(), "undeclared key {}", key);
+        }
+        // AS WRITTEN BY v1
+        let v1 = r#"{
+            "schema_version": 1,
+            "formats": ["Webp"],
+            "max_size": {"width": 800, "height": 600},
+            "decoders": ["Turbo"],
+            "read_mode": "Heap"
+        }"#;
+        let profile = OptProfile::from_json(v1).expect("parse v1 profile");
+        assert_eq!(profile.formats, vec![OutputFormat::Webp]);
+        assert_eq!(profile.decode_options().chain, DecoderChain(vec![Decoder::Turbo]));
+        assert!(!profile.extreme);
+        assert_eq!(OptProfile::from_json("{}").expect("unversioned"), OptProfile::default());
+        assert!(OptProfile::from_json(r#"{"schema_version": 2}"#).is_err());
+        let tuned = OptProfile::from_json(r#"{"tuning": {"jpeg": {"min": 40, "max": 90}}}"#).expect("tuned");
+        assert_eq!((tuned.tuning.jpeg.max, tuned.tuning.webp.max), (90, 100));
+        assert!(OptProfile::from_json(r#"{"tuning": {"webp": {"min": 90, "max": 40}}}"#).is_err());
+        let too_large = r#"{"formats": ["Webp"], "max_size": {"width": 20000, "height": 10}}"#;
+        assert!(OptProfile::from_json(too_large).is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_toml() {
+        use crate::data::Subsampling;
+        let profile = OptProfile {
+            max_size: Some(Resolution::new(800, 600)),
+            tuning: Tuning {
+                jpeg: QualityRange { min: 40, max: 85 },
+                jpeg_subsampling: Subsampling::Yuv444,
+                webp_effort: 4,
+                max_distance: Some(1.5),
+                ..Tuning::default()
+            },
+            ..OptProfile::default()
+        };
+        let toml = profile.to_toml().expect("to toml");
+        assert!(toml.contains("jpeg_subsampling = \"Yuv444\""), "{}", toml);
+        assert_eq!(OptProfile::from_toml(&toml).expect("parse toml"), profile);
+        let written = "schema_version = 1\n[tuning]\nwebp_effort = 2\n[tuning.webp]\nmin = 60\nmax = 90\n";
+        let tuned = OptProfile::from_toml(written).expect("tuned");
+        assert_eq!((tuned.tuning.webp_effort, tuned.tuning.webp.min), (2, 60));
+        assert!(OptProfile::from_toml("schema_version = 2").is_err());
+        assert!(OptProfile::from_toml("[tuning]\nwebp_effort = 7").is_err());
+        // SAVED AS THE EXTENSION SAYS
+        let dir = std::env::temp_dir().join(format!("imager-profile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create dir");
+        for name in ["imager.toml", "imager.json"] {
+            profile.save(dir.join(name)).expect("save");
+            assert_eq!(OptProfile::open(dir.join(name)).expect("open"), profile);
+        }
+        assert!(std::fs::read_to_string(dir.join("imager.json")).expect("read").starts_with('{'));
+        std::fs::remove_dir_all(&dir).expect("remove dir");
+    }
+
+    #[test]
+    fn test_preset() {
+        let preset = "data-saver".parse::<Preset>().expect("preset");
+        assert!("saver".parse::<Preset>().is_err());
+        let base = OptProfile {
+            max_size: Some(Resolution::new(2000, 800)),
+            text_protect: true,
+            ..OptProfile::default()
+        };
+        let profile = preset.apply(&base);
+        assert_eq!(profile.max_size, Some(Resolution::new(1280, 800)));
+        assert_eq!(profile.tuning.jpeg, QualityRange { min: 0, max: DATA_SAVER_QUALITY });
+        assert_eq!(profile.tuning.max_distance, Some(DATA_SAVER_DISTANCE));
+        // THE PRESET ONLY EVER LOOSENS, OR SHRINKS
+        let loose = Tuning {
+            max_distance: Some(4.0),
+            ..Tuning::default()
+        };
+        assert_eq!(preset.tuning(loose).max_distance, Some(4.0));
+        assert_eq!(preset.max_size(None), Some(Resolution::new(1280, 1280)));
+        assert!(!profile.text_protect && profile.formats == base.formats);
+        assert!(profile.validate().is_ok());
+    }
+}
@@ -0,0 +1,198 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Report schema versioning, and the per-file error and warning types of
+//! batch reports. (The report itself, with its per-output metadata, lives
+//! in `imager::report`.)
+//!
+//! The JSON layout is versioned (see `schemas/report.v1.json`). Adding
+//! optional fields keeps the version; removing, renaming or retyping a
+//! field bumps `REPORT_SCHEMA_VERSION`.
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::data::OutputFormat;
+
+///////////////////////////////////////////////////////////////////////////////
+// SCHEMA
+///////////////////////////////////////////////////////////////////////////////
+
+pub const REPORT_SCHEMA_VERSION: u32 = 1;
+
+/// The JSON Schema (draft 7) of the current report version.
+pub const REPORT_JSON_SCHEMA: &str = include_str!("../schemas/report.v1.json");
+
+/// Rejects documents written by a newer (incompatible) imager. Documents
+/// without a `schema_version` predate versioning and count as version 1.
+pub fn check_schema_version(value: &serde_json::Value, supported: u32) -> Result<(), String> {
+    let version = match value.get("schema_version") {
+        None => 1,
+        Some(x) => x.as_u64().ok_or("schema_version must be an integer")?,
+    };
+    if version > u64::from(supported) {
+        return Err(format!(
+            "unsupported schema_version {} (this imager supports up to {})",
+            version, supported
+        ));
+    }
+    Ok(())
+}
+
+/// The `schema_version` of documents that don’t have one.
+pub fn unversioned_schema() -> u32 {
+    1
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// FILE ERRORS
+///////////////////////////////////////////////////////////////////////////////
+
+/// Why a single file of a batch failed.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FileErrorKind {
+    /// The input couldn’t be read, or the output couldn’t be written.
+    Io,
+    /// The input isn’t in a (recognized) image format.
+    Unsupported,
+    /// No decoder of the chain could decode the input.
+    Decode,
+    /// The image exceeds what the output format can represent.
+    TooLarge,
+    /// The encoder failed.
+    Encode,
+}
+
+impl core::fmt::Display for FileErrorKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Io => write!(f, "io"),
+            Self::Unsupported => write!(f, "unsupported"),
+            Self::Decode => write!(f, "decode"),
+            Self::TooLarge => write!(f, "too-large"),
+            Self::Encode => write!(f, "encode"),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// WARNINGS
+///////////////////////////////////////////////////////////////////////////////
+
+/// Conditions that put the quality of an (otherwise successful) output at
+/// risk.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WarningKind {
+    /// The source was enlarged, which can’t add detail.
+    Upscaled,
+    /// Transparency was flattened (the output format has no alpha).
+    AlphaFlattened,
+    /// The source’s ICC profile was dropped, so colors shift unless it
+    /// was (close to) sRGB.
+    ColorProfileDiscarded,
+    /// Text or line art (edge dense content) was compressed lossily, to
+    /// the lowest quality thresholds.
+    TextCompressed,
+    /// The output only approximates the brand palette (JPEG).
+    PaletteApproximated,
+    /// The output is too small for the watermark to be reliably detected.
+    WatermarkUnreliable,
+    /// The encoder of the requested format failed, so the output is of a
+    /// fallback format (see `Fallback`).
+    EncoderFallback,
+    /// Only the first frame of an animated source (e.g. an APNG) was
+    /// kept.
+    AnimationFlattened,
+    /// No quality of the range kept the output within the max distance
+    /// (see `DistanceCheck`), so it’s at the top of the range.
+    DistanceExceeded,
+}
+
+impl core::fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Upscaled => write!(f, "upscaled"),
+            Self::AlphaFlattened => write!(f, "alpha-flattened"),
+            Self::ColorProfileDiscarded => write!(f, "color-profile-discarded"),
+            Self::TextCompressed => write!(f, "text-compressed"),
+            Self::PaletteApproximated => write!(f, "palette-approximated"),
+            Self::WatermarkUnreliable => write!(f, "watermark-unreliable"),
+            Self::EncoderFallback => write!(f, "encoder-fallback"),
+            Self::AnimationFlattened => write!(f, "animation-flattened"),
+            Self::DistanceExceeded => write!(f, "distance-exceeded"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub message: String,
+}
+
+impl Warning {
+    pub fn new<S: Into<String>>(kind: WarningKind, message: S) -> Self {
+        Warning {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl core::fmt::Display for Warning {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}: {}", self.kind, self.message)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// FALLBACKS
+///////////////////////////////////////////////////////////////////////////////
+
+/// The output of a job whose requested encoder failed (e.g. an encoder bug,
+/// or dimensions the format can’t represent), in the next format of the
+/// preference list that worked.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fallback {
+    pub requested: OutputFormat,
+    pub output: OutputFormat,
+    /// Why each format before the `output` failed, in order.
+    pub errors: Vec<String>,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// DISTANCES
+///////////////////////////////////////////////////////////////////////////////
+
+/// Of an output encoded for a max butteraugli distance (in place of the
+/// VMAF search).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DistanceCheck {
+    /// Of the output from its source.
+    pub distance: f64,
+    pub max_distance: f64,
+    /// Whether the output is within the max; if not, it’s at the top of the
+    /// quality range.
+    pub passed: bool,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// VERSIONS
+///////////////////////////////////////////////////////////////////////////////
+
+/// What encoded an output, so that the outputs of a buggy encoder release
+/// can be found (and re-run) long after.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Versions {
+    /// Which also pins its Rust encoders (e.g. lodepng), via its lockfile.
+    pub imager: String,
+    /// E.g. `libwebp`, or the plugin’s name.
+    pub encoder: String,
+    /// As the library reports it at runtime (e.g. `1.2.4`), where it does:
+    /// of the C encoders, which may be linked or loaded from elsewhere.
+    #[serde(default)]
+    pub encoder_version: Option<String>,
+}
@@ -0,0 +1,441 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Migrating from sharp (or libvips): their operations, as JSON, translated
+//! into imager pipelines (see `crate::pipeline`).
+//!
+//! The source is a list of operations, each an object whose only key is
+//! the sharp method (or libvips operation) and whose value is its options,
+//! or its arguments, e.g.
+//!
+//! ```json
+//! [
+//!     {"resize": {"width": 800, "withoutEnlargement": true}},
+//!     {"greyscale": true},
+//!     {"webp": {"quality": 80}}
+//! ]
+//! ```
+//!
+//! | sharp | libvips | imager |
+//! |---|---|---|
+//! | `resize` (`width`, `height`, `fit`, `position`, `kernel`, `withoutEnlargement`) | `thumbnail` (`width`, `height`, `size`, `crop`) | `resize`, at the fit, crop and filter |
+//! | `grayscale`, `greyscale`, `toColourspace` (`b-w`) | `colourspace` (`b-w`) | `encode`, grayscale |
+//! | `threshold` | | `encode`, bilevel |
+//! | `withMetadata`, `keepMetadata`, `keepExif` | `strip: false` on saves | `metadata`, keeping every EXIF group |
+//! | `jpeg`, `png`, `webp`, `tiff`, `avif`, `heif` (AV1), `jxl`, `toFormat` (`quality`) | `jpegsave`, …, `jxlsave` (`Q`) | `encode`, at the fixed quality |
+//! | `rotate` (without an angle) | `autorot` | `decode`, auto-orienting |
+//!
+//! Operations without an equivalent (e.g. `blur`, `extract`, `composite`)
+//! are errors; options imager ignores, or only approximates (e.g. crops
+//! by `position: left`, as imager only crops centered or by content), are
+//! reported as notes.
+use alloc::borrow::ToOwned;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use serde_json::{Map, Value};
+
+use crate::data::{
+    ColorMode, Crop, Fit, OutputFormat, QualityRange, ResizeFilter, Resolution, Threshold, Tuning, Upscaler,
+};
+use crate::decode::DecoderChain;
+use crate::pipeline::{Pipeline, Stage};
+
+/// A translated pipeline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Translation {
+    pub pipeline: Pipeline,
+    /// What imager does differently, e.g. ignored options.
+    pub notes: Vec<String>,
+}
+
+/// Parses sharp (or libvips) operations JSON, into a validated pipeline.
+pub fn translate(source: &str) -> Result<Translation, String> {
+    let value: Value = serde_json::from_str(source).map_err(|e| e.to_string())?;
+    let operations = value
+        .as_array()
+        .ok_or("expected a list of operations, e.g. [{\"webp\": {\"quality\": 80}}]")?;
+    let mut translator = Translator::default();
+    for operation in operations {
+        let (name, args) = match operation.as_object() {
+            Some(x) if x.len() == 1 => x.iter().next().expect("one key"),
+            _ => return Err(format!("expected an object with a single operation, got {}", operation)),
+        };
+        translator.apply(name, args)?;
+    }
+    translator.finish()
+}
+
+#[derive(Default)]
+struct Translator {
+    width: Option<u32>,
+    height: Option<u32>,
+    enlarge: bool,
+    fit: Fit,
+    filter: ResizeFilter,
+    crop: Crop,
+    color_mode: ColorMode,
+    threshold: Threshold,
+    format: Option<OutputFormat>,
+    quality: Option<u8>,
+    keep_metadata: bool,
+    auto_orient: bool,
+    notes: Vec<String>,
+}
+
+impl Translator {
+    fn apply(&mut self, name: &str, args: &Value) -> Result<(), String> {
+        match name {
+            "resize" => {
+                // resize(width), resize(width, height, options) OR resize(options)
+                let (width, height, options) = match args {
+                    Value::Array(args) => (args.first(), args.get(1), args.get(2).and_then(Value::as_object)),
+                    Value::Object(options) => (options.get("width"), options.get("height"), Some(options)),
+                    width => (Some(width), None, None),
+                };
+                self.resize(name, width, height)?;
+                let options = options.cloned().unwrap_or_default();
+                self.enlarge = options.get("withoutEnlargement") != Some(&Value::Bool(true));
+                // ONLY BOTH DIMENSIONS CAN CHANGE THE ASPECT RATIO
+                let fit = options.get("fit").and_then(Value::as_str).unwrap_or("cover");
+                self.fit = match fit.parse::<Fit>().map_err(|e| format!("{}: {}", name, e))? {
+                    _ if self.width.is_none() || self.height.is_none() => Fit::Inside,
+                    fit => fit,
+                };
+                self.filter = match options.get("kernel").and_then(Value::as_str).unwrap_or("lanczos3") {
+                    "lanczos3" => ResizeFilter::Lanczos3,
+                    "cubic" => ResizeFilter::CatmullRom,
+                    "linear" => ResizeFilter::Triangle,
+                    kernel => {
+                        self.notes.push(format!("resize: kernel {} is approximated by lanczos3", kernel));
+                        ResizeFilter::Lanczos3
+                    }
+                };
+                // STRINGS, OR THE `sharp.gravity` AND `sharp.strategy` CONSTANTS
+                self.crop = match options.get("position") {
+                    None => Crop::Centre,
+                    Some(Value::String(x)) if CENTERED.contains(&x.as_str()) => Crop::Centre,
+                    Some(x) if x == "entropy" || x == 16 => Crop::Entropy,
+                    Some(x) if x == "attention" || x == 17 => Crop::Attention,
+                    Some(x) if x == 0 => Crop::Centre,
+                    Some(_) => {
+                        self.notes.push(String::from("resize: position is approximated by centre"));
+                        Crop::Centre
+                    }
+                };
+                if self.fit == Fit::Contain && options.contains_key("background") {
+                    self.notes.push(String::from(
+                        "resize: background is ignored (padding is transparent for sources with alpha, else black)",
+                    ));
+                }
+                let known = ["width", "height", "withoutEnlargement", "fit", "kernel", "position", "background"];
+                self.ignored(name, &options, &known);
+            }
+            "thumbnail" => {
+                let options = object(name, args)?;
+                self.resize(name, options.get("width"), options.get("height"))?;
+                self.enlarge = options.get("size").and_then(Value::as_str) != Some("down");
+                let crop = options.get("crop").and_then(Value::as_str).unwrap_or("none");
+                if crop != "none" && self.width.is_some() && self.height.is_some() {
+                    self.fit = Fit::Cover;
+                }
+                self.crop = match crop {
+                    "entropy" => Crop::Entropy,
+                    "attention" => Crop::Attention,
+                    crop if crop != "none" && !CENTERED.contains(&crop) => {
+                        self.notes.push(format!("thumbnail: crop {} is approximated by centre", crop));
+                        Crop::Centre
+                    }
+                    _ => Crop::Centre,
+                };
+                self.ignored(name, &options, &["width", "height", "size", "crop"]);
+            }
+            "grayscale" | "greyscale" => {
+                if args.as_bool() != Some(false) && self.color_mode == ColorMode::Color {
+                    self.color_mode = ColorMode::Grayscale;
+                }
+            }
+            "toColourspace" | "toColorspace" | "colourspace" => {
+                let space = match args {
+                    Value::Object(options) => options.get("space"),
+                    space => Some(space),
+                };
+                match space.and_then(Value::as_str) {
+                    Some("b-w") => {
+                        if self.color_mode == ColorMode::Color {
+                            self.color_mode = ColorMode::Grayscale;
+                        }
+                    }
+                    Some("srgb") => (),
+                    space => return Err(format!("{}: unsupported color space {:?}", name, space)),
+                }
+            }
+            "threshold" => {
+                let value = match args {
+                    Value::Array(args) => args.first().cloned().unwrap_or(Value::Null),
+                    Value::Object(options) => options.get("threshold").cloned().unwrap_or(Value::Null),
+                    value => value.clone(),
+                };
+                let value = match value {
+                    Value::Null => 128,
+                    value => value
+                        .as_u64()
+                        .and_then(|x| u8::try_from(x).ok())
+                        .ok_or_else(|| format!("threshold: invalid threshold {}", value))?,
+                };
+                self.color_mode = ColorMode::Bilevel;
+                self.threshold = Threshold::Fixed(value);
+            }
+            "withMetadata" | "keepMetadata" | "keepExif" => self.keep_metadata(),
+            "rotate" | "autoOrient" | "autorot" => {
+                let angle = match args {
+                    Value::Array(args) => args.first(),
+                    Value::Object(options) => options.get("angle"),
+                    angle => Some(angle),
+                };
+                if angle.and_then(Value::as_f64).is_some_and(|x| x != 0.0) {
+                    return Err(format!("{}: rotating by an angle is unsupported", name));
+                }
+                self.auto_orient = true;
+            }
+            "toFormat" => {
+                let (format, options) = match args {
+                    Value::Array(args) => (args.first(), args.get(1).and_then(Value::as_object).cloned()),
+                    Value::Object(options) => (options.get("format"), Some(options.clone())),
+                    format => (Some(format), None),
+                };
+                let format = format
+                    .and_then(Value::as_str)
+                    .ok_or("toFormat: no format given")?
+                    .to_owned();
+                let mut options = options.unwrap_or_default();
+                options.remove("format");
+                self.save(&format, &format, &options)?;
+            }
+            _ => match name.strip_suffix("save") {
+                Some(format) => {
+                    let options = object(name, args)?;
+                    self.save(name, format, &options)?;
+                    if options.get("strip") == Some(&Value::Bool(false)) {
+                        self.keep_metadata();
+                    }
+                }
+                None if SAVES.contains(&name) => self.save(name, name, &object(name, args)?)?,
+                None => return Err(format!("unsupported operation {} (imager has no equivalent)", name)),
+            },
+        }
+        Ok(())
+    }
+    fn resize(&mut self, name: &str, width: Option<&Value>, height: Option<&Value>) -> Result<(), String> {
+        let dimension = |value: Option<&Value>| match value {
+            None | Some(Value::Null) => Ok(None),
+            Some(value) => value
+                .as_u64()
+                .and_then(|x| u32::try_from(x).ok())
+                .filter(|x| *x > 0)
+                .map(Some)
+                .ok_or_else(|| format!("{}: invalid dimension {}", name, value)),
+        };
+        self.width = dimension(width)?;
+        self.height = dimension(height)?;
+        if self.width.is_none() && self.height.is_none() {
+            return Err(format!("{}: neither a width nor a height given", name));
+        }
+        Ok(())
+    }
+    fn save(&mut self, name: &str, format: &str, options: &Map<String, Value>) -> Result<(), String> {
+        let format = match format {
+            "jpeg" | "jpg" => OutputFormat::Jpeg,
+            "png" => OutputFormat::Png,
+            "webp" => OutputFormat::Webp,
+            "tiff" | "tif" => OutputFormat::Tiff,
+            "avif" => OutputFormat::Avif,
+            "heif" | "heic" => match options.get("compression").and_then(Value::as_str) {
+                None | Some("av1") => OutputFormat::Avif,
+                Some(compression) => return Err(format!("{}: unsupported compression {}", name, compression)),
+            },
+            "jxl" => OutputFormat::Jxl,
+            format => return Err(format!("{}: unsupported format {}", name, format)),
+        };
+        let quality = match options.get("quality").or_else(|| options.get("Q")) {
+            None => None,
+            Some(quality) => Some(
+                quality
+                    .as_u64()
+                    .and_then(|x| u8::try_from(x).ok())
+                    .filter(|x| (1..=100).contains(x))
+                    .ok_or_else(|| format!("{}: invalid quality {}", name, quality))?,
+            ),
+        };
+        if quality.is_some() && matches!(format, OutputFormat::Png | OutputFormat::Tiff | OutputFormat::Jxl) {
+            self.notes.push(format!("{}: quality is ignored ({:?} outputs use imager’s own settings)", name, format));
+        }
+        if self.format.is_some() {
+            self.notes.push(format!("{}: replaces the earlier output format", name));
+        }
+        self.format = Some(format);
+        self.quality = quality;
+        self.ignored(name, options, &["quality", "Q", "compression", "strip"]);
+        Ok(())
+    }
+    fn keep_metadata(&mut self) {
+        self.keep_metadata = true;
+        self.notes.push(String::from(
+            "metadata: GPS data, serial numbers and maker notes are still dropped",
+        ));
+    }
+    fn ignored(&mut self, name: &str, options: &Map<String, Value>, known: &[&str]) {
+        let ignored = options
+            .keys()
+            .filter(|x| !known.contains(&x.as_str()))
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        if !ignored.is_empty() {
+            self.notes.push(format!("{}: ignored {}", name, ignored.join(", ")));
+        }
+    }
+    fn finish(self) -> Result<Translation, String> {
+        let format = self
+            .format
+            .ok_or("no output format, e.g. {\"webp\": {\"quality\": 80}}")?;
+        let mut stages = Vec::new();
+        if self.auto_orient {
+            stages.push(Stage::Decode {
+                decoders: DecoderChain::default().0,
+                tolerate_truncated: false,
+                auto_orient: true,
+            });
+        }
+        if self.width.is_some() || self.height.is_some() {
+            // AN UNBOUNDED SIDE IS BOUNDED BY THE FORMAT
+            let limit = format.max_dimension();
+            let max_size = Resolution::new(self.width.unwrap_or(limit), self.height.unwrap_or(limit));
+            let upscaler = self.enlarge.then_some(Upscaler::Lanczos);
+            stages.push(Stage::Resize {
+                max_size,
+                upscaler,
+                fit: self.fit,
+                filter: self.filter,
+                crop: self.crop,
+            });
+        }
+        let mut tuning = Tuning::default();
+        if let Some(quality) = self.quality {
+            let range = QualityRange {
+                min: quality,
+                max: quality,
+            };
+            match format {
+                OutputFormat::Jpeg => tuning.jpeg = range,
+                OutputFormat::Webp => tuning.webp = range,
+                OutputFormat::Avif => tuning.avif = range,
+                OutputFormat::Png | OutputFormat::Tiff | OutputFormat::Jxl => (),
+            }
+        }
+        stages.push(Stage::Encode {
+            format,
+            fallbacks: Vec::new(),
+            extreme: false,
+            color_mode: self.color_mode,
+            threshold: self.threshold,
+            text_protect: false,
+            tuning,
+        });
+        if self.keep_metadata {
+            stages.push(Stage::Metadata {
+                privacy: "camera,exposure,capture-time,copyright".parse().expect("valid policy"),
+                attribution: Default::default(),
+                exif_thumbnail: false,
+            });
+        }
+        let pipeline = Pipeline::new(stages);
+        pipeline.validate()?;
+        Ok(Translation {
+            pipeline,
+            notes: self.notes,
+        })
+    }
+}
+
+/// The `position` (and libvips `crop`) values that crop centered.
+const CENTERED: [&str; 2] = ["centre", "center"];
+
+/// The format methods of sharp.
+const SAVES: [&str; 9] = ["jpeg", "jpg", "png", "webp", "tiff", "avif", "heif", "jxl", "gif"];
+
+fn object(name: &str, args: &Value) -> Result<Map<String, Value>, String> {
+    match args {
+        Value::Object(options) => Ok(options.clone()),
+        Value::Null | Value::Bool(true) => Ok(Map::new()),
+        args => Err(format!("{}: expected options, got {}", name, args)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_translate() {
+        let source = r#"[
+            {"rotate": null},
+            {"resize": [800, 600, {"fit": "inside", "withoutEnlargement": true}]},
+            {"greyscale": true},
+            {"webp": {"quality": 80, "effort": 6}},
+            {"withMetadata": {}}
+        ]"#;
+        let translation = translate(source).expect("translate");
+        let pipeline = &translation.pipeline;
+        let stages = pipeline.stages.iter().map(Stage::name).collect::<Vec<_>>();
+        assert_eq!(stages, ["decode", "resize", "encode", "metadata"]);
+        assert!(pipeline.decode_options().auto_orient);
+        assert_eq!(
+            pipeline.stage("resize"),
+            Some(&Stage::Resize {
+                max_size: Resolution::new(800, 600),
+                upscaler: None,
+                fit: Fit::Inside,
+                filter: ResizeFilter::Lanczos3,
+                crop: Crop::Centre,
+            })
+        );
+        match pipeline.stage("encode") {
+            Some(Stage::Encode {
+                format,
+                color_mode,
+                tuning,
+                ..
+            }) => {
+                assert_eq!(format, &OutputFormat::Webp);
+                assert_eq!(color_mode, &ColorMode::Grayscale);
+                assert_eq!(tuning.webp, QualityRange { min: 80, max: 80 });
+            }
+            stage => panic!("{:?}", stage),
+        }
+        assert_eq!(translation.notes.len(), 2, "{:?}", translation.notes);
+        assert!(translation.notes[0].contains("effort"));
+        // LIBVIPS, WITH AN UNBOUNDED HEIGHT
+        let translation = translate(r#"[{"thumbnail": {"width": 320}}, {"jpegsave": {"Q": 75}}]"#).expect("translate");
+        assert_eq!(translation.pipeline.max_size(), Some(&Resolution::new(320, 65_500)));
+        assert!(translation.notes.is_empty());
+        // CROPPED, AT SHARP’S DEFAULT FIT
+        let source = r#"[{"resize": [320, 240, {"kernel": "cubic", "position": "attention"}]}, {"png": {}}]"#;
+        let translation = translate(source).expect("translate");
+        assert!(matches!(
+            translation.pipeline.stage("resize"),
+            Some(Stage::Resize {
+                fit: Fit::Cover,
+                filter: ResizeFilter::CatmullRom,
+                crop: Crop::Attention,
+                ..
+            })
+        ));
+        assert!(translation.notes.is_empty());
+        let translation = translate(&source.replace("\"attention\"", "\"left top\"")).expect("translate");
+        assert_eq!(translation.notes, ["resize: position is approximated by centre"]);
+        // NO EQUIVALENT, OR NO OUTPUT
+        assert!(translate(r#"[{"blur": 3}, {"png": {}}]"#).is_err());
+        assert!(translate(r#"[{"resize": 100}]"#).is_err());
+        assert!(translate(r#"[{"gif": {}}]"#).is_err());
+    }
+}
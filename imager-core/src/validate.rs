@@ -0,0 +1,245 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Checks for contradictory options, as assembled by the CLI, a profile or
+//! a pipeline, before any source is decoded: settings that would be
+//! silently ignored, or fail every job, are rejected with what to change.
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::data::{ColorMode, OutputFormat, Resolution, Tuning, Upscaler, VmafTargets};
+
+/// The job options that constrain each other.
+#[derive(Debug, Clone)]
+pub struct Options<'a> {
+    pub formats: &'a [OutputFormat],
+    pub max_size: Option<&'a Resolution>,
+    pub allow_upscale: bool,
+    /// As chosen; `Upscaler::default()` if not.
+    pub upscaler: Upscaler,
+    pub remove_background: bool,
+    pub palette: bool,
+    pub color_mode: ColorMode,
+    pub exif_thumbnail: bool,
+    pub text_protect: bool,
+    pub tuning: &'a Tuning,
+}
+
+impl Options<'_> {
+    /// Every problem, each with a fix.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.formats.is_empty() {
+            problems.push(String::from("no output formats given"));
+        }
+        if self.allow_upscale && self.max_size.is_none() {
+            problems.push(String::from(
+                "upscaling is allowed, but there’s no max size to upscale to; set a max size",
+            ));
+        }
+        if !self.allow_upscale && self.upscaler != Upscaler::default() {
+            problems.push(format!(
+                "the {:?} upscaler is set, but upscaling isn’t allowed; allow upscaling, or drop \
+                 the upscaler",
+                self.upscaler
+            ));
+        }
+        if let Some(max_size) = self.max_size {
+            if max_size.width == 0 || max_size.height == 0 {
+                problems.push(format!("invalid max size {}", max_size));
+            }
+            for format in self.formats {
+                let limit = format.max_dimension();
+                if max_size.width > limit || max_size.height > limit {
+                    problems.push(format!(
+                        "max size {} exceeds the {:?} limit of {}; set a smaller max size",
+                        max_size, format, limit
+                    ));
+                }
+            }
+        }
+        let only_jpeg = self.formats.iter().all(|x| x == &OutputFormat::Jpeg);
+        if self.remove_background && !self.formats.is_empty() && only_jpeg {
+            problems.push(String::from(
+                "background removal makes backgrounds transparent, but JPEG has no alpha; add a \
+                 PNG or WebP output format",
+            ));
+        }
+        if self.color_mode == ColorMode::Bilevel {
+            let unsupported = self
+                .formats
+                .iter()
+                .filter(|x| !matches!(x, OutputFormat::Png | OutputFormat::Tiff))
+                .map(|x| format!("{:?}", x))
+                .collect::<Vec<_>>();
+            if !unsupported.is_empty() {
+                problems.push(format!(
+                    "bilevel outputs need PNG or TIFF, not {}; drop those formats, or use grayscale",
+                    unsupported.join(" or ")
+                ));
+            }
+        }
+        if self.exif_thumbnail && !self.formats.contains(&OutputFormat::Jpeg) {
+            problems.push(String::from(
+                "EXIF thumbnails are only embedded in JPEG outputs; add the JPEG format, or drop \
+                 the thumbnail",
+            ));
+        }
+        if self.text_protect && !self.formats.contains(&OutputFormat::Jpeg) {
+            problems.push(String::from(
+                "text protection only applies to JPEG outputs (WebP ones already use sharp YUV); \
+                 add the JPEG format, or drop text protection",
+            ));
+        }
+        if self.palette && self.color_mode != ColorMode::Color {
+            problems.push(format!(
+                "a palette and {:?} outputs contradict; drop the palette, or the color mode",
+                self.color_mode
+            ));
+        }
+        let ranges = Tuning {
+            max_distance: None,
+            vmaf: VmafTargets::default(),
+            ..*self.tuning
+        };
+        if let Err(message) = ranges.validate() {
+            problems.push(format!(
+                "{}; the minimum must be at most the maximum, and both at most 100",
+                message
+            ));
+        }
+        if let Some(distance) = self.tuning.max_distance {
+            if !(distance.is_finite() && distance > 0.0) {
+                problems.push(format!(
+                    "invalid max distance {}; it must be above 0 (about 1 is just noticeable)",
+                    distance
+                ));
+            }
+            if self.text_protect {
+                problems.push(String::from(
+                    "text protection is part of the JPEG VMAF search, which a max distance \
+                     replaces; drop one",
+                ));
+            }
+            if !self.tuning.vmaf.is_empty() {
+                problems.push(String::from(
+                    "VMAF targets are of the VMAF searches, which a max distance replaces; drop one",
+                ));
+            }
+        }
+        for format in [OutputFormat::Jpeg, OutputFormat::Webp, OutputFormat::Avif] {
+            let Some(target) = self.tuning.vmaf.get(&format) else {
+                continue;
+            };
+            if !(0.0..=100.0).contains(&target) {
+                problems.push(format!("invalid {:?} VMAF target {}; VMAF scores are 0 to 100", format, target));
+            }
+            if !self.formats.contains(&format) {
+                problems.push(format!(
+                    "a {:?} VMAF target is set, but {:?} isn’t an output format; add the format, or \
+                     drop the target",
+                    format, format
+                ));
+            }
+        }
+        problems
+    }
+    /// All problems, `; ` separated.
+    pub fn validate(&self) -> Result<(), String> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.join("; "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_validate() {
+        let tuning = Tuning::default();
+        let options = Options {
+            formats: &[OutputFormat::Jpeg],
+            max_size: None,
+            allow_upscale: false,
+            upscaler: Upscaler::default(),
+            remove_background: false,
+            palette: false,
+            color_mode: ColorMode::default(),
+            exif_thumbnail: false,
+            text_protect: false,
+            tuning: &tuning,
+        };
+        assert_eq!(options.validate(), Ok(()));
+        let contradictory = Options {
+            remove_background: true,
+            upscaler: Upscaler::Esrgan,
+            ..options.clone()
+        };
+        let problems = contradictory.problems();
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[1].ends_with("add a PNG or WebP output format"));
+        let formats = vec![OutputFormat::Jpeg, OutputFormat::Png];
+        assert!(Options { formats: &formats, ..contradictory }.problems().len() == 1);
+        let max_size = Resolution::new(20000, 10);
+        let too_large = Options {
+            formats: &[OutputFormat::Webp],
+            max_size: Some(&max_size),
+            ..options
+        };
+        assert!(too_large.validate().unwrap_err().ends_with("set a smaller max size"));
+        let bilevel = Options {
+            color_mode: ColorMode::Bilevel,
+            palette: true,
+            ..options
+        };
+        let problems = bilevel.problems();
+        assert!(problems[0].starts_with("bilevel outputs need PNG or TIFF, not Jpeg;"));
+        assert_eq!(problems.len(), 2);
+        let formats = [OutputFormat::Png];
+        let thumbnail = Options {
+            formats: &formats,
+            exif_thumbnail: true,
+            ..options
+        };
+        assert!(thumbnail.validate().unwrap_err().starts_with("EXIF thumbnails are only embedded in JPEG"));
+        let text = Options {
+            formats: &formats,
+            text_protect: true,
+            ..options
+        };
+        assert!(text.validate().unwrap_err().starts_with("text protection only applies to JPEG"));
+        let tuning = Tuning {
+            max_distance: Some(0.0),
+            ..tuning
+        };
+        let perceptual = Options {
+            text_protect: true,
+            tuning: &tuning,
+            ..options
+        };
+        assert_eq!(perceptual.problems().len(), 2);
+        assert!(perceptual.problems()[1].ends_with("which a max distance replaces; drop one"));
+        let tuning = Tuning {
+            vmaf: "webp=92,avif=120".parse().expect("targets"),
+            ..Tuning::default()
+        };
+        let targeted = Options {
+            formats: &[OutputFormat::Webp, OutputFormat::Avif],
+            tuning: &tuning,
+            ..options
+        };
+        assert_eq!(targeted.problems(), vec![String::from(
+            "invalid Avif VMAF target 120; VMAF scores are 0 to 100"
+        )]);
+        let jpeg_only = Options { tuning: &tuning, ..options };
+        assert_eq!(jpeg_only.problems().len(), 3);
+        assert!("png=90".parse::<VmafTargets>().is_err());
+    }
+}
@@ -0,0 +1,1212 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! A small, pure-Rust lossy WebP (VP8) encoder.
+//!
+//! Key frames with whole-macroblock intra prediction (the mode of each
+//! 16x16 luma and 8x8 chroma block picked by its error), one quantizer
+//! and the default token probabilities; alpha is compressed as `vp8l`.
+//! Far simpler (and larger output) than libwebp, but needs neither C nor
+//! threads: for `imager-edge`.
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::vp8l;
+
+/// Dimensions are 14 bits in the frame header.
+pub const MAX_DIMENSION: u32 = (1 << 14) - 1;
+
+/// The first partition’s size is 19 bits in the frame tag.
+const MAX_FIRST_PARTITION: usize = (1 << 19) - 1;
+
+// INTRA MODES, AS NUMBERED BY THE DECODER
+const DC_PRED: usize = 0;
+const V_PRED: usize = 1;
+const H_PRED: usize = 2;
+const TM_PRED: usize = 3;
+const MODES: [usize; 4] = [DC_PRED, V_PRED, H_PRED, TM_PRED];
+
+// TOKEN PROBABILITY TABLES, BY THE TYPE OF BLOCK
+const TYPE_Y_AFTER_Y2: usize = 0;
+const TYPE_Y2: usize = 1;
+const TYPE_CHROMA: usize = 2;
+
+// BLOCKS OF A MACROBLOCK, IN ORDER: Y2, 16 Y, 4 U AND 4 V
+const Y2_BLOCK: usize = 0;
+const Y_BLOCKS: usize = 1;
+const U_BLOCKS: usize = 17;
+const V_BLOCKS: usize = 21;
+
+/// Levels above this would overflow the `DCT_CAT6` extra bits.
+const MAX_LEVEL: i32 = 2048;
+
+///////////////////////////////////////////////////////////////////////////////
+// BOOLEAN ENCODER
+///////////////////////////////////////////////////////////////////////////////
+
+/// The arithmetic coder of RFC 6386 (section 7).
+struct BoolEncoder {
+    bytes: Vec<u8>,
+    range: u32,
+    bottom: u32,
+    bit_count: u32,
+}
+
+impl BoolEncoder {
+    fn new() -> Self {
+        BoolEncoder { bytes: Vec::new(), range: 255, bottom: 0, bit_count: 24 }
+    }
+    /// A bit that’s 0 with probability `prob / 256`.
+    fn put(&mut self, prob: u8, bit: bool) {
+        let split = 1 + (((self.range - 1) * u32::from(prob)) >> 8);
+        if bit {
+            self.bottom = self.bottom.wrapping_add(split);
+            self.range -= split;
+        } else {
+            self.range = split;
+        }
+        while self.range < 128 {
+            self.range <<= 1;
+            if self.bottom & (1 << 31) != 0 {
+                self.carry();
+            }
+            self.bottom <<= 1;
+            self.bit_count -= 1;
+            if self.bit_count == 0 {
+                self.bytes.push((self.bottom >> 24) as u8);
+                self.bottom &= (1 << 24) - 1;
+                self.bit_count = 8;
+            }
+        }
+    }
+    fn carry(&mut self) {
+        for byte in self.bytes.iter_mut().rev() {
+            if *byte == 255 {
+                *byte = 0;
+            } else {
+                *byte += 1;
+                return;
+            }
+        }
+    }
+    /// An unsigned value of `bits` bits, most significant first.
+    fn literal(&mut self, bits: u32, value: u32) {
+        for bit in (0..bits).rev() {
+            self.put(128, (value >> bit) & 1 == 1);
+        }
+    }
+    fn finish(mut self) -> Vec<u8> {
+        // PADDING THAT PUSHES OUT THE PENDING BITS (AS LIBVPX DOES)
+        for _ in 0..32 {
+            self.put(128, false);
+        }
+        self.bytes
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// TRANSFORMS
+///////////////////////////////////////////////////////////////////////////////
+
+/// The forward DCT of libvpx, which the decoder’s `idct` undoes.
+fn fdct(block: &mut [i32; 16]) {
+    for row in block.chunks_exact_mut(4) {
+        let a1 = (row[0] + row[3]) * 8;
+        let b1 = (row[1] + row[2]) * 8;
+        let c1 = (row[1] - row[2]) * 8;
+        let d1 = (row[0] - row[3]) * 8;
+        row[0] = a1 + b1;
+        row[2] = a1 - b1;
+        row[1] = (c1 * 2217 + d1 * 5352 + 14500) >> 12;
+        row[3] = (d1 * 2217 - c1 * 5352 + 7500) >> 12;
+    }
+    for i in 0..4 {
+        let a1 = block[i] + block[12 + i];
+        let b1 = block[4 + i] + block[8 + i];
+        let c1 = block[4 + i] - block[8 + i];
+        let d1 = block[i] - block[12 + i];
+        block[i] = (a1 + b1 + 7) >> 4;
+        block[8 + i] = (a1 - b1 + 7) >> 4;
+        block[4 + i] = ((c1 * 2217 + d1 * 5352 + 12000) >> 16) + i32::from(d1 != 0);
+        block[12 + i] = (d1 * 2217 - c1 * 5352 + 51000) >> 16;
+    }
+}
+
+/// The inverse DCT, exactly as decoders compute it (RFC 6386, 14.4).
+fn idct(block: &mut [i32; 16]) {
+    const C1: i64 = 20091;
+    const C2: i64 = 35468;
+    let mut tmp = [0i64; 16];
+    for i in 0..4 {
+        let x = |ix: usize| i64::from(block[ix]);
+        let a1 = x(i) + x(8 + i);
+        let b1 = x(i) - x(8 + i);
+        let c1 = ((x(4 + i) * C2) >> 16) - (x(12 + i) + ((x(12 + i) * C1) >> 16));
+        let d1 = (x(4 + i) + ((x(4 + i) * C1) >> 16)) + ((x(12 + i) * C2) >> 16);
+        tmp[i] = a1 + d1;
+        tmp[4 + i] = b1 + c1;
+        tmp[8 + i] = b1 - c1;
+        tmp[12 + i] = a1 - d1;
+    }
+    for i in 0..4 {
+        let x = |ix: usize| tmp[4 * i + ix];
+        let a1 = x(0) + x(2);
+        let b1 = x(0) - x(2);
+        let c1 = ((x(1) * C2) >> 16) - (x(3) + ((x(3) * C1) >> 16));
+        let d1 = (x(1) + ((x(1) * C1) >> 16)) + ((x(3) * C2) >> 16);
+        block[4 * i] = ((a1 + d1 + 4) >> 3) as i32;
+        block[4 * i + 1] = ((b1 + c1 + 4) >> 3) as i32;
+        block[4 * i + 2] = ((b1 - c1 + 4) >> 3) as i32;
+        block[4 * i + 3] = ((a1 - d1 + 4) >> 3) as i32;
+    }
+}
+
+/// The forward Walsh-Hadamard transform of libvpx, of the luma DCs.
+fn fwht(block: &mut [i32; 16]) {
+    for row in block.chunks_exact_mut(4) {
+        let a1 = (row[0] + row[2]) * 4;
+        let d1 = (row[1] + row[3]) * 4;
+        let c1 = (row[1] - row[3]) * 4;
+        let b1 = (row[0] - row[2]) * 4;
+        row[0] = a1 + d1 + i32::from(a1 != 0);
+        row[1] = b1 + c1;
+        row[2] = b1 - c1;
+        row[3] = a1 - d1;
+    }
+    for i in 0..4 {
+        let a1 = block[i] + block[8 + i];
+        let d1 = block[4 + i] + block[12 + i];
+        let c1 = block[4 + i] - block[12 + i];
+        let b1 = block[i] - block[8 + i];
+        let round = |x: i32| (x + i32::from(x < 0) + 3) >> 3;
+        block[i] = round(a1 + d1);
+        block[4 + i] = round(b1 + c1);
+        block[8 + i] = round(b1 - c1);
+        block[12 + i] = round(a1 - d1);
+    }
+}
+
+/// The inverse Walsh-Hadamard transform, exactly as decoders compute it
+/// (RFC 6386, 14.3).
+fn iwht(block: &mut [i32; 16]) {
+    for i in 0..4 {
+        let a1 = block[i] + block[12 + i];
+        let b1 = block[4 + i] + block[8 + i];
+        let c1 = block[4 + i] - block[8 + i];
+        let d1 = block[i] - block[12 + i];
+        block[i] = a1 + b1;
+        block[4 + i] = c1 + d1;
+        block[8 + i] = a1 - b1;
+        block[12 + i] = d1 - c1;
+    }
+    for row in block.chunks_exact_mut(4) {
+        let a1 = row[0] + row[3];
+        let b1 = row[1] + row[2];
+        let c1 = row[1] - row[2];
+        let d1 = row[0] - row[3];
+        row[0] = (a1 + b1 + 3) >> 3;
+        row[1] = (c1 + d1 + 3) >> 3;
+        row[2] = (a1 - b1 + 3) >> 3;
+        row[3] = (d1 - c1 + 3) >> 3;
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// QUANTIZATION
+///////////////////////////////////////////////////////////////////////////////
+
+/// The DC and AC steps of a type of block.
+#[derive(Clone, Copy)]
+struct Quant {
+    dc: i32,
+    ac: i32,
+}
+
+/// The quality (0 to 100) as a quantizer index (127 to 0).
+fn quant_index(quality: u8) -> usize {
+    (100 - usize::from(quality.min(100))) * 127 / 100
+}
+
+/// The Y, Y2 and chroma steps of the index, as decoders derive them.
+fn quants(index: usize) -> (Quant, Quant, Quant) {
+    let (dc, ac) = (i32::from(DC_QUANT[index]), i32::from(AC_QUANT[index]));
+    let y = Quant { dc, ac };
+    let y2 = Quant { dc: dc * 2, ac: (ac * 155 / 100).max(8) };
+    let chroma = Quant { dc: dc.min(132), ac };
+    (y, y2, chroma)
+}
+
+/// Quantizes the coefficients (from `first`) into levels, in zigzag
+/// order, and leaves them dequantized, as the decoder will see them.
+fn quantize(coeffs: &mut [i32; 16], quant: Quant, first: usize, levels: &mut [i32; 16]) {
+    for (i, zigzag) in ZIGZAG.iter().enumerate().skip(first) {
+        let coeff = &mut coeffs[*zigzag as usize];
+        // ROUNDED DC, AND AC WITH A DEAD ZONE (SMALL ONES COST MORE THAN THEY’RE WORTH)
+        let (step, bias) = if i == 0 { (quant.dc, quant.dc / 2) } else { (quant.ac, quant.ac * 3 / 8) };
+        let level = ((coeff.abs() + bias) / step).min(MAX_LEVEL) * coeff.signum();
+        levels[i] = level;
+        *coeff = level * step;
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PREDICTION
+///////////////////////////////////////////////////////////////////////////////
+
+/// A plane, padded to whole macroblocks.
+struct Plane {
+    stride: usize,
+    data: Vec<u8>,
+}
+
+impl Plane {
+    /// The prediction of the `size` block at the macroblock, from the
+    /// (reconstructed) pixels above and to its left.
+    fn predict(&self, size: usize, mbx: usize, mby: usize, mode: usize) -> [u8; 256] {
+        let (x0, y0) = (mbx * size, mby * size);
+        // OUTSIDE THE FRAME, DECODERS ASSUME 127 ABOVE AND 129 TO THE LEFT
+        let mut above = [127u8; 16];
+        let mut left = [129u8; 16];
+        if mby > 0 {
+            let start = (y0 - 1) * self.stride + x0;
+            above[..size].copy_from_slice(&self.data[start..start + size]);
+        }
+        if mbx > 0 {
+            for (y, px) in left[..size].iter_mut().enumerate() {
+                *px = self.data[(y0 + y) * self.stride + x0 - 1];
+            }
+        }
+        let corner = match (mbx, mby) {
+            (_, 0) => 127,
+            (0, _) => 129,
+            _ => self.data[(y0 - 1) * self.stride + x0 - 1],
+        };
+        let mut output = [0u8; 256];
+        let rows = output[..size * size].chunks_exact_mut(size);
+        match mode {
+            DC_PRED => {
+                let mut sum = 0;
+                let mut shift = if size == 16 { 3 } else { 2 };
+                if mby > 0 {
+                    sum += above[..size].iter().map(|x| u32::from(*x)).sum::<u32>();
+                    shift += 1;
+                }
+                if mbx > 0 {
+                    sum += left[..size].iter().map(|x| u32::from(*x)).sum::<u32>();
+                    shift += 1;
+                }
+                let dc = match (mbx, mby) {
+                    (0, 0) => 128,
+                    _ => ((sum + (1 << (shift - 1))) >> shift) as u8,
+                };
+                output[..size * size].fill(dc);
+            }
+            V_PRED => {
+                for row in rows {
+                    row.copy_from_slice(&above[..size]);
+                }
+            }
+            H_PRED => {
+                for (row, px) in rows.zip(left.iter()) {
+                    row.fill(*px);
+                }
+            }
+            _ => {
+                for (row, l) in rows.zip(left.iter()) {
+                    for (px, a) in row.iter_mut().zip(above.iter()) {
+                        let value = i32::from(*l) + i32::from(*a) - i32::from(corner);
+                        *px = value.clamp(0, 255) as u8;
+                    }
+                }
+            }
+        }
+        output
+    }
+    /// The sum of squared errors of the `size` block at the macroblock.
+    fn sse(&self, size: usize, mbx: usize, mby: usize, prediction: &[u8; 256]) -> u64 {
+        let mut sse = 0;
+        for (y, row) in prediction[..size * size].chunks_exact(size).enumerate() {
+            let start = (mby * size + y) * self.stride + mbx * size;
+            for (a, b) in self.data[start..start + size].iter().zip(row) {
+                sse += (i64::from(*a) - i64::from(*b)).pow(2) as u64;
+            }
+        }
+        sse
+    }
+    /// The residual of the 4x4 block at (`x`, `y`) in the `size` block at
+    /// the macroblock.
+    fn residual(
+        &self,
+        size: usize,
+        (mbx, mby): (usize, usize),
+        (x, y): (usize, usize),
+        prediction: &[u8; 256],
+    ) -> [i32; 16] {
+        let mut block = [0; 16];
+        for (row, values) in block.chunks_exact_mut(4).enumerate() {
+            let start = (mby * size + y + row) * self.stride + mbx * size + x;
+            let predicted = &prediction[(y + row) * size + x..][..4];
+            for ((value, px), p) in values.iter_mut().zip(&self.data[start..start + 4]).zip(predicted) {
+                *value = i32::from(*px) - i32::from(*p);
+            }
+        }
+        block
+    }
+    /// Writes the prediction plus the (inverse transformed) residual of the
+    /// 4x4 block, clamped, as decoders will.
+    fn reconstruct(
+        &mut self,
+        size: usize,
+        (mbx, mby): (usize, usize),
+        (x, y): (usize, usize),
+        prediction: &[u8; 256],
+        residual: &[i32; 16],
+    ) {
+        for (row, values) in residual.chunks_exact(4).enumerate() {
+            let start = (mby * size + y + row) * self.stride + mbx * size + x;
+            let predicted = &prediction[(y + row) * size + x..][..4];
+            for ((px, value), p) in self.data[start..start + 4].iter_mut().zip(values).zip(predicted) {
+                *px = (i32::from(*p) + value).clamp(0, 255) as u8;
+            }
+        }
+    }
+}
+
+/// The mode of the lowest error.
+fn best_mode(planes: &[&Plane], size: usize, mbx: usize, mby: usize) -> usize {
+    *MODES
+        .iter()
+        .min_by_key(|mode| {
+            planes
+                .iter()
+                .map(|x| x.sse(size, mbx, mby, &x.predict(size, mbx, mby, **mode)))
+                .sum::<u64>()
+        })
+        .unwrap()
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// TOKENS
+///////////////////////////////////////////////////////////////////////////////
+
+/// Writes the token of the level (RFC 6386, 13.2), without the EOB branch
+/// if the previous level was zero.
+fn put_level(encoder: &mut BoolEncoder, probs: &[u8; 11], level: i32, skip_eob: bool) {
+    if !skip_eob {
+        encoder.put(probs[0], true);
+    }
+    let value = level.unsigned_abs();
+    encoder.put(probs[1], value != 0);
+    if value == 0 {
+        return;
+    }
+    encoder.put(probs[2], value > 1);
+    if value > 1 {
+        encoder.put(probs[3], value > 4);
+        if value <= 4 {
+            encoder.put(probs[4], value > 2);
+            if value > 2 {
+                encoder.put(probs[5], value == 4);
+            }
+        } else {
+            encoder.put(probs[6], value > 10);
+            let category = if value <= 10 {
+                encoder.put(probs[7], value > 6);
+                usize::from(value > 6)
+            } else {
+                encoder.put(probs[8], value > 34);
+                if value <= 34 {
+                    encoder.put(probs[9], value > 18);
+                    2 + usize::from(value > 18)
+                } else {
+                    encoder.put(probs[10], value > 66);
+                    4 + usize::from(value > 66)
+                }
+            };
+            let extra = value - u32::from(DCT_CAT_BASE[category]);
+            let probs = &PROB_DCT_CAT[category];
+            let bits = probs.iter().position(|x| *x == 0).unwrap_or(probs.len());
+            for (i, prob) in probs[..bits].iter().enumerate() {
+                encoder.put(*prob, (extra >> (bits - 1 - i)) & 1 == 1);
+            }
+        }
+    }
+    encoder.put(128, level < 0);
+}
+
+/// Writes the levels of a block, from `first`, in the context of the
+/// neighbouring blocks; whether any is nonzero, the next blocks’ context.
+fn put_block(
+    encoder: &mut BoolEncoder,
+    probs: &[[[u8; 11]; 3]; 8],
+    levels: &[i32; 16],
+    first: usize,
+    context: usize,
+) -> bool {
+    let last = (first..16).rev().find(|i| levels[*i] != 0);
+    let mut context = context;
+    let mut skip_eob = false;
+    for (i, level) in levels.iter().enumerate().skip(first) {
+        let probs = &probs[COEFF_BANDS[i] as usize][context];
+        if last.is_none_or(|last| i > last) {
+            encoder.put(probs[0], false);
+            break;
+        }
+        put_level(encoder, probs, *level, skip_eob);
+        context = match level.unsigned_abs() {
+            0 => 0,
+            1 => 1,
+            _ => 2,
+        };
+        skip_eob = *level == 0;
+    }
+    last.is_some()
+}
+
+/// Whether the last blocks above or to the left had nonzero levels: the
+/// Y2 block, the 4 Y, 2 U and 2 V blocks along the edge.
+#[derive(Clone, Copy, Default)]
+struct Context {
+    y2: bool,
+    y: [bool; 4],
+    u: [bool; 2],
+    v: [bool; 2],
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ENCODE
+///////////////////////////////////////////////////////////////////////////////
+
+struct Macroblock {
+    luma_mode: usize,
+    chroma_mode: usize,
+    skip: bool,
+}
+
+/// Of the macroblock, predicted, transformed and quantized: its levels,
+/// with the planes updated to the decoder’s reconstruction.
+fn encode_macroblock(
+    planes: &mut [Plane; 3],
+    (y, y2, chroma): (Quant, Quant, Quant),
+    mbx: usize,
+    mby: usize,
+) -> (Macroblock, [[i32; 16]; 25]) {
+    let mut levels = [[0i32; 16]; 25];
+    let [luma, u, v] = planes;
+    // LUMA
+    let luma_mode = best_mode(&[luma], 16, mbx, mby);
+    let prediction = luma.predict(16, mbx, mby, luma_mode);
+    let mut coeffs = [[0; 16]; 16];
+    let mut dcs = [0; 16];
+    for (b, block) in coeffs.iter_mut().enumerate() {
+        *block = luma.residual(16, (mbx, mby), (b % 4 * 4, b / 4 * 4), &prediction);
+        fdct(block);
+        dcs[b] = block[0];
+        quantize(block, y, 1, &mut levels[Y_BLOCKS + b]);
+    }
+    fwht(&mut dcs);
+    quantize(&mut dcs, y2, 0, &mut levels[Y2_BLOCK]);
+    iwht(&mut dcs);
+    for (b, block) in coeffs.iter_mut().enumerate() {
+        block[0] = dcs[b];
+        idct(block);
+        luma.reconstruct(16, (mbx, mby), (b % 4 * 4, b / 4 * 4), &prediction, block);
+    }
+    // CHROMA
+    let chroma_mode = best_mode(&[u, v], 8, mbx, mby);
+    for (plane, first) in [(u, U_BLOCKS), (v, V_BLOCKS)] {
+        let prediction = plane.predict(8, mbx, mby, chroma_mode);
+        for b in 0..4 {
+            let (x, y) = (b % 2 * 4, b / 2 * 4);
+            let mut block = plane.residual(8, (mbx, mby), (x, y), &prediction);
+            fdct(&mut block);
+            quantize(&mut block, chroma, 0, &mut levels[first + b]);
+            idct(&mut block);
+            plane.reconstruct(8, (mbx, mby), (x, y), &prediction, &block);
+        }
+    }
+    let skip = levels.iter().flatten().all(|x| *x == 0);
+    (Macroblock { luma_mode, chroma_mode, skip }, levels)
+}
+
+/// Writes the levels of a macroblock that isn’t skipped.
+fn put_macroblock(
+    tokens: &mut BoolEncoder,
+    levels: &[[i32; 16]; 25],
+    above: &mut Context,
+    left: &mut Context,
+) {
+    let context = |a: bool, b: bool| usize::from(a) + usize::from(b);
+    let y2 = put_block(
+        tokens,
+        &COEFF_PROBS[TYPE_Y2],
+        &levels[Y2_BLOCK],
+        0,
+        context(above.y2, left.y2),
+    );
+    above.y2 = y2;
+    left.y2 = y2;
+    for b in 0..16 {
+        let (x, y) = (b % 4, b / 4);
+        let probs = &COEFF_PROBS[TYPE_Y_AFTER_Y2];
+        let nonzero = put_block(tokens, probs, &levels[Y_BLOCKS + b], 1, context(above.y[x], left.y[y]));
+        above.y[x] = nonzero;
+        left.y[y] = nonzero;
+    }
+    let chroma = [(U_BLOCKS, &mut above.u, &mut left.u), (V_BLOCKS, &mut above.v, &mut left.v)];
+    for (first, above, left) in chroma {
+        for b in 0..4 {
+            let (x, y) = (b % 2, b / 2);
+            let probs = &COEFF_PROBS[TYPE_CHROMA];
+            let nonzero = put_block(tokens, probs, &levels[first + b], 0, context(above[x], left[y]));
+            above[x] = nonzero;
+            left[y] = nonzero;
+        }
+    }
+}
+
+/// The Y, U and V planes (BT.601, as libwebp converts), padded to whole
+/// macroblocks by repeating the last row and column.
+fn planes(width: usize, height: usize, rgba: &[u8]) -> [Plane; 3] {
+    let (mbw, mbh) = (width.div_ceil(16), height.div_ceil(16));
+    let px = |x: usize, y: usize| {
+        let ix = (y.min(height - 1) * width + x.min(width - 1)) * 4;
+        (i32::from(rgba[ix]), i32::from(rgba[ix + 1]), i32::from(rgba[ix + 2]))
+    };
+    let mut luma = Plane { stride: mbw * 16, data: vec![0; mbw * 16 * mbh * 16] };
+    for (y, row) in luma.data.chunks_exact_mut(mbw * 16).enumerate() {
+        for (x, value) in row.iter_mut().enumerate() {
+            let (r, g, b) = px(x, y);
+            *value = ((16839 * r + 33059 * g + 6420 * b + (16 << 16) + (1 << 15)) >> 16) as u8;
+        }
+    }
+    let mut u = Plane { stride: mbw * 8, data: vec![0; mbw * 8 * mbh * 8] };
+    let mut v = Plane { stride: mbw * 8, data: vec![0; mbw * 8 * mbh * 8] };
+    for (ix, (u, v)) in u.data.iter_mut().zip(v.data.iter_mut()).enumerate() {
+        let (x, y) = (ix % (mbw * 8) * 2, ix / (mbw * 8) * 2);
+        // SUMS OF THE 2X2 PIXELS
+        let (mut r, mut g, mut b) = (0, 0, 0);
+        for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let (pr, pg, pb) = px(x + dx, y + dy);
+            r += pr;
+            g += pg;
+            b += pb;
+        }
+        let round = (128 << 18) + (1 << 17);
+        *u = ((-9719 * r - 19081 * g + 28800 * b + round) >> 18).clamp(0, 255) as u8;
+        *v = ((28800 * r - 24116 * g - 4684 * b + round) >> 18).clamp(0, 255) as u8;
+    }
+    [luma, u, v]
+}
+
+/// Appends a RIFF chunk, padded to an even length.
+fn put_chunk(output: &mut Vec<u8>, fourcc: &[u8; 4], payload: &[u8]) {
+    output.extend_from_slice(fourcc);
+    output.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    output.extend_from_slice(payload);
+    if payload.len() % 2 == 1 {
+        output.push(0);
+    }
+}
+
+/// Encodes RGBA8 pixels as a lossy WebP file, of the given quality (from
+/// 0 to 100).
+pub fn encode_rgba(width: u32, height: u32, rgba: &[u8], quality: u8) -> Result<Vec<u8>, String> {
+    if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(format!("{}x{} is out of range for WebP", width, height));
+    }
+    if rgba.len() != width as usize * height as usize * 4 {
+        return Err(String::from("pixel buffer doesn’t match the resolution"));
+    }
+    let index = quant_index(quality);
+    let quants = quants(index);
+    // THE LOOP FILTER HIDES BLOCKING, WHICH GROWS WITH THE QUANTIZER
+    let filter_level = (index * 5 / 16) as u32;
+    let (mbw, mbh) = ((width as usize).div_ceil(16), (height as usize).div_ceil(16));
+    let mut planes = planes(width as usize, height as usize, rgba);
+    // MACROBLOCKS, WITH THEIR TOKENS IN THE SECOND PARTITION
+    let mut tokens = BoolEncoder::new();
+    let mut macroblocks = Vec::with_capacity(mbw * mbh);
+    let mut above = vec![Context::default(); mbw];
+    for mby in 0..mbh {
+        let mut left = Context::default();
+        for (mbx, above) in above.iter_mut().enumerate() {
+            let (macroblock, levels) = encode_macroblock(&mut planes, quants, mbx, mby);
+            if macroblock.skip {
+                *above = Context::default();
+                left = Context::default();
+            } else {
+                put_macroblock(&mut tokens, &levels, above, &mut left);
+            }
+            macroblocks.push(macroblock);
+        }
+    }
+    // THE FIRST PARTITION: FRAME HEADER AND MODES
+    let skipped = macroblocks.iter().filter(|x| x.skip).count();
+    let prob_skip_false = ((macroblocks.len() - skipped) * 256 / macroblocks.len()).clamp(1, 255) as u8;
+    let mut header = BoolEncoder::new();
+    // COLOR SPACE, CLAMPING
+    header.literal(2, 0);
+    // ONE SEGMENT, OF ABSOLUTE LEVELS, SINCE SOME DECODERS (E.G. THE IMAGE
+    // CRATE’S) IGNORE THE FRAME’S QUANTIZER WITHOUT: ENABLED, UPDATING
+    // THE MAP AND THE LEVELS
+    header.literal(4, 0b1111);
+    // THE QUANTIZER, THEN THE FILTER LEVEL, OF SEGMENT 0 (NONE FOR THE REST)
+    header.literal(1, 1);
+    header.literal(7, index as u32);
+    header.literal(1, 0);
+    header.literal(3, 0);
+    header.literal(1, 1);
+    header.literal(6, filter_level);
+    header.literal(1, 0);
+    header.literal(3, 0);
+    // DEFAULT SEGMENT ID PROBABILITIES
+    header.literal(3, 0);
+    // NORMAL LOOP FILTER, NO SHARPNESS OR DELTAS
+    header.literal(1, 0);
+    header.literal(6, filter_level);
+    header.literal(3, 0);
+    header.literal(1, 0);
+    // ONE TOKEN PARTITION
+    header.literal(2, 0);
+    // THE QUANTIZER, WITH NO DELTAS
+    header.literal(7, index as u32);
+    header.literal(5, 0);
+    // REFRESH ENTROPY PROBABILITIES, NO TOKEN PROBABILITY UPDATES
+    header.literal(1, 0);
+    for prob in COEFF_UPDATE_PROBS.iter().flatten().flatten().flatten() {
+        header.put(*prob, false);
+    }
+    header.literal(1, 1);
+    header.literal(8, u32::from(prob_skip_false));
+    for macroblock in macroblocks.iter() {
+        // SEGMENT 0
+        header.put(255, false);
+        header.put(255, false);
+        header.put(prob_skip_false, macroblock.skip);
+        // NOT B_PRED, THEN THE KEY FRAME TREES OF RFC 6386 (11.2)
+        let luma_mode = macroblock.luma_mode;
+        header.put(145, true);
+        header.put(156, luma_mode >= H_PRED);
+        header.put(if luma_mode >= H_PRED { 128 } else { 163 }, luma_mode % 2 == 1);
+        let chroma_mode = macroblock.chroma_mode;
+        header.put(142, chroma_mode != DC_PRED);
+        if chroma_mode != DC_PRED {
+            header.put(114, chroma_mode != V_PRED);
+            if chroma_mode != V_PRED {
+                header.put(183, chroma_mode == TM_PRED);
+            }
+        }
+    }
+    let first_partition = header.finish();
+    if first_partition.len() > MAX_FIRST_PARTITION {
+        return Err(format!("{}x{} is too large for a VP8 frame", width, height));
+    }
+    // THE FRAME: TAG (KEY FRAME, SHOWN), START CODE AND DIMENSIONS
+    let tokens = tokens.finish();
+    let mut frame = Vec::with_capacity(10 + first_partition.len() + tokens.len());
+    let tag = (first_partition.len() as u32) << 5 | 1 << 4;
+    frame.extend_from_slice(&tag.to_le_bytes()[..3]);
+    frame.extend_from_slice(&[0x9d, 0x01, 0x2a]);
+    frame.extend_from_slice(&(width as u16).to_le_bytes());
+    frame.extend_from_slice(&(height as u16).to_le_bytes());
+    frame.extend_from_slice(&first_partition);
+    frame.extend_from_slice(&tokens);
+    // RIFF CONTAINER; EXTENDED, FOR ALPHA
+    let mut chunks = Vec::new();
+    if rgba.chunks_exact(4).any(|px| px[3] != 255) {
+        let mut header = vec![0x10, 0, 0, 0];
+        header.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        header.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        put_chunk(&mut chunks, b"VP8X", &header);
+        // LOSSLESS, UNFILTERED
+        let mut alpha = vec![1];
+        alpha.extend_from_slice(&vp8l::encode_alpha(rgba));
+        put_chunk(&mut chunks, b"ALPH", &alpha);
+    }
+    put_chunk(&mut chunks, b"VP8 ", &frame);
+    let mut output = Vec::with_capacity(12 + chunks.len());
+    output.extend_from_slice(b"RIFF");
+    output.extend_from_slice(&(4 + chunks.len() as u32).to_le_bytes());
+    output.extend_from_slice(b"WEBP");
+    output.extend_from_slice(&chunks);
+    Ok(output)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// TABLES (RFC 6386)
+///////////////////////////////////////////////////////////////////////////////
+
+type TokenProbs = [[[[u8; 11]; 3]; 8]; 4];
+
+/// The probabilities of updating each token probability.
+static COEFF_UPDATE_PROBS: TokenProbs = [
+    [
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [176, 246, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [223, 241, 252, 255, 255, 255, 255, 255, 255, 255, 255],
+            [249, 253, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 244, 252, 255, 255, 255, 255, 255, 255, 255, 255],
+            [234, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [253, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 246, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [239, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 248, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [251, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [251, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 253, 255, 254, 255, 255, 255, 255, 255, 255],
+            [250, 255, 254, 255, 254, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+    ],
+    [
+        [
+            [217, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [225, 252, 241, 253, 255, 255, 254, 255, 255, 255, 255],
+            [234, 250, 241, 250, 253, 255, 253, 254, 255, 255, 255],
+        ],
+        [
+            [255, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [223, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [238, 253, 254, 254, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 248, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [249, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [247, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [252, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [253, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [250, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+    ],
+    [
+        [
+            [186, 251, 250, 255, 255, 255, 255, 255, 255, 255, 255],
+            [234, 251, 244, 254, 255, 255, 255, 255, 255, 255, 255],
+            [251, 251, 243, 253, 254, 255, 254, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [236, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [251, 253, 253, 254, 254, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+    ],
+    [
+        [
+            [248, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [250, 254, 252, 254, 255, 255, 255, 255, 255, 255, 255],
+            [248, 254, 249, 253, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [246, 253, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [252, 254, 251, 254, 254, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 252, 255, 255, 255, 255, 255, 255, 255, 255],
+            [248, 254, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [253, 255, 254, 254, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 251, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [245, 251, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [253, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 251, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [252, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 252, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [249, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [250, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+    ],
+];
+
+/// The default token probabilities.
+static COEFF_PROBS: TokenProbs = [
+    [
+        [
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [253, 136, 254, 255, 228, 219, 128, 128, 128, 128, 128],
+            [189, 129, 242, 255, 227, 213, 255, 219, 128, 128, 128],
+            [106, 126, 227, 252, 214, 209, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 98, 248, 255, 236, 226, 255, 255, 128, 128, 128],
+            [181, 133, 238, 254, 221, 234, 255, 154, 128, 128, 128],
+            [78, 134, 202, 247, 198, 180, 255, 219, 128, 128, 128],
+        ],
+        [
+            [1, 185, 249, 255, 243, 255, 128, 128, 128, 128, 128],
+            [184, 150, 247, 255, 236, 224, 128, 128, 128, 128, 128],
+            [77, 110, 216, 255, 236, 230, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 101, 251, 255, 241, 255, 128, 128, 128, 128, 128],
+            [170, 139, 241, 252, 236, 209, 255, 255, 128, 128, 128],
+            [37, 116, 196, 243, 228, 255, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 204, 254, 255, 245, 255, 128, 128, 128, 128, 128],
+            [207, 160, 250, 255, 238, 128, 128, 128, 128, 128, 128],
+            [102, 103, 231, 255, 211, 171, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 152, 252, 255, 240, 255, 128, 128, 128, 128, 128],
+            [177, 135, 243, 255, 234, 225, 128, 128, 128, 128, 128],
+            [80, 129, 211, 255, 194, 224, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [246, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [255, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+    ],
+    [
+        [
+            [198, 35, 237, 223, 193, 187, 162, 160, 145, 155, 62],
+            [131, 45, 198, 221, 172, 176, 220, 157, 252, 221, 1],
+            [68, 47, 146, 208, 149, 167, 221, 162, 255, 223, 128],
+        ],
+        [
+            [1, 149, 241, 255, 221, 224, 255, 255, 128, 128, 128],
+            [184, 141, 234, 253, 222, 220, 255, 199, 128, 128, 128],
+            [81, 99, 181, 242, 176, 190, 249, 202, 255, 255, 128],
+        ],
+        [
+            [1, 129, 232, 253, 214, 197, 242, 196, 255, 255, 128],
+            [99, 121, 210, 250, 201, 198, 255, 202, 128, 128, 128],
+            [23, 91, 163, 242, 170, 187, 247, 210, 255, 255, 128],
+        ],
+        [
+            [1, 200, 246, 255, 234, 255, 128, 128, 128, 128, 128],
+            [109, 178, 241, 255, 231, 245, 255, 255, 128, 128, 128],
+            [44, 130, 201, 253, 205, 192, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 132, 239, 251, 219, 209, 255, 165, 128, 128, 128],
+            [94, 136, 225, 251, 218, 190, 255, 255, 128, 128, 128],
+            [22, 100, 174, 245, 186, 161, 255, 199, 128, 128, 128],
+        ],
+        [
+            [1, 182, 249, 255, 232, 235, 128, 128, 128, 128, 128],
+            [124, 143, 241, 255, 227, 234, 128, 128, 128, 128, 128],
+            [35, 77, 181, 251, 193, 211, 255, 205, 128, 128, 128],
+        ],
+        [
+            [1, 157, 247, 255, 236, 231, 255, 255, 128, 128, 128],
+            [121, 141, 235, 255, 225, 227, 255, 255, 128, 128, 128],
+            [45, 99, 188, 251, 195, 217, 255, 224, 128, 128, 128],
+        ],
+        [
+            [1, 1, 251, 255, 213, 255, 128, 128, 128, 128, 128],
+            [203, 1, 248, 255, 255, 128, 128, 128, 128, 128, 128],
+            [137, 1, 177, 255, 224, 255, 128, 128, 128, 128, 128],
+        ],
+    ],
+    [
+        [
+            [253, 9, 248, 251, 207, 208, 255, 192, 128, 128, 128],
+            [175, 13, 224, 243, 193, 185, 249, 198, 255, 255, 128],
+            [73, 17, 171, 221, 161, 179, 236, 167, 255, 234, 128],
+        ],
+        [
+            [1, 95, 247, 253, 212, 183, 255, 255, 128, 128, 128],
+            [239, 90, 244, 250, 211, 209, 255, 255, 128, 128, 128],
+            [155, 77, 195, 248, 188, 195, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 24, 239, 251, 218, 219, 255, 205, 128, 128, 128],
+            [201, 51, 219, 255, 196, 186, 128, 128, 128, 128, 128],
+            [69, 46, 190, 239, 201, 218, 255, 228, 128, 128, 128],
+        ],
+        [
+            [1, 191, 251, 255, 255, 128, 128, 128, 128, 128, 128],
+            [223, 165, 249, 255, 213, 255, 128, 128, 128, 128, 128],
+            [141, 124, 248, 255, 255, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 16, 248, 255, 255, 128, 128, 128, 128, 128, 128],
+            [190, 36, 230, 255, 236, 255, 128, 128, 128, 128, 128],
+            [149, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 226, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [247, 192, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [240, 128, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 134, 252, 255, 255, 128, 128, 128, 128, 128, 128],
+            [213, 62, 250, 255, 255, 128, 128, 128, 128, 128, 128],
+            [55, 93, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+    ],
+    [
+        [
+            [202, 24, 213, 235, 186, 191, 220, 160, 240, 175, 255],
+            [126, 38, 182, 232, 169, 184, 228, 174, 255, 187, 128],
+            [61, 46, 138, 219, 151, 178, 240, 170, 255, 216, 128],
+        ],
+        [
+            [1, 112, 230, 250, 199, 191, 247, 159, 255, 255, 128],
+            [166, 109, 228, 252, 211, 215, 255, 174, 128, 128, 128],
+            [39, 77, 162, 232, 172, 180, 245, 178, 255, 255, 128],
+        ],
+        [
+            [1, 52, 220, 246, 198, 199, 249, 220, 255, 255, 128],
+            [124, 74, 191, 243, 183, 193, 250, 221, 255, 255, 128],
+            [24, 71, 130, 219, 154, 170, 243, 182, 255, 255, 128],
+        ],
+        [
+            [1, 182, 225, 249, 219, 240, 255, 224, 128, 128, 128],
+            [149, 150, 226, 252, 216, 205, 255, 171, 128, 128, 128],
+            [28, 108, 170, 242, 183, 194, 254, 223, 255, 255, 128],
+        ],
+        [
+            [1, 81, 230, 252, 204, 203, 255, 192, 128, 128, 128],
+            [123, 102, 209, 247, 188, 196, 255, 233, 128, 128, 128],
+            [20, 95, 153, 243, 164, 173, 255, 203, 128, 128, 128],
+        ],
+        [
+            [1, 222, 248, 255, 216, 213, 128, 128, 128, 128, 128],
+            [168, 175, 246, 252, 235, 205, 255, 255, 128, 128, 128],
+            [47, 116, 215, 255, 211, 212, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 121, 236, 253, 212, 214, 255, 255, 128, 128, 128],
+            [141, 84, 213, 252, 201, 202, 255, 219, 128, 128, 128],
+            [42, 80, 160, 240, 162, 185, 255, 205, 128, 128, 128],
+        ],
+        [
+            [1, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [244, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [238, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+    ],
+];
+
+static PROB_DCT_CAT: [[u8; 12]; 6] = [
+    [159, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [165, 145, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [173, 148, 140, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [176, 155, 140, 135, 0, 0, 0, 0, 0, 0, 0, 0],
+    [180, 157, 141, 134, 130, 0, 0, 0, 0, 0, 0, 0],
+    [254, 254, 243, 230, 196, 177, 153, 140, 133, 130, 129, 0],
+];
+static DCT_CAT_BASE: [u8; 6] = [5, 7, 11, 19, 35, 67];
+static COEFF_BANDS: [u8; 16] = [0, 1, 2, 3, 6, 4, 5, 6, 6, 6, 6, 6, 6, 6, 6, 7];
+
+#[rustfmt::skip]
+static DC_QUANT: [i16; 128] = [
+      4,   5,   6,   7,   8,   9,  10,  10,
+     11,  12,  13,  14,  15,  16,  17,  17,
+     18,  19,  20,  20,  21,  21,  22,  22,
+     23,  23,  24,  25,  25,  26,  27,  28,
+     29,  30,  31,  32,  33,  34,  35,  36,
+     37,  37,  38,  39,  40,  41,  42,  43,
+     44,  45,  46,  46,  47,  48,  49,  50,
+     51,  52,  53,  54,  55,  56,  57,  58,
+     59,  60,  61,  62,  63,  64,  65,  66,
+     67,  68,  69,  70,  71,  72,  73,  74,
+     75,  76,  76,  77,  78,  79,  80,  81,
+     82,  83,  84,  85,  86,  87,  88,  89,
+     91,  93,  95,  96,  98, 100, 101, 102,
+    104, 106, 108, 110, 112, 114, 116, 118,
+    122, 124, 126, 128, 130, 132, 134, 136,
+    138, 140, 143, 145, 148, 151, 154, 157,
+];
+
+#[rustfmt::skip]
+static AC_QUANT: [i16; 128] = [
+      4,   5,   6,   7,   8,    9,  10,  11,
+      12,  13,  14,  15,  16,  17,  18,  19,
+      20,  21,  22,  23,  24,  25,  26,  27,
+      28,  29,  30,  31,  32,  33,  34,  35,
+      36,  37,  38,  39,  40,  41,  42,  43,
+      44,  45,  46,  47,  48,  49,  50,  51,
+      52,  53,  54,  55,  56,  57,  58,  60,
+      62,  64,  66,  68,  70,  72,  74,  76,
+      78,  80,  82,  84,  86,  88,  90,  92,
+      94,  96,  98, 100, 102, 104, 106, 108,
+     110, 112, 114, 116, 119, 122, 125, 128,
+     131, 134, 137, 140, 143, 146, 149, 152,
+     155, 158, 161, 164, 167, 170, 173, 177,
+     181, 185, 189, 193, 197, 201, 205, 209,
+     213, 217, 221, 225, 229, 234, 239, 245,
+     249, 254, 259, 264, 269, 274, 279, 284,
+];
+
+static ZIGZAG: [u8; 16] = [0, 1, 4, 8, 5, 2, 3, 6, 9, 12, 13, 10, 7, 11, 14, 15];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_transforms() {
+        // THE INVERSES UNDO THE FORWARD TRANSFORMS, UP TO ROUNDING
+        let source: [i32; 16] = core::array::from_fn(|i| (i as i32 * 37) % 255 - 128);
+        let mut block = source;
+        fdct(&mut block);
+        idct(&mut block);
+        for (a, b) in block.iter().zip(source.iter()) {
+            assert!((a - b).abs() <= 1, "{:?} != {:?}", block, source);
+        }
+        let source: [i32; 16] = core::array::from_fn(|i| (i as i32 * 301) % 4000 - 2000);
+        let mut block = source;
+        fwht(&mut block);
+        iwht(&mut block);
+        for (a, b) in block.iter().zip(source.iter()) {
+            assert!((a - b).abs() <= 1, "{:?} != {:?}", block, source);
+        }
+    }
+
+    fn psnr(a: &[u8], b: &[u8]) -> f64 {
+        let mse = a
+            .iter()
+            .zip(b.iter())
+            .map(|(x, y)| (f64::from(*x) - f64::from(*y)).powi(2))
+            .sum::<f64>() / a.len() as f64;
+        10.0 * (255.0 * 255.0 / mse.max(1e-9)).log10()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        // NOT WHOLE MACROBLOCKS
+        let (width, height) = (67, 41);
+        let mut rgba = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                // FLAT REGIONS, GRADIENTS AND AN EDGE
+                let flat = (x / 16 + y / 16) % 2 == 0;
+                let value = if flat { 200 } else { (x * 3 + y * 2) as u8 };
+                let edge = if x > width / 2 { 220 } else { 30 };
+                rgba.extend_from_slice(&[value, (x * 3) as u8, edge, 255]);
+            }
+        }
+        let mut previous = usize::MAX;
+        for quality in [100, 80, 20] {
+            let encoded = encode_rgba(width, height, &rgba, quality).expect("encode");
+            let decoded = image::load_from_memory_with_format(&encoded, image::ImageFormat::WebP)
+                .expect("decode")
+                .to_rgba8();
+            assert_eq!(decoded.dimensions(), (width, height));
+            let psnr = psnr(&decoded.into_raw(), &rgba);
+            assert!(psnr > if quality == 20 { 25.0 } else { 30.0 }, "{}: {}", quality, psnr);
+            assert!(encoded.len() < previous);
+            previous = encoded.len();
+        }
+        // ALPHA
+        for px in rgba.chunks_exact_mut(4).step_by(3) {
+            px[3] = 0;
+        }
+        let encoded = encode_rgba(width, height, &rgba, 80).expect("encode");
+        let decoded = image::load_from_memory_with_format(&encoded, image::ImageFormat::WebP)
+            .expect("decode")
+            .to_rgba8();
+        let alpha = decoded.pixels().map(|x| x.0[3]).collect::<Vec<_>>();
+        assert_eq!(alpha, rgba.chunks_exact(4).map(|x| x[3]).collect::<Vec<_>>());
+        assert!(encode_rgba(0, 1, &[], 80).is_err());
+        assert!(encode_rgba(2, 2, &rgba[..4], 80).is_err());
+    }
+}
@@ -0,0 +1,462 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! A small, pure-Rust lossless WebP (VP8L) encoder.
+//!
+//! Uses the subtract-green transform, greedy LZ77 backward references and
+//! a single group of prefix codes. Far simpler (and larger output) than
+//! libwebp, but needs neither C nor threads: for `imager-edge`, and the
+//! `pure-rust` builds of imager.
+use alloc::collections::BinaryHeap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+
+pub const MAX_DIMENSION: u32 = 1 << 14;
+
+const NUM_LENGTH_CODES: usize = 24;
+const NUM_DISTANCE_CODES: usize = 40;
+const CODE_LENGTH_CODES: usize = 19;
+const CODE_LENGTH_ORDER: [usize; CODE_LENGTH_CODES] =
+    [17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+const SUBTRACT_GREEN: u32 = 2;
+
+// LZ77 PARAMETERS
+const HASH_BITS: u32 = 16;
+const MAX_CHAIN: usize = 32;
+const MIN_MATCH: usize = 3;
+const MAX_LENGTH: usize = 4096;
+/// Distances are sent as plane codes (`distance + 120`), which the 40
+/// distance prefix codes can represent up to `1 << 20`.
+const MAX_DISTANCE: usize = (1 << 20) - 121;
+
+///////////////////////////////////////////////////////////////////////////////
+// BIT WRITER
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    len: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, bits: u32, count: u32) {
+        debug_assert!(count <= 32);
+        self.acc |= u64::from(bits) << self.len;
+        self.len += count;
+        while self.len >= 8 {
+            self.bytes.push(self.acc as u8);
+            self.acc >>= 8;
+            self.len -= 8;
+        }
+    }
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.bytes.push(self.acc as u8);
+        }
+        self.bytes
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PREFIX CODES
+///////////////////////////////////////////////////////////////////////////////
+
+/// Huffman code lengths, limited to `max_len` bits.
+fn code_lengths(freqs: &[u32], max_len: u8) -> Vec<u8> {
+    let mut lengths = vec![0u8; freqs.len()];
+    let used = freqs.iter().filter(|x| **x > 0).count();
+    if used <= 1 {
+        if let Some(ix) = freqs.iter().position(|x| *x > 0) {
+            lengths[ix] = 1;
+        }
+        return lengths;
+    }
+    let mut freqs = freqs.to_vec();
+    loop {
+        // NODES: LEAVES FIRST, THEN INTERNAL; PARENTS FOR COMPUTING DEPTHS
+        let mut parents: Vec<usize> = vec![usize::MAX; freqs.len()];
+        let mut heap = BinaryHeap::new();
+        for (symbol, freq) in freqs.iter().enumerate() {
+            if *freq > 0 {
+                heap.push(Reverse((u64::from(*freq), symbol)));
+            }
+        }
+        while heap.len() > 1 {
+            let Reverse((a_freq, a)) = heap.pop().expect("heap node");
+            let Reverse((b_freq, b)) = heap.pop().expect("heap node");
+            let parent = parents.len();
+            parents.push(usize::MAX);
+            parents[a] = parent;
+            parents[b] = parent;
+            heap.push(Reverse((a_freq + b_freq, parent)));
+        }
+        let mut depths = vec![0u8; parents.len()];
+        for node in (0..parents.len()).rev() {
+            if parents[node] != usize::MAX {
+                depths[node] = depths[parents[node]] + 1;
+            }
+        }
+        for (symbol, freq) in freqs.iter().enumerate() {
+            lengths[symbol] = if *freq > 0 { depths[symbol] } else { 0 };
+        }
+        if lengths.iter().all(|x| *x <= max_len) {
+            return lengths;
+        }
+        // FLATTEN THE DISTRIBUTION AND RETRY
+        for freq in freqs.iter_mut().filter(|x| **x > 0) {
+            *freq = (*freq >> 1).max(1);
+        }
+    }
+}
+
+struct PrefixCode {
+    lengths: Vec<u8>,
+    /// Bit-reversed canonical codes, ready for the LSB-first writer.
+    codes: Vec<u32>,
+    /// A single symbol is coded with zero bits.
+    single: bool,
+}
+
+impl PrefixCode {
+    fn from_lengths(lengths: Vec<u8>) -> Self {
+        let single = lengths.iter().filter(|x| **x > 0).count() <= 1;
+        let max_len = lengths.iter().copied().max().unwrap_or(0) as usize;
+        let mut counts = vec![0u32; max_len + 1];
+        for len in lengths.iter().filter(|x| **x > 0) {
+            counts[*len as usize] += 1;
+        }
+        let mut next = vec![0u32; max_len + 1];
+        let mut code = 0;
+        for len in 1..=max_len {
+            code = (code + counts[len - 1]) << 1;
+            next[len] = code;
+        }
+        let codes = lengths
+            .iter()
+            .map(|len| {
+                if *len == 0 {
+                    return 0;
+                }
+                let code = next[*len as usize];
+                next[*len as usize] += 1;
+                code.reverse_bits() >> (32 - u32::from(*len))
+            })
+            .collect();
+        PrefixCode {
+            lengths,
+            codes,
+            single,
+        }
+    }
+    fn write_symbol(&self, writer: &mut BitWriter, symbol: usize) {
+        if !self.single {
+            writer.write(self.codes[symbol], u32::from(self.lengths[symbol]));
+        }
+    }
+}
+
+/// Writes the code for the given symbol frequencies, returning it.
+fn write_prefix_code(writer: &mut BitWriter, freqs: &[u32]) -> PrefixCode {
+    let used = freqs
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| **x > 0)
+        .map(|(ix, _)| ix)
+        .collect::<Vec<_>>();
+    if used.len() <= 2 && used.iter().all(|x| *x < 256) {
+        // SIMPLE CODE
+        writer.write(1, 1);
+        let first = used.first().copied().unwrap_or(0);
+        writer.write(used.len().saturating_sub(1) as u32, 1);
+        if first < 2 {
+            writer.write(0, 1);
+            writer.write(first as u32, 1);
+        } else {
+            writer.write(1, 1);
+            writer.write(first as u32, 8);
+        }
+        let mut lengths = vec![0u8; freqs.len()];
+        lengths[first] = 1;
+        if let Some(second) = used.get(1) {
+            writer.write(*second as u32, 8);
+            lengths[*second] = 1;
+        }
+        return PrefixCode::from_lengths(lengths);
+    }
+    // NORMAL CODE
+    writer.write(0, 1);
+    let lengths = code_lengths(freqs, 15);
+    // RUN-LENGTH ENCODE ZEROS: (SYMBOL, EXTRA BITS VALUE)
+    let mut tokens: Vec<(usize, u32)> = Vec::new();
+    let mut ix = 0;
+    while ix < lengths.len() {
+        if lengths[ix] != 0 {
+            tokens.push((lengths[ix] as usize, 0));
+            ix += 1;
+            continue;
+        }
+        let mut run = lengths[ix..].iter().take_while(|x| **x == 0).count();
+        ix += run;
+        while run >= 11 {
+            let take = run.min(138);
+            tokens.push((18, (take - 11) as u32));
+            run -= take;
+        }
+        if run >= 3 {
+            tokens.push((17, (run - 3) as u32));
+        } else {
+            tokens.extend(core::iter::repeat_n((0, 0), run));
+        }
+    }
+    let mut cl_freqs = [0u32; CODE_LENGTH_CODES];
+    for (symbol, _) in tokens.iter() {
+        cl_freqs[*symbol] += 1;
+    }
+    let cl_code = PrefixCode::from_lengths(code_lengths(&cl_freqs, 7));
+    let count = CODE_LENGTH_ORDER
+        .iter()
+        .rposition(|x| cl_code.lengths[*x] > 0)
+        .map(|x| x + 1)
+        .unwrap_or(0)
+        .max(4);
+    writer.write(count as u32 - 4, 4);
+    for symbol in &CODE_LENGTH_ORDER[..count] {
+        writer.write(u32::from(cl_code.lengths[*symbol]), 3);
+    }
+    // NO `max_symbol`: EVERY CODE LENGTH IS SENT
+    writer.write(0, 1);
+    for (symbol, extra) in tokens {
+        cl_code.write_symbol(writer, symbol);
+        match symbol {
+            16 => writer.write(extra, 2),
+            17 => writer.write(extra, 3),
+            18 => writer.write(extra, 7),
+            _ => {}
+        }
+    }
+    PrefixCode::from_lengths(lengths)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// LZ77
+///////////////////////////////////////////////////////////////////////////////
+
+enum Token {
+    Literal(u32),
+    Copy { length: usize, distance: usize },
+}
+
+fn hash(pixels: &[u32], ix: usize) -> usize {
+    let key = pixels[ix] ^ pixels[ix + 1].rotate_left(7);
+    (key.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+}
+
+fn backward_references(pixels: &[u32]) -> Vec<Token> {
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; pixels.len()];
+    let insert = |ix: usize, head: &mut [usize], prev: &mut [usize]| {
+        if ix + 1 < pixels.len() {
+            let key = hash(pixels, ix);
+            prev[ix] = head[key];
+            head[key] = ix;
+        }
+    };
+    let mut tokens = Vec::new();
+    let mut ix = 0;
+    while ix < pixels.len() {
+        let mut best = (0, 0);
+        if ix + MIN_MATCH <= pixels.len() {
+            let max_length = (pixels.len() - ix).min(MAX_LENGTH);
+            let mut candidate = head[hash(pixels, ix)];
+            for _ in 0..MAX_CHAIN {
+                if candidate == usize::MAX || ix - candidate > MAX_DISTANCE {
+                    break;
+                }
+                let length = pixels[candidate..]
+                    .iter()
+                    .zip(&pixels[ix..ix + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best.0 {
+                    best = (length, ix - candidate);
+                }
+                candidate = prev[candidate];
+            }
+        }
+        if best.0 >= MIN_MATCH {
+            for jx in ix..ix + best.0 {
+                insert(jx, &mut head, &mut prev);
+            }
+            tokens.push(Token::Copy {
+                length: best.0,
+                distance: best.1,
+            });
+            ix += best.0;
+        } else {
+            insert(ix, &mut head, &mut prev);
+            tokens.push(Token::Literal(pixels[ix]));
+            ix += 1;
+        }
+    }
+    tokens
+}
+
+/// The prefix symbol, extra bit count and extra bits of an LZ77 value
+/// (a length, or a distance plane code), which starts at 1.
+fn prefix_encode(value: usize) -> (usize, u32, u32) {
+    let value = value - 1;
+    if value < 4 {
+        return (value, 0, 0);
+    }
+    let high = usize::BITS - 1 - value.leading_zeros();
+    let second = (value >> (high - 1)) & 1;
+    let extra_bits = high - 1;
+    let symbol = 2 * high as usize + second;
+    (symbol, extra_bits, (value & ((1 << extra_bits) - 1)) as u32)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ENCODE
+///////////////////////////////////////////////////////////////////////////////
+
+/// The alpha of RGBA8 pixels, as the lossless payload of a `vp8` file’s
+/// `ALPH` chunk: the values as green, in an image stream with no header.
+pub(crate) fn encode_alpha(rgba: &[u8]) -> Vec<u8> {
+    let pixels = rgba.chunks_exact(4).map(|px| u32::from(px[3]) << 8).collect::<Vec<_>>();
+    let mut writer = BitWriter::default();
+    // NO TRANSFORMS
+    writer.write(0, 1);
+    write_pixels(&mut writer, &pixels);
+    writer.finish()
+}
+
+/// The rest of an image stream, after its transforms: the prefix codes,
+/// then the LZ77 tokens of the (transformed) ARGB pixels.
+fn write_pixels(writer: &mut BitWriter, pixels: &[u32]) {
+    let tokens = backward_references(pixels);
+    // SYMBOL STATISTICS
+    let mut green = vec![0u32; 256 + NUM_LENGTH_CODES];
+    let mut red = vec![0u32; 256];
+    let mut blue = vec![0u32; 256];
+    let mut alpha = vec![0u32; 256];
+    let mut dist = vec![0u32; NUM_DISTANCE_CODES];
+    for token in tokens.iter() {
+        match token {
+            Token::Literal(argb) => {
+                let [a, r, g, b] = argb.to_be_bytes();
+                green[g as usize] += 1;
+                red[r as usize] += 1;
+                blue[b as usize] += 1;
+                alpha[a as usize] += 1;
+            }
+            Token::Copy { length, distance } => {
+                green[256 + prefix_encode(*length).0] += 1;
+                dist[prefix_encode(distance + 120).0] += 1;
+            }
+        }
+    }
+    // NO COLOR CACHE, NO META PREFIX CODES
+    writer.write(0, 1);
+    writer.write(0, 1);
+    let green = write_prefix_code(writer, &green);
+    let red = write_prefix_code(writer, &red);
+    let blue = write_prefix_code(writer, &blue);
+    let alpha = write_prefix_code(writer, &alpha);
+    let dist = write_prefix_code(writer, &dist);
+    // PIXELS
+    for token in tokens {
+        match token {
+            Token::Literal(argb) => {
+                let [a, r, g, b] = argb.to_be_bytes();
+                green.write_symbol(writer, g as usize);
+                red.write_symbol(writer, r as usize);
+                blue.write_symbol(writer, b as usize);
+                alpha.write_symbol(writer, a as usize);
+            }
+            Token::Copy { length, distance } => {
+                let (symbol, extra_bits, extra) = prefix_encode(length);
+                green.write_symbol(writer, 256 + symbol);
+                writer.write(extra, extra_bits);
+                let (symbol, extra_bits, extra) = prefix_encode(distance + 120);
+                dist.write_symbol(writer, symbol);
+                writer.write(extra, extra_bits);
+            }
+        }
+    }
+}
+
+/// Encodes RGBA8 pixels as a lossless WebP file.
+pub fn encode_rgba(width: u32, height: u32, rgba: &[u8]) -> Result<Vec<u8>, String> {
+    if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(format!("{}x{} is out of range for WebP", width, height));
+    }
+    if rgba.len() != width as usize * height as usize * 4 {
+        return Err(String::from("pixel buffer doesn’t match the resolution"));
+    }
+    let has_alpha = rgba.chunks_exact(4).any(|px| px[3] != 255);
+    // ARGB, AFTER THE SUBTRACT-GREEN TRANSFORM
+    let pixels = rgba
+        .chunks_exact(4)
+        .map(|px| {
+            let (r, g, b, a) = (px[0], px[1], px[2], px[3]);
+            let r = r.wrapping_sub(g);
+            let b = b.wrapping_sub(g);
+            u32::from_be_bytes([a, r, g, b])
+        })
+        .collect::<Vec<_>>();
+    // HEADER
+    let mut writer = BitWriter::default();
+    writer.write(0x2f, 8);
+    writer.write(width - 1, 14);
+    writer.write(height - 1, 14);
+    writer.write(u32::from(has_alpha), 1);
+    writer.write(0, 3);
+    // TRANSFORMS
+    writer.write(1, 1);
+    writer.write(SUBTRACT_GREEN, 2);
+    writer.write(0, 1);
+    write_pixels(&mut writer, &pixels);
+    let payload = writer.finish();
+    // RIFF CONTAINER
+    let padded_len = payload.len() + payload.len() % 2;
+    let mut output = Vec::with_capacity(20 + padded_len);
+    output.extend_from_slice(b"RIFF");
+    output.extend_from_slice(&(12 + padded_len as u32).to_le_bytes());
+    output.extend_from_slice(b"WEBPVP8L");
+    output.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    output.extend_from_slice(&payload);
+    if payload.len() % 2 == 1 {
+        output.push(0);
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let (width, height) = (67, 41);
+        let mut rgba = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                // FLAT REGIONS (BACKWARD REFERENCES), GRADIENTS AND ALPHA
+                let flat = (x / 16 + y / 16) % 2 == 0;
+                let value = if flat { 200 } else { (x * 3 + y * 5) as u8 };
+                rgba.extend_from_slice(&[value, (x * 4) as u8, (y * 6) as u8, 255 - (x as u8)]);
+            }
+        }
+        let encoded = encode_rgba(width, height, &rgba).expect("encode");
+        let decoded = image::load_from_memory_with_format(&encoded, image::ImageFormat::WebP)
+            .expect("decode")
+            .to_rgba8();
+        assert_eq!(decoded.dimensions(), (width, height));
+        assert_eq!(decoded.into_raw(), rgba);
+    }
+}
@@ -0,0 +1,11 @@
+# Builds the workers module, i.e. `target/wasm32-unknown-unknown/edge/imager_edge.wasm`.
+# Requires the `wasm32-unknown-unknown` target, and `wasm-opt` (binaryen) on the `PATH`.
+set -e
+cd "$(dirname "$0")/../.."
+
+cargo build -p imager-edge --target wasm32-unknown-unknown --profile edge
+
+OUT=target/wasm32-unknown-unknown/edge
+wasm-opt -Oz --strip-debug --strip-producers -o $OUT/imager_edge.opt.wasm $OUT/imager_edge.wasm
+mv $OUT/imager_edge.opt.wasm $OUT/imager_edge.wasm
+ls -lh $OUT/imager_edge.wasm
@@ -0,0 +1,218 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Imager for edge runtimes (e.g. Cloudflare Workers): resize and encode
+//! small images, with no threads, no filesystem and no C dependencies.
+//!
+//! WebP output is lossy at a fixed quality (see `vp8`), or lossless (see
+//! `vp8l`); AVIF output is lossy at a fixed quality, with rav1e (through
+//! ravif) on a single thread. The quality search of the full imager needs
+//! libwebp and VMAF, which don’t build for these runtimes.
+//! Build the module with `scripts/build.sh`.
+use image::{DynamicImage, GenericImageView};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::io::Cursor;
+
+pub use imager_core::data::{OutputFormat, Resolution};
+
+pub use imager_core::{vp8, vp8l};
+
+/// Of rav1e, from 1 (slowest) to 10: edge runtimes bound the CPU time of a
+/// request, and AV1 is slow to encode.
+const AVIF_SPEED: u8 = 8;
+
+/// Sources are rejected above this many pixels, before decoding, to stay
+/// within the memory limits of edge runtimes.
+pub const MAX_SOURCE_PIXELS: u64 = 4096 * 2048;
+
+///////////////////////////////////////////////////////////////////////////////
+// OPTIONS
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EdgeOptions {
+    /// `Webp`, `Avif` or `Png`.
+    pub format: OutputFormat,
+    /// Downscale (preserving the aspect ratio) to fit.
+    pub max_size: Option<Resolution>,
+    /// Of lossy WebP and AVIF, from 0 to 100.
+    pub quality: u8,
+    /// WebP without loss, ignoring `quality`.
+    pub lossless: bool,
+}
+
+impl Default for EdgeOptions {
+    fn default() -> Self {
+        EdgeOptions {
+            format: OutputFormat::Webp,
+            max_size: None,
+            quality: 80,
+            lossless: false,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// TRANSFORM
+///////////////////////////////////////////////////////////////////////////////
+
+pub fn transform(source: &[u8], options: &EdgeOptions) -> Result<Vec<u8>, String> {
+    let reader = || {
+        image::io::Reader::new(Cursor::new(source))
+            .with_guessed_format()
+            .map_err(|e| e.to_string())
+    };
+    let (width, height) = reader()?.into_dimensions().map_err(|e| e.to_string())?;
+    if u64::from(width) * u64::from(height) > MAX_SOURCE_PIXELS {
+        return Err(format!(
+            "{}x{} exceeds the edge limit of {} pixels",
+            width, height, MAX_SOURCE_PIXELS
+        ));
+    }
+    let mut image = reader()?.decode().map_err(|e| e.to_string())?;
+    if let Some(res) = &options.max_size {
+        if (res.width, res.height) < image.dimensions() {
+            image = image.resize(res.width, res.height, image::imageops::FilterType::Lanczos3);
+        }
+    }
+    encode(&image, options)
+}
+
+fn encode(image: &DynamicImage, options: &EdgeOptions) -> Result<Vec<u8>, String> {
+    match options.format {
+        OutputFormat::Webp => {
+            let (width, height) = image.dimensions();
+            let rgba = image.to_rgba8();
+            if options.lossless {
+                vp8l::encode_rgba(width, height, rgba.as_raw())
+            } else {
+                vp8::encode_rgba(width, height, rgba.as_raw(), options.quality)
+            }
+        }
+        OutputFormat::Png => {
+            let mut output = Cursor::new(Vec::new());
+            image
+                .write_to(&mut output, image::ImageOutputFormat::Png)
+                .map_err(|e| e.to_string())?;
+            Ok(output.into_inner())
+        }
+        OutputFormat::Jpeg => Err(String::from(
+            "JPEG output needs mozjpeg, which the edge build doesn’t include",
+        )),
+        OutputFormat::Tiff => Err(String::from(
+            "TIFF output needs the tiff crate, which the edge build doesn’t include",
+        )),
+        OutputFormat::Avif => {
+            let (width, height) = image.dimensions();
+            let pixels = image
+                .to_rgba8()
+                .pixels()
+                .map(|px| ravif::RGBA8::new(px[0], px[1], px[2], px[3]))
+                .collect::<Vec<_>>();
+            let quality = f32::from(options.quality.clamp(1, 100));
+            let encoded = ravif::Encoder::new()
+                .with_quality(quality)
+                .with_alpha_quality(quality)
+                .with_speed(AVIF_SPEED)
+                .with_num_threads(Some(1))
+                .encode_rgba(ravif::Img::new(&pixels[..], width as usize, height as usize))
+                .map_err(|e| e.to_string())?;
+            Ok(encoded.avif_file)
+        }
+        OutputFormat::Jxl => Err(String::from(
+            "JPEG XL output needs libjxl, which the edge build doesn’t include",
+        )),
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// WASM ABI
+///////////////////////////////////////////////////////////////////////////////
+
+// The host copies its inputs into buffers from `imager_edge_alloc`, calls
+// `imager_edge_transform`, then reads the result (the encoded image, or a
+// UTF-8 error message) via `imager_edge_result_ptr`/`_len`.
+
+thread_local! {
+    static RESULT: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+#[no_mangle]
+pub extern "C" fn imager_edge_alloc(len: usize) -> *mut u8 {
+    let mut buffer = Vec::<u8>::with_capacity(len);
+    let ptr = buffer.as_mut_ptr();
+    std::mem::forget(buffer);
+    ptr
+}
+
+/// # Safety
+///
+/// `ptr` and `len` must come from the same `imager_edge_alloc` call.
+#[no_mangle]
+pub unsafe extern "C" fn imager_edge_free(ptr: *mut u8, len: usize) {
+    drop(Vec::from_raw_parts(ptr, 0, len));
+}
+
+/// Returns 0 on success, 1 on failure. `options` is `EdgeOptions` JSON.
+///
+/// # Safety
+///
+/// Both buffers must be valid for reads of their given lengths.
+#[no_mangle]
+pub unsafe extern "C" fn imager_edge_transform(
+    source: *const u8,
+    source_len: usize,
+    options: *const u8,
+    options_len: usize,
+) -> i32 {
+    let source = std::slice::from_raw_parts(source, source_len);
+    let options = std::slice::from_raw_parts(options, options_len);
+    let result = serde_json::from_slice::<EdgeOptions>(options)
+        .map_err(|e| e.to_string())
+        .and_then(|options| transform(source, &options));
+    let (status, output) = match result {
+        Ok(x) => (0, x),
+        Err(e) => (1, e.into_bytes()),
+    };
+    RESULT.with(|x| *x.borrow_mut() = output);
+    status
+}
+
+#[no_mangle]
+pub extern "C" fn imager_edge_result_ptr() -> *const u8 {
+    RESULT.with(|x| x.borrow().as_ptr())
+}
+
+#[no_mangle]
+pub extern "C" fn imager_edge_result_len() -> usize {
+    RESULT.with(|x| x.borrow().len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_transform() {
+        let source = include_bytes!("../../imager/assets/test/1.jpeg");
+        let mut options = EdgeOptions {
+            format: OutputFormat::Webp,
+            max_size: Some(Resolution::new(200, 200)),
+            ..EdgeOptions::default()
+        };
+        let lossy = transform(source, &options).expect("transform");
+        options.lossless = true;
+        let lossless = transform(source, &options).expect("transform");
+        assert!(lossy.len() < lossless.len());
+        for output in [&lossy, &lossless] {
+            let output = image::load_from_memory(output).expect("decode output");
+            assert!(output.width() <= 200 && output.height() <= 200);
+        }
+        options.format = OutputFormat::Avif;
+        let avif = transform(source, &options).expect("transform");
+        assert_eq!(&avif[4..12], b"ftypavif");
+        assert!(avif.len() < lossless.len());
+    }
+}
@@ -0,0 +1,59 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+/* The C ABI of imager (`libimager_ffi`), for calling the optimizer in
+ * process. Every function returning `int32_t` returns an IMAGER_* status;
+ * on failure, `imager_last_error` gives the message. The library may be
+ * called from several threads at once, with an options handle per
+ * thread. */
+#ifndef IMAGER_H
+#define IMAGER_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define IMAGER_OK 0
+/* E.g. a null pointer, or an unknown format. */
+#define IMAGER_INVALID_ARGUMENT 1
+#define IMAGER_DECODE_ERROR 2
+#define IMAGER_ENCODE_ERROR 3
+/* The format (or a stage) needs a cargo feature the library lacks. */
+#define IMAGER_UNSUPPORTED 4
+
+typedef struct ImagerOptions ImagerOptions;
+
+/* Owned by the library, until passed to `imager_output_free`. */
+typedef struct ImagerOutput {
+    uint8_t *data;
+    size_t len;
+} ImagerOutput;
+
+/* The defaults: the source's own format, at its own size. */
+ImagerOptions *imager_options_new(void);
+void imager_options_free(ImagerOptions *options);
+/* E.g. "webp", as the CLI's `--format`. */
+int32_t imager_options_set_format(ImagerOptions *options, const char *format);
+/* Downscale (preserving the aspect ratio) to fit. */
+int32_t imager_options_set_max_size(ImagerOptions *options, uint32_t width, uint32_t height);
+/* The max butteraugli distance of lossy outputs, in place of the VMAF
+ * search. */
+int32_t imager_options_set_max_distance(ImagerOptions *options, double distance);
+/* Spend more time searching for smaller outputs. */
+int32_t imager_options_set_extreme(ImagerOptions *options, bool extreme);
+
+/* Optimizes the image of `len` bytes at `source`, into `output`. `options`
+ * may be null, for the defaults. */
+int32_t imager_encode(const uint8_t *source, size_t len, const ImagerOptions *options,
+                      ImagerOutput *output);
+/* Releases the output's data, and resets it. */
+void imager_output_free(ImagerOutput *output);
+
+/* Of the calling thread's last failure, or null; valid until its next
+ * call. */
+const char *imager_last_error(void);
+/* E.g. "0.3.3". */
+const char *imager_version(void);
+
+#endif
@@ -0,0 +1,278 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! A C ABI for imager (see `include/imager.h`), so other languages (e.g.
+//! Python via ctypes, Go via cgo) can call the optimizer in process, rather
+//! than shelling out to the CLI. Encodes run the same `Job` as the CLI.
+//!
+//! Options are an opaque handle, from `imager_options_new`, configured by
+//! setters; outputs are buffers owned by the library, until passed to
+//! `imager_output_free`. Every function returns an `IMAGER_*` status; the
+//! message of the last failure (of the calling thread) is given by
+//! `imager_last_error`.
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+
+use imager::api::{panic_message, Job};
+use imager::data::{OutputFormat, OutputSize, Resolution};
+use imager::error::ImagerError;
+
+pub const IMAGER_OK: i32 = 0;
+/// E.g. a null pointer, or an unknown format.
+pub const IMAGER_INVALID_ARGUMENT: i32 = 1;
+pub const IMAGER_DECODE_ERROR: i32 = 2;
+pub const IMAGER_ENCODE_ERROR: i32 = 3;
+/// The format (or a stage) needs a cargo feature the library lacks.
+pub const IMAGER_UNSUPPORTED: i32 = 4;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Records the message, for `imager_last_error`, returning the status.
+fn fail(status: i32, message: impl ToString) -> i32 {
+    // INTERIOR NULS WOULD TRUNCATE THE MESSAGE
+    let message = message.to_string().replace('\0', " ");
+    LAST_ERROR.with(|x| *x.borrow_mut() = CString::new(message).ok());
+    status
+}
+
+fn status(error: &ImagerError) -> i32 {
+    match error {
+        ImagerError::Decode(_) => IMAGER_DECODE_ERROR,
+        ImagerError::InvalidInput(_) => IMAGER_INVALID_ARGUMENT,
+        ImagerError::FeatureDisabled(_) => IMAGER_UNSUPPORTED,
+        ImagerError::Encode(_) | ImagerError::Io { .. } | ImagerError::Transfer(_) => IMAGER_ENCODE_ERROR,
+    }
+}
+
+/// Runs the call, so that panics (which can’t unwind into C) are statuses.
+fn guard(call: impl FnOnce() -> i32) -> i32 {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(call))
+        .unwrap_or_else(|panic| fail(IMAGER_ENCODE_ERROR, panic_message(panic)))
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// OPTIONS
+///////////////////////////////////////////////////////////////////////////////
+
+/// `ImagerOptions`, opaque to C.
+#[derive(Debug, Clone, Default)]
+pub struct ImagerOptions {
+    /// The source’s own format, if none.
+    format: Option<OutputFormat>,
+    max_size: Option<Resolution>,
+    max_distance: Option<f64>,
+    extreme: bool,
+}
+
+#[no_mangle]
+pub extern "C" fn imager_options_new() -> *mut ImagerOptions {
+    Box::into_raw(Box::default())
+}
+
+/// # Safety
+///
+/// `options` must come from `imager_options_new` (or be null), and not be
+/// used after.
+#[no_mangle]
+pub unsafe extern "C" fn imager_options_free(options: *mut ImagerOptions) {
+    if !options.is_null() {
+        drop(Box::from_raw(options));
+    }
+}
+
+/// E.g. `webp`, as the CLI’s `--format`.
+///
+/// # Safety
+///
+/// `options` must come from `imager_options_new`, and `format` must be a
+/// C string.
+#[no_mangle]
+pub unsafe extern "C" fn imager_options_set_format(options: *mut ImagerOptions, format: *const c_char) -> i32 {
+    let Some(options) = options.as_mut() else {
+        return fail(IMAGER_INVALID_ARGUMENT, "null options");
+    };
+    if format.is_null() {
+        return fail(IMAGER_INVALID_ARGUMENT, "null format");
+    }
+    match CStr::from_ptr(format).to_string_lossy().parse() {
+        Ok(format) => {
+            options.format = Some(format);
+            IMAGER_OK
+        }
+        Err(message) => fail(IMAGER_INVALID_ARGUMENT, message),
+    }
+}
+
+/// Downscale (preserving the aspect ratio) to fit.
+///
+/// # Safety
+///
+/// `options` must come from `imager_options_new`.
+#[no_mangle]
+pub unsafe extern "C" fn imager_options_set_max_size(options: *mut ImagerOptions, width: u32, height: u32) -> i32 {
+    let Some(options) = options.as_mut() else {
+        return fail(IMAGER_INVALID_ARGUMENT, "null options");
+    };
+    if width == 0 || height == 0 {
+        return fail(IMAGER_INVALID_ARGUMENT, "the max size must be positive");
+    }
+    options.max_size = Some(Resolution::new(width, height));
+    IMAGER_OK
+}
+
+/// The max butteraugli distance of lossy outputs, in place of the VMAF
+/// search (see `Job::max_distance`).
+///
+/// # Safety
+///
+/// `options` must come from `imager_options_new`.
+#[no_mangle]
+pub unsafe extern "C" fn imager_options_set_max_distance(options: *mut ImagerOptions, distance: f64) -> i32 {
+    let Some(options) = options.as_mut() else {
+        return fail(IMAGER_INVALID_ARGUMENT, "null options");
+    };
+    options.max_distance = Some(distance);
+    IMAGER_OK
+}
+
+/// Spend more time searching for smaller outputs.
+///
+/// # Safety
+///
+/// `options` must come from `imager_options_new`.
+#[no_mangle]
+pub unsafe extern "C" fn imager_options_set_extreme(options: *mut ImagerOptions, extreme: bool) -> i32 {
+    let Some(options) = options.as_mut() else {
+        return fail(IMAGER_INVALID_ARGUMENT, "null options");
+    };
+    options.extreme = extreme;
+    IMAGER_OK
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ENCODING
+///////////////////////////////////////////////////////////////////////////////
+
+/// `ImagerOutput`: the encoded image, owned by the library.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ImagerOutput {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+/// Optimizes the image in `source`, into `output` (unless failing).
+///
+/// # Safety
+///
+/// `source` must be valid for reads of `len` bytes, `options` must come
+/// from `imager_options_new` (or be null, for the defaults), and `output`
+/// must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn imager_encode(
+    source: *const u8,
+    len: usize,
+    options: *const ImagerOptions,
+    output: *mut ImagerOutput,
+) -> i32 {
+    if source.is_null() || output.is_null() {
+        return fail(IMAGER_INVALID_ARGUMENT, "null source or output");
+    }
+    let source = std::slice::from_raw_parts(source, len);
+    let options = options.as_ref().cloned().unwrap_or_default();
+    guard(|| {
+        let mut job = Job::new(source)
+            .formats(options.format.clone())
+            .extreme(options.extreme);
+        if let Some(max_size) = options.max_size.clone() {
+            job = job.max_size(OutputSize::Px(max_size));
+        }
+        if let Some(distance) = options.max_distance {
+            job = job.max_distance(distance);
+        }
+        match job.run() {
+            Ok(outputs) => {
+                let data = outputs.into_iter().next().expect("an output").data.into_boxed_slice();
+                let len = data.len();
+                *output = ImagerOutput {
+                    data: Box::into_raw(data) as *mut u8,
+                    len,
+                };
+                IMAGER_OK
+            }
+            Err(error) => fail(status(&error), &error),
+        }
+    })
+}
+
+/// # Safety
+///
+/// `output` must have been filled by `imager_encode` (or be null), and not
+/// be freed twice.
+#[no_mangle]
+pub unsafe extern "C" fn imager_output_free(output: *mut ImagerOutput) {
+    let Some(output) = output.as_mut() else {
+        return;
+    };
+    if !output.data.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(output.data, output.len)));
+    }
+    *output = ImagerOutput {
+        data: std::ptr::null_mut(),
+        len: 0,
+    };
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// MISC
+///////////////////////////////////////////////////////////////////////////////
+
+/// Of the calling thread’s last failure, or null; valid until its next
+/// call.
+#[no_mangle]
+pub extern "C" fn imager_last_error() -> *const c_char {
+    LAST_ERROR.with(|x| x.borrow().as_ref().map_or(std::ptr::null(), |x| x.as_ptr()))
+}
+
+/// E.g. `0.3.3`; static.
+#[no_mangle]
+pub extern "C" fn imager_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let source = include_bytes!("../../imager/assets/test/1.jpeg");
+        unsafe {
+            let options = imager_options_new();
+            assert_eq!(imager_options_set_format(options, c"png".as_ptr()), IMAGER_OK);
+            assert_eq!(imager_options_set_max_size(options, 64, 64), IMAGER_OK);
+            let mut output = ImagerOutput {
+                data: std::ptr::null_mut(),
+                len: 0,
+            };
+            assert_eq!(imager_encode(source.as_ptr(), source.len(), options, &mut output), IMAGER_OK);
+            let encoded = std::slice::from_raw_parts(output.data, output.len);
+            let decoded = image::load_from_memory_with_format(encoded, image::ImageFormat::Png).expect("decode");
+            assert!(decoded.width() <= 64 && decoded.height() <= 64);
+            imager_output_free(&mut output);
+            assert!(output.data.is_null());
+            // FAILURES ARE STATUSES, WITH MESSAGES
+            assert_eq!(imager_options_set_format(options, c"bmp".as_ptr()), IMAGER_INVALID_ARGUMENT);
+            assert_eq!(
+                CStr::from_ptr(imager_last_error()).to_str(),
+                Ok("Unknown or unsupported output format bmp")
+            );
+            let status = imager_encode(b"not an image".as_ptr(), 12, options, &mut output);
+            assert_eq!(status, IMAGER_DECODE_ERROR);
+            imager_options_free(options);
+        }
+    }
+}
@@ -0,0 +1,15 @@
+# Generates the Swift and Kotlin bindings into `imager-mobile/bindings`.
+#
+# The native library itself is built per platform, e.g.
+# `cargo build --release --target aarch64-apple-ios` (iOS) or
+# `cargo ndk -t arm64-v8a build --release` (Android).
+set -e
+cd "$(dirname "$0")/.."
+
+cargo build --release
+for LANGUAGE in swift kotlin
+do
+    cargo run --bin uniffi-bindgen -- generate src/imager.udl \
+        --language $LANGUAGE \
+        --out-dir bindings/$LANGUAGE
+done
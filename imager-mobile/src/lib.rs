@@ -0,0 +1,84 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Swift/Kotlin bindings (via UniFFI) for on-device optimization, using the
+//! exact same `OptJob` as the CLI and server. See `src/imager.udl` for the
+//! exposed interface, and `scripts/generate-bindings.sh`.
+use imager::api::OptJob;
+use imager::data::{OutputFormat, Resolution};
+use imager::decode::DecodeOptions;
+
+uniffi::include_scaffolding!("imager");
+
+#[derive(Debug, Clone, Copy)]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+    Webp,
+}
+
+impl From<ImageFormat> for OutputFormat {
+    fn from(format: ImageFormat) -> Self {
+        match format {
+            ImageFormat::Jpeg => OutputFormat::Jpeg,
+            ImageFormat::Png => OutputFormat::Png,
+            ImageFormat::Webp => OutputFormat::Webp,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OptOptions {
+    pub format: ImageFormat,
+    /// Both or neither of `max_width` and `max_height` must be given.
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    pub extreme: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct OptOutput {
+    pub output: Vec<u8>,
+    pub input_class: String,
+    pub vmaf_score: Option<f64>,
+}
+
+#[derive(Debug)]
+pub enum ImagerError {
+    /// The source isn’t a (supported) image.
+    Decode,
+    Encode,
+}
+
+impl std::fmt::Display for ImagerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Decode => write!(f, "failed to decode the source image"),
+            Self::Encode => write!(f, "failed to encode the output image"),
+        }
+    }
+}
+
+impl std::error::Error for ImagerError {}
+
+pub fn optimize(source: Vec<u8>, options: OptOptions) -> Result<OptOutput, ImagerError> {
+    let max_size = match (options.max_width, options.max_height) {
+        (Some(width), Some(height)) => Some(Resolution::new(width, height)),
+        _ => None,
+    };
+    let decode_options = DecodeOptions {
+        max_size,
+        ..DecodeOptions::default()
+    };
+    let mut opt_job =
+        OptJob::new_with_options(&source, &decode_options).map_err(|_| ImagerError::Decode)?;
+    opt_job.output_format(options.format.into());
+    let (output, meta) = opt_job
+        .run(options.extreme)
+        .map_err(|_| ImagerError::Encode)?;
+    Ok(OptOutput {
+        output,
+        input_class: format!("{:?}", meta.input_class),
+        vmaf_score: meta.vmaf_score,
+    })
+}
@@ -0,0 +1,147 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! AV1 encoding (via rav1e) of a `VideoBuffer` to WebM.
+//!
+//! rav1e is a pure Rust encoder; it’s slower than libvpx’s VP9 at like
+//! settings, but its outputs are smaller at the same quality.
+use rav1e::prelude::{
+    ChromaSampling,
+    Config,
+    Context,
+    EncoderConfig,
+    EncoderStatus,
+    FrameType,
+    Rational,
+    SpeedSettings,
+};
+
+use crate::data::VideoBuffer;
+use crate::format::encode::{Packet, Track};
+
+
+///////////////////////////////////////////////////////////////////////////////
+// DATA TYPES
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// rav1e’s speed preset, from 0 (slowest, best) to 10.
+    pub speed: u8,
+    /// The base quantizer, from 0 (lossless) to 255.
+    pub quantizer: u8,
+    /// Frames per second, as a fraction (e.g. `(30000, 1001)`).
+    pub frame_rate: (u32, u32),
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            speed: 6,
+            quantizer: 100,
+            frame_rate: (30, 1),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// HELPERS
+///////////////////////////////////////////////////////////////////////////////
+
+fn new_context(stream: &VideoBuffer, options: &Options) -> Result<Context<u8>, String> {
+    let (width, height) = stream.dimensions();
+    let (rate_num, rate_den) = options.frame_rate;
+    let encoder = EncoderConfig {
+        width: width as usize,
+        height: height as usize,
+        bit_depth: 8,
+        chroma_sampling: ChromaSampling::Cs420,
+        // ONE TICK PER FRAME
+        time_base: Rational::new(rate_den as u64, rate_num as u64),
+        quantizer: options.quantizer as usize,
+        speed_settings: SpeedSettings::from_preset(options.speed.min(10)),
+        ..Default::default()
+    };
+    let threads = std::thread::available_parallelism()
+        .map(|x| x.get())
+        .unwrap_or(1);
+    Config::new()
+        .with_encoder_config(encoder)
+        .with_threads(threads)
+        .new_context()
+        .map_err(|error| format!("rav1e: {:?}", error))
+}
+
+/// Takes the encoder’s output so far; whether it’s done (every frame,
+/// after a flush, is out).
+fn drain(
+    context: &mut Context<u8>,
+    (rate_num, rate_den): (u32, u32),
+    packets: &mut Vec<Packet>,
+) -> Result<bool, String> {
+    loop {
+        match context.receive_packet() {
+            Ok(packet) => {
+                // FRAMES TO MILLISECONDS
+                let timestamp = packet.input_frameno * 1000 * rate_den as u64 / rate_num as u64;
+                packets.push(Packet {
+                    data: packet.data,
+                    timestamp,
+                    keyframe: packet.frame_type == FrameType::KEY,
+                });
+            }
+            // E.G. A FRAME ONLY USED FOR REFERENCE
+            Err(EncoderStatus::Encoded) => (),
+            Err(EncoderStatus::NeedMoreData) => return Ok(false),
+            Err(EncoderStatus::LimitReached) => return Ok(true),
+            Err(error) => return Err(format!("rav1e: {:?}", error)),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ENCODER
+///////////////////////////////////////////////////////////////////////////////
+
+/// AV1, muxed into WebM.
+pub fn encode(stream: &VideoBuffer, options: &Options) -> Result<Vec<u8>, String> {
+    if stream.as_frames().is_empty() {
+        return Err(String::from("no frames to encode"));
+    }
+    let (width, height) = stream.dimensions();
+    if width % 2 != 0 || height % 2 != 0 {
+        return Err(format!("{}x{} frames; AV1 4:2:0 needs even dimensions", width, height));
+    }
+    let mut context = new_context(stream, options)?;
+    let mut packets = Vec::<Packet>::new();
+    for source in stream.as_frames() {
+        assert!(source.expected_yuv420p_size());
+        let mut frame = context.new_frame();
+        let planes = [
+            (source.y(), width as usize),
+            (source.u(), width as usize / 2),
+            (source.v(), width as usize / 2),
+        ];
+        for (plane, (data, stride)) in frame.planes.iter_mut().zip(planes) {
+            plane.copy_from_raw_u8(data, stride, 1);
+        }
+        context
+            .send_frame(frame)
+            .map_err(|error| format!("rav1e: {:?}", error))?;
+        drain(&mut context, options.frame_rate, &mut packets)?;
+    }
+    ///////////////////////////////////////////////////////////////////////////
+    // FLUSH DELAYED FRAMES
+    ///////////////////////////////////////////////////////////////////////////
+    context.flush();
+    while !drain(&mut context, options.frame_rate, &mut packets)? {}
+    let track = Track {
+        codec_id: "V_AV1",
+        width,
+        height,
+        frame_rate: options.frame_rate,
+        // THE av1C, OF THE SEQUENCE HEADER
+        codec_private: Some(context.container_sequence_header()),
+    };
+    Ok(crate::format::encode::mux_webm(&track, &packets))
+}
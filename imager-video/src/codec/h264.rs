@@ -13,6 +13,7 @@ use itertools::Itertools;
 use serde::{Serialize, Deserialize};
 
 use crate::data::{Yuv420P, VideoBuffer};
+use crate::format::mp4::{Sample, Track};
 use crate::tool::classifier::{self, Class};
 
 
@@ -27,6 +28,57 @@ pub enum Mode {
     Quality,
 }
 
+/// The H.264 profiles of compatibility targets; older (or low-end) devices
+/// only decode `Baseline` (no B-frames or CABAC).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    Baseline,
+    Main,
+    High,
+}
+
+impl Profile {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Profile::Baseline => "baseline",
+            Profile::Main => "main",
+            Profile::High => "high",
+        }
+    }
+}
+
+/// Of MP4 outputs (see `encode_mp4`).
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Constant quality, from 0 (lossless) to 51.
+    pub crf: f32,
+    pub profile: Profile,
+    /// The level, as its `level_idc` (e.g. `31` for 3.1); if none, x264
+    /// picks the lowest one the resolution and frame rate fit.
+    pub level: Option<u8>,
+    /// x264’s preset, e.g. `medium` or `slow`.
+    pub preset: &'static str,
+    /// Frames per second, as a fraction (e.g. `(30000, 1001)`).
+    pub frame_rate: (u32, u32),
+    /// CRFs from frames on (by index, in order), overriding `crf`, e.g. of
+    /// the scenes of `codec::rate`; each of these frames is a keyframe, so
+    /// scenes don’t reference each other.
+    pub scene_crfs: Vec<(usize, f32)>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            crf: 23.0,
+            profile: Profile::High,
+            level: None,
+            preset: "medium",
+            frame_rate: (30, 1),
+            scene_crfs: Vec::new(),
+        }
+    }
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 // MISCELLANEOUS
 ///////////////////////////////////////////////////////////////////////////////
@@ -276,6 +328,183 @@ pub unsafe fn encode(stream: &VideoBuffer, crf: f32) -> Result<Vec<u8>, String>
     Ok(output)
 }
 
+///////////////////////////////////////////////////////////////////////////////
+// MP4 ENCODER
+///////////////////////////////////////////////////////////////////////////////
+
+unsafe fn new_mp4_param(stream: &VideoBuffer, options: &Options) -> Result<sys::X264ParamT, String> {
+    let (width, height) = stream.dimensions();
+    let (rate_num, rate_den) = options.frame_rate;
+    let mut param: sys::X264ParamT = std::mem::zeroed();
+    let preset = c_str(options.preset);
+    if sys::x264_param_default_preset(&mut param, preset.as_ptr(), std::ptr::null()) != 0 {
+        return Err(format!("x264: invalid preset {:?}", options.preset));
+    }
+    param.i_bitdepth = 8;
+    param.i_csp = raw::X264_CSP_I420 as i32;
+    param.i_width = width as i32;
+    param.i_height = height as i32;
+    param.i_fps_num = rate_num;
+    param.i_fps_den = rate_den;
+    // ONE TICK PER FRAME
+    param.i_timebase_num = rate_den;
+    param.i_timebase_den = rate_num;
+    param.b_vfr_input = 0;
+    // MP4 SAMPLES ARE LENGTH-PREFIXED, AND THE PARAMETER SETS ARE IN THE avcC
+    param.b_annexb = 0;
+    param.b_repeat_headers = 0;
+    param.i_log_level = 1;
+    // A KEYFRAME EVERY FEW SECONDS, FOR SEEKING
+    param.i_keyint_max = ((rate_num / rate_den.max(1)).max(1) * 5) as i32;
+    apply(&mut param, "crf", &format!("{}", options.crf.clamp(0.0, 51.0)));
+    if let Some(level) = options.level {
+        param.i_level_idc = level as i32;
+    }
+    let profile = c_str(options.profile.as_str());
+    if sys::x264_param_apply_profile(&mut param, profile.as_ptr()) != 0 {
+        // E.G. LOSSLESS (CRF 0) NEEDS HIGH 4:4:4 PREDICTIVE
+        return Err(format!("x264: the settings don’t fit the {:?} profile", options.profile));
+    }
+    Ok(param)
+}
+
+/// The NAL units of an `x264_encoder_encode` (or `x264_encoder_headers`)
+/// call, still length-prefixed.
+unsafe fn nal_units<'a>(p_nal: *const sys::X264NalT, i_nal: i32) -> impl Iterator<Item = (i32, &'a [u8])> {
+    (0..i_nal.max(0) as usize).map(move |index| {
+        let nal = &*p_nal.add(index);
+        (nal.i_type, std::slice::from_raw_parts(nal.p_payload, nal.i_payload as usize))
+    })
+}
+
+/// H.264, muxed into MP4; for clients that can’t decode VP9 or AV1 (see
+/// `codec::encode_webm`).
+// OF `x264_picture_t.i_type`, FROM `x264.h`
+const X264_TYPE_AUTO: c_int = 0x0000;
+const X264_TYPE_KEYFRAME: c_int = 0x0006;
+
+pub unsafe fn encode_mp4(stream: &VideoBuffer, options: &Options) -> Result<Vec<u8>, String> {
+    if stream.as_frames().is_empty() {
+        return Err(String::from("no frames to encode"));
+    }
+    let (width, height) = stream.dimensions();
+    if width % 2 != 0 || height % 2 != 0 {
+        return Err(format!("{}x{} frames; H.264 4:2:0 needs even dimensions", width, height));
+    }
+    let luma_size = (width * height) as usize;
+    let chroma_size = luma_size / 4;
+    ///////////////////////////////////////////////////////////////////////////
+    // ENCODER CONTEXT
+    ///////////////////////////////////////////////////////////////////////////
+    let mut param = new_mp4_param(stream, options)?;
+    let encoder_ctx: *mut sys::X264T = sys::x264_encoder_open(&mut param);
+    if encoder_ctx.is_null() {
+        return Err(String::from("x264: failed to open the encoder"));
+    }
+    let mut picture: sys::X264PictureT = std::mem::zeroed();
+    if sys::x264_picture_alloc(&mut picture, param.i_csp, param.i_width, param.i_height) != 0 {
+        sys::x264_encoder_close(encoder_ctx);
+        return Err(String::from("x264: failed to allocate a picture"));
+    }
+    let mut picture_output: sys::X264PictureT = std::mem::zeroed();
+    let mut p_nal: *mut sys::X264NalT = std::ptr::null_mut();
+    let mut i_nal: i32 = 0;
+    ///////////////////////////////////////////////////////////////////////////
+    // PARAMETER SETS
+    ///////////////////////////////////////////////////////////////////////////
+    let mut sps = Vec::<u8>::new();
+    let mut pps = Vec::<u8>::new();
+    if sys::x264_encoder_headers(encoder_ctx, &mut p_nal, &mut i_nal) >= 0 {
+        for (kind, payload) in nal_units(p_nal, i_nal) {
+            // WITHOUT THE LENGTH PREFIX
+            match kind {
+                7 => sps = payload[4..].to_vec(),
+                8 => pps = payload[4..].to_vec(),
+                _ => (),
+            }
+        }
+    }
+    ///////////////////////////////////////////////////////////////////////////
+    // GO!
+    ///////////////////////////////////////////////////////////////////////////
+    let mut samples = Vec::<Sample>::new();
+    let mut result = Ok(());
+    let mut take = |size: i32, p_nal: *mut sys::X264NalT, output: &sys::X264PictureT| {
+        if size < 0 {
+            return Err(String::from("x264: failed to encode a frame"));
+        }
+        if size > 0 {
+            // THE UNITS OF A FRAME ARE CONTIGUOUS
+            let data = std::slice::from_raw_parts((*p_nal).p_payload, size as usize);
+            samples.push(Sample {
+                data: data.to_vec(),
+                pts: output.i_pts,
+                dts: output.i_dts,
+                keyframe: output.b_keyframe != 0,
+            });
+        }
+        Ok(())
+    };
+    for (index, source) in stream.as_frames().iter().enumerate() {
+        assert!(source.expected_yuv420p_size());
+        std::slice::from_raw_parts_mut(picture.img.plane[0], luma_size).copy_from_slice(source.y());
+        std::slice::from_raw_parts_mut(picture.img.plane[1], chroma_size).copy_from_slice(source.u());
+        std::slice::from_raw_parts_mut(picture.img.plane[2], chroma_size).copy_from_slice(source.v());
+        picture.i_pts = index as i64;
+        picture.i_type = X264_TYPE_AUTO;
+        if let Some((_, crf)) = options.scene_crfs.iter().find(|x| x.0 == index) {
+            picture.i_type = X264_TYPE_KEYFRAME;
+            // APPLIED FROM THIS FRAME ON
+            param.rc.f_rf_constant = crf.clamp(0.0, 51.0);
+            if sys::x264_encoder_reconfig(encoder_ctx, &mut param) < 0 {
+                result = Err(String::from("x264: failed to change the CRF"));
+                break;
+            }
+        }
+        let size = sys::x264_encoder_encode(
+            encoder_ctx,
+            &mut p_nal,
+            &mut i_nal,
+            &mut picture,
+            &mut picture_output,
+        );
+        result = take(size, p_nal, &picture_output);
+        if result.is_err() {
+            break;
+        }
+    }
+    ///////////////////////////////////////////////////////////////////////////
+    // FLUSH DELAYED FRAMES
+    ///////////////////////////////////////////////////////////////////////////
+    while result.is_ok() && sys::x264_encoder_delayed_frames(encoder_ctx) > 0 {
+        let size = sys::x264_encoder_encode(
+            encoder_ctx,
+            &mut p_nal,
+            &mut i_nal,
+            std::ptr::null_mut(),
+            &mut picture_output,
+        );
+        result = take(size, p_nal, &picture_output);
+    }
+    ///////////////////////////////////////////////////////////////////////////
+    // CLEANUP
+    ///////////////////////////////////////////////////////////////////////////
+    sys::x264_encoder_close(encoder_ctx);
+    sys::x264_picture_clean(&mut picture);
+    result?;
+    if sps.len() < 4 || pps.is_empty() {
+        return Err(String::from("x264: missing parameter sets"));
+    }
+    let track = Track {
+        width,
+        height,
+        frame_rate: options.frame_rate,
+        sps,
+        pps,
+    };
+    Ok(crate::format::mp4::mux_mp4(&track, &samples))
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 // DEV - PICTURE OPT
 ///////////////////////////////////////////////////////////////////////////////
@@ -316,7 +545,7 @@ pub unsafe fn opt_frame(index: usize, source: Yuv420P) -> (Vec<u8>, FrameReport)
         let run = |q| -> bool {
             let encoded = encode(&source_video, q as f32).expect("encode yuv420p");
             let ref_video = VideoBuffer::load_from_memory(&encoded).expect("reconstruct");
-            let vmaf_report = crate::tool::vmaf::get_report(&source_video, &ref_video);
+            let vmaf_report = crate::eval::vmaf::get_report(&source_video, &ref_video);
             term(vmaf_report)
         };
         let reduce_starting_values = |qs: Vec<u8>| -> Option<u8> {
@@ -347,7 +576,7 @@ pub unsafe fn opt_frame(index: usize, source: Yuv420P) -> (Vec<u8>, FrameReport)
     for crf in (0 .. start_pos).rev().filter(|x| x % 2 == 0) {
         let encoded = encode(&source_video, crf as f32).expect("encode yuv420p");
         let ref_video = VideoBuffer::load_from_memory(&encoded).expect("reconstruct");
-        let vmaf_report = crate::tool::vmaf::get_report(&source_video, &ref_video);
+        let vmaf_report = crate::eval::vmaf::get_report(&source_video, &ref_video);
         if term(vmaf_report) {
             // return (crf, vmaf_report, class_report.class.clone(), encoded);
             let meta = FrameReport{
@@ -1 +1,21 @@
-pub mod h264;
\ No newline at end of file
+pub mod av1;
+pub mod h264;
+pub mod rate;
+pub mod vp9;
+
+use crate::data::VideoBuffer;
+
+/// The codecs of WebM outputs, with their options.
+#[derive(Debug, Clone)]
+pub enum WebmCodec {
+    Vp9(vp9::Options),
+    Av1(av1::Options),
+}
+
+/// Encodes with the codec, muxed into WebM.
+pub fn encode_webm(stream: &VideoBuffer, codec: &WebmCodec) -> Result<Vec<u8>, String> {
+    match codec {
+        WebmCodec::Vp9(options) => unsafe { vp9::encode(stream, options) },
+        WebmCodec::Av1(options) => av1::encode(stream, options),
+    }
+}
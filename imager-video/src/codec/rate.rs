@@ -0,0 +1,177 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! VMAF-targeted rate control: the CRF of every scene is searched, by
+//! encoding the scene alone, until its VMAF meets the target; so each one
+//! gets the fewest bits that still look right, rather than the same CRF
+//! (too many bits for static scenes, too few for busy ones).
+//!
+//! VMAF falls as the CRF rises, so the search bisects the CRF range for
+//! the highest one that meets the target. Scenes are the shots of
+//! `scene::detect`, so each one’s content is uniform.
+use std::ops::Range;
+use serde::{Serialize, Deserialize};
+
+use crate::data::VideoBuffer;
+use crate::codec::h264;
+use crate::scene;
+
+
+///////////////////////////////////////////////////////////////////////////////
+// DATA TYPES
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone)]
+pub struct Target {
+    /// The VMAF every scene should meet, e.g. `93.0`.
+    pub vmaf: f64,
+    /// The CRFs to search, inclusive.
+    pub crf_range: (u8, u8),
+    /// Of the scene detection.
+    pub scenes: scene::Options,
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Target {
+            vmaf: 93.0,
+            crf_range: (16, 40),
+            scenes: scene::Options::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneReport {
+    pub frames: Range<usize>,
+    pub crf: u8,
+    pub vmaf: f64,
+    /// Whether even the lowest CRF of the range fell short of the target.
+    pub below_target: bool,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// SEARCH
+///////////////////////////////////////////////////////////////////////////////
+
+/// The highest CRF of the range whose output (of `encode`, decoded) meets
+/// the target VMAF, with its score; else the lowest CRF’s.
+pub fn search(
+    source: &VideoBuffer,
+    vmaf: f64,
+    (min, max): (u8, u8),
+    encode: &dyn Fn(&VideoBuffer, u8) -> Result<Vec<u8>, String>,
+) -> Result<SceneReport, String> {
+    let frames = 0..source.as_frames().len();
+    let score = |crf: u8| -> Result<f64, String> {
+        let encoded = encode(source, crf)?;
+        let output = VideoBuffer::load_from_memory(&encoded)
+            .map_err(|_| format!("failed to decode the output of CRF {}", crf))?;
+        if output.as_frames().len() != source.as_frames().len() {
+            return Err(format!(
+                "the output of CRF {} has {} frames, of {}",
+                crf,
+                output.as_frames().len(),
+                source.as_frames().len(),
+            ));
+        }
+        Ok(crate::eval::vmaf::get_report(source, &output))
+    };
+    let (min, max) = (min.min(max), max.max(min));
+    match bisect(vmaf, (min, max), &score)? {
+        Some((crf, report)) => Ok(SceneReport {frames, crf, vmaf: report, below_target: false}),
+        None => Ok(SceneReport {frames, crf: min, vmaf: score(min)?, below_target: true}),
+    }
+}
+
+/// The highest CRF of the (ordered) range whose score meets the target,
+/// with the score, which falls as the CRF rises; none if even `min` falls
+/// short.
+fn bisect(
+    vmaf: f64,
+    (min, max): (u8, u8),
+    score: &dyn Fn(u8) -> Result<f64, String>,
+) -> Result<Option<(u8, f64)>, String> {
+    let (mut low, mut high) = (min as i32, max as i32);
+    let mut best: Option<(u8, f64)> = None;
+    // EVERY CRF BELOW `low` MEETS THE TARGET, AND NONE ABOVE `high` DOES
+    while low <= high {
+        let crf = (low + (high - low) / 2) as u8;
+        let report = score(crf)?;
+        if report >= vmaf {
+            best = Some((crf, report));
+            low = crf as i32 + 1;
+        } else {
+            high = crf as i32 - 1;
+        }
+    }
+    Ok(best)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ENCODERS
+///////////////////////////////////////////////////////////////////////////////
+
+/// H.264 (to MP4, see `h264::encode_mp4`), at the CRF of every scene,
+/// each starting with a keyframe; `options.crf` and `options.scene_crfs`
+/// are ignored.
+pub fn encode_h264(
+    stream: &VideoBuffer,
+    target: &Target,
+    options: &h264::Options,
+) -> Result<(Vec<u8>, Vec<SceneReport>), String> {
+    let encode = |scene: &VideoBuffer, crf: u8| unsafe {
+        let options = h264::Options {
+            crf: crf as f32,
+            scene_crfs: Vec::new(),
+            ..options.clone()
+        };
+        h264::encode_mp4(scene, &options)
+    };
+    let mut reports = Vec::new();
+    for frames in scene::detect(stream, &target.scenes) {
+        let scene = stream.slice(frames.clone());
+        let report = search(&scene, target.vmaf, target.crf_range, &encode)?;
+        reports.push(SceneReport {frames, ..report});
+    }
+    let options = h264::Options {
+        scene_crfs: reports
+            .iter()
+            .map(|x| (x.frames.start, x.crf as f32))
+            .collect(),
+        ..options.clone()
+    };
+    let encoded = unsafe { h264::encode_mp4(stream, &options)? };
+    Ok((encoded, reports))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_bisect() {
+        let probes = RefCell::new(Vec::new());
+        let score = |crf: u8| -> Result<f64, String> {
+            probes.borrow_mut().push(crf);
+            Ok(100.0 - crf as f64)
+        };
+        // THE HIGHEST CRF THAT MEETS THE TARGET, IN LOG2 OF THE RANGE’S PROBES
+        assert_eq!(bisect(70.0, (16, 40), &score), Ok(Some((30, 70.0))));
+        assert!(probes.borrow().len() <= 5, "{:?}", probes.borrow());
+        assert!(probes.borrow().iter().all(|x| (16..=40).contains(x)));
+        probes.borrow_mut().clear();
+        assert_eq!(bisect(0.0, (0, 255), &score), Ok(Some((100, 0.0))));
+        assert!(probes.borrow().len() <= 8, "{:?}", probes.borrow());
+        // BOUNDS: EVERY CRF MEETS THE TARGET, NONE DOES, A SINGLE CRF
+        assert_eq!(bisect(0.0, (16, 40), &score), Ok(Some((40, 60.0))));
+        assert_eq!(bisect(99.0, (16, 40), &score), Ok(None));
+        assert_eq!(bisect(99.0, (0, 255), &score), Ok(Some((1, 99.0))));
+        assert_eq!(bisect(80.0, (20, 20), &score), Ok(Some((20, 80.0))));
+        assert_eq!(bisect(81.0, (20, 20), &score), Ok(None));
+        // ERRORS ARE PASSED ON
+        let failing = |_: u8| -> Result<f64, String> { Err(String::from("encoder failed")) };
+        assert_eq!(bisect(70.0, (16, 40), &failing), Err(String::from("encoder failed")));
+    }
+}
@@ -0,0 +1,296 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! VP9 encoding (via libvpx) of a `VideoBuffer` to WebM.
+//!
+//! Encodes are always two-pass: the first pass only gathers statistics of
+//! the frames, which the second uses to spread the bits where they count,
+//! for either a constant quality (CRF) or an average bitrate.
+use std::ffi::CStr;
+use std::os::raw::{c_int, c_ulong};
+use vpx_sys::{
+    self,
+    vpx_codec_ctx_t,
+    vpx_codec_enc_cfg_t,
+    vpx_codec_err_t,
+    vpx_codec_cx_pkt_kind,
+    vpx_enc_pass,
+    vpx_fixed_buf_t,
+    vpx_image_t,
+    vpx_img_fmt,
+    vpx_rc_mode,
+    vp8e_enc_control_id,
+    VPX_DL_GOOD_QUALITY,
+    VPX_ENCODER_ABI_VERSION,
+    VPX_FRAME_IS_KEY,
+};
+
+use crate::data::VideoBuffer;
+use crate::format::encode::{Packet, Track};
+
+
+///////////////////////////////////////////////////////////////////////////////
+// DATA TYPES
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy)]
+pub enum RateControl {
+    /// Constant quality, from 0 (best) to 63.
+    Crf(u8),
+    /// Average bitrate, in kbit/s.
+    Bitrate(u32),
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub rate_control: RateControl,
+    /// Frames per second, as a fraction (e.g. `(30000, 1001)`).
+    pub frame_rate: (u32, u32),
+    /// libvpx’s speed (`cpu-used`), from 0 (slowest, best) to 5; the first
+    /// pass always runs at 4, since its statistics barely depend on it.
+    pub speed: u8,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            rate_control: RateControl::Crf(31),
+            frame_rate: (30, 1),
+            speed: 1,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// HELPERS
+///////////////////////////////////////////////////////////////////////////////
+
+unsafe fn check(status: vpx_codec_err_t) -> Result<(), String> {
+    if status == vpx_codec_err_t::VPX_CODEC_OK {
+        return Ok(());
+    }
+    let message = CStr::from_ptr(vpx_sys::vpx_codec_err_to_string(status));
+    Err(format!("libvpx: {}", message.to_string_lossy()))
+}
+
+unsafe fn control(ctx: &mut vpx_codec_ctx_t, id: vp8e_enc_control_id, value: c_int) -> Result<(), String> {
+    check(vpx_sys::vpx_codec_control_(ctx, id as c_int, value))
+}
+
+unsafe fn new_config(
+    stream: &VideoBuffer,
+    options: &Options,
+    pass: vpx_enc_pass,
+) -> Result<vpx_codec_enc_cfg_t, String> {
+    let (width, height) = stream.dimensions();
+    let (rate_num, rate_den) = options.frame_rate;
+    let mut config: vpx_codec_enc_cfg_t = std::mem::zeroed();
+    check(vpx_sys::vpx_codec_enc_config_default(vpx_sys::vpx_codec_vp9_cx(), &mut config, 0))?;
+    config.g_w = width;
+    config.g_h = height;
+    // ONE TICK PER FRAME
+    config.g_timebase.num = rate_den as c_int;
+    config.g_timebase.den = rate_num as c_int;
+    config.g_threads = threads();
+    config.g_pass = pass;
+    // THE LOOKAHEAD OF ALT-REF FRAMES
+    config.g_lag_in_frames = 25;
+    match options.rate_control {
+        RateControl::Crf(_) => {
+            config.rc_end_usage = vpx_rc_mode::VPX_Q;
+            config.rc_min_quantizer = 0;
+            config.rc_max_quantizer = 63;
+        }
+        RateControl::Bitrate(kbps) => {
+            config.rc_end_usage = vpx_rc_mode::VPX_VBR;
+            config.rc_target_bitrate = kbps;
+        }
+    }
+    Ok(config)
+}
+
+fn threads() -> u32 {
+    std::thread::available_parallelism()
+        .map(|x| x.get() as u32)
+        .unwrap_or(1)
+        .min(16)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// LOW-LEVEL ENCODER
+///////////////////////////////////////////////////////////////////////////////
+
+/// Takes the encoder’s output so far: statistics packets (of a first pass)
+/// into `stats`, and frames into `packets`.
+unsafe fn drain(
+    ctx: &mut vpx_codec_ctx_t,
+    (rate_num, rate_den): (u32, u32),
+    packets: &mut Vec<Packet>,
+    stats: &mut Vec<u8>,
+) {
+    let mut iter = std::ptr::null();
+    loop {
+        let packet = vpx_sys::vpx_codec_get_cx_data(ctx, &mut iter);
+        if packet.is_null() {
+            break;
+        }
+        match (*packet).kind {
+            vpx_codec_cx_pkt_kind::VPX_CODEC_STATS_PKT => {
+                let data = (*packet).data.twopass_stats;
+                stats.extend_from_slice(std::slice::from_raw_parts(
+                    data.buf as *const u8,
+                    data.sz as usize,
+                ));
+            }
+            vpx_codec_cx_pkt_kind::VPX_CODEC_CX_FRAME_PKT => {
+                let frame = (*packet).data.frame;
+                let data = std::slice::from_raw_parts(frame.buf as *const u8, frame.sz as usize);
+                // TICKS TO MILLISECONDS
+                let timestamp = frame.pts as u64 * 1000 * rate_den as u64 / rate_num as u64;
+                packets.push(Packet {
+                    data: data.to_vec(),
+                    timestamp,
+                    keyframe: (frame.flags & VPX_FRAME_IS_KEY) != 0,
+                });
+            }
+            _ => (),
+        }
+    }
+}
+
+/// One pass over the frames: of the first, the statistics; of the last,
+/// the encoded frames (given the statistics).
+unsafe fn run_pass(
+    stream: &VideoBuffer,
+    options: &Options,
+    stats: &mut Vec<u8>,
+    pass: vpx_enc_pass,
+) -> Result<Vec<Packet>, String> {
+    ///////////////////////////////////////////////////////////////////////////
+    // INIT CONFIG
+    ///////////////////////////////////////////////////////////////////////////
+    let first_pass = pass == vpx_enc_pass::VPX_RC_FIRST_PASS;
+    let mut config = new_config(stream, options, pass)?;
+    if !first_pass {
+        config.rc_twopass_stats_in = vpx_fixed_buf_t {
+            buf: stats.as_mut_ptr() as *mut _,
+            sz: stats.len() as _,
+        };
+    }
+    ///////////////////////////////////////////////////////////////////////////
+    // ENCODER CONTEXT
+    ///////////////////////////////////////////////////////////////////////////
+    let mut ctx: vpx_codec_ctx_t = std::mem::zeroed();
+    check(vpx_sys::vpx_codec_enc_init_ver(
+        &mut ctx,
+        vpx_sys::vpx_codec_vp9_cx(),
+        &config,
+        0,
+        VPX_ENCODER_ABI_VERSION as c_int,
+    ))?;
+    let speed = if first_pass {4} else {options.speed.min(5)};
+    let mut settings = vec![
+        (vp8e_enc_control_id::VP8E_SET_CPUUSED, speed as c_int),
+        (vp8e_enc_control_id::VP9E_SET_ROW_MT, 1),
+        (vp8e_enc_control_id::VP8E_SET_ENABLEAUTOALTREF, 1),
+    ];
+    if let RateControl::Crf(crf) = options.rate_control {
+        settings.push((vp8e_enc_control_id::VP8E_SET_CQ_LEVEL, crf.min(63) as c_int));
+    }
+    let mut result = settings
+        .into_iter()
+        .try_for_each(|(id, value)| control(&mut ctx, id, value));
+    ///////////////////////////////////////////////////////////////////////////
+    // GO!
+    ///////////////////////////////////////////////////////////////////////////
+    let (width, height) = stream.dimensions();
+    let mut packets = Vec::<Packet>::new();
+    let mut image: vpx_image_t = std::mem::zeroed();
+    for (index, source) in stream.as_frames().iter().enumerate() {
+        if result.is_err() {
+            break;
+        }
+        // THE Y, U AND V PLANES ARE CONTIGUOUS (CHECKED BY `encode`)
+        let wrapped = vpx_sys::vpx_img_wrap(
+            &mut image,
+            vpx_img_fmt::VPX_IMG_FMT_I420,
+            width,
+            height,
+            1,
+            source.data.as_ptr() as *mut u8,
+        );
+        if wrapped.is_null() {
+            result = Err(String::from("libvpx: failed to wrap frame"));
+            break;
+        }
+        result = check(vpx_sys::vpx_codec_encode(
+            &mut ctx,
+            &image,
+            index as i64,
+            1,
+            0,
+            VPX_DL_GOOD_QUALITY as c_ulong,
+        ));
+        drain(&mut ctx, options.frame_rate, &mut packets, stats);
+    }
+    ///////////////////////////////////////////////////////////////////////////
+    // FLUSH DELAYED FRAMES
+    ///////////////////////////////////////////////////////////////////////////
+    while result.is_ok() {
+        result = check(vpx_sys::vpx_codec_encode(
+            &mut ctx,
+            std::ptr::null(),
+            -1,
+            1,
+            0,
+            VPX_DL_GOOD_QUALITY as c_ulong,
+        ));
+        let before = packets.len();
+        let stats_before = stats.len();
+        drain(&mut ctx, options.frame_rate, &mut packets, stats);
+        if packets.len() == before && stats.len() == stats_before {
+            break;
+        }
+    }
+    ///////////////////////////////////////////////////////////////////////////
+    // CLEANUP
+    ///////////////////////////////////////////////////////////////////////////
+    vpx_sys::vpx_codec_destroy(&mut ctx);
+    result.map(|_| packets)
+}
+
+/// Two-pass VP9, muxed into WebM.
+pub unsafe fn encode(stream: &VideoBuffer, options: &Options) -> Result<Vec<u8>, String> {
+    if stream.as_frames().is_empty() {
+        return Err(String::from("no frames to encode"));
+    }
+    let (width, height) = stream.dimensions();
+    if width % 2 != 0 || height % 2 != 0 {
+        return Err(format!("{}x{} frames; VP9 4:2:0 needs even dimensions", width, height));
+    }
+    // LIBVPX READS EVERY FRAME AS ONE OF THE STREAM’S DIMENSIONS
+    for (index, frame) in stream.as_frames().iter().enumerate() {
+        if frame.dimensions() != (width, height) || !frame.expected_yuv420p_size() {
+            return Err(format!(
+                "frame {} is {}x{} ({} bytes), not {}x{} like the stream",
+                index,
+                frame.width,
+                frame.height,
+                frame.data.len(),
+                width,
+                height,
+            ));
+        }
+    }
+    let mut stats = Vec::<u8>::new();
+    run_pass(stream, options, &mut stats, vpx_enc_pass::VPX_RC_FIRST_PASS)?;
+    let packets = run_pass(stream, options, &mut stats, vpx_enc_pass::VPX_RC_LAST_PASS)?;
+    let track = Track {
+        codec_id: "V_VP9",
+        width,
+        height,
+        frame_rate: options.frame_rate,
+        codec_private: None,
+    };
+    Ok(crate::format::encode::mux_webm(&track, &packets))
+}
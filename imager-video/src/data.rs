@@ -280,6 +280,17 @@ impl VideoBuffer {
             cursor: 0,
         }
     }
+    /// Of frames that share the first one’s dimensions.
+    pub fn from_frames(frames: Vec<Yuv420P>) -> Self {
+        assert!(!frames.is_empty());
+        assert!(frames.iter().all(|x| x.dimensions() == frames[0].dimensions()));
+        VideoBuffer {
+            width: frames[0].width,
+            height: frames[0].height,
+            frames: Rc::new(frames),
+            cursor: 0,
+        }
+    }
     pub fn load_from_memory(source: &[u8]) -> Result<Self, ()> {
         let result = unsafe {
             crate::format::decode::demux_decode(source.to_vec())
@@ -330,6 +341,15 @@ impl VideoBuffer {
     pub fn as_frames(&self) -> &[Yuv420P] {
         self.frames.as_ref()
     }
+    /// A copy of the frames of the range, e.g. of a scene.
+    pub fn slice(&self, frames: std::ops::Range<usize>) -> VideoBuffer {
+        VideoBuffer {
+            width: self.width,
+            height: self.height,
+            frames: Rc::new(self.frames[frames].to_vec()),
+            cursor: 0,
+        }
+    }
     pub fn into_frames(self) -> Vec<Yuv420P> {
         let refs = Rc::strong_count(&self.frames);
         if refs == 0 {
@@ -358,3 +378,136 @@ impl VideoBuffer {
         }
     }
 }
+
+
+///////////////////////////////////////////////////////////////////////////////
+// FRAME EXTRACTION
+///////////////////////////////////////////////////////////////////////////////
+
+/// Which frames `VideoBuffer::extract_frames` returns, e.g. a poster frame,
+/// or the frames of a preview strip.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameStrategy {
+    First,
+    Middle,
+    /// Every Nth frame from the first, e.g. `EveryNth(30)` for one a second
+    /// at 30 fps.
+    EveryNth(usize),
+    /// The sharpest frame (see `Yuv420P::sharpness`), since the first and
+    /// middle ones are often mid-motion or mid-fade.
+    LeastBlurry,
+}
+
+impl Yuv420P {
+    /// The variance of the luma’s Laplacian; higher is sharper.
+    pub fn sharpness(&self) -> f64 {
+        let (width, height) = (self.width as usize, self.height as usize);
+        if width < 3 || height < 3 {
+            return 0.0;
+        }
+        let luma = self.y();
+        let at = |x: usize, y: usize| luma[y * width + x] as f64;
+        let mut sum = 0.0;
+        let mut sum_squares = 0.0;
+        for y in 1..height - 1 {
+            for x in 1..width - 1 {
+                let laplacian = at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4.0 * at(x, y);
+                sum += laplacian;
+                sum_squares += laplacian * laplacian;
+            }
+        }
+        let count = ((width - 2) * (height - 2)) as f64;
+        let mean = sum / count;
+        sum_squares / count - mean * mean
+    }
+}
+
+impl VideoBuffer {
+    /// The frames of the strategy, in order, as RGBA images (e.g. for the
+    /// image encoders); none of an empty buffer.
+    pub fn extract_frames(&self, strategy: &FrameStrategy) -> Vec<DynamicImage> {
+        let frames = self.as_frames();
+        self.frame_indices(strategy)
+            .into_par_iter()
+            .map(|index| frames[index].to_rgba_image())
+            .collect()
+    }
+    /// The indices of the frames of the strategy, in order.
+    pub fn frame_indices(&self, strategy: &FrameStrategy) -> Vec<usize> {
+        let frames = self.as_frames();
+        if frames.is_empty() {
+            return Vec::new();
+        }
+        match strategy {
+            FrameStrategy::First => vec![0],
+            FrameStrategy::Middle => vec![frames.len() / 2],
+            FrameStrategy::EveryNth(n) => (0..frames.len()).step_by((*n).max(1)).collect(),
+            FrameStrategy::LeastBlurry => {
+                let scores = frames
+                    .par_iter()
+                    .map(Yuv420P::sharpness)
+                    .collect::<Vec<_>>();
+                // THE FIRST OF TIES
+                let best = scores
+                    .iter()
+                    .enumerate()
+                    .fold(0, |best, (index, score)| if *score > scores[best] { index } else { best });
+                vec![best]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// 8x8, of the luma, with neutral chroma.
+    fn frame(luma: impl Fn(usize, usize) -> u8) -> Yuv420P {
+        let mut data = (0..64).map(|i| luma(i % 8, i / 8)).collect::<Vec<_>>();
+        data.resize(96, 128);
+        Yuv420P {width: 8, height: 8, data}
+    }
+
+    #[test]
+    fn test_frame_indices() {
+        let stream = VideoBuffer::from_frames((0..10).map(|_| frame(|_, _| 0)).collect());
+        assert_eq!(stream.frame_indices(&FrameStrategy::First), vec![0]);
+        assert_eq!(stream.frame_indices(&FrameStrategy::Middle), vec![5]);
+        // EVENLY SPACED FROM THE FIRST, UP TO THE LAST IF IT FALLS ON ONE
+        assert_eq!(stream.frame_indices(&FrameStrategy::EveryNth(3)), vec![0, 3, 6, 9]);
+        assert_eq!(stream.frame_indices(&FrameStrategy::EveryNth(4)), vec![0, 4, 8]);
+        // MORE THAN THE FRAMES: JUST THE FIRST; 0: EVERY FRAME
+        assert_eq!(stream.frame_indices(&FrameStrategy::EveryNth(25)), vec![0]);
+        assert_eq!(stream.frame_indices(&FrameStrategy::EveryNth(0)), (0..10).collect::<Vec<_>>());
+        let single = VideoBuffer::singleton(frame(|_, _| 0));
+        assert_eq!(single.frame_indices(&FrameStrategy::Middle), vec![0]);
+        assert_eq!(single.frame_indices(&FrameStrategy::EveryNth(2)), vec![0]);
+        let empty = stream.slice(0..0);
+        assert!(empty.frame_indices(&FrameStrategy::First).is_empty());
+        assert!(empty.frame_indices(&FrameStrategy::LeastBlurry).is_empty());
+    }
+
+    #[test]
+    fn test_least_blurry() {
+        let flat = frame(|_, _| 100);
+        let soft = frame(|x, y| if (x + y) % 2 == 0 { 100 } else { 110 });
+        let sharp = frame(|x, y| if (x + y) % 2 == 0 { 0 } else { 255 });
+        assert_eq!(flat.sharpness(), 0.0);
+        assert!(soft.sharpness() < sharp.sharpness());
+        let stream = VideoBuffer::from_frames(vec![
+            flat.clone(),
+            soft.clone(),
+            sharp.clone(),
+            soft,
+            sharp,
+        ]);
+        // THE SHARPEST, AND THE FIRST OF TIES
+        assert_eq!(stream.frame_indices(&FrameStrategy::LeastBlurry), vec![2]);
+        let flat = VideoBuffer::from_frames(vec![flat.clone(), flat]);
+        assert_eq!(flat.frame_indices(&FrameStrategy::LeastBlurry), vec![0]);
+        // TOO SMALL FOR THE LAPLACIAN
+        let tiny = Yuv420P {width: 2, height: 2, data: vec![0, 255, 255, 0, 128, 128]};
+        assert_eq!(tiny.sharpness(), 0.0);
+    }
+}
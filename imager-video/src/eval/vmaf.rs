@@ -1,6 +1,8 @@
 // This Source Code Form is subject to the terms of the Mozilla Public
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! VMAF (via libvmaf) of a decoded output, against its source; the score
+//! of the searches of `codec::rate`.
 use std::path::PathBuf;
 use std::sync::{Mutex, MutexGuard};
 use std::ffi::{CStr, CString};
@@ -0,0 +1,202 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! WebM muxing of encoded video frames (one video track, no audio), of
+//! `codec::vp9` and `codec::av1`. The whole file is built in memory, so
+//! every element size is known, and no Cues are needed for the players we
+//! target.
+
+
+///////////////////////////////////////////////////////////////////////////////
+// DATA TYPES
+///////////////////////////////////////////////////////////////////////////////
+
+/// An encoded frame, in presentation order.
+#[derive(Debug, Clone)]
+pub struct Packet {
+    pub data: Vec<u8>,
+    /// In milliseconds.
+    pub timestamp: u64,
+    pub keyframe: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Track {
+    /// E.g. `V_VP9`.
+    pub codec_id: &'static str,
+    pub width: u32,
+    pub height: u32,
+    /// Frames per second, as a fraction (e.g. `(30000, 1001)`).
+    pub frame_rate: (u32, u32),
+    /// Of codecs that need one, e.g. the `av1C` of AV1.
+    pub codec_private: Option<Vec<u8>>,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// EBML IDS
+///////////////////////////////////////////////////////////////////////////////
+
+const EBML: u32 = 0x1A45DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x18538067;
+const INFO: u32 = 0x1549A966;
+const TIMECODE_SCALE: u32 = 0x2AD7B1;
+const DURATION: u32 = 0x4489;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const DEFAULT_DURATION: u32 = 0x23E383;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const CLUSTER: u32 = 0x1F43B675;
+const TIMECODE: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+
+/// Of the one track.
+const TRACK: u8 = 1;
+
+///////////////////////////////////////////////////////////////////////////////
+// EBML HELPERS
+///////////////////////////////////////////////////////////////////////////////
+
+/// Element sizes are variable length integers: the count of leading zero
+/// bits is the count of bytes that follow the first; all ones is reserved
+/// (for unknown sizes).
+fn size_vint(size: u64) -> Vec<u8> {
+    let length = (1..=8)
+        .find(|n| size < (1u64 << (7 * n)) - 1)
+        .expect("EBML element too large");
+    let marked = size | (1u64 << (7 * length));
+    marked.to_be_bytes()[8 - length as usize..].to_vec()
+}
+
+/// IDs carry their own length marker, so are written as they are.
+fn id_bytes(id: u32) -> Vec<u8> {
+    let bytes = id.to_be_bytes();
+    let start = bytes.iter().position(|x| *x != 0).unwrap_or(3);
+    bytes[start..].to_vec()
+}
+
+fn element(id: u32, body: &[u8]) -> Vec<u8> {
+    let mut output = id_bytes(id);
+    output.extend(size_vint(body.len() as u64));
+    output.extend_from_slice(body);
+    output
+}
+
+fn uint(id: u32, value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let start = bytes.iter().position(|x| *x != 0).unwrap_or(7);
+    element(id, &bytes[start..])
+}
+
+fn float(id: u32, value: f64) -> Vec<u8> {
+    element(id, &value.to_be_bytes())
+}
+
+fn string(id: u32, value: &str) -> Vec<u8> {
+    element(id, value.as_bytes())
+}
+
+fn simple_block(packet: &Packet, relative: i16) -> Vec<u8> {
+    let mut body = Vec::with_capacity(packet.data.len() + 4);
+    // THE TRACK NUMBER, AS A ONE BYTE VINT
+    body.push(0x80 | TRACK);
+    body.extend_from_slice(&relative.to_be_bytes());
+    body.push(if packet.keyframe {0x80} else {0x00});
+    body.extend_from_slice(&packet.data);
+    element(SIMPLE_BLOCK, &body)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// MUXER
+///////////////////////////////////////////////////////////////////////////////
+
+/// A WebM file of the one track. Clusters start at keyframes (so players
+/// can seek to them), and whenever a block’s timestamp would overflow its
+/// 16 bit offset from the cluster’s.
+pub fn mux_webm(track: &Track, packets: &[Packet]) -> Vec<u8> {
+    let (rate_num, rate_den) = track.frame_rate;
+    let frame_duration_ns = 1_000_000_000 * rate_den as u64 / rate_num as u64;
+    let header = [
+        uint(EBML_VERSION, 1),
+        uint(EBML_READ_VERSION, 1),
+        uint(EBML_MAX_ID_LENGTH, 4),
+        uint(EBML_MAX_SIZE_LENGTH, 8),
+        string(DOC_TYPE, "webm"),
+        uint(DOC_TYPE_VERSION, 4),
+        uint(DOC_TYPE_READ_VERSION, 2),
+    ].concat();
+    let duration = packets
+        .last()
+        .map(|x| x.timestamp as f64 + frame_duration_ns as f64 / 1_000_000.0)
+        .unwrap_or(0.0);
+    let info = [
+        // MILLISECOND TIMESTAMPS
+        uint(TIMECODE_SCALE, 1_000_000),
+        float(DURATION, duration),
+        string(MUXING_APP, "imager-video"),
+        string(WRITING_APP, "imager-video"),
+    ].concat();
+    let video = [
+        uint(PIXEL_WIDTH, track.width as u64),
+        uint(PIXEL_HEIGHT, track.height as u64),
+    ].concat();
+    let mut track_entry = [
+        uint(TRACK_NUMBER, TRACK as u64),
+        uint(TRACK_UID, TRACK as u64),
+        // VIDEO
+        uint(TRACK_TYPE, 1),
+        string(CODEC_ID, track.codec_id),
+        uint(DEFAULT_DURATION, frame_duration_ns),
+        element(VIDEO, &video),
+    ].concat();
+    if let Some(codec_private) = &track.codec_private {
+        track_entry.extend(element(CODEC_PRIVATE, codec_private));
+    }
+    let mut segment = [
+        element(INFO, &info),
+        element(TRACKS, &element(TRACK_ENTRY, &track_entry)),
+    ].concat();
+    let mut cluster: Option<(u64, Vec<u8>)> = None;
+    for packet in packets {
+        let fits = |start: u64| {
+            packet.timestamp >= start &&
+            packet.timestamp - start <= i16::MAX as u64
+        };
+        match cluster.as_mut() {
+            Some((start, body)) if !packet.keyframe && fits(*start) => {
+                let relative = (packet.timestamp - *start) as i16;
+                body.extend(simple_block(packet, relative));
+            }
+            _ => {
+                if let Some((_, body)) = cluster.take() {
+                    segment.extend(element(CLUSTER, &body));
+                }
+                let mut body = uint(TIMECODE, packet.timestamp);
+                body.extend(simple_block(packet, 0));
+                cluster = Some((packet.timestamp, body));
+            }
+        }
+    }
+    if let Some((_, body)) = cluster {
+        segment.extend(element(CLUSTER, &body));
+    }
+    [
+        element(EBML, &header),
+        element(SEGMENT, &segment),
+    ].concat()
+}
@@ -3,6 +3,7 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 pub mod decode;
 pub mod encode;
+pub mod mp4;
 
 use std::collections::LinkedList;
 use std::convert::AsRef;
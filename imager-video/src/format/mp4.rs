@@ -0,0 +1,215 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! MP4 muxing of H.264 frames (one video track, no audio), of
+//! `codec::h264`. Like the WebM muxer, the whole file is built in memory;
+//! the `moov` precedes the `mdat` (i.e. “fast start”), so players can
+//! begin before the download ends, and every sample is in the one chunk.
+
+
+///////////////////////////////////////////////////////////////////////////////
+// DATA TYPES
+///////////////////////////////////////////////////////////////////////////////
+
+/// An encoded frame (length-prefixed NAL units), in decoding order.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub data: Vec<u8>,
+    /// In frames, the presentation and decoding timestamps; with B-frames,
+    /// decoding runs ahead, so the first decoding timestamps are negative.
+    pub pts: i64,
+    pub dts: i64,
+    pub keyframe: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Track {
+    pub width: u32,
+    pub height: u32,
+    /// Frames per second, as a fraction (e.g. `(30000, 1001)`).
+    pub frame_rate: (u32, u32),
+    /// The parameter sets, without start codes or length prefixes.
+    pub sps: Vec<u8>,
+    pub pps: Vec<u8>,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// BOX HELPERS
+///////////////////////////////////////////////////////////////////////////////
+
+fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(body.len() + 8);
+    output.extend_from_slice(&(body.len() as u32 + 8).to_be_bytes());
+    output.extend_from_slice(kind);
+    output.extend_from_slice(body);
+    output
+}
+
+/// A box with a version and flags.
+fn full_box(kind: &[u8; 4], version: u8, flags: u32, body: &[u8]) -> Vec<u8> {
+    let mut header = flags.to_be_bytes();
+    header[0] = version;
+    mp4_box(kind, &[&header[..], body].concat())
+}
+
+fn u16s(values: &[u16]) -> Vec<u8> {
+    values.iter().flat_map(|x| x.to_be_bytes()).collect()
+}
+
+fn u32s(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|x| x.to_be_bytes()).collect()
+}
+
+/// The identity transform, of `mvhd` and `tkhd`.
+const MATRIX: [u32; 9] = [0x10000, 0, 0, 0, 0x10000, 0, 0, 0, 0x40000000];
+
+/// Of the `stsd`: the SPS and PPS, with the profile and level of the SPS.
+fn avcc(track: &Track) -> Vec<u8> {
+    let mut body = vec![1, track.sps[1], track.sps[2], track.sps[3]];
+    // FOUR BYTE NAL LENGTHS; ONE SPS
+    body.extend_from_slice(&[0xFF, 0xE1]);
+    body.extend(u16s(&[track.sps.len() as u16]));
+    body.extend_from_slice(&track.sps);
+    body.push(1);
+    body.extend(u16s(&[track.pps.len() as u16]));
+    body.extend_from_slice(&track.pps);
+    mp4_box(b"avcC", &body)
+}
+
+/// Runs of equal values, as (count, value) pairs, e.g. of the `ctts`.
+fn runs(values: impl Iterator<Item = u32>) -> Vec<(u32, u32)> {
+    let mut runs = Vec::<(u32, u32)>::new();
+    for value in values {
+        match runs.last_mut() {
+            Some((count, last)) if *last == value => *count += 1,
+            _ => runs.push((1, value)),
+        }
+    }
+    runs
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// MUXER
+///////////////////////////////////////////////////////////////////////////////
+
+/// The metadata, given the offset of the samples (in the file).
+fn moov(track: &Track, samples: &[Sample], chunk_offset: u32) -> Vec<u8> {
+    let (rate_num, rate_den) = track.frame_rate;
+    // ONE TICK PER FRAME, AT THE FRAME RATE’S DENOMINATOR
+    let (timescale, delta) = (rate_num, rate_den);
+    let duration = samples.len() as u32 * delta;
+    // DECODING STARTS AT ZERO; PRESENTATION THEN STARTS LATER, BY AS MANY
+    // FRAMES AS DECODING RAN AHEAD, WHICH THE EDIT LIST SKIPS
+    let shift = samples.iter().map(|x| -x.dts).max().unwrap_or(0).max(0);
+    let mvhd = [
+        u32s(&[0, 0, timescale, duration, 0x10000]),
+        u16s(&[0x100, 0]),
+        u32s(&[0, 0]),
+        u32s(&MATRIX),
+        u32s(&[0; 6]),
+        // THE NEXT TRACK ID
+        u32s(&[2]),
+    ].concat();
+    let tkhd = [
+        // TRACK 1
+        u32s(&[0, 0, 1, 0, duration, 0, 0]),
+        u16s(&[0, 0, 0, 0]),
+        u32s(&MATRIX),
+        u32s(&[track.width << 16, track.height << 16]),
+    ].concat();
+    let elst = u32s(&[1, duration, shift as u32 * delta, 0x10000]);
+    let mdhd = [
+        u32s(&[0, 0, timescale, duration]),
+        // UNDETERMINED LANGUAGE
+        u16s(&[0x55C4, 0]),
+    ].concat();
+    let hdlr = [
+        &[0u8; 4][..],
+        b"vide",
+        &[0u8; 12],
+        b"VideoHandler\0",
+    ].concat();
+    let vmhd = u16s(&[0, 0, 0, 0]);
+    let dinf = mp4_box(b"dref", &[
+        &u32s(&[0, 1])[..],
+        // SELF-CONTAINED
+        &full_box(b"url ", 0, 1, &[]),
+    ].concat());
+    let avc1 = [
+        &[0u8; 6][..],
+        // DATA REFERENCE INDEX
+        &u16s(&[1]),
+        &[0u8; 16],
+        &u16s(&[track.width as u16, track.height as u16]),
+        // 72 DPI
+        &u32s(&[0x480000, 0x480000, 0]),
+        &u16s(&[1]),
+        &[0u8; 32],
+        &u16s(&[0x18, 0xFFFF]),
+        &avcc(track),
+    ].concat();
+    let stsd = [u32s(&[0, 1]), mp4_box(b"avc1", &avc1)].concat();
+    let stts = u32s(&[0, 1, samples.len() as u32, delta]);
+    let offsets = runs(samples.iter().map(|x| (x.pts - x.dts) as u32 * delta));
+    let keyframes = samples
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| x.keyframe)
+        .map(|(index, _)| index as u32 + 1)
+        .collect::<Vec<_>>();
+    let sizes = samples.iter().map(|x| x.data.len() as u32).collect::<Vec<_>>();
+    let mut stbl = [
+        mp4_box(b"stsd", &stsd),
+        mp4_box(b"stts", &stts),
+    ].concat();
+    if offsets.iter().any(|(_, offset)| *offset != 0) {
+        let entries = offsets.iter().flat_map(|(count, offset)| [*count, *offset]).collect::<Vec<_>>();
+        stbl.extend(full_box(b"ctts", 0, 0, &[u32s(&[offsets.len() as u32]), u32s(&entries)].concat()));
+    }
+    stbl.extend(full_box(b"stss", 0, 0, &[u32s(&[keyframes.len() as u32]), u32s(&keyframes)].concat()));
+    // ONE CHUNK OF EVERY SAMPLE
+    stbl.extend(full_box(b"stsc", 0, 0, &u32s(&[1, 1, samples.len() as u32, 1])));
+    stbl.extend(full_box(b"stsz", 0, 0, &[u32s(&[0, sizes.len() as u32]), u32s(&sizes)].concat()));
+    stbl.extend(full_box(b"stco", 0, 0, &u32s(&[1, chunk_offset])));
+    let minf = [
+        full_box(b"vmhd", 0, 1, &vmhd),
+        mp4_box(b"dinf", &dinf),
+        mp4_box(b"stbl", &stbl),
+    ].concat();
+    let mdia = [
+        full_box(b"mdhd", 0, 0, &mdhd),
+        full_box(b"hdlr", 0, 0, &hdlr),
+        mp4_box(b"minf", &minf),
+    ].concat();
+    let trak = [
+        // ENABLED, IN THE MOVIE AND PREVIEW
+        full_box(b"tkhd", 0, 7, &tkhd),
+        mp4_box(b"edts", &full_box(b"elst", 0, 0, &elst)),
+        mp4_box(b"mdia", &mdia),
+    ].concat();
+    mp4_box(b"moov", &[
+        full_box(b"mvhd", 0, 0, &mvhd),
+        mp4_box(b"trak", &trak),
+    ].concat())
+}
+
+/// An MP4 file of the one track.
+pub fn mux_mp4(track: &Track, samples: &[Sample]) -> Vec<u8> {
+    let ftyp = mp4_box(b"ftyp", b"isom\0\0\x02\0isomiso2avc1mp41");
+    let payload = samples.iter().map(|x| x.data.len() as u64).sum::<u64>();
+    // 64 BIT SIZES, IF NEEDED
+    let mdat_header = match u32::try_from(payload + 8) {
+        Ok(size) => [&size.to_be_bytes()[..], b"mdat"].concat(),
+        Err(_) => [&1u32.to_be_bytes()[..], b"mdat", &(payload + 16).to_be_bytes()].concat(),
+    };
+    // THE SIZE OF THE `moov` DOESN’T DEPEND ON THE OFFSET
+    let chunk_offset = ftyp.len() + moov(track, samples, 0).len() + mdat_header.len();
+    let mut output = Vec::with_capacity(chunk_offset + payload as usize);
+    output.extend(ftyp);
+    output.extend(moov(track, samples, chunk_offset as u32));
+    output.extend(mdat_header);
+    for sample in samples {
+        output.extend_from_slice(&sample.data);
+    }
+    output
+}
@@ -2,6 +2,8 @@
 pub mod codec;
 pub mod format;
 pub mod data;
+pub mod eval;
+pub mod scene;
 pub mod tool;
 
 use data::{VideoBuffer, Yuv420P};
@@ -27,7 +29,7 @@ fn encode_from_dir() {
 fn main() {
     codec::h264::run();
     // encode_from_dir();
-    // tool::vmaf::run();
+    // eval::vmaf::run();
     // let source = Yuv420P::open_image("assets/samples/3183183.jpg").expect("load source image");
     // let result = source.to_rgba_image();
     // result.save("assets/output/test.jpeg").expect("save image");
@@ -0,0 +1,195 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Scene (shot) detection: where the content cuts, by how much the luma of
+//! consecutive frames differs, so encoders can pick their parameters per
+//! scene (see `codec::rate`) rather than for the whole video.
+//!
+//! The difference of two frames is the mean of the distance of their luma
+//! histograms and of their (normalized) sum of absolute differences: the
+//! histograms alone miss cuts between similarly lit shots, and the SAD
+//! alone fires on fast motion, which shifts pixels but not the histogram.
+use std::ops::Range;
+use rayon::prelude::*;
+
+use crate::data::{VideoBuffer, Yuv420P};
+
+
+///////////////////////////////////////////////////////////////////////////////
+// DATA TYPES
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The difference (from 0 to 1) of a frame from the previous one above
+    /// which it starts a scene.
+    pub threshold: f64,
+    /// In frames; cuts closer than this to the previous one (e.g. flashes)
+    /// are ignored.
+    pub min_length: usize,
+    /// In frames; longer scenes are split evenly, e.g. to bound the cost of
+    /// searching their parameters.
+    pub max_length: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            threshold: 0.3,
+            min_length: 12,
+            max_length: 120,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// FRAME DIFFERENCES
+///////////////////////////////////////////////////////////////////////////////
+
+/// Of every other luma row and column, which is plenty for cuts.
+const STEP: usize = 2;
+
+const BINS: usize = 64;
+
+fn histogram(frame: &Yuv420P) -> [u32; BINS] {
+    let width = frame.width as usize;
+    let mut histogram = [0; BINS];
+    for row in frame.y().chunks(width).step_by(STEP) {
+        for px in row.iter().step_by(STEP) {
+            histogram[*px as usize * BINS / 256] += 1;
+        }
+    }
+    histogram
+}
+
+/// From 0 (the same) to 1 (nothing in common).
+pub fn difference(a: &Yuv420P, b: &Yuv420P) -> f64 {
+    assert!(a.dimensions() == b.dimensions());
+    let (histogram_a, histogram_b) = (histogram(a), histogram(b));
+    let samples = histogram_a.iter().sum::<u32>().max(1) as f64;
+    let histogram_distance = histogram_a
+        .iter()
+        .zip(histogram_b.iter())
+        .map(|(x, y)| (*x as i64 - *y as i64).unsigned_abs())
+        .sum::<u64>() as f64 / (2.0 * samples);
+    let width = a.width as usize;
+    let sad = a.y()
+        .chunks(width)
+        .zip(b.y().chunks(width))
+        .step_by(STEP)
+        .flat_map(|(x, y)| x.iter().zip(y.iter()).step_by(STEP))
+        .map(|(x, y)| (*x as i32 - *y as i32).unsigned_abs() as u64)
+        .sum::<u64>() as f64 / (255.0 * samples);
+    (histogram_distance + sad) / 2.0
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// SCENES
+///////////////////////////////////////////////////////////////////////////////
+
+/// The range, split into even runs of at most `length`.
+pub fn split(frames: Range<usize>, length: usize) -> Vec<Range<usize>> {
+    let count = frames.len().div_ceil(length.max(1)).max(1);
+    (0..count)
+        .map(|i| {
+            let start = frames.start + i * frames.len() / count;
+            let end = frames.start + (i + 1) * frames.len() / count;
+            start..end
+        })
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+/// The scenes of the stream, in order and covering every frame.
+pub fn detect(stream: &VideoBuffer, options: &Options) -> Vec<Range<usize>> {
+    let frames = stream.as_frames();
+    // OF EVERY FRAME FROM THE PREVIOUS ONE
+    let differences = frames
+        .par_windows(2)
+        .map(|x| difference(&x[0], &x[1]))
+        .collect::<Vec<_>>();
+    let mut starts = vec![0];
+    for (index, difference) in differences.iter().enumerate().map(|(i, x)| (i + 1, *x)) {
+        let last = *starts.last().unwrap();
+        if difference > options.threshold && index - last >= options.min_length {
+            starts.push(index);
+        }
+    }
+    starts.push(frames.len());
+    starts
+        .windows(2)
+        .flat_map(|x| split(x[0]..x[1], options.max_length))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Of the luma, with neutral chroma.
+    fn frame(luma: impl Fn(usize, usize) -> u8) -> Yuv420P {
+        let (width, height) = (16, 16);
+        let mut data = Vec::with_capacity(width * height * 3 / 2);
+        for y in 0..height {
+            for x in 0..width {
+                data.push(luma(x, y));
+            }
+        }
+        data.resize(width * height * 3 / 2, 128);
+        Yuv420P {width: width as u32, height: height as u32, data}
+    }
+
+    #[test]
+    fn test_difference() {
+        let (black, white) = (frame(|_, _| 0), frame(|_, _| 255));
+        let gradient = frame(|x, y| (x * 8 + y * 4) as u8);
+        // THE SAME GRADIENT, MOVED BY A PIXEL
+        let moved = frame(|x, y| ((x + 1) * 8 + y * 4) as u8);
+        assert_eq!(difference(&black, &black), 0.0);
+        assert_eq!(difference(&gradient, &gradient), 0.0);
+        assert_eq!(difference(&black, &white), 1.0);
+        assert_eq!(difference(&black, &white), difference(&white, &black));
+        let motion = difference(&gradient, &moved);
+        assert!(motion > 0.0 && motion < Options::default().threshold, "{}", motion);
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split(0..10, 4), vec![0..3, 3..6, 6..10]);
+        assert_eq!(split(5..8, 10), vec![5..8]);
+        assert_eq!(split(0..3, 0), vec![0..1, 1..2, 2..3]);
+        assert!(split(4..4, 10).is_empty());
+        // EVEN, AND COVERING THE RANGE
+        let scenes = split(7..107, 30);
+        assert_eq!(scenes.len(), 4);
+        assert!(scenes.iter().all(|x| x.len() == 25));
+        assert_eq!((scenes[0].start, scenes[3].end), (7, 107));
+        assert!(scenes.windows(2).all(|x| x[0].end == x[1].start));
+    }
+
+    #[test]
+    fn test_detect() {
+        // A DARK SHOT WITH A ONE FRAME FLASH AT 10, THEN A BRIGHT SHOT AT 20
+        // THAT SLOWLY BRIGHTENS (NOT A CUT)
+        let frames = (0..40)
+            .map(|index| match index {
+                10 => frame(|_, _| 255),
+                0..=19 => frame(|x, _| (x * 2) as u8),
+                _ => frame(|x, _| (150 + x * 2 + index - 20) as u8),
+            })
+            .collect::<Vec<_>>();
+        let stream = VideoBuffer::from_frames(frames);
+        let options = |min_length, max_length| Options {threshold: 0.3, min_length, max_length};
+        // THE FLASH STARTS A SCENE, BUT ITS END IS TOO CLOSE TO IT
+        assert_eq!(detect(&stream, &options(5, 100)), vec![0..10, 10..20, 20..40]);
+        // MERGED INTO THE FIRST SCENE, WITH A LONGER MINIMUM
+        assert_eq!(detect(&stream, &options(12, 100)), vec![0..20, 20..40]);
+        // LONG SCENES SPLIT EVENLY
+        assert_eq!(
+            detect(&stream, &options(12, 8)),
+            vec![0..6, 6..13, 13..20, 20..26, 26..33, 33..40],
+        );
+        let single = VideoBuffer::singleton(frame(|_, _| 0));
+        assert_eq!(detect(&single, &Options::default()), vec![0..1]);
+    }
+}
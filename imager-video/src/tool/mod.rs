@@ -1,2 +1 @@
-pub mod vmaf;
-pub mod classifier;
\ No newline at end of file
+pub mod classifier;
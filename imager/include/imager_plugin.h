@@ -0,0 +1,39 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+/* The ABI of imager encoder plugins (`imager --plugin <library>`, with the
+ * `plugins` feature). A plugin's encoder replaces imager's own for its
+ * format. Plugins are never unloaded, and may be called from several
+ * threads at once. */
+#ifndef IMAGER_PLUGIN_H
+#define IMAGER_PLUGIN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define IMAGER_PLUGIN_ABI_VERSION 1
+
+typedef struct ImagerEncoderPlugin {
+    /* IMAGER_PLUGIN_ABI_VERSION. */
+    uint32_t abi_version;
+    /* E.g. "acme-jpeg". */
+    const char *name;
+    /* "jpeg", "png" or "webp". */
+    const char *format;
+    /* Encodes `height` rows of `width` RGBA8 pixels (unpadded). `options`
+     * is a JSON object: {"extreme": bool, "seed": int, "palette": bool},
+     * where `palette` means the colors must be kept exactly. On success,
+     * returns 0 and sets `*output` and `*output_len`; any other status is
+     * a failure. */
+    int32_t (*encode)(const uint8_t *rgba, uint32_t width, uint32_t height,
+                      const char *options, uint8_t **output, size_t *output_len);
+    /* Releases an `encode` output. */
+    void (*free_output)(uint8_t *output, size_t output_len);
+} ImagerEncoderPlugin;
+
+/* The entry point every plugin exports; the table must outlive the
+ * process. */
+const ImagerEncoderPlugin *imager_encoder_plugin(void);
+
+#endif
@@ -1,21 +1,78 @@
 use either::{Either, Either::*};
 use image::{DynamicImage, GenericImage, GenericImageView, ImageFormat};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::{
     convert::AsRef,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use crate::{
-    codec::{jpeg, png, webp},
-    data::{OutputFormat, Resolution},
+    background::BackgroundRemover,
+    codec::registry::EncodeOptions,
+    codec::{jpeg, jxl, png, webp},
+    crop::Crop,
+    data::{
+        BrandPalette, ColorMode, Matte, OutputFormat, OutputSize, QualityRange, Resolution, Seed, Subsampling,
+        Threshold, Tuning, VmafTargets,
+    },
+    decode::{DecodeOptions, Decoder},
+    error::ImagerError,
+    input::{InputBuffer, ReadMode},
+    meta::{Attribution, PrivacyPolicy, SourceMetadata},
+    report::{DistanceCheck, Fallback, Versions, Warning, WarningKind},
+    resize::{Fit, Geometry, ResizeFilter},
+    upscale::Upscaler,
 };
 
+#[derive(Clone)]
 pub struct OptJob {
     source: DynamicImage,
     source_format: ImageFormat,
+    decoder: Decoder,
     output_format: OutputFormat,
     max_size: Option<Resolution>,
+    fit: Fit,
+    filter: ResizeFilter,
+    /// Of `cover` fits.
+    crop: Crop,
+    /// How to enlarge sources smaller than the `max_size`, if at all.
+    upscaler: Option<Upscaler>,
+    /// Cut out the foreground, if given.
+    background: Option<Arc<BackgroundRemover>>,
+    /// The source’s EXIF, ICC profile and XMP, if any.
+    metadata: SourceMetadata,
+    /// The EXIF orientation applied to the pixels, 1 if none.
+    orientation: u16,
+    /// Of animated sources, of which only the first frame is decoded.
+    frame_count: Option<u64>,
+    /// The file of JPEG sources decoded at full size, to transcode to JPEG
+    /// XL losslessly.
+    jpeg: Option<Vec<u8>>,
+    privacy: PrivacyPolicy,
+    attribution: Attribution,
+    /// Colors to snap the output to, and whether to dither.
+    palette: Option<BrandPalette>,
+    dither: bool,
+    /// The ID to invisibly mark outputs with.
+    watermark: Option<u32>,
+    /// For stages that use randomness.
+    seed: Seed,
+    tuning: Tuning,
+    /// Embed a thumbnail of the output in its EXIF (JPEG only).
+    exif_thumbnail: bool,
+    /// Keep text crisp (JPEG only).
+    text_protect: bool,
+    /// To composite transparency onto (lossy WebP only).
+    matte: Option<Matte>,
+    /// Bracket the VMAF searches on a low-resolution proxy.
+    fast_search: bool,
+    /// The share of tiles very large sources are scored on.
+    metric_coverage: Option<f64>,
+    color_mode: ColorMode,
+    /// Of bilevel outputs.
+    threshold: Threshold,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -24,41 +81,119 @@ pub struct OutMeda {
     pub input_path: Option<PathBuf>,
     pub output_path: Option<PathBuf>,
     pub vmaf_score: Option<f64>,
+    /// Of outputs encoded for a `Tuning::max_distance`, in place of the
+    /// VMAF search.
+    #[serde(default)]
+    pub distance: Option<DistanceCheck>,
     pub extreme_mode: Option<bool>,
+    /// The decoder (of the fallback chain) that handled the input.
+    pub decoder: Option<Decoder>,
+    /// The source’s content credentials, if it had any or the output was
+    /// signed.
+    pub c2pa: Option<crate::meta::c2pa::C2paReport>,
+    /// Quality risks of this output.
+    #[serde(default)]
+    pub warnings: Vec<Warning>,
+    /// Set when the output is of a fallback format, since the encoder of
+    /// the requested one failed.
+    #[serde(default)]
+    pub fallback: Option<Fallback>,
+    /// Of imager and the encoder, e.g. to re-run the outputs of a buggy
+    /// encoder release.
+    #[serde(default)]
+    pub versions: Option<Versions>,
+    /// In bytes.
+    pub input_size: Option<u64>,
+    pub output_size: Option<u64>,
+    /// The time taken, from reading the input to writing the output.
+    pub duration_ms: Option<u64>,
+}
+
+impl OutMeda {
+    /// The fraction of the input size saved (negative when the output is
+    /// larger).
+    pub fn savings(&self) -> Option<f64> {
+        match (self.input_size?, self.output_size?) {
+            (0, _) => None,
+            (input, output) => Some(1.0 - output as f64 / input as f64),
+        }
+    }
 }
 
 impl OptJob {
-    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ()> {
-        let source = std::fs::read(path).expect("input file path");
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ImagerError> {
+        OptJob::open_with_read_mode(path, ReadMode::default())
+    }
+    pub fn open_with_read_mode<P: AsRef<Path>>(path: P, read_mode: ReadMode) -> Result<Self, ImagerError> {
+        let source = InputBuffer::open(path.as_ref(), read_mode).map_err(ImagerError::io(path.as_ref()))?;
         OptJob::new(&source)
     }
-    pub fn new(source: &[u8]) -> Result<Self, ()> {
-        let source_format = ::image::guess_format(source).map_err(drop)?;
+    pub fn new(source: &[u8]) -> Result<Self, ImagerError> {
+        OptJob::new_with_options(source, &DecodeOptions::default())
+    }
+    /// Like `OptJob::new`, with a custom decoder chain. When a `max_size`
+    /// is given, large JPEG and PNG sources are downscaled while decoding,
+    /// which is much faster for thumbnail sized outputs. With
+    /// `auto_orient`, the source’s EXIF orientation is applied to the
+    /// pixels (and the `max_size` is of the oriented source).
+    pub fn new_with_options(source: &[u8], options: &DecodeOptions) -> Result<Self, ImagerError> {
+        let source_format = ::image::guess_format(source)?;
         let output_format = match source_format {
             ImageFormat::Png => OutputFormat::Png,
             ImageFormat::WebP => OutputFormat::Webp,
+            ImageFormat::Avif => OutputFormat::Avif,
             _ => OutputFormat::Jpeg,
         };
-        if source_format == ImageFormat::WebP {
-            let source = webp::decode::decode(source);
-            let source = crate::data::ensure_even_reslution(&source);
-            Ok(OptJob {
-                output_format,
-                source,
-                source_format,
-                max_size: None,
-            })
-        } else {
-            let source =
-                ::image::load_from_memory_with_format(source, source_format).map_err(drop)?;
-            let source = crate::data::ensure_even_reslution(&source);
-            Ok(OptJob {
-                output_format,
-                source,
-                source_format,
-                max_size: None,
-            })
-        }
+        let metadata = SourceMetadata::extract(source, source_format);
+        let frame_count = match source_format {
+            ImageFormat::Png => crate::codec::png::apng::frame_count(source).filter(|x| *x > 1),
+            _ => None,
+        };
+        let orientation = match options.auto_orient {
+            true => metadata.exif.as_deref().and_then(crate::meta::exif::orientation).unwrap_or(1),
+            false => 1,
+        };
+        let jpeg = Some(source.to_vec())
+            .filter(|_| source_format == ImageFormat::Jpeg && options.max_size.is_none() && orientation == 1);
+        // DECODED AS STORED, I.E. TRANSPOSED
+        let transposed = DecodeOptions {
+            max_size: options.max_size.clone().map(|x| Resolution::new(x.height, x.width)),
+            ..options.clone()
+        };
+        let decode_options = if orientation >= 5 { &transposed } else { options };
+        let (source, decoder) = crate::decode::decode(source, source_format, decode_options)?;
+        let source = crate::meta::exif::orient(source, orientation);
+        let source = crate::data::ensure_even_reslution(&source);
+        Ok(OptJob {
+            output_format,
+            source,
+            source_format,
+            decoder,
+            max_size: options.max_size.clone(),
+            fit: options.fit,
+            filter: ResizeFilter::default(),
+            crop: Crop::default(),
+            upscaler: None,
+            background: None,
+            metadata,
+            orientation,
+            frame_count,
+            jpeg,
+            privacy: PrivacyPolicy::default(),
+            attribution: Attribution::default(),
+            palette: None,
+            dither: true,
+            watermark: None,
+            seed: Seed::default(),
+            tuning: Tuning::default(),
+            exif_thumbnail: false,
+            text_protect: false,
+            matte: None,
+            fast_search: false,
+            metric_coverage: None,
+            color_mode: ColorMode::default(),
+            threshold: Threshold::default(),
+        })
     }
 
     pub fn output_format(&mut self, output_format: OutputFormat) {
@@ -67,54 +202,899 @@ impl OptJob {
     pub fn max_size(&mut self, max_size: Resolution) {
         self.max_size = Some(max_size);
     }
-    pub fn run(self, extreme_mode: bool) -> Result<(Vec<u8>, OutMeda), ()> {
-        let input = match self.max_size {
-            Some(res) if (res.width, res.height) < self.source.dimensions() => self.source.resize(
-                res.width,
-                res.height,
-                ::image::imageops::FilterType::Lanczos3,
+    /// How sources are resized to the `max_size` (see `resize`); by
+    /// default, to fit inside it.
+    pub fn fit(&mut self, fit: Fit) {
+        self.fit = fit;
+    }
+    /// By default, Lanczos3; tiny `inside` outputs (see `thumbnail`) use
+    /// their own downscaler regardless.
+    pub fn resize_filter(&mut self, filter: ResizeFilter) {
+        self.filter = filter;
+    }
+    /// Which part of `cover` fits is kept (see `crop`); by default, the
+    /// center.
+    pub fn crop(&mut self, crop: Crop) {
+        self.crop = crop;
+    }
+    /// Enlarge sources smaller than the `max_size` to fit it (by at most
+    /// `MAX_UPSCALE`); by default, sources are only ever downscaled.
+    pub fn allow_upscale(&mut self, upscaler: Upscaler) {
+        self.upscaler = Some(upscaler);
+    }
+    /// Make the background transparent (see `background`).
+    pub fn remove_background(&mut self, remover: Arc<BackgroundRemover>) {
+        self.background = Some(remover);
+    }
+    /// What source EXIF to carry over; by default, none.
+    pub fn privacy_policy(&mut self, policy: PrivacyPolicy) {
+        self.privacy = policy;
+    }
+    /// Embed a thumbnail in the EXIF of JPEG outputs, regenerated from the
+    /// output’s pixels; by default, none.
+    pub fn exif_thumbnail(&mut self, enabled: bool) {
+        self.exif_thumbnail = enabled;
+    }
+    /// Correct the chroma of text blocks, and hold them to a higher quality,
+    /// for UI screenshots (see `text_protect`); JPEG outputs only.
+    pub fn text_protect(&mut self, enabled: bool) {
+        self.text_protect = enabled;
+    }
+    /// Composite transparency onto the matte, for lossy WebP outputs (by
+    /// default, it’s kept) and JPEG outputs (by default, onto white).
+    pub fn matte(&mut self, matte: Matte) {
+        self.matte = Some(matte);
+    }
+    /// Bracket the VMAF searches by ones on a low-resolution proxy, then
+    /// only confirm them at full size (see `codec::proxy`), for 2-3x fewer
+    /// full-size encodes at a little accuracy; by default, off.
+    pub fn fast_search(&mut self, enabled: bool) {
+        self.fast_search = enabled;
+    }
+    /// Score the VMAF searches of sources of at least 16MP on this share
+    /// (above 0, below 1) of their tiles, mostly the detailed ones, rather
+    /// than the whole frame (see `codec::tiles`); by default, whole.
+    pub fn metric_coverage(&mut self, coverage: f64) {
+        self.metric_coverage = Some(coverage);
+    }
+    /// Attribution to write into the output; by default, none.
+    pub fn attribution(&mut self, attribution: Attribution) {
+        self.attribution = attribution;
+    }
+    /// Use only the palette’s colors: exactly for PNG (indexed) and WebP
+    /// (then encoded losslessly), approximately for JPEG.
+    pub fn brand_palette(&mut self, palette: BrandPalette, dither: bool) {
+        self.palette = Some(palette);
+        self.dither = dither;
+    }
+    /// Invisibly mark the output with the given ID (see `watermark`).
+    pub fn watermark(&mut self, id: u32) {
+        self.watermark = Some(id);
+    }
+    /// Seeds the stochastic stages; outputs are reproducible for a given
+    /// seed (the default seed included).
+    pub fn seed(&mut self, seed: Seed) {
+        self.seed = seed;
+    }
+    /// Bounds the quality searches; by default, they span the full range.
+    pub fn tuning(&mut self, tuning: Tuning) {
+        self.tuning = tuning;
+    }
+    /// Grayscale, or bilevel (PNG and TIFF only) at the threshold.
+    pub fn color_mode(&mut self, mode: ColorMode, threshold: Threshold) {
+        self.color_mode = mode;
+        self.threshold = threshold;
+    }
+    /// The resolution `run` will encode at.
+    pub fn output_dimensions(&self) -> (u32, u32) {
+        if let Some(geometry) = self.geometry() {
+            return geometry.output;
+        }
+        match &self.max_size {
+            Some(res) if (res.width, res.height) < self.source.dimensions() => {
+                resize_dimensions(self.source.dimensions(), res)
+            }
+            Some(res) if self.upscaler.is_some() => {
+                crate::upscale::upscale_dimensions(self.source.dimensions(), res)
+                    .unwrap_or_else(|| self.source.dimensions())
+            }
+            _ => self.source.dimensions(),
+        }
+    }
+    /// Of fits other than `inside`, which keeps its own path (below).
+    fn geometry(&self) -> Option<Geometry> {
+        let max_size = self.max_size.as_ref().filter(|_| self.fit != Fit::Inside)?;
+        Some(Geometry::new(self.source.dimensions(), max_size, self.fit, self.upscaler.is_some()))
+    }
+    fn resize(&self) -> Resize {
+        let source = self.source.dimensions();
+        match self.geometry() {
+            Some(Geometry { scaled, output }) if scaled == source && output == source => return Resize::None,
+            Some(geometry) => return Resize::Fit(geometry, self.upscaler.filter(|_| geometry.enlarges(source))),
+            None => (),
+        }
+        match (&self.max_size, self.upscaler) {
+            // ICON SIZED OUTPUTS GET THEIR OWN DOWNSCALER
+            (Some(res), _) if (res.width, res.height) < self.source.dimensions() => {
+                if crate::thumbnail::is_tiny(self.output_dimensions()) {
+                    Resize::Thumbnail
+                } else {
+                    Resize::Filter(res.clone())
+                }
+            }
+            (Some(_), Some(upscaler)) if self.output_dimensions() != self.source.dimensions() => {
+                Resize::Upscale(upscaler)
+            }
+            _ => Resize::None,
+        }
+    }
+    /// The JPEG source, without its metadata, if `run` will transcode it to
+    /// JPEG XL losslessly, i.e. its pixels are encoded unchanged, by the
+    /// built-in JPEG XL encoder.
+    fn jpeg_to_transcode(&self) -> Option<Vec<u8>> {
+        let unchanged = matches!(self.resize(), Resize::None)
+            && self.background.is_none()
+            && self.watermark.is_none()
+            && self.palette.is_none()
+            && self.color_mode == ColorMode::Color;
+        let builtin = crate::codec::registry::encoder(&OutputFormat::Jxl).is_ok_and(|x| x.is_builtin());
+        if self.output_format != OutputFormat::Jxl || !unchanged || !builtin {
+            return None;
+        }
+        crate::meta::container::jpeg_strip_metadata(self.jpeg.as_deref()?)
+    }
+    /// The quality risks `run` will incur, as far as they’re known before
+    /// encoding.
+    pub fn warnings(&self) -> Vec<Warning> {
+        let mut warnings = Vec::new();
+        let (width, height) = self.output_dimensions();
+        let jpeg = self.output_format == OutputFormat::Jpeg;
+        let upscaler = match self.resize() {
+            Resize::Upscale(upscaler) | Resize::Fit(_, Some(upscaler)) => Some(upscaler),
+            _ => None,
+        };
+        if let Some(upscaler) = upscaler {
+            let (source_width, source_height) = self.source.dimensions();
+            warnings.push(Warning::new(
+                WarningKind::Upscaled,
+                format!(
+                    "enlarged from {}x{} to {}x{} ({:?})",
+                    source_width, source_height, width, height, upscaler
+                ),
+            ));
+        }
+        let transparent = || {
+            self.source.color().has_alpha()
+                && self.source.pixels().any(|(_, _, px)| px.0[3] < 255)
+        };
+        if jpeg && (self.background.is_some() || transparent()) {
+            warnings.push(Warning::new(
+                WarningKind::AlphaFlattened,
+                format!(
+                    "JPEG has no alpha channel; transparency is composited onto {}",
+                    self.matte.unwrap_or_default()
+                ),
+            ));
+        }
+        let carries_icc = matches!(self.output_format, OutputFormat::Jpeg | OutputFormat::Png | OutputFormat::Webp);
+        if self.metadata.icc_profile.is_some() && !(self.privacy.keep_icc && carries_icc) {
+            warnings.push(Warning::new(
+                WarningKind::ColorProfileDiscarded,
+                "the source’s ICC profile isn’t carried over",
+            ));
+        }
+        if jpeg && self.palette.is_some() {
+            warnings.push(Warning::new(
+                WarningKind::PaletteApproximated,
+                "JPEG only approximates the palette colors",
+            ));
+        }
+        if self.watermark.is_some() && !crate::watermark::can_embed((width, height)) {
+            warnings.push(Warning::new(
+                WarningKind::WatermarkUnreliable,
+                format!("{}x{} is too small to be reliably watermarked", width, height),
+            ));
+        }
+        if let Some(frames) = self.frame_count {
+            warnings.push(Warning::new(
+                WarningKind::AnimationFlattened,
+                format!("only the first of the source’s {} frames is kept", frames),
+            ));
+        }
+        warnings
+    }
+    /// What `run` would do, i.e. the stages in the order they’d run (named
+    /// as in `pipeline::Stage`), with their settings; nothing is encoded.
+    pub fn plan(&self, extreme_mode: bool) -> Plan {
+        let (width, height) = self.output_dimensions();
+        let mut stages = Vec::new();
+        let mut push = |stage: &str, detail: String| {
+            stages.push(PlannedStage {
+                stage: String::from(stage),
+                detail,
+            })
+        };
+        let (source_width, source_height) = self.source.dimensions();
+        push(
+            "decode",
+            format!(
+                "{} decoder ({:?} source, {}x{})",
+                self.decoder, self.source_format, source_width, source_height
             ),
-            _ => self.source.clone(),
-        };
-        match self.output_format {
-            OutputFormat::Webp => {
-                let (out, meta) = webp::opt::opt(&input);
-                let meta = OutMeda {
-                    input_class: meta.class,
-                    input_path: meta.input_path,
-                    output_path: meta.output_path,
-                    vmaf_score: None,
-                    extreme_mode: Some(extreme_mode),
+        );
+        if self.orientation != 1 {
+            push("orient", format!("EXIF orientation {} applied", self.orientation));
+        }
+        match self.resize() {
+            Resize::Thumbnail => push("resize", format!("{}x{}, area average and adaptive sharpen", width, height)),
+            Resize::Filter(_) => push("resize", format!("{}x{}, {}", width, height, self.filter)),
+            Resize::Upscale(upscaler) => push("resize", format!("{}x{}, upscaled via {:?}", width, height, upscaler)),
+            Resize::Fit(geometry, upscaler) => {
+                let (scaled_width, scaled_height) = geometry.scaled;
+                let scaler = match upscaler {
+                    Some(upscaler) => format!("upscaled via {:?}", upscaler),
+                    None => self.filter.to_string(),
                 };
-                Ok((out, meta))
-            }
-            OutputFormat::Jpeg => {
-                let (out, meta) = jpeg::OptContext::from_image(input).run_search(extreme_mode);
-                let meta = OutMeda {
-                    input_class: meta.class,
-                    input_path: None,
-                    output_path: None,
-                    vmaf_score: meta.vmaf_score,
-                    extreme_mode: Some(extreme_mode),
+                let fit = match self.fit {
+                    Fit::Cover => format!("{}, {} crop", self.fit, self.crop),
+                    fit => fit.to_string(),
                 };
-                Ok((out, meta))
+                push(
+                    "resize",
+                    format!("{}x{}, {} (scaled to {}x{}, {})", width, height, fit, scaled_width, scaled_height, scaler),
+                );
             }
-            OutputFormat::Png => {
-                let class_report = crate::classifier::report(&input);
-                let out = png::basic_optimize(&input);
-                let meta = OutMeda {
-                    input_class: class_report.class,
-                    input_path: None,
-                    output_path: None,
-                    vmaf_score: None,
-                    extreme_mode: Some(extreme_mode),
-                };
-                Ok((out, meta))
+            Resize::None => (),
+        }
+        if self.background.is_some() {
+            push("remove-background", String::from("ONNX segmentation matte"));
+        }
+        if let Some(id) = self.watermark {
+            push("watermark", format!("ID {}", id));
+        }
+        if let Some(palette) = &self.palette {
+            let dither = if self.dither { "Floyd-Steinberg dithered" } else { "not dithered" };
+            push("palette", format!("{} colors, {}", palette.0.len(), dither));
+        }
+        match self.color_mode {
+            ColorMode::Color => (),
+            ColorMode::Grayscale => push("color-mode", String::from("grayscale")),
+            ColorMode::Bilevel => push(
+                "color-mode",
+                match self.threshold {
+                    Threshold::Otsu => String::from("bilevel, Otsu threshold"),
+                    Threshold::Fixed(x) => format!("bilevel, threshold {}", x),
+                },
+            ),
+        }
+        // AS `codec::registry` ENCODES THEM
+        let lossy = match self.output_format {
+            OutputFormat::Jpeg | OutputFormat::Avif => true,
+            OutputFormat::Webp => self.palette.is_none(),
+            OutputFormat::Jxl => self.palette.is_none() && self.jpeg_to_transcode().is_none(),
+            OutputFormat::Png | OutputFormat::Tiff => false,
+        };
+        let within_distance = self.tuning.max_distance.filter(|_| lossy);
+        let vmaf_target = match self.tuning.vmaf.get(&self.output_format) {
+            Some(target) => format!("reaches the target of {}", target),
+            None => String::from("passes the class (and size) dependent threshold"),
+        };
+        let vmaf_target = match self.fast_search {
+            true => format!("{} (bracketed by a search on a low-resolution proxy)", vmaf_target),
+            false => vmaf_target,
+        };
+        let vmaf_target = match self.metric_coverage {
+            Some(coverage) => format!(
+                "{} (scored on {}% of the tiles of sources from 16MP)",
+                vmaf_target,
+                coverage * 100.0
+            ),
+            None => vmaf_target,
+        };
+        let encoder = match self.output_format {
+            _ if within_distance.is_some() => format!(
+                "quality bisection for the lowest with a butteraugli distance within {}",
+                within_distance.expect("max distance")
+            ),
+            OutputFormat::Webp if self.palette.is_some() => {
+                String::from("libwebp lossless (keeps the exact palette colors)")
+            }
+            OutputFormat::Webp => format!(
+                "libwebp lossy (method {}){}; quality search (q{}-q{}) until the VMAF score {}, else q{}",
+                self.tuning.webp_effort,
+                match self.matte {
+                    Some(matte) => format!(", transparency composited onto {}", matte),
+                    None => String::from(", with alpha"),
+                },
+                self.tuning.webp.min,
+                self.tuning.webp.max,
+                vmaf_target,
+                self.tuning.webp.max
+            ),
+            OutputFormat::Jpeg => format!(
+                "mozjpeg {}; quality search (q{}-q{}) until the VMAF score {}, else q{}{}{}",
+                match self.tuning.jpeg_subsampling {
+                    Subsampling::Yuv420 => "4:2:0",
+                    Subsampling::Yuv444 => "4:4:4",
+                },
+                self.tuning.jpeg.min,
+                self.tuning.jpeg.max,
+                vmaf_target,
+                self.tuning.jpeg.max,
+                if extreme_mode { "; extreme mode" } else { "" },
+                if self.text_protect {
+                    format!(
+                        "; text blocks chroma corrected, and held to {} dB",
+                        crate::text_protect::TEXT_PSNR
+                    )
+                } else {
+                    String::new()
+                }
+            ),
+            OutputFormat::Png if self.palette.is_some() => {
+                String::from("indexed PNG of the palette colors")
+            }
+            OutputFormat::Png if self.color_mode == ColorMode::Bilevel => String::from("1 bit PNG"),
+            OutputFormat::Png if self.color_mode == ColorMode::Grayscale => {
+                String::from("8 bit grayscale PNG")
+            }
+            OutputFormat::Tiff if self.color_mode == ColorMode::Bilevel => {
+                String::from("1 bit TIFF, CCITT Group 4 compressed")
+            }
+            OutputFormat::Tiff => String::from("Deflate compressed TIFF"),
+            OutputFormat::Avif => format!(
+                "libheif AV1; quality bisection (q{}-q{}) for the lowest whose VMAF score {}, else q{}",
+                self.tuning.avif.min, self.tuning.avif.max, vmaf_target, self.tuning.avif.max
+            ),
+            OutputFormat::Jxl if self.jpeg_to_transcode().is_some() => String::from(
+                "libjxl lossless JPEG transcoding (keeps the DCT coefficients, and the data to \
+                 reconstruct the JPEG)",
+            ),
+            OutputFormat::Jxl if self.palette.is_some() => {
+                String::from("libjxl lossless (keeps the exact palette colors)")
+            }
+            OutputFormat::Jxl => format!(
+                "libjxl lossy at q{} (distance {})",
+                jxl::DEFAULT_QUALITY,
+                jxl::distance(jxl::DEFAULT_QUALITY)
+            ),
+            OutputFormat::Png => String::from(
+                "indexed PNG; the fewest colors with a VMAF score of at least 90, else 256",
+            ),
+        };
+        push("encode", encoder);
+        let mut metadata = Vec::new();
+        if let Some(exif) = &self.metadata.exif {
+            metadata.push(format!("EXIF ({} bytes) {}", exif.len(), self.privacy));
+        }
+        let carried = self.carried_metadata();
+        if let Some(profile) = carried.icc_profile.as_ref().filter(|_| self.privacy.keep_icc) {
+            metadata.push(format!("ICC profile ({} bytes)", profile.len()));
+        }
+        if let Some(packet) = carried.xmp.as_ref().filter(|_| self.privacy.keep_xmp) {
+            metadata.push(format!("source XMP ({} bytes)", packet.len()));
+        }
+        if self.exif_thumbnail && self.output_format == OutputFormat::Jpeg {
+            let (width, height) = crate::meta::exif::THUMBNAIL_SIZE;
+            metadata.push(format!("EXIF thumbnail (within {}x{})", width, height));
+        }
+        if !self.attribution.is_empty() {
+            metadata.push(String::from("attribution XMP"));
+        }
+        if !metadata.is_empty() {
+            push("metadata", metadata.join(", "));
+        }
+        Plan {
+            warnings: self.warnings(),
+            decoder: self.decoder,
+            source_dimensions: (source_width, source_height),
+            output_dimensions: (width, height),
+            output_format: self.output_format.clone(),
+            stages,
+        }
+    }
+    /// Encoders may still panic on internal failures; see `ImagerError`.
+    pub fn run(self, extreme_mode: bool) -> Result<(Vec<u8>, OutMeda), ImagerError> {
+        let mut warnings = self.warnings();
+        if let Some(jpeg) = self.jpeg_to_transcode() {
+            return self.transcode_jpeg(jpeg, extreme_mode, warnings);
+        }
+        let target = self.output_dimensions();
+        let input = match self.resize() {
+            Resize::Thumbnail => crate::thumbnail::downscale(&self.source, target),
+            Resize::Filter(res) => self
+                .source
+                .resize(res.width, res.height, crate::resize::filter_type(self.filter)),
+            Resize::Fit(geometry, upscaler) => {
+                crate::resize::resize(&self.source, &geometry, self.filter, upscaler, self.crop)
+                    .map_err(ImagerError::Encode)?
+            }
+            Resize::Upscale(upscaler) => {
+                crate::upscale::upscale(&self.source, target, upscaler).map_err(ImagerError::Encode)?
+            }
+            Resize::None => self.source.clone(),
+        };
+        let input = match &self.background {
+            Some(remover) => remover.remove(&input).map_err(ImagerError::Encode)?,
+            None => input,
+        };
+        let input = match self.watermark {
+            Some(id) => crate::watermark::embed(&input, id),
+            None => input,
+        };
+        // LAST, SINCE ANY LATER CHANGE WOULD INTRODUCE OFF-PALETTE COLORS
+        let input = match &self.palette {
+            Some(palette) => png::snap_to_palette(&input, palette, self.dither),
+            None => input,
+        };
+        let input = crate::gray::convert(&input, self.color_mode, self.threshold);
+        let dimensions = input.dimensions();
+        // OF THE FINAL PIXELS, RATHER THAN DECODING THE OUTPUT
+        let thumbnail = match (self.exif_thumbnail, &self.output_format) {
+            (true, OutputFormat::Jpeg) => crate::meta::exif::encode_thumbnail(&input),
+            _ => None,
+        };
+        let encoder = crate::codec::registry::encoder(&self.output_format)?;
+        let options = EncodeOptions {
+            palette: self.palette.as_ref(),
+            extreme: extreme_mode,
+            seed: self.seed,
+            tuning: self.tuning,
+            color_mode: self.color_mode,
+            text_protect: self.text_protect,
+            matte: self.matte,
+            fast_search: self.fast_search,
+            metric_coverage: self.metric_coverage,
+        };
+        let encoded = encoder.encode(&input, &options)?;
+        let out = encoded.output;
+        let mut meta = OutMeda {
+            input_class: encoded.class,
+            input_path: None,
+            output_path: None,
+            vmaf_score: encoded.vmaf_score,
+            distance: encoded.distance,
+            extreme_mode: Some(extreme_mode),
+            decoder: Some(self.decoder),
+            c2pa: None,
+            warnings: Vec::new(),
+            fallback: None,
+            versions: Some(encoder.versions()),
+            input_size: None,
+            output_size: None,
+            duration_ms: None,
+        };
+        let out = crate::meta::apply_privacy_policy(
+            out,
+            &self.output_format,
+            &self.carried_metadata(),
+            &self.privacy,
+            dimensions,
+            thumbnail,
+        );
+        let out = crate::meta::apply_attribution(
+            out,
+            &self.output_format,
+            &self.attribution,
+            dimensions,
+        );
+        // EDGE DENSE CLASSES GET THE LOWEST QUALITY THRESHOLDS
+        let lossy = match self.output_format {
+            OutputFormat::Jpeg | OutputFormat::Avif => true,
+            OutputFormat::Webp | OutputFormat::Jxl => self.palette.is_none(),
+            OutputFormat::Png | OutputFormat::Tiff => false,
+        };
+        let edge_dense = matches!(
+            meta.input_class,
+            crate::classifier::Class::H1 | crate::classifier::Class::H2
+        );
+        if lossy && edge_dense {
+            let score = meta
+                .vmaf_score
+                .map(|x| format!(" (VMAF {:.1})", x))
+                .unwrap_or_default();
+            warnings.push(Warning::new(
+                WarningKind::TextCompressed,
+                format!(
+                    "edge dense ({}) content, e.g. text, compressed lossily{}; PNG keeps it sharp",
+                    meta.input_class, score
+                ),
+            ));
+        }
+        if let Some(check) = meta.distance.filter(|x| !x.passed) {
+            warnings.push(Warning::new(
+                WarningKind::DistanceExceeded,
+                format!(
+                    "butteraugli distance {:.2} exceeds the max of {}, even at the top of the quality range",
+                    check.distance, check.max_distance
+                ),
+            ));
+        }
+        meta.warnings = warnings;
+        Ok((out, meta))
+    }
+    /// Like `OptJob::run`, but when the encoder fails (including panics, and
+    /// dimensions the format can’t represent), the job is run again in each
+    /// of the `fallbacks` in turn, and the output (of the first that works)
+    /// records the fallback. If every format fails, the error is the
+    /// requested format’s.
+    pub fn run_with_fallbacks(
+        self,
+        extreme_mode: bool,
+        fallbacks: &[OutputFormat],
+    ) -> Result<(Vec<u8>, OutMeda), ImagerError> {
+        let requested = self.output_format.clone();
+        let (width, height) = self.output_dimensions();
+        let mut errors = Vec::new();
+        let mut first_error = None;
+        for format in std::iter::once(&requested).chain(fallbacks) {
+            let mut job = self.clone();
+            job.output_format(format.clone());
+            let limit = format.max_dimension();
+            let result = if width > limit || height > limit {
+                let message = format!("{}x{} exceeds the {:?} limit of {}", width, height, format, limit);
+                Err(ImagerError::Encode(message))
+            } else {
+                std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| job.run(extreme_mode)))
+                    .unwrap_or_else(|panic| Err(ImagerError::Encode(panic_message(panic))))
+            };
+            match result {
+                Ok((out, mut meta)) if format != &requested => {
+                    let message = format!(
+                        "{:?} failed ({}), so the output is {:?}",
+                        requested,
+                        errors.join("; "),
+                        format
+                    );
+                    meta.warnings.push(Warning::new(WarningKind::EncoderFallback, message));
+                    meta.fallback = Some(Fallback {
+                        requested: requested.clone(),
+                        output: format.clone(),
+                        errors,
+                    });
+                    return Ok((out, meta));
+                }
+                Ok(x) => return Ok(x),
+                Err(error @ (ImagerError::Encode(_) | ImagerError::FeatureDisabled(_))) => {
+                    errors.push(format!("{:?}: {}", format, error));
+                    first_error.get_or_insert(error);
+                }
+                Err(error) => return Err(error),
             }
         }
+        Err(first_error.expect("the requested format was tried"))
+    }
+    /// The source metadata the policy may copy; an attribution replaces the
+    /// source’s XMP, and an applied orientation the source’s.
+    fn carried_metadata(&self) -> SourceMetadata {
+        let exif = self.metadata.exif.as_deref();
+        SourceMetadata {
+            exif: match self.orientation {
+                1 => exif.map(<[u8]>::to_vec),
+                _ => exif.and_then(crate::meta::exif::without_orientation),
+            },
+            xmp: self.metadata.xmp.clone().filter(|_| self.attribution.is_empty()),
+            ..self.metadata.clone()
+        }
+    }
+    /// The metadata is put back into the `jpeg` (as for JPEG outputs), so
+    /// the JPEG XL keeps it too.
+    fn transcode_jpeg(
+        self,
+        jpeg: Vec<u8>,
+        extreme_mode: bool,
+        warnings: Vec<Warning>,
+    ) -> Result<(Vec<u8>, OutMeda), ImagerError> {
+        let dimensions = self.source.dimensions();
+        let jpeg = crate::meta::apply_privacy_policy(
+            jpeg,
+            &OutputFormat::Jpeg,
+            &self.carried_metadata(),
+            &self.privacy,
+            dimensions,
+            None,
+        );
+        let jpeg = crate::meta::apply_attribution(jpeg, &OutputFormat::Jpeg, &self.attribution, dimensions);
+        let out = jxl::transcode_jpeg(&jpeg)?;
+        let meta = OutMeda {
+            input_class: crate::classifier::report_seeded(&self.source, self.seed).class,
+            input_path: None,
+            output_path: None,
+            vmaf_score: None,
+            distance: None,
+            extreme_mode: Some(extreme_mode),
+            decoder: Some(self.decoder),
+            c2pa: None,
+            warnings,
+            fallback: None,
+            versions: Some(Versions {
+                imager: String::from(env!("CARGO_PKG_VERSION")),
+                encoder: String::from("libjxl"),
+                encoder_version: jxl::version(),
+            }),
+            input_size: None,
+            output_size: None,
+            duration_ms: None,
+        };
+        Ok((out, meta))
+    }
+}
+
+/// Of a caught panic, e.g. of an encoder.
+pub fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|x| x.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| String::from("encoder panicked"))
+}
+
+enum Resize {
+    None,
+    Thumbnail,
+    /// Inside the box, by the filter.
+    Filter(Resolution),
+    Upscale(Upscaler),
+    /// Any other fit, enlarged by the upscaler if given.
+    Fit(Geometry, Option<Upscaler>),
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PLAN
+///////////////////////////////////////////////////////////////////////////////
+
+/// What `OptJob::run` will do (see `OptJob::plan`).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Plan {
+    pub decoder: Decoder,
+    pub source_dimensions: (u32, u32),
+    pub output_dimensions: (u32, u32),
+    pub output_format: OutputFormat,
+    pub stages: Vec<PlannedStage>,
+    pub warnings: Vec<Warning>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlannedStage {
+    pub stage: String,
+    pub detail: String,
+}
+
+impl std::fmt::Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (ix, stage) in self.stages.iter().enumerate() {
+            writeln!(f, "{}. {}: {}", ix + 1, stage.stage, stage.detail)?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "warning: {}", warning)?;
+        }
+        Ok(())
     }
 }
 
+/// Mirrors the aspect ratio preserving fit of `DynamicImage::resize`.
+pub fn resize_dimensions((width, height): (u32, u32), max_size: &Resolution) -> (u32, u32) {
+    let ratio = f64::min(
+        max_size.width as f64 / width as f64,
+        max_size.height as f64 / height as f64,
+    );
+    let width = ((width as f64 * ratio).round() as u32).max(1);
+    let height = ((height as f64 * ratio).round() as u32).max(1);
+    (width, height)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// JOB
+///////////////////////////////////////////////////////////////////////////////
+
+/// The high level encode API: the source is decoded once, then resized to
+/// every size and encoded to every format (in parallel), e.g.
+///
+/// ```no_run
+/// # use imager::{api::Job, data::{OutputFormat, OutputSize, Resolution}};
+/// # let source = std::fs::read("photo.jpeg").unwrap();
+/// let outputs = Job::new(&source)
+///     .formats([OutputFormat::Webp, OutputFormat::Jpeg])
+///     .max_size(OutputSize::Px(Resolution::new(1200, 1200)))
+///     .run()?;
+/// # Ok::<(), imager::error::ImagerError>(())
+/// ```
+///
+/// or, for responsive images (see `srcset`), with several `sizes`.
+///
+/// For the stages without a builder method (e.g. watermarks, upscaling),
+/// configure an `OptJob` instead.
+#[derive(Clone)]
+pub struct Job<'a> {
+    source: &'a [u8],
+    /// The source’s own format, if empty.
+    formats: Vec<OutputFormat>,
+    sizes: Vec<OutputSize>,
+    fit: Fit,
+    filter: ResizeFilter,
+    crop: Crop,
+    tuning: Tuning,
+    privacy: PrivacyPolicy,
+    decode: DecodeOptions,
+    auto_orient: bool,
+    fallbacks: Vec<OutputFormat>,
+    extreme: bool,
+}
+
+/// The output of a `Job`, for one of its sizes and formats.
+#[derive(Clone, Debug)]
+pub struct JobOutput {
+    /// The format of the output, i.e. a fallback if the `meta` has one.
+    pub format: OutputFormat,
+    pub size: OutputSize,
+    /// Of the output.
+    pub dimensions: (u32, u32),
+    pub data: Vec<u8>,
+    pub meta: OutMeda,
+}
+
+impl<'a> Job<'a> {
+    pub fn new(source: &'a [u8]) -> Self {
+        Job {
+            source,
+            formats: Vec::new(),
+            sizes: vec![OutputSize::Full],
+            fit: Fit::default(),
+            filter: ResizeFilter::default(),
+            crop: Crop::default(),
+            tuning: Tuning::default(),
+            privacy: PrivacyPolicy::default(),
+            decode: DecodeOptions::default(),
+            auto_orient: false,
+            fallbacks: Vec::new(),
+            extreme: false,
+        }
+    }
+    pub fn formats(mut self, formats: impl IntoIterator<Item = OutputFormat>) -> Self {
+        self.formats = formats.into_iter().collect();
+        self
+    }
+    pub fn max_size(mut self, max_size: OutputSize) -> Self {
+        self.sizes = vec![max_size];
+        self
+    }
+    /// Several `max_size`s, e.g. the widths of a `srcset`; each format is
+    /// encoded at each size.
+    pub fn sizes(mut self, sizes: impl IntoIterator<Item = OutputSize>) -> Self {
+        self.sizes = sizes.into_iter().collect();
+        self
+    }
+    /// How sources are resized to the `max_size` (see `resize`).
+    pub fn fit(mut self, fit: Fit) -> Self {
+        self.fit = fit;
+        self
+    }
+    pub fn resize_filter(mut self, filter: ResizeFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+    /// Of `cover` fits (see `crop`).
+    pub fn crop(mut self, crop: Crop) -> Self {
+        self.crop = crop;
+        self
+    }
+    /// Bounds the quality searches of every format (see `QualityRange`).
+    pub fn quality_target(mut self, range: QualityRange) -> Self {
+        self.tuning = Tuning {
+            jpeg: range,
+            webp: range,
+            avif: range,
+            ..self.tuning
+        };
+        self
+    }
+    /// Per format VMAF targets, in place of the content dependent ones (see
+    /// `VmafTargets`), e.g. a lower one for AVIF than for WebP.
+    pub fn vmaf_targets(mut self, targets: VmafTargets) -> Self {
+        self.tuning.vmaf = targets;
+        self
+    }
+    /// The max butteraugli distance of every (lossy) output, in place of
+    /// quality bounds (see `Tuning::max_distance`).
+    pub fn max_distance(mut self, distance: f64) -> Self {
+        self.tuning.max_distance = Some(distance);
+        self
+    }
+    /// Per format quality bounds, e.g. as written by `imager tune`.
+    pub fn tuning(mut self, tuning: Tuning) -> Self {
+        self.tuning = tuning;
+        self
+    }
+    pub fn privacy_policy(mut self, policy: PrivacyPolicy) -> Self {
+        self.privacy = policy;
+        self
+    }
+    /// The decoder chain; the `max_size`, `fit` and `auto_orient` are the
+    /// job’s.
+    pub fn decode_options(mut self, options: DecodeOptions) -> Self {
+        self.decode = options;
+        self
+    }
+    /// Rotate (or flip) the pixels by the source’s EXIF orientation, and
+    /// reset it in the outputs’ EXIF, for viewers that ignore the tag; by
+    /// default, the tag is carried instead.
+    pub fn auto_orient(mut self, enabled: bool) -> Self {
+        self.auto_orient = enabled;
+        self
+    }
+    /// The formats to try in order when the encoder of one of the `formats`
+    /// fails, rather than failing the job (see `OptJob::run_with_fallbacks`).
+    pub fn fallbacks(mut self, formats: impl IntoIterator<Item = OutputFormat>) -> Self {
+        self.fallbacks = formats.into_iter().collect();
+        self
+    }
+    /// Spend more time searching for smaller outputs.
+    pub fn extreme(mut self, extreme: bool) -> Self {
+        self.extreme = extreme;
+        self
+    }
+    /// The outputs, by the order of the `sizes`, then of the `formats`.
+    pub fn run(self) -> Result<Vec<JobOutput>, ImagerError> {
+        self.tuning
+            .validate()
+            .map_err(ImagerError::InvalidInput)?;
+        if self.sizes.is_empty() {
+            return Err(ImagerError::InvalidInput(String::from("no sizes")));
+        }
+        let options = DecodeOptions {
+            max_size: bounds(&self.sizes),
+            fit: self.fit,
+            auto_orient: self.auto_orient,
+            ..self.decode
+        };
+        let mut opt_job = OptJob::new_with_options(self.source, &options)?;
+        opt_job.resize_filter(self.filter);
+        opt_job.crop(self.crop);
+        opt_job.tuning(self.tuning);
+        opt_job.privacy_policy(self.privacy);
+        let formats = if self.formats.is_empty() {
+            vec![opt_job.output_format.clone()]
+        } else {
+            self.formats
+        };
+        let variants = self
+            .sizes
+            .iter()
+            .flat_map(|size| formats.iter().map(move |format| (size.clone(), format.clone())))
+            .collect::<Vec<_>>();
+        variants
+            .into_par_iter()
+            .map(|(size, format)| {
+                let mut opt_job = opt_job.clone();
+                if let OutputSize::Px(max_size) = &size {
+                    opt_job.max_size(max_size.clone());
+                }
+                opt_job.output_format(format.clone());
+                let dimensions = opt_job.output_dimensions();
+                let fallbacks = self.fallbacks.iter().filter(|x| **x != format).cloned().collect::<Vec<_>>();
+                let (data, meta) = opt_job.run_with_fallbacks(self.extreme, &fallbacks)?;
+                let format = meta.fallback.as_ref().map_or(format, |x| x.output.clone());
+                Ok(JobOutput {
+                    format,
+                    size,
+                    dimensions,
+                    data,
+                    meta,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The box covering every size, to downscale to while decoding, if none
+/// is `Full`.
+fn bounds(sizes: &[OutputSize]) -> Option<Resolution> {
+    sizes.iter().try_fold(Resolution::new(0, 0), |bounds, size| match size {
+        OutputSize::Px(x) => Some(Resolution::new(bounds.width.max(x.width), bounds.height.max(x.height))),
+        OutputSize::Full => None,
+    })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -130,4 +1110,145 @@ mod test {
             assert!(result.is_ok());
         }
     }
+
+    #[test]
+    fn test_plan() {
+        let test_image = include_bytes!("../assets/test/1.jpeg");
+        let mut opt_job = OptJob::new(test_image).expect("new opt job");
+        opt_job.output_format(OutputFormat::Webp);
+        opt_job.max_size(Resolution::new(64, 64));
+        opt_job.watermark(7);
+        let plan = opt_job.plan(false);
+        let stages = plan.stages.iter().map(|x| x.stage.as_str()).collect::<Vec<_>>();
+        assert_eq!(stages, ["decode", "resize", "watermark", "encode"]);
+        assert!(plan.stages[1].detail.contains("area average"));
+        assert_eq!(plan.output_dimensions, opt_job.output_dimensions());
+        assert!(plan.to_string().starts_with("1. decode: image decoder"));
+        assert_eq!(plan.warnings.len(), 1);
+        assert_eq!(plan.warnings[0].kind, WarningKind::WatermarkUnreliable);
+        // PER FORMAT VMAF TARGETS
+        opt_job.tuning(Tuning {
+            vmaf: "webp=92,avif=90".parse().expect("targets"),
+            ..Tuning::default()
+        });
+        let plan = opt_job.plan(false);
+        let encode = plan.stages.last().expect("encode stage");
+        assert!(encode.detail.contains("VMAF score reaches the target of 92"), "{}", encode.detail);
+        opt_job.fast_search(true);
+        let plan = opt_job.plan(false);
+        let encode = plan.stages.last().expect("encode stage");
+        assert!(encode.detail.contains("bracketed by a search on a low-resolution proxy"), "{}", encode.detail);
+        opt_job.metric_coverage(0.25);
+        let plan = opt_job.plan(false);
+        let encode = plan.stages.last().expect("encode stage");
+        assert!(encode.detail.contains("scored on 25% of the tiles"), "{}", encode.detail);
+        // UPSCALING
+        let mut opt_job = OptJob::new(test_image).expect("new opt job");
+        opt_job.max_size(Resolution::new(2000, 2000));
+        opt_job.allow_upscale(Upscaler::Lanczos);
+        let kinds = opt_job.warnings().into_iter().map(|x| x.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, [WarningKind::Upscaled]);
+    }
+
+    #[test]
+    fn test_seeded_run_is_reproducible() {
+        use crate::data::StageRng;
+        use rand::Rng;
+        let test_image = include_bytes!("../assets/test/1.jpeg");
+        let run = || {
+            let mut opt_job = OptJob::new(test_image).expect("new opt job");
+            opt_job.output_format(OutputFormat::Png);
+            opt_job.max_size(Resolution::new(200, 200));
+            opt_job.seed(Seed(7));
+            opt_job.run(false).expect("run").0
+        };
+        assert_eq!(run(), run());
+        // EVERY STAGE HAS ITS OWN STREAM
+        let draw = |seed: Seed, stage: &str| seed.rng(stage).gen::<u64>();
+        assert_eq!(draw(Seed(7), "classifier"), draw(Seed(7), "classifier"));
+        assert_ne!(draw(Seed(7), "classifier"), draw(Seed(7), "dither"));
+        assert_ne!(draw(Seed(7), "classifier"), draw(Seed(8), "classifier"));
+    }
+
+    #[test]
+    fn test_job() {
+        let test_image = include_bytes!("../assets/test/1.jpeg");
+        let outputs = Job::new(test_image)
+            .formats([OutputFormat::Png, OutputFormat::Jpeg])
+            .max_size(OutputSize::Px(Resolution::new(100, 100)))
+            .quality_target(QualityRange { min: 50, max: 90 })
+            .run()
+            .expect("run job");
+        let formats = outputs.iter().map(|x| x.format.clone()).collect::<Vec<_>>();
+        assert_eq!(formats, [OutputFormat::Png, OutputFormat::Jpeg]);
+        for output in &outputs {
+            let decoded = ::image::load_from_memory(&output.data).expect("decode output");
+            assert!(decoded.width() <= 100 && decoded.height() <= 100);
+        }
+        // CROPPED TO THE BOX, DOWNSCALED WHILE DECODING NO FURTHER THAN COVERS IT
+        let outputs = Job::new(test_image)
+            .formats([OutputFormat::Png])
+            .max_size(OutputSize::Px(Resolution::new(100, 30)))
+            .fit(Fit::Cover)
+            .resize_filter(ResizeFilter::Triangle)
+            .crop(Crop::Attention)
+            .run()
+            .expect("run job");
+        let decoded = ::image::load_from_memory(&outputs[0].data).expect("decode output");
+        assert_eq!(decoded.dimensions(), (100, 30));
+        // THE SOURCE’S FORMAT BY DEFAULT
+        let outputs = Job::new(test_image).run().expect("run job");
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].format, OutputFormat::Jpeg);
+        // ROTATED 90° CLOCKWISE, BY THE EXIF
+        let tiff = [
+            &b"II*\0\x08\0\0\0\x01\0"[..],
+            &[0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0],
+            &[0, 0, 0, 0],
+        ]
+        .concat();
+        let (width, height) = ::image::load_from_memory(test_image).expect("decode").dimensions();
+        let rotated = crate::meta::container::insert_exif(test_image.to_vec(), &OutputFormat::Jpeg, &tiff, (width, height));
+        let run = |auto_orient: bool| {
+            let outputs = Job::new(&rotated)
+                .formats([OutputFormat::Png])
+                .privacy_policy("basic".parse().expect("policy"))
+                .auto_orient(auto_orient)
+                .run()
+                .expect("run job");
+            let output = outputs[0].data.clone();
+            let exif = crate::meta::container::extract_exif(&output, ImageFormat::Png);
+            let dimensions = ::image::load_from_memory(&output).expect("decode output").dimensions();
+            (dimensions, exif.as_deref().and_then(crate::meta::exif::orientation))
+        };
+        assert_eq!(run(false), ((width, height), Some(6)));
+        assert_eq!(run(true), ((height, width), None));
+        // TOO WIDE FOR WEBP (AND THE VMAF OF PNG), SO TIFF
+        let wide = DynamicImage::new_rgb8(17_000, 2);
+        let mut wide_png = Vec::new();
+        wide.write_to(&mut std::io::Cursor::new(&mut wide_png), ImageFormat::Png)
+            .expect("encode");
+        let job = Job::new(&wide_png).formats([OutputFormat::Webp]);
+        assert!(job.clone().run().is_err());
+        let outputs = job.fallbacks([OutputFormat::Webp, OutputFormat::Tiff]).run().expect("run job");
+        assert_eq!(outputs[0].format, OutputFormat::Tiff);
+        let fallback = outputs[0].meta.fallback.clone().expect("fallback");
+        assert_eq!((fallback.requested, fallback.errors.len()), (OutputFormat::Webp, 1));
+        assert_eq!(outputs[0].meta.warnings.last().map(|x| x.kind), Some(WarningKind::EncoderFallback));
+        // NO QUALITY IS WITHIN A DISTANCE THAT SMALL, SO THE TOP OF THE RANGE
+        let outputs = Job::new(test_image)
+            .formats([OutputFormat::Jpeg])
+            .max_size(OutputSize::Px(Resolution::new(64, 64)))
+            .max_distance(0.01)
+            .run()
+            .expect("run job");
+        let check = outputs[0].meta.distance.expect("distance check");
+        assert!(!check.passed && check.distance > 0.01, "{:?}", check);
+        assert!(outputs[0].meta.warnings.iter().any(|x| x.kind == WarningKind::DistanceExceeded));
+        let invalid = QualityRange { min: 90, max: 50 };
+        assert!(matches!(
+            Job::new(test_image).quality_target(invalid).run(),
+            Err(ImagerError::InvalidInput(_))
+        ));
+    }
 }
@@ -0,0 +1,366 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Zip and tar archives (e.g. the asset drops of design teams), read as a
+//! list of members, and written from one, preserving their paths.
+//!
+//! Only regular files are members (directories are implied by the paths);
+//! paths escaping the archive (absolute, or with `..`) are refused, since
+//! members are extracted. Zip members are stored or deflated, without
+//! ZIP64 or encryption; tars may be gzipped (`.tar.gz` or `.tgz`).
+//! Written archives are deterministic: members keep their order, with a
+//! fixed modification time.
+//!
+//! Read archives are untrusted: members are decompressed up to the size
+//! their headers declare, and the sizes of all members are bounded (see
+//! `MAX_UNPACKED_SIZE`), so zip bombs fail before they exhaust memory.
+use flate2::read::{DeflateDecoder, GzDecoder};
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::Compression;
+use std::io::{Read, Write};
+use std::path::{Component, Path};
+
+use crate::error::{ImagerError, Result};
+
+/// Of the members of a read archive, together.
+pub const MAX_UNPACKED_SIZE: u64 = 2 << 30;
+
+/// Of the buffer of a member, before reading it: sizes in headers are
+/// only claims.
+const MAX_PREALLOCATION: u64 = 64 << 20;
+
+/// Adds a member’s size to the total, if within `MAX_UNPACKED_SIZE`.
+fn account(total: &mut u64, path: &str, size: u64) -> std::result::Result<Vec<u8>, String> {
+    *total += size;
+    if *total > MAX_UNPACKED_SIZE {
+        return Err(format!("{}: the archive unpacks to over {} bytes", path, MAX_UNPACKED_SIZE));
+    }
+    Ok(Vec::with_capacity(size.min(MAX_PREALLOCATION) as usize))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// By the extension, e.g. `drop.tar.gz`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    /// Relative, with `/` separators.
+    pub path: String,
+    pub data: Vec<u8>,
+}
+
+/// Whether the member path stays within the archive once extracted.
+fn is_safe(path: &str) -> bool {
+    !path.is_empty()
+        && !path.starts_with('/')
+        && !path.contains('\\')
+        && Path::new(path).components().all(|x| matches!(x, Component::Normal(_)))
+}
+
+pub fn read(source: &[u8], format: ArchiveFormat) -> Result<Vec<Member>> {
+    let members = match format {
+        ArchiveFormat::Zip => read_zip(source),
+        ArchiveFormat::Tar => read_tar(source),
+        ArchiveFormat::TarGz => read_tar(GzDecoder::new(source)),
+    }
+    .map_err(ImagerError::Decode)?;
+    match members.iter().find(|x| !is_safe(&x.path)) {
+        Some(member) => Err(ImagerError::Decode(format!("unsafe archive member path {:?}", member.path))),
+        None => Ok(members),
+    }
+}
+
+pub fn write(members: &[Member], format: ArchiveFormat) -> Result<Vec<u8>> {
+    match format {
+        ArchiveFormat::Zip => write_zip(members, &[]),
+        ArchiveFormat::Tar => write_tar(members, Vec::new()).map_err(ImagerError::Encode),
+        ArchiveFormat::TarGz => {
+            let encoder = write_tar(members, GzEncoder::new(Vec::new(), Compression::default()))
+                .map_err(ImagerError::Encode)?;
+            encoder.finish().map_err(|e| ImagerError::Encode(e.to_string()))
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// TAR
+///////////////////////////////////////////////////////////////////////////////
+
+fn read_tar(source: impl Read) -> std::result::Result<Vec<Member>, String> {
+    let mut archive = tar::Archive::new(source);
+    let mut members = Vec::new();
+    let mut total = 0;
+    for entry in archive.entries().map_err(|e| e.to_string())? {
+        let mut entry = entry.map_err(|e| e.to_string())?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path().map_err(|e| e.to_string())?;
+        let path = path.to_string_lossy().trim_start_matches("./").to_owned();
+        // THE ENTRY ENDS AT ITS SIZE
+        let mut data = account(&mut total, &path, entry.size())?;
+        entry.read_to_end(&mut data).map_err(|e| e.to_string())?;
+        members.push(Member { path, data });
+    }
+    Ok(members)
+}
+
+fn write_tar<W: Write>(members: &[Member], output: W) -> std::result::Result<W, String> {
+    let mut builder = tar::Builder::new(output);
+    for member in members {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(member.data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        header.set_entry_type(tar::EntryType::Regular);
+        // `append_data` SETS THE PATH (WITH GNU LONG NAMES) AND CHECKSUM
+        builder
+            .append_data(&mut header, &member.path, member.data.as_slice())
+            .map_err(|e| format!("{}: {}", member.path, e))?;
+    }
+    builder.into_inner().map_err(|e| e.to_string())
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ZIP
+///////////////////////////////////////////////////////////////////////////////
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+/// Of the general purpose flags: names are UTF-8.
+const UTF8_NAMES: u16 = 1 << 11;
+const ENCRYPTED: u16 = 1;
+
+/// 1980-01-01, the earliest DOS date.
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+fn u16_at(source: &[u8], offset: usize) -> std::result::Result<u16, String> {
+    source
+        .get(offset..offset + 2)
+        .map(|x| u16::from_le_bytes([x[0], x[1]]))
+        .ok_or_else(|| String::from("truncated zip"))
+}
+
+fn u32_at(source: &[u8], offset: usize) -> std::result::Result<u32, String> {
+    source
+        .get(offset..offset + 4)
+        .map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+        .ok_or_else(|| String::from("truncated zip"))
+}
+
+fn bytes_at(source: &[u8], offset: usize, len: usize) -> std::result::Result<&[u8], String> {
+    source
+        .get(offset..offset + len)
+        .ok_or_else(|| String::from("truncated zip"))
+}
+
+fn read_zip(source: &[u8]) -> std::result::Result<Vec<Member>, String> {
+    // THE END RECORD, BEFORE A COMMENT OF AT MOST 64KB
+    let search_start = source.len().saturating_sub(22 + 0xFFFF);
+    let end = (search_start..source.len().saturating_sub(21))
+        .rev()
+        .find(|x| u32_at(source, *x) == Ok(END_OF_CENTRAL_DIRECTORY))
+        .ok_or_else(|| String::from("not a zip archive"))?;
+    let count = u16_at(source, end + 10)?;
+    let mut offset = u32_at(source, end + 16)? as usize;
+    if count == 0xFFFF || offset == 0xFFFF_FFFF {
+        return Err(String::from("ZIP64 archives aren’t supported"));
+    }
+    let mut members = Vec::with_capacity(count as usize);
+    let mut total = 0;
+    for _ in 0..count {
+        if u32_at(source, offset)? != CENTRAL_HEADER {
+            return Err(String::from("corrupt zip central directory"));
+        }
+        let flags = u16_at(source, offset + 8)?;
+        let method = u16_at(source, offset + 10)?;
+        let crc = u32_at(source, offset + 16)?;
+        let compressed_size = u32_at(source, offset + 20)? as usize;
+        let size = u32_at(source, offset + 24)? as usize;
+        let name_len = u16_at(source, offset + 28)? as usize;
+        let extra_len = u16_at(source, offset + 30)? as usize;
+        let comment_len = u16_at(source, offset + 32)? as usize;
+        let local = u32_at(source, offset + 42)? as usize;
+        let path = String::from_utf8_lossy(bytes_at(source, offset + 46, name_len)?).into_owned();
+        offset += 46 + name_len + extra_len + comment_len;
+        if path.ends_with('/') {
+            continue;
+        }
+        if flags & ENCRYPTED != 0 {
+            return Err(format!("{}: encrypted zip members aren’t supported", path));
+        }
+        // THE SIZES OF THE LOCAL HEADER MAY BE ZERO (WITH A DATA DESCRIPTOR)
+        if u32_at(source, local)? != LOCAL_HEADER {
+            return Err(format!("{}: corrupt zip local header", path));
+        }
+        let data_start = local + 30 + u16_at(source, local + 26)? as usize + u16_at(source, local + 28)? as usize;
+        let compressed = bytes_at(source, data_start, compressed_size)?;
+        let mut data = account(&mut total, &path, size as u64)?;
+        match method {
+            STORED => data.extend_from_slice(compressed),
+            // ONE BYTE PAST THE SIZE, TO TELL IT WAS EXCEEDED
+            DEFLATED => {
+                DeflateDecoder::new(compressed)
+                    .take(size as u64 + 1)
+                    .read_to_end(&mut data)
+                    .map_err(|e| format!("{}: {}", path, e))?;
+            }
+            method => return Err(format!("{}: unsupported zip compression method {}", path, method)),
+        }
+        if data.len() != size || crc32fast::hash(&data) != crc {
+            return Err(format!("{}: zip member checksum mismatch", path));
+        }
+        members.push(Member { path, data });
+    }
+    Ok(members)
+}
+
+/// Members of the `stored` paths aren’t deflated, e.g. an EPUB’s
+/// `mimetype`, which readers sniff at a fixed offset.
+pub fn write_zip(members: &[Member], stored: &[&str]) -> Result<Vec<u8>> {
+    if members.len() >= 0xFFFF {
+        return Err(ImagerError::InvalidInput(format!("{} members need ZIP64, which isn’t supported", members.len())));
+    }
+    let mut output = Vec::new();
+    let mut central = Vec::new();
+    for member in members {
+        // DEFLATED, UNLESS THAT DOESN’T HELP (E.G. OF IMAGES)
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        let deflating = |e: std::io::Error| ImagerError::Encode(e.to_string());
+        encoder.write_all(&member.data).map_err(deflating)?;
+        let deflated = encoder.finish().map_err(deflating)?;
+        let deflate = deflated.len() < member.data.len() && !stored.contains(&member.path.as_str());
+        let (method, data) = match deflate {
+            true => (DEFLATED, deflated.as_slice()),
+            false => (STORED, member.data.as_slice()),
+        };
+        let local = output.len();
+        if local + data.len() > 0xFFFF_FFFF - 0xFFFF || member.path.len() > 0xFFFF {
+            let message = format!("{}: too large without ZIP64, which isn’t supported", member.path);
+            return Err(ImagerError::InvalidInput(message));
+        }
+        // VERSION 2.0; FLAGS; METHOD; TIME; DATE; CRC; SIZES; NAME LENGTH;
+        // NO EXTRA FIELD
+        let fields = [
+            &20u16.to_le_bytes()[..],
+            &UTF8_NAMES.to_le_bytes(),
+            &method.to_le_bytes(),
+            &0u16.to_le_bytes(),
+            &DOS_DATE.to_le_bytes(),
+            &crc32fast::hash(&member.data).to_le_bytes(),
+            &(data.len() as u32).to_le_bytes(),
+            &(member.data.len() as u32).to_le_bytes(),
+            &(member.path.len() as u16).to_le_bytes(),
+            &0u16.to_le_bytes(),
+        ].concat();
+        output.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+        output.extend_from_slice(&fields);
+        output.extend_from_slice(member.path.as_bytes());
+        output.extend_from_slice(data);
+        // MADE BY VERSION 2.0; THEN AS THE LOCAL HEADER; NO COMMENT; DISK 0;
+        // NO ATTRIBUTES; THE LOCAL HEADER’S OFFSET
+        central.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+        central.extend_from_slice(&20u16.to_le_bytes());
+        central.extend_from_slice(&fields);
+        central.extend_from_slice(&[0; 10]);
+        central.extend_from_slice(&(local as u32).to_le_bytes());
+        central.extend_from_slice(member.path.as_bytes());
+    }
+    let central_offset = output.len() as u32;
+    let central_size = central.len() as u32;
+    output.extend(central);
+    output.extend_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+    // ONE DISK
+    output.extend_from_slice(&[0; 4]);
+    output.extend_from_slice(&(members.len() as u16).to_le_bytes());
+    output.extend_from_slice(&(members.len() as u16).to_le_bytes());
+    output.extend_from_slice(&central_size.to_le_bytes());
+    output.extend_from_slice(&central_offset.to_le_bytes());
+    // NO COMMENT
+    output.extend_from_slice(&0u16.to_le_bytes());
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trips() {
+        let members = vec![
+            Member { path: String::from("icons/a.png"), data: std::fs::read("assets/test/1.jpeg").expect("read") },
+            Member { path: String::from("README.txt"), data: b"hello hello hello hello".to_vec() },
+            Member { path: format!("{}/b.jpg", "deep/".repeat(30).trim_end_matches('/')), data: Vec::new() },
+        ];
+        for format in [ArchiveFormat::Zip, ArchiveFormat::Tar, ArchiveFormat::TarGz] {
+            let archive = write(&members, format).expect("write");
+            assert_eq!(read(&archive, format).expect("read"), members, "{:?}", format);
+            assert_eq!(write(&members, format).expect("write"), archive);
+        }
+        assert_eq!(ArchiveFormat::from_path(Path::new("drop.TGZ")), Some(ArchiveFormat::TarGz));
+        assert_eq!(ArchiveFormat::from_path(Path::new("photo.jpeg")), None);
+    }
+
+    #[test]
+    fn test_bombs() {
+        let members = vec![Member { path: String::from("zeros.bin"), data: vec![0; 1 << 20] }];
+        let archive = write(&members, ArchiveFormat::Zip).expect("write");
+        let central = archive
+            .windows(4)
+            .position(|x| x == CENTRAL_HEADER.to_le_bytes())
+            .expect("central header");
+        // SIZES IN THE CENTRAL DIRECTORY BELOW AND ABOVE THE CONTENTS’
+        let with_size = |size: u32| {
+            let mut archive = archive.clone();
+            archive[central + 24..central + 28].copy_from_slice(&size.to_le_bytes());
+            read(&archive, ArchiveFormat::Zip).expect_err("bomb")
+        };
+        assert!(with_size(100).to_string().contains("checksum mismatch"));
+        assert!(with_size(3 << 30).to_string().contains("unpacks to over"));
+        let mut tar = write(&members, ArchiveFormat::Tar).expect("write");
+        // THE OCTAL SIZE FIELD OF THE HEADER (AND ITS CHECKSUM)
+        tar[124..136].copy_from_slice(b"30000000000\0");
+        let checksum = tar[..512]
+            .iter()
+            .enumerate()
+            .map(|(i, x)| if (148..156).contains(&i) { 32 } else { *x as u32 })
+            .sum::<u32>();
+        tar[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+        assert!(read(&tar, ArchiveFormat::Tar).expect_err("bomb").to_string().contains("unpacks to over"));
+    }
+
+    #[test]
+    fn test_unsafe_paths() {
+        for path in ["../etc/passwd", "/etc/passwd", "a/../../b", "a\\..\\b", ""] {
+            assert!(!is_safe(path), "{}", path);
+        }
+        assert!(is_safe("icons/a.png"));
+        let members = vec![Member { path: String::from("../a.png"), data: Vec::new() }];
+        let archive = write(&members, ArchiveFormat::Zip).expect("write");
+        assert!(matches!(read(&archive, ArchiveFormat::Zip), Err(ImagerError::Decode(_))));
+    }
+}
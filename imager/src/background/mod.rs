@@ -0,0 +1,121 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Background removal (alpha matting), e.g. for product cutouts.
+//!
+//! Foreground masks come from a salient object segmentation model, run by
+//! ONNX Runtime: any of the U²-Net family (e.g. `u2netp.onnx`), i.e. a
+//! `1x3xNxN` ImageNet normalized RGB input and a `1x1xNxN` foreground
+//! probability as the first output. Requires the `background-removal`
+//! feature, and the ONNX Runtime shared library at runtime (see `onnx`).
+//!
+//! Only PNG and WebP outputs keep the cutout; JPEG has no alpha.
+use image::{DynamicImage, GenericImageView, RgbaImage};
+use std::path::Path;
+
+#[cfg(feature = "background-removal")]
+mod onnx;
+
+/// The model input resolution (per side).
+pub const MODEL_INPUT_SIZE: u32 = 320;
+
+const MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+const STD: [f32; 3] = [0.229, 0.224, 0.225];
+
+pub struct BackgroundRemover {
+    #[cfg(feature = "background-removal")]
+    session: onnx::Session,
+}
+
+impl BackgroundRemover {
+    #[cfg(feature = "background-removal")]
+    pub fn open<P: AsRef<Path>>(model: P) -> Result<Self, String> {
+        let session = onnx::Session::open(model.as_ref())?;
+        Ok(BackgroundRemover { session })
+    }
+    #[cfg(not(feature = "background-removal"))]
+    pub fn open<P: AsRef<Path>>(_: P) -> Result<Self, String> {
+        Err(crate::codec::registry::FeatureDisabled::BACKGROUND_REMOVAL.into())
+    }
+    /// The source, with everything but the foreground made transparent.
+    pub fn remove(&self, source: &DynamicImage) -> Result<DynamicImage, String> {
+        let (mask, dimensions) = self.mask(source)?;
+        Ok(DynamicImage::ImageRgba8(apply_matte(source, &mask, dimensions)))
+    }
+    #[cfg(feature = "background-removal")]
+    fn mask(&self, source: &DynamicImage) -> Result<(Vec<f32>, (u32, u32)), String> {
+        let size = MODEL_INPUT_SIZE as i64;
+        let mut input = input_tensor(source);
+        let (mask, shape) = self.session.run(&mut input, &[1, 3, size, size])?;
+        match shape.as_slice() {
+            [.., height, width] if (width * height) as usize == mask.len() => {
+                Ok((mask, (*width as u32, *height as u32)))
+            }
+            _ => Err(format!("unexpected model output shape {:?}", shape)),
+        }
+    }
+    #[cfg(not(feature = "background-removal"))]
+    fn mask(&self, _: &DynamicImage) -> Result<(Vec<f32>, (u32, u32)), String> {
+        unreachable!("`open` fails without the feature")
+    }
+}
+
+/// The planar (`CHW`) normalized model input.
+fn input_tensor(source: &DynamicImage) -> Vec<f32> {
+    let size = MODEL_INPUT_SIZE;
+    let resized = source
+        .resize_exact(size, size, image::imageops::FilterType::Triangle)
+        .to_rgb8();
+    let plane = (size * size) as usize;
+    let mut output = vec![0.0; plane * 3];
+    for (ix, pixel) in resized.pixels().enumerate() {
+        for channel in 0..3 {
+            let value = pixel.0[channel] as f32 / 255.0;
+            output[channel * plane + ix] = (value - MEAN[channel]) / STD[channel];
+        }
+    }
+    output
+}
+
+/// Multiplies the source alpha by the mask, stretched to `[0, 1]` and
+/// bilinearly resampled to the source resolution.
+fn apply_matte(source: &DynamicImage, mask: &[f32], (mask_width, mask_height): (u32, u32)) -> RgbaImage {
+    let (min, max) = mask
+        .iter()
+        .fold((f32::MAX, f32::MIN), |(min, max), x| (min.min(*x), max.max(*x)));
+    let range = (max - min).max(f32::EPSILON);
+    let sample = |x: u32, y: u32| (mask[(y * mask_width + x) as usize] - min) / range;
+    let (width, height) = source.dimensions();
+    let mut output = source.to_rgba8();
+    for (x, y, pixel) in output.enumerate_pixels_mut() {
+        // PIXEL CENTERS, IN MASK COORDINATES
+        let mx = ((x as f32 + 0.5) * mask_width as f32 / width as f32 - 0.5).max(0.0);
+        let my = ((y as f32 + 0.5) * mask_height as f32 / height as f32 - 0.5).max(0.0);
+        let (x0, y0) = ((mx as u32).min(mask_width - 1), (my as u32).min(mask_height - 1));
+        let (x1, y1) = ((x0 + 1).min(mask_width - 1), (y0 + 1).min(mask_height - 1));
+        let (fx, fy) = (mx - x0 as f32, my - y0 as f32);
+        let top = sample(x0, y0) * (1.0 - fx) + sample(x1, y0) * fx;
+        let bottom = sample(x0, y1) * (1.0 - fx) + sample(x1, y1) * fx;
+        let alpha = (top * (1.0 - fy) + bottom * fy).clamp(0.0, 1.0);
+        pixel.0[3] = (pixel.0[3] as f32 * alpha).round() as u8;
+    }
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_apply_matte() {
+        let source = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(8, 8, image::Rgb([9, 9, 9])));
+        // FOREGROUND ON THE LEFT, AS UNNORMALIZED LOGITS
+        let mask = [4.0, -2.0, 4.0, -2.0];
+        let output = apply_matte(&source, &mask, (2, 2));
+        assert_eq!(output.get_pixel(0, 0).0, [9, 9, 9, 255]);
+        assert_eq!(output.get_pixel(7, 7).0, [9, 9, 9, 0]);
+        let alpha = (0..8).map(|x| output.get_pixel(x, 4).0[3]).collect::<Vec<_>>();
+        assert!(alpha.windows(2).all(|x| x[0] >= x[1]));
+        assert_eq!(input_tensor(&source).len(), (MODEL_INPUT_SIZE * MODEL_INPUT_SIZE * 3) as usize);
+    }
+}
@@ -0,0 +1,315 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! A minimal ONNX Runtime binding: one float tensor in, one out.
+//!
+//! The shared library is loaded at runtime, from `ORT_DYLIB_PATH` or else
+//! the platform’s library search path, so builds don’t need it. Functions
+//! are looked up in the `OrtApi` table by their index in
+//! `onnxruntime_c_api.h`; the table is append-only, so the indices of API
+//! version 1 hold for every release.
+use libloading::Library;
+use std::ffi::{c_void, CStr, CString};
+use std::os::raw::c_char;
+use std::path::Path;
+
+type Status = *mut c_void;
+type Handle = *mut c_void;
+
+const API_VERSION: u32 = 1;
+
+// OrtApi INDICES
+const GET_ERROR_MESSAGE: usize = 2;
+const CREATE_ENV: usize = 3;
+const CREATE_SESSION: usize = 7;
+const RUN: usize = 9;
+const CREATE_SESSION_OPTIONS: usize = 10;
+const SESSION_GET_INPUT_NAME: usize = 36;
+const SESSION_GET_OUTPUT_NAME: usize = 37;
+const CREATE_TENSOR_WITH_DATA_AS_ORT_VALUE: usize = 49;
+const GET_TENSOR_MUTABLE_DATA: usize = 51;
+const GET_DIMENSIONS_COUNT: usize = 61;
+const GET_DIMENSIONS: usize = 62;
+const GET_TENSOR_TYPE_AND_SHAPE: usize = 65;
+const CREATE_CPU_MEMORY_INFO: usize = 69;
+const ALLOCATOR_FREE: usize = 76;
+const GET_ALLOCATOR_WITH_DEFAULT_OPTIONS: usize = 78;
+const RELEASE_ENV: usize = 92;
+const RELEASE_STATUS: usize = 93;
+const RELEASE_MEMORY_INFO: usize = 94;
+const RELEASE_SESSION: usize = 95;
+const RELEASE_VALUE: usize = 96;
+const RELEASE_TENSOR_TYPE_AND_SHAPE_INFO: usize = 99;
+const RELEASE_SESSION_OPTIONS: usize = 100;
+
+// ENUM VALUES
+const LOGGING_LEVEL_WARNING: i32 = 2;
+const ARENA_ALLOCATOR: i32 = 1;
+const MEM_TYPE_DEFAULT: i32 = 0;
+const TENSOR_ELEMENT_FLOAT: i32 = 1;
+
+#[cfg(target_os = "windows")]
+const DEFAULT_LIBRARY: &str = "onnxruntime.dll";
+#[cfg(target_os = "macos")]
+const DEFAULT_LIBRARY: &str = "libonnxruntime.dylib";
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const DEFAULT_LIBRARY: &str = "libonnxruntime.so";
+
+///////////////////////////////////////////////////////////////////////////////
+// API TABLE
+///////////////////////////////////////////////////////////////////////////////
+
+#[repr(C)]
+struct ApiBase {
+    get_api: unsafe extern "system" fn(u32) -> *const *const c_void,
+    get_version_string: unsafe extern "system" fn() -> *const c_char,
+}
+
+struct Api {
+    table: *const *const c_void,
+    // KEEPS THE TABLE MAPPED
+    _library: Library,
+}
+
+impl Api {
+    fn load() -> Result<Self, String> {
+        let path = std::env::var_os("ORT_DYLIB_PATH").unwrap_or_else(|| DEFAULT_LIBRARY.into());
+        let library = Library::new(&path)
+            .map_err(|e| format!("failed to load ONNX Runtime ({:?}): {}", path, e))?;
+        let table = unsafe {
+            let get_api_base = library
+                .get::<unsafe extern "system" fn() -> *const ApiBase>(b"OrtGetApiBase\0")
+                .map_err(|e| e.to_string())?;
+            ((*get_api_base()).get_api)(API_VERSION)
+        };
+        if table.is_null() {
+            return Err(String::from("unsupported ONNX Runtime version"));
+        }
+        Ok(Api {
+            table,
+            _library: library,
+        })
+    }
+    /// # Safety
+    ///
+    /// `F` must be the function pointer type of the entry at `index`.
+    unsafe fn get<F: Copy>(&self, index: usize) -> F {
+        std::mem::transmute_copy::<*const c_void, F>(&*self.table.add(index))
+    }
+    /// Converts (and releases) a returned `OrtStatus`.
+    fn check(&self, status: Status) -> Result<(), String> {
+        if status.is_null() {
+            return Ok(());
+        }
+        unsafe {
+            let message: unsafe extern "system" fn(Status) -> *const c_char =
+                self.get(GET_ERROR_MESSAGE);
+            let message = CStr::from_ptr(message(status)).to_string_lossy().into_owned();
+            let release: unsafe extern "system" fn(Status) = self.get(RELEASE_STATUS);
+            release(status);
+            Err(message)
+        }
+    }
+    fn release(&self, index: usize, handle: Handle) {
+        if !handle.is_null() {
+            unsafe { self.get::<unsafe extern "system" fn(Handle)>(index)(handle) }
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// SESSION
+///////////////////////////////////////////////////////////////////////////////
+
+pub struct Session {
+    api: Api,
+    env: Handle,
+    session: Handle,
+    input_name: CString,
+    output_name: CString,
+}
+
+// ONNX RUNTIME SESSIONS SUPPORT CONCURRENT `Run` CALLS
+unsafe impl Send for Session {}
+unsafe impl Sync for Session {}
+
+impl Session {
+    /// Loads a model whose first input and output are float tensors.
+    pub fn open(model: &Path) -> Result<Self, String> {
+        let api = Api::load()?;
+        let mut env: Handle = std::ptr::null_mut();
+        let mut options: Handle = std::ptr::null_mut();
+        let mut session: Handle = std::ptr::null_mut();
+        unsafe {
+            let create_env: unsafe extern "system" fn(i32, *const c_char, *mut Handle) -> Status =
+                api.get(CREATE_ENV);
+            api.check(create_env(LOGGING_LEVEL_WARNING, c"imager".as_ptr(), &mut env))?;
+            let create_options: unsafe extern "system" fn(*mut Handle) -> Status =
+                api.get(CREATE_SESSION_OPTIONS);
+            if let Err(e) = api.check(create_options(&mut options)) {
+                api.release(RELEASE_ENV, env);
+                return Err(e);
+            }
+            let create_session: unsafe extern "system" fn(
+                Handle,
+                *const c_void,
+                Handle,
+                *mut Handle,
+            ) -> Status = api.get(CREATE_SESSION);
+            let model_path = model_path(model)?;
+            let status = create_session(env, model_path.as_ptr().cast(), options, &mut session);
+            api.release(RELEASE_SESSION_OPTIONS, options);
+            if let Err(e) = api.check(status) {
+                api.release(RELEASE_ENV, env);
+                return Err(e);
+            }
+        }
+        let name = |index: usize| -> Result<CString, String> {
+            unsafe {
+                let mut allocator: Handle = std::ptr::null_mut();
+                let get_allocator: unsafe extern "system" fn(*mut Handle) -> Status =
+                    api.get(GET_ALLOCATOR_WITH_DEFAULT_OPTIONS);
+                api.check(get_allocator(&mut allocator))?;
+                let get_name: unsafe extern "system" fn(
+                    Handle,
+                    usize,
+                    Handle,
+                    *mut *mut c_char,
+                ) -> Status = api.get(index);
+                let mut name: *mut c_char = std::ptr::null_mut();
+                api.check(get_name(session, 0, allocator, &mut name))?;
+                let owned = CStr::from_ptr(name).to_owned();
+                let free: unsafe extern "system" fn(Handle, *mut c_void) -> Status =
+                    api.get(ALLOCATOR_FREE);
+                api.check(free(allocator, name.cast()))?;
+                Ok(owned)
+            }
+        };
+        let names = name(SESSION_GET_INPUT_NAME)
+            .and_then(|input| Ok((input, name(SESSION_GET_OUTPUT_NAME)?)));
+        let (input_name, output_name) = match names {
+            Ok(x) => x,
+            Err(e) => {
+                api.release(RELEASE_SESSION, session);
+                api.release(RELEASE_ENV, env);
+                return Err(e);
+            }
+        };
+        Ok(Session {
+            api,
+            env,
+            session,
+            input_name,
+            output_name,
+        })
+    }
+
+    /// Runs the model, returning the first output and its shape.
+    pub fn run(&self, input: &mut [f32], shape: &[i64]) -> Result<(Vec<f32>, Vec<i64>), String> {
+        let api = &self.api;
+        let mut memory_info: Handle = std::ptr::null_mut();
+        let mut input_value: Handle = std::ptr::null_mut();
+        let mut output_value: Handle = std::ptr::null_mut();
+        let result = unsafe {
+            (|| {
+                let create_memory_info: unsafe extern "system" fn(i32, i32, *mut Handle) -> Status =
+                    api.get(CREATE_CPU_MEMORY_INFO);
+                api.check(create_memory_info(ARENA_ALLOCATOR, MEM_TYPE_DEFAULT, &mut memory_info))?;
+                let create_tensor: unsafe extern "system" fn(
+                    Handle,
+                    *mut c_void,
+                    usize,
+                    *const i64,
+                    usize,
+                    i32,
+                    *mut Handle,
+                ) -> Status = api.get(CREATE_TENSOR_WITH_DATA_AS_ORT_VALUE);
+                api.check(create_tensor(
+                    memory_info,
+                    input.as_mut_ptr().cast(),
+                    std::mem::size_of_val(input),
+                    shape.as_ptr(),
+                    shape.len(),
+                    TENSOR_ELEMENT_FLOAT,
+                    &mut input_value,
+                ))?;
+                let run: unsafe extern "system" fn(
+                    Handle,
+                    *const c_void,
+                    *const *const c_char,
+                    *const Handle,
+                    usize,
+                    *const *const c_char,
+                    usize,
+                    *mut Handle,
+                ) -> Status = api.get(RUN);
+                api.check(run(
+                    self.session,
+                    std::ptr::null(),
+                    &self.input_name.as_ptr(),
+                    &input_value,
+                    1,
+                    &self.output_name.as_ptr(),
+                    1,
+                    &mut output_value,
+                ))?;
+                let output_shape = self.shape(output_value)?;
+                let len = output_shape.iter().map(|x| (*x).max(0) as usize).product::<usize>();
+                let get_data: unsafe extern "system" fn(Handle, *mut *mut c_void) -> Status =
+                    api.get(GET_TENSOR_MUTABLE_DATA);
+                let mut data: *mut c_void = std::ptr::null_mut();
+                api.check(get_data(output_value, &mut data))?;
+                let output = std::slice::from_raw_parts(data as *const f32, len).to_vec();
+                Ok((output, output_shape))
+            })()
+        };
+        api.release(RELEASE_VALUE, output_value);
+        api.release(RELEASE_VALUE, input_value);
+        api.release(RELEASE_MEMORY_INFO, memory_info);
+        result
+    }
+
+    fn shape(&self, value: Handle) -> Result<Vec<i64>, String> {
+        let api = &self.api;
+        unsafe {
+            let get_info: unsafe extern "system" fn(Handle, *mut Handle) -> Status =
+                api.get(GET_TENSOR_TYPE_AND_SHAPE);
+            let mut info: Handle = std::ptr::null_mut();
+            api.check(get_info(value, &mut info))?;
+            let result = (|| {
+                let get_count: unsafe extern "system" fn(Handle, *mut usize) -> Status =
+                    api.get(GET_DIMENSIONS_COUNT);
+                let mut count = 0;
+                api.check(get_count(info, &mut count))?;
+                let get_dimensions: unsafe extern "system" fn(Handle, *mut i64, usize) -> Status =
+                    api.get(GET_DIMENSIONS);
+                let mut dimensions = vec![0i64; count];
+                api.check(get_dimensions(info, dimensions.as_mut_ptr(), count))?;
+                Ok(dimensions)
+            })();
+            api.release(RELEASE_TENSOR_TYPE_AND_SHAPE_INFO, info);
+            result
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.api.release(RELEASE_SESSION, self.session);
+        self.api.release(RELEASE_ENV, self.env);
+    }
+}
+
+/// `ORTCHAR_T` is `wchar_t` on Windows, `char` elsewhere.
+#[cfg(target_os = "windows")]
+fn model_path(path: &Path) -> Result<Vec<u16>, String> {
+    use std::os::windows::ffi::OsStrExt;
+    Ok(path.as_os_str().encode_wide().chain(Some(0)).collect())
+}
+
+#[cfg(not(target_os = "windows"))]
+fn model_path(path: &Path) -> Result<Vec<u8>, String> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = CString::new(path.as_os_str().as_bytes()).map_err(|e| e.to_string())?;
+    Ok(path.into_bytes_with_nul())
+}
@@ -0,0 +1,325 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Directory batches (`imager batch`): every image under a directory (by
+//! its content, whatever the extension), processed in parallel by a `Job`
+//! each, into the same relative paths under the output directory, e.g.
+//!
+//! ```no_run
+//! # use imager::{batch::Batch, data::OutputFormat};
+//! let report = Batch::new("photos", "optimized")
+//!     .formats([OutputFormat::Webp])
+//!     .threads(4)
+//!     .run(|progress| eprintln!("{}/{}", progress.done, progress.total))?;
+//! # Ok::<(), imager::error::ImagerError>(())
+//! ```
+//!
+//! Progress is reported as every input finishes, to a callback (`run`), or
+//! a channel (`spawn`), e.g. for progress bars. Outputs keep their input’s
+//! file name, with the extension of their format, unless the input’s is
+//! already one of it (e.g. `.jpg`); inputs sharing their stem (e.g. `a.png`
+//! and `a.jpg`) keep their extension too (`a.png.webp`, `a.jpg.webp`), and
+//! inputs whose output path another input already has fail, rather than
+//! overwrite it.
+//!
+//! Symlinks aren’t followed, and the output directory is skipped if it’s
+//! under the input directory.
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use crate::api::Job;
+use crate::data::{InferOutputFormat, OutputFormat};
+use crate::error::{ImagerError, Result};
+use crate::report::{FileError, FileErrorKind, Report};
+
+/// Of the `Job` of every input, e.g. its sizes or tuning (but not its
+/// formats, which are the batch’s).
+type Configure = Arc<dyn for<'a> Fn(Job<'a>) -> Job<'a> + Send + Sync>;
+
+/// The output paths of a run.
+struct Outputs {
+    /// Of inputs sharing their stem with another, without the extension.
+    shared_stems: HashSet<PathBuf>,
+    /// The output paths written, with their input.
+    claimed: Mutex<HashMap<PathBuf, PathBuf>>,
+}
+
+/// Reported as each input finishes.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    /// The inputs finished, of the `total`.
+    pub done: usize,
+    pub total: usize,
+    pub input_path: PathBuf,
+    /// The output paths, or why the input failed.
+    pub result: std::result::Result<Vec<PathBuf>, String>,
+}
+
+#[derive(Clone)]
+pub struct Batch {
+    input_dir: PathBuf,
+    output_dir: PathBuf,
+    /// The source’s own format, if empty.
+    formats: Vec<OutputFormat>,
+    /// Rayon’s default (the number of CPUs), if none.
+    threads: Option<usize>,
+    configure: Configure,
+}
+
+impl Batch {
+    pub fn new(input_dir: impl Into<PathBuf>, output_dir: impl Into<PathBuf>) -> Self {
+        Batch {
+            input_dir: input_dir.into(),
+            output_dir: output_dir.into(),
+            formats: Vec::new(),
+            threads: None,
+            configure: Arc::new(|job| job),
+        }
+    }
+    pub fn formats(mut self, formats: impl IntoIterator<Item = OutputFormat>) -> Self {
+        self.formats = formats.into_iter().collect();
+        self
+    }
+    /// Of the batch’s own thread pool.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+    /// Applied to the `Job` of every input; jobs of several sizes are
+    /// rejected, since their outputs would share a path.
+    pub fn configure(mut self, configure: impl for<'a> Fn(Job<'a>) -> Job<'a> + Send + Sync + 'static) -> Self {
+        self.configure = Arc::new(configure);
+        self
+    }
+    /// The images under the input directory (but not the output one), by
+    /// path.
+    pub fn inputs(&self) -> Result<Vec<PathBuf>> {
+        let mut inputs = Vec::new();
+        let output_dir = self.output_dir.canonicalize().ok();
+        walk(&self.input_dir, output_dir.as_deref(), &mut inputs)?;
+        inputs.sort();
+        Ok(inputs)
+    }
+    /// Of the output of the input in the format; `shared_stem` if another
+    /// input in its directory has its stem, e.g. `a.jpg` of `a.png`.
+    pub fn output_path(&self, input_path: &Path, format: &OutputFormat, shared_stem: bool) -> PathBuf {
+        let relative = input_path.strip_prefix(&self.input_dir).unwrap_or(input_path);
+        let output_path = self.output_dir.join(relative);
+        match OutputFormat::infer_from_path(input_path) {
+            Some(input_format) if input_format == *format => output_path,
+            _ if shared_stem => {
+                let mut file_name = output_path.file_name().unwrap_or_default().to_owned();
+                file_name.push(".");
+                file_name.push(format.extension());
+                output_path.with_file_name(file_name)
+            }
+            _ => output_path.with_extension(format.extension()),
+        }
+    }
+    /// Processes every input, calling `on_progress` (from the pool’s
+    /// threads) as each finishes. Failed inputs are in the report’s
+    /// `errors`, once per format.
+    pub fn run(&self, on_progress: impl Fn(&Progress) + Send + Sync) -> Result<Report> {
+        let inputs = self.inputs()?;
+        let mut stems = HashMap::<PathBuf, usize>::new();
+        for input_path in &inputs {
+            *stems.entry(input_path.with_extension("")).or_default() += 1;
+        }
+        let outputs = Outputs {
+            shared_stems: stems.into_iter().filter(|x| x.1 > 1).map(|x| x.0).collect(),
+            claimed: Mutex::new(HashMap::new()),
+        };
+        let mut builder = rayon::ThreadPoolBuilder::new();
+        if let Some(threads) = self.threads {
+            builder = builder.num_threads(threads);
+        }
+        let pool = builder.build().map_err(|e| ImagerError::InvalidInput(e.to_string()))?;
+        let done = AtomicUsize::new(0);
+        let results = pool.install(|| {
+            inputs
+                .par_iter()
+                .flat_map_iter(|input_path| {
+                    let results = self.process(input_path, &outputs);
+                    let outputs = results
+                        .iter()
+                        .map(|x| x.as_ref().map_err(|e| e.message.clone()))
+                        .map(|x| x.map(|meta| meta.output_path.clone().unwrap_or_default()))
+                        .collect::<std::result::Result<Vec<_>, _>>();
+                    on_progress(&Progress {
+                        done: done.fetch_add(1, Ordering::SeqCst) + 1,
+                        total: inputs.len(),
+                        input_path: input_path.clone(),
+                        result: outputs,
+                    });
+                    results
+                })
+                .collect::<Vec<_>>()
+        });
+        Ok(Report::from_results(results))
+    }
+    /// Runs the batch on its own thread, sending progress to the receiver.
+    pub fn spawn(self) -> (Receiver<Progress>, JoinHandle<Result<Report>>) {
+        let (sender, receiver) = channel();
+        let handle = std::thread::spawn(move || {
+            self.run(|progress| {
+                // THE RECEIVER MAY BE GONE; THE BATCH STILL COMPLETES
+                let _ = sender.send(progress.clone());
+            })
+        });
+        (receiver, handle)
+    }
+    fn process(&self, input_path: &Path, outputs: &Outputs) -> Vec<std::result::Result<crate::api::OutMeda, FileError>> {
+        let fail = |format: &OutputFormat, kind: FileErrorKind, message: String| FileError {
+            input_path: input_path.to_owned(),
+            output_format: format.clone(),
+            kind,
+            message,
+        };
+        let source = match std::fs::read(input_path) {
+            Ok(source) => source,
+            Err(e) => return self.failed(input_path, |format| fail(format, FileErrorKind::Io, e.to_string())),
+        };
+        let job = (self.configure)(Job::new(&source)).formats(self.formats.clone());
+        // AN OUTPUT PER FORMAT, I.E. OF A SINGLE SIZE
+        let results = job.run().and_then(|results| {
+            if results.len() > self.formats.len().max(1) {
+                return Err(ImagerError::InvalidInput(String::from("batch jobs have a single size")));
+            }
+            Ok(results)
+        });
+        let results = match results {
+            Ok(outputs) => outputs,
+            Err(error) => {
+                let kind = match &error {
+                    ImagerError::Io { .. } | ImagerError::Transfer(_) => FileErrorKind::Io,
+                    ImagerError::Decode(_) => FileErrorKind::Decode,
+                    ImagerError::FeatureDisabled(_) => FileErrorKind::Unsupported,
+                    ImagerError::Encode(_) | ImagerError::InvalidInput(_) => FileErrorKind::Encode,
+                };
+                return self.failed(input_path, |format| fail(format, kind, error.to_string()));
+            }
+        };
+        let shared_stem = outputs.shared_stems.contains(&input_path.with_extension(""));
+        results
+            .into_iter()
+            .map(|output| {
+                let output_path = self.output_path(input_path, &output.format, shared_stem);
+                let claimed = outputs
+                    .claimed
+                    .lock()
+                    .expect("claimed lock")
+                    .insert(output_path.clone(), input_path.to_owned());
+                if let Some(other) = claimed.filter(|x| x != input_path) {
+                    let message = format!("{} is the output of {}", output_path.display(), other.display());
+                    return Err(fail(&output.format, FileErrorKind::Io, message));
+                }
+                let write = || {
+                    if let Some(parent) = output_path.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    std::fs::write(&output_path, &output.data)
+                };
+                write().map_err(|e| fail(&output.format, FileErrorKind::Io, e.to_string()))?;
+                let mut meta = output.meta;
+                meta.input_path = Some(input_path.to_owned());
+                meta.output_path = Some(output_path);
+                meta.input_size = Some(source.len() as u64);
+                meta.output_size = Some(output.data.len() as u64);
+                Ok(meta)
+            })
+            .collect()
+    }
+    /// An error per format (of the input’s own, if none).
+    fn failed<T>(
+        &self,
+        input_path: &Path,
+        error: impl Fn(&OutputFormat) -> FileError,
+    ) -> Vec<std::result::Result<T, FileError>> {
+        let own = OutputFormat::infer_from_path(input_path).unwrap_or(OutputFormat::Jpeg);
+        let formats = if self.formats.is_empty() {
+            vec![own]
+        } else {
+            self.formats.clone()
+        };
+        formats.iter().map(|format| Err(error(format))).collect()
+    }
+}
+
+/// The files under the directory whose content is of a recognized image
+/// format, but not under `skip` (canonical); symlinks aren’t followed.
+fn walk(dir: &Path, skip: Option<&Path>, inputs: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir).map_err(ImagerError::io(dir))? {
+        let entry = entry.map_err(ImagerError::io(dir))?;
+        let (path, file_type) = (entry.path(), entry.file_type().map_err(ImagerError::io(dir))?);
+        if file_type.is_dir() {
+            if skip.is_some() && path.canonicalize().ok().as_deref() == skip {
+                continue;
+            }
+            walk(&path, skip, inputs)?;
+        } else if file_type.is_file() && is_image(&path) {
+            inputs.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn is_image(path: &Path) -> bool {
+    let mut header = [0u8; 32];
+    let read = std::fs::File::open(path).and_then(|mut file| std::io::Read::read(&mut file, &mut header));
+    matches!(read, Ok(len) if ::image::guess_format(&header[..len]).is_ok())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_batch() {
+        let root = std::env::temp_dir().join(format!("imager-batch-{}", std::process::id()));
+        let input_dir = root.join("in");
+        std::fs::create_dir_all(input_dir.join("nested")).expect("input dir");
+        let mut png = std::io::Cursor::new(Vec::new());
+        ::image::DynamicImage::ImageRgb8(::image::RgbImage::from_fn(32, 16, |x, y| {
+            ::image::Rgb([x as u8 * 8, y as u8 * 16, 64])
+        }))
+        .write_to(&mut png, ::image::ImageFormat::Png)
+        .expect("encode");
+        std::fs::write(input_dir.join("a.png"), png.get_ref()).expect("write");
+        std::fs::write(input_dir.join("nested/b.bin"), png.get_ref()).expect("write");
+        std::fs::write(input_dir.join("nested/notes.txt"), "not an image").expect("write");
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&input_dir, input_dir.join("nested/loop")).expect("symlink");
+        let batch = Batch::new(&input_dir, root.join("out"))
+            .formats([OutputFormat::Png])
+            .threads(2);
+        assert_eq!(batch.inputs().expect("inputs").len(), 2);
+        let progress = Mutex::new(Vec::new());
+        let report = batch.run(|x| progress.lock().expect("lock").push(x.done)).expect("run");
+        assert!(report.errors.is_empty());
+        assert!(root.join("out/a.png").exists());
+        // THE DIRECTORY STRUCTURE IS MIRRORED
+        assert!(root.join("out/nested/b.png").exists());
+        let mut progress = progress.into_inner().expect("lock");
+        progress.sort();
+        assert_eq!(progress, [1, 2]);
+        let (receiver, handle) = batch.clone().formats([OutputFormat::Jpeg]).spawn();
+        assert_eq!(receiver.iter().map(|x| x.total).collect::<Vec<_>>(), [2, 2]);
+        assert_eq!(handle.join().expect("join").expect("run").outputs.len(), 2);
+        // OF THE SAME STEM, AND WITH THE OUTPUTS UNDER THE INPUTS
+        let jpeg = root.join("out/nested/b.jpeg");
+        std::fs::copy(&jpeg, input_dir.join("a.jpg")).expect("copy");
+        let batch = Batch::new(&input_dir, input_dir.join("webp")).formats([OutputFormat::Webp]);
+        let report = batch.run(|_| ()).expect("run");
+        assert!(report.errors.is_empty());
+        assert!(input_dir.join("webp/a.png.webp").exists() && input_dir.join("webp/a.jpg.webp").exists());
+        assert!(input_dir.join("webp/nested/b.webp").exists());
+        assert_eq!(batch.inputs().expect("inputs").len(), 3);
+        std::fs::remove_dir_all(&root).expect("remove dir");
+    }
+}
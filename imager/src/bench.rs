@@ -0,0 +1,216 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Codec benchmarks over a corpus (`imager bench`): the rate-distortion
+//! curve (see `rd`) of every image per codec, compared to the first codec
+//! (the anchor) by
+//!
+//! - the size at a target score (e.g. `85vq`, a VMAF of 85), interpolated
+//!   between the sweep’s settings, and
+//! - the BD-rate: the mean size difference at equal scores, over the range
+//!   of scores both curves cover (negative is smaller than the anchor).
+//!
+//! Over the corpus, ratios are combined by geometric mean.
+use image::DynamicImage;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::data::OutputFormat;
+use crate::rd::{Metric, Point};
+
+/// Scores sampled, over the common range, for BD-rates.
+const BD_SAMPLES: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Target {
+    pub score: f64,
+    pub metric: Metric,
+}
+
+impl FromStr for Target {
+    type Err = String;
+    /// A score, and a suffix for the metric: `vq` (or `vmaf`, the default)
+    /// or `db` (or `psnr`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lowercase = s.to_lowercase();
+        let split = lowercase.find(|x: char| x.is_ascii_alphabetic()).unwrap_or(lowercase.len());
+        let (score, suffix) = lowercase.split_at(split);
+        let metric = match suffix {
+            "" | "vq" | "vmaf" => Metric::Vmaf,
+            "db" | "psnr" => Metric::Psnr,
+            _ => return Err(format!("Unknown target {}", s)),
+        };
+        let score = score.parse::<f64>().map_err(|_| format!("Unknown target {}", s))?;
+        Ok(Target { score, metric })
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.metric {
+            Metric::Vmaf => write!(f, "{}vq", self.score),
+            Metric::Psnr => write!(f, "{}db", self.score),
+            Metric::Dssim => write!(f, "{}dssim", self.score),
+            Metric::Butteraugli => write!(f, "{}butteraugli", self.score),
+        }
+    }
+}
+
+/// One image, per codec (in order).
+#[derive(Debug, Clone, Serialize)]
+pub struct Sample {
+    pub path: PathBuf,
+    /// In bytes; none if the codec doesn’t reach the target.
+    pub size_at_target: Vec<Option<f64>>,
+    /// Versus the anchor (so zero for the anchor itself); none if the
+    /// curves don’t overlap.
+    pub bd_rate: Vec<Option<f64>>,
+}
+
+pub fn measure(
+    path: PathBuf,
+    source: &DynamicImage,
+    codecs: &[OutputFormat],
+    target: Target,
+) -> Result<Sample, String> {
+    let points = crate::rd::sweep(source, codecs, target.metric)?;
+    let curves = codecs.iter().map(|codec| curve(&points, codec)).collect::<Vec<_>>();
+    Ok(Sample {
+        path,
+        size_at_target: curves.iter().map(|x| size_at(x, target.score)).collect(),
+        bd_rate: curves.iter().map(|x| bd_rate(&curves[0], x)).collect(),
+    })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub codec: OutputFormat,
+    /// Images where the codec reaches the target.
+    pub reached: usize,
+    /// The mean size at the target, in bytes, over images where every
+    /// codec reaches it.
+    pub mean_size_at_target: Option<f64>,
+    /// Versus the anchor, e.g. `-0.25` for 25% smaller, over the same
+    /// images.
+    pub size_vs_anchor: Option<f64>,
+    pub bd_rate: Option<f64>,
+}
+
+pub fn summarize(samples: &[Sample], codecs: &[OutputFormat]) -> Vec<Summary> {
+    let reached_by_all = samples
+        .iter()
+        .filter(|x| x.size_at_target.iter().all(Option::is_some))
+        .collect::<Vec<_>>();
+    codecs
+        .iter()
+        .enumerate()
+        .map(|(ix, codec)| {
+            let sizes = reached_by_all
+                .iter()
+                .map(|x| (x.size_at_target[0].unwrap(), x.size_at_target[ix].unwrap()))
+                .collect::<Vec<_>>();
+            let mean_size_at_target = Some(sizes.iter().map(|(_, x)| x).sum::<f64>() / sizes.len() as f64)
+                .filter(|_| !sizes.is_empty());
+            let size_vs_anchor = geometric_mean(sizes.iter().map(|(anchor, x)| x / anchor));
+            let bd_rate = geometric_mean(samples.iter().filter_map(|x| x.bd_rate[ix]).map(|x| 1.0 + x));
+            Summary {
+                codec: codec.clone(),
+                reached: samples.iter().filter(|x| x.size_at_target[ix].is_some()).count(),
+                mean_size_at_target,
+                size_vs_anchor: size_vs_anchor.map(|x| x - 1.0),
+                bd_rate: bd_rate.map(|x| x - 1.0),
+            }
+        })
+        .collect()
+}
+
+fn geometric_mean(ratios: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, len) = ratios.fold((0.0, 0), |(sum, len), x| (sum + x.ln(), len + 1));
+    Some((sum / len as f64).exp()).filter(|_| len > 0)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// CURVES
+///////////////////////////////////////////////////////////////////////////////
+
+/// `(score, ln(size))` of the format, by size, dropping settings that don’t
+/// improve the score (so scores strictly increase).
+fn curve(points: &[Point], format: &OutputFormat) -> Vec<(f64, f64)> {
+    let mut points = points.iter().filter(|x| &x.format == format).collect::<Vec<_>>();
+    points.sort_by_key(|x| x.size);
+    let mut curve: Vec<(f64, f64)> = Vec::new();
+    for point in points {
+        if curve.last().is_none_or(|(score, _)| point.score > *score) {
+            curve.push((point.score, (point.size.max(1) as f64).ln()));
+        }
+    }
+    curve
+}
+
+fn log_size_at(curve: &[(f64, f64)], score: f64) -> Option<f64> {
+    curve.windows(2).find(|x| x[0].0 <= score && score <= x[1].0).map(|x| {
+        let ((s0, l0), (s1, l1)) = (x[0], x[1]);
+        l0 + (l1 - l0) * (score - s0) / (s1 - s0)
+    })
+}
+
+/// The smallest size with at least `score`.
+fn size_at(curve: &[(f64, f64)], score: f64) -> Option<f64> {
+    match curve.first() {
+        Some((first, log_size)) if score <= *first => Some(log_size.exp()),
+        _ => log_size_at(curve, score).map(f64::exp),
+    }
+}
+
+fn bd_rate(anchor: &[(f64, f64)], test: &[(f64, f64)]) -> Option<f64> {
+    let (anchor_first, anchor_last) = (anchor.first()?.0, anchor.last()?.0);
+    let (test_first, test_last) = (test.first()?.0, test.last()?.0);
+    let (low, high) = (anchor_first.max(test_first), anchor_last.min(test_last));
+    if low >= high {
+        return None;
+    }
+    let mean_difference = (0..BD_SAMPLES)
+        .map(|ix| low + (high - low) * (ix as f64 + 0.5) / BD_SAMPLES as f64)
+        .map(|score| Some(log_size_at(test, score)? - log_size_at(anchor, score)?))
+        .sum::<Option<f64>>()?
+        / BD_SAMPLES as f64;
+    Some(mean_difference.exp() - 1.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bench() {
+        assert_eq!(Target::from_str("85vq"), Ok(Target { score: 85.0, metric: Metric::Vmaf }));
+        assert_eq!(Target::from_str("40.5dB").map(|x| x.metric), Ok(Metric::Psnr));
+        assert!(Target::from_str("85x").is_err());
+        let point = |format: OutputFormat, size, score| Point { format, setting: 0, size, score };
+        let points = [
+            point(OutputFormat::Jpeg, 1000, 30.0),
+            point(OutputFormat::Jpeg, 4000, 40.0),
+            point(OutputFormat::Webp, 500, 30.0),
+            point(OutputFormat::Webp, 2000, 40.0),
+            // NO BETTER THAN A SMALLER SETTING
+            point(OutputFormat::Webp, 3000, 39.0),
+        ];
+        let (jpeg, webp) = (curve(&points, &OutputFormat::Jpeg), curve(&points, &OutputFormat::Webp));
+        assert_eq!(webp.len(), 2);
+        assert!((size_at(&jpeg, 35.0).unwrap() - 2000.0).abs() < 1e-6);
+        assert_eq!(size_at(&jpeg, 41.0), None);
+        assert!((bd_rate(&jpeg, &webp).unwrap() + 0.5).abs() < 1e-9);
+        assert_eq!(bd_rate(&jpeg, &jpeg), Some(0.0));
+        let sample = |webp| Sample {
+            path: PathBuf::new(),
+            size_at_target: vec![Some(2000.0), webp],
+            bd_rate: vec![Some(0.0), Some(-0.5)],
+        };
+        let summary = summarize(&[sample(Some(1000.0)), sample(None)], &[OutputFormat::Jpeg, OutputFormat::Webp]);
+        assert_eq!((summary[0].reached, summary[1].reached), (2, 1));
+        assert_eq!(summary[1].mean_size_at_target, Some(1000.0));
+        assert!((summary[1].size_vs_anchor.unwrap() + 0.5).abs() < 1e-9);
+        assert!((summary[1].bd_rate.unwrap() + 0.5).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,158 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! EPUB and CBZ books (`imager book`): their embedded images optimized in
+//! place, and the container repacked.
+//!
+//! Images keep their format and path, since the package document (and
+//! its manifest’s media types) and the pages refer to them, and readers
+//! only support a few formats; only JPEG, PNG and WebP images are
+//! optimized, the rest (e.g. GIF, SVG) are carried as is, as are images
+//! whose output isn’t smaller. An EPUB’s `mimetype` is repacked first and
+//! stored, as the OCF requires.
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::api::OptJob;
+use crate::archive::{self, ArchiveFormat, Member};
+use crate::data::OutputFormat;
+
+const EPUB_MIMETYPE: &str = "mimetype";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookFormat {
+    Epub,
+    /// A zip of pages (comic book archive).
+    Cbz,
+}
+
+impl BookFormat {
+    /// By the extension, e.g. `novel.epub`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "epub" => Some(BookFormat::Epub),
+            "cbz" => Some(BookFormat::Cbz),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageReport {
+    pub path: String,
+    /// In bytes; the same if the image was carried as is.
+    pub before: usize,
+    pub after: usize,
+    /// Why the image was carried as is, if it failed to optimize.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BookReport {
+    /// Of the containers, in bytes.
+    pub before: usize,
+    pub after: usize,
+    /// Of the optimized formats.
+    pub images: Vec<ImageReport>,
+}
+
+impl BookReport {
+    /// The share of the container’s size saved, e.g. `0.25`.
+    pub fn reduction(&self) -> f64 {
+        match self.before {
+            0 => 0.0,
+            before => 1.0 - self.after as f64 / before as f64,
+        }
+    }
+}
+
+/// The output format of the image, if one that’s optimized.
+fn image_format(data: &[u8]) -> Option<OutputFormat> {
+    match ::image::guess_format(data).ok()? {
+        ::image::ImageFormat::Jpeg => Some(OutputFormat::Jpeg),
+        ::image::ImageFormat::Png => Some(OutputFormat::Png),
+        ::image::ImageFormat::WebP => Some(OutputFormat::Webp),
+        _ => None,
+    }
+}
+
+/// The image, in its own format; none if the output isn’t smaller.
+fn optimize_image(data: &[u8], format: OutputFormat, extreme: bool) -> Result<Option<Vec<u8>>, String> {
+    let mut opt_job = OptJob::new(data).map_err(|e| e.to_string())?;
+    opt_job.output_format(format);
+    let (output, _) = opt_job.run(extreme).map_err(|e| e.to_string())?;
+    Ok(Some(output).filter(|x| x.len() < data.len()))
+}
+
+/// The repacked book, with the report of its images.
+pub fn optimize(source: &[u8], format: BookFormat, extreme: bool) -> Result<(Vec<u8>, BookReport), String> {
+    let mut members = archive::read(source, ArchiveFormat::Zip)?;
+    let stored: &[&str] = match format {
+        BookFormat::Epub => {
+            let index = members
+                .iter()
+                .position(|x| x.path == EPUB_MIMETYPE)
+                .ok_or_else(|| String::from("not an EPUB: no mimetype"))?;
+            let mimetype = members.remove(index);
+            members.insert(0, mimetype);
+            &[EPUB_MIMETYPE]
+        }
+        BookFormat::Cbz => &[],
+    };
+    let images = members
+        .par_iter_mut()
+        .filter_map(|member| Some((image_format(&member.data)?, member)))
+        .map(|(image_format, member)| {
+            let before = member.data.len();
+            let error = match optimize_image(&member.data, image_format, extreme) {
+                Ok(Some(output)) => {
+                    member.data = output;
+                    None
+                }
+                Ok(None) => None,
+                Err(message) => Some(message),
+            };
+            ImageReport { path: member.path.clone(), before, after: member.data.len(), error }
+        })
+        .collect::<Vec<_>>();
+    let output = archive::write_zip(&members, stored)?;
+    let report = BookReport { before: source.len(), after: output.len(), images };
+    Ok((output, report))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn member(path: &str, data: Vec<u8>) -> Member {
+        Member { path: String::from(path), data }
+    }
+
+    #[test]
+    fn test_epub() {
+        let source = archive::write(&[
+            member("OEBPS/content.opf", b"<package/>".to_vec()),
+            member("mimetype", b"application/epub+zip".to_vec()),
+            member("OEBPS/cover.jpeg", std::fs::read("assets/test/1.jpeg").expect("read")),
+            member("OEBPS/blank.jpeg", b"not a jpeg".to_vec()),
+        ], ArchiveFormat::Zip).expect("write");
+        let (output, report) = optimize(&source, BookFormat::Epub, false).expect("optimize");
+        // STORED, FIRST: THE NAME AT 30, THEN THE CONTENTS
+        assert_eq!(&output[30..38], b"mimetype");
+        assert_eq!(&output[38..58], b"application/epub+zip");
+        let members = archive::read(&output, ArchiveFormat::Zip).expect("read");
+        let paths = members.iter().map(|x| x.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths, ["mimetype", "OEBPS/content.opf", "OEBPS/cover.jpeg", "OEBPS/blank.jpeg"]);
+        assert_eq!(report.images.len(), 1);
+        let cover = &report.images[0];
+        assert!(cover.after <= cover.before && cover.error.is_none(), "{:?}", cover);
+        assert_eq!(::image::guess_format(&members[2].data).ok(), Some(::image::ImageFormat::Jpeg));
+        assert_eq!(report.after, output.len());
+        let cbz = archive::write(&[member("001.jpeg", Vec::new())], ArchiveFormat::Zip).expect("write");
+        assert!(optimize(&cbz, BookFormat::Epub, false).is_err());
+        assert!(optimize(&cbz, BookFormat::Cbz, false).is_ok());
+        assert_eq!(BookFormat::from_path(Path::new("issue-1.CBZ")), Some(BookFormat::Cbz));
+    }
+}
@@ -0,0 +1,109 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Best-frame selection (`imager burst`): of near identical stills (e.g. a
+//! camera’s burst), the one to keep, by
+//!
+//! - sharpness, the variance of the luma’s Laplacian (motion blur and
+//!   missed focus lower it), relative to the sharpest of the burst, and
+//! - exposure, the share of pixels that aren’t clipped, less how far the
+//!   mean luma is from a mid gray.
+//!
+//! Sharpness dominates, since bursts are usually metered alike. There’s no
+//! face model, so closed eyes aren’t detected.
+use image::{imageops::FilterType, DynamicImage, GenericImageView, GrayImage};
+use serde::{Deserialize, Serialize};
+
+/// Frames are scored at most this large, so sizes compare alike.
+const SCORED_SIZE: u32 = 1024;
+const SHARPNESS_WEIGHT: f64 = 0.7;
+/// Luma at or beyond which pixels are clipped.
+const CLIPPED: (u8, u8) = (2, 253);
+const MID_GRAY: f64 = 118.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Score {
+    /// The variance of the Laplacian.
+    pub sharpness: f64,
+    /// From 0 to 1.
+    pub exposure: f64,
+    /// From 0 to 1, of the sharpness relative to the burst’s sharpest.
+    pub total: f64,
+}
+
+/// Every frame’s score, and the index of the best; none without frames.
+pub fn select(frames: &[DynamicImage]) -> Option<(usize, Vec<Score>)> {
+    let mut scores = frames.iter().map(score).collect::<Vec<_>>();
+    let sharpest = scores.iter().map(|x| x.sharpness).fold(0.0, f64::max);
+    for score in &mut scores {
+        let sharpness = if sharpest > 0.0 { score.sharpness / sharpest } else { 1.0 };
+        score.total = SHARPNESS_WEIGHT * sharpness + (1.0 - SHARPNESS_WEIGHT) * score.exposure;
+    }
+    let best = (0..scores.len()).max_by(|a, b| scores[*a].total.total_cmp(&scores[*b].total))?;
+    Some((best, scores))
+}
+
+/// The frame’s sharpness and exposure; the total is left to `select`.
+pub fn score(frame: &DynamicImage) -> Score {
+    let (width, height) = frame.dimensions();
+    let luma = if width.max(height) > SCORED_SIZE {
+        frame.resize(SCORED_SIZE, SCORED_SIZE, FilterType::Triangle).to_luma8()
+    } else {
+        frame.to_luma8()
+    };
+    Score {
+        sharpness: laplacian_variance(&luma),
+        exposure: exposure(&luma),
+        total: 0.0,
+    }
+}
+
+fn laplacian_variance(luma: &GrayImage) -> f64 {
+    let (width, height) = luma.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+    let pixel = |x: u32, y: u32| f64::from(luma.get_pixel(x, y).0[0]);
+    let responses = (1..height - 1)
+        .flat_map(|y| (1..width - 1).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            pixel(x - 1, y) + pixel(x + 1, y) + pixel(x, y - 1) + pixel(x, y + 1) - 4.0 * pixel(x, y)
+        })
+        .collect::<Vec<_>>();
+    let mean = responses.iter().sum::<f64>() / responses.len() as f64;
+    responses.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / responses.len() as f64
+}
+
+fn exposure(luma: &GrayImage) -> f64 {
+    let count = luma.pixels().len().max(1) as f64;
+    let clipped = luma
+        .pixels()
+        .filter(|x| x.0[0] <= CLIPPED.0 || x.0[0] >= CLIPPED.1)
+        .count();
+    let mean = luma.pixels().map(|x| f64::from(x.0[0])).sum::<f64>() / count;
+    let offset = (mean - MID_GRAY).abs() / MID_GRAY.max(255.0 - MID_GRAY);
+    (1.0 - clipped as f64 / count - offset / 2.0).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_select() {
+        let frame = |blur: f32, brightness: i32| {
+            let source = image::RgbImage::from_fn(64, 64, |x, y| {
+                let value = if (x / 4 + y / 4) % 2 == 0 { 60 } else { 180 };
+                image::Rgb([(value + brightness).clamp(0, 255) as u8; 3])
+            });
+            DynamicImage::ImageRgb8(image::imageops::blur(&source, blur))
+        };
+        let frames = [frame(2.0, 0), frame(0.1, 0), frame(0.1, 120), frame(1.0, 0)];
+        let (best, scores) = select(&frames).expect("frames");
+        assert_eq!(best, 1);
+        assert!(scores[1].sharpness > scores[3].sharpness && scores[3].sharpness > scores[0].sharpness);
+        // AS SHARP, BUT BLOWN OUT
+        assert!(scores[2].exposure < scores[1].exposure);
+        assert_eq!(select(&[]), None);
+    }
+}
@@ -15,6 +15,8 @@ use std::collections::*;
 use std::hash::{Hash, Hasher};
 use std::path::PathBuf;
 
+use crate::data::{Seed, StageRng};
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Class {
     L0,
@@ -63,24 +65,18 @@ impl std::str::FromStr for Class {
 // UTILS
 ///////////////////////////////////////////////////////////////////////////////
 
+/// A random color per region label (black for the background), drawn in
+/// label order, so it’s reproducible for a given `rng` state.
 #[must_use]
-pub fn random_color_map(keys: HashSet<u32>) -> HashMap<u32, image::Rgb<u8>> {
-    use colourado::{Color, ColorPalette, PaletteType};
-    let palette = ColorPalette::new(keys.len() as u32, PaletteType::Random, false);
+pub fn random_color_map<R: rand::Rng>(keys: HashSet<u32>, rng: &mut R) -> HashMap<u32, image::Rgb<u8>> {
+    let mut keys = keys.into_iter().collect::<Vec<_>>();
+    keys.sort_unstable();
     let mut output: HashMap<u32, image::Rgb<u8>> = HashMap::new();
-    for (key, ix) in keys.iter().zip(0..keys.len()) {
-        let key = key.clone();
+    for key in keys {
         if key == 0 {
             output.insert(key, image::Rgb([0, 0, 0]));
         } else {
-            fn convert(x: f32) -> u8 {
-                (x * 255.0) as u8
-            }
-            let red = convert(palette.colors[ix].red);
-            let green = convert(palette.colors[ix].green);
-            let blue = convert(palette.colors[ix].blue);
-
-            output.insert(key, image::Rgb([red, green, blue]));
+            output.insert(key, image::Rgb(rng.gen()));
         }
     }
     output
@@ -136,7 +132,10 @@ pub fn is_white_dominant(media: &DynamicImage) -> bool {
     }
     // DEBUG IMAGE
     if false {
-        let debug_colors = random_color_map(components.pixels().map(|p| p[0]).collect());
+        let debug_colors = random_color_map(
+            components.pixels().map(|p| p[0]).collect(),
+            &mut Seed::default().rng("classifier"),
+        );
         let debug_media = ImageBuffer::from_fn(media.width(), media.height(), |x, y| {
             let px_key = components.get_pixel(x, y).channels()[0];
             let color = debug_colors.get(&px_key).expect("missing color entry");
@@ -199,6 +198,11 @@ pub fn grayscale_segmentation(media: &DynamicImage) -> GrayImage {
 }
 
 pub fn report(media: &DynamicImage) -> Report {
+    report_seeded(media, Seed::default())
+}
+
+/// `report`, with the debug images drawn from the given seed.
+pub fn report_seeded(media: &DynamicImage, seed: Seed) -> Report {
     // MISC
     let white_dominant = is_white_dominant(media);
     // PRE-PROCESS IMAGE
@@ -260,7 +264,10 @@ pub fn report(media: &DynamicImage) -> Report {
         .max()
         .map(|x| x.clone())
         .unwrap_or(0);
-    let debug_colors = random_color_map(components.pixels().map(|p| p[0]).map(|x| x).collect());
+    let debug_colors = random_color_map(
+        components.pixels().map(|p| p[0]).map(|x| x).collect(),
+        &mut seed.rng("classifier"),
+    );
     let regions_media =
         ImageBuffer::from_fn(regions_media.width(), regions_media.height(), |x, y| {
             let px_key = components.get_pixel(x, y).channels()[0];
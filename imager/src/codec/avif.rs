@@ -0,0 +1,244 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! AVIF (AV1 in HEIF) encoding and decoding, via libheif (see `heif`) and
+//! its AV1 codecs, e.g. libaom; AVIF usually beats WebP on photos.
+//!
+//! With `ffi`, the quality is searched for the lowest that passes the class
+//! (and size) dependent VMAF threshold; by bisection, since AV1 encodes are
+//! slow. A fast search bisects a low-resolution proxy first, and then
+//! only the bracket around its quality (see `proxy`). `pure-rust` builds (without libvmaf) encode at `FIXED_QUALITY`.
+use image::DynamicImage;
+#[cfg(not(feature = "pure-rust"))]
+use image::GenericImageView;
+use serde::{Deserialize, Serialize};
+
+use crate::classifier::{self, Class};
+#[cfg(not(feature = "pure-rust"))]
+use crate::codec::proxy;
+#[cfg(not(feature = "pure-rust"))]
+use crate::codec::tiles::Tiles;
+use crate::data::QualityRange;
+#[cfg(not(feature = "pure-rust"))]
+use crate::data::VideoBuffer;
+#[cfg(feature = "avif")]
+use crate::error::ImagerError;
+use crate::error::Result;
+#[cfg(not(feature = "pure-rust"))]
+use crate::vmaf;
+
+/// The quality without a search.
+pub const FIXED_QUALITY: u8 = 60;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OutMeta {
+    pub class: Class,
+    pub vmaf_score: Option<f64>,
+    pub end_q: u8,
+    pub passed: bool,
+}
+
+/// At `quality` (0-100), with alpha.
+#[cfg(feature = "avif")]
+pub fn encode(source: &DynamicImage, quality: u8) -> Result<Vec<u8>> {
+    use super::heif::ffi;
+    ffi::encode(&source.to_rgba8(), ffi::COMPRESSION_AV1, &[], quality).map_err(ImagerError::Encode)
+}
+
+#[cfg(not(feature = "avif"))]
+pub fn encode(_: &DynamicImage, _: u8) -> Result<Vec<u8>> {
+    Err(crate::codec::registry::FeatureDisabled::AVIF.into())
+}
+
+/// Of libheif, with its AV1 encoder (e.g. `1.16.2 (rav1e 0.6.6)`).
+#[cfg(feature = "avif")]
+pub fn version() -> Option<String> {
+    super::heif::ffi::version(super::heif::ffi::COMPRESSION_AV1).ok()
+}
+
+#[cfg(not(feature = "avif"))]
+pub fn version() -> Option<String> {
+    None
+}
+
+#[cfg(feature = "avif")]
+pub fn decode(source: &[u8]) -> Result<DynamicImage> {
+    super::heif::ffi::decode(source)
+        .map(DynamicImage::ImageRgba8)
+        .map_err(ImagerError::Decode)
+}
+
+#[cfg(not(feature = "avif"))]
+pub fn decode(_: &[u8]) -> Result<DynamicImage> {
+    Err(crate::codec::registry::FeatureDisabled::AVIF.into())
+}
+
+/// Searches the `range` for the VMAF target (by default, the class and
+/// size dependent threshold), falling back to its maximum; if fast, within
+/// the bracket a search on a proxy found, then above it. With a metric
+/// coverage, very large sources are scored on a sample of their tiles.
+#[cfg(not(feature = "pure-rust"))]
+pub fn opt(
+    source: &DynamicImage,
+    range: QualityRange,
+    vmaf_target: Option<f64>,
+    fast_search: bool,
+    metric_coverage: Option<f64>,
+) -> Result<(Vec<u8>, OutMeta)> {
+    let class = classifier::report(source).class;
+    let (width, height) = source.dimensions();
+    let threshold = vmaf_target.unwrap_or_else(|| threshold(&class, (width * height) < (600 * 600)));
+    let tiles = metric_coverage.and_then(|x| Tiles::sample(source, x));
+    let scored = |image: DynamicImage| match &tiles {
+        Some(tiles) => tiles.mosaic(&image),
+        None => image,
+    };
+    let vmaf_source = VideoBuffer::from_image(&scored(source.clone())).expect("image to yuv frame");
+    let mut passing = None;
+    let mut error = None;
+    // OF THE PROXY, HELD TO THE SOURCE’S THRESHOLD
+    let bracket = match proxy::proxy(source) {
+        Some(proxy) if fast_search => match opt(&proxy, range, Some(threshold), false, None)? {
+            (_, meta) if meta.passed => Some(proxy::bracket(range, meta.end_q)),
+            _ => None,
+        },
+        _ => None,
+    };
+    let mut passes = |q: u8| {
+        let result = encode(source, q).and_then(|compressed| {
+            let decoded = decode(&compressed)?;
+            let decoded = VideoBuffer::from_image(&scored(decoded)).expect("image to yuv frame");
+            Ok((compressed, vmaf::get_report(&vmaf_source, &decoded)))
+        });
+        match result {
+            Ok((compressed, score)) if score >= threshold => {
+                passing = Some((compressed, score));
+                true
+            }
+            Ok(_) => false,
+            Err(message) => {
+                error.get_or_insert(message);
+                false
+            }
+        }
+    };
+    let end_q = match bracket {
+        Some(bracket) => match bisect(bracket, &mut passes) {
+            Some(q) => Some(q),
+            None if bracket.max < range.max => {
+                let above = QualityRange {
+                    min: bracket.max + 1,
+                    max: range.max,
+                };
+                bisect(above, &mut passes)
+            }
+            None => None,
+        },
+        None => bisect(range, &mut passes),
+    };
+    if let Some(message) = error {
+        return Err(message);
+    }
+    match (end_q, passing) {
+        (Some(end_q), Some((output, score))) => {
+            let meta = OutMeta {
+                class,
+                vmaf_score: Some(score),
+                end_q,
+                passed: true,
+            };
+            Ok((output, meta))
+        }
+        // FALLBACK
+        _ => {
+            let meta = OutMeta {
+                class,
+                vmaf_score: None,
+                end_q: range.max,
+                passed: false,
+            };
+            Ok((encode(source, range.max)?, meta))
+        }
+    }
+}
+
+/// Encodes at `FIXED_QUALITY` (within the range), the whole “search” of
+/// `pure-rust` builds.
+#[cfg(feature = "pure-rust")]
+pub fn opt(
+    source: &DynamicImage,
+    range: QualityRange,
+    _: Option<f64>,
+    _: bool,
+    _: Option<f64>,
+) -> Result<(Vec<u8>, OutMeta)> {
+    let quality = range.clamp(FIXED_QUALITY);
+    let meta = OutMeta {
+        class: classifier::report(source).class,
+        vmaf_score: None,
+        end_q: quality,
+        passed: false,
+    };
+    Ok((encode(source, quality)?, meta))
+}
+
+/// The VMAF score outputs must reach.
+#[cfg(not(feature = "pure-rust"))]
+fn threshold(class: &Class, is_small: bool) -> f64 {
+    match class {
+        Class::L0 | Class::L1 | Class::L2 if is_small => 99.0,
+        Class::L0 | Class::L1 | Class::L2 => 95.0,
+        Class::M1 if is_small => 96.0,
+        Class::M1 => 90.0,
+        Class::H1 | Class::H2 if is_small => 85.0,
+        Class::H1 => 80.0,
+        Class::H2 => 75.0,
+    }
+}
+
+/// The lowest quality of the range that `passes`, assuming higher ones do
+/// too. Each passing call is of a lower quality than the last, so the last
+/// is of the result.
+#[cfg(not(feature = "pure-rust"))]
+fn bisect(range: QualityRange, mut passes: impl FnMut(u8) -> bool) -> Option<u8> {
+    let (mut low, mut high) = (range.min, range.max);
+    let mut lowest = None;
+    while low <= high {
+        let q = low + (high - low) / 2;
+        if passes(q) {
+            lowest = Some(q);
+            if q == range.min {
+                break;
+            }
+            high = q - 1;
+        } else {
+            low = q + 1;
+        }
+    }
+    lowest
+}
+
+#[cfg(all(test, not(feature = "pure-rust")))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bisect() {
+        let mut passed = Vec::new();
+        let mut calls = 0;
+        let range = QualityRange { min: 10, max: 90 };
+        let lowest = bisect(range, |q| {
+            calls += 1;
+            if q >= 37 {
+                passed.push(q);
+            }
+            q >= 37
+        });
+        assert_eq!(lowest, Some(37));
+        assert_eq!(passed.last(), Some(&37));
+        assert!(passed.windows(2).all(|x| x[0] > x[1]));
+        assert!(calls <= 7, "{}", calls);
+        assert_eq!(bisect(range, |_| false), None);
+        assert_eq!(bisect(range, |_| true), Some(10));
+    }
+}
@@ -0,0 +1,97 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! GIF inputs, expanded into their (composited) frames, e.g. to convert
+//! them to animated WebP (see `webp::encode::anim`) or to video (see
+//! `video::encode`), which are typically far smaller.
+use image::codecs::gif::GifDecoder;
+use image::{AnimationDecoder, DynamicImage, RgbaImage};
+use std::time::Duration;
+
+use crate::data::{VideoBuffer, Yuv420P};
+use crate::error::{ImagerError, Result};
+
+/// Browsers show frames of shorter (e.g. zero) delays for this long.
+pub const MIN_DELAY: Duration = Duration::from_millis(20);
+const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
+/// A frame of the full canvas, with the frames before it composited (by
+/// their disposal methods).
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub image: RgbaImage,
+    /// How long the frame is shown.
+    pub delay: Duration,
+}
+
+/// The frames, in order; still GIFs have one.
+pub fn decode_frames(source: &[u8]) -> Result<Vec<Frame>> {
+    let decoder = GifDecoder::new(std::io::Cursor::new(source))?;
+    let frames = decoder
+        .into_frames()
+        .map(|frame| {
+            let frame = frame?;
+            let delay = Duration::from(frame.delay());
+            Ok(Frame {
+                // AS BROWSERS DO
+                delay: if delay < MIN_DELAY { DEFAULT_DELAY } else { delay },
+                image: frame.into_buffer(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if frames.is_empty() {
+        return Err(ImagerError::decode("the GIF has no frames"));
+    }
+    Ok(frames)
+}
+
+/// The frames as a `VideoBuffer` (without their delays, nor alpha), e.g.
+/// for VMAF.
+pub fn decode(source: &[u8]) -> Result<VideoBuffer> {
+    let frames = decode_frames(source)?
+        .into_iter()
+        .map(|frame| Yuv420P::from_image(&DynamicImage::ImageRgba8(frame.image)))
+        .collect::<Result<Vec<_>>>()?;
+    VideoBuffer::from_frames(frames)
+}
+
+/// Of all frames.
+pub fn duration(frames: &[Frame]) -> Duration {
+    frames.iter().map(|x| x.delay).sum()
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use image::codecs::gif::GifEncoder;
+    use image::Delay;
+
+    /// Of three frames, with a zero delay last.
+    pub(crate) fn animated_gif() -> Vec<u8> {
+        let mut output = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut output);
+            for (ix, delay) in [50, 150, 0].into_iter().enumerate() {
+                let image = RgbaImage::from_fn(20, 12, |x, _| {
+                    let lit = x as usize / 5 == ix;
+                    image::Rgba(if lit { [240, 40, 40, 255] } else { [20, 20, 200, 255] })
+                });
+                let frame = image::Frame::from_parts(image, 0, 0, Delay::from_numer_denom_ms(delay, 1));
+                encoder.encode_frame(frame).expect("encode frame");
+            }
+        }
+        output
+    }
+
+    #[test]
+    fn test_decode() {
+        let frames = decode_frames(&animated_gif()).expect("decode");
+        let delays = frames.iter().map(|x| x.delay.as_millis()).collect::<Vec<_>>();
+        assert_eq!(delays, [50, 150, 100]);
+        assert_eq!(duration(&frames), Duration::from_millis(300));
+        assert_eq!(frames[1].image.get_pixel(7, 3).0, [240, 40, 40, 255]);
+        let video = decode(&animated_gif()).expect("video buffer");
+        assert_eq!((video.as_frames().len(), video.dimensions()), (3, (20, 12)));
+        assert!(decode_frames(b"GIF89a").is_err());
+    }
+}
@@ -0,0 +1,294 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! HEIF (HEVC) encoding via libheif, with embedded thumbnails, e.g. for
+//! galleries to list without decoding the full image.
+//!
+//! With the `heif` feature, libheif is loaded at runtime, from
+//! `LIBHEIF_DYLIB_PATH` or else the platform’s library search path, so
+//! builds don’t need it; it must have an HEVC encoder (e.g. x265). AVIF
+//! (see `avif`) is the same container with AV1, via libheif’s AV1 encoder
+//! (e.g. libaom) and decoder (e.g. dav1d).
+use image::DynamicImage;
+
+use crate::error::Result;
+
+#[cfg(feature = "heif")]
+pub(super) mod ffi {
+    use libloading::{Library, Symbol};
+    use std::ffi::{c_void, CStr};
+    use std::os::raw::{c_char, c_int};
+
+    #[cfg(target_os = "windows")]
+    const DEFAULT_LIBRARY: &str = "heif.dll";
+    #[cfg(target_os = "macos")]
+    const DEFAULT_LIBRARY: &str = "libheif.1.dylib";
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    const DEFAULT_LIBRARY: &str = "libheif.so.1";
+
+    // ENUM VALUES (libheif/heif.h)
+    pub const COMPRESSION_HEVC: c_int = 1;
+    pub const COMPRESSION_AV1: c_int = 4;
+    const COLORSPACE_RGB: c_int = 1;
+    const CHROMA_INTERLEAVED_RGBA: c_int = 11;
+    const CHANNEL_INTERLEAVED: c_int = 10;
+
+    type Handle = *mut c_void;
+
+    #[repr(C)]
+    pub struct Error {
+        code: c_int,
+        subcode: c_int,
+        message: *const c_char,
+    }
+
+    #[repr(C)]
+    struct Writer {
+        writer_api_version: c_int,
+        write: unsafe extern "C" fn(Handle, *const c_void, usize, *mut c_void) -> Error,
+    }
+
+    unsafe extern "C" fn write(_: Handle, data: *const c_void, size: usize, output: *mut c_void) -> Error {
+        let output = &mut *(output as *mut Vec<u8>);
+        output.extend_from_slice(std::slice::from_raw_parts(data as *const u8, size));
+        Error {
+            code: 0,
+            subcode: 0,
+            message: c"Success".as_ptr(),
+        }
+    }
+
+    fn check(error: Error) -> Result<(), String> {
+        match error.code {
+            0 => Ok(()),
+            _ if error.message.is_null() => Err(format!("libheif error {}", error.code)),
+            _ => Err(unsafe { CStr::from_ptr(error.message) }.to_string_lossy().into_owned()),
+        }
+    }
+
+    fn open() -> Result<Library, String> {
+        let path = std::env::var_os("LIBHEIF_DYLIB_PATH").unwrap_or_else(|| DEFAULT_LIBRARY.into());
+        Library::new(&path).map_err(|e| format!("failed to load libheif ({:?}): {}", path, e))
+    }
+
+    macro_rules! symbol {
+        ($library:ident, $name:literal, $type:ty) => {{
+            let symbol: Symbol<$type> = $library.get($name).map_err(|e| e.to_string())?;
+            symbol
+        }};
+    }
+
+    /// With `compression`, one of `COMPRESSION_HEVC` and `COMPRESSION_AV1`.
+    pub fn encode(
+        rgba: &image::RgbaImage,
+        compression: c_int,
+        thumbnails: &[u32],
+        quality: u8,
+    ) -> Result<Vec<u8>, String> {
+        let library = open()?;
+        unsafe {
+            let context_alloc = symbol!(library, b"heif_context_alloc\0", unsafe extern "C" fn() -> Handle);
+            let context_free = symbol!(library, b"heif_context_free\0", unsafe extern "C" fn(Handle));
+            let get_encoder = symbol!(
+                library,
+                b"heif_context_get_encoder_for_format\0",
+                unsafe extern "C" fn(Handle, c_int, *mut Handle) -> Error
+            );
+            let set_quality = symbol!(
+                library,
+                b"heif_encoder_set_lossy_quality\0",
+                unsafe extern "C" fn(Handle, c_int) -> Error
+            );
+            let encoder_release = symbol!(library, b"heif_encoder_release\0", unsafe extern "C" fn(Handle));
+            let image_create = symbol!(
+                library,
+                b"heif_image_create\0",
+                unsafe extern "C" fn(c_int, c_int, c_int, c_int, *mut Handle) -> Error
+            );
+            let add_plane = symbol!(
+                library,
+                b"heif_image_add_plane\0",
+                unsafe extern "C" fn(Handle, c_int, c_int, c_int, c_int) -> Error
+            );
+            let get_plane = symbol!(
+                library,
+                b"heif_image_get_plane\0",
+                unsafe extern "C" fn(Handle, c_int, *mut c_int) -> *mut u8
+            );
+            let image_release = symbol!(library, b"heif_image_release\0", unsafe extern "C" fn(Handle));
+            let encode_image = symbol!(
+                library,
+                b"heif_context_encode_image\0",
+                unsafe extern "C" fn(Handle, Handle, Handle, *const c_void, *mut Handle) -> Error
+            );
+            let encode_thumbnail = symbol!(
+                library,
+                b"heif_context_encode_thumbnail\0",
+                unsafe extern "C" fn(Handle, Handle, Handle, Handle, *const c_void, c_int, *mut Handle) -> Error
+            );
+            let handle_release = symbol!(library, b"heif_image_handle_release\0", unsafe extern "C" fn(Handle));
+            let context_write = symbol!(
+                library,
+                b"heif_context_write\0",
+                unsafe extern "C" fn(Handle, *const Writer, *mut c_void) -> Error
+            );
+            // RELEASED IN REVERSE, WHATEVER SUCCEEDED
+            let context = context_alloc();
+            let mut encoder: Handle = std::ptr::null_mut();
+            let mut image: Handle = std::ptr::null_mut();
+            let mut primary: Handle = std::ptr::null_mut();
+            let mut output = Vec::new();
+            let result = (|| {
+                check(get_encoder(context, compression, &mut encoder))?;
+                check(set_quality(encoder, c_int::from(quality)))?;
+                let (width, height) = (rgba.width() as c_int, rgba.height() as c_int);
+                check(image_create(width, height, COLORSPACE_RGB, CHROMA_INTERLEAVED_RGBA, &mut image))?;
+                check(add_plane(image, CHANNEL_INTERLEAVED, width, height, 8))?;
+                let mut stride: c_int = 0;
+                let plane = get_plane(image, CHANNEL_INTERLEAVED, &mut stride);
+                if plane.is_null() {
+                    return Err(String::from("libheif image without a plane"));
+                }
+                for (y, row) in rgba.as_raw().chunks_exact(rgba.width() as usize * 4).enumerate() {
+                    let start = plane.add(y * stride as usize);
+                    std::ptr::copy_nonoverlapping(row.as_ptr(), start, row.len());
+                }
+                check(encode_image(context, image, encoder, std::ptr::null(), &mut primary))?;
+                for size in thumbnails {
+                    let mut thumbnail: Handle = std::ptr::null_mut();
+                    let size = *size as c_int;
+                    check(encode_thumbnail(context, image, primary, encoder, std::ptr::null(), size, &mut thumbnail))?;
+                    // NO THUMBNAIL IF THE IMAGE ISN’T LARGER
+                    if !thumbnail.is_null() {
+                        handle_release(thumbnail);
+                    }
+                }
+                let writer = Writer {
+                    writer_api_version: 1,
+                    write,
+                };
+                check(context_write(context, &writer, &mut output as *mut Vec<u8> as *mut c_void))
+            })();
+            for (handle, release) in [(primary, &handle_release), (image, &image_release), (encoder, &encoder_release)] {
+                if !handle.is_null() {
+                    release(handle);
+                }
+            }
+            context_free(context);
+            result.map(|()| output)
+        }
+    }
+
+    /// Of the loaded libheif, with the name of its encoder of the
+    /// compression, e.g. `1.16.2 (rav1e 0.6.6)`.
+    pub fn version(compression: c_int) -> Result<String, String> {
+        let library = open()?;
+        unsafe {
+            let get_version = symbol!(library, b"heif_get_version\0", unsafe extern "C" fn() -> *const c_char);
+            let context_alloc = symbol!(library, b"heif_context_alloc\0", unsafe extern "C" fn() -> Handle);
+            let context_free = symbol!(library, b"heif_context_free\0", unsafe extern "C" fn(Handle));
+            let get_encoder = symbol!(
+                library,
+                b"heif_context_get_encoder_for_format\0",
+                unsafe extern "C" fn(Handle, c_int, *mut Handle) -> Error
+            );
+            let get_name = symbol!(library, b"heif_encoder_get_name\0", unsafe extern "C" fn(Handle) -> *const c_char);
+            let encoder_release = symbol!(library, b"heif_encoder_release\0", unsafe extern "C" fn(Handle));
+            let string = |x: *const c_char| match x.is_null() {
+                true => String::from("unknown"),
+                false => CStr::from_ptr(x).to_string_lossy().into_owned(),
+            };
+            let version = string(get_version());
+            let context = context_alloc();
+            let mut encoder: Handle = std::ptr::null_mut();
+            let name = match check(get_encoder(context, compression, &mut encoder)) {
+                Ok(()) => string(get_name(encoder)),
+                Err(_) => String::from("no encoder"),
+            };
+            if !encoder.is_null() {
+                encoder_release(encoder);
+            }
+            context_free(context);
+            Ok(format!("{} ({})", version, name))
+        }
+    }
+
+    /// The primary image.
+    pub fn decode(source: &[u8]) -> Result<image::RgbaImage, String> {
+        let library = open()?;
+        unsafe {
+            let context_alloc = symbol!(library, b"heif_context_alloc\0", unsafe extern "C" fn() -> Handle);
+            let context_free = symbol!(library, b"heif_context_free\0", unsafe extern "C" fn(Handle));
+            let read = symbol!(
+                library,
+                b"heif_context_read_from_memory_without_copy\0",
+                unsafe extern "C" fn(Handle, *const c_void, usize, *const c_void) -> Error
+            );
+            let get_primary = symbol!(
+                library,
+                b"heif_context_get_primary_image_handle\0",
+                unsafe extern "C" fn(Handle, *mut Handle) -> Error
+            );
+            let decode_image = symbol!(
+                library,
+                b"heif_decode_image\0",
+                unsafe extern "C" fn(Handle, *mut Handle, c_int, c_int, *const c_void) -> Error
+            );
+            let get_plane = symbol!(
+                library,
+                b"heif_image_get_plane_readonly\0",
+                unsafe extern "C" fn(Handle, c_int, *mut c_int) -> *const u8
+            );
+            let get_width = symbol!(library, b"heif_image_get_width\0", unsafe extern "C" fn(Handle, c_int) -> c_int);
+            let get_height =
+                symbol!(library, b"heif_image_get_height\0", unsafe extern "C" fn(Handle, c_int) -> c_int);
+            let image_release = symbol!(library, b"heif_image_release\0", unsafe extern "C" fn(Handle));
+            let handle_release = symbol!(library, b"heif_image_handle_release\0", unsafe extern "C" fn(Handle));
+            // RELEASED IN REVERSE, WHATEVER SUCCEEDED
+            let context = context_alloc();
+            let mut primary: Handle = std::ptr::null_mut();
+            let mut image: Handle = std::ptr::null_mut();
+            let result = (|| {
+                check(read(context, source.as_ptr() as *const c_void, source.len(), std::ptr::null()))?;
+                check(get_primary(context, &mut primary))?;
+                let (colorspace, chroma) = (COLORSPACE_RGB, CHROMA_INTERLEAVED_RGBA);
+                check(decode_image(primary, &mut image, colorspace, chroma, std::ptr::null()))?;
+                let (width, height) = (get_width(image, CHANNEL_INTERLEAVED), get_height(image, CHANNEL_INTERLEAVED));
+                let mut stride: c_int = 0;
+                let plane = get_plane(image, CHANNEL_INTERLEAVED, &mut stride);
+                if plane.is_null() || width <= 0 || height <= 0 {
+                    return Err(String::from("libheif image without a plane"));
+                }
+                let (width, height) = (width as usize, height as usize);
+                let mut pixels = Vec::with_capacity(width * height * 4);
+                for y in 0..height {
+                    let row = std::slice::from_raw_parts(plane.add(y * stride as usize), width * 4);
+                    pixels.extend_from_slice(row);
+                }
+                image::RgbaImage::from_raw(width as u32, height as u32, pixels)
+                    .ok_or_else(|| String::from("libheif image of an unexpected size"))
+            })();
+            if !image.is_null() {
+                image_release(image);
+            }
+            if !primary.is_null() {
+                handle_release(primary);
+            }
+            context_free(context);
+            result
+        }
+    }
+}
+
+/// The source as the primary image, at `quality` (0-100), and a thumbnail
+/// fitting each of the `thumbnails` sizes (if smaller than the source).
+#[cfg(feature = "heif")]
+pub fn encode(source: &DynamicImage, thumbnails: &[u32], quality: u8) -> Result<Vec<u8>> {
+    ffi::encode(&source.to_rgba8(), ffi::COMPRESSION_HEVC, thumbnails, quality)
+        .map_err(crate::error::ImagerError::Encode)
+}
+
+#[cfg(not(feature = "heif"))]
+pub fn encode(_: &DynamicImage, _: &[u32], _: u8) -> Result<Vec<u8>> {
+    Err(crate::codec::registry::FeatureDisabled::HEIF.into())
+}
@@ -12,35 +12,181 @@ use std::os::raw::{c_char, c_int};
 use std::path::PathBuf;
 
 use crate::classifier::{self, Class};
-use crate::data::{VideoBuffer, Yuv420P};
+#[cfg(not(feature = "pure-rust"))]
+use crate::codec::proxy;
+#[cfg(not(feature = "pure-rust"))]
+use crate::codec::tiles::Tiles;
+use crate::data::{QualityRange, Resolution, Subsampling, Tuning, VideoBuffer, Yuv420P};
+use crate::error::ImagerError;
+use crate::text_protect::{self, TextMask};
+#[cfg(not(feature = "pure-rust"))]
 use crate::vmaf;
 
 ///////////////////////////////////////////////////////////////////////////////
 // MOZJPEG FFI HELPERS
 ///////////////////////////////////////////////////////////////////////////////
 
+#[cfg(not(feature = "pure-rust"))]
 #[allow(non_snake_case)]
 const TRUE: mozjpeg_sys::boolean = true as mozjpeg_sys::boolean;
+#[cfg(not(feature = "pure-rust"))]
 #[allow(non_snake_case)]
 const FALSE: mozjpeg_sys::boolean = false as mozjpeg_sys::boolean;
 
+#[cfg(not(feature = "pure-rust"))]
 const COLOR_SPACE: mozjpeg_sys::J_COLOR_SPACE = mozjpeg_sys::J_COLOR_SPACE::JCS_RGB;
+#[cfg(not(feature = "pure-rust"))]
 const COLOR_SPACE_COMPONENTS: libc::c_int = 3 as libc::c_int;
 
+/// Of mozjpeg’s base quantization tables: ImageMagick’s, tuned for
+/// (trellis quantized) progressive outputs.
+#[cfg(not(feature = "pure-rust"))]
+const BASE_QUANT_TABLE: libc::c_int = 3;
+
+/// Of the linked mozjpeg, as `cjpeg -version` reports it: mozjpeg-sys
+/// builds report the crate’s version.
+#[cfg(not(feature = "pure-rust"))]
+pub fn version() -> Option<String> {
+    // THE MESSAGE CODE (jerror.h), AFTER THE CODES OF THE ABI’S VERSION
+    let jmsg_version: usize = if mozjpeg_sys::JPEG_LIB_VERSION >= 70 { 76 } else { 74 };
+    unsafe {
+        let mut err: mozjpeg_sys::jpeg_error_mgr = std::mem::zeroed();
+        let err = mozjpeg_sys::jpeg_std_error(&mut err);
+        if err.jpeg_message_table.is_null() || err.last_jpeg_message < jmsg_version as c_int {
+            return None;
+        }
+        let message = *err.jpeg_message_table.add(jmsg_version);
+        (!message.is_null()).then(|| CStr::from_ptr(message).to_string_lossy().into_owned())
+    }
+}
+
+/// A libjpeg error, which `unwind_error_exit` unwinds with.
+#[cfg(not(feature = "pure-rust"))]
+struct LibjpegError(String);
+
+/// In place of libjpeg’s `error_exit`, which exits the process.
+#[cfg(not(feature = "pure-rust"))]
+unsafe extern "C-unwind" fn unwind_error_exit(cinfo: &mut mozjpeg_sys::jpeg_common_struct) {
+    let buffer = [0u8; 80];
+    if let Some(format_message) = (*cinfo.err).format_message {
+        format_message(cinfo, &buffer);
+    }
+    let message = CStr::from_bytes_until_nul(&buffer).map_or_else(
+        |_| String::from("unknown libjpeg error"),
+        |x| x.to_string_lossy().into_owned(),
+    );
+    // NOT A PANIC: THERE’S NOTHING FOR THE PANIC HOOK TO REPORT
+    std::panic::resume_unwind(Box::new(LibjpegError(message)))
+}
+
+/// `jpeg_std_error`, with an `error_exit` that unwinds rather than exiting
+/// the process; so every libjpeg call that may fail must be of `unwinding`,
+/// within `guard`.
+#[cfg(not(feature = "pure-rust"))]
+unsafe fn error_mgr(err: &mut mozjpeg_sys::jpeg_error_mgr) -> &mut mozjpeg_sys::jpeg_error_mgr {
+    let err = mozjpeg_sys::jpeg_std_error(err);
+    // THE BINDINGS DECLARE IT "C", THOUGH THE LIBRARY IS BUILT TO UNWIND
+    err.error_exit = Some(std::mem::transmute::<
+        unsafe extern "C-unwind" fn(&mut mozjpeg_sys::jpeg_common_struct),
+        unsafe extern "C" fn(&mut mozjpeg_sys::jpeg_common_struct),
+    >(unwind_error_exit));
+    err
+}
+
+/// Runs libjpeg calls, failing with the message of the error that aborted
+/// them (see `error_mgr`); other panics carry on.
+#[cfg(not(feature = "pure-rust"))]
+fn guard<T>(calls: impl FnOnce() -> T) -> Result<T, String> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(calls)).map_err(|payload| {
+        match payload.downcast::<LibjpegError>() {
+            Ok(error) => error.0,
+            Err(payload) => std::panic::resume_unwind(payload),
+        }
+    })
+}
+
+/// The libjpeg calls that may fail, declared as unwinding (from
+/// `unwind_error_exit`, through libjpeg’s frames, which mozjpeg-sys’s
+/// `unwinding` feature builds with `-fexceptions`).
+#[cfg(not(feature = "pure-rust"))]
+mod unwinding {
+    use mozjpeg_sys::{
+        boolean, c_int, jpeg_compress_struct, jpeg_decompress_struct, JDIMENSION, JSAMPARRAY, JSAMPARRAY_MUT,
+    };
+
+    extern "C-unwind" {
+        pub fn jpeg_start_compress(cinfo: &mut jpeg_compress_struct, write_all_tables: boolean);
+        pub fn jpeg_write_scanlines(
+            cinfo: &mut jpeg_compress_struct,
+            scanlines: JSAMPARRAY,
+            num_lines: JDIMENSION,
+        ) -> JDIMENSION;
+        pub fn jpeg_finish_compress(cinfo: &mut jpeg_compress_struct);
+        pub fn jpeg_read_header(cinfo: &mut jpeg_decompress_struct, require_image: boolean) -> c_int;
+        pub fn jpeg_start_decompress(cinfo: &mut jpeg_decompress_struct) -> boolean;
+        pub fn jpeg_read_scanlines(
+            cinfo: &mut jpeg_decompress_struct,
+            scanlines: JSAMPARRAY_MUT,
+            max_lines: JDIMENSION,
+        ) -> JDIMENSION;
+        pub fn jpeg_finish_decompress(cinfo: &mut jpeg_decompress_struct) -> boolean;
+    }
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 // MOZJPEG ENCODER
 ///////////////////////////////////////////////////////////////////////////////
 
+#[cfg(not(feature = "pure-rust"))]
 #[must_use] pub unsafe fn encode(source: &DynamicImage, quality: u8) -> Vec<u8> {
-    ///////////////////////////////////////////////////////////////////////////
-    // INPUT
-    ///////////////////////////////////////////////////////////////////////////
+    encode_subsampled(source, quality, Subsampling::default())
+}
+
+/// Like `encode`, with the chroma `subsampling`.
+///
+/// # Safety
+///
+/// Calls into mozjpeg, as `encode` does.
+#[cfg(not(feature = "pure-rust"))]
+#[must_use] pub unsafe fn encode_subsampled(
+    source: &DynamicImage,
+    quality: u8,
+    subsampling: Subsampling,
+) -> Vec<u8> {
     let rgb_source = source
         .to_rgb8()
         .pixels()
         .flat_map(|x| x.0.to_vec())
         .collect::<Vec<_>>();
-    let (width, height) = source.dimensions();
+    encode_samples(&rgb_source, source.dimensions(), COLOR_SPACE, quality, subsampling)
+}
+
+/// Encodes (interleaved, full range) YCbCr samples, as of
+/// `text_protect::ycbcr`.
+///
+/// # Safety
+///
+/// Calls into mozjpeg, as `encode` does.
+#[cfg(not(feature = "pure-rust"))]
+#[must_use] pub unsafe fn encode_ycbcr(
+    ycbcr: &[u8],
+    dimensions: (u32, u32),
+    quality: u8,
+    subsampling: Subsampling,
+) -> Vec<u8> {
+    let color_space = mozjpeg_sys::J_COLOR_SPACE::JCS_YCbCr;
+    encode_samples(ycbcr, dimensions, color_space, quality, subsampling)
+}
+
+/// Interleaved, 3 component `samples`.
+#[cfg(not(feature = "pure-rust"))]
+unsafe fn encode_samples(
+    samples: &[u8],
+    (width, height): (u32, u32),
+    color_space: mozjpeg_sys::J_COLOR_SPACE,
+    quality: u8,
+    subsampling: Subsampling,
+) -> Vec<u8> {
 
     ///////////////////////////////////////////////////////////////////////////
     // INIT ENCODER CONTEXT
@@ -61,11 +207,33 @@ const COLOR_SPACE_COMPONENTS: libc::c_int = 3 as libc::c_int;
     cinfo.image_height = height;
     cinfo.input_components = COLOR_SPACE_COMPONENTS;
     let row_stride = cinfo.image_width as usize * cinfo.input_components as usize;
-    cinfo.in_color_space = COLOR_SPACE;
+    cinfo.in_color_space = color_space;
+    // EVERY MOZJPEG EXTENSION, EXPLICITLY RATHER THAN BY ITS BUILD’S DEFAULT
+    // PROFILE (BEFORE THE DEFAULTS, WHICH FOLLOW IT)
+    mozjpeg_sys::jpeg_c_set_int_param(
+        &mut cinfo,
+        mozjpeg_sys::JINT_COMPRESS_PROFILE,
+        mozjpeg_sys::JCP_MAX_COMPRESSION as libc::c_int,
+    );
     mozjpeg_sys::jpeg_set_defaults(&mut cinfo);
     cinfo.dct_method = mozjpeg_sys::J_DCT_METHOD::JDCT_ISLOW;
     cinfo.write_JFIF_header = FALSE;
+    // THE DEFAULTS ARE 4:2:0 (LUMA AT TWICE THE CHROMA RESOLUTION)
+    if subsampling == Subsampling::Yuv444 {
+        let luma = &mut *cinfo.comp_info;
+        luma.h_samp_factor = 1;
+        luma.v_samp_factor = 1;
+    }
     cinfo.optimize_coding = TRUE;
+    for param in [
+        mozjpeg_sys::JBOOLEAN_TRELLIS_QUANT,
+        mozjpeg_sys::JBOOLEAN_TRELLIS_QUANT_DC,
+        mozjpeg_sys::JBOOLEAN_OPTIMIZE_SCANS,
+    ] {
+        mozjpeg_sys::jpeg_c_set_bool_param(&mut cinfo, param, TRUE);
+    }
+    mozjpeg_sys::jpeg_c_set_int_param(&mut cinfo, mozjpeg_sys::JINT_BASE_QUANT_TBL_IDX, BASE_QUANT_TABLE);
+    // THE SCAN SCRIPT IS SEARCHED FOR (WITH `OPTIMIZE_SCANS`)
     mozjpeg_sys::jpeg_simple_progression(&mut cinfo);
     mozjpeg_sys::jpeg_c_set_bool_param(
         &mut cinfo,
@@ -85,7 +253,7 @@ const COLOR_SPACE_COMPONENTS: libc::c_int = 3 as libc::c_int;
     mozjpeg_sys::jpeg_start_compress(&mut cinfo, TRUE);
     while cinfo.next_scanline < cinfo.image_height {
         let offset = cinfo.next_scanline as usize * row_stride;
-        let jsamparray = [rgb_source[offset..].as_ptr()];
+        let jsamparray = [samples[offset..].as_ptr()];
         mozjpeg_sys::jpeg_write_scanlines(&mut cinfo, jsamparray.as_ptr(), 1);
     }
     mozjpeg_sys::jpeg_finish_compress(&mut cinfo);
@@ -112,6 +280,238 @@ const COLOR_SPACE_COMPONENTS: libc::c_int = 3 as libc::c_int;
     output_data
 }
 
+/// The libjpeg destination of `encode_streaming`; `mgr` is first, so
+/// `cinfo.dest` is a pointer to the whole.
+#[cfg(not(feature = "pure-rust"))]
+#[repr(C)]
+struct SinkDestination<'a> {
+    mgr: mozjpeg_sys::jpeg_destination_mgr,
+    buffer: Vec<u8>,
+    sink: &'a mut dyn std::io::Write,
+    /// Of the sink, reported once compression is done (libjpeg can’t).
+    error: Option<std::io::Error>,
+}
+
+#[cfg(not(feature = "pure-rust"))]
+unsafe extern "C" fn init_destination(cinfo: &mut mozjpeg_sys::jpeg_compress_struct) {
+    let dest = &mut *(cinfo.dest as *mut SinkDestination);
+    dest.mgr.next_output_byte = dest.buffer.as_mut_ptr();
+    dest.mgr.free_in_buffer = dest.buffer.len();
+}
+
+#[cfg(not(feature = "pure-rust"))]
+unsafe extern "C" fn empty_output_buffer(cinfo: &mut mozjpeg_sys::jpeg_compress_struct) -> mozjpeg_sys::boolean {
+    let dest = &mut *(cinfo.dest as *mut SinkDestination);
+    if dest.error.is_none() {
+        dest.error = dest.sink.write_all(&dest.buffer).err();
+    }
+    init_destination(cinfo);
+    TRUE
+}
+
+#[cfg(not(feature = "pure-rust"))]
+unsafe extern "C" fn term_destination(cinfo: &mut mozjpeg_sys::jpeg_compress_struct) {
+    let dest = &mut *(cinfo.dest as *mut SinkDestination);
+    let written = dest.buffer.len() - dest.mgr.free_in_buffer;
+    if dest.error.is_none() {
+        dest.error = dest.sink.write_all(&dest.buffer[..written]).err();
+    }
+}
+
+/// The largest width or height libjpeg encodes (jmorecfg.h).
+#[cfg(not(feature = "pure-rust"))]
+const JPEG_MAX_DIMENSION: u32 = 65500;
+
+/// Encodes rows as they’re read, so memory stays bounded (see
+/// `codec::stream`): baseline, since progressive and optimized coding
+/// buffer the whole image.
+#[cfg(not(feature = "pure-rust"))]
+pub fn encode_streaming(
+    mut source: impl crate::codec::stream::RowProvider,
+    quality: u8,
+    mut sink: impl std::io::Write,
+) -> std::io::Result<()> {
+    let (width, height) = source.dimensions();
+    if width == 0 || height == 0 || width > JPEG_MAX_DIMENSION || height > JPEG_MAX_DIMENSION {
+        let message = format!("{}x{} is beyond JPEG’s dimensions", width, height);
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, message));
+    }
+    let mut row = vec![0u8; width as usize * 3];
+    unsafe {
+        let mut err = std::mem::zeroed();
+        let mut cinfo: mozjpeg_sys::jpeg_compress_struct = std::mem::zeroed();
+        cinfo.common.err = mozjpeg_sys::jpeg_std_error(&mut err);
+        mozjpeg_sys::jpeg_create_compress(&mut cinfo);
+        let mut dest = SinkDestination {
+            mgr: std::mem::zeroed(),
+            buffer: vec![0; 64 * 1024],
+            sink: &mut sink,
+            error: None,
+        };
+        dest.mgr.init_destination = Some(init_destination);
+        dest.mgr.empty_output_buffer = Some(empty_output_buffer);
+        dest.mgr.term_destination = Some(term_destination);
+        cinfo.dest = &mut dest.mgr;
+        cinfo.image_width = width;
+        cinfo.image_height = height;
+        cinfo.input_components = COLOR_SPACE_COMPONENTS;
+        cinfo.in_color_space = COLOR_SPACE;
+        // LIBJPEG-TURBO’S DEFAULTS: SINGLE PASS
+        mozjpeg_sys::jpeg_c_set_int_param(
+            &mut cinfo,
+            mozjpeg_sys::J_INT_PARAM::JINT_COMPRESS_PROFILE,
+            mozjpeg_sys::JINT_COMPRESS_PROFILE_VALUE::JCP_FASTEST as c_int,
+        );
+        mozjpeg_sys::jpeg_set_defaults(&mut cinfo);
+        cinfo.dct_method = mozjpeg_sys::J_DCT_METHOD::JDCT_ISLOW;
+        cinfo.optimize_coding = FALSE;
+        mozjpeg_sys::jpeg_set_quality(&mut cinfo, i32::from(quality), TRUE);
+        mozjpeg_sys::jpeg_start_compress(&mut cinfo, TRUE);
+        let mut result = Ok(());
+        while cinfo.next_scanline < cinfo.image_height {
+            if let Err(error) = source.read_rows(&mut row) {
+                result = Err(error);
+                break;
+            }
+            let jsamparray = [row.as_ptr()];
+            mozjpeg_sys::jpeg_write_scanlines(&mut cinfo, jsamparray.as_ptr(), 1);
+        }
+        if result.is_ok() {
+            mozjpeg_sys::jpeg_finish_compress(&mut cinfo);
+        }
+        mozjpeg_sys::jpeg_destroy_compress(&mut cinfo);
+        match dest.error {
+            Some(error) => Err(error),
+            None => result,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// MOZJPEG DECODER
+///////////////////////////////////////////////////////////////////////////////
+
+/// Picks the largest libjpeg DCT scale factor (1/1, 1/2, 1/4 or 1/8) that
+/// still yields an image at least as large as `max_size` (when fit within it).
+#[must_use] pub fn dct_scale_denom(source: (u32, u32), max_size: &Resolution) -> u32 {
+    let (width, height) = source;
+    let ratio = f64::min(
+        f64::from(max_size.width) / f64::from(width),
+        f64::from(max_size.height) / f64::from(height),
+    );
+    [8, 4, 2]
+        .iter()
+        .copied()
+        .find(|denom| 1.0 / f64::from(*denom) >= ratio)
+        .unwrap_or(1)
+}
+
+/// Decodes a JPEG using libjpeg’s DCT scaling, so that large sources are
+/// never fully materialized when the target is a fraction of their size.
+///
+/// The result is at least as large as `max_size` (when fit within it), so
+/// callers still resize to the exact target afterwards. Corrupt or
+/// truncated sources are rejected, see `decode_tolerant`.
+#[cfg(not(feature = "pure-rust"))]
+pub fn decode_scaled(source: &[u8], max_size: &Resolution) -> Result<DynamicImage, ImagerError> {
+    decode_with_libjpeg(source, Some(max_size), false)
+}
+
+/// Decodes a JPEG with libjpeg(-turbo), which recovers from many kinds of
+/// damage the `image` crate gives up on.
+///
+/// With `tolerate_truncated`, truncated or corrupt entropy coded data is
+/// accepted (missing rows are filled with gray), otherwise libjpeg warnings
+/// are treated as errors.
+#[cfg(not(feature = "pure-rust"))]
+pub fn decode_tolerant(
+    source: &[u8],
+    max_size: Option<&Resolution>,
+    tolerate_truncated: bool,
+) -> Result<DynamicImage, ImagerError> {
+    decode_with_libjpeg(source, max_size, tolerate_truncated)
+}
+
+/// CMYK and YCCK sources are decoded with the `image` crate, since libjpeg
+/// only converts them to CMYK. Damaged entropy data only produces libjpeg
+/// warnings.
+#[cfg(not(feature = "pure-rust"))]
+fn decode_with_libjpeg(
+    source: &[u8],
+    max_size: Option<&Resolution>,
+    allow_warnings: bool,
+) -> Result<DynamicImage, ImagerError> {
+    match unsafe { decode_with_scale(source, max_size, allow_warnings) } {
+        Ok(Some(image)) => Ok(image),
+        Ok(None) => Ok(::image::load_from_memory_with_format(source, ::image::ImageFormat::Jpeg)?),
+        Err(message) => Err(ImagerError::decode(format!("libjpeg: {}", message))),
+    }
+}
+
+/// None of CMYK and YCCK sources.
+#[cfg(not(feature = "pure-rust"))]
+unsafe fn decode_with_scale(
+    source: &[u8],
+    max_size: Option<&Resolution>,
+    allow_warnings: bool,
+) -> Result<Option<DynamicImage>, String> {
+    ///////////////////////////////////////////////////////////////////////////
+    // INIT DECODER CONTEXT
+    ///////////////////////////////////////////////////////////////////////////
+    let mut err: mozjpeg_sys::jpeg_error_mgr = std::mem::zeroed();
+    let mut dinfo: mozjpeg_sys::jpeg_decompress_struct = std::mem::zeroed();
+    dinfo.common.err = error_mgr(&mut err);
+    mozjpeg_sys::jpeg_create_decompress(&mut dinfo);
+    mozjpeg_sys::jpeg_mem_src(&mut dinfo, source.as_ptr(), source.len() as libc::c_ulong);
+    let result = guard(|| {
+        unwinding::jpeg_read_header(&mut dinfo, TRUE);
+        if matches!(dinfo.jpeg_color_space, mozjpeg_sys::JCS_CMYK | mozjpeg_sys::JCS_YCCK) {
+            return None;
+        }
+
+        ///////////////////////////////////////////////////////////////////////
+        // DECODER CONFIG
+        ///////////////////////////////////////////////////////////////////////
+        let dimensions = (dinfo.image_width, dinfo.image_height);
+        dinfo.out_color_space = COLOR_SPACE;
+        dinfo.scale_num = 1;
+        dinfo.scale_denom = max_size.map_or(1, |max_size| dct_scale_denom(dimensions, max_size));
+        dinfo.dct_method = mozjpeg_sys::J_DCT_METHOD::JDCT_ISLOW;
+
+        ///////////////////////////////////////////////////////////////////////
+        // GO!
+        ///////////////////////////////////////////////////////////////////////
+        unwinding::jpeg_start_decompress(&mut dinfo);
+        let (width, height) = (dinfo.output_width, dinfo.output_height);
+        let row_stride = width as usize * dinfo.output_components as usize;
+        let mut output = vec![0u8; row_stride * height as usize];
+        while dinfo.output_scanline < dinfo.output_height {
+            let offset = dinfo.output_scanline as usize * row_stride;
+            let mut jsamparray = [output[offset..].as_mut_ptr()];
+            unwinding::jpeg_read_scanlines(&mut dinfo, jsamparray.as_mut_ptr(), 1);
+        }
+        unwinding::jpeg_finish_decompress(&mut dinfo);
+        Some((width, height, output))
+    });
+    mozjpeg_sys::jpeg_destroy_decompress(&mut dinfo);
+
+    ///////////////////////////////////////////////////////////////////////////
+    // CHECKS
+    ///////////////////////////////////////////////////////////////////////////
+    let Some((width, height, output)) = result? else {
+        return Ok(None);
+    };
+    if err.num_warnings > 0 && !allow_warnings {
+        return Err(String::from("corrupt or truncated data"));
+    }
+
+    ///////////////////////////////////////////////////////////////////////////
+    // DONE
+    ///////////////////////////////////////////////////////////////////////////
+    let output = ::image::RgbImage::from_raw(width, height, output).ok_or("decoded pixels of the wrong size")?;
+    Ok(Some(DynamicImage::ImageRgb8(output)))
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 // OPT
 ///////////////////////////////////////////////////////////////////////////////
@@ -127,11 +527,63 @@ pub struct OptReport {
 
 pub struct OptContext {
     source: DynamicImage,
+    #[cfg(not(feature = "pure-rust"))]
     vmaf_source: VideoBuffer,
     class_report: classifier::Report,
     extreme_mode: bool,
+    quality_range: QualityRange,
+    /// In place of the class (and size) dependent threshold.
+    vmaf_target: Option<f64>,
+    /// Bracket the search by one on a proxy (see `proxy`).
+    fast_search: bool,
+    /// Of the source, if scored on a sample of them (see `tiles`).
+    #[cfg(not(feature = "pure-rust"))]
+    tiles: Option<Tiles>,
+    /// The text mask, and the samples it corrected.
+    text: Option<(TextMask, Vec<u8>)>,
+    subsampling: Subsampling,
+}
+
+impl OptContext {
+    /// Bounds the search; by default `Tuning::default().jpeg`.
+    pub fn quality_range(&mut self, range: QualityRange) {
+        self.quality_range = range;
+    }
+    /// The VMAF score the search must reach; by default, it depends on the
+    /// class (and size) of the source.
+    pub fn vmaf_target(&mut self, target: f64) {
+        self.vmaf_target = Some(target);
+    }
+    /// Starts the search near the quality a search on a low-resolution
+    /// proxy found (see `proxy`), for fewer full-size encodes; a no-op in
+    /// `pure-rust` builds, without a search.
+    pub fn fast_search(&mut self) {
+        self.fast_search = true;
+    }
+    /// By default 4:2:0; a no-op in `pure-rust` builds, whose encoder only
+    /// subsamples 4:2:0.
+    pub fn subsampling(&mut self, subsampling: Subsampling) {
+        self.subsampling = subsampling;
+    }
+    /// Protects the source’s text blocks, if any (see `text_protect`); a
+    /// no-op in `pure-rust` builds, whose encoder only takes RGB.
+    pub fn text_protect(&mut self) {
+        if cfg!(feature = "pure-rust") {
+            return;
+        }
+        let mask = TextMask::detect(&self.source);
+        if !mask.is_empty() {
+            let ycbcr = text_protect::ycbcr(&self.source, &mask);
+            self.text = Some((mask, ycbcr));
+        }
+    }
+    /// The share of text blocks, as protected.
+    pub fn text_coverage(&self) -> Option<f64> {
+        self.text.as_ref().map(|(mask, _)| mask.coverage())
+    }
 }
 
+#[cfg(not(feature = "pure-rust"))]
 impl OptContext {
     #[must_use] pub fn from_image(source: DynamicImage) -> Self {
         Self {
@@ -139,50 +591,69 @@ impl OptContext {
             class_report: classifier::report(&source),
             source,
             extreme_mode: false,
+            quality_range: Tuning::default().jpeg,
+            vmaf_target: None,
+            fast_search: false,
+            tiles: None,
+            text: None,
+            subsampling: Subsampling::default(),
         }
     }
-    fn terminate(&self, score: f64) -> bool {
-        let mut threshold;
+    /// Scores very large sources on a sample of their tiles, the `coverage`
+    /// (see `tiles`).
+    pub fn metric_coverage(&mut self, coverage: f64) {
+        self.tiles = Tiles::sample(&self.source, coverage);
+        if let Some(tiles) = &self.tiles {
+            self.vmaf_source = VideoBuffer::from_image(&tiles.mosaic(&self.source)).expect("to VideoBuffer");
+        }
+    }
+    /// The VMAF score outputs must reach.
+    fn threshold(&self) -> f64 {
+        if let Some(target) = self.vmaf_target {
+            return target;
+        }
         let (width, height) = self.source.dimensions();
         let is_small = { (width * height) <= (500 * 500) };
         let is_big = { (width * height) >= (1200 * 1000) };
         match self.class_report.class {
             Class::L0 if self.class_report.white_backdrop => {
-                threshold = 96.0;
+                96.0
             }
             Class::L1 if self.class_report.white_backdrop => {
-                threshold = 94.0;
+                94.0
             }
             Class::L2 if self.class_report.white_backdrop => {
-                threshold = 93.0;
+                93.0
             }
             Class::L0 | Class::L1 | Class::L2 if self.extreme_mode && is_big => {
-                threshold = 95.0;
+                95.0
             }
             Class::L0 => {
-                threshold = 99.0;
+                99.0
             }
             Class::L1 => {
-                threshold = 98.0;
+                98.0
             }
             Class::L2 => {
-                threshold = 96.0;
+                96.0
             }
             Class::M1 => {
-                threshold = 92.0;
+                92.0
             }
             Class::H1 if !is_small => {
-                threshold = 84.0;
+                84.0
             }
             Class::H2 if !is_small => {
-                threshold = 76.0;
+                76.0
             }
             Class::H1 | Class::H2 => {
                 assert!(is_small);
-                threshold = 88.0;
+                88.0
             }
         }
-        score >= threshold
+    }
+    fn terminate(&self, score: f64) -> bool {
+        score >= self.threshold()
     }
     fn find_starting_position(&self) -> Option<u8> {
         let reduce_starting_values = |qs: Vec<u8>| -> Option<u8> {
@@ -210,14 +681,63 @@ impl OptContext {
             _ => bad_fallback(),
         }
     }
+    /// The bottom of the bracket a search on a proxy found, if fast (and the
+    /// source large enough to have one); the proxy is held to the source’s
+    /// threshold, rather than that of its own size.
+    fn proxy_start(&self) -> Option<u8> {
+        if !self.fast_search {
+            return None;
+        }
+        let source = proxy::proxy(&self.source)?;
+        let mut context = OptContext {
+            vmaf_source: VideoBuffer::from_image(&source).expect("to VideoBuffer"),
+            class_report: self.class_report.clone(),
+            source,
+            extreme_mode: self.extreme_mode,
+            quality_range: self.quality_range,
+            vmaf_target: Some(self.threshold()),
+            fast_search: false,
+            tiles: None,
+            text: None,
+            subsampling: self.subsampling,
+        };
+        let (_, report) = context.run_search(self.extreme_mode);
+        report.passed.then(|| proxy::bracket(self.quality_range, report.end_q).min)
+    }
+    fn encode(&self, q: u8) -> Vec<u8> {
+        match &self.text {
+            Some((_, ycbcr)) => unsafe {
+                encode_ycbcr(ycbcr, self.source.dimensions(), q, self.subsampling)
+            },
+            None => unsafe { encode_subsampled(&self.source, q, self.subsampling) },
+        }
+    }
+    /// Whether the text blocks (if protected) pass `TEXT_PSNR`.
+    fn text_passes(&self, compressed: &[u8]) -> bool {
+        let Some((mask, ycbcr)) = &self.text else {
+            return true;
+        };
+        let Ok(decoded) = ::image::load_from_memory_with_format(compressed, ::image::ImageFormat::Jpeg) else {
+            return false;
+        };
+        let psnr = text_protect::text_psnr(ycbcr, &decoded.to_rgb8(), mask);
+        psnr.is_none_or(|x| x >= text_protect::TEXT_PSNR)
+    }
     fn run_instance(&self, q: u8) -> (Vec<u8>, bool, f64) {
-        let compressed = unsafe { encode(&self.source, q) };
+        let compressed = self.encode(q);
         // TODO - CLEANUP
         let report: f64 = {
-            let vmaf_derivative = VideoBuffer::from_jpeg(&compressed).expect("load jpeg image");
+            let vmaf_derivative = match &self.tiles {
+                Some(tiles) => {
+                    let decoded = ::image::load_from_memory_with_format(&compressed, ::image::ImageFormat::Jpeg)
+                        .expect("load jpeg image");
+                    VideoBuffer::from_image(&tiles.mosaic(&decoded)).expect("to VideoBuffer")
+                }
+                None => VideoBuffer::from_jpeg(&compressed).expect("load jpeg image"),
+            };
             vmaf::get_report(&self.vmaf_source, &vmaf_derivative)
         };
-        if self.terminate(report) {
+        if self.terminate(report) && self.text_passes(&compressed) {
             (compressed, true, report)
         } else {
             (compressed, false, report)
@@ -226,8 +746,12 @@ impl OptContext {
     pub fn run_search(&mut self, extreme_mode: bool) -> (Vec<u8>, OptReport) {
         self.extreme_mode = extreme_mode;
         let mut passed_output: Option<(Vec<u8>, OptReport)> = None;
-        let starting_q = self.find_starting_position().unwrap_or(0);
-        for q in starting_q..=98 {
+        let range = self.quality_range;
+        let starting_q = match self.proxy_start() {
+            Some(q) => q,
+            None => range.clamp(self.find_starting_position().unwrap_or(0)),
+        };
+        for q in starting_q..=range.max {
             let (compressed, done, score) = self.run_instance(q);
             if done {
                 let out_meta = OptReport {
@@ -244,8 +768,8 @@ impl OptContext {
         match passed_output {
             // BAD
             None => {
-                let fallback_q = 98;
-                let payload = unsafe { encode(&self.source, fallback_q) };
+                let fallback_q = range.max;
+                let payload = self.encode(fallback_q);
                 let out_meta = OptReport {
                     start_q: starting_q,
                     end_q: fallback_q,
@@ -259,8 +783,8 @@ impl OptContext {
             Some((payload, meta)) => {
                 // BAD
                 if meta.start_q == 0 && meta.end_q == 0 {
-                    let fallback_q = 75;
-                    let payload = unsafe { encode(&self.source, fallback_q) };
+                    let fallback_q = range.clamp(75);
+                    let payload = self.encode(fallback_q);
                     let out_meta = OptReport {
                         start_q: starting_q,
                         end_q: fallback_q,
@@ -279,6 +803,85 @@ impl OptContext {
     }
 }
 
+///////////////////////////////////////////////////////////////////////////////
+// PURE RUST
+///////////////////////////////////////////////////////////////////////////////
+
+/// The quality of `pure-rust` builds, which can’t search for one (without
+/// libvmaf).
+#[cfg(feature = "pure-rust")]
+pub const PURE_RUST_QUALITY: u8 = 80;
+
+/// The `image` crate’s (baseline) encoder.
+#[cfg(feature = "pure-rust")]
+#[must_use] pub fn encode_in_rust(source: &DynamicImage, quality: u8) -> Vec<u8> {
+    let mut output = Vec::new();
+    ::image::codecs::jpeg::JpegEncoder::new_with_quality(&mut output, quality)
+        .encode_image(&DynamicImage::ImageRgb8(source.to_rgb8()))
+        .expect("encode jpeg");
+    output
+}
+
+/// Decodes a JPEG using `jpeg-decoder`’s DCT scaling (see the libjpeg
+/// version).
+#[cfg(feature = "pure-rust")]
+pub fn decode_scaled(source: &[u8], max_size: &Resolution) -> Result<DynamicImage, ImagerError> {
+    let mut decoder = jpeg_decoder::Decoder::new(std::io::Cursor::new(source));
+    decoder.read_info().map_err(ImagerError::decode)?;
+    let info = decoder.info().expect("read info");
+    let denom = dct_scale_denom((u32::from(info.width), u32::from(info.height)), max_size);
+    let requested = |x: u16| (u32::from(x).div_ceil(denom)) as u16;
+    decoder
+        .scale(requested(info.width), requested(info.height))
+        .map_err(ImagerError::decode)?;
+    let pixels = decoder.decode().map_err(ImagerError::decode)?;
+    let info = decoder.info().expect("read info");
+    let (width, height) = (u32::from(info.width), u32::from(info.height));
+    match info.pixel_format {
+        jpeg_decoder::PixelFormat::RGB24 => ::image::RgbImage::from_raw(width, height, pixels)
+            .map(DynamicImage::ImageRgb8)
+            .ok_or_else(|| ImagerError::decode("JPEG pixels of the wrong size")),
+        jpeg_decoder::PixelFormat::L8 => ::image::GrayImage::from_raw(width, height, pixels)
+            .map(DynamicImage::ImageLuma8)
+            .ok_or_else(|| ImagerError::decode("JPEG pixels of the wrong size")),
+        // CMYK AND 16-BIT SOURCES
+        _ => Ok(::image::load_from_memory_with_format(source, ::image::ImageFormat::Jpeg)?),
+    }
+}
+
+#[cfg(feature = "pure-rust")]
+impl OptContext {
+    #[must_use] pub fn from_image(source: DynamicImage) -> Self {
+        Self {
+            class_report: classifier::report(&source),
+            source,
+            extreme_mode: false,
+            quality_range: Tuning::default().jpeg,
+            vmaf_target: None,
+            fast_search: false,
+            text: None,
+            subsampling: Subsampling::default(),
+        }
+    }
+    /// A no-op, without a search to score for.
+    pub fn metric_coverage(&mut self, _: f64) {}
+    /// Encodes at `PURE_RUST_QUALITY` (within the quality range), the whole
+    /// “search” of `pure-rust` builds.
+    pub fn run_search(&mut self, extreme_mode: bool) -> (Vec<u8>, OptReport) {
+        self.extreme_mode = extreme_mode;
+        let quality = self.quality_range.clamp(PURE_RUST_QUALITY);
+        let payload = encode_in_rust(&self.source, quality);
+        let out_meta = OptReport {
+            start_q: quality,
+            end_q: quality,
+            passed: false,
+            class: self.class_report.class.clone(),
+            vmaf_score: None,
+        };
+        (payload, out_meta)
+    }
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 // DEV
 ///////////////////////////////////////////////////////////////////////////////
@@ -290,3 +893,81 @@ pub fn run() {
     println!("results: {:#?}", report);
     std::fs::write("assets/output/test.jpeg", encoded);
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_scaled() {
+        let source = include_bytes!("../../assets/test/1.jpeg");
+        let full = ::image::load_from_memory(source).expect("decode test image");
+        let (width, height) = full.dimensions();
+        let max_size = Resolution::new(width / 5, height / 5);
+        assert_eq!(dct_scale_denom((width, height), &max_size), 4);
+        let scaled = decode_scaled(source, &max_size).expect("decode scaled");
+        assert_eq!(scaled.dimensions(), (width.div_ceil(4), height.div_ceil(4)));
+    }
+
+    #[cfg(not(feature = "pure-rust"))]
+    #[test]
+    fn test_decode_errors() {
+        // (ADOBE) YCCK, WHICH LIBJPEG WOULD FAIL TO CONVERT TO RGB
+        let source = include_bytes!("../../assets/test/cmyk.jpeg");
+        let output = decode_tolerant(source, None, false).expect("decode cmyk");
+        assert_eq!(output.dimensions(), (48, 32));
+        assert!(decode_scaled(source, &Resolution::new(16, 16)).is_ok());
+        // FATAL LIBJPEG ERRORS, WHICH WOULD EXIT THE PROCESS
+        let error = decode_tolerant(b"not a jpeg", None, true).expect_err("not a jpeg");
+        assert!(error.to_string().contains("Not a JPEG file"), "{}", error);
+        let header = &include_bytes!("../../assets/test/1.jpeg")[..100];
+        assert!(decode_tolerant(header, None, true).is_err());
+    }
+
+    #[cfg(not(feature = "pure-rust"))]
+    #[test]
+    fn test_encode() {
+        let source = ::image::load_from_memory(include_bytes!("../../assets/test/1.jpeg"))
+            .expect("decode test image")
+            .thumbnail(320, 320);
+        let output = unsafe { encode(&source, 80) };
+        // PROGRESSIVE (SOF2)
+        assert!(output.windows(2).any(|x| x == [0xFF, 0xC2]));
+        let mut baseline = Vec::new();
+        ::image::codecs::jpeg::JpegEncoder::new_with_quality(&mut baseline, 80)
+            .encode_image(&DynamicImage::ImageRgb8(source.to_rgb8()))
+            .expect("encode jpeg");
+        assert!(output.len() < baseline.len(), "{} {}", output.len(), baseline.len());
+        // THE LUMA’S SAMPLING FACTORS, IN THE SOF
+        let luma_sampling = |jpeg: &[u8]| {
+            let sof = jpeg.windows(2).position(|x| x == [0xFF, 0xC2]).expect("sof");
+            jpeg[sof + 11]
+        };
+        let full_chroma = unsafe { encode_subsampled(&source, 80, Subsampling::Yuv444) };
+        assert_eq!((luma_sampling(&output), luma_sampling(&full_chroma)), (0x22, 0x11));
+        assert!(full_chroma.len() > output.len());
+    }
+
+    #[cfg(not(feature = "pure-rust"))]
+    #[test]
+    fn test_encode_streaming_dimensions() {
+        use crate::codec::stream::ImageRows;
+        for (width, height) in [(0, 16), (16, 0), (65501, 1)] {
+            let source = ::image::RgbImage::new(width, height);
+            let error = encode_streaming(ImageRows::new(&source), 80, Vec::new()).expect_err("invalid");
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        }
+        let source = ::image::RgbImage::new(65500, 1);
+        let mut output = Vec::new();
+        encode_streaming(ImageRows::new(&source), 80, &mut output).expect("encode");
+        assert_eq!(::image::load_from_memory(&output).expect("decode").dimensions(), (65500, 1));
+    }
+
+    #[cfg(not(feature = "pure-rust"))]
+    #[test]
+    fn test_version() {
+        let version = version().expect("version");
+        let parts = version.split('.').map(str::parse::<u32>).collect::<Result<Vec<_>, _>>();
+        assert!(parts.is_ok_and(|x| x.len() >= 2), "{}", version);
+    }
+}
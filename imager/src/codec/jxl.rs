@@ -0,0 +1,438 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! JPEG XL encoding via libjxl: lossy, lossless, and the lossless
+//! transcoding of JPEG sources, which keeps their DCT coefficients (so the
+//! pixels are unchanged, and the JPEG can be reconstructed bit for bit) at
+//! about a fifth less size, e.g. for archives.
+//!
+//! With the `jxl` feature, libjxl is loaded at runtime, from
+//! `LIBJXL_DYLIB_PATH` or else the platform’s library search path, so
+//! builds don’t need it. Lossy encodes aren’t searched: JPEG XL’s distance
+//! is a perceptual target already, so `DEFAULT_QUALITY` (distance 1, about
+//! visually lossless) suits most sources.
+use image::DynamicImage;
+
+#[cfg(feature = "jxl")]
+use crate::error::ImagerError;
+use crate::error::Result;
+
+/// Of lossy encodes, as cjxl’s quality (see `distance`).
+pub const DEFAULT_QUALITY: u8 = 90;
+
+/// The Butteraugli distance of a (cjxl) quality from 0 to 100, as
+/// `JxlEncoderDistanceFromQuality`; 100 is lossless.
+pub fn distance(quality: u8) -> f32 {
+    let quality = f32::from(quality.min(100));
+    if quality >= 100.0 {
+        0.0
+    } else if quality >= 30.0 {
+        0.1 + (100.0 - quality) * 0.09
+    } else {
+        53.0 / 3000.0 * quality * quality - 23.0 / 20.0 * quality + 25.0
+    }
+}
+
+#[cfg(feature = "jxl")]
+mod ffi {
+    use libloading::{Library, Symbol};
+    use std::ffi::c_void;
+    use std::os::raw::c_int;
+
+    #[cfg(target_os = "windows")]
+    const DEFAULT_LIBRARY: &str = "jxl.dll";
+    #[cfg(target_os = "macos")]
+    const DEFAULT_LIBRARY: &str = "libjxl.dylib";
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    const DEFAULT_LIBRARY: &str = "libjxl.so";
+
+    // ENUM VALUES (jxl/encode.h, jxl/decode.h, jxl/types.h)
+    const ENC_SUCCESS: c_int = 0;
+    const ENC_NEED_MORE_OUTPUT: c_int = 2;
+    const FRAME_SETTING_EFFORT: c_int = 0;
+    const DEC_SUCCESS: c_int = 0;
+    const DEC_NEED_IMAGE_OUT_BUFFER: c_int = 5;
+    const DEC_BASIC_INFO: c_int = 0x40;
+    const DEC_FULL_IMAGE: c_int = 0x1000;
+    const TYPE_UINT8: c_int = 2;
+    const ENDIAN_NATIVE: c_int = 0;
+    const TRUE: c_int = 1;
+
+    /// cjxl’s default.
+    const EFFORT: i64 = 7;
+
+    type Handle = *mut c_void;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct BasicInfo {
+        have_container: c_int,
+        xsize: u32,
+        ysize: u32,
+        bits_per_sample: u32,
+        exponent_bits_per_sample: u32,
+        intensity_target: f32,
+        min_nits: f32,
+        relative_to_max_display: c_int,
+        linear_below: f32,
+        uses_original_profile: c_int,
+        have_preview: c_int,
+        have_animation: c_int,
+        orientation: c_int,
+        num_color_channels: u32,
+        num_extra_channels: u32,
+        alpha_bits: u32,
+        alpha_exponent_bits: u32,
+        alpha_premultiplied: c_int,
+        preview: [u32; 2],
+        animation: [u32; 4],
+        intrinsic_xsize: u32,
+        intrinsic_ysize: u32,
+        padding: Padding,
+    }
+
+    #[repr(C)]
+    struct Padding([u8; 100]);
+
+    impl Default for Padding {
+        fn default() -> Self {
+            Padding([0; 100])
+        }
+    }
+
+    #[repr(C)]
+    struct PixelFormat {
+        num_channels: u32,
+        data_type: c_int,
+        endianness: c_int,
+        align: usize,
+    }
+
+    /// A `JxlColorEncoding`, only ever filled by libjxl.
+    #[repr(C, align(8))]
+    struct ColorEncoding([u8; 256]);
+
+    fn open() -> Result<Library, String> {
+        let path = std::env::var_os("LIBJXL_DYLIB_PATH").unwrap_or_else(|| DEFAULT_LIBRARY.into());
+        Library::new(&path).map_err(|e| format!("failed to load libjxl ({:?}): {}", path, e))
+    }
+
+    macro_rules! symbol {
+        ($library:ident, $name:literal, $type:ty) => {{
+            let symbol: Symbol<$type> = $library.get($name).map_err(|e| e.to_string())?;
+            symbol
+        }};
+    }
+
+    /// What to add as the (only) frame.
+    pub enum Frame<'a> {
+        /// 8 bit RGB(A) pixels; lossless, or at a distance.
+        Pixels {
+            image: &'a image::DynamicImage,
+            distance: f32,
+        },
+        /// A JPEG’s coefficients, with its reconstruction data.
+        Jpeg(&'a [u8]),
+    }
+
+    pub fn version() -> Result<String, String> {
+        let library = open()?;
+        unsafe {
+            let version = symbol!(library, b"JxlEncoderVersion\0", unsafe extern "C" fn() -> u32);
+            let version = version();
+            Ok(format!("{}.{}.{}", version / 1_000_000, version / 1000 % 1000, version % 1000))
+        }
+    }
+
+    pub fn encode(frame: Frame<'_>) -> Result<Vec<u8>, String> {
+        let library = open()?;
+        unsafe {
+            let create = symbol!(library, b"JxlEncoderCreate\0", unsafe extern "C" fn(*const c_void) -> Handle);
+            let destroy = symbol!(library, b"JxlEncoderDestroy\0", unsafe extern "C" fn(Handle));
+            let get_error = symbol!(library, b"JxlEncoderGetError\0", unsafe extern "C" fn(Handle) -> c_int);
+            let settings_create = symbol!(
+                library,
+                b"JxlEncoderFrameSettingsCreate\0",
+                unsafe extern "C" fn(Handle, Handle) -> Handle
+            );
+            let set_option = symbol!(
+                library,
+                b"JxlEncoderFrameSettingsSetOption\0",
+                unsafe extern "C" fn(Handle, c_int, i64) -> c_int
+            );
+            let close_input = symbol!(library, b"JxlEncoderCloseInput\0", unsafe extern "C" fn(Handle));
+            let process_output = symbol!(
+                library,
+                b"JxlEncoderProcessOutput\0",
+                unsafe extern "C" fn(Handle, *mut *mut u8, *mut usize) -> c_int
+            );
+            let encoder = create(std::ptr::null());
+            if encoder.is_null() {
+                return Err(String::from("failed to create a libjxl encoder"));
+            }
+            let result = (|| {
+                let check = |status: c_int, what: &str| match status {
+                    ENC_SUCCESS => Ok(()),
+                    _ => Err(format!("libjxl failed to {} (error {})", what, get_error(encoder))),
+                };
+                let settings = settings_create(encoder, std::ptr::null_mut());
+                check(set_option(settings, FRAME_SETTING_EFFORT, EFFORT), "set the effort")?;
+                match frame {
+                    Frame::Pixels { image, distance } => {
+                        let init_basic_info = symbol!(
+                            library,
+                            b"JxlEncoderInitBasicInfo\0",
+                            unsafe extern "C" fn(*mut BasicInfo)
+                        );
+                        let set_basic_info = symbol!(
+                            library,
+                            b"JxlEncoderSetBasicInfo\0",
+                            unsafe extern "C" fn(Handle, *const BasicInfo) -> c_int
+                        );
+                        let srgb = symbol!(
+                            library,
+                            b"JxlColorEncodingSetToSRGB\0",
+                            unsafe extern "C" fn(*mut ColorEncoding, c_int)
+                        );
+                        let set_color_encoding = symbol!(
+                            library,
+                            b"JxlEncoderSetColorEncoding\0",
+                            unsafe extern "C" fn(Handle, *const ColorEncoding) -> c_int
+                        );
+                        let set_lossless = symbol!(
+                            library,
+                            b"JxlEncoderSetFrameLossless\0",
+                            unsafe extern "C" fn(Handle, c_int) -> c_int
+                        );
+                        let set_distance = symbol!(
+                            library,
+                            b"JxlEncoderSetFrameDistance\0",
+                            unsafe extern "C" fn(Handle, f32) -> c_int
+                        );
+                        let add_image_frame = symbol!(
+                            library,
+                            b"JxlEncoderAddImageFrame\0",
+                            unsafe extern "C" fn(Handle, *const PixelFormat, *const c_void, usize) -> c_int
+                        );
+                        let alpha = image.color().has_alpha();
+                        let pixels = if alpha {
+                            image.to_rgba8().into_raw()
+                        } else {
+                            image.to_rgb8().into_raw()
+                        };
+                        let lossless = distance == 0.0;
+                        let mut info = BasicInfo::default();
+                        init_basic_info(&mut info);
+                        info.xsize = image.width();
+                        info.ysize = image.height();
+                        info.bits_per_sample = 8;
+                        info.uses_original_profile = c_int::from(lossless);
+                        if alpha {
+                            info.num_extra_channels = 1;
+                            info.alpha_bits = 8;
+                        }
+                        check(set_basic_info(encoder, &info), "set the basic info")?;
+                        let mut color = ColorEncoding([0; 256]);
+                        srgb(&mut color, 0);
+                        check(set_color_encoding(encoder, &color), "set the color encoding")?;
+                        if lossless {
+                            check(set_lossless(settings, TRUE), "encode losslessly")?;
+                        } else {
+                            check(set_distance(settings, distance), "set the distance")?;
+                        }
+                        let format = PixelFormat {
+                            num_channels: if alpha { 4 } else { 3 },
+                            data_type: TYPE_UINT8,
+                            endianness: ENDIAN_NATIVE,
+                            align: 0,
+                        };
+                        let data = pixels.as_ptr() as *const c_void;
+                        check(add_image_frame(settings, &format, data, pixels.len()), "add the frame")?;
+                    }
+                    Frame::Jpeg(jpeg) => {
+                        let use_container = symbol!(
+                            library,
+                            b"JxlEncoderUseContainer\0",
+                            unsafe extern "C" fn(Handle, c_int) -> c_int
+                        );
+                        let store_jpeg_metadata = symbol!(
+                            library,
+                            b"JxlEncoderStoreJPEGMetadata\0",
+                            unsafe extern "C" fn(Handle, c_int) -> c_int
+                        );
+                        let add_jpeg_frame = symbol!(
+                            library,
+                            b"JxlEncoderAddJPEGFrame\0",
+                            unsafe extern "C" fn(Handle, *const u8, usize) -> c_int
+                        );
+                        check(use_container(encoder, TRUE), "use the container")?;
+                        check(store_jpeg_metadata(encoder, TRUE), "keep the JPEG reconstruction data")?;
+                        check(add_jpeg_frame(settings, jpeg.as_ptr(), jpeg.len()), "add the JPEG")?;
+                    }
+                }
+                close_input(encoder);
+                let mut output = vec![0u8; 64 * 1024];
+                let mut written = 0;
+                loop {
+                    let mut next = output.as_mut_ptr().add(written);
+                    let mut available = output.len() - written;
+                    let status = process_output(encoder, &mut next, &mut available);
+                    written = output.len() - available;
+                    match status {
+                        ENC_NEED_MORE_OUTPUT => output.resize(output.len() * 2, 0),
+                        _ => {
+                            check(status, "encode")?;
+                            output.truncate(written);
+                            return Ok(output);
+                        }
+                    }
+                }
+            })();
+            destroy(encoder);
+            result
+        }
+    }
+
+    /// As 8 bit RGBA, in sRGB.
+    pub fn decode(source: &[u8]) -> Result<image::RgbaImage, String> {
+        let library = open()?;
+        unsafe {
+            let create = symbol!(library, b"JxlDecoderCreate\0", unsafe extern "C" fn(*const c_void) -> Handle);
+            let destroy = symbol!(library, b"JxlDecoderDestroy\0", unsafe extern "C" fn(Handle));
+            let subscribe = symbol!(
+                library,
+                b"JxlDecoderSubscribeEvents\0",
+                unsafe extern "C" fn(Handle, c_int) -> c_int
+            );
+            let set_input = symbol!(
+                library,
+                b"JxlDecoderSetInput\0",
+                unsafe extern "C" fn(Handle, *const u8, usize) -> c_int
+            );
+            let close_input = symbol!(library, b"JxlDecoderCloseInput\0", unsafe extern "C" fn(Handle));
+            let process_input = symbol!(library, b"JxlDecoderProcessInput\0", unsafe extern "C" fn(Handle) -> c_int);
+            let get_basic_info = symbol!(
+                library,
+                b"JxlDecoderGetBasicInfo\0",
+                unsafe extern "C" fn(Handle, *mut BasicInfo) -> c_int
+            );
+            let set_image_out_buffer = symbol!(
+                library,
+                b"JxlDecoderSetImageOutBuffer\0",
+                unsafe extern "C" fn(Handle, *const PixelFormat, *mut c_void, usize) -> c_int
+            );
+            let decoder = create(std::ptr::null());
+            if decoder.is_null() {
+                return Err(String::from("failed to create a libjxl decoder"));
+            }
+            let result = (|| {
+                let format = PixelFormat {
+                    num_channels: 4,
+                    data_type: TYPE_UINT8,
+                    endianness: ENDIAN_NATIVE,
+                    align: 0,
+                };
+                if subscribe(decoder, DEC_BASIC_INFO | DEC_FULL_IMAGE) != DEC_SUCCESS
+                    || set_input(decoder, source.as_ptr(), source.len()) != DEC_SUCCESS
+                {
+                    return Err(String::from("libjxl rejected the decoder setup"));
+                }
+                close_input(decoder);
+                let mut info = BasicInfo::default();
+                let mut pixels = Vec::new();
+                loop {
+                    match process_input(decoder) {
+                        DEC_BASIC_INFO => {
+                            if get_basic_info(decoder, &mut info) != DEC_SUCCESS {
+                                return Err(String::from("libjxl failed to read the basic info"));
+                            }
+                        }
+                        DEC_NEED_IMAGE_OUT_BUFFER => {
+                            pixels = vec![0u8; info.xsize as usize * info.ysize as usize * 4];
+                            let buffer = pixels.as_mut_ptr() as *mut c_void;
+                            if set_image_out_buffer(decoder, &format, buffer, pixels.len()) != DEC_SUCCESS {
+                                return Err(String::from("libjxl rejected the output buffer"));
+                            }
+                        }
+                        DEC_FULL_IMAGE => (),
+                        DEC_SUCCESS => break,
+                        status => return Err(format!("libjxl failed to decode (status {})", status)),
+                    }
+                }
+                image::RgbaImage::from_raw(info.xsize, info.ysize, pixels)
+                    .ok_or_else(|| String::from("libjxl image of an unexpected size"))
+            })();
+            destroy(decoder);
+            result
+        }
+    }
+}
+
+/// At `quality` (0-100, as `distance`; 100 is lossless), with alpha.
+#[cfg(feature = "jxl")]
+pub fn encode(source: &DynamicImage, quality: u8) -> Result<Vec<u8>> {
+    let distance = distance(quality);
+    ffi::encode(ffi::Frame::Pixels { image: source, distance }).map_err(ImagerError::Encode)
+}
+
+#[cfg(not(feature = "jxl"))]
+pub fn encode(_: &DynamicImage, _: u8) -> Result<Vec<u8>> {
+    Err(crate::codec::registry::FeatureDisabled::JXL.into())
+}
+
+/// Losslessly, with the data to reconstruct the exact JPEG, so metadata
+/// segments (e.g. EXIF) are kept as they are.
+#[cfg(feature = "jxl")]
+pub fn transcode_jpeg(source: &[u8]) -> Result<Vec<u8>> {
+    ffi::encode(ffi::Frame::Jpeg(source)).map_err(ImagerError::Encode)
+}
+
+#[cfg(not(feature = "jxl"))]
+pub fn transcode_jpeg(_: &[u8]) -> Result<Vec<u8>> {
+    Err(crate::codec::registry::FeatureDisabled::JXL.into())
+}
+
+/// Of the loaded libjxl’s encoder, e.g. `0.8.2`.
+#[cfg(feature = "jxl")]
+pub fn version() -> Option<String> {
+    ffi::version().ok()
+}
+
+#[cfg(not(feature = "jxl"))]
+pub fn version() -> Option<String> {
+    None
+}
+
+#[cfg(feature = "jxl")]
+pub fn decode(source: &[u8]) -> Result<DynamicImage> {
+    ffi::decode(source)
+        .map(DynamicImage::ImageRgba8)
+        .map_err(ImagerError::Decode)
+}
+
+#[cfg(not(feature = "jxl"))]
+pub fn decode(_: &[u8]) -> Result<DynamicImage> {
+    Err(crate::codec::registry::FeatureDisabled::JXL.into())
+}
+
+/// Whether the file is a JPEG XL codestream, or container.
+pub fn is_jxl(source: &[u8]) -> bool {
+    const CONTAINER: &[u8] = b"\0\0\0\x0CJXL \r\n\x87\n";
+    source.starts_with(&[0xFF, 0x0A]) || source.starts_with(CONTAINER)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_distance() {
+        assert_eq!(distance(100), 0.0);
+        assert!((distance(DEFAULT_QUALITY) - 1.0).abs() < 1e-6);
+        // CONTINUOUS AT 30, AND DECREASING
+        assert!((distance(30) - (53.0 * 900.0 / 3000.0 - 23.0 * 30.0 / 20.0 + 25.0)).abs() < 1e-4);
+        assert!((0..100).all(|q| distance(q) > distance(q + 1)));
+        assert!(is_jxl(&[0xFF, 0x0A, 0xFA]));
+        assert!(!is_jxl(&include_bytes!("../../assets/test/1.jpeg")[..]));
+    }
+}
@@ -1,3 +1,16 @@
+pub mod avif;
+pub mod gif;
+pub mod heif;
 pub mod jpeg;
+pub mod jxl;
+pub mod plugin;
 pub mod png;
+#[cfg(not(feature = "pure-rust"))]
+pub mod proxy;
+pub mod quantize;
+pub mod registry;
+pub mod stream;
+#[cfg(not(feature = "pure-rust"))]
+pub mod tiles;
+pub mod tiff;
 pub mod webp;
@@ -0,0 +1,179 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Encoder plugins: shared libraries, loaded at runtime (`--plugin`, with
+//! the `plugins` feature), implementing the C ABI of
+//! `include/imager_plugin.h`, so deployments that can’t rebuild imager can
+//! slot in other (e.g. proprietary) encoders.
+//!
+//! A plugin exports `imager_encoder_plugin`, which returns its (static)
+//! `ImagerEncoderPlugin` table; the encoder then replaces imager’s own for
+//! its format (see `registry::encoder`). Plugins are never unloaded, and
+//! must be thread safe.
+use image::DynamicImage;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+
+use crate::data::OutputFormat;
+
+/// `IMAGER_PLUGIN_ABI_VERSION`.
+pub const ABI_VERSION: u32 = 1;
+
+/// The exported symbol.
+pub const ENTRY_POINT: &[u8] = b"imager_encoder_plugin\0";
+
+/// `ImagerEncoderPlugin`.
+#[repr(C)]
+pub struct PluginTable {
+    pub abi_version: u32,
+    pub name: *const c_char,
+    pub format: *const c_char,
+    pub encode: unsafe extern "C" fn(
+        rgba: *const u8,
+        width: u32,
+        height: u32,
+        options: *const c_char,
+        output: *mut *mut u8,
+        output_len: *mut usize,
+    ) -> i32,
+    pub free_output: unsafe extern "C" fn(output: *mut u8, output_len: usize),
+}
+
+// PLUGINS MUST BE THREAD SAFE, AND THE TABLE IS IMMUTABLE
+unsafe impl Sync for PluginTable {}
+
+pub struct Plugin {
+    table: &'static PluginTable,
+    pub name: String,
+    pub format: OutputFormat,
+    /// The library, unless built in (e.g. by tests).
+    pub path: Option<PathBuf>,
+}
+
+impl Plugin {
+    /// # Safety
+    ///
+    /// The table’s strings must be valid C strings, and its functions must
+    /// implement the ABI.
+    pub unsafe fn from_table(table: &'static PluginTable, path: Option<PathBuf>) -> Result<Self, String> {
+        if table.abi_version != ABI_VERSION {
+            return Err(format!(
+                "unsupported plugin ABI version {} (expected {})",
+                table.abi_version, ABI_VERSION
+            ));
+        }
+        if table.name.is_null() || table.format.is_null() {
+            return Err(String::from("plugin without a name or format"));
+        }
+        let name = CStr::from_ptr(table.name).to_string_lossy().into_owned();
+        let format = CStr::from_ptr(table.format).to_string_lossy().parse()?;
+        Ok(Plugin {
+            table,
+            name,
+            format,
+            path,
+        })
+    }
+    #[cfg(feature = "plugins")]
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let library = libloading::Library::new(path)
+            .map_err(|e| format!("failed to load plugin {}: {}", path.display(), e))?;
+        let table = unsafe {
+            let entry_point = library
+                .get::<unsafe extern "C" fn() -> *const PluginTable>(ENTRY_POINT)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            entry_point()
+        };
+        if table.is_null() {
+            return Err(format!("{}: no plugin table", path.display()));
+        }
+        // NEVER UNLOADED, SO THE TABLE STAYS MAPPED
+        std::mem::forget(library);
+        unsafe { Plugin::from_table(&*table, Some(path.to_owned())) }
+    }
+    #[cfg(not(feature = "plugins"))]
+    pub fn open(_: &Path) -> Result<Self, String> {
+        Err(crate::codec::registry::FeatureDisabled::PLUGINS.into())
+    }
+    /// Encodes the source (as RGBA); `options` is JSON.
+    pub fn encode(&self, source: &DynamicImage, options: &str) -> Result<Vec<u8>, String> {
+        let rgba = source.to_rgba8();
+        let options = CString::new(options).map_err(|e| e.to_string())?;
+        let mut output: *mut u8 = std::ptr::null_mut();
+        let mut output_len = 0usize;
+        let status = unsafe {
+            (self.table.encode)(
+                rgba.as_ptr(),
+                rgba.width(),
+                rgba.height(),
+                options.as_ptr(),
+                &mut output,
+                &mut output_len,
+            )
+        };
+        if status != 0 {
+            return Err(format!("plugin {} failed with status {}", self.name, status));
+        }
+        if output.is_null() {
+            return Err(format!("plugin {} returned no output", self.name));
+        }
+        let encoded = unsafe { std::slice::from_raw_parts(output, output_len).to_vec() };
+        unsafe { (self.table.free_output)(output, output_len) };
+        Ok(encoded)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // A PNG “PLUGIN”, BUILT IN
+    unsafe extern "C" fn encode(
+        rgba: *const u8,
+        width: u32,
+        height: u32,
+        options: *const c_char,
+        output: *mut *mut u8,
+        output_len: *mut usize,
+    ) -> i32 {
+        let options = CStr::from_ptr(options).to_string_lossy();
+        if !options.contains("\"seed\"") {
+            return 2;
+        }
+        let pixels = std::slice::from_raw_parts(rgba, (width * height * 4) as usize).to_vec();
+        let image = image::RgbaImage::from_raw(width, height, pixels).expect("pixels");
+        let mut encoded = std::io::Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(image)
+            .write_to(&mut encoded, image::ImageFormat::Png)
+            .expect("encode png");
+        let mut encoded = encoded.into_inner().into_boxed_slice();
+        *output_len = encoded.len();
+        *output = encoded.as_mut_ptr();
+        std::mem::forget(encoded);
+        0
+    }
+
+    unsafe extern "C" fn free_output(output: *mut u8, output_len: usize) {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(output, output_len)));
+    }
+
+    static TABLE: PluginTable = PluginTable {
+        abi_version: ABI_VERSION,
+        name: c"test-png".as_ptr(),
+        format: c"png".as_ptr(),
+        encode,
+        free_output,
+    };
+
+    #[test]
+    fn test_plugin_abi() {
+        let plugin = unsafe { Plugin::from_table(&TABLE, None) }.expect("plugin");
+        assert_eq!((plugin.name.as_str(), plugin.format.clone()), ("test-png", OutputFormat::Png));
+        let source = DynamicImage::ImageRgba8(image::RgbaImage::new(3, 2));
+        let encoded = plugin.encode(&source, r#"{"seed": 0}"#).expect("encode");
+        let decoded = image::load_from_memory(&encoded).expect("decode");
+        assert_eq!(decoded.to_rgba8().dimensions(), (3, 2));
+        assert!(plugin.encode(&source, "{}").is_err());
+    }
+}
@@ -0,0 +1,139 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Animated PNGs: their (composited) frames, as for GIFs (see
+//! `codec::gif`), and APNG outputs of frames.
+//!
+//! Still decodes (e.g. of `OptJob`) keep only the default image, which
+//! is the first frame, or a fallback for viewers without APNG support.
+use image::codecs::png::PngDecoder;
+use image::{AnimationDecoder, DynamicImage};
+use std::time::Duration;
+
+use crate::codec::gif::Frame;
+use crate::data::{VideoBuffer, Yuv420P};
+use crate::error::{ImagerError, Result};
+
+/// Of the `acTL` chunk, which must precede the image data; none for still
+/// PNGs.
+pub fn frame_count(source: &[u8]) -> Option<u64> {
+    let mut offset = 8;
+    while let Some(chunk) = source.get(offset..offset + 8) {
+        let len = u32::from_be_bytes(chunk[..4].try_into().ok()?) as usize;
+        match &chunk[4..] {
+            b"acTL" => {
+                let data = source.get(offset + 8..offset + 12)?;
+                return Some(u64::from(u32::from_be_bytes(data.try_into().ok()?)));
+            }
+            b"IDAT" | b"IEND" => return None,
+            _ => offset += 12 + len,
+        }
+    }
+    None
+}
+
+pub fn is_apng(source: &[u8]) -> bool {
+    frame_count(source).is_some()
+}
+
+/// The frames, in order; still PNGs have one.
+pub fn decode_frames(source: &[u8]) -> Result<Vec<Frame>> {
+    let decoder = PngDecoder::new(std::io::Cursor::new(source))?;
+    if !decoder.is_apng() {
+        let image = DynamicImage::from_decoder(decoder)?.into_rgba8();
+        return Ok(vec![Frame {
+            image,
+            delay: Duration::ZERO,
+        }]);
+    }
+    let frames = decoder
+        .apng()
+        .into_frames()
+        .map(|frame| {
+            let frame = frame?;
+            Ok(Frame {
+                delay: Duration::from(frame.delay()),
+                image: frame.into_buffer(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if frames.is_empty() {
+        return Err(ImagerError::decode("the APNG has no frames"));
+    }
+    Ok(frames)
+}
+
+/// The frames as a `VideoBuffer` (without their delays, nor alpha), e.g.
+/// for VMAF.
+pub fn decode(source: &[u8]) -> Result<VideoBuffer> {
+    let frames = decode_frames(source)?
+        .into_iter()
+        .map(|frame| Yuv420P::from_image(&DynamicImage::ImageRgba8(frame.image)))
+        .collect::<Result<Vec<_>>>()?;
+    VideoBuffer::from_frames(frames)
+}
+
+/// Of the frames (of the same size), looping forever, as 8 bit RGBA; see
+/// `png::optimize` for smaller stills.
+pub fn encode(frames: &[Frame]) -> Result<Vec<u8>> {
+    let first = frames
+        .first()
+        .ok_or_else(|| ImagerError::InvalidInput(String::from("no frames")))?;
+    let (width, height) = first.image.dimensions();
+    if frames.iter().any(|x| x.image.dimensions() != (width, height)) {
+        return Err(ImagerError::InvalidInput(String::from("frames differ in size")));
+    }
+    let encoding = |x: ::png::EncodingError| ImagerError::Encode(x.to_string());
+    let mut output = Vec::new();
+    let mut encoder = ::png::Encoder::new(&mut output, width, height);
+    encoder.set_color(::png::ColorType::Rgba);
+    encoder.set_depth(::png::BitDepth::Eight);
+    encoder.set_compression(::png::Compression::Best);
+    encoder.set_animated(frames.len() as u32, 0).map_err(encoding)?;
+    let mut writer = encoder.write_header().map_err(encoding)?;
+    for frame in frames {
+        let (numerator, denominator) = delay_fraction(frame.delay);
+        writer.set_frame_delay(numerator, denominator).map_err(encoding)?;
+        writer.write_image_data(frame.image.as_raw()).map_err(encoding)?;
+    }
+    writer.finish().map_err(encoding)?;
+    Ok(output)
+}
+
+/// In milliseconds, or (beyond what 16 bits hold) in seconds.
+fn delay_fraction(delay: Duration) -> (u16, u16) {
+    match u16::try_from(delay.as_millis()) {
+        Ok(millis) => (millis, 1000),
+        Err(_) => (u16::try_from(delay.as_secs()).unwrap_or(u16::MAX), 1),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let frames = crate::codec::gif::decode_frames(&crate::codec::gif::test::animated_gif()).expect("decode gif");
+        let encoded = encode(&frames).expect("encode");
+        assert_eq!(frame_count(&encoded), Some(3));
+        let decoded = decode_frames(&encoded).expect("decode");
+        assert_eq!(decoded.len(), 3);
+        for (frame, source) in decoded.iter().zip(&frames) {
+            assert_eq!((frame.delay, &frame.image), (source.delay, &source.image));
+        }
+        assert_eq!(decode(&encoded).expect("video buffer").as_frames().len(), 3);
+        // THE DEFAULT IMAGE IS THE FIRST FRAME
+        let still = image::load_from_memory(&encoded).expect("decode still").into_rgba8();
+        assert_eq!(still, frames[0].image);
+        let warnings = crate::api::OptJob::new(&encoded).expect("opt job").warnings();
+        assert_eq!(warnings[0].kind, crate::report::WarningKind::AnimationFlattened);
+        let mut png = std::io::Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(still)
+            .write_to(&mut png, image::ImageFormat::Png)
+            .expect("encode still");
+        assert!(!is_apng(png.get_ref()));
+        assert_eq!(delay_fraction(Duration::from_secs(100)), (100, 1));
+        assert!(matches!(encode(&[]), Err(ImagerError::InvalidInput(_))));
+    }
+}
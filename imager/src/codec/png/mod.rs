@@ -0,0 +1,372 @@
+pub mod apng;
+pub mod optimize;
+
+use exoquant::{Color, ColorSpace, Remapper, SimpleColorSpace, ditherer, optimizer::{WeightedKMeans, Optimizer}};
+use image::{DynamicImage, GenericImage, GenericImageView, GrayImage};
+use lodepng::Bitmap;
+use lodepng::RGBA;
+use std::convert::{AsRef, From};
+use std::io::{BufWriter, Read, Write};
+use std::iter::Extend;
+use std::iter::FromIterator;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use crate::codec::quantize::{self, Quantizer};
+use crate::data::{BrandPalette, Resolution, VideoBuffer, Yuv420P};
+use crate::error::ImagerError;
+#[cfg(not(feature = "pure-rust"))]
+use crate::vmaf;
+
+///////////////////////////////////////////////////////////////////////////////
+// DATA TYPES
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ImageMode {
+    Text,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ENCODER
+///////////////////////////////////////////////////////////////////////////////
+
+fn encode_indexed(palette: &[Color], image: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let mut state = lodepng::Encoder::new();
+    for color in palette {
+        unsafe {
+            lodepng::ffi::lodepng_palette_add(
+                &mut state.info_png_mut().color,
+                color.r,
+                color.g,
+                color.b,
+                color.a,
+            );
+            lodepng::ffi::lodepng_palette_add(
+                state.info_raw_mut(),
+                color.r,
+                color.g,
+                color.b,
+                color.a,
+            );
+        }
+    }
+    state.info_png_mut().color.set_bitdepth(8);
+    state.info_png_mut().color.colortype = lodepng::ColorType::PALETTE;
+    state.info_raw_mut().set_bitdepth(8);
+    state.info_raw_mut().colortype = lodepng::ColorType::PALETTE;
+    state
+        .encode(image, width as usize, height as usize)
+        .expect("encode png data")
+}
+
+/// Grayscale, or with `bilevel` 1 bit per pixel (pixels below 128 are
+/// black), instead of a quantized palette.
+pub fn encode_gray(source: &GrayImage, bilevel: bool) -> Vec<u8> {
+    let mut state = lodepng::Encoder::new();
+    state.info_png_mut().color.colortype = lodepng::ColorType::GREY;
+    state.info_png_mut().color.set_bitdepth(if bilevel { 1 } else { 8 });
+    state.info_raw_mut().colortype = lodepng::ColorType::GREY;
+    state.info_raw_mut().set_bitdepth(8);
+    let data = if bilevel {
+        source.pixels().map(|x| if x.0[0] < 128 { 0 } else { 255 }).collect()
+    } else {
+        source.as_raw().clone()
+    };
+    state
+        .encode(&data, source.width() as usize, source.height() as usize)
+        .expect("encode png data")
+}
+
+pub fn compress(
+    source: &DynamicImage,
+    mode: ImageMode,
+    num_colors: usize,
+) -> Result<Vec<u8>, String> {
+    let (palette, out_data) = quantize(source, mode, num_colors);
+    // ENCODE
+    let out_file = encode_indexed(&palette, &out_data, source.width(), source.height());
+    // DONE
+    Ok(out_file)
+}
+
+/// The palette of at most `num_colors`, and every pixel’s index into it.
+pub(crate) fn quantize(source: &DynamicImage, mode: ImageMode, num_colors: usize) -> (Vec<Color>, Vec<u8>) {
+    // CHECKS
+    assert!(num_colors <= 256);
+    // SETUP
+    let (ditherer, optimizer) = match mode {
+        ImageMode::Text => {
+            // VALUES
+            let d = ditherer::None;
+            let o = WeightedKMeans;
+            // DONE
+            let d: Box<dyn ditherer::Ditherer> = Box::new(d);
+            let o: Box<dyn Optimizer> = Box::new(o);
+            (d, o)
+        }
+    };
+    let input_pixels = source
+        .pixels()
+        .map(|(_, _, px)| Color::new(px.0[0], px.0[1], px.0[2], px.0[3]))
+        .collect::<Vec<Color>>();
+    let dev = false;
+    let colorspace = SimpleColorSpace::default();
+    let histogram = quantize::histogram(&input_pixels, &colorspace);
+    let mut quantizer = Quantizer::new(histogram.clone());
+    for _ in 0..num_colors {
+        quantizer.step();
+        quantizer = quantizer.optimize(&*optimizer, 16);
+    }
+    // PALETTE DATA
+    let mut palette = quantizer
+        .colors(&colorspace)
+        .into_iter()
+        .map(|x| colorspace.to_float(x))
+        .collect::<Vec<_>>();
+    for _ in 0..16 {
+        palette = optimizer.step(palette, &histogram);
+    }
+    let palette = palette
+        .into_iter()
+        .map(|x| colorspace.from_float(x))
+        .collect::<Vec<_>>();
+    let remapper = Remapper::new(&palette, &colorspace, &*ditherer);
+    // PIXEL DATA
+    let out_data: Vec<u8> = remapper
+        .remap_iter(Box::new(input_pixels.into_iter()), source.width() as usize)
+        .collect();
+    (palette, out_data)
+}
+
+#[cfg(not(feature = "pure-rust"))]
+#[must_use] pub fn basic_optimize(source: &DynamicImage) -> Vec<u8> {
+    let vmaf_source = VideoBuffer::from_image(source).expect("to VideoBuffer");
+    let run = |num_colors: usize| {
+        let mode = ImageMode::Text;
+        let compressed = compress(source, mode, num_colors).expect("compress png source");
+        let report = {
+            let vmaf_derivative = VideoBuffer::from_png(&compressed).expect("to VideoBuffer");
+            vmaf::get_report(&vmaf_source, &vmaf_derivative)
+        };
+        // println!("vmaf: {}", report);
+        (compressed, report)
+    };
+    let fallback = || {
+        let num_colors = 255;
+        let mode = ImageMode::Text;
+        compress(source, mode, num_colors).expect("compress png source")
+    };
+    // RUN
+    for num_colors in 1..256 {
+        // println!("num_colors: {}", num_colors);
+        let (compressed, report) = run(num_colors);
+        if report >= 90.0 || num_colors <= 5 {
+            return compressed;
+        }
+    }
+    // OR RUN FALLBACK
+    fallback()
+}
+
+/// Without libvmaf there’s no search for the fewest colors; this is the
+/// fallback of the search.
+#[cfg(feature = "pure-rust")]
+#[must_use] pub fn basic_optimize(source: &DynamicImage) -> Vec<u8> {
+    compress(source, ImageMode::Text, 255).expect("compress png source")
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// BRAND PALETTES
+///////////////////////////////////////////////////////////////////////////////
+
+/// Palette indices of every pixel, optionally Floyd-Steinberg dithered
+/// (with the variant that reduces color bleeding, so flat areas stay flat).
+fn remap_to_palette(source: &DynamicImage, palette: &[Color], dither: bool) -> Vec<u8> {
+    let input_pixels = source
+        .pixels()
+        .map(|(_, _, px)| Color::new(px.0[0], px.0[1], px.0[2], px.0[3]))
+        .collect::<Vec<Color>>();
+    let colorspace = SimpleColorSpace::default();
+    let ditherer: Box<dyn ditherer::Ditherer> = if dither {
+        Box::new(ditherer::FloydSteinberg::new())
+    } else {
+        Box::new(ditherer::None)
+    };
+    Remapper::new(palette, &colorspace, &*ditherer).remap(&input_pixels, source.width() as usize)
+}
+
+fn palette_colors(palette: &BrandPalette) -> Vec<Color> {
+    palette
+        .0
+        .iter()
+        .map(|[r, g, b, a]| Color::new(*r, *g, *b, *a))
+        .collect()
+}
+
+/// An indexed PNG using exactly (and only) the palette’s colors.
+pub fn compress_with_palette(source: &DynamicImage, palette: &BrandPalette, dither: bool) -> Vec<u8> {
+    let palette = palette_colors(palette);
+    let indices = remap_to_palette(source, &palette, dither);
+    encode_indexed(&palette, &indices, source.width(), source.height())
+}
+
+/// Snaps every pixel to the palette, e.g. ahead of a (lossless) encode
+/// to another format.
+pub fn snap_to_palette(source: &DynamicImage, palette: &BrandPalette, dither: bool) -> DynamicImage {
+    let colors = palette_colors(palette);
+    let indices = remap_to_palette(source, &colors, dither);
+    let pixels = indices
+        .iter()
+        .flat_map(|ix| palette.0[*ix as usize])
+        .collect::<Vec<u8>>();
+    let output = ::image::RgbaImage::from_raw(source.width(), source.height(), pixels)
+        .expect("palette remap preserves resolution");
+    DynamicImage::ImageRgba8(output)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// DECODER
+///////////////////////////////////////////////////////////////////////////////
+
+/// Decodes a PNG row by row, box-filtering rows as they arrive so the full
+/// resolution image is never held in memory.
+///
+/// The box size is the largest integer factor that keeps the result at
+/// least as large as `max_size` (when fit within it), so callers still
+/// resize to the exact target afterwards. Boxes at the right and bottom
+/// edges are partial (averaged over the pixels they cover), so no pixels
+/// are dropped, and colors are averaged premultiplied by their alpha, so
+/// transparent pixels don’t bleed into opaque ones. Interlaced images are
+/// decoded in full, since their rows arrive out of order.
+pub fn decode_scaled(source: &[u8], max_size: &Resolution) -> Result<DynamicImage, ImagerError> {
+    let mut decoder = ::png::Decoder::new(source);
+    decoder.set_transformations(::png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(ImagerError::decode)?;
+    let (width, height) = (reader.info().width, reader.info().height);
+    let factor = box_factor((width, height), max_size);
+    if reader.info().interlaced || factor == 1 {
+        return Ok(::image::load_from_memory_with_format(source, ::image::ImageFormat::Png)?);
+    }
+    let channels = match reader.output_color_type().0 {
+        ::png::ColorType::Grayscale => 1,
+        ::png::ColorType::GrayscaleAlpha => 2,
+        ::png::ColorType::Rgb => 3,
+        ::png::ColorType::Rgba => 4,
+        // EXPANDED BY `normalize_to_color8`
+        ::png::ColorType::Indexed => unreachable!("indexed PNG rows"),
+    };
+    let (out_width, out_height) = (width.div_ceil(factor), height.div_ceil(factor));
+    let mut output = Vec::with_capacity((out_width * out_height * 4) as usize);
+    // PREMULTIPLIED COLORS, AND ALPHA
+    let mut sums = vec![0u64; (out_width * 4) as usize];
+    for y in 0..height {
+        let row = reader
+            .next_row()
+            .map_err(ImagerError::decode)?
+            .ok_or_else(|| ImagerError::decode("truncated PNG"))?;
+        for (x, px) in row.data().chunks(channels).take(width as usize).enumerate() {
+            let [r, g, b, a] = match px {
+                [l] => [*l, *l, *l, 255],
+                [l, a] => [*l, *l, *l, *a],
+                [r, g, b] => [*r, *g, *b, 255],
+                [r, g, b, a] => [*r, *g, *b, *a],
+                _ => unreachable!("{} channel PNG rows", channels),
+            };
+            let ix = (x / factor as usize) * 4;
+            let a = u64::from(a);
+            sums[ix] += u64::from(r) * a;
+            sums[ix + 1] += u64::from(g) * a;
+            sums[ix + 2] += u64::from(b) * a;
+            sums[ix + 3] += a;
+        }
+        if (y + 1) % factor == 0 || y + 1 == height {
+            let rows = u64::from(y % factor + 1);
+            for (column, sum) in sums.chunks_mut(4).enumerate() {
+                let columns = u64::from(factor.min(width - column as u32 * factor));
+                let (area, alpha) = (rows * columns, sum[3]);
+                let unpremultiply = |x: u64| (x + alpha / 2).checked_div(alpha).unwrap_or(0) as u8;
+                output.extend([
+                    unpremultiply(sum[0]),
+                    unpremultiply(sum[1]),
+                    unpremultiply(sum[2]),
+                    ((alpha + area / 2) / area) as u8,
+                ]);
+                sum.iter_mut().for_each(|x| *x = 0);
+            }
+        }
+    }
+    let output = ::image::RgbaImage::from_raw(out_width, out_height, output).expect("box filtered rows");
+    Ok(DynamicImage::ImageRgba8(output))
+}
+
+/// At most the shorter side, so that no side is scaled to nothing.
+fn box_factor(source: (u32, u32), max_size: &Resolution) -> u32 {
+    let (width, height) = source;
+    let factor = f64::max(
+        f64::from(width) / f64::from(max_size.width.max(1)),
+        f64::from(height) / f64::from(max_size.height.max(1)),
+    );
+    (factor.floor() as u32).min(width.min(height)).max(1)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// DEV
+///////////////////////////////////////////////////////////////////////////////
+
+pub fn run() {
+    // DEV
+    let input_path = "assets/samples/code.png";
+    let output_path = "assets/output/test.png";
+    // LOAD & DECODE
+    let img = ::image::open(input_path).expect("load input png");
+    let out = basic_optimize(&img);
+    std::fs::write(output_path, &out);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    fn encode_png(image: &DynamicImage) -> Vec<u8> {
+        let mut output = std::io::Cursor::new(Vec::new());
+        image.write_to(&mut output, ::image::ImageOutputFormat::Png).expect("encode png");
+        output.into_inner()
+    }
+
+    #[test]
+    fn test_decode_scaled() {
+        // A STRIP MUCH WIDER THAN THE TARGET IS ONLY SCALED BY ITS HEIGHT
+        let strip = DynamicImage::ImageRgb8(::image::RgbImage::new(4000, 3));
+        let scaled = decode_scaled(&encode_png(&strip), &Resolution::new(100, 100)).expect("decode strip");
+        assert_eq!(scaled.dimensions(), (1334, 1));
+        // THE PARTIAL BOXES OF THE LAST COLUMN AND ROW: A 2X1 AND 1X1 BOX,
+        // AND A HALF TRANSPARENT RED PIXEL NEXT TO AN OPAQUE BLUE ONE
+        let mut source = ::image::RgbaImage::from_pixel(5, 5, ::image::Rgba([0, 0, 255, 255]));
+        source.put_pixel(0, 0, ::image::Rgba([255, 0, 0, 0]));
+        source.put_pixel(4, 4, ::image::Rgba([0, 255, 0, 255]));
+        source.put_pixel(2, 4, ::image::Rgba([255, 255, 255, 255]));
+        let source = encode_png(&DynamicImage::ImageRgba8(source));
+        let scaled = decode_scaled(&source, &Resolution::new(2, 2)).expect("decode scaled").to_rgba8();
+        assert_eq!(scaled.dimensions(), (3, 3));
+        assert_eq!(scaled.get_pixel(0, 0).0, [0, 0, 255, 191]);
+        assert_eq!(scaled.get_pixel(1, 2).0, [128, 128, 255, 255]);
+        assert_eq!(scaled.get_pixel(2, 2).0, [0, 255, 0, 255]);
+        assert_eq!(scaled.get_pixel(2, 1).0, [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_compress_with_palette() {
+        let source = ::image::load_from_memory(include_bytes!("../../../assets/test/1.jpeg"))
+            .expect("decode test image")
+            .thumbnail(64, 64);
+        let palette = BrandPalette::from_str("#1a2b3c, #ffffff #ff6600cc").expect("palette");
+        for dither in [true, false] {
+            let output = compress_with_palette(&source, &palette, dither);
+            let output = ::image::load_from_memory(&output).expect("decode output");
+            assert_eq!(output.dimensions(), source.dimensions());
+            assert!(output.pixels().all(|(_, _, px)| palette.0.contains(&px.0)));
+        }
+    }
+}
@@ -0,0 +1,225 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Lossless optimization of PNG outputs (after oxipng): the smallest
+//! re-encode at the fewest bits that keep every pixel exactly.
+//!
+//! - 16 bit samples that are 8 bit ones (both bytes the same) become 8 bit.
+//! - Opaque alpha is dropped, and RGB that’s gray becomes gray, of 1, 2 or
+//!   4 bits where every level is exact.
+//! - Up to 256 colors become a palette of 1, 2, 4 or 8 bits, translucent
+//!   entries first (so the `tRNS` is shortest), then by frequency.
+//! - Every layout is deflated (at the best level) with every one of the
+//!   `FILTERS`.
+//!
+//! Only pixels are kept, so ancillary chunks are dropped: outputs get their
+//! metadata after the encode. The source is returned if nothing is smaller.
+use lodepng::{ColorType, FilterStrategy, Image, RGBA};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crate::error::{ImagerError, Result};
+
+/// The row filters to try.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Filters {
+    /// Chosen per row by lodepng.
+    Adaptive(FilterStrategy),
+    /// The same filter (0-4) for every row.
+    Every(u8),
+}
+
+pub const FILTERS: [Filters; 4] = [
+    Filters::Adaptive(FilterStrategy::ZERO),
+    Filters::Adaptive(FilterStrategy::MINSUM),
+    Filters::Adaptive(FilterStrategy::ENTROPY),
+    // PAETH
+    Filters::Every(4),
+];
+
+pub fn optimize(source: &[u8]) -> Result<Vec<u8>> {
+    let pixels = match lodepng::decode_memory(source, ColorType::RGBA, 16) {
+        Ok(Image::RGBA16(bitmap)) => bitmap,
+        Ok(_) => unreachable!("decoded to RGBA16"),
+        Err(error) => return Err(ImagerError::Decode(format!("failed to decode the png: {}", error))),
+    };
+    let mut best: Option<Vec<u8>> = None;
+    let samples = pixels.buffer.iter().map(|px| [px.r, px.g, px.b, px.a]).collect::<Vec<_>>();
+    for layout in layouts(&samples) {
+        for filters in FILTERS {
+            let output = layout.encode(filters, pixels.width, pixels.height)?;
+            if best.as_ref().is_none_or(|best| output.len() < best.len()) {
+                best = Some(output);
+            }
+        }
+    }
+    match best {
+        Some(best) if best.len() < source.len() => Ok(best),
+        _ => Ok(source.to_vec()),
+    }
+}
+
+/// A color type and bit depth of the PNG, with the pixels in it (8 bit
+/// samples or indices, or big endian 16 bit samples).
+#[derive(Debug, Clone)]
+struct Layout {
+    color: ColorType,
+    depth: u32,
+    palette: Vec<RGBA>,
+    data: Vec<u8>,
+}
+
+impl Layout {
+    fn encode(&self, filters: Filters, width: usize, height: usize) -> Result<Vec<u8>> {
+        let mut encoder = lodepng::Encoder::new();
+        encoder.set_auto_convert(false);
+        match filters {
+            Filters::Adaptive(strategy) => encoder.set_filter_strategy(strategy, false),
+            Filters::Every(filter) => encoder.set_predefined_filters(vec![filter; height]),
+        }
+        encoder.settings_mut().zlibsettings.set_level(9);
+        let mut color = encoder.info_raw().clone();
+        color.colortype = self.color;
+        for entry in &self.palette {
+            color.palette_add(*entry).map_err(|error| ImagerError::Encode(error.to_string()))?;
+        }
+        // LOWER DEPTHS ARE PACKED BY LODEPNG
+        color.set_bitdepth(self.depth.max(8));
+        *encoder.info_raw_mut() = color.clone();
+        color.set_bitdepth(self.depth);
+        encoder.info_png_mut().color = color;
+        encoder
+            .encode(&self.data, width, height)
+            .map_err(|error| ImagerError::Encode(format!("failed to encode the png: {}", error)))
+    }
+}
+
+/// The lossless layouts of the pixels: the fewest channels and bits, and a
+/// palette if there are few enough colors.
+fn layouts(pixels: &[[u16; 4]]) -> Vec<Layout> {
+    let eight = pixels.iter().flatten().all(|x| x % 257 == 0);
+    let opaque = pixels.iter().all(|[_, _, _, a]| *a == u16::MAX);
+    let gray = pixels.iter().all(|[r, g, b, _]| r == g && g == b);
+    let color = match (gray, opaque) {
+        (true, true) => ColorType::GREY,
+        (true, false) => ColorType::GREY_ALPHA,
+        (false, true) => ColorType::RGB,
+        (false, false) => ColorType::RGBA,
+    };
+    let mut data = Vec::with_capacity(pixels.len() * 8);
+    for [r, g, b, a] in pixels.iter().copied() {
+        let samples = match color {
+            ColorType::GREY => &[r][..],
+            ColorType::GREY_ALPHA => &[r, a][..],
+            ColorType::RGB => &[r, g, b][..],
+            _ => &[r, g, b, a][..],
+        };
+        for sample in samples {
+            if eight {
+                data.push((sample >> 8) as u8);
+            } else {
+                data.extend(sample.to_be_bytes());
+            }
+        }
+    }
+    let depth = match (color, eight) {
+        (_, false) => 16,
+        (ColorType::GREY, true) => [1_u32, 2, 4]
+            .into_iter()
+            .find(|depth| data.iter().all(|x| u32::from(*x) % (255 / ((1 << depth) - 1)) == 0))
+            .unwrap_or(8),
+        _ => 8,
+    };
+    let mut layouts = vec![Layout {
+        color,
+        depth,
+        palette: Vec::new(),
+        data,
+    }];
+    if eight {
+        layouts.extend(palette_layout(pixels));
+    }
+    layouts
+}
+
+/// Of at most 256 colors.
+fn palette_layout(pixels: &[[u16; 4]]) -> Option<Layout> {
+    let to_8 = |px: &[u16; 4]| {
+        let [r, g, b, a] = px.map(|x| (x >> 8) as u8);
+        RGBA::new(r, g, b, a)
+    };
+    let mut counts = HashMap::<RGBA, usize>::new();
+    for px in pixels {
+        *counts.entry(to_8(px)).or_default() += 1;
+        if counts.len() > 256 {
+            return None;
+        }
+    }
+    let mut palette = counts.into_iter().collect::<Vec<_>>();
+    palette.sort_by_key(|(color, count)| (color.a == u8::MAX, Reverse(*count), [color.r, color.g, color.b, color.a]));
+    let palette = palette.into_iter().map(|(color, _)| color).collect::<Vec<_>>();
+    let indices = palette
+        .iter()
+        .enumerate()
+        .map(|(index, color)| (*color, index as u8))
+        .collect::<HashMap<_, _>>();
+    let depth = match palette.len() {
+        0..=2 => 1,
+        3..=4 => 2,
+        5..=16 => 4,
+        _ => 8,
+    };
+    Some(Layout {
+        color: ColorType::PALETTE,
+        depth,
+        data: pixels.iter().map(|px| indices[&to_8(px)]).collect(),
+        palette,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::{DynamicImage, ImageBuffer, Rgba};
+
+    fn encode(source: &DynamicImage) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        source
+            .write_to(&mut std::io::Cursor::new(&mut encoded), image::ImageFormat::Png)
+            .expect("encode");
+        encoded
+    }
+
+    fn info(png: &[u8]) -> (::png::ColorType, ::png::BitDepth) {
+        let reader = ::png::Decoder::new(png).read_info().expect("decode");
+        let info = reader.info();
+        (info.color_type, info.bit_depth)
+    }
+
+    #[test]
+    fn test_optimize() {
+        // 16 BIT, OPAQUE GRAY OF FOUR EXACT 2 BIT LEVELS, IN RGBA
+        let levels = ImageBuffer::from_fn(64, 64, |x, y| {
+            let level = ((x / 16 + y / 16) % 4) as u16 * 0x5555;
+            Rgba([level, level, level, u16::MAX])
+        });
+        let source = DynamicImage::ImageRgba16(levels);
+        let input = encode(&source);
+        let output = optimize(&input).expect("optimize");
+        assert!(output.len() < input.len());
+        assert_eq!(info(&output), (::png::ColorType::Grayscale, ::png::BitDepth::Two));
+        let decoded = image::load_from_memory(&output).expect("decode");
+        assert_eq!(decoded.to_rgba16(), source.to_rgba16());
+        // THREE COLORS, ONE TRANSLUCENT
+        let colors = [[255, 0, 0, 255], [0, 0, 255, 255], [0, 128, 0, 100]];
+        let source = DynamicImage::ImageRgba8(ImageBuffer::from_fn(64, 64, |x, y| {
+            Rgba(colors[(((x * 31 + y * 17) ^ (x * y)) % 3) as usize])
+        }));
+        let output = optimize(&encode(&source)).expect("optimize");
+        assert_eq!(info(&output), (::png::ColorType::Indexed, ::png::BitDepth::Two));
+        assert_eq!(image::load_from_memory(&output).expect("decode").to_rgba8(), source.to_rgba8());
+        // ALREADY OPTIMAL OUTPUTS ARE KEPT
+        assert_eq!(optimize(&output).expect("optimize"), output);
+        assert!(matches!(optimize(b"not a png"), Err(ImagerError::Decode(_))));
+    }
+}
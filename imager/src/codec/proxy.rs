@@ -0,0 +1,50 @@
+//! Low-resolution proxies of sources, for fast quality searches (see
+//! `--fast-search`): a search on the proxy brackets the quality, and the
+//! full-size search only confirms it, for 2-3x fewer full-size encodes.
+//! VMAF scores of a downscaled image differ somewhat from those of the
+//! source, so the result can be a little off the full search’s.
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView};
+
+use crate::data::QualityRange;
+
+/// The longer side of proxies.
+pub const PROXY_SIZE: u32 = 512;
+
+/// Either side of the proxy’s quality, of the full-size search.
+pub const MARGIN: u8 = 6;
+
+/// The source downscaled to `PROXY_SIZE`, if larger; smaller sources are
+/// cheap enough to search as they are.
+pub fn proxy(source: &DynamicImage) -> Option<DynamicImage> {
+    let (width, height) = source.dimensions();
+    if width.max(height) <= PROXY_SIZE {
+        return None;
+    }
+    Some(source.resize(PROXY_SIZE, PROXY_SIZE, FilterType::Triangle))
+}
+
+/// The part of the range within `MARGIN` of the proxy’s quality.
+pub fn bracket(range: QualityRange, proxy_q: u8) -> QualityRange {
+    QualityRange {
+        min: range.clamp(proxy_q.saturating_sub(MARGIN)),
+        max: range.clamp(proxy_q.saturating_add(MARGIN)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_proxy() {
+        let source = DynamicImage::new_rgb8(1600, 900);
+        let proxy = proxy(&source).expect("proxy");
+        assert_eq!(proxy.dimensions(), (512, 288));
+        assert!(super::proxy(&DynamicImage::new_rgb8(512, 400)).is_none());
+        let range = QualityRange { min: 10, max: 90 };
+        assert_eq!(bracket(range, 50), QualityRange { min: 44, max: 56 });
+        assert_eq!(bracket(range, 12), QualityRange { min: 10, max: 18 });
+        assert_eq!(bracket(range, 88), QualityRange { min: 82, max: 90 });
+    }
+}
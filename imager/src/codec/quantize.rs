@@ -0,0 +1,191 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Reproducible palette quantization.
+//!
+//! > original credits: the quantizer of the [exoquant](https://github.com/exoticorn/exoquant-rs) crate, MIT.
+//!
+//! Exoquant’s `Histogram` is a `HashMap`, so its quantizer sees the colors
+//! in a different order each run (and ties in its sorts, and float sums,
+//! come out differently). Here the histogram is in color order instead, so
+//! the same input always gives the same palette.
+use exoquant::optimizer::Optimizer;
+use exoquant::{Color, ColorCount, ColorMap, ColorSpace, Colorf};
+use std::collections::BTreeMap;
+
+/// The histogram of `pixels`, in color order.
+pub fn histogram(pixels: &[Color], colorspace: &dyn ColorSpace) -> Vec<ColorCount> {
+    let mut counts: BTreeMap<[u8; 4], usize> = BTreeMap::new();
+    for px in pixels {
+        *counts.entry([px.r, px.g, px.b, px.a]).or_insert(0) += 1;
+    }
+    counts
+        .into_iter()
+        .map(|([r, g, b, a], count)| ColorCount {
+            color: colorspace.to_float(Color::new(r, g, b, a)),
+            count,
+        })
+        .collect()
+}
+
+struct Node {
+    /// The colors this node represents.
+    histogram: Vec<ColorCount>,
+    avg: Colorf,
+    /// The reduction of the total variance from splitting at `split`.
+    vdif: f64,
+    split: usize,
+}
+
+fn sort_by_key<F: Fn(&Colorf) -> f64>(histogram: &mut [ColorCount], key: F) {
+    histogram.sort_by(|a, b| key(&a.color).total_cmp(&key(&b.color)));
+}
+
+impl Node {
+    fn new(mut histogram: Vec<ColorCount>) -> Self {
+        // AVERAGE & VARIANCE
+        let mut n = 0usize;
+        let mut fsum = Colorf::zero();
+        let mut fsum2 = Colorf::zero();
+        for entry in &histogram {
+            n += entry.count;
+            fsum += entry.color * entry.count as f64;
+            fsum2 += entry.color * entry.color * entry.count as f64;
+        }
+        if n == 0 {
+            return Node {
+                histogram,
+                avg: Colorf::zero(),
+                vdif: 0.0,
+                split: 0,
+            };
+        }
+        let avg = fsum * (1.0 / n as f64);
+        let vc = fsum2 - fsum * avg;
+        let v = vc.r + vc.g + vc.b + vc.a;
+        // BY THE CHANNEL WITH THE LARGEST VARIANCE
+        if vc.r > vc.g && vc.r > vc.b && vc.r > vc.a {
+            sort_by_key(&mut histogram, |c| c.r);
+        } else if vc.g > vc.b && vc.g > vc.a {
+            sort_by_key(&mut histogram, |c| c.g);
+        } else if vc.b > vc.a {
+            sort_by_key(&mut histogram, |c| c.b);
+        } else {
+            sort_by_key(&mut histogram, |c| c.a);
+        }
+        // THEN BY THE PRIMARY AXIS OF THE DISTRIBUTION
+        let mut dir = Colorf::zero();
+        for entry in &histogram {
+            let mut tmp = (entry.color - avg) * entry.count as f64;
+            if tmp.dot(&dir) < 0.0 {
+                tmp *= -1.0;
+            }
+            dir += tmp;
+        }
+        let length = dir.dot(&dir).sqrt();
+        dir *= if length < 0.000_000_001 { 1.0 } else { 1.0 / length };
+        sort_by_key(&mut histogram, |c| c.dot(&dir));
+        // THE SPLIT WITH THE LOWEST TOTAL VARIANCE
+        let mut sum = Colorf::zero();
+        let mut sum2 = Colorf::zero();
+        let mut vdif = -v;
+        let mut n2 = 0;
+        let mut split = 0usize;
+        for (ix, entry) in histogram.iter().enumerate() {
+            n2 += entry.count;
+            sum += entry.color * entry.count as f64;
+            sum2 += entry.color * entry.color * entry.count as f64;
+            if n2 < n {
+                let tmp = sum2 - sum * sum * (1.0 / n2 as f64);
+                let dif_sum = fsum - sum;
+                let tmp2 = (fsum2 - sum2) - dif_sum * dif_sum * (1.0 / (n - n2) as f64);
+                let nv = tmp.r + tmp.g + tmp.b + tmp.a + tmp2.r + tmp2.g + tmp2.b + tmp2.a;
+                if -nv > vdif {
+                    vdif = -nv;
+                    split = ix + 1;
+                }
+            }
+        }
+        Node {
+            histogram,
+            avg,
+            vdif: vdif + v,
+            split,
+        }
+    }
+}
+
+/// Median cut (variance based) quantization, with optional k-means
+/// refinement between steps; the API of `exoquant::Quantizer`.
+pub struct Quantizer(Vec<Node>);
+
+impl Quantizer {
+    pub fn new(histogram: Vec<ColorCount>) -> Self {
+        Quantizer(vec![Node::new(histogram)])
+    }
+    /// Splits the node with the largest variance reduction, i.e. adds
+    /// one color.
+    pub fn step(&mut self) {
+        let mut best = 0;
+        let mut best_vdif = 0.0;
+        for (ix, node) in self.0.iter().enumerate() {
+            if node.vdif >= best_vdif {
+                best_vdif = node.vdif;
+                best = ix;
+            }
+        }
+        let node = self.0.swap_remove(best);
+        let mut first = node.histogram;
+        let second = first.split_off(node.split);
+        self.0.push(Node::new(first));
+        self.0.push(Node::new(second));
+    }
+    pub fn colors(&self, colorspace: &dyn ColorSpace) -> Vec<Color> {
+        self.0.iter().map(|node| colorspace.from_float(node.avg)).collect()
+    }
+    /// Runs `iterations` k-means steps, then regroups the colors by their
+    /// nearest palette entry.
+    pub fn optimize(self, optimizer: &dyn Optimizer, iterations: usize) -> Self {
+        if optimizer.is_noop() {
+            return self;
+        }
+        let (mut colors, histograms): (Vec<Colorf>, Vec<Vec<ColorCount>>) =
+            self.0.into_iter().map(|node| (node.avg, node.histogram)).unzip();
+        let histogram = histograms.into_iter().flatten().collect::<Vec<_>>();
+        for _ in 0..iterations {
+            colors = optimizer.step(colors, &histogram);
+        }
+        let mut histograms: Vec<Vec<ColorCount>> = colors.iter().map(|_| Vec::new()).collect();
+        let map = ColorMap::from_float_colors(colors);
+        for entry in histogram {
+            histograms[map.find_nearest(entry.color)].push(entry);
+        }
+        Quantizer(histograms.into_iter().map(Node::new).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use exoquant::optimizer::WeightedKMeans;
+    use exoquant::SimpleColorSpace;
+
+    #[test]
+    fn test_quantizer_is_reproducible() {
+        let pixels = (0..4096u32)
+            .map(|ix| Color::new((ix % 251) as u8, (ix * 7 % 256) as u8, (ix / 16) as u8, 255))
+            .collect::<Vec<_>>();
+        let colorspace = SimpleColorSpace::default();
+        let palette = || {
+            let mut quantizer = Quantizer::new(histogram(&pixels, &colorspace));
+            for _ in 0..16 {
+                quantizer.step();
+                quantizer = quantizer.optimize(&WeightedKMeans, 4);
+            }
+            quantizer.colors(&colorspace)
+        };
+        let first = palette();
+        assert_eq!(first.len(), 17);
+        assert!(first == palette());
+    }
+}
@@ -0,0 +1,692 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! The codecs (and other optional stages) of this build, looked up at
+//! runtime, so that asking for one whose cargo feature is disabled is a
+//! `FeatureDisabled` error naming the feature, rather than a build or
+//! startup failure.
+//!
+//! | | `ffi` (default) | `pure-rust` |
+//! |---|---|---|
+//! | JPEG encoding | mozjpeg, VMAF search | `image`, fixed quality |
+//! | PNG encoding | VMAF search | fixed palette size |
+//! | WebP encoding | libwebp, VMAF search | `imager_core::vp8`, fixed quality |
+//! | `turbo` decoder | libjpeg-turbo | skipped in chains (`ffi`) |
+//! | VMAF scores (e.g. `rd-curve`) | libvmaf | disabled (`ffi`) |
+//! | `esrgan` upscaler | with `esrgan` | with `esrgan` |
+//! | `remove-background` | with `background-removal` | with `background-removal` |
+//! | `--plugin` | with `plugins` | with `plugins` |
+//! | AVIF | with `avif`, VMAF search | with `avif`, fixed quality |
+//! | JPEG XL | with `jxl`, fixed quality | with `jxl`, fixed quality |
+//!
+//! Encoder plugins (see `plugin`) replace the built-in encoder of their
+//! format, including disabled ones.
+//!
+//! Decoder chains are fallbacks, so disabled decoders are skipped; only
+//! chains without any enabled decoder are rejected.
+use image::DynamicImage;
+use lazy_static::lazy_static;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use crate::classifier::Class;
+use crate::codec::plugin::Plugin;
+use crate::codec::{jpeg, png, tiff};
+#[cfg(not(feature = "pure-rust"))]
+use crate::codec::webp;
+use crate::data::{BrandPalette, ColorMode, Matte, OutputFormat, QualityRange, Seed, Tuning};
+use crate::eval::Target;
+use crate::decode::{Decoder, DecoderChain};
+use crate::pipeline::{Pipeline, Stage};
+use crate::report::{DistanceCheck, Versions};
+use crate::upscale::Upscaler;
+
+///////////////////////////////////////////////////////////////////////////////
+// ERRORS
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeatureDisabled {
+    /// What was asked for, e.g. `WebP encoding`.
+    pub codec: &'static str,
+    /// The cargo feature that provides it.
+    pub feature: &'static str,
+}
+
+impl FeatureDisabled {
+    pub const WEBP_ENCODING: Self = FeatureDisabled {
+        codec: "animated WebP encoding",
+        feature: "ffi",
+    };
+    pub const TURBO_DECODER: Self = FeatureDisabled {
+        codec: "the `turbo` decoder",
+        feature: "ffi",
+    };
+    pub const VMAF: Self = FeatureDisabled {
+        codec: "VMAF scoring",
+        feature: "ffi",
+    };
+    pub const ESRGAN_UPSCALER: Self = FeatureDisabled {
+        codec: "the `esrgan` upscaler",
+        feature: "esrgan",
+    };
+    pub const BACKGROUND_REMOVAL: Self = FeatureDisabled {
+        codec: "background removal",
+        feature: "background-removal",
+    };
+    pub const PLUGINS: Self = FeatureDisabled {
+        codec: "codec plugins",
+        feature: "plugins",
+    };
+    pub const HEIF: Self = FeatureDisabled {
+        codec: "HEIF encoding",
+        feature: "heif",
+    };
+    pub const AVIF: Self = FeatureDisabled {
+        codec: "AVIF encoding and decoding",
+        feature: "avif",
+    };
+    pub const JXL: Self = FeatureDisabled {
+        codec: "JPEG XL encoding",
+        feature: "jxl",
+    };
+    pub const PANORAMA: Self = FeatureDisabled {
+        codec: "panorama stitching",
+        feature: "panorama",
+    };
+    pub const VECTORIZE: Self = FeatureDisabled {
+        codec: "vectorization",
+        feature: "vectorize",
+    };
+}
+
+impl std::fmt::Display for FeatureDisabled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} requires imager to be built with the `{}` feature",
+            self.codec, self.feature
+        )?;
+        // `pure-rust` TAKES PRECEDENCE
+        if self.feature == "ffi" {
+            write!(f, " (and without `pure-rust`)")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for FeatureDisabled {}
+
+impl From<FeatureDisabled> for String {
+    fn from(error: FeatureDisabled) -> Self {
+        error.to_string()
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ENCODERS
+///////////////////////////////////////////////////////////////////////////////
+
+pub struct EncodeOptions<'a> {
+    /// Colors the source was snapped to, to keep exactly.
+    pub palette: Option<&'a BrandPalette>,
+    pub extreme: bool,
+    pub seed: Seed,
+    pub tuning: Tuning,
+    /// What `gray::convert` converted the source to, if not color.
+    pub color_mode: ColorMode,
+    /// See `text_protect` (JPEG only).
+    pub text_protect: bool,
+    /// Composite transparency onto this, rather than keep it (lossy WebP);
+    /// JPEG, without alpha, always composites, by default onto white.
+    pub matte: Option<Matte>,
+    /// Bracket the VMAF searches by ones on a low-resolution proxy (see
+    /// `proxy`).
+    pub fast_search: bool,
+    /// Score sources of at least `tiles::MIN_PIXELS` on this share of their
+    /// tiles, rather than whole.
+    pub metric_coverage: Option<f64>,
+}
+
+pub struct Encoded {
+    pub output: Vec<u8>,
+    pub class: Class,
+    pub vmaf_score: Option<f64>,
+    /// Of encodes for a `Tuning::max_distance`.
+    pub distance: Option<DistanceCheck>,
+}
+
+pub type EncodeFn = fn(&DynamicImage, &EncodeOptions<'_>) -> Encoded;
+
+enum Backend {
+    Builtin(EncodeFn),
+    Plugin(Plugin),
+    Disabled(FeatureDisabled),
+}
+
+pub struct Encoder {
+    pub format: OutputFormat,
+    /// The implementation, e.g. `mozjpeg`, or the plugin’s name.
+    pub name: &'static str,
+    /// Of the library, as it reports it, if it does.
+    version: fn() -> Option<String>,
+    backend: Backend,
+}
+
+impl Encoder {
+    pub fn available(&self) -> Result<(), FeatureDisabled> {
+        match &self.backend {
+            Backend::Disabled(error) => Err(error.clone()),
+            _ => Ok(()),
+        }
+    }
+    /// Whether this is imager’s own encoder, rather than a plugin.
+    pub fn is_builtin(&self) -> bool {
+        matches!(self.backend, Backend::Builtin(_))
+    }
+    /// Of this build and the encoder, for reports.
+    pub fn versions(&self) -> Versions {
+        Versions {
+            imager: String::from(env!("CARGO_PKG_VERSION")),
+            encoder: String::from(self.name),
+            encoder_version: (self.version)(),
+        }
+    }
+    /// The library of plugin encoders.
+    pub fn plugin_path(&self) -> Option<&Path> {
+        match &self.backend {
+            Backend::Plugin(plugin) => plugin.path.as_deref(),
+            _ => None,
+        }
+    }
+    /// The `Tuning::max_distance` this encode searches for, if any: of
+    /// built-in lossy encodes.
+    fn within_distance(&self, options: &EncodeOptions<'_>) -> Option<f64> {
+        let lossy = match self.format {
+            OutputFormat::Jpeg | OutputFormat::Avif => true,
+            OutputFormat::Webp | OutputFormat::Jxl => options.palette.is_none(),
+            OutputFormat::Png | OutputFormat::Tiff => false,
+        };
+        options.tuning.max_distance.filter(|_| lossy)
+    }
+    /// Encoders signal failure by panicking.
+    pub fn encode(
+        &self,
+        source: &DynamicImage,
+        options: &EncodeOptions<'_>,
+    ) -> Result<Encoded, FeatureDisabled> {
+        match &self.backend {
+            Backend::Builtin(_) if self.within_distance(options).is_some() => {
+                let max_distance = self.within_distance(options).expect("max distance");
+                Ok(encode_within_distance(source, &self.format, options, max_distance))
+            }
+            Backend::Builtin(encode) => Ok(encode(source, options)),
+            Backend::Plugin(plugin) => {
+                let class_report = crate::classifier::report_seeded(source, options.seed);
+                let options = serde_json::json!({
+                    "extreme": options.extreme,
+                    "seed": options.seed.0,
+                    "palette": options.palette.is_some(),
+                });
+                let output = plugin
+                    .encode(source, &options.to_string())
+                    .unwrap_or_else(|message| panic!("{}", message));
+                Ok(Encoded {
+                    output,
+                    class: class_report.class,
+                    vmaf_score: None,
+                    distance: None,
+                })
+            }
+            Backend::Disabled(error) => Err(error.clone()),
+        }
+    }
+}
+
+#[cfg(not(feature = "pure-rust"))]
+const JPEG_ENCODER: &str = "mozjpeg";
+#[cfg(feature = "pure-rust")]
+const JPEG_ENCODER: &str = "image";
+
+#[cfg(not(feature = "pure-rust"))]
+const WEBP_ENCODER: &str = "libwebp";
+#[cfg(feature = "pure-rust")]
+const WEBP_ENCODER: &str = "vp8";
+
+/// Of the Rust encoders, pinned by imager’s own version.
+fn unversioned() -> Option<String> {
+    None
+}
+
+#[cfg(not(feature = "pure-rust"))]
+const JPEG_VERSION: fn() -> Option<String> = jpeg::version;
+#[cfg(feature = "pure-rust")]
+const JPEG_VERSION: fn() -> Option<String> = unversioned;
+
+#[cfg(not(feature = "pure-rust"))]
+const WEBP_VERSION: fn() -> Option<String> = || Some(webp::encode::version());
+#[cfg(feature = "pure-rust")]
+const WEBP_VERSION: fn() -> Option<String> = unversioned;
+
+#[cfg(not(feature = "pure-rust"))]
+const WEBP_BACKEND: Backend = Backend::Builtin(encode_webp);
+#[cfg(feature = "pure-rust")]
+const WEBP_BACKEND: Backend = Backend::Builtin(encode_webp_in_rust);
+
+#[cfg(feature = "avif")]
+const AVIF_BACKEND: Backend = Backend::Builtin(encode_avif);
+#[cfg(not(feature = "avif"))]
+const AVIF_BACKEND: Backend = Backend::Disabled(FeatureDisabled::AVIF);
+
+#[cfg(feature = "jxl")]
+const JXL_BACKEND: Backend = Backend::Builtin(encode_jxl);
+#[cfg(not(feature = "jxl"))]
+const JXL_BACKEND: Backend = Backend::Disabled(FeatureDisabled::JXL);
+
+static ENCODERS: [Encoder; 6] = [
+    Encoder {
+        format: OutputFormat::Jpeg,
+        name: JPEG_ENCODER,
+        version: JPEG_VERSION,
+        backend: Backend::Builtin(encode_jpeg),
+    },
+    Encoder {
+        format: OutputFormat::Png,
+        name: "lodepng",
+        version: unversioned,
+        backend: Backend::Builtin(encode_png),
+    },
+    Encoder {
+        format: OutputFormat::Webp,
+        name: WEBP_ENCODER,
+        version: WEBP_VERSION,
+        backend: WEBP_BACKEND,
+    },
+    Encoder {
+        format: OutputFormat::Tiff,
+        name: "tiff",
+        version: unversioned,
+        backend: Backend::Builtin(encode_tiff),
+    },
+    Encoder {
+        format: OutputFormat::Avif,
+        name: "libheif",
+        version: crate::codec::avif::version,
+        backend: AVIF_BACKEND,
+    },
+    Encoder {
+        format: OutputFormat::Jxl,
+        name: "libjxl",
+        version: crate::codec::jxl::version,
+        backend: JXL_BACKEND,
+    },
+];
+
+lazy_static! {
+    /// In the order they were loaded.
+    static ref PLUGINS: RwLock<Vec<&'static Encoder>> = RwLock::new(Vec::new());
+}
+
+/// Every encoder, including disabled and replaced ones.
+pub fn encoders() -> Vec<&'static Encoder> {
+    let plugins = PLUGINS.read().expect("plugins lock");
+    plugins.iter().copied().chain(ENCODERS.iter()).collect()
+}
+
+/// The encoder of `format`, if enabled: that of the last plugin loaded for
+/// it, or else the built-in one.
+pub fn encoder(format: &OutputFormat) -> Result<&'static Encoder, FeatureDisabled> {
+    let plugin = {
+        let plugins = PLUGINS.read().expect("plugins lock");
+        plugins.iter().rev().copied().find(|x| x.format == *format)
+    };
+    let encoder = plugin.unwrap_or_else(|| {
+        ENCODERS
+            .iter()
+            .find(|x| x.format == *format)
+            .expect("every format has an encoder")
+    });
+    encoder.available()?;
+    Ok(encoder)
+}
+
+/// Loads an encoder plugin (for good), in place of the current encoder of
+/// its format.
+pub fn load_plugin(path: &Path) -> Result<&'static Encoder, String> {
+    let plugin = Plugin::open(path)?;
+    let encoder: &'static Encoder = Box::leak(Box::new(Encoder {
+        format: plugin.format.clone(),
+        name: Box::leak(plugin.name.clone().into_boxed_str()),
+        version: unversioned,
+        backend: Backend::Plugin(plugin),
+    }));
+    PLUGINS.write().expect("plugins lock").push(encoder);
+    Ok(encoder)
+}
+
+/// The libraries of the loaded plugins, e.g. for subprocesses to load.
+pub fn plugin_paths() -> Vec<PathBuf> {
+    let plugins = PLUGINS.read().expect("plugins lock");
+    plugins
+        .iter()
+        .filter_map(|x| x.plugin_path().map(Path::to_owned))
+        .collect()
+}
+
+fn encode_jpeg(source: &DynamicImage, options: &EncodeOptions<'_>) -> Encoded {
+    let source = crate::composite::flatten(source, options.matte.unwrap_or_default());
+    let mut context = jpeg::OptContext::from_image(source);
+    context.quality_range(options.tuning.jpeg);
+    context.subsampling(options.tuning.jpeg_subsampling);
+    if let Some(target) = options.tuning.vmaf.jpeg {
+        context.vmaf_target(target);
+    }
+    if options.text_protect {
+        context.text_protect();
+    }
+    if options.fast_search {
+        context.fast_search();
+    }
+    if let Some(coverage) = options.metric_coverage {
+        context.metric_coverage(coverage);
+    }
+    let (output, report) = context.run_search(options.extreme);
+    Encoded {
+        output,
+        class: report.class,
+        vmaf_score: report.vmaf_score,
+        distance: None,
+    }
+}
+
+/// The lowest quality of the format’s range whose output is within the
+/// butteraugli distance (see `eval::search`), else its maximum, which the
+/// `DistanceCheck` reports as not passed.
+fn encode_within_distance(
+    source: &DynamicImage,
+    format: &OutputFormat,
+    options: &EncodeOptions<'_>,
+    max_distance: f64,
+) -> Encoded {
+    let class_report = crate::classifier::report_seeded(source, options.seed);
+    let flattened = match (format, options.matte) {
+        (OutputFormat::Jpeg, matte) => Some(crate::composite::flatten(source, matte.unwrap_or_default())),
+        (OutputFormat::Webp, Some(matte)) => Some(crate::composite::over(source, matte)),
+        _ => None,
+    };
+    let source = flattened.as_ref().unwrap_or(source);
+    let range = match format {
+        OutputFormat::Jpeg => options.tuning.jpeg,
+        OutputFormat::Webp => options.tuning.webp,
+        OutputFormat::Avif => options.tuning.avif,
+        _ => QualityRange { min: 0, max: 100 },
+    };
+    let found = crate::eval::search(source, format, Target::Butteraugli(max_distance), range)
+        .unwrap_or_else(|message| panic!("{}", message));
+    Encoded {
+        output: found.output,
+        class: class_report.class,
+        vmaf_score: None,
+        distance: Some(DistanceCheck {
+            distance: found.distance,
+            max_distance,
+            passed: found.passed,
+        }),
+    }
+}
+
+fn encode_png(source: &DynamicImage, options: &EncodeOptions<'_>) -> Encoded {
+    let class_report = crate::classifier::report_seeded(source, options.seed);
+    let output = match (options.palette, options.color_mode) {
+        (Some(palette), _) => png::compress_with_palette(source, palette, false),
+        (None, ColorMode::Color) => png::basic_optimize(source),
+        (None, mode) => png::encode_gray(&source.to_luma8(), mode == ColorMode::Bilevel),
+    };
+    let output = png::optimize::optimize(&output).unwrap_or_else(|message| panic!("{}", message));
+    Encoded {
+        output,
+        class: class_report.class,
+        vmaf_score: None,
+        distance: None,
+    }
+}
+
+fn encode_tiff(source: &DynamicImage, options: &EncodeOptions<'_>) -> Encoded {
+    let class_report = crate::classifier::report_seeded(source, options.seed);
+    let output = match options.color_mode {
+        ColorMode::Bilevel => tiff::encode_bilevel(&source.to_luma8()),
+        _ => tiff::encode(source),
+    };
+    Encoded {
+        output,
+        class: class_report.class,
+        vmaf_score: None,
+        distance: None,
+    }
+}
+
+#[cfg(feature = "avif")]
+fn encode_avif(source: &DynamicImage, options: &EncodeOptions<'_>) -> Encoded {
+    let (output, meta) = crate::codec::avif::opt(
+        source,
+        options.tuning.avif,
+        options.tuning.vmaf.avif,
+        options.fast_search,
+        options.metric_coverage,
+    )
+    .unwrap_or_else(|message| panic!("{}", message));
+    Encoded {
+        output,
+        class: meta.class,
+        vmaf_score: meta.vmaf_score,
+        distance: None,
+    }
+}
+
+#[cfg(feature = "jxl")]
+fn encode_jxl(source: &DynamicImage, options: &EncodeOptions<'_>) -> Encoded {
+    use crate::codec::jxl;
+    let class_report = crate::classifier::report_seeded(source, options.seed);
+    // LOSSY WOULD LOSE THE EXACT PALETTE COLORS
+    let quality = if options.palette.is_some() { 100 } else { jxl::DEFAULT_QUALITY };
+    let output = jxl::encode(source, quality).unwrap_or_else(|message| panic!("{}", message));
+    Encoded {
+        output,
+        class: class_report.class,
+        vmaf_score: None,
+        distance: None,
+    }
+}
+
+#[cfg(not(feature = "pure-rust"))]
+fn encode_webp(source: &DynamicImage, options: &EncodeOptions<'_>) -> Encoded {
+    // LOSSY WOULD LOSE THE EXACT PALETTE COLORS
+    if options.palette.is_some() {
+        let class_report = crate::classifier::report_seeded(source, options.seed);
+        return Encoded {
+            output: webp::encode::lossless::encode(source),
+            class: class_report.class,
+            vmaf_score: None,
+            distance: None,
+        };
+    }
+    let (output, meta) = webp::opt::opt_with_matte(
+        source,
+        options.tuning.webp,
+        options.tuning.webp_effort,
+        options.matte,
+        options.tuning.vmaf.webp,
+        options.fast_search,
+        options.metric_coverage,
+    );
+    Encoded {
+        output,
+        class: meta.class,
+        vmaf_score: None,
+        distance: None,
+    }
+}
+
+/// Lossy at `PURE_RUST_QUALITY` (within the quality range); lossless with
+/// a palette, like `encode_webp`.
+#[cfg(feature = "pure-rust")]
+fn encode_webp_in_rust(source: &DynamicImage, options: &EncodeOptions<'_>) -> Encoded {
+    let class_report = crate::classifier::report_seeded(source, options.seed);
+    let flattened = options.matte.map(|matte| crate::composite::over(source, matte));
+    let rgba = flattened.as_ref().unwrap_or(source).to_rgba8();
+    let (width, height) = rgba.dimensions();
+    let output = match options.palette {
+        Some(_) => imager_core::vp8l::encode_rgba(width, height, rgba.as_raw()),
+        None => {
+            let quality = options.tuning.webp.clamp(jpeg::PURE_RUST_QUALITY);
+            imager_core::vp8::encode_rgba(width, height, rgba.as_raw(), quality)
+        }
+    };
+    Encoded {
+        output: output.unwrap_or_else(|message| panic!("{}", message)),
+        class: class_report.class,
+        vmaf_score: None,
+        distance: None,
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// DECODERS AND STAGES
+///////////////////////////////////////////////////////////////////////////////
+
+pub fn decoder(decoder: Decoder) -> Result<(), FeatureDisabled> {
+    match decoder {
+        Decoder::Turbo if cfg!(feature = "pure-rust") => Err(FeatureDisabled::TURBO_DECODER),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that the chain has an enabled decoder.
+pub fn decoders(chain: &DecoderChain) -> Result<(), FeatureDisabled> {
+    let results = chain.0.iter().map(|x| decoder(*x)).collect::<Vec<_>>();
+    match results.iter().find(|x| x.is_ok()) {
+        Some(_) => Ok(()),
+        None => results.into_iter().find_map(Result::err).map_or(Ok(()), Err),
+    }
+}
+
+pub fn upscaler(upscaler: Upscaler) -> Result<(), FeatureDisabled> {
+    match upscaler {
+        Upscaler::Esrgan if !cfg!(feature = "esrgan") => Err(FeatureDisabled::ESRGAN_UPSCALER),
+        _ => Ok(()),
+    }
+}
+
+pub fn background_removal() -> Result<(), FeatureDisabled> {
+    if cfg!(feature = "background-removal") {
+        Ok(())
+    } else {
+        Err(FeatureDisabled::BACKGROUND_REMOVAL)
+    }
+}
+
+/// Checks that every stage of the pipeline is enabled.
+pub fn check_pipeline(pipeline: &Pipeline) -> Result<(), FeatureDisabled> {
+    decoders(&pipeline.decode_options().chain)?;
+    for stage in &pipeline.stages {
+        match stage {
+            Stage::Resize {
+                upscaler: Some(x), ..
+            } => upscaler(*x)?,
+            Stage::RemoveBackground { .. } => background_removal()?,
+            // A DISABLED FORMAT FALLS BACK TOO
+            Stage::Encode { fallbacks, .. } if fallbacks.iter().any(|x| encoder(x).is_ok()) => (),
+            Stage::Encode { format, .. } => drop(encoder(format)?),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        let webp = encoder(&OutputFormat::Webp).map(|x| x.name);
+        if cfg!(feature = "pure-rust") {
+            assert_eq!(webp, Ok("vp8"));
+        } else {
+            assert_eq!(webp, Ok("libwebp"));
+        }
+        assert!(encoder(&OutputFormat::Png).is_ok());
+        assert!(encoders().len() >= 3);
+        // ONLY THE C ENCODERS REPORT THEIR OWN VERSIONS
+        let versions = |format: &OutputFormat| encoder(format).map(Encoder::versions);
+        let png = versions(&OutputFormat::Png).expect("png");
+        assert_eq!((png.imager.as_str(), png.encoder_version), (env!("CARGO_PKG_VERSION"), None));
+        if !cfg!(feature = "pure-rust") {
+            let webp = versions(&OutputFormat::Webp).expect("webp").encoder_version.expect("version");
+            assert_eq!(webp.split('.').count(), 3);
+            assert!(versions(&OutputFormat::Jpeg).expect("jpeg").encoder_version.is_some());
+        }
+        // DISABLED DECODERS ARE SKIPPED, UNLESS THEY’RE ALL THERE IS
+        assert!(decoders(&DecoderChain::default()).is_ok());
+        let turbo = decoders(&DecoderChain(vec![Decoder::Turbo]));
+        assert_eq!(turbo.is_err(), cfg!(feature = "pure-rust"));
+        assert_eq!(
+            FeatureDisabled::ESRGAN_UPSCALER.to_string(),
+            "the `esrgan` upscaler requires imager to be built with the `esrgan` feature"
+        );
+        let pipeline = Pipeline::new(vec![
+            Stage::RemoveBackground {
+                model: String::from("u2net.onnx"),
+            },
+            Stage::Encode {
+                format: OutputFormat::Png,
+                fallbacks: Vec::new(),
+                extreme: false,
+                color_mode: Default::default(),
+                threshold: Default::default(),
+                text_protect: false,
+                tuning: Default::default(),
+            },
+        ]);
+        let expected = if cfg!(feature = "background-removal") {
+            Ok(())
+        } else {
+            Err(FeatureDisabled::BACKGROUND_REMOVAL)
+        };
+        assert_eq!(check_pipeline(&pipeline), expected);
+        // A BUTTERAUGLI TARGET, IN PLACE OF THE VMAF SEARCH
+        let source = DynamicImage::ImageRgb8(image::RgbImage::from_fn(48, 32, |x, y| {
+            image::Rgb([(x * 5) as u8, (y * 7) as u8, 128])
+        }));
+        let options = EncodeOptions {
+            palette: None,
+            extreme: false,
+            seed: Seed::default(),
+            tuning: Tuning {
+                max_distance: Some(1.5),
+                ..Tuning::default()
+            },
+            color_mode: ColorMode::Color,
+            text_protect: false,
+            matte: None,
+            fast_search: false,
+            metric_coverage: None,
+        };
+        let encoded = encoder(&OutputFormat::Jpeg).unwrap().encode(&source, &options).unwrap();
+        let decoded = crate::rd::decode(&encoded.output, &OutputFormat::Jpeg).unwrap();
+        assert!(crate::eval::butteraugli::distance(&source, &decoded) <= 1.5);
+        assert_eq!(encoded.vmaf_score, None);
+        assert!(encoded.distance.expect("distance check").passed);
+        let encoded = encoder(&OutputFormat::Webp).unwrap().encode(&source, &options).unwrap();
+        assert!(encoded.distance.expect("distance check").passed);
+        // A PALETTE IS KEPT LOSSLESSLY, WHATEVER THE TARGET
+        if cfg!(feature = "pure-rust") {
+            let palette = BrandPalette(Vec::new());
+            let options = EncodeOptions { palette: Some(&palette), ..options };
+            let encoded = encoder(&OutputFormat::Webp).unwrap().encode(&source, &options).unwrap();
+            let decoded = crate::rd::decode(&encoded.output, &OutputFormat::Webp).unwrap();
+            assert_eq!(decoded.to_rgb8(), source.to_rgb8());
+            assert!(encoded.distance.is_none());
+        }
+    }
+}
@@ -0,0 +1,182 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Streaming encodes, for sources too large to hold decoded (e.g. 100+ MP
+//! scans): RGB rows are pulled from a `RowProvider`, top to bottom, a few at
+//! a time, and the output is written to a sink, rather than a `Vec`.
+//!
+//! | | Peak memory (besides the codec’s own) |
+//! |---|---|
+//! | PNG (`encode_streaming`) | a row |
+//! | JPEG (`jpeg::encode_streaming`) | a row; baseline, without mozjpeg’s multi-pass tricks (trellis, optimized Huffman tables), which buffer the whole image |
+//! | WebP (`webp::encode::lossy::encode_streaming`) | libwebp’s YUV 4:2:0 picture (1.5 bytes per pixel), which it needs whole, rather than the RGBA copies of `encode` (about 8 bytes per pixel) |
+//!
+//! The encodes use fixed settings; there’s no VMAF search, since it’d need
+//! the whole image.
+use image::RgbImage;
+use std::io::{self, Read, Write};
+
+pub trait RowProvider {
+    fn dimensions(&self) -> (u32, u32);
+    /// Fills `rows` (a whole number of RGB8 rows) with the next rows.
+    fn read_rows(&mut self, rows: &mut [u8]) -> io::Result<()>;
+}
+
+impl<T: RowProvider + ?Sized> RowProvider for &mut T {
+    fn dimensions(&self) -> (u32, u32) {
+        (**self).dimensions()
+    }
+    fn read_rows(&mut self, rows: &mut [u8]) -> io::Result<()> {
+        (**self).read_rows(rows)
+    }
+}
+
+/// The rows of an image in memory.
+pub struct ImageRows<'a> {
+    image: &'a RgbImage,
+    /// The offset of the next row.
+    at: usize,
+}
+
+impl<'a> ImageRows<'a> {
+    pub fn new(image: &'a RgbImage) -> Self {
+        ImageRows { image, at: 0 }
+    }
+}
+
+impl RowProvider for ImageRows<'_> {
+    fn dimensions(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+    fn read_rows(&mut self, rows: &mut [u8]) -> io::Result<()> {
+        let source = self
+            .image
+            .as_raw()
+            .get(self.at..self.at + rows.len())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "read past the last row"))?;
+        rows.copy_from_slice(source);
+        self.at += rows.len();
+        Ok(())
+    }
+}
+
+/// The rows of a (non-interlaced) PNG, as they’re decoded; alpha is
+/// flattened over white.
+pub struct PngRows<R: Read> {
+    reader: ::png::Reader<R>,
+    color_type: ::png::ColorType,
+}
+
+impl<R: Read> PngRows<R> {
+    pub fn new(source: R) -> io::Result<Self> {
+        let mut decoder = ::png::Decoder::new(source);
+        decoder.set_transformations(::png::Transformations::normalize_to_color8());
+        let reader = decoder.read_info().map_err(io::Error::other)?;
+        if reader.info().interlaced {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "interlaced PNGs can’t be read row by row",
+            ));
+        }
+        let (color_type, _) = reader.output_color_type();
+        Ok(PngRows { reader, color_type })
+    }
+}
+
+impl<R: Read> RowProvider for PngRows<R> {
+    fn dimensions(&self) -> (u32, u32) {
+        let info = self.reader.info();
+        (info.width, info.height)
+    }
+    fn read_rows(&mut self, rows: &mut [u8]) -> io::Result<()> {
+        let width = self.dimensions().0 as usize;
+        for output in rows.chunks_exact_mut(width * 3) {
+            let row = self
+                .reader
+                .next_row()
+                .map_err(io::Error::other)?
+                .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "read past the last row"))?;
+            let over_white = |value: u8, alpha: u8| {
+                ((u32::from(value) * u32::from(alpha) + 255 * (255 - u32::from(alpha)) + 127) / 255) as u8
+            };
+            let pixels = output.chunks_exact_mut(3);
+            match self.color_type {
+                ::png::ColorType::Rgb => output.copy_from_slice(row.data()),
+                ::png::ColorType::Rgba => {
+                    for (pixel, source) in pixels.zip(row.data().chunks_exact(4)) {
+                        for c in 0..3 {
+                            pixel[c] = over_white(source[c], source[3]);
+                        }
+                    }
+                }
+                ::png::ColorType::Grayscale => {
+                    for (pixel, source) in pixels.zip(row.data()) {
+                        pixel.fill(*source);
+                    }
+                }
+                ::png::ColorType::GrayscaleAlpha => {
+                    for (pixel, source) in pixels.zip(row.data().chunks_exact(2)) {
+                        pixel.fill(over_white(source[0], source[1]));
+                    }
+                }
+                // EXPANDED BY `normalize_to_color8`
+                ::png::ColorType::Indexed => unreachable!("indexed PNG rows"),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A losslessly compressed (Deflate, Sub filter) RGB PNG.
+pub fn encode_streaming(mut source: impl RowProvider, sink: impl Write) -> io::Result<()> {
+    let (width, height) = source.dimensions();
+    let mut encoder = ::png::Encoder::new(sink, width, height);
+    encoder.set_color(::png::ColorType::Rgb);
+    encoder.set_depth(::png::BitDepth::Eight);
+    encoder.set_compression(::png::Compression::Best);
+    // NOTE: png’s `StreamWriter` keeps the filtered (not the raw) row as the
+    // previous one, which breaks the Up, Average & Paeth filters
+    encoder.set_filter(::png::FilterType::Sub);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    let mut stream = writer.stream_writer().map_err(io::Error::other)?;
+    let mut row = vec![0; width as usize * 3];
+    for _ in 0..height {
+        source.read_rows(&mut row)?;
+        stream.write_all(&row)?;
+    }
+    stream.finish().map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::GenericImageView;
+
+    #[test]
+    fn test_encode_streaming() {
+        let source = RgbImage::from_fn(33, 21, |x, y| image::Rgb([(x * 7) as u8, (y * 11) as u8, ((x ^ y) * 5) as u8]));
+        let mut png = Vec::new();
+        encode_streaming(ImageRows::new(&source), &mut png).expect("encode png");
+        assert_eq!(image::load_from_memory(&png).expect("decode png").to_rgb8(), source);
+        let mut rows = PngRows::new(png.as_slice()).expect("png rows");
+        assert_eq!(rows.dimensions(), (33, 21));
+        let mut copy = vec![0; 33 * 3 * 21];
+        rows.read_rows(&mut copy).expect("read rows");
+        assert_eq!(copy, source.as_raw().clone());
+        assert!(rows.read_rows(&mut [0; 99]).is_err());
+        #[cfg(not(feature = "pure-rust"))]
+        {
+            let mut jpeg = Vec::new();
+            crate::codec::jpeg::encode_streaming(ImageRows::new(&source), 90, &mut jpeg).expect("encode jpeg");
+            let decoded = image::load_from_memory(&jpeg).expect("decode jpeg");
+            assert_eq!(decoded.dimensions(), (33, 21));
+            let mut webp = Vec::new();
+            crate::codec::webp::encode::lossy::encode_streaming(ImageRows::new(&source), 90.0, &mut webp)
+                .expect("encode webp");
+            let decoded = crate::codec::webp::decode::decode(&webp).expect("decode webp");
+            let psnr = crate::rd::psnr(&image::DynamicImage::ImageRgb8(source), &decoded);
+            assert!(psnr > 25.0, "{}", psnr);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! TIFF outputs: lossless (Deflate) grayscale and RGB(A) via the `tiff`
+//! crate, and bilevel images in CCITT Group 4 (T.6), the compression of
+//! document archives, which the crate can’t write.
+//!
+//! Group 4 codes each row relative to the previous one (initially white),
+//! by the positions where pixels change color; rows of text mostly differ
+//! by a pixel or two from the row above, which takes one to seven bits.
+use image::{DynamicImage, GenericImageView, GrayImage};
+use std::io::Cursor;
+use tiff::encoder::colortype::{Gray8, RGB8, RGBA8};
+use tiff::encoder::compression::{Deflate, DeflateLevel};
+use tiff::encoder::TiffEncoder;
+
+///////////////////////////////////////////////////////////////////////////////
+// DEFLATE
+///////////////////////////////////////////////////////////////////////////////
+
+/// Grayscale sources stay 8 bit grayscale; others are RGB, or RGBA if they
+/// have alpha.
+pub fn encode(source: &DynamicImage) -> Vec<u8> {
+    let (width, height) = source.dimensions();
+    let mut output = Cursor::new(Vec::new());
+    let mut encoder = TiffEncoder::new(&mut output).expect("init tiff encoder");
+    let compression = || Deflate::with_level(DeflateLevel::Best);
+    let result = match source {
+        DynamicImage::ImageLuma8(gray) => {
+            encoder.write_image_with_compression::<Gray8, _>(width, height, compression(), gray)
+        }
+        _ if source.color().has_alpha() => {
+            let rgba = source.to_rgba8();
+            encoder.write_image_with_compression::<RGBA8, _>(width, height, compression(), &rgba)
+        }
+        _ => {
+            let rgb = source.to_rgb8();
+            encoder.write_image_with_compression::<RGB8, _>(width, height, compression(), &rgb)
+        }
+    };
+    result.expect("encode tiff");
+    output.into_inner()
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// GROUP 4
+///////////////////////////////////////////////////////////////////////////////
+
+/// A G4 compressed, 1 bit TIFF; pixels below 128 are black.
+pub fn encode_bilevel(source: &GrayImage) -> Vec<u8> {
+    let (width, height) = source.dimensions();
+    let data = g4(source);
+    const SHORT: u16 = 3;
+    const LONG: u16 = 4;
+    // HEADER, ONE IFD (ENTRY COUNT, ENTRIES, NO NEXT IFD), THEN ONE STRIP
+    const DATA_OFFSET: u32 = 8 + 2 + 9 * 12 + 4;
+    let entries: [(u16, u16, u32); 9] = [
+        (256, LONG, width),
+        (257, LONG, height),
+        // BITS PER SAMPLE
+        (258, SHORT, 1),
+        // COMPRESSION: CCITT T.6
+        (259, SHORT, 4),
+        // PHOTOMETRIC INTERPRETATION: WHITE IS ZERO
+        (262, SHORT, 0),
+        // STRIP OFFSETS
+        (273, LONG, DATA_OFFSET),
+        // SAMPLES PER PIXEL
+        (277, SHORT, 1),
+        // ROWS PER STRIP
+        (278, LONG, height),
+        // STRIP BYTE COUNTS
+        (279, LONG, data.len() as u32),
+    ];
+    let mut output = Vec::with_capacity(DATA_OFFSET as usize + data.len());
+    output.extend_from_slice(b"II*\0");
+    output.extend_from_slice(&8u32.to_le_bytes());
+    output.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    for (tag, kind, value) in entries {
+        output.extend_from_slice(&tag.to_le_bytes());
+        output.extend_from_slice(&kind.to_le_bytes());
+        output.extend_from_slice(&1u32.to_le_bytes());
+        // SHORTS ARE LEFT JUSTIFIED IN THE VALUE FIELD
+        match kind {
+            SHORT => output.extend_from_slice(&[(value as u16).to_le_bytes(), [0, 0]].concat()),
+            _ => output.extend_from_slice(&value.to_le_bytes()),
+        }
+    }
+    // NO NEXT IFD
+    output.extend_from_slice(&0u32.to_le_bytes());
+    output.extend_from_slice(&data);
+    output
+}
+
+struct BitWriter {
+    output: Vec<u8>,
+    buffer: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn push(&mut self, code: u16, len: u8) {
+        for ix in (0..len).rev() {
+            self.buffer = (self.buffer << 1) | u32::from((code >> ix) & 1);
+            self.bits += 1;
+            if self.bits == 8 {
+                self.output.push(self.buffer as u8);
+                self.buffer = 0;
+                self.bits = 0;
+            }
+        }
+    }
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.output.push((self.buffer << (8 - self.bits)) as u8);
+        }
+        self.output
+    }
+}
+
+/// The first position after `from` (or from 0, before the first pixel)
+/// whose color isn’t `color`; `row.len()` if none.
+fn next_other(row: &[bool], from: Option<usize>, color: bool) -> usize {
+    let start = from.map_or(0, |x| x + 1);
+    row[start.min(row.len())..]
+        .iter()
+        .position(|x| *x != color)
+        .map_or(row.len(), |x| x + start)
+}
+
+/// `b1`: the first changing element of the reference row after `a0`, to
+/// the color opposite of `color` (pixels before the row are white).
+fn next_change_to(reference: &[bool], from: Option<usize>, color: bool) -> usize {
+    let start = from.map_or(0, |x| x + 1);
+    (start..reference.len())
+        .find(|ix| {
+            let before = if *ix == 0 { false } else { reference[ix - 1] };
+            reference[*ix] != color && before == color
+        })
+        .unwrap_or(reference.len())
+}
+
+fn g4(source: &GrayImage) -> Vec<u8> {
+    let (width, height) = source.dimensions();
+    let mut writer = BitWriter {
+        output: Vec::new(),
+        buffer: 0,
+        bits: 0,
+    };
+    // `true` IS BLACK
+    let mut reference = vec![false; width as usize];
+    for y in 0..height {
+        let row = (0..width).map(|x| source.get_pixel(x, y).0[0] < 128).collect::<Vec<_>>();
+        let end = row.len();
+        let mut a0: Option<usize> = None;
+        let mut color = false;
+        loop {
+            let a1 = next_other(&row, a0, color);
+            let b1 = next_change_to(&reference, a0, color);
+            let b2 = if b1 < end { next_other(&reference, Some(b1), !color) } else { end };
+            if b2 < a1 {
+                // PASS
+                writer.push(0b0001, 4);
+                a0 = Some(b2);
+            } else if (a1 as isize - b1 as isize).abs() <= 3 {
+                let (code, len) = VERTICAL[(a1 as isize - b1 as isize + 3) as usize];
+                writer.push(code, len);
+                a0 = Some(a1);
+                color = !color;
+            } else {
+                let a2 = next_other(&row, Some(a1), !color);
+                writer.push(0b001, 3);
+                push_run(&mut writer, a1 - a0.unwrap_or(0), color);
+                push_run(&mut writer, a2 - a1, !color);
+                a0 = Some(a2);
+            }
+            if a0.is_some_and(|x| x >= end) {
+                break;
+            }
+        }
+        reference = row;
+    }
+    // END OF FACSIMILE BLOCK
+    writer.push(0b000000000001, 12);
+    writer.push(0b000000000001, 12);
+    writer.finish()
+}
+
+fn push_run(writer: &mut BitWriter, mut run: usize, black: bool) {
+    let (terminating, makeup) = if black {
+        (&BLACK_TERMINATING, &BLACK_MAKEUP)
+    } else {
+        (&WHITE_TERMINATING, &WHITE_MAKEUP)
+    };
+    while run > 2560 {
+        let (code, len) = EXTENDED_MAKEUP[EXTENDED_MAKEUP.len() - 1];
+        writer.push(code, len);
+        run -= 2560;
+    }
+    if run >= 64 {
+        let (code, len) = match run / 64 {
+            x if x <= 27 => makeup[x - 1],
+            x => EXTENDED_MAKEUP[x - 28],
+        };
+        writer.push(code, len);
+        run %= 64;
+    }
+    let (code, len) = terminating[run];
+    writer.push(code, len);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// CODES (T.4 AND T.6)
+///////////////////////////////////////////////////////////////////////////////
+
+/// `a1 - b1` from -3 to 3.
+const VERTICAL: [(u16, u8); 7] = [
+    (0b0000010, 7),
+    (0b000010, 6),
+    (0b010, 3),
+    (0b1, 1),
+    (0b011, 3),
+    (0b000011, 6),
+    (0b0000011, 7),
+];
+
+/// White runs of 0 to 63, as `(code, length)`.
+const WHITE_TERMINATING: [(u16, u8); 64] = [
+    (0b00110101, 8), (0b000111, 6), (0b0111, 4), (0b1000, 4), (0b1011, 4), (0b1100, 4), (0b1110, 4),
+    (0b1111, 4), (0b10011, 5), (0b10100, 5), (0b00111, 5), (0b01000, 5), (0b001000, 6),
+    (0b000011, 6), (0b110100, 6), (0b110101, 6), (0b101010, 6), (0b101011, 6), (0b0100111, 7),
+    (0b0001100, 7), (0b0001000, 7), (0b0010111, 7), (0b0000011, 7), (0b0000100, 7), (0b0101000, 7),
+    (0b0101011, 7), (0b0010011, 7), (0b0100100, 7), (0b0011000, 7), (0b00000010, 8),
+    (0b00000011, 8), (0b00011010, 8), (0b00011011, 8), (0b00010010, 8), (0b00010011, 8),
+    (0b00010100, 8), (0b00010101, 8), (0b00010110, 8), (0b00010111, 8), (0b00101000, 8),
+    (0b00101001, 8), (0b00101010, 8), (0b00101011, 8), (0b00101100, 8), (0b00101101, 8),
+    (0b00000100, 8), (0b00000101, 8), (0b00001010, 8), (0b00001011, 8), (0b01010010, 8),
+    (0b01010011, 8), (0b01010100, 8), (0b01010101, 8), (0b00100100, 8), (0b00100101, 8),
+    (0b01011000, 8), (0b01011001, 8), (0b01011010, 8), (0b01011011, 8), (0b01001010, 8),
+    (0b01001011, 8), (0b00110010, 8), (0b00110011, 8), (0b00110100, 8),
+];
+/// White runs of 64 to 1728, by 64.
+const WHITE_MAKEUP: [(u16, u8); 27] = [
+    (0b11011, 5), (0b10010, 5), (0b010111, 6), (0b0110111, 7), (0b00110110, 8), (0b00110111, 8),
+    (0b01100100, 8), (0b01100101, 8), (0b01101000, 8), (0b01100111, 8), (0b011001100, 9),
+    (0b011001101, 9), (0b011010010, 9), (0b011010011, 9), (0b011010100, 9), (0b011010101, 9),
+    (0b011010110, 9), (0b011010111, 9), (0b011011000, 9), (0b011011001, 9), (0b011011010, 9),
+    (0b011011011, 9), (0b010011000, 9), (0b010011001, 9), (0b010011010, 9), (0b011000, 6),
+    (0b010011011, 9),
+];
+/// Black runs of 0 to 63.
+const BLACK_TERMINATING: [(u16, u8); 64] = [
+    (0b0000110111, 10), (0b010, 3), (0b11, 2), (0b10, 2), (0b011, 3), (0b0011, 4), (0b0010, 4),
+    (0b00011, 5), (0b000101, 6), (0b000100, 6), (0b0000100, 7), (0b0000101, 7), (0b0000111, 7),
+    (0b00000100, 8), (0b00000111, 8), (0b000011000, 9), (0b0000010111, 10), (0b0000011000, 10),
+    (0b0000001000, 10), (0b00001100111, 11), (0b00001101000, 11), (0b00001101100, 11),
+    (0b00000110111, 11), (0b00000101000, 11), (0b00000010111, 11), (0b00000011000, 11),
+    (0b000011001010, 12), (0b000011001011, 12), (0b000011001100, 12), (0b000011001101, 12),
+    (0b000001101000, 12), (0b000001101001, 12), (0b000001101010, 12), (0b000001101011, 12),
+    (0b000011010010, 12), (0b000011010011, 12), (0b000011010100, 12), (0b000011010101, 12),
+    (0b000011010110, 12), (0b000011010111, 12), (0b000001101100, 12), (0b000001101101, 12),
+    (0b000011011010, 12), (0b000011011011, 12), (0b000001010100, 12), (0b000001010101, 12),
+    (0b000001010110, 12), (0b000001010111, 12), (0b000001100100, 12), (0b000001100101, 12),
+    (0b000001010010, 12), (0b000001010011, 12), (0b000000100100, 12), (0b000000110111, 12),
+    (0b000000111000, 12), (0b000000100111, 12), (0b000000101000, 12), (0b000001011000, 12),
+    (0b000001011001, 12), (0b000000101011, 12), (0b000000101100, 12), (0b000001011010, 12),
+    (0b000001100110, 12), (0b000001100111, 12),
+];
+/// Black runs of 64 to 1728, by 64.
+const BLACK_MAKEUP: [(u16, u8); 27] = [
+    (0b0000001111, 10), (0b000011001000, 12), (0b000011001001, 12), (0b000001011011, 12),
+    (0b000000110011, 12), (0b000000110100, 12), (0b000000110101, 12), (0b0000001101100, 13),
+    (0b0000001101101, 13), (0b0000001001010, 13), (0b0000001001011, 13), (0b0000001001100, 13),
+    (0b0000001001101, 13), (0b0000001110010, 13), (0b0000001110011, 13), (0b0000001110100, 13),
+    (0b0000001110101, 13), (0b0000001110110, 13), (0b0000001110111, 13), (0b0000001010010, 13),
+    (0b0000001010011, 13), (0b0000001010100, 13), (0b0000001010101, 13), (0b0000001011010, 13),
+    (0b0000001011011, 13), (0b0000001100100, 13), (0b0000001100101, 13),
+];
+/// Runs of 1792 to 2560, by 64, of either color.
+const EXTENDED_MAKEUP: [(u16, u8); 13] = [
+    (0b00000001000, 11), (0b00000001100, 11), (0b00000001101, 11), (0b000000010010, 12),
+    (0b000000010011, 12), (0b000000010100, 12), (0b000000010101, 12), (0b000000010110, 12),
+    (0b000000010111, 12), (0b000000011100, 12), (0b000000011101, 12), (0b000000011110, 12),
+    (0b000000011111, 12),
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode_bilevel() {
+        // EVERY ROW IS V0 (ONE BIT) BELOW THE WHITE REFERENCE, THEN EOFB
+        let white = GrayImage::from_pixel(300, 8, image::Luma([255]));
+        assert_eq!(g4(&white), [0xFF, 0x00, 0x10, 0x01]);
+        let output = encode_bilevel(&white);
+        assert_eq!(&output[..4], b"II*\0");
+        assert_eq!(&output[output.len() - 4..], [0xFF, 0x00, 0x10, 0x01]);
+        let source = DynamicImage::ImageLuma8(white);
+        let decoded = image::load_from_memory(&encode(&source)).expect("decode tiff");
+        assert_eq!(decoded.to_luma8(), source.to_luma8());
+    }
+}
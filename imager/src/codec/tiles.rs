@@ -0,0 +1,112 @@
+//! Scoring very large sources on a sample of their tiles (see
+//! `--metric-coverage`), since VMAF of the full frame dominates the cost
+//! of the searches on e.g. 50MP photos.
+//!
+//! Most sampled tiles are the most detailed ones, where compression
+//! artifacts show first; the rest are spread over the flatter remainder,
+//! where banding does. The source and every output are scored as a mosaic
+//! of the same tiles.
+use image::{imageops, DynamicImage, GenericImageView, GrayImage, RgbImage};
+
+/// Sources of fewer pixels are scored whole.
+pub const MIN_PIXELS: u64 = 16_000_000;
+
+/// The side of tiles.
+pub const TILE: u32 = 256;
+
+/// Of the sampled tiles, the share spread over the less detailed ones.
+const SPREAD_SHARE: f64 = 0.25;
+
+/// The tiles (their top left corners) to score, in reading order.
+#[derive(Debug, Clone)]
+pub struct Tiles {
+    corners: Vec<(u32, u32)>,
+}
+
+impl Tiles {
+    /// The `coverage` (a share of the whole tiles, above 0 and below 1) of
+    /// a source of at least `MIN_PIXELS`; none if scored whole.
+    pub fn sample(source: &DynamicImage, coverage: f64) -> Option<Self> {
+        let (width, height) = source.dimensions();
+        if (width as u64 * height as u64) < MIN_PIXELS || !(coverage > 0.0 && coverage < 1.0) {
+            return None;
+        }
+        let luma = source.to_luma8();
+        let mut tiles = Vec::new();
+        for y in (0..height / TILE).map(|x| x * TILE) {
+            for x in (0..width / TILE).map(|x| x * TILE) {
+                tiles.push(((x, y), detail(&luma, (x, y))));
+            }
+        }
+        if tiles.is_empty() {
+            return None;
+        }
+        // MOST DETAILED FIRST; TIES IN READING ORDER
+        tiles.sort_by_key(|x| std::cmp::Reverse(x.1));
+        let count = ((tiles.len() as f64 * coverage).ceil() as usize).clamp(1, tiles.len());
+        let spread = (count as f64 * SPREAD_SHARE) as usize;
+        let detailed = count - spread;
+        let rest = &tiles[detailed..];
+        let mut corners = tiles[..detailed].iter().map(|x| x.0).collect::<Vec<_>>();
+        corners.extend((0..spread).map(|i| rest[i * rest.len() / spread].0));
+        corners.sort_by_key(|&(x, y)| (y, x));
+        Some(Tiles { corners })
+    }
+    pub fn len(&self) -> usize {
+        self.corners.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.corners.is_empty()
+    }
+    /// The tiles of the image (of the source’s size), in a grid of about
+    /// as many columns as rows.
+    pub fn mosaic(&self, image: &DynamicImage) -> DynamicImage {
+        let columns = (self.corners.len() as f64).sqrt().ceil() as u32;
+        let rows = (self.corners.len() as u32).div_ceil(columns);
+        let mut output = RgbImage::new(columns * TILE, rows * TILE);
+        for (index, &(x, y)) in self.corners.iter().enumerate() {
+            let tile = image.crop_imm(x, y, TILE, TILE).to_rgb8();
+            let (column, row) = (index as u32 % columns, index as u32 / columns);
+            imageops::replace(&mut output, &tile, (column * TILE) as i64, (row * TILE) as i64);
+        }
+        DynamicImage::ImageRgb8(output)
+    }
+}
+
+/// The sum of the luma gradients of the tile, on every other pixel.
+fn detail(luma: &GrayImage, (x0, y0): (u32, u32)) -> u64 {
+    let mut sum = 0;
+    for y in (y0..y0 + TILE - 1).step_by(2) {
+        for x in (x0..x0 + TILE - 1).step_by(2) {
+            let px = luma.get_pixel(x, y).0[0] as i32;
+            let right = luma.get_pixel(x + 1, y).0[0] as i32;
+            let below = luma.get_pixel(x, y + 1).0[0] as i32;
+            sum += ((right - px).abs() + (below - px).abs()) as u64;
+        }
+    }
+    sum
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tiles() {
+        // DETAILED ON THE LEFT, FLAT ON THE RIGHT
+        let source = DynamicImage::ImageRgb8(RgbImage::from_fn(4096, 4096, |x, y| {
+            let value = if x < 2048 { ((x * 7 + y * 13) % 256) as u8 } else { 128 };
+            image::Rgb([value, value, value])
+        }));
+        let tiles = Tiles::sample(&source, 0.1).expect("tiles");
+        assert_eq!(tiles.len(), 26);
+        let left = tiles.corners.iter().filter(|(x, _)| *x < 2048).count();
+        assert!((20..26).contains(&left), "{}", left);
+        let mosaic = tiles.mosaic(&source);
+        assert_eq!(mosaic.dimensions(), (6 * TILE, 5 * TILE));
+        let (x, y) = tiles.corners[0];
+        assert_eq!(mosaic.to_rgb8().get_pixel(0, 0), source.to_rgb8().get_pixel(x, y));
+        assert!(Tiles::sample(&source, 1.0).is_none());
+        assert!(Tiles::sample(&DynamicImage::new_rgb8(1024, 1024), 0.1).is_none());
+    }
+}
@@ -1,18 +1,29 @@
 use image::{DynamicImage, GenericImage, GenericImageView, ImageBuffer, RgbaImage};
 use libc::{c_float, size_t};
+#[cfg(not(feature = "pure-rust"))]
 use libwebp_sys::WebPDecodeRGBA;
 use std::ffi::{c_void, CString};
 use std::os::raw::{c_char, c_int};
 
-#[must_use] pub fn decode(source: &[u8]) -> DynamicImage {
+use crate::error::ImagerError;
+
+#[cfg(not(feature = "pure-rust"))]
+pub fn decode(source: &[u8]) -> Result<DynamicImage, ImagerError> {
     let mut width: i32 = 0;
     let mut height: i32 = 0;
     let decoded = unsafe { WebPDecodeRGBA(source.as_ptr(), source.len(), &mut width, &mut height) };
-    assert!(!decoded.is_null());
-    assert!(width != 0 && height != 0);
+    if decoded.is_null() || width == 0 || height == 0 {
+        return Err(ImagerError::decode("libwebp failed to decode the WebP"));
+    }
     let (width, height) = (width as u32, height as u32);
     let size = (width * height * 4) as usize;
     let output = unsafe { std::slice::from_raw_parts_mut(decoded, size).to_vec() };
     let media: RgbaImage = ImageBuffer::from_vec(width, height, output).expect("to ImageBuffer");
-    DynamicImage::ImageRgba8(media)
+    Ok(DynamicImage::ImageRgba8(media))
+}
+
+/// The `image` crate’s decoder (of `pure-rust` builds).
+#[cfg(feature = "pure-rust")]
+pub fn decode(source: &[u8]) -> Result<DynamicImage, ImagerError> {
+    Ok(::image::load_from_memory_with_format(source, ::image::ImageFormat::WebP)?)
 }
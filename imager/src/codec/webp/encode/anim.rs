@@ -0,0 +1,97 @@
+//! Animated WebP, via libwebp’s `WebPAnimEncoder`, which encodes only the
+//! changed rectangle of each frame (and picks lossy or lossless per frame
+//! when allowed).
+use image::DynamicImage;
+use libwebp_sys::{
+    WebPAnimEncoderAdd, WebPAnimEncoderAssemble, WebPAnimEncoderDelete, WebPAnimEncoderGetError,
+    WebPAnimEncoderNewInternal, WebPAnimEncoderOptions, WebPAnimEncoderOptionsInitInternal, WebPData, WebPDataClear,
+    WebPGetMuxABIVersion, WebPMemoryWriterClear, WebPPictureFree, WEBP_MAX_DIMENSION,
+};
+use std::ffi::CStr;
+use std::os::raw::c_int;
+
+use crate::codec::gif::Frame;
+use crate::error::{ImagerError, Result};
+
+/// Of the frames (of the same size), looping forever; lossless if no
+/// quality is given.
+pub fn encode(frames: &[Frame], quality: Option<f32>) -> Result<Vec<u8>> {
+    let first = frames
+        .first()
+        .ok_or_else(|| ImagerError::InvalidInput(String::from("no frames")))?;
+    let (width, height) = first.image.dimensions();
+    if width >= WEBP_MAX_DIMENSION || height >= WEBP_MAX_DIMENSION {
+        let message = format!("{}x{} is beyond WebP’s dimensions", width, height);
+        return Err(ImagerError::InvalidInput(message));
+    }
+    if frames.iter().any(|x| x.image.dimensions() != (width, height)) {
+        return Err(ImagerError::InvalidInput(String::from("frames differ in size")));
+    }
+    let mut config = match quality {
+        Some(quality) => super::lossy::init_config(quality),
+        None => super::lossless::init_config(),
+    };
+    // METHOD 6 IS TOO SLOW FOR EVERY FRAME
+    config.method = 4;
+    unsafe {
+        let mut options: WebPAnimEncoderOptions = std::mem::zeroed();
+        assert_ne!(
+            WebPAnimEncoderOptionsInitInternal(&mut options, WebPGetMuxABIVersion()),
+            0
+        );
+        options.anim_params.loop_count = 0;
+        let encoder = WebPAnimEncoderNewInternal(width as c_int, height as c_int, &options, WebPGetMuxABIVersion());
+        if encoder.is_null() {
+            return Err(ImagerError::Encode(String::from("failed to create the WebP animation encoder")));
+        }
+        let error = || {
+            let message = CStr::from_ptr(WebPAnimEncoderGetError(encoder)).to_string_lossy();
+            ImagerError::Encode(message.into_owned())
+        };
+        let mut timestamp = 0;
+        let mut result = Ok(());
+        for frame in frames {
+            let source = DynamicImage::ImageRgba8(frame.image.clone());
+            let (mut picture, writer) = super::lossless::init_picture(&source);
+            let added = WebPAnimEncoderAdd(encoder, &mut picture, timestamp, &config);
+            WebPPictureFree(&mut picture);
+            WebPMemoryWriterClear(writer);
+            drop(Box::from_raw(writer));
+            if added == 0 {
+                result = Err(error());
+                break;
+            }
+            timestamp += frame.delay.as_millis() as c_int;
+        }
+        // THE END OF THE LAST FRAME
+        if result.is_ok() && WebPAnimEncoderAdd(encoder, std::ptr::null_mut(), timestamp, std::ptr::null()) == 0 {
+            result = Err(error());
+        }
+        let mut data: WebPData = std::mem::zeroed();
+        if result.is_ok() && WebPAnimEncoderAssemble(encoder, &mut data) == 0 {
+            result = Err(error());
+        }
+        let output = result.map(|()| std::slice::from_raw_parts(data.bytes, data.size).to_vec());
+        WebPDataClear(&mut data);
+        WebPAnimEncoderDelete(encoder);
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_anim() {
+        let frames = crate::codec::gif::decode_frames(&crate::codec::gif::test::animated_gif()).expect("decode");
+        for quality in [None, Some(75.0)] {
+            let output = encode(&frames, quality).expect("encode");
+            assert_eq!(&output[..4], b"RIFF");
+            assert!(output.windows(4).any(|x| x == b"ANMF"));
+        }
+        let mut resized = frames.clone();
+        resized[1].image = image::RgbaImage::new(4, 4);
+        assert!(matches!(encode(&resized, None), Err(ImagerError::InvalidInput(_))));
+    }
+}
@@ -8,7 +8,15 @@ use libwebp_sys::{
 use std::ffi::{c_void, CString};
 use std::os::raw::{c_char, c_int};
 
+use crate::data::{Yuva420P, MAX_WEBP_EFFORT};
+
 #[must_use] pub fn init_config(q: f32) -> WebPConfig {
+    init_config_with_effort(q, MAX_WEBP_EFFORT)
+}
+
+/// The `effort` is libwebp’s `method`, from 0 (the fastest) to
+/// `MAX_WEBP_EFFORT`.
+#[must_use] pub fn init_config_with_effort(q: f32, effort: u8) -> WebPConfig {
     let mut config: WebPConfig = unsafe { std::mem::zeroed() };
     unsafe {
         WebPConfigInitInternal(
@@ -21,7 +29,7 @@ use std::os::raw::{c_char, c_int};
     };
     config.quality = q;
     config.lossless = 0;
-    config.method = 6;
+    config.method = c_int::from(effort.min(MAX_WEBP_EFFORT));
     config
 }
 
@@ -59,3 +67,144 @@ use std::os::raw::{c_char, c_int};
     // DONE
     output
 }
+
+/// Of the planes as they are, e.g. to encode one conversion at many
+/// qualities (or efforts, see `init_config_with_effort`); the alpha plane
+/// (if any) is compressed losslessly.
+#[must_use] pub fn encode_yuva(source: &Yuva420P, q: f32, effort: u8) -> Vec<u8> {
+    use libwebp_sys::{WebPEncCSP, WebPMemoryWrite, WebPMemoryWriterInit, WebPPictureAlloc, WebPPictureInit};
+    let config = init_config_with_effort(q, effort);
+    let mut writer: WebPMemoryWriter = unsafe { std::mem::zeroed() };
+    let mut picture: WebPPicture = unsafe { std::mem::zeroed() };
+    unsafe {
+        WebPMemoryWriterInit(&mut writer);
+        assert!(WebPPictureInit(&mut picture));
+        picture.use_argb = 0;
+        picture.colorspace = match source.a {
+            Some(_) => WebPEncCSP::WEBP_YUV420A,
+            None => WebPEncCSP::WEBP_YUV420,
+        };
+        picture.width = source.width as c_int;
+        picture.height = source.height as c_int;
+        assert_ne!(WebPPictureAlloc(&mut picture), 0);
+        picture.writer = Some(WebPMemoryWrite);
+        picture.custom_ptr = &mut writer as *mut WebPMemoryWriter as *mut c_void;
+    };
+    let copy = |plane: &[u8], data: *mut u8, stride: c_int, width: u32| unsafe {
+        for (row, pixels) in plane.chunks_exact(width as usize).enumerate() {
+            std::ptr::copy_nonoverlapping(pixels.as_ptr(), data.add(row * stride as usize), pixels.len());
+        }
+    };
+    let chroma_width = source.chroma_dimensions().0;
+    copy(&source.y, picture.y, picture.y_stride, source.width);
+    copy(&source.u, picture.u, picture.uv_stride, chroma_width);
+    copy(&source.v, picture.v, picture.uv_stride, chroma_width);
+    if let Some(alpha) = &source.a {
+        copy(alpha, picture.a, picture.a_stride, source.width);
+    }
+    unsafe {
+        assert_ne!(WebPEncode(&config, &mut picture), 0);
+        let output = std::slice::from_raw_parts(writer.mem, writer.size).to_vec();
+        WebPPictureFree(&mut picture);
+        WebPMemoryWriterClear(&mut writer);
+        output
+    }
+}
+
+/// The sink of `encode_streaming`, as the picture’s `custom_ptr`.
+struct Sink<'a> {
+    sink: &'a mut dyn std::io::Write,
+    error: Option<std::io::Error>,
+}
+
+/// Fills libwebp’s YUV 4:2:0 picture two rows at a time (converting as
+/// libwebp’s `WebPPictureImportRGB` does), rather than from RGBA copies of
+/// the whole image, and writes the output to the sink (see
+/// `codec::stream`).
+pub fn encode_streaming(
+    mut source: impl crate::codec::stream::RowProvider,
+    q: f32,
+    mut sink: impl std::io::Write,
+) -> std::io::Result<()> {
+    use libwebp_sys::{WebPEncCSP, WebPPictureAlloc, WebPPictureInit, WEBP_MAX_DIMENSION};
+    let (width, height) = source.dimensions();
+    if width == 0 || height == 0 || width >= WEBP_MAX_DIMENSION || height >= WEBP_MAX_DIMENSION {
+        let message = format!("{}x{} is beyond WebP’s dimensions", width, height);
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, message));
+    }
+    let config = init_config(q);
+    let mut sink = Sink {
+        sink: &mut sink,
+        error: None,
+    };
+    unsafe extern "C" fn on_write(data: *const u8, data_size: usize, picture: *const WebPPicture) -> c_int {
+        let sink = &mut *((*picture).custom_ptr as *mut Sink);
+        let data = std::slice::from_raw_parts(data, data_size);
+        match sink.sink.write_all(data) {
+            Ok(()) => 1,
+            Err(error) => {
+                sink.error = Some(error);
+                0
+            }
+        }
+    }
+    unsafe {
+        let mut picture: WebPPicture = std::mem::zeroed();
+        assert!(WebPPictureInit(&mut picture));
+        picture.use_argb = 0;
+        picture.colorspace = WebPEncCSP::WEBP_YUV420;
+        picture.width = width as c_int;
+        picture.height = height as c_int;
+        if WebPPictureAlloc(&mut picture) == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::OutOfMemory, "failed to allocate the WebP picture"));
+        }
+        picture.writer = Some(on_write);
+        picture.custom_ptr = &mut sink as *mut Sink as *mut c_void;
+        let result = (|| {
+            let (width, height) = (width as usize, height as usize);
+            let (y_stride, uv_stride) = (picture.y_stride as usize, picture.uv_stride as usize);
+            let mut rows = vec![0u8; width * 3 * 2];
+            for pair in 0..height.div_ceil(2) {
+                // AN ODD LAST ROW IS REPEATED FOR ITS CHROMA
+                let count = (height - pair * 2).min(2);
+                source.read_rows(&mut rows[..width * 3 * count])?;
+                if count == 1 {
+                    rows.copy_within(..width * 3, width * 3);
+                }
+                let pixel = |row: usize, x: usize| {
+                    let ix = row * width * 3 + x.min(width - 1) * 3;
+                    (i32::from(rows[ix]), i32::from(rows[ix + 1]), i32::from(rows[ix + 2]))
+                };
+                for row in 0..count {
+                    let y = picture.y.add((pair * 2 + row) * y_stride);
+                    for x in 0..width {
+                        let (r, g, b) = pixel(row, x);
+                        *y.add(x) = ((16839 * r + 33059 * g + 6420 * b + (1 << 15) + (16 << 16)) >> 16) as u8;
+                    }
+                }
+                let (u, v) = (picture.u.add(pair * uv_stride), picture.v.add(pair * uv_stride));
+                for x in 0..width.div_ceil(2) {
+                    let (mut r, mut g, mut b) = (0, 0, 0);
+                    for (row, column) in [(0, 2 * x), (0, 2 * x + 1), (1, 2 * x), (1, 2 * x + 1)] {
+                        let (pr, pg, pb) = pixel(row, column);
+                        r += pr;
+                        g += pg;
+                        b += pb;
+                    }
+                    // OF THE SUMS OF 4, SO SHIFTED 2 MORE
+                    let rounding = (1 << 17) + (128 << 18);
+                    *u.add(x) = ((-9719 * r - 19081 * g + 28800 * b + rounding) >> 18) as u8;
+                    *v.add(x) = ((28800 * r - 24116 * g - 4684 * b + rounding) >> 18) as u8;
+                }
+            }
+            if WebPEncode(&config, &mut picture) == 0 {
+                return Err(sink.error.take().unwrap_or_else(|| {
+                    std::io::Error::other(format!("libwebp failed to encode ({:?})", picture.error_code))
+                }));
+            }
+            Ok(())
+        })();
+        WebPPictureFree(&mut picture);
+        result
+    }
+}
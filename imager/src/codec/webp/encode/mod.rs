@@ -1,2 +1,9 @@
+pub mod anim;
 pub mod lossless;
 pub mod lossy;
+
+/// Of the linked libwebp’s encoder, e.g. `1.2.4`.
+pub fn version() -> String {
+    let version = unsafe { libwebp_sys::WebPGetEncoderVersion() };
+    format!("{}.{}.{}", (version >> 16) & 0xff, (version >> 8) & 0xff, version & 0xff)
+}
@@ -1,3 +1,5 @@
 pub mod decode;
+#[cfg(not(feature = "pure-rust"))]
 pub mod encode;
+#[cfg(not(feature = "pure-rust"))]
 pub mod opt;
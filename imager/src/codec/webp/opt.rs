@@ -1,6 +1,8 @@
 use crate::classifier::{self, Class};
-use crate::codec::webp::encode::lossy::encode;
-use crate::data::{VideoBuffer, Yuv420P};
+use crate::codec::proxy;
+use crate::codec::tiles::Tiles;
+use crate::codec::webp::encode::lossy::encode_yuva;
+use crate::data::{Matte, QualityRange, VideoBuffer, Yuv420P, Yuva420P, MAX_WEBP_EFFORT};
 use crate::vmaf;
 use image::{DynamicImage, GenericImage, GenericImageView};
 use itertools::Itertools;
@@ -18,21 +20,66 @@ pub struct OutMeta {
     pub output_path: Option<PathBuf>,
 }
 
-#[must_use] pub fn opt(source: &DynamicImage) -> (Vec<u8>, OutMeta) {
+/// Searches the `range`, falling back to its maximum; transparency is
+/// kept.
+#[must_use] pub fn opt(source: &DynamicImage, range: QualityRange) -> (Vec<u8>, OutMeta) {
+    opt_with_matte(source, range, MAX_WEBP_EFFORT, None, None, false, None)
+}
+
+/// Over the matte, e.g. for VMAF, which has no alpha; the colors of
+/// invisible pixels (which libwebp alters) then don’t count.
+pub fn flatten(image: &DynamicImage, matte: Matte) -> DynamicImage {
+    crate::composite::over(image, matte)
+}
+
+/// Like `opt`; with a matte, transparency is composited onto it, rather
+/// than kept, and with a VMAF target, it replaces the class (and size)
+/// dependent threshold. A fast search starts near the quality a search on
+/// a low-resolution proxy found (see `proxy`), and with a metric coverage,
+/// very large sources are scored on a sample of their tiles (see `tiles`).
+/// The source is converted to YUV(A) once, for every quality, and encoded
+/// at the `effort` (see `encode::lossy::init_config_with_effort`).
+#[must_use] pub fn opt_with_matte(
+    source: &DynamicImage,
+    range: QualityRange,
+    effort: u8,
+    matte: Option<Matte>,
+    vmaf_target: Option<f64>,
+    fast_search: bool,
+    metric_coverage: Option<f64>,
+) -> (Vec<u8>, OutMeta) {
     let class = classifier::report(source);
-    let vmaf_source = VideoBuffer::from_image(source).expect("image to yuv frame");
+    let picture = Yuva420P::from_image(source).expect("image to yuva picture");
+    let picture = match matte {
+        Some(matte) => picture.flatten(matte),
+        None => picture,
+    };
+    let transparent = !picture.is_opaque();
+    let score_matte = matte.unwrap_or_default();
+    let tiles = metric_coverage.and_then(|x| Tiles::sample(source, x));
+    let scored = |image: &DynamicImage| {
+        let image = match transparent || matte.is_some() {
+            true => flatten(image, score_matte),
+            false => image.clone(),
+        };
+        match &tiles {
+            Some(tiles) => tiles.mosaic(&image),
+            None => image,
+        }
+    };
+    let vmaf_source = VideoBuffer::from_image(&scored(source)).expect("image to yuv frame");
     let run = |q: f32| -> (Vec<u8>, f64) {
-        let compressed = encode(source, q);
+        let compressed = encode_yuva(&picture, q, effort);
         let score = {
-            let vmaf_derivative = crate::codec::webp::decode::decode(&compressed);
+            let vmaf_derivative = crate::codec::webp::decode::decode(&compressed).expect("decode webp");
             let vmaf_derivative =
-                VideoBuffer::from_image(&vmaf_derivative).expect("image to yuv frame");
+                VideoBuffer::from_image(&scored(&vmaf_derivative)).expect("image to yuv frame");
             vmaf::get_report(&vmaf_source, &vmaf_derivative)
         };
         (compressed, score)
     };
     let fallback = |end_q, score| {
-        let compressed = encode(source, 100.0);
+        let compressed = encode_yuva(&picture, f32::from(range.max), effort);
         let meta = OutMeta {
             class: class.class.clone(),
             score,
@@ -43,38 +90,19 @@ pub struct OutMeta {
         };
         (compressed, meta)
     };
-    let terminate = |score: f64| {
-        let (width, height) = source.dimensions();
-        let is_small = { (width * height) < (600 * 600) };
-        let mut threshold;
-        match class.class {
-            Class::L0 | Class::L1 | Class::L2 if is_small => {
-                threshold = 99.0;
-            }
-            Class::L0 | Class::L1 | Class::L2 => {
-                threshold = 95.0;
-            }
-            Class::M1 => {
-                if is_small {
-                    threshold = 98.0;
-                } else {
-                    threshold = 90.0;
-                }
-            }
-            Class::H1 | Class::H2 if is_small => {
-                threshold = 70.0;
-            }
-            Class::H1 => {
-                threshold = 60.0;
-            }
-            Class::H2 => {
-                threshold = 55.0;
-            }
+    let (width, height) = source.dimensions();
+    let threshold = vmaf_target.unwrap_or_else(|| threshold(&class.class, (width * height) < (600 * 600)));
+    let terminate = |score: f64| score >= threshold;
+    // OF THE PROXY, HELD TO THE SOURCE’S THRESHOLD
+    let proxy_start = match proxy::proxy(source) {
+        Some(proxy) if fast_search => {
+            let (_, meta) = opt_with_matte(&proxy, range, effort, matte, Some(threshold), false, None);
+            meta.passed.then(|| proxy::bracket(range, meta.end_q.min(100) as u8).min)
         }
-        score >= threshold
+        _ => None,
     };
     // SEARCH
-    let start_q = {
+    let start_q = proxy_start.or_else(|| {
         let reduce_starting_values = |qs: Vec<u8>| -> Option<u8> {
             let mut last_q = 0;
             for q in qs {
@@ -97,11 +125,11 @@ pub struct OutMeta {
             Class::L0 | Class::L1 | Class::L2 => bad_fallback_low_range(),
             _ => bad_fallback(),
         }
-    };
-    let start_q = u32::from(start_q.unwrap_or(1));
+    });
+    let start_q = u32::from(range.clamp(start_q.unwrap_or(1)));
     let mut last_q = None;
     let mut last_score = None;
-    for q in start_q..100 {
+    for q in start_q..u32::from(range.max) {
         let (compressed, score) = run(q as f32);
         last_q = Some(q);
         last_score = Some(score);
@@ -118,7 +146,21 @@ pub struct OutMeta {
         }
     }
     // FALLBACK
-    let last_q = last_q.expect("should run at least once");
-    let last_score = last_score.expect("should run at least once");
+    // E.G. A RANGE OF ONE QUALITY
+    let last_q = last_q.unwrap_or(u32::from(range.max));
+    let last_score = last_score.unwrap_or(0.0);
     fallback(last_q, last_score)
 }
+
+/// The VMAF score outputs must reach.
+fn threshold(class: &Class, is_small: bool) -> f64 {
+    match class {
+        Class::L0 | Class::L1 | Class::L2 if is_small => 99.0,
+        Class::L0 | Class::L1 | Class::L2 => 95.0,
+        Class::M1 if is_small => 98.0,
+        Class::M1 => 90.0,
+        Class::H1 | Class::H2 if is_small => 70.0,
+        Class::H1 => 60.0,
+        Class::H2 => 55.0,
+    }
+}
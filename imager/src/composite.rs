@@ -0,0 +1,50 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Compositing transparency onto an opaque matte (by default, white), for
+//! outputs without alpha, e.g. JPEG: dropping the alpha instead would show
+//! the colors of invisible pixels, typically black, as fringes around
+//! anti-aliased edges.
+use image::DynamicImage;
+
+use crate::data::Matte;
+
+/// The image over the matte (“over”, in sRGB), without alpha.
+pub fn over(image: &DynamicImage, Matte(matte): Matte) -> DynamicImage {
+    let mut output = image.to_rgb8();
+    if image.color().has_alpha() {
+        for (px, source) in output.pixels_mut().zip(image.to_rgba8().pixels()) {
+            let alpha = u32::from(source.0[3]);
+            for (channel, matte) in px.0.iter_mut().zip(matte) {
+                *channel = ((u32::from(*channel) * alpha + u32::from(matte) * (255 - alpha) + 127) / 255) as u8;
+            }
+        }
+    }
+    DynamicImage::ImageRgb8(output)
+}
+
+/// The image over the matte, if it has alpha; else as is.
+pub fn flatten(image: &DynamicImage, matte: Matte) -> DynamicImage {
+    match image.color().has_alpha() {
+        true => over(image, matte),
+        false => image.clone(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_over() {
+        // INVISIBLE BLACK, HALF-TRANSPARENT RED, OPAQUE BLUE
+        let pixels = [[0, 0, 0, 0], [255, 0, 0, 128], [0, 0, 255, 255]];
+        let source = image::RgbaImage::from_fn(3, 1, |x, _| image::Rgba(pixels[x as usize]));
+        let output = over(&DynamicImage::ImageRgba8(source), Matte::default()).to_rgb8();
+        assert_eq!(output.get_pixel(0, 0).0, [255, 255, 255]);
+        assert_eq!(output.get_pixel(1, 0).0, [255, 127, 127]);
+        assert_eq!(output.get_pixel(2, 0).0, [0, 0, 255]);
+        let opaque = DynamicImage::new_luma8(2, 2);
+        assert_eq!(flatten(&opaque, Matte([9, 9, 9])), opaque);
+    }
+}
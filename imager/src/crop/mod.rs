@@ -0,0 +1,36 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Crop windows, i.e. which part of a `cover` resize is kept (see
+//! `resize`): the centre, or (see `smart`) the part with the content.
+pub mod smart;
+
+pub use imager_core::data::Crop;
+
+use image::{DynamicImage, GenericImageView};
+
+/// The offset of the `width`x`height` window of the image to keep.
+pub fn window(image: &DynamicImage, (width, height): (u32, u32), crop: Crop) -> (u32, u32) {
+    let width = width.min(image.width());
+    let height = height.min(image.height());
+    match crop {
+        Crop::Centre => ((image.width() - width) / 2, (image.height() - height) / 2),
+        Crop::Entropy => smart::entropy(image, (width, height)),
+        Crop::Attention => smart::attention(image, (width, height)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_window() {
+        let image = DynamicImage::new_rgb8(400, 100);
+        assert_eq!(window(&image, (100, 100), Crop::Centre), (150, 0));
+        assert_eq!(window(&image, (500, 50), Crop::Centre), (0, 25));
+        // FLAT IMAGES HAVE NOTHING TO FIND, SO CENTERED
+        assert_eq!(window(&image, (100, 100), Crop::Entropy), (150, 0));
+        assert_eq!(window(&image, (100, 100), Crop::Attention), (150, 0));
+    }
+}
@@ -0,0 +1,216 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Content-aware crop windows, so `cover` thumbnails keep the subject
+//! rather than whatever is in the middle.
+//!
+//! - `entropy` (as libvips’): trims, a slice at a time, whichever edge is
+//!   the less busy (of the lower luma entropy), until the window is left.
+//! - `attention`: slides the window over a map of interest and keeps the
+//!   most interesting one, where each pixel’s interest is its edge
+//!   strength (detail), plus bonuses for skin tones (faces) and saturated
+//!   colors (subjects, rather than backdrops).
+//!
+//! Both run on a copy of at most `ANALYSIS_SIZE` a side, and center the
+//! window where there’s nothing to find (e.g. flat images) or on ties.
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, GrayImage, RgbImage};
+
+/// The larger side of the copy windows are chosen on.
+pub const ANALYSIS_SIZE: u32 = 256;
+/// Of every skin toned pixel, on the scale of edge strengths (0-510).
+const SKIN_BONUS: u64 = 128;
+/// Of the saturation (the max minus the min channel, 0-255).
+const SATURATION_WEIGHT: f64 = 0.25;
+
+/// The copy, and its scale (full size pixels per copy pixel) per axis.
+struct Analysis {
+    image: RgbImage,
+    scale: (f64, f64),
+}
+
+impl Analysis {
+    fn new(source: &DynamicImage) -> Self {
+        let (width, height) = source.dimensions();
+        let ratio = (ANALYSIS_SIZE as f64 / width.max(height) as f64).min(1.0);
+        let size = |side: u32| ((side as f64 * ratio).round() as u32).max(1);
+        let image = source
+            .resize_exact(size(width), size(height), FilterType::Triangle)
+            .to_rgb8();
+        let scale = (
+            width as f64 / image.width() as f64,
+            height as f64 / image.height() as f64,
+        );
+        Analysis { image, scale }
+    }
+    /// Of the full size window.
+    fn window(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        let side = |length: u32, scale: f64, limit: u32| ((length as f64 / scale).round() as u32).clamp(1, limit);
+        (
+            side(width, self.scale.0, self.image.width()),
+            side(height, self.scale.1, self.image.height()),
+        )
+    }
+    /// Of the full size image, where the window is at `(x, y)` of the copy.
+    fn offset(&self, source: &DynamicImage, (width, height): (u32, u32), (x, y): (u32, u32)) -> (u32, u32) {
+        let full = |offset: u32, scale: f64, limit: u32| ((offset as f64 * scale).round() as u32).min(limit);
+        (
+            full(x, self.scale.0, source.width() - width),
+            full(y, self.scale.1, source.height() - height),
+        )
+    }
+}
+
+fn centered(source: &DynamicImage, (width, height): (u32, u32)) -> (u32, u32) {
+    ((source.width() - width) / 2, (source.height() - height) / 2)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ENTROPY
+///////////////////////////////////////////////////////////////////////////////
+
+/// The offset of the window (no larger than the source) to keep.
+pub fn entropy(source: &DynamicImage, window: (u32, u32)) -> (u32, u32) {
+    let analysis = Analysis::new(source);
+    let luma = DynamicImage::ImageRgb8(analysis.image.clone()).to_luma8();
+    let flat = luma.pixels().all(|px| px.0 == luma.get_pixel(0, 0).0);
+    if flat {
+        return centered(source, window);
+    }
+    let (width, height) = analysis.window(window);
+    let (left, right) = trim(width, luma.width(), |xs| region_entropy(&luma, xs, 0..luma.height()));
+    let (top, _) = trim(height, luma.height(), |ys| region_entropy(&luma, left..right, ys));
+    analysis.offset(source, window, (left, top))
+}
+
+/// The span of `length` left of `0..size`, by trimming the edge slice of
+/// the lower score (the one trimmed less, on ties).
+fn trim(length: u32, size: u32, score: impl Fn(std::ops::Range<u32>) -> f64) -> (u32, u32) {
+    let (mut start, mut end) = (0, size);
+    while end - start > length {
+        let slice = (end - start - length).min(((end - start) / 16).max(1));
+        let first = score(start..start + slice);
+        let last = score(end - slice..end);
+        if first < last || (first == last && start <= size - end) {
+            start += slice;
+        } else {
+            end -= slice;
+        }
+    }
+    (start, end)
+}
+
+/// In bits.
+fn region_entropy(luma: &GrayImage, xs: std::ops::Range<u32>, ys: std::ops::Range<u32>) -> f64 {
+    let mut histogram = [0u32; 256];
+    for y in ys {
+        for x in xs.clone() {
+            histogram[luma.get_pixel(x, y).0[0] as usize] += 1;
+        }
+    }
+    let total = histogram.iter().sum::<u32>() as f64;
+    histogram
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ATTENTION
+///////////////////////////////////////////////////////////////////////////////
+
+/// The offset of the window (no larger than the source) to keep.
+pub fn attention(source: &DynamicImage, window: (u32, u32)) -> (u32, u32) {
+    let analysis = Analysis::new(source);
+    let (image_width, image_height) = analysis.image.dimensions();
+    let interest = interest(&analysis.image);
+    // SUMMED AREA TABLE, WITH A ROW AND COLUMN OF ZEROS
+    let stride = image_width as usize + 1;
+    let mut table = vec![0u64; stride * (image_height as usize + 1)];
+    for y in 0..image_height as usize {
+        let mut row = 0;
+        for x in 0..image_width as usize {
+            row += interest[y * image_width as usize + x];
+            table[(y + 1) * stride + x + 1] = table[y * stride + x + 1] + row;
+        }
+    }
+    let (width, height) = analysis.window(window);
+    let sum = |x: u32, y: u32| {
+        let (x0, y0, x1, y1) = (x as usize, y as usize, (x + width) as usize, (y + height) as usize);
+        table[y1 * stride + x1] + table[y0 * stride + x0] - table[y0 * stride + x1] - table[y1 * stride + x0]
+    };
+    let center = ((image_width - width) / 2, (image_height - height) / 2);
+    let distance = |(x, y): (u32, u32)| x.abs_diff(center.0) + y.abs_diff(center.1);
+    let mut best = (center, sum(center.0, center.1));
+    for y in 0..=image_height - height {
+        for x in 0..=image_width - width {
+            let score = sum(x, y);
+            if score > best.1 || (score == best.1 && distance((x, y)) < distance(best.0)) {
+                best = ((x, y), score);
+            }
+        }
+    }
+    if table[table.len() - 1] == 0 {
+        return centered(source, window);
+    }
+    analysis.offset(source, window, best.0)
+}
+
+/// Per pixel, in rows.
+fn interest(image: &RgbImage) -> Vec<u64> {
+    let (width, height) = image.dimensions();
+    let luma = |x: u32, y: u32| {
+        let [r, g, b] = image.get_pixel(x, y).0;
+        (299 * r as i32 + 587 * g as i32 + 114 * b as i32) / 1000
+    };
+    let mut interest = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let horizontal = luma((x + 1).min(width - 1), y) - luma(x.saturating_sub(1), y);
+            let vertical = luma(x, (y + 1).min(height - 1)) - luma(x, y.saturating_sub(1));
+            let edge = (horizontal.abs() + vertical.abs()) as u64;
+            let [r, g, b] = image.get_pixel(x, y).0;
+            let saturation = (r.max(g).max(b) - r.min(g).min(b)) as f64 * SATURATION_WEIGHT;
+            let skin = if is_skin([r, g, b]) { SKIN_BONUS } else { 0 };
+            interest.push(edge + saturation as u64 + skin);
+        }
+    }
+    interest
+}
+
+/// The RGB rule of Kovac et al., for (uniform daylight) skin tones.
+fn is_skin([r, g, b]: [u8; 3]) -> bool {
+    let spread = r.max(g).max(b) - r.min(g).min(b);
+    r > 95 && g > 40 && b > 20 && spread > 15 && r.abs_diff(g) > 15 && r > g && r > b
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_smart_crops() {
+        // A DETAILED, SATURATED PATCH OFF TO THE RIGHT OF A FLAT BACKDROP
+        let source = DynamicImage::ImageRgb8(RgbImage::from_fn(800, 400, |x, y| {
+            if (600..720).contains(&x) && (140..260).contains(&y) {
+                let level = ((x * 37 + y * 91) % 7 * 36) as u8;
+                image::Rgb([level, 255 - level, 40])
+            } else {
+                image::Rgb([120, 120, 120])
+            }
+        }));
+        for crop in [entropy, attention] {
+            let (x, y) = crop(&source, (400, 400));
+            assert!((320..=600).contains(&x), "{}", x);
+            assert_eq!(y, 0);
+        }
+        let (x, y) = attention(&source, (200, 200));
+        assert!(x <= 600 && x + 200 >= 720, "{}", x);
+        assert!(y <= 140 && y + 200 >= 260, "{}", y);
+        assert!(is_skin([224, 172, 140]) && !is_skin([120, 120, 120]));
+    }
+}
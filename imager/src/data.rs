@@ -4,6 +4,7 @@
 use image::{DynamicImage, GenericImage, GenericImageView, ImageBuffer, ImageFormat};
 use itertools::Itertools;
 use libc::{c_float, c_void, size_t};
+#[cfg(not(feature = "pure-rust"))]
 use libwebp_sys::{WebPConfig, WebPMemoryWriter, WebPPicture, WEBP_MAX_DIMENSION};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -16,172 +17,58 @@ use std::rc::Rc;
 use std::str::FromStr;
 use std::sync::Arc;
 
+use crate::error::{ImagerError, Result};
+
+pub use imager_core::data::{
+    BrandPalette, ColorMode, Matte, OutputFormat, OutputFormats, OutputSize, QualityRange, Resolution,
+    Seed, Subsampling, Threshold, Tuning, VmafTargets, MAX_WEBP_EFFORT,
+};
+
 ///////////////////////////////////////////////////////////////////////////////
 // OUTPUT-FORMAT
 ///////////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
-pub enum OutputFormat {
-    Jpeg,
-    Png,
-    Webp,
+/// `OutputFormat` inference, which needs the filesystem and `image`.
+pub trait InferOutputFormat: Sized {
+    fn infer_from_file_container<P: AsRef<Path>>(path: P) -> Option<Self>;
+    fn infer_from_path<P: AsRef<Path>>(path: P) -> Option<Self>;
 }
 
-impl OutputFormat {
-    pub fn infer_from_file_container<P: AsRef<Path>>(path: P) -> Option<Self> {
+impl InferOutputFormat for OutputFormat {
+    fn infer_from_file_container<P: AsRef<Path>>(path: P) -> Option<Self> {
         let buffer = std::fs::read(path).ok()?;
+        // NOT (YET) KNOWN TO THE IMAGE CRATE
+        if crate::codec::jxl::is_jxl(&buffer) {
+            return Some(Self::Jxl);
+        }
         let format = ::image::guess_format(&buffer).ok()?;
         match format {
             ImageFormat::Jpeg => Some(Self::Jpeg),
             ImageFormat::Png => Some(Self::Png),
             ImageFormat::WebP => Some(Self::Webp),
+            ImageFormat::Tiff => Some(Self::Tiff),
+            ImageFormat::Avif => Some(Self::Avif),
             _ => None,
         }
     }
-    pub fn infer_from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+    fn infer_from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
         let ext = path.as_ref().extension()?.to_str()?;
         Self::from_str(ext).ok()
     }
 }
 
-impl FromStr for OutputFormat {
-    type Err = String;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "jpeg" => Ok(Self::Jpeg),
-            "jpg" => Ok(Self::Jpeg),
-            "png" => Ok(Self::Png),
-            "webp" => Ok(Self::Webp),
-            _ => Err(format!("Unknown or unsupported output format {}", s)),
-        }
-    }
-}
-
-impl Default for OutputFormat {
-    fn default() -> Self {
-        Self::Jpeg
-    }
-}
-
-#[derive(Debug, Clone)]
-pub struct OutputFormats(pub Vec<OutputFormat>);
-
-impl Default for OutputFormats {
-    fn default() -> Self {
-        OutputFormats(vec![OutputFormat::Jpeg, OutputFormat::Webp])
-    }
-}
-
-impl FromStr for OutputFormats {
-    type Err = String;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut invalids = Vec::new();
-        let results = s
-            .split_whitespace()
-            .filter_map(|x| match OutputFormat::from_str(x) {
-                Ok(x) => Some(x),
-                Err(e) => {
-                    invalids.push(e);
-                    None
-                }
-            })
-            .collect::<Vec<_>>();
-        if invalids.is_empty() {
-            Ok(Self(results))
-        } else {
-            Err(invalids.join(", "))
-        }
-    }
-}
-
 ///////////////////////////////////////////////////////////////////////////////
-// RESOLUTION
+// SEED
 ///////////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Eq)]
-pub struct Resolution {
-    pub width: u32,
-    pub height: u32,
-}
-
-impl Resolution {
-    #[must_use] pub fn new(width: u32, height: u32) -> Self {
-        Self { width, height }
-    }
+/// Per-stage random number generators, which need `rand`.
+pub trait StageRng {
+    fn rng(&self, stage: &str) -> rand::rngs::StdRng;
 }
 
-impl std::fmt::Display for Resolution {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}x{}", self.width, self.height)
-    }
-}
-
-impl FromStr for Resolution {
-    type Err = String;
-    fn from_str(input: &str) -> Result<Self, Self::Err> {
-        let ix = input.find("x").ok_or("invalid")?;
-        let (width, height) = input.split_at(ix);
-        let height = height.trim_start_matches("x");
-        let width = u32::from_str(width).map_err(|_| "invalid")?;
-        let height = u32::from_str(height).map_err(|_| "invalid")?;
-        Ok(Self { width, height })
-    }
-}
-
-///////////////////////////////////////////////////////////////////////////////
-// OUTPUT-SIZE
-///////////////////////////////////////////////////////////////////////////////
-
-#[derive(Debug, Clone, PartialEq)]
-pub enum OutputSize {
-    /// Output image resolution. Akin to the 'px' CSS unit.
-    Px(Resolution),
-    /// Retain the original resolution. Akin to the '100%' CSS value.
-    Full,
-}
-
-impl std::fmt::Display for OutputSize {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Px(px) => write!(f, "{}", px),
-            Self::Full => write!(f, "full"),
-        }
-    }
-}
-
-impl FromStr for OutputSize {
-    type Err = String;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s == "full" { Ok(Self::Full) } else {
-            let val: Resolution = Resolution::from_str(s)?;
-            Ok(Self::Px(val))
-        }
-    }
-}
-
-impl Default for OutputSize {
-    fn default() -> Self {
-        Self::Full
-    }
-}
-
-impl Serialize for OutputSize {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        serializer.serialize_str(&self.to_string())
-    }
-}
-
-impl<'de> Deserialize<'de> for OutputSize {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        String::deserialize(deserializer)?
-            .parse()
-            .map_err(serde::de::Error::custom)
+impl StageRng for Seed {
+    fn rng(&self, stage: &str) -> rand::rngs::StdRng {
+        rand::SeedableRng::seed_from_u64(self.stage(stage))
     }
 }
 
@@ -219,18 +106,17 @@ impl<'de> Deserialize<'de> for OutputSize {
 // INTERNAL HELPERS
 ///////////////////////////////////////////////////////////////////////////////
 
-pub fn open_dir_sorted_paths<P: AsRef<Path>>(path: P) -> Vec<PathBuf> {
-    std::fs::read_dir(path)
-        .expect("read source dir")
-        .filter_map(Result::ok)
-        .filter(|x| x.file_type().expect("source filetype").is_file())
+/// The files of the directory whose names start with an index, by it.
+pub fn open_dir_sorted_paths<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>> {
+    let entries = std::fs::read_dir(path.as_ref()).map_err(ImagerError::io(path.as_ref()))?;
+    let paths = entries
+        .filter_map(std::result::Result::ok)
+        .filter(|x| x.file_type().is_ok_and(|x| x.is_file()))
         .map(|x| x.path())
         .filter_map(|x| {
             let file_name = x
-                .file_name()
-                .expect("missing file name")
-                .to_str()
-                .expect("file name to str")
+                .file_name()?
+                .to_str()?
                 .chars()
                 .take_while(char::is_ascii_digit)
                 .collect::<String>();
@@ -239,9 +125,11 @@ pub fn open_dir_sorted_paths<P: AsRef<Path>>(path: P) -> Vec<PathBuf> {
         })
         .sorted_by(|(i, _), (j, _)| i.cmp(j))
         .map(|(_, x)| x)
-        .collect::<Vec<_>>()
+        .collect::<Vec<_>>();
+    Ok(paths)
 }
 
+#[cfg(not(feature = "pure-rust"))]
 unsafe fn convert_to_yuv_using_webp(source: &DynamicImage) -> Yuv420P {
     // ENSURE IMAGE IS EVEN
     let source = ensure_even_reslution(source);
@@ -310,6 +198,7 @@ unsafe fn convert_to_yuv_using_webp(source: &DynamicImage) -> Yuv420P {
     result
 }
 
+#[cfg(not(feature = "pure-rust"))]
 unsafe fn convert_to_rgba_using_webp(source: &Yuv420P) -> DynamicImage {
     let (width, height) = source.dimensions();
     assert!(width < WEBP_MAX_DIMENSION);
@@ -366,6 +255,63 @@ unsafe fn convert_to_rgba_using_webp(source: &Yuv420P) -> DynamicImage {
     rgba_output
 }
 
+/// BT.601 (limited range) RGB to YUV 4:2:0, with the coefficients of
+/// libwebp’s (non-sharp) conversion; chroma is averaged over 2x2 blocks.
+#[cfg(feature = "pure-rust")]
+fn convert_to_yuv_in_rust(source: &DynamicImage) -> Yuv420P {
+    let source = ensure_even_reslution(source).to_rgb8();
+    let (width, height) = source.dimensions();
+    let luma = |[r, g, b]: [u8; 3]| {
+        let (r, g, b) = (i32::from(r), i32::from(g), i32::from(b));
+        ((16839 * r + 33059 * g + 6420 * b + (16 << 16) + (1 << 15)) >> 16) as u8
+    };
+    let mut y = Vec::with_capacity((width * height) as usize);
+    let mut u = Vec::with_capacity((width * height / 4) as usize);
+    let mut v = Vec::with_capacity((width * height / 4) as usize);
+    y.extend(source.pixels().map(|px| luma(px.0)));
+    for block_y in (0..height).step_by(2) {
+        for block_x in (0..width).step_by(2) {
+            let (mut r, mut g, mut b) = (0, 0, 0);
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let [pr, pg, pb] = source.get_pixel(block_x + dx, block_y + dy).0;
+                r += i32::from(pr);
+                g += i32::from(pg);
+                b += i32::from(pb);
+            }
+            let (r, g, b) = (r / 4, g / 4, b / 4);
+            u.push(((-9719 * r - 19081 * g + 28800 * b + (128 << 16) + (1 << 15)) >> 16) as u8);
+            v.push(((28800 * r - 24116 * g - 4684 * b + (128 << 16) + (1 << 15)) >> 16) as u8);
+        }
+    }
+    let result = Yuv420P {
+        width,
+        height,
+        data: [y, u, v].concat(),
+    };
+    assert!(result.expected_yuv420p_size());
+    result
+}
+
+/// The inverse of `convert_to_yuv_in_rust`, i.e. opaque.
+#[cfg(feature = "pure-rust")]
+fn convert_to_rgba_in_rust(source: &Yuv420P) -> DynamicImage {
+    let (width, height) = source.dimensions();
+    let (y, u, v) = (source.y(), source.u(), source.v());
+    let output = ::image::RgbaImage::from_fn(width, height, |x_pos, y_pos| {
+        let luma = f32::from(y[(y_pos * width + x_pos) as usize]) - 16.0;
+        let chroma_ix = ((y_pos / 2) * (width / 2) + x_pos / 2) as usize;
+        let (cb, cr) = (f32::from(u[chroma_ix]) - 128.0, f32::from(v[chroma_ix]) - 128.0);
+        let clamp = |x: f32| x.round().clamp(0.0, 255.0) as u8;
+        ::image::Rgba([
+            clamp(1.164 * luma + 1.596 * cr),
+            clamp(1.164 * luma - 0.392 * cb - 0.813 * cr),
+            clamp(1.164 * luma + 2.017 * cb),
+            255,
+        ])
+    });
+    DynamicImage::ImageRgba8(output)
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 // PICTURE BUFFERS
 ///////////////////////////////////////////////////////////////////////////////
@@ -378,21 +324,51 @@ pub struct Yuv420P {
 }
 
 impl Yuv420P {
-    pub fn open_image<P: AsRef<Path>>(path: P) -> Result<Self, ()> {
-        let source = ::image::open(path).expect("Yuv420P::open_image - load image");
-        Self::from_image(&source)
-    }
-    pub fn from_image(source: &DynamicImage) -> Result<Self, ()> {
-        Ok(unsafe { convert_to_yuv_using_webp(source) })
+    pub fn open_image<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let source = crate::input::InputBuffer::open(path.as_ref(), Default::default())
+            .map_err(ImagerError::io(path.as_ref()))?;
+        Self::from_image(&::image::load_from_memory(&source)?)
+    }
+    /// Odd dimensions are cropped to even ones; fails for images less
+    /// than 2x2 (or, with libwebp, too large for it).
+    pub fn from_image(source: &DynamicImage) -> Result<Self> {
+        let (width, height) = source.dimensions();
+        if width < 2 || height < 2 {
+            return Err(ImagerError::InvalidInput(format!(
+                "{}x{} is too small for YUV 4:2:0 frames",
+                width, height
+            )));
+        }
+        #[cfg(not(feature = "pure-rust"))]
+        {
+            if width >= WEBP_MAX_DIMENSION || height >= WEBP_MAX_DIMENSION {
+                return Err(ImagerError::InvalidInput(format!(
+                    "{}x{} exceeds the limit of {} for YUV frames",
+                    width,
+                    height,
+                    WEBP_MAX_DIMENSION - 1
+                )));
+            }
+            Ok(unsafe { convert_to_yuv_using_webp(source) })
+        }
+        #[cfg(feature = "pure-rust")]
+        return Ok(convert_to_yuv_in_rust(source));
     }
-    pub fn open_yuv<P: AsRef<Path>>(path: P, width: u32, height: u32) -> Result<Self, ()> {
-        let source = std::fs::read(path).expect("read raw yuv file");
+    pub fn open_yuv<P: AsRef<Path>>(path: P, width: u32, height: u32) -> Result<Self> {
+        let source = std::fs::read(path.as_ref()).map_err(ImagerError::io(path.as_ref()))?;
         let result = Self {
             width,
             height,
             data: source,
         };
-        assert!(result.expected_yuv420p_size());
+        if !result.expected_yuv420p_size() {
+            return Err(ImagerError::InvalidInput(format!(
+                "{} bytes aren’t a {}x{} YUV 4:2:0 frame",
+                result.data.len(),
+                width,
+                height
+            )));
+        }
         Ok(result)
     }
     #[must_use]
@@ -421,7 +397,10 @@ impl Yuv420P {
     }
     #[must_use]
     pub fn to_rgba_image(&self) -> DynamicImage {
-        unsafe { convert_to_rgba_using_webp(self) }
+        #[cfg(not(feature = "pure-rust"))]
+        return unsafe { convert_to_rgba_using_webp(self) };
+        #[cfg(feature = "pure-rust")]
+        return convert_to_rgba_in_rust(self);
     }
     #[must_use]
     pub fn y(&self) -> &[u8] {
@@ -463,10 +442,183 @@ impl Yuv420P {
     }
 }
 
+/// A YUV 4:2:0 picture with an alpha plane (at full resolution), for lossy
+/// WebP (see `webp::encode::lossy::encode_yuva`). Unlike `Yuv420P`, odd
+/// dimensions are kept (the last chroma row and column cover one pixel).
+#[cfg(not(feature = "pure-rust"))]
+#[derive(Debug, Clone)]
+pub struct Yuva420P {
+    pub width: u32,
+    pub height: u32,
+    pub y: Vec<u8>,
+    pub u: Vec<u8>,
+    pub v: Vec<u8>,
+    /// None if opaque.
+    pub a: Option<Vec<u8>>,
+}
+
+#[cfg(not(feature = "pure-rust"))]
+impl Yuva420P {
+    /// By libwebp’s sharp conversion, which weighs chroma by alpha (so the
+    /// colors of invisible pixels don’t bleed into visible ones).
+    pub fn from_image(source: &DynamicImage) -> Result<Self> {
+        let (width, height) = source.dimensions();
+        if width == 0 || height == 0 || width >= WEBP_MAX_DIMENSION || height >= WEBP_MAX_DIMENSION {
+            return Err(ImagerError::InvalidInput(format!(
+                "{}x{} is beyond WebP’s dimensions",
+                width, height
+            )));
+        }
+        let rgba = source.to_rgba8();
+        unsafe {
+            let mut picture: WebPPicture = std::mem::zeroed();
+            assert!(libwebp_sys::WebPPictureInit(&mut picture));
+            picture.use_argb = 1;
+            picture.width = width as i32;
+            picture.height = height as i32;
+            assert_ne!(libwebp_sys::WebPPictureImportRGBA(&mut picture, rgba.as_ptr(), width as i32 * 4), 0);
+            assert_ne!(libwebp_sys::WebPPictureSharpARGBToYUVA(&mut picture), 0);
+            let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+            let plane = |data: *const u8, stride: i32, (width, height): (u32, u32)| {
+                (0..height as usize)
+                    .flat_map(|row| std::slice::from_raw_parts(data.add(row * stride as usize), width as usize))
+                    .copied()
+                    .collect::<Vec<_>>()
+            };
+            let result = Yuva420P {
+                width,
+                height,
+                y: plane(picture.y, picture.y_stride, (width, height)),
+                u: plane(picture.u, picture.uv_stride, (chroma_width, chroma_height)),
+                v: plane(picture.v, picture.uv_stride, (chroma_width, chroma_height)),
+                // ONLY ALLOCATED FOR PICTURES WITH TRANSPARENCY
+                a: (!picture.a.is_null()).then(|| plane(picture.a, picture.a_stride, (width, height))),
+            };
+            libwebp_sys::WebPPictureFree(&mut picture);
+            Ok(result)
+        }
+    }
+    #[must_use]
+    pub fn chroma_dimensions(&self) -> (u32, u32) {
+        (self.width.div_ceil(2), self.height.div_ceil(2))
+    }
+    #[must_use]
+    pub fn is_opaque(&self) -> bool {
+        self.a.is_none()
+    }
+    /// Composited onto the matte, i.e. opaque: YUV is an affine map of RGB,
+    /// so blending the planes by alpha blends the colors (chroma by the
+    /// mean alpha of its 2x2 pixels).
+    #[must_use]
+    pub fn flatten(&self, matte: Matte) -> Self {
+        let Some(alpha) = self.a.as_ref() else {
+            return self.clone();
+        };
+        // AS LIBWEBP’S (NON-SHARP) CONVERSION
+        let [r, g, b] = matte.0.map(i32::from);
+        let matte_y = (16839 * r + 33059 * g + 6420 * b + (16 << 16) + (1 << 15)) >> 16;
+        let matte_u = (-9719 * r - 19081 * g + 28800 * b + (128 << 16) + (1 << 15)) >> 16;
+        let matte_v = (28800 * r - 24116 * g - 4684 * b + (128 << 16) + (1 << 15)) >> 16;
+        let blend = |value: u8, matte: i32, alpha: u32| {
+            let alpha = alpha as i32;
+            ((i32::from(value) * alpha + matte * (255 - alpha) + 127) / 255) as u8
+        };
+        let y = self
+            .y
+            .iter()
+            .zip(alpha)
+            .map(|(value, alpha)| blend(*value, matte_y, u32::from(*alpha)))
+            .collect();
+        let (chroma_width, chroma_height) = self.chroma_dimensions();
+        let mut mean_alpha = Vec::with_capacity((chroma_width * chroma_height) as usize);
+        for chroma_y in 0..chroma_height {
+            for chroma_x in 0..chroma_width {
+                let (mut sum, mut count) = (0, 0);
+                for y in chroma_y * 2..(chroma_y * 2 + 2).min(self.height) {
+                    for x in chroma_x * 2..(chroma_x * 2 + 2).min(self.width) {
+                        sum += u32::from(alpha[(y * self.width + x) as usize]);
+                        count += 1;
+                    }
+                }
+                mean_alpha.push((sum + count / 2) / count);
+            }
+        }
+        let chroma = |plane: &[u8], matte| {
+            plane
+                .iter()
+                .zip(&mean_alpha)
+                .map(|(value, alpha)| blend(*value, matte, *alpha))
+                .collect()
+        };
+        Yuva420P {
+            width: self.width,
+            height: self.height,
+            y,
+            u: chroma(&self.u, matte_u),
+            v: chroma(&self.v, matte_v),
+            a: None,
+        }
+    }
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 // VIDEO FRAME BUFFERS
 ///////////////////////////////////////////////////////////////////////////////
 
+/// How the images of an image sequence are fitted to the canvas (see
+/// `VideoBuffer::open_image_dir_normalized`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Normalization {
+    /// Images must be of the canvas’s size.
+    #[default]
+    Off,
+    /// Scaled to fit inside the canvas, then padded (centered, black).
+    Pad,
+    /// Scaled to cover the canvas, then cropped (centered).
+    Crop,
+    /// Stretched to the canvas.
+    Stretch,
+}
+
+impl Normalization {
+    /// The image, fitted to the canvas; if `Off`, fails with the image’s
+    /// size, unless it’s the canvas’s.
+    pub fn apply(self, image: DynamicImage, canvas: (u32, u32)) -> std::result::Result<DynamicImage, (u32, u32)> {
+        let (width, height) = image.dimensions();
+        if (width, height) == canvas {
+            return Ok(image);
+        }
+        let ratio_x = canvas.0 as f64 / width as f64;
+        let ratio_y = canvas.1 as f64 / height as f64;
+        let filter = crate::resize::filter_type(Default::default());
+        let scale = |ratio: f64| {
+            let side = |side: u32| ((side as f64 * ratio).round() as u32).max(1);
+            // WITHOUT ALPHA, SO THAT PADDING IS BLACK
+            DynamicImage::ImageRgb8(image.resize_exact(side(width), side(height), filter).to_rgb8())
+        };
+        let place = |image| crate::resize::place(image, canvas, crate::crop::Crop::Centre);
+        match self {
+            Normalization::Off => Err((width, height)),
+            Normalization::Pad => Ok(place(scale(ratio_x.min(ratio_y)))),
+            Normalization::Crop => Ok(place(scale(ratio_x.max(ratio_y)))),
+            Normalization::Stretch => Ok(image.resize_exact(canvas.0, canvas.1, filter)),
+        }
+    }
+}
+
+impl FromStr for Normalization {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(Normalization::Off),
+            "pad" => Ok(Normalization::Pad),
+            "crop" => Ok(Normalization::Crop),
+            "stretch" => Ok(Normalization::Stretch),
+            _ => Err(format!("Unknown normalization {}", s)),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct VideoBuffer {
     width: u32,
@@ -476,17 +628,13 @@ pub struct VideoBuffer {
 }
 
 impl VideoBuffer {
-    pub fn from_png(source: &[u8]) -> Result<Self, ()> {
-        let source = ::image::load_from_memory_with_format(source, ::image::ImageFormat::Png);
-        let source = source.expect("load png source");
-        Self::from_image(&source)
+    pub fn from_png(source: &[u8]) -> Result<Self> {
+        Self::from_image(&::image::load_from_memory_with_format(source, ImageFormat::Png)?)
     }
-    pub fn from_jpeg(source: &[u8]) -> Result<Self, ()> {
-        let source = ::image::load_from_memory_with_format(source, ::image::ImageFormat::Jpeg);
-        let source = source.expect("load jpeg source");
-        Self::from_image(&source)
+    pub fn from_jpeg(source: &[u8]) -> Result<Self> {
+        Self::from_image(&::image::load_from_memory_with_format(source, ImageFormat::Jpeg)?)
     }
-    pub fn from_image(source: &DynamicImage) -> Result<Self, ()> {
+    pub fn from_image(source: &DynamicImage) -> Result<Self> {
         Ok(Self::singleton(Yuv420P::from_image(source)?))
     }
     #[must_use] pub fn singleton(frame: Yuv420P) -> Self {
@@ -497,18 +645,16 @@ impl VideoBuffer {
             cursor: 0,
         }
     }
-    pub fn open_image_dir<P: AsRef<Path>>(dir_path: P) -> Result<Self, ()> {
-        assert!(dir_path.as_ref().exists());
-        let frames = open_dir_sorted_paths(dir_path)
-            .into_par_iter()
-            .map(|path| Yuv420P::open_image(&path).expect("open and decode image"))
-            .collect::<Vec<_>>();
-        assert!(!frames.is_empty());
-        let (width, height) = {
-            let w = frames[0].width;
-            let h = frames[0].height;
-            (w, h)
-        };
+    /// Fails given no frames, or frames of different sizes.
+    pub fn from_frames(frames: Vec<Yuv420P>) -> Result<Self> {
+        let first = frames.first().ok_or_else(|| ImagerError::InvalidInput(String::from("no frames")))?;
+        let (width, height) = first.dimensions();
+        if let Some(frame) = frames.iter().find(|x| x.dimensions() != (width, height)) {
+            return Err(ImagerError::InvalidInput(format!(
+                "frames differ in size ({}x{} and {}x{})",
+                width, height, frame.width, frame.height
+            )));
+        }
         Ok(VideoBuffer {
             width,
             height,
@@ -516,6 +662,48 @@ impl VideoBuffer {
             cursor: 0,
         })
     }
+    /// The images of the directory (see `open_dir_sorted_paths`), as
+    /// frames; they must be of the same size.
+    pub fn open_image_dir<P: AsRef<Path>>(dir_path: P) -> Result<Self> {
+        Self::open_image_dir_normalized(dir_path, Normalization::Off, None)
+    }
+    /// Like `open_image_dir`, with the images of other sizes than the
+    /// canvas (by default, the first image’s size) fitted to it by the
+    /// normalization.
+    pub fn open_image_dir_normalized<P: AsRef<Path>>(
+        dir_path: P,
+        normalization: Normalization,
+        canvas: Option<(u32, u32)>,
+    ) -> Result<Self> {
+        let paths = open_dir_sorted_paths(dir_path.as_ref())?;
+        let first = paths
+            .first()
+            .ok_or_else(|| ImagerError::InvalidInput(format!("no frames in {}", dir_path.as_ref().display())))?;
+        let canvas = match canvas {
+            Some(canvas) => canvas,
+            None => ::image::image_dimensions(first)?,
+        };
+        let frames = paths
+            .into_par_iter()
+            .map(|path| {
+                let source = crate::input::InputBuffer::open(&path, Default::default())
+                    .map_err(ImagerError::io(&path))?;
+                let image = ::image::load_from_memory(&source)?;
+                let image = normalization.apply(image, canvas).map_err(|(width, height)| {
+                    ImagerError::InvalidInput(format!(
+                        "{} is {}x{}, unlike the {}x{} canvas; normalize the frames (pad, crop or stretch)",
+                        path.display(),
+                        width,
+                        height,
+                        canvas.0,
+                        canvas.1
+                    ))
+                })?;
+                Yuv420P::from_image(&image)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Self::from_frames(frames)
+    }
     #[must_use] pub fn width(&self) -> u32 {
         self.width
     }
@@ -556,3 +744,73 @@ impl VideoBuffer {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_yuv_round_trip() {
+        let source = ::image::RgbImage::from_pixel(16, 16, ::image::Rgb([200, 100, 50]));
+        let yuv = Yuv420P::from_image(&DynamicImage::ImageRgb8(source)).expect("to yuv");
+        assert!(yuv.expected_yuv420p_size());
+        let output = yuv.to_rgba_image().to_rgba8();
+        for (expected, actual) in [200u8, 100, 50].iter().zip(output.get_pixel(7, 7).0.iter()) {
+            assert!(expected.abs_diff(*actual) <= 3, "{} vs {}", expected, actual);
+        }
+    }
+
+    #[test]
+    fn test_normalization() {
+        let dir = std::env::temp_dir().join(format!("imager-frames-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create dir");
+        let frame = |width, height| {
+            DynamicImage::ImageRgb8(::image::RgbImage::from_pixel(width, height, ::image::Rgb([200, 100, 50])))
+        };
+        frame(64, 32).save(dir.join("0.png")).expect("save");
+        frame(32, 32).save(dir.join("1.png")).expect("save");
+        let error = VideoBuffer::open_image_dir(&dir).unwrap_err();
+        assert!(error.to_string().contains("1.png is 32x32, unlike the 64x32 canvas"), "{}", error);
+        for normalization in [Normalization::Pad, Normalization::Crop, Normalization::Stretch] {
+            let frames = VideoBuffer::open_image_dir_normalized(&dir, normalization, None).expect("normalize");
+            assert_eq!((frames.dimensions(), frames.as_frames().len()), ((64, 32), 2));
+        }
+        let frames =
+            VideoBuffer::open_image_dir_normalized(&dir, Normalization::Crop, Some((16, 16))).expect("normalize");
+        assert_eq!(frames.dimensions(), (16, 16));
+        // PADDED LEFT AND RIGHT, IN BLACK
+        let padded = Normalization::Pad.apply(frame(32, 32), (64, 32)).expect("pad").to_rgb8();
+        assert_eq!((padded.get_pixel(2, 16).0, padded.get_pixel(32, 16).0), ([0, 0, 0], [200, 100, 50]));
+        assert_eq!("stretch".parse(), Ok(Normalization::Stretch));
+        std::fs::remove_dir_all(&dir).expect("remove dir");
+    }
+
+    #[test]
+    #[cfg(not(feature = "pure-rust"))]
+    fn test_yuva() {
+        // OPAQUE RED LEFT, INVISIBLE (BLACK) RIGHT, OF ODD DIMENSIONS
+        let source = ::image::RgbaImage::from_fn(33, 17, |x, _| match x < 16 {
+            true => ::image::Rgba([220, 30, 30, 255]),
+            false => ::image::Rgba([0, 0, 0, 0]),
+        });
+        let source = DynamicImage::ImageRgba8(source);
+        let picture = Yuva420P::from_image(&source).expect("to yuva");
+        assert_eq!((picture.chroma_dimensions(), picture.u.len()), ((17, 9), 17 * 9));
+        assert!(!picture.is_opaque());
+        assert!(Yuva420P::from_image(&DynamicImage::new_rgb8(4, 4)).expect("opaque").is_opaque());
+        let encoded = crate::codec::webp::encode::lossy::encode_yuva(&picture, 80.0, MAX_WEBP_EFFORT);
+        let decoded = crate::codec::webp::decode::decode(&encoded).expect("decode").to_rgba8();
+        assert_eq!(decoded.dimensions(), (33, 17));
+        assert_eq!((decoded.get_pixel(4, 8).0[3], decoded.get_pixel(30, 8).0[3]), (255, 0));
+        // OVER WHITE, THE INVISIBLE HALF IS WHITE (NOT BLACK)
+        let flattened = picture.flatten(Matte::default());
+        assert!(flattened.is_opaque());
+        let encoded = crate::codec::webp::encode::lossy::encode_yuva(&flattened, 80.0, MAX_WEBP_EFFORT);
+        let decoded = crate::codec::webp::decode::decode(&encoded).expect("decode").to_rgba8();
+        assert!(decoded.get_pixel(30, 8).0.iter().all(|x| *x >= 245));
+        let red = decoded.get_pixel(4, 8).0;
+        assert!(red[0] > 180 && red[1] < 70, "{:?}", red);
+        assert_eq!("#000000".parse::<Matte>().map(|x| x.to_string()), Ok(String::from("#000000")));
+        assert!("#00000080".parse::<Matte>().is_err());
+    }
+}
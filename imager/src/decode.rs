@@ -0,0 +1,140 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::codec::{avif, jpeg, png, webp};
+use crate::data::Resolution;
+use crate::error::{ImagerError, Result};
+use crate::resize::{Fit, Geometry};
+
+pub use imager_core::decode::{DecodeOptions, Decoder, DecoderChain};
+
+///////////////////////////////////////////////////////////////////////////////
+// DECODE
+///////////////////////////////////////////////////////////////////////////////
+
+/// Decode an (already identified) source image, trying each decoder of
+/// the chain in order. Returns the decoder that succeeded; the error of
+/// none succeeding includes the first decoder’s.
+///
+/// When `max_size` is given the result may be downscaled while decoding
+/// (see `jpeg::decode_scaled` and `png::decode_scaled`), though it may
+/// still be larger than `max_size` (or, for the fits that crop or exceed
+/// it, than the source scaled to cover it).
+pub fn decode(
+    source: &[u8],
+    format: ImageFormat,
+    options: &DecodeOptions,
+) -> Result<(DynamicImage, Decoder)> {
+    let max_size = scaled_size(source, format, options);
+    let mut first_error = None;
+    for decoder in &options.chain.0 {
+        let result = match decoder {
+            Decoder::Image => decode_image(source, format, max_size.as_ref()),
+            #[cfg(not(feature = "pure-rust"))]
+            Decoder::Turbo if format == ImageFormat::Jpeg => jpeg::decode_tolerant(
+                source,
+                max_size.as_ref(),
+                options.tolerate_truncated,
+            ),
+            #[cfg(not(feature = "pure-rust"))]
+            Decoder::Turbo => Err(ImagerError::decode("the `turbo` decoder only decodes JPEG")),
+            #[cfg(feature = "pure-rust")]
+            Decoder::Turbo => Err(crate::codec::registry::FeatureDisabled::TURBO_DECODER.into()),
+            Decoder::Ffmpeg => decode_ffmpeg(source, options.tolerate_truncated),
+        };
+        match result {
+            Ok(decoded) => return Ok((decoded, *decoder)),
+            Err(error) => {
+                first_error.get_or_insert(error);
+            }
+        }
+    }
+    let reason = first_error.map(|x| format!(" ({})", x)).unwrap_or_default();
+    Err(ImagerError::Decode(format!(
+        "no decoder of {:?} succeeded{}",
+        options.chain.0, reason
+    )))
+}
+
+/// The size to downscale to while decoding: the `max_size` for `inside`,
+/// else the size the fit scales the source to, if its header is readable.
+fn scaled_size(source: &[u8], format: ImageFormat, options: &DecodeOptions) -> Option<Resolution> {
+    let max_size = options.max_size.as_ref()?;
+    if options.fit == Fit::Inside {
+        return Some(max_size.clone());
+    }
+    let reader = ::image::io::Reader::with_format(std::io::Cursor::new(source), format);
+    let dimensions = reader.into_dimensions().ok()?;
+    let (width, height) = Geometry::new(dimensions, max_size, options.fit, false).scaled;
+    Some(Resolution::new(width, height))
+}
+
+fn decode_image(
+    source: &[u8],
+    format: ImageFormat,
+    max_size: Option<&Resolution>,
+) -> Result<DynamicImage> {
+    match (format, max_size) {
+        (ImageFormat::WebP, _) => webp::decode::decode(source),
+        (ImageFormat::Avif, _) => avif::decode(source),
+        (ImageFormat::Jpeg, Some(max_size)) => jpeg::decode_scaled(source, max_size),
+        (ImageFormat::Png, Some(max_size)) => png::decode_scaled(source, max_size),
+        _ => Ok(::image::load_from_memory_with_format(source, format)?),
+    }
+}
+
+/// Decode the first frame via an `ffmpeg` subprocess, transcoding to PNG.
+fn decode_ffmpeg(source: &[u8], tolerate_truncated: bool) -> Result<DynamicImage> {
+    let mut command = Command::new("ffmpeg");
+    command.args(["-nostdin", "-v", "error"]);
+    if tolerate_truncated {
+        command.args(["-err_detect", "ignore_err"]);
+    } else {
+        command.arg("-xerror");
+    }
+    let mut child = command
+        .args(["-i", "pipe:0", "-frames:v", "1", "-f", "image2pipe", "-vcodec", "png", "pipe:1"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|x| ImagerError::decode(format!("failed to run ffmpeg: {}", x)))?;
+    let mut stdin = child.stdin.take().expect("piped stdin");
+    let output = std::thread::scope(|scope| {
+        // WRITE FROM ANOTHER THREAD, SO LARGE OUTPUTS CAN’T DEADLOCK
+        scope.spawn(move || stdin.write_all(source));
+        child.wait_with_output()
+    })
+    .map_err(|x| ImagerError::decode(format!("ffmpeg failed: {}", x)))?;
+    if !output.status.success() {
+        return Err(ImagerError::decode(format!("ffmpeg failed ({})", output.status)));
+    }
+    Ok(::image::load_from_memory_with_format(&output.stdout, ImageFormat::Png)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    #[cfg(not(feature = "pure-rust"))]
+    fn test_truncated_jpeg_fallback() {
+        let source = include_bytes!("../assets/test/1.jpeg");
+        let truncated = &source[..source.len() / 2];
+        let strict = DecodeOptions {
+            chain: DecoderChain(vec![Decoder::Image, Decoder::Turbo]),
+            ..Default::default()
+        };
+        assert!(decode(truncated, ImageFormat::Jpeg, &strict).is_err());
+        let tolerant = DecodeOptions {
+            tolerate_truncated: true,
+            ..strict
+        };
+        let (_, decoder) = decode(truncated, ImageFormat::Jpeg, &tolerant).expect("decode");
+        assert_eq!(decoder, Decoder::Turbo);
+    }
+}
@@ -0,0 +1,127 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Difference heatmaps (`imager diff`), to make subtle encoder artifacts
+//! visible: the per-pixel CIELAB ΔE (CIE76, where about 2.3 is a just
+//! noticeable difference), times `amplify`, from black (identical) through
+//! blue, red and yellow to white.
+//!
+//! Pixels are compared composited over both black and white, and differ by
+//! the larger ΔE, so changes in alpha alone show too, while the color of
+//! fully transparent pixels (invisible either way) doesn’t.
+use image::{DynamicImage, GenericImageView, Rgb, RgbImage, Rgba};
+
+/// ΔE (times `amplify`) mapped to white.
+const SATURATION: f32 = 20.0;
+
+pub struct Diff {
+    pub heatmap: DynamicImage,
+    pub max_delta_e: f32,
+    pub mean_delta_e: f32,
+}
+
+pub fn diff(a: &DynamicImage, b: &DynamicImage, amplify: f32) -> Result<Diff, String> {
+    if a.dimensions() != b.dimensions() {
+        let ((aw, ah), (bw, bh)) = (a.dimensions(), b.dimensions());
+        return Err(format!("dimensions differ: {}x{} and {}x{}", aw, ah, bw, bh));
+    }
+    let (a, b) = (a.to_rgba8(), b.to_rgba8());
+    let mut heatmap = RgbImage::new(a.width(), a.height());
+    let (mut max, mut sum) = (0f32, 0f64);
+    for ((x, y, pa), pb) in a.enumerate_pixels().zip(b.pixels()) {
+        let delta_e = delta_e(*pa, *pb);
+        max = max.max(delta_e);
+        sum += delta_e as f64;
+        heatmap.put_pixel(x, y, heat(delta_e * amplify / SATURATION));
+    }
+    let len = (a.width() as f64 * a.height() as f64).max(1.0);
+    Ok(Diff {
+        heatmap: DynamicImage::ImageRgb8(heatmap),
+        max_delta_e: max,
+        mean_delta_e: (sum / len) as f32,
+    })
+}
+
+fn delta_e(a: Rgba<u8>, b: Rgba<u8>) -> f32 {
+    [0.0, 1.0]
+        .iter()
+        .map(|background| {
+            let (a, b) = (lab(composite(a, *background)), lab(composite(b, *background)));
+            ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+        })
+        .fold(0.0, f32::max)
+}
+
+/// Linear RGB, over a gray `background` (linear).
+fn composite(Rgba([r, g, b, a]): Rgba<u8>, background: f32) -> [f32; 3] {
+    let alpha = a as f32 / 255.0;
+    let linear = |x: u8| {
+        let x = x as f32 / 255.0;
+        if x <= 0.04045 {
+            x / 12.92
+        } else {
+            ((x + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    [r, g, b].map(|x| linear(x) * alpha + background * (1.0 - alpha))
+}
+
+/// From linear sRGB, with a D65 white point.
+fn lab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f32| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// Black, blue, red, yellow, white, for `value` from 0 to 1 (clamped).
+fn heat(value: f32) -> Rgb<u8> {
+    const STOPS: [[f32; 3]; 5] = [
+        [0.0, 0.0, 0.0],
+        [0.0, 0.0, 255.0],
+        [255.0, 0.0, 0.0],
+        [255.0, 255.0, 0.0],
+        [255.0, 255.0, 255.0],
+    ];
+    let position = value.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+    let ix = (position as usize).min(STOPS.len() - 2);
+    let t = position - ix as f32;
+    let (from, to) = (STOPS[ix], STOPS[ix + 1]);
+    Rgb([0, 1, 2].map(|c| (from[c] + (to[c] - from[c]) * t).round() as u8))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let mut a = image::RgbaImage::from_pixel(4, 2, Rgba([120, 60, 200, 255]));
+        let mut b = a.clone();
+        b.put_pixel(1, 0, Rgba([126, 60, 200, 255]));
+        // ONLY ALPHA DIFFERS
+        b.put_pixel(2, 0, Rgba([120, 60, 200, 128]));
+        // INVISIBLE EITHER WAY
+        a.put_pixel(3, 0, Rgba([255, 0, 0, 0]));
+        b.put_pixel(3, 0, Rgba([0, 255, 0, 0]));
+        let (a, b) = (DynamicImage::ImageRgba8(a), DynamicImage::ImageRgba8(b));
+        let result = diff(&a, &b, 8.0).expect("diff");
+        let heatmap = result.heatmap.to_rgb8();
+        assert_eq!(heatmap.get_pixel(0, 0), &Rgb([0, 0, 0]));
+        assert_ne!(heatmap.get_pixel(1, 0), &Rgb([0, 0, 0]));
+        assert!(result.max_delta_e > 20.0);
+        assert_eq!(heatmap.get_pixel(3, 0), &Rgb([0, 0, 0]));
+        assert!(result.mean_delta_e > 0.0 && result.mean_delta_e < result.max_delta_e);
+        assert_eq!(heat(1.0), Rgb([255, 255, 255]));
+        let small = DynamicImage::ImageRgba8(image::RgbaImage::new(2, 2));
+        assert!(diff(&a, &small, 1.0).is_err());
+    }
+}
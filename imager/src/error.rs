@@ -0,0 +1,108 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! The error of the library API, so that bad inputs (unreadable files,
+//! undecodable or oversized images, mismatched frames) are errors callers
+//! can recover from, rather than panics.
+//!
+//! Helpers that already report a `String` (e.g. pipelines, plugins) are
+//! unchanged; `ImagerError` converts into one, so `?` works in them.
+use std::path::PathBuf;
+
+use crate::codec::registry::FeatureDisabled;
+
+#[derive(Debug)]
+pub enum ImagerError {
+    /// Reading a file (or directory) failed.
+    Io { path: PathBuf, source: std::io::Error },
+    /// The source isn’t an image, or none of the decoders could decode it.
+    Decode(String),
+    /// Encoding (or a stage before it, e.g. upscaling) failed.
+    Encode(String),
+    /// Arguments that can’t work, e.g. a raw YUV file of the wrong size, or
+    /// frames of different sizes.
+    InvalidInput(String),
+    /// Reading or writing a remote file (e.g. an `sftp://` URL) failed.
+    Transfer(String),
+    /// Needs a cargo feature this build lacks.
+    FeatureDisabled(FeatureDisabled),
+}
+
+pub type Result<T> = std::result::Result<T, ImagerError>;
+
+impl ImagerError {
+    pub(crate) fn io(path: impl Into<PathBuf>) -> impl FnOnce(std::io::Error) -> Self {
+        let path = path.into();
+        move |source| ImagerError::Io { path, source }
+    }
+    pub(crate) fn decode(message: impl std::fmt::Display) -> Self {
+        ImagerError::Decode(message.to_string())
+    }
+}
+
+impl std::fmt::Display for ImagerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImagerError::Io { path, source } => write!(f, "failed to read {}: {}", path.display(), source),
+            ImagerError::Decode(message)
+            | ImagerError::Encode(message)
+            | ImagerError::InvalidInput(message)
+            | ImagerError::Transfer(message) => write!(f, "{}", message),
+            ImagerError::FeatureDisabled(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for ImagerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ImagerError::Io { source, .. } => Some(source),
+            ImagerError::FeatureDisabled(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<::image::ImageError> for ImagerError {
+    fn from(error: ::image::ImageError) -> Self {
+        ImagerError::decode(error)
+    }
+}
+
+impl From<FeatureDisabled> for ImagerError {
+    fn from(error: FeatureDisabled) -> Self {
+        ImagerError::FeatureDisabled(error)
+    }
+}
+
+impl From<ImagerError> for String {
+    fn from(error: ImagerError) -> Self {
+        error.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::{VideoBuffer, Yuv420P};
+
+    #[test]
+    fn test_bad_inputs() {
+        let missing = Yuv420P::open_image("/nonexistent/frame.png").unwrap_err();
+        assert!(matches!(missing, ImagerError::Io { .. }), "{}", missing);
+        assert!(matches!(
+            crate::api::OptJob::new(b"not an image"),
+            Err(ImagerError::Decode(_))
+        ));
+        let frame = |width, height| Yuv420P {
+            width,
+            height,
+            data: vec![0; (width * height * 3 / 2) as usize],
+        };
+        let error = VideoBuffer::from_frames(vec![frame(4, 4), frame(8, 8)]).unwrap_err();
+        assert_eq!(error.to_string(), "frames differ in size (4x4 and 8x8)");
+        assert!(VideoBuffer::from_frames(Vec::new()).is_err());
+        let source = ::image::DynamicImage::new_rgb8(1, 1);
+        assert!(matches!(Yuv420P::from_image(&source), Err(ImagerError::InvalidInput(_))));
+    }
+}
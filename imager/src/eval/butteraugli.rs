@@ -0,0 +1,119 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Butteraugli distances: how visible the differences of two images are,
+//! at 1:1; 0 for identical images, below 1 where they look the same, and
+//! above 2 where differences are plainly visible.
+//!
+//! Computed by the `butteraugli` crate, a port of libjxl’s (opsin dynamics,
+//! frequency bands, Malta edge filters, masking and the half resolution
+//! pass) that matches libjxl’s `butteraugli_main` on sRGB images; the tests
+//! check reference values of libjxl. Like libjxl, images smaller than 8x8
+//! are compared with their borders extended to 8x8. Alpha is ignored.
+use butteraugli::{butteraugli, ButteraugliParams, Img, RGB8};
+use image::{DynamicImage, GenericImageView};
+
+/// The smallest side butteraugli compares.
+const MIN_SIZE: usize = 8;
+
+/// The max over pixels, as butteraugli reports it; panics unless the
+/// dimensions match.
+pub fn distance(a: &DynamicImage, b: &DynamicImage) -> f64 {
+    assert_eq!(a.dimensions(), b.dimensions());
+    let (width, height) = (a.width() as usize, a.height() as usize);
+    if width == 0 || height == 0 {
+        return 0.0;
+    }
+    if width < MIN_SIZE || height < MIN_SIZE {
+        return diffmap(a, b).into_iter().fold(0.0, f64::max);
+    }
+    compare(a, b, false).score
+}
+
+/// The distance at every pixel, row by row.
+pub fn diffmap(a: &DynamicImage, b: &DynamicImage) -> Vec<f64> {
+    assert_eq!(a.dimensions(), b.dimensions());
+    let (width, height) = (a.width() as usize, a.height() as usize);
+    if width == 0 || height == 0 {
+        return Vec::new();
+    }
+    // OF THE EXTENDED IMAGES, CENTERED (AS LIBJXL DOES IT)
+    let (x_border, y_border) = (MIN_SIZE.saturating_sub(width) / 2, MIN_SIZE.saturating_sub(height) / 2);
+    let extend = |source: &DynamicImage| {
+        let rgb = source.to_rgb8();
+        let (extended_width, extended_height) = (width.max(MIN_SIZE) as u32, height.max(MIN_SIZE) as u32);
+        let extended = ::image::RgbImage::from_fn(extended_width, extended_height, |x, y| {
+            let x = (x as usize).saturating_sub(x_border).min(width - 1);
+            let y = (y as usize).saturating_sub(y_border).min(height - 1);
+            *rgb.get_pixel(x as u32, y as u32)
+        });
+        DynamicImage::ImageRgb8(extended)
+    };
+    let (a, b) = (extend(a), extend(b));
+    let diffmap = compare(&a, &b, true).diffmap.expect("computed diffmap");
+    diffmap
+        .rows()
+        .skip(y_border)
+        .take(height)
+        .flat_map(|row| row[x_border..x_border + width].iter().map(|x| f64::from(*x)))
+        .collect()
+}
+
+/// Of images at least `MIN_SIZE` on both sides.
+fn compare(a: &DynamicImage, b: &DynamicImage, with_diffmap: bool) -> butteraugli::ButteraugliResult {
+    let image = |source: &DynamicImage| {
+        let pixels = source
+            .to_rgb8()
+            .pixels()
+            .map(|px| RGB8::new(px[0], px[1], px[2]))
+            .collect::<Vec<_>>();
+        Img::new(pixels, source.width() as usize, source.height() as usize)
+    };
+    let (a, b) = (image(a), image(b));
+    let params = ButteraugliParams::default().with_compute_diffmap(with_diffmap);
+    butteraugli(a.as_ref(), b.as_ref(), &params).expect("butteraugli of valid images")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn gray(width: u32, height: u32, value: impl Fn(u32, u32) -> u8) -> DynamicImage {
+        DynamicImage::ImageRgb8(::image::RgbImage::from_fn(width, height, |x, y| {
+            ::image::Rgb([value(x, y); 3])
+        }))
+    }
+
+    #[test]
+    fn test_reference() {
+        // OF LIBJXL’S `butteraugli_main`, AT ITS DEFAULT 80 NITS
+        let shift = distance(&gray(32, 32, |_, _| 128), &gray(32, 32, |_, _| 138));
+        assert!((shift - 21.285_724_6).abs() < 1e-3, "{}", shift);
+        let checker = |inverse: bool| {
+            gray(32, 32, move |x, y| if ((x / 4 + y / 4) % 2 == 0) != inverse { 200 } else { 50 })
+        };
+        let checker = distance(&checker(false), &checker(true));
+        assert!((checker - 39.762_924_2).abs() < 1e-3, "{}", checker);
+    }
+
+    #[test]
+    fn test_distance() {
+        let source = ::image::load_from_memory(include_bytes!("../../assets/test/1.jpeg"))
+            .expect("decode")
+            .thumbnail(160, 160);
+        assert_eq!(distance(&source, &source), 0.0);
+        let at = |quality: u32| {
+            let encoded = crate::rd::encode(&source, &crate::data::OutputFormat::Jpeg, quality).expect("encode");
+            let decoded = crate::rd::decode(&encoded, &crate::data::OutputFormat::Jpeg).expect("decode");
+            distance(&source, &decoded)
+        };
+        let (low, high) = (at(30), at(95));
+        assert!(0.0 < high && high < low, "{} {}", high, low);
+        // SMALLER THAN 8X8, AND THE DIFFMAP MAX IS THE DISTANCE
+        let (a, b) = (gray(5, 3, |x, _| (x * 40) as u8), gray(5, 3, |x, _| (x * 40 + 10) as u8));
+        let diffmap = diffmap(&a, &b);
+        assert_eq!(diffmap.len(), 15);
+        assert_eq!(distance(&a, &b), diffmap.iter().copied().fold(0.0, f64::max));
+        assert!(distance(&a, &b) > 0.0 && distance(&a, &a) == 0.0);
+    }
+}
@@ -0,0 +1,150 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Scoring encodes without libvmaf (so in `pure-rust` builds too), and
+//! picking their quality by that score: `search` bisects the quality range
+//! for the lowest quality whose output is within a DSSIM (see `ssim`) or
+//! butteraugli (see `butteraugli`) target, rather than callers guessing
+//! one.
+//!
+//! Unlike the VMAF searches of the encoders, the target is the same for
+//! every class of content (and every format); both metrics already weigh
+//! what’s visibly lost.
+use image::DynamicImage;
+
+use crate::data::{OutputFormat, QualityRange, Tuning};
+
+pub mod butteraugli;
+pub mod ssim;
+
+/// Of `encode`: about where artifacts start to show on photos at 1:1.
+pub const DEFAULT_TARGET: Target = Target::Dssim(0.01);
+
+/// The max distance of outputs from the source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Dssim(f64),
+    Butteraugli(f64),
+}
+
+impl Target {
+    pub fn max(&self) -> f64 {
+        match self {
+            Target::Dssim(x) | Target::Butteraugli(x) => *x,
+        }
+    }
+    /// Of the metric.
+    pub fn distance(&self, source: &DynamicImage, output: &DynamicImage) -> f64 {
+        match self {
+            Target::Dssim(_) => ssim::dssim(source, output),
+            Target::Butteraugli(_) => butteraugli::distance(source, output),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Found {
+    pub quality: u8,
+    pub output: Vec<u8>,
+    /// Of the target’s metric.
+    pub distance: f64,
+    /// Whether the output is within the target; if not, it’s at the maximum
+    /// of the range.
+    pub passed: bool,
+    /// The encodes it took.
+    pub encodes: usize,
+}
+
+/// At the lowest quality (within the format’s default tuning) whose output
+/// is within `DEFAULT_TARGET`.
+pub fn encode(source: &DynamicImage, format: &OutputFormat) -> Result<Found, String> {
+    let tuning = Tuning::default();
+    let range = match format {
+        OutputFormat::Jpeg => tuning.jpeg,
+        OutputFormat::Webp => tuning.webp,
+        OutputFormat::Avif => tuning.avif,
+        _ => QualityRange { min: 0, max: 100 },
+    };
+    search(source, format, DEFAULT_TARGET, range)
+}
+
+/// Bisects the `range` (distances fall as the quality rises), so it takes
+/// about `log2` of its size encodes; JPEG, WebP, AVIF and JPEG XL only.
+pub fn search(
+    source: &DynamicImage,
+    format: &OutputFormat,
+    target: Target,
+    range: QualityRange,
+) -> Result<Found, String> {
+    if matches!(format, OutputFormat::Png | OutputFormat::Tiff) {
+        return Err(format!("{:?} has no quality to search", format));
+    }
+    let mut encodes = 0;
+    let mut run = |quality: u8| -> Result<Found, String> {
+        let output = crate::rd::encode(source, format, u32::from(quality))?;
+        let distance = target.distance(source, &crate::rd::decode(&output, format)?);
+        encodes += 1;
+        Ok(Found {
+            quality,
+            output,
+            distance,
+            passed: distance <= target.max(),
+            encodes,
+        })
+    };
+    let (mut low, mut high) = (range.min, range.max);
+    let mut best: Option<Found> = None;
+    let mut fallback = None;
+    while low <= high {
+        let middle = low + (high - low) / 2;
+        let found = run(middle)?;
+        if found.passed {
+            best = Some(found);
+            match middle.checked_sub(1) {
+                Some(x) => high = x,
+                None => break,
+            }
+        } else {
+            if middle == range.max {
+                fallback = Some(found);
+                break;
+            }
+            low = middle + 1;
+        }
+    }
+    let found = match (best, fallback) {
+        (Some(found), _) | (None, Some(found)) => found,
+        (None, None) => run(range.max)?,
+    };
+    Ok(Found { encodes, ..found })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_search() {
+        let source = ::image::load_from_memory(include_bytes!("../../assets/test/1.jpeg"))
+            .expect("decode")
+            .thumbnail(160, 160);
+        let range = QualityRange { min: 0, max: 95 };
+        let found = search(&source, &OutputFormat::Jpeg, DEFAULT_TARGET, range).expect("search");
+        assert!(found.passed && found.distance <= DEFAULT_TARGET.max(), "{:?}", found.distance);
+        assert!(found.encodes <= 8, "{}", found.encodes);
+        // THE LOWEST QUALITY THAT PASSES
+        if found.quality > 0 {
+            let lower = search(&source, &OutputFormat::Jpeg, DEFAULT_TARGET, QualityRange {
+                min: found.quality - 1,
+                max: found.quality - 1,
+            })
+            .expect("search");
+            assert!(!lower.passed);
+        }
+        let strict = search(&source, &OutputFormat::Jpeg, Target::Dssim(0.0), range).expect("search");
+        assert!(!strict.passed && strict.quality == 95);
+        let found = search(&source, &OutputFormat::Jpeg, Target::Butteraugli(3.0), range).expect("search");
+        assert!(found.passed && found.distance <= 3.0, "{:?}", found.distance);
+        assert!(search(&source, &OutputFormat::Png, DEFAULT_TARGET, range).is_err());
+    }
+}
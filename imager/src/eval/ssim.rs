@@ -0,0 +1,108 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! SSIM (Wang et al., 2004) of the luma, over the usual 11×11 Gaussian
+//! window (σ 1.5), and DSSIM, as the `dssim` tool reports it: `1 / SSIM -
+//! 1`, 0 for identical images, rising with the distortion.
+//!
+//! Single scale, so it’s most telling for images viewed at (about) 1:1.
+use image::{DynamicImage, GenericImageView};
+
+const SIGMA: f64 = 1.5;
+/// The stabilizers, of 8 bit samples.
+const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+/// The mean SSIM, from -1 to 1 (identical).
+pub fn ssim(a: &DynamicImage, b: &DynamicImage) -> f64 {
+    assert_eq!(a.dimensions(), b.dimensions());
+    let (width, height) = (a.width() as usize, a.height() as usize);
+    if width == 0 || height == 0 {
+        return 1.0;
+    }
+    let (x, y) = (luma(a), luma(b));
+    let product = |p: &[f64], q: &[f64]| p.iter().zip(q).map(|(p, q)| p * q).collect::<Vec<_>>();
+    let blur = |plane: &[f64]| blur(plane, width, height, SIGMA);
+    let (mean_x, mean_y) = (blur(&x), blur(&y));
+    let (mean_xx, mean_yy, mean_xy) = (blur(&product(&x, &x)), blur(&product(&y, &y)), blur(&product(&x, &y)));
+    let total: f64 = (0..width * height)
+        .map(|i| {
+            let (mx, my) = (mean_x[i], mean_y[i]);
+            let variance_x = mean_xx[i] - mx * mx;
+            let variance_y = mean_yy[i] - my * my;
+            let covariance = mean_xy[i] - mx * my;
+            ((2.0 * mx * my + C1) * (2.0 * covariance + C2))
+                / ((mx * mx + my * my + C1) * (variance_x + variance_y + C2))
+        })
+        .sum();
+    total / (width * height) as f64
+}
+
+/// Of SSIMs at or below 0, `f64::INFINITY`.
+pub fn dssim(a: &DynamicImage, b: &DynamicImage) -> f64 {
+    match ssim(a, b) {
+        x if x <= 0.0 => f64::INFINITY,
+        x => 1.0 / x - 1.0,
+    }
+}
+
+/// BT.601, as JPEG’s YCbCr; alpha is ignored.
+fn luma(source: &DynamicImage) -> Vec<f64> {
+    source
+        .to_rgb8()
+        .pixels()
+        .map(|x| 0.299 * f64::from(x[0]) + 0.587 * f64::from(x[1]) + 0.114 * f64::from(x[2]))
+        .collect()
+}
+
+/// Gaussian, out to 3σ; separable, weighting the taps within the image (so
+/// edges aren’t darkened).
+pub(super) fn blur(plane: &[f64], width: usize, height: usize, sigma: f64) -> Vec<f64> {
+    let radius = (3.0 * sigma).ceil() as usize;
+    let kernel = (0..=2 * radius)
+        .map(|i| (-((i as f64 - radius as f64).powi(2)) / (2.0 * sigma * sigma)).exp())
+        .collect::<Vec<_>>();
+    let pass = |source: &[f64], length: usize, count: usize, index: &dyn Fn(usize, usize) -> usize| {
+        let mut output = vec![0.0; source.len()];
+        for line in 0..count {
+            for at in 0..length {
+                let (start, end) = (at.saturating_sub(radius), (at + radius).min(length - 1));
+                let (mut sum, mut weight) = (0.0, 0.0);
+                for tap in start..=end {
+                    let w = kernel[tap + radius - at];
+                    sum += w * source[index(line, tap)];
+                    weight += w;
+                }
+                output[index(line, at)] = sum / weight;
+            }
+        }
+        output
+    };
+    let horizontal = pass(plane, width, height, &|y, x| y * width + x);
+    pass(&horizontal, height, width, &|x, y| y * width + x)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ssim() {
+        let source = DynamicImage::ImageRgb8(image::RgbImage::from_fn(48, 32, |x, y| {
+            image::Rgb([(x * 5) as u8, (y * 7) as u8, ((x * y) % 256) as u8])
+        }));
+        assert!((ssim(&source, &source) - 1.0).abs() < 1e-9);
+        assert_eq!(dssim(&source, &source), 0.0);
+        let noisy = |amplitude: u32| {
+            let mut noisy = source.to_rgb8();
+            for (ix, pixel) in noisy.pixels_mut().enumerate() {
+                let offset = if ix % 2 == 0 { amplitude } else { 0 };
+                pixel.0 = pixel.0.map(|x| (u32::from(x) + offset).min(255) as u8);
+            }
+            DynamicImage::ImageRgb8(noisy)
+        };
+        let (slight, heavy) = (dssim(&source, &noisy(4)), dssim(&source, &noisy(40)));
+        assert!(0.0 < slight && slight < heavy, "{} {}", slight, heavy);
+        assert!((dssim(&noisy(40), &source) - heavy).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,182 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Long exposure stills from short clips (`imager long-exposure`): the
+//! frames of a `VideoBuffer`, averaged, e.g. to smooth water or streak
+//! lights. Handheld clips shake, so frames can first be aligned to the
+//! first one; alignment is a global translation, so rotation and parallax
+//! still blur.
+use rayon::prelude::*;
+
+use crate::data::{VideoBuffer, Yuv420P};
+
+/// The default bound of alignment shifts, in pixels.
+pub const DEFAULT_MAX_SHIFT: u32 = 32;
+
+/// Of the coarse alignment search.
+const PYRAMID_SCALE: u32 = 4;
+
+/// Averages every frame; with a `max_shift`, each is first aligned to the
+/// first frame, within it. Pixels a shift moves off the frame are filled
+/// from its edge.
+pub fn average(frames: &VideoBuffer, max_shift: Option<u32>) -> Result<Yuv420P, String> {
+    let frames = frames.as_frames();
+    let reference = frames.first().ok_or("no frames to average")?;
+    let (width, height) = reference.dimensions();
+    if let Some(frame) = frames.iter().find(|x| x.dimensions() != (width, height)) {
+        return Err(format!(
+            "frames differ in size ({}x{} and {}x{})",
+            width, height, frame.width, frame.height
+        ));
+    }
+    let shifts = frames
+        .par_iter()
+        .map(|frame| match max_shift {
+            Some(max_shift) => estimate_shift(reference, frame, max_shift),
+            None => (0, 0),
+        })
+        .collect::<Vec<_>>();
+    let mut sums = vec![0u32; reference.data.len()];
+    for (frame, (dx, dy)) in frames.iter().zip(shifts) {
+        let planes = [
+            (frame.y(), width, height, dx, dy, 0),
+            (frame.u(), width / 2, height / 2, dx.div_euclid(2), dy.div_euclid(2), frame.luma_size()),
+            (
+                frame.v(),
+                width / 2,
+                height / 2,
+                dx.div_euclid(2),
+                dy.div_euclid(2),
+                frame.luma_size() + frame.chroma_size(),
+            ),
+        ];
+        for (plane, width, height, dx, dy, offset) in planes {
+            for y in 0..height {
+                for x in 0..width {
+                    let sample = sample(plane, (width, height), x as i32 + dx, y as i32 + dy);
+                    sums[(offset + y * width + x) as usize] += u32::from(sample);
+                }
+            }
+        }
+    }
+    let count = frames.len() as u32;
+    let data = sums.into_iter().map(|x| ((x + count / 2) / count) as u8).collect();
+    Ok(Yuv420P { width, height, data })
+}
+
+fn sample(plane: &[u8], (width, height): (u32, u32), x: i32, y: i32) -> u8 {
+    let x = x.clamp(0, width as i32 - 1) as u32;
+    let y = y.clamp(0, height as i32 - 1) as u32;
+    plane[(y * width + x) as usize]
+}
+
+/// The `(dx, dy)` for which `frame(x + dx, y + dy)` best matches
+/// `reference(x, y)`, by the mean absolute (luma) difference of the
+/// overlap: searched at a quarter scale, then refined.
+pub fn estimate_shift(reference: &Yuv420P, frame: &Yuv420P, max_shift: u32) -> (i32, i32) {
+    let dimensions = reference.dimensions();
+    let (small_reference, small_dimensions) = downscale(reference.y(), dimensions);
+    let (small_frame, _) = downscale(frame.y(), dimensions);
+    let radius = max_shift.div_ceil(PYRAMID_SCALE) as i32;
+    let coarse = best_shift(&small_reference, &small_frame, small_dimensions, (0, 0), radius, 1);
+    let scale = PYRAMID_SCALE as i32;
+    let center = (coarse.0 * scale, coarse.1 * scale);
+    let (dx, dy) = best_shift(reference.y(), frame.y(), dimensions, center, scale - 1, 2);
+    let max_shift = max_shift as i32;
+    (dx.clamp(-max_shift, max_shift), dy.clamp(-max_shift, max_shift))
+}
+
+/// Box downscaled by `PYRAMID_SCALE`.
+fn downscale(plane: &[u8], (width, height): (u32, u32)) -> (Vec<u8>, (u32, u32)) {
+    let (small_width, small_height) = ((width / PYRAMID_SCALE).max(1), (height / PYRAMID_SCALE).max(1));
+    let mut output = Vec::with_capacity((small_width * small_height) as usize);
+    for y in 0..small_height {
+        for x in 0..small_width {
+            let mut sum = 0u32;
+            let mut count = 0u32;
+            for sy in (y * PYRAMID_SCALE)..((y + 1) * PYRAMID_SCALE).min(height) {
+                for sx in (x * PYRAMID_SCALE)..((x + 1) * PYRAMID_SCALE).min(width) {
+                    sum += u32::from(plane[(sy * width + sx) as usize]);
+                    count += 1;
+                }
+            }
+            output.push((sum / count.max(1)) as u8);
+        }
+    }
+    (output, (small_width, small_height))
+}
+
+/// The shift within `radius` of `center` of the lowest mean absolute
+/// difference, sampling every `step`th pixel; ties go to the smaller shift.
+fn best_shift(
+    reference: &[u8],
+    frame: &[u8],
+    (width, height): (u32, u32),
+    center: (i32, i32),
+    radius: i32,
+    step: usize,
+) -> (i32, i32) {
+    let (width, height) = (width as i32, height as i32);
+    let cost = |(dx, dy): (i32, i32)| -> Option<f64> {
+        let (x0, x1) = (0.max(-dx), width.min(width - dx));
+        let (y0, y1) = (0.max(-dy), height.min(height - dy));
+        // AT LEAST HALF OF THE FRAME MUST OVERLAP
+        if (x1 - x0) * 2 < width || (y1 - y0) * 2 < height {
+            return None;
+        }
+        let mut sum = 0u64;
+        let mut count = 0u64;
+        for y in (y0..y1).step_by(step) {
+            for x in (x0..x1).step_by(step) {
+                let a = reference[(y * width + x) as usize];
+                let b = frame[((y + dy) * width + x + dx) as usize];
+                sum += u64::from(a.abs_diff(b));
+                count += 1;
+            }
+        }
+        Some(sum as f64 / count.max(1) as f64)
+    };
+    let candidates = (-radius..=radius).flat_map(|y| (-radius..=radius).map(move |x| (x, y)));
+    candidates
+        .map(|(x, y)| (center.0 + x, center.1 + y))
+        .filter_map(|shift| Some((cost(shift)?, shift)))
+        .min_by(|(a, a_shift), (b, b_shift)| {
+            let magnitude = |(x, y): (i32, i32)| x.abs() + y.abs();
+            a.total_cmp(b).then(magnitude(*a_shift).cmp(&magnitude(*b_shift)))
+        })
+        .map_or(center, |(_, shift)| shift)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_average() {
+        // A TEXTURED FRAME, AND COPIES SHIFTED AS BY A SHAKY HAND
+        let frame = |dx: i32, dy: i32| {
+            let (width, height) = (64, 48);
+            let value = |x: i32, y: i32| {
+                let (x, y) = ((x - dx).div_euclid(3), (y - dy).div_euclid(3));
+                (x.wrapping_mul(73_856_093) ^ y.wrapping_mul(19_349_663)).rem_euclid(251) as u8
+            };
+            let mut data = (0..height)
+                .flat_map(|y| (0..width).map(move |x| value(x, y)))
+                .collect::<Vec<_>>();
+            data.resize((width * height * 3 / 2) as usize, 128);
+            Yuv420P { width: width as u32, height: height as u32, data }
+        };
+        assert_eq!(estimate_shift(&frame(0, 0), &frame(6, -4), 16), (6, -4));
+        let frames = VideoBuffer::from_frames(vec![frame(0, 0), frame(6, -4), frame(-2, 10)]).expect("frames");
+        let aligned = average(&frames, Some(16)).expect("average");
+        let blurred = average(&frames, None).expect("average");
+        let reference = frame(0, 0);
+        // AWAY FROM THE EDGES, WHICH SHIFTS FILL
+        let interior = |image: &Yuv420P| {
+            let pixels = (12..36).flat_map(|y| (8..56).map(move |x| (x, y)));
+            pixels.map(|(x, y)| image.y()[y * 64 + x]).collect::<Vec<_>>()
+        };
+        assert_eq!(interior(&aligned), interior(&reference));
+        assert_ne!(interior(&blurred), interior(&reference));
+    }
+}
@@ -0,0 +1,231 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Static HTML galleries of batch reports (`--report html`), for reviewing
+//! a batch visually: each output next to its source, with a before/after
+//! slider, the file sizes, VMAF score and warnings.
+//!
+//! The page is self contained, but references the images by relative path;
+//! move it together with the inputs and outputs. Sources browsers can’t
+//! display (e.g. TIFF) show as broken images.
+use std::fmt::Write;
+use std::path::{Component, Path, PathBuf};
+
+use crate::api::OutMeda;
+use crate::report::Report;
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em; background: #f4f4f4; color: #222; }
+.asset { background: #fff; margin-bottom: 2em; padding: 1em; border-radius: 4px; }
+.compare { position: relative; display: inline-block; max-width: 100%; }
+.compare img { display: block; max-width: 100%; max-height: 80vh; }
+.compare .after { position: absolute; top: 0; left: 0; width: 100%; height: 100%; object-fit: contain; }
+.compare input { width: 100%; }
+table { border-collapse: collapse; }
+td, th { padding: 0.2em 1em 0.2em 0; text-align: left; }
+.warning { color: #a55a00; }
+.error { color: #b00020; }
+"#;
+
+// CLIPS THE OUTPUT TO THE RIGHT OF THE SLIDER
+const SCRIPT: &str = r#"
+for (const slider of document.querySelectorAll('.compare input')) {
+  const after = slider.parentElement.querySelector('.after');
+  const update = () => { after.style.clipPath = 'inset(0 0 0 ' + slider.value + '%)'; };
+  slider.addEventListener('input', update);
+  update();
+}
+"#;
+
+/// The gallery page of `report`, to be written into `dir`.
+pub fn render(report: &Report, dir: &Path) -> String {
+    let source_size: u64 = report.outputs.iter().filter_map(|x| x.input_size).sum();
+    let output_size: u64 = report.outputs.iter().filter_map(|x| x.output_size).sum();
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str("<title>imager report</title>\n<style>");
+    html.push_str(STYLE);
+    html.push_str("</style>\n</head>\n<body>\n<h1>imager report</h1>\n");
+    writeln!(
+        html,
+        "<p>{} outputs, {} errors, {} warnings; {} → {}</p>",
+        report.outputs.len(),
+        report.errors.len(),
+        report.warning_count(),
+        file_size(Some(source_size)),
+        file_size(Some(output_size)),
+    )
+    .expect("write to string");
+    if !report.errors.is_empty() {
+        html.push_str("<ul class=\"error\">\n");
+        for error in &report.errors {
+            writeln!(html, "<li>{}</li>", escape(&error.to_string())).expect("write to string");
+        }
+        html.push_str("</ul>\n");
+    }
+    for output in &report.outputs {
+        asset(&mut html, output, dir);
+    }
+    html.push_str("<script>");
+    html.push_str(SCRIPT);
+    html.push_str("</script>\n</body>\n</html>\n");
+    html
+}
+
+fn asset(html: &mut String, output: &OutMeda, dir: &Path) {
+    let name = |x: &Option<PathBuf>| {
+        x.as_ref()
+            .map(|x| x.display().to_string())
+            .unwrap_or_default()
+    };
+    html.push_str("<div class=\"asset\">\n");
+    writeln!(
+        html,
+        "<h2>{} → {}</h2>",
+        escape(&name(&output.input_path)),
+        escape(&name(&output.output_path))
+    )
+    .expect("write to string");
+    // REPLACED INPUTS ARE GONE
+    let before = output.input_path.as_ref().filter(|x| Some(*x) != output.output_path.as_ref());
+    match (before, &output.output_path) {
+        (Some(before), Some(after)) => {
+            writeln!(
+                html,
+                "<div class=\"compare\"><img src=\"{}\" alt=\"before\">\
+                 <img class=\"after\" src=\"{}\" alt=\"after\">\
+                 <input type=\"range\" min=\"0\" max=\"100\" value=\"50\"></div>",
+                url(before, dir),
+                url(after, dir)
+            )
+            .expect("write to string");
+        }
+        (_, Some(after)) => {
+            writeln!(html, "<div><img src=\"{}\" alt=\"output\"></div>", url(after, dir))
+                .expect("write to string");
+        }
+        _ => (),
+    }
+    let savings = output
+        .savings()
+        .map(|x| format!(" ({:+.1}%)", -x * 100.0))
+        .unwrap_or_default();
+    let vmaf = output
+        .vmaf_score
+        .map(|x| format!("{:.2}", x))
+        .unwrap_or_else(|| String::from("–"));
+    writeln!(
+        html,
+        "<table>\n<tr><th>Size</th><td>{} → {}{}</td></tr>\n\
+         <tr><th>VMAF</th><td>{}</td></tr>\n\
+         <tr><th>Class</th><td>{:?}</td></tr>\n</table>",
+        file_size(output.input_size),
+        file_size(output.output_size),
+        savings,
+        vmaf,
+        output.input_class
+    )
+    .expect("write to string");
+    if !output.warnings.is_empty() {
+        html.push_str("<ul class=\"warning\">\n");
+        for warning in &output.warnings {
+            writeln!(html, "<li>{}</li>", escape(&warning.to_string())).expect("write to string");
+        }
+        html.push_str("</ul>\n");
+    }
+    html.push_str("</div>\n");
+}
+
+pub fn file_size(bytes: Option<u64>) -> String {
+    match bytes {
+        None => String::from("?"),
+        Some(x) if x < 1024 => format!("{} B", x),
+        Some(x) if x < 1024 * 1024 => format!("{:.1} KiB", x as f64 / 1024.0),
+        Some(x) => format!("{:.1} MiB", x as f64 / (1024.0 * 1024.0)),
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&#39;"),
+            _ => output.push(c),
+        }
+    }
+    output
+}
+
+/// The (percent encoded) URL of `path`, relative to `dir`.
+fn url(path: &Path, dir: &Path) -> String {
+    let absolute = |x: &Path| {
+        x.canonicalize()
+            .or_else(|_| std::env::current_dir().map(|cwd| cwd.join(x)))
+            .unwrap_or_else(|_| x.to_path_buf())
+    };
+    let (path, dir) = (absolute(path), absolute(dir));
+    let common = path
+        .components()
+        .zip(dir.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut segments = vec![String::from(".."); dir.components().count() - common];
+    for component in path.components().skip(common) {
+        if let Component::Normal(x) = component {
+            segments.push(x.to_string_lossy().into_owned());
+        }
+    }
+    segments
+        .iter()
+        .map(|segment| {
+            let mut encoded = String::new();
+            for byte in segment.bytes() {
+                match byte {
+                    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                        encoded.push(byte as char)
+                    }
+                    _ => write!(encoded, "%{:02X}", byte).expect("write to string"),
+                }
+            }
+            encoded
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::report::{Warning, WarningKind};
+
+    #[test]
+    fn test_render_gallery() {
+        let output = OutMeda {
+            input_class: crate::classifier::Class::L1,
+            input_path: Some(PathBuf::from("/photos/a b.jpeg")),
+            output_path: Some(PathBuf::from("/photos/out/a b.webp")),
+            vmaf_score: Some(91.5),
+            distance: None,
+            extreme_mode: Some(false),
+            decoder: None,
+            c2pa: None,
+            warnings: vec![Warning::new(WarningKind::Upscaled, "<enlarged>")],
+            fallback: None,
+            versions: None,
+            input_size: Some(2048),
+            output_size: Some(512),
+            duration_ms: None,
+        };
+        let report = Report::from_results(vec![Ok(output)]);
+        let html = render(&report, Path::new("/photos/out"));
+        assert!(html.contains("src=\"../a%20b.jpeg\""));
+        assert!(html.contains("src=\"a%20b.webp\""));
+        assert!(html.contains("2.0 KiB → 512 B (-75.0%)"));
+        assert!(html.contains("91.50"));
+        assert!(html.contains("upscaled: &lt;enlarged&gt;"));
+    }
+}
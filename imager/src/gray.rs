@@ -0,0 +1,95 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Grayscale and bilevel (1 bit) outputs, e.g. of scanned documents for
+//! archives: alpha is flattened over white, then for bilevel every pixel is
+//! thresholded to black or white, at a fixed luma or Otsu’s (the luma that
+//! best separates the histogram into two classes, ink and paper).
+use image::{DynamicImage, GenericImageView, GrayImage, Luma};
+
+use crate::data::{ColorMode, Threshold};
+
+/// The source in the mode; colors are left as is.
+pub fn convert(source: &DynamicImage, mode: ColorMode, threshold: Threshold) -> DynamicImage {
+    if mode == ColorMode::Color {
+        return source.clone();
+    }
+    let (width, height) = source.dimensions();
+    let mut gray = GrayImage::from_fn(width, height, |x, y| {
+        let [r, g, b, a] = source.get_pixel(x, y).0;
+        // BT.601, AS `image`’S GRAYSCALE CONVERSION
+        let luma = (299 * u32::from(r) + 587 * u32::from(g) + 114 * u32::from(b)) / 1000;
+        let alpha = u32::from(a);
+        Luma([((luma * alpha + 255 * (255 - alpha)) / 255) as u8])
+    });
+    if mode == ColorMode::Bilevel {
+        let threshold = match threshold {
+            Threshold::Fixed(x) => x,
+            Threshold::Otsu => otsu(&histogram(&gray)),
+        };
+        for pixel in gray.pixels_mut() {
+            pixel.0[0] = if pixel.0[0] >= threshold { 255 } else { 0 };
+        }
+    }
+    DynamicImage::ImageLuma8(gray)
+}
+
+fn histogram(gray: &GrayImage) -> [u64; 256] {
+    let mut histogram = [0; 256];
+    for pixel in gray.pixels() {
+        histogram[usize::from(pixel.0[0])] += 1;
+    }
+    histogram
+}
+
+/// The threshold maximizing the between-class variance; pixels at or above
+/// it are white.
+pub fn otsu(histogram: &[u64; 256]) -> u8 {
+    let total = histogram.iter().sum::<u64>() as f64;
+    let sum = histogram.iter().enumerate().map(|(ix, x)| ix as f64 * *x as f64).sum::<f64>();
+    let (mut best, mut best_variance) = (128, 0.0);
+    let (mut weight, mut weighted) = (0.0, 0.0);
+    // BLACK IS BELOW `threshold`
+    for threshold in 1..=255 {
+        weight += histogram[threshold - 1] as f64;
+        weighted += (threshold - 1) as f64 * histogram[threshold - 1] as f64;
+        let other = total - weight;
+        if weight == 0.0 || other == 0.0 {
+            continue;
+        }
+        let difference = weighted / weight - (sum - weighted) / other;
+        let variance = weight * other * difference * difference;
+        if variance > best_variance {
+            best = threshold as u8;
+            best_variance = variance;
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_convert() {
+        let mut histogram = [0; 256];
+        histogram[40] = 100;
+        histogram[200] = 300;
+        assert_eq!(otsu(&histogram), 41);
+        // GRAY INK ON LIGHT PAPER, AND A TRANSPARENT CORNER
+        let source = image::RgbaImage::from_fn(8, 8, |x, y| match (x, y) {
+            (0, 0) => image::Rgba([0, 0, 0, 0]),
+            _ if x < 4 => image::Rgba([90, 90, 90, 255]),
+            _ => image::Rgba([220, 220, 220, 255]),
+        });
+        let source = DynamicImage::ImageRgba8(source);
+        let gray = convert(&source, ColorMode::Grayscale, Threshold::Otsu).to_luma8();
+        assert_eq!((gray.get_pixel(0, 0).0, gray.get_pixel(1, 0).0), ([255], [90]));
+        let bilevel = convert(&source, ColorMode::Bilevel, Threshold::Otsu).to_luma8();
+        assert_eq!(bilevel.get_pixel(1, 0).0, [0]);
+        assert_eq!(bilevel.get_pixel(7, 7).0, [255]);
+        let dark = convert(&source, ColorMode::Bilevel, Threshold::Fixed(230)).to_luma8();
+        assert_eq!(dark.get_pixel(7, 7).0, [0]);
+    }
+}
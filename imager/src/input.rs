@@ -0,0 +1,137 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+use std::convert::AsRef;
+use std::path::Path;
+
+pub use imager_core::input::{ReadMode, MMAP_THRESHOLD};
+
+///////////////////////////////////////////////////////////////////////////////
+// MEMORY-MAPPED FILES
+///////////////////////////////////////////////////////////////////////////////
+
+/// A read-only, private mapping of an entire file.
+#[cfg(unix)]
+pub struct MappedFile {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// The mapping is read-only, so sharing it across threads is fine.
+#[cfg(unix)]
+unsafe impl Send for MappedFile {}
+#[cfg(unix)]
+unsafe impl Sync for MappedFile {}
+
+#[cfg(unix)]
+impl MappedFile {
+    fn open(file: &std::fs::File, len: usize) -> std::io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self { ptr, len })
+    }
+    #[must_use]
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        };
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// INPUT BUFFER
+///////////////////////////////////////////////////////////////////////////////
+
+/// The raw (still encoded) bytes of an input file.
+pub enum InputBuffer {
+    Heap(Vec<u8>),
+    #[cfg(unix)]
+    Mapped(MappedFile),
+}
+
+impl InputBuffer {
+    pub fn open<P: AsRef<Path>>(path: P, mode: ReadMode) -> std::io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let len = file.metadata()?.len();
+        let use_mmap = match mode {
+            ReadMode::Auto => len >= MMAP_THRESHOLD,
+            ReadMode::Mmap => true,
+            ReadMode::Heap => false,
+        };
+        // ZERO LENGTH FILES CAN’T BE MAPPED
+        if use_mmap && len > 0 {
+            #[cfg(unix)]
+            {
+                if let Ok(mapped) = MappedFile::open(&file, len as usize) {
+                    return Ok(Self::Mapped(mapped));
+                }
+            }
+        }
+        Self::read_to_heap(file, len)
+    }
+    fn read_to_heap(mut file: std::fs::File, len: u64) -> std::io::Result<Self> {
+        use std::io::Read;
+        let mut buffer = Vec::with_capacity(len as usize);
+        file.read_to_end(&mut buffer)?;
+        Ok(Self::Heap(buffer))
+    }
+    #[must_use]
+    pub fn is_mapped(&self) -> bool {
+        match self {
+            Self::Heap(_) => false,
+            #[cfg(unix)]
+            Self::Mapped(_) => true,
+        }
+    }
+}
+
+impl AsRef<[u8]> for InputBuffer {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            Self::Heap(x) => x.as_slice(),
+            #[cfg(unix)]
+            Self::Mapped(x) => x.as_slice(),
+        }
+    }
+}
+
+impl std::ops::Deref for InputBuffer {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        self.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_modes_agree() {
+        let path = "assets/test/1.jpeg";
+        let expected = std::fs::read(path).expect("read test image");
+        for mode in [ReadMode::Auto, ReadMode::Mmap, ReadMode::Heap] {
+            let buffer = InputBuffer::open(path, mode).expect("open test image");
+            assert_eq!(&buffer[..], expected.as_slice());
+        }
+    }
+}
@@ -1,6 +1,61 @@
 #![allow(unused)]
+
+#[cfg(not(any(feature = "ffi", feature = "pure-rust")))]
+compile_error!("imager needs the `ffi` (default) or the `pure-rust` feature");
+
 pub mod api;
+pub mod archive;
+pub mod background;
+pub mod batch;
+pub mod bench;
+pub mod book;
+pub mod burst;
 pub mod classifier;
 pub mod codec;
+pub mod composite;
+pub mod crop;
 pub mod data;
+pub mod decode;
+pub mod diff;
+pub mod error;
+pub mod eval;
+pub mod exposure;
+pub mod gallery;
+pub mod gray;
+pub mod input;
+pub mod manifest;
+pub mod meta;
+pub mod montage;
+pub mod multires;
+#[cfg(feature = "panorama")]
+pub mod panorama;
+pub mod pipeline;
+pub mod planes;
+pub mod preview;
+pub mod probe;
+pub mod provenance;
+pub use imager_core::profile;
+pub mod rd;
+pub mod replan;
+pub mod report;
+pub mod resize;
+pub mod sandbox;
+pub mod server;
+pub mod sftp;
+pub use imager_core::sharp;
+pub mod srcset;
+pub mod text;
+pub mod text_protect;
+pub mod thumbnail;
+pub mod trace;
+pub mod tune;
+pub mod upscale;
+#[cfg(feature = "vectorize")]
+pub mod vectorize;
+pub mod verify;
+pub mod video;
+#[cfg(not(feature = "pure-rust"))]
 pub mod vmaf;
+pub mod watermark;
+pub mod webhook;
+pub mod workspace;
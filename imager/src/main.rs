@@ -1,28 +1,92 @@
 #![allow(unused)]
 
-#[cfg(not(target_os = "windows"))]
+#[cfg(all(not(target_os = "windows"), feature = "ffi"))]
 use jemallocator::Jemalloc;
 
-#[cfg(not(target_os = "windows"))]
+#[cfg(all(not(target_os = "windows"), feature = "ffi"))]
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
 pub mod api;
+pub mod archive;
+pub mod background;
+pub mod batch;
+pub mod bench;
+pub mod book;
+pub mod burst;
 pub mod classifier;
 pub mod codec;
+pub mod composite;
+pub mod crop;
 pub mod data;
+pub mod decode;
+pub mod diff;
+pub mod error;
+pub mod eval;
+pub mod exposure;
+pub mod gallery;
+pub mod gray;
+pub mod input;
+pub mod manifest;
+pub mod meta;
+pub mod montage;
+pub mod multires;
+#[cfg(feature = "panorama")]
+pub mod panorama;
+pub mod pipeline;
+pub mod planes;
+pub mod preview;
+pub mod probe;
+mod provenance;
+pub use imager_core::profile;
+pub mod rd;
+pub mod replan;
+pub mod report;
+pub mod resize;
+pub mod sandbox;
+pub mod server;
+pub mod sftp;
+pub use imager_core::sharp;
+pub mod srcset;
+pub mod text;
+pub mod text_protect;
+pub mod thumbnail;
+pub mod trace;
+pub mod tune;
+pub mod upscale;
+#[cfg(feature = "vectorize")]
+pub mod vectorize;
+pub mod verify;
+pub mod video;
+#[cfg(not(feature = "pure-rust"))]
 pub mod vmaf;
+pub mod watermark;
+pub mod webhook;
+pub mod workspace;
 
 use indicatif::{ProgressBar, ProgressStyle};
 use itertools::Itertools;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use structopt::clap::ArgGroup;
+use structopt::clap::{AppSettings, ArgGroup};
 use structopt::StructOpt;
 
-use crate::data::{OutputFormat, OutputFormats, Resolution};
+use crate::archive::ArchiveFormat;
+use crate::crop::Crop;
+use crate::data::{
+    BrandPalette, ColorMode, InferOutputFormat, OutputFormat, OutputFormats, Resolution, Seed, Threshold,
+};
+use crate::decode::{DecodeOptions, DecoderChain};
+use crate::input::ReadMode;
+use crate::meta::{Attribution, PrivacyPolicy};
+use crate::provenance::{Provenance, ProvenanceMode};
+use crate::report::{FileError, FileErrorKind, Report, ReportFormat};
+use crate::resize::{Fit, ResizeFilter};
+use crate::upscale::Upscaler;
+use crate::workspace::JobDir;
 
 ///////////////////////////////////////////////////////////////////////////////
 // CLI FRONTEND - INTERNAL HELPER TYPES
@@ -62,28 +126,38 @@ impl OutputType {
 
 /// The Imager CLI Interface
 ///
-/// Output type much be one of: `--output-file`, `--output-dir`, or `--replace`.
+/// Output type much be one of: `--output-file`, `--output-dir`, or `--replace`
+/// (or `--explain`, to only print what would be done).
 #[derive(Debug, Clone, StructOpt)]
 #[structopt(
     name = "imager",
     // rename_all = "kebab-case",
-    group = (ArgGroup::with_name("output_type").required(true)),
+    // EXCLUSIVITY IS CHECKED IN `Command::run`, SINCE `--explain` GOES WITH ANY
+    group = (ArgGroup::with_name("output_type").required(true).multiple(true)),
+    setting = AppSettings::SubcommandsNegateReqs,
 )]
 pub struct Command {
     /// Input file(s) path.
+    ///
+    /// Glob patterns, or `sftp://[user@]host[:port]/path` URLs of files on
+    /// remote servers (patterns too), read over one pooled SSH connection
+    /// per server. Zip and tar (`.tar`, `.tar.gz`) archives are inputs
+    /// too: their images are optimized in parallel, into an archive of the
+    /// same paths (of `--output-file`’s format, if given), where other
+    /// members (and images that failed) are kept as they were.
     #[structopt(short, long, required = true, min_values = 1)]
     inputs: Vec<String>,
 
     /// Save the result to this file path.
     ///
-    /// Save the optimized file to this path.
+    /// Save the optimized file to this path (or `sftp://` URL).
     /// Only works for single input/output files.
     #[structopt(short = "o", long, parse(from_os_str), group = "output_type")]
     output_file: Option<PathBuf>,
 
     /// Save results under this directory.
     ///
-    /// Dump results to this directory.
+    /// Dump results to this directory (or `sftp://` URL).
     /// Files will have the same name as the input file.
     /// Valid for multiple input/output files.
     #[structopt(short = "O", long, parse(from_os_str), group = "output_type")]
@@ -91,7 +165,8 @@ pub struct Command {
 
     /// Replace input files with their optimized results.
     ///
-    /// Valid for multiple input/output files.
+    /// Valid for multiple input/output files, and `sftp://` inputs, which
+    /// are replaced in place on their server.
     #[structopt(long, group = "output_type")]
     replace: bool,
 
@@ -103,51 +178,1073 @@ pub struct Command {
     #[structopt(short, long, default_value = "jpeg webp")]
     formats: Vec<OutputFormats>,
 
+    /// Formats to fall back to, in order, when the encoder of one of the
+    /// `--formats` fails (e.g. an encoder bug, or dimensions the format
+    /// can’t represent), rather than failing the file, e.g. `--fallback
+    /// "webp jpeg"` for AVIF outputs. The log file records each fallback.
+    #[structopt(long)]
+    fallback: Vec<OutputFormats>,
+
     /// Resize or downscale images if their resolution exceeds the given size.
     #[structopt(long)]
     max_size: Option<Resolution>,
 
-    /// Internal. No stability guarantees.
+    /// How images are resized to the `--max-size`: `inside` (fit within
+    /// it), `outside` (cover it), `contain` (fit within it, padded to it),
+    /// `cover` (cover it, cropped to it) or `fill` (stretched to it).
+    #[structopt(long, default_value = "inside", requires = "max-size")]
+    fit: Fit,
+
+    /// The filter of resizes: `lanczos3`, `catmull-rom` or `triangle`
+    /// (bilinear, the softest).
+    #[structopt(long, default_value = "lanczos3")]
+    resize_filter: ResizeFilter,
+
+    /// Which part of `--fit cover` resizes is kept: `centre`, `entropy`
+    /// (the busiest part) or `attention` (the part with the most detail,
+    /// skin tones and saturated colors).
+    #[structopt(long, default_value = "centre")]
+    crop: Crop,
+
+    /// Also enlarge images smaller than the `--max-size` to fit it, by at
+    /// most 4x per side.
+    #[structopt(long, requires = "max-size")]
+    allow_upscale: bool,
+
+    /// How to upscale (see `--allow-upscale`): `lanczos`, or `esrgan`
+    /// (needs the `esrgan` build feature and `realesrgan-ncnn-vulkan`).
+    #[structopt(long, default_value = "lanczos")]
+    upscaler: Upscaler,
+
+    /// How input files are read: `auto`, `mmap` or `heap`.
+    ///
+    /// `auto` memory-maps large inputs and reads small ones into memory.
+    /// Use `heap` on network filesystems or wherever mmap is undesirable.
+    #[structopt(long, default_value = "auto")]
+    read_mode: ReadMode,
+
+    /// Where stages that need disk (e.g. C2PA signing) keep their files,
+    /// in a directory per job, removed after; also `IMAGER_TMPDIR`. It
+    /// must be the user’s own, with mode 700 on Unix. Given before a
+    /// subcommand, it applies to it too.
+    #[structopt(long, parse(from_os_str))]
+    temp_dir: Option<PathBuf>,
+
+    /// Decoders to try, in order, until one succeeds.
+    ///
+    /// Any of `image`, `turbo` (libjpeg-turbo, JPEG only) and `ffmpeg`
+    /// (requires an `ffmpeg` executable). The decoder that handled each
+    /// input is recorded in the log file.
+    #[structopt(long, default_value = "image turbo ffmpeg")]
+    decoders: DecoderChain,
+
+    /// Accept truncated or partially corrupt inputs where the decoder can
+    /// recover, instead of failing (or moving on to the next decoder).
+    #[structopt(long)]
+    tolerate_truncated: bool,
+
+    /// Rotate (or flip) the pixels by the source’s EXIF orientation, for
+    /// viewers that ignore the tag; the output’s EXIF then has the default
+    /// orientation.
+    #[structopt(long)]
+    auto_orient: bool,
+
+    /// Snap output colors to this palette (e.g. design-system tokens), as
+    /// `#RRGGBB` or `#RRGGBBAA` colors. PNG and WebP outputs use exactly
+    /// these colors; JPEG only approximately.
+    #[structopt(long)]
+    palette: Option<BrandPalette>,
+
+    /// Don’t dither when snapping to the `--palette`, e.g. for flat icons.
+    #[structopt(long)]
+    no_palette_dither: bool,
+
+    /// `color`, `grayscale`, or `bilevel` (1 bit, PNG and TIFF only), e.g.
+    /// for scanned documents.
+    #[structopt(long, default_value = "color")]
+    color_mode: ColorMode,
+
+    /// Of `--color-mode bilevel`: `otsu` (picked per image), or a luma from
+    /// 0 to 255, at or above which pixels are white.
+    #[structopt(long, default_value = "otsu")]
+    threshold: Threshold,
+
+    /// One perceptual quality knob for every lossy format: the max
+    /// butteraugli distance of outputs (about 1 where differences start to
+    /// be noticeable, e.g. 1.5 or 2 for smaller files). Each gets the
+    /// lowest quality within it, in place of the VMAF search.
+    #[structopt(long)]
+    max_distance: Option<f64>,
+
+    /// Per format VMAF targets of the searches, in place of the content
+    /// dependent ones, e.g. `webp=92,avif=90` (AVIF degrades more
+    /// gracefully).
+    #[structopt(long)]
+    vmaf_target: Option<crate::data::VmafTargets>,
+
+    /// A built-in profile: `data-saver`, for low-bandwidth traffic (qualities
+    /// capped at 55, a max distance of at least 2.5, and at most 1280x1280).
+    #[structopt(long)]
+    preset: Option<crate::profile::Preset>,
+
+    /// Keep the text of UI screenshots crisp: text blocks get corrected
+    /// chroma, and a higher quality (JPEG only).
+    #[structopt(long)]
+    text_protect: bool,
+
+    /// Speed up the VMAF searches: bracket the quality by a search on a
+    /// low-resolution proxy, then confirm it at full size, for 2-3x fewer
+    /// full-size encodes at a little accuracy.
+    #[structopt(long)]
+    fast_search: bool,
+
+    /// Speed up the VMAF searches of very large sources (from 16MP): score
+    /// them on this share (e.g. 0.25) of their 256x256 tiles, mostly the
+    /// most detailed ones, rather than the whole frame.
+    #[structopt(long)]
+    metric_coverage: Option<f64>,
+
+    /// Composite transparency onto this color (`#RRGGBB`) for lossy WebP
+    /// outputs, rather than keep it, and for JPEG outputs, rather than onto
+    /// white.
+    #[structopt(long)]
+    matte: Option<crate::data::Matte>,
+
+    /// Seeds any stage that uses randomness; the same inputs, options and
+    /// seed always give the same outputs.
+    #[structopt(long, default_value = "0")]
+    seed: Seed,
+
+    /// Make backgrounds transparent, e.g. for product cutouts, using the
+    /// given ONNX segmentation model (U²-Net style, e.g. `u2netp.onnx`).
+    /// Needs the `background-removal` build feature and the ONNX Runtime
+    /// library (found via `ORT_DYLIB_PATH`). JPEG outputs have no alpha.
+    #[structopt(long, parse(from_os_str))]
+    remove_background: Option<PathBuf>,
+
+    /// Metadata to carry over from the source (also `--metadata`): `strip`,
+    /// or any of `basic` (orientation, resolution), `camera`, `exposure`,
+    /// `capture-time` and `copyright` of the EXIF, `icc` (the color
+    /// profile) and `xmp` (as is). GPS data and serial numbers are always
+    /// stripped from the EXIF, and a non-default orientation always kept.
+    #[structopt(long, alias = "metadata", default_value = "strip")]
+    exif: PrivacyPolicy,
+
+    /// Embed a thumbnail (regenerated from the output) in the EXIF of JPEG
+    /// outputs, for DAM systems and cameras that show it.
+    #[structopt(long)]
+    exif_thumbnail: bool,
+
+    /// Credit every output to this creator (XMP `dc:creator`), whether or
+    /// not the source carried any attribution.
+    #[structopt(long)]
+    creator: Option<String>,
+
+    /// Copyright notice for every output (XMP `dc:rights`).
+    #[structopt(long)]
+    copyright: Option<String>,
+
+    /// License URL for every output (XMP `xmpRights:WebStatement` and
+    /// `cc:license`).
+    #[structopt(long)]
+    license_url: Option<String>,
+
+    /// Sign outputs with C2PA content credentials (via `c2patool`), using
+    /// the given JSON config: `alg`, `private_key`, `sign_cert`, and
+    /// optionally `ta_url` and `tool`. Sources with credentials become the
+    /// parent ingredient, so their provenance chain is preserved.
+    #[structopt(long, parse(from_os_str))]
+    c2pa_signer: Option<PathBuf>,
+
+    /// Invisibly mark outputs with this (32 bit) ID. The mark survives
+    /// lossy re-encoding, but not cropping or resizing; read it back with
+    /// `imager verify-mark`.
+    #[structopt(long)]
+    watermark: Option<u32>,
+
+    /// Write a JSON report of every output, and of every file that
+    /// failed (with the kind of failure), to the given path.
     #[structopt(long, parse(from_os_str))]
     log_file: Option<PathBuf>,
 
+    /// The format of the `--log-file` report: `json`, `csv` (one row per
+    /// output or failed file), or `html` for a static gallery page with
+    /// before/after sliders, sizes and VMAF scores of every output, for
+    /// visual review.
+    #[structopt(long = "report", default_value = "json")]
+    report_format: ReportFormat,
+
+    /// Write a JSON manifest of the SHA-256 (and size) of every output,
+    /// and of its source, to the given path, e.g. for supply-chain
+    /// attestation of generated assets.
+    #[structopt(long, parse(from_os_str))]
+    manifest: Option<PathBuf>,
+
+    /// Record the provenance of every output: the SHA-256 of its source,
+    /// its parameters (as `--explain` shows them) and their SHA-256, and
+    /// the imager and encoder versions. `sidecar` writes them to
+    /// `<output>.provenance.json`; `xmp` embeds the digests and versions
+    /// (but in TIFF, AVIF and JPEG XL outputs, which get the sidecar).
+    #[structopt(long)]
+    provenance: Option<ProvenanceMode>,
+
+    /// POST the final JSON report to this URL when the batch completes
+    /// (via `curl`). Set `IMAGER_WEBHOOK_SECRET` to sign requests with an
+    /// `X-Imager-Signature-256` HMAC-SHA256 header. A `TRACEPARENT` in the
+    /// environment is passed on.
+    #[structopt(long)]
+    webhook: Option<String>,
+
     /// Internal. No stability guarantees.
     #[structopt(long)]
     extreme: bool,
+
+    /// Print the plan of each output (decoder, stages in order, encoder
+    /// settings and search strategy) instead of optimizing.
+    #[structopt(long, group = "output_type")]
+    explain: bool,
+
+    /// Exit with status 2 when any output has quality warnings (e.g.
+    /// upscaled, alpha flattened, color profile discarded), for CI. Errors
+    /// still exit with 1.
+    #[structopt(long)]
+    deny_warnings: bool,
+
+    /// Encode with the encoder plugin (a shared library implementing
+    /// `include/imager_plugin.h`) in place of the built-in encoder of its
+    /// format; requires the `plugins` feature.
+    #[structopt(long, parse(from_os_str))]
+    plugin: Vec<PathBuf>,
+
+    #[structopt(subcommand)]
+    tool: Option<Tool>,
+}
+
+/// Tools other than optimizing.
+#[derive(Debug, Clone, StructOpt)]
+pub enum Tool {
+    /// Check images for corruption (truncation, bad checksums, undecodable
+    /// data); fails if any are corrupt.
+    Verify(Verify),
+    /// Report the invisible watermark (see `--watermark`) of images.
+    VerifyMark(VerifyMark),
+    /// Run a pipeline (stages and their options, as JSON) on images.
+    Pipeline(RunPipeline),
+    /// Optimize every image under a directory, recursively and in
+    /// parallel, mirroring its structure in the output directory.
+    Batch(RunBatch),
+    /// Arrange images into a labeled grid (contact sheet).
+    Montage(Montage),
+    /// Stitch two images (e.g. a source and its output) for comparison.
+    Stitch(Stitch),
+    /// Render a heatmap of the (perceptual) differences of two images.
+    Diff(Diff),
+    /// Write the Y/U/V planes and R/G/B/A channels of images as grayscale
+    /// PNGs.
+    Planes(Planes),
+    /// Encode an image across a sweep of qualities per codec, and write
+    /// the size-vs-score curve.
+    RdCurve(RdCurve),
+    /// Compare codecs over a corpus of images, by size at a target score
+    /// and BD-rate.
+    Bench(Bench),
+    /// Write an optimization profile (`imager.toml`) with quality ranges,
+    /// JPEG subsampling and WebP effort tuned to a sample of images (e.g.
+    /// for `imager serve --profile`).
+    Tune(Tune),
+    /// Write an ICNS, multi-size ICO, or HEIF (with thumbnails) of an
+    /// image, e.g. for app packaging.
+    Pack(Pack),
+    /// Optimize the images of an EPUB or CBZ book in place (keeping their
+    /// formats, for readers), and repack it.
+    Book(Book),
+    /// Write the variants of an image at several widths (responsive
+    /// images), from a single decode, with a manifest of their `srcset`s.
+    Srcset(Srcset),
+    /// List the outputs of past batches (by their `--log-file` reports)
+    /// worth re-processing, e.g. of an older imager or a buggy encoder.
+    Replan(Replan),
+    /// Convert an animated GIF (or APNG) to animated WebP, APNG, MP4 or WebM
+    /// (by the output’s extension), which are typically far smaller; video
+    /// outputs require an `ffmpeg` executable.
+    Gif(ConvertGif),
+    /// Average the frames of a short clip (a directory of frames) into a
+    /// long exposure style still, and optimize it.
+    LongExposure(LongExposure),
+    /// Pick the best of a burst of near identical stills (by sharpness and
+    /// exposure), and optimize only it.
+    Burst(Burst),
+    /// Stitch overlapping images (in order) into a panorama, and optimize
+    /// it; requires the `panorama` feature.
+    Panorama(Panorama),
+    /// Trace a flat colored image (e.g. a logo) into an SVG; requires the
+    /// `vectorize` feature.
+    Vectorize(Vectorize),
+    /// Preview an image in the terminal (kitty graphics, sixels, or ANSI
+    /// colors), e.g. to check outputs over SSH.
+    Show(Show),
+    /// Serve the HTTP API (synchronous `/opt`, and async `/jobs`).
+    Serve(Serve),
+    /// Run a job confined (see `imager serve --sandbox`); internal.
+    #[structopt(setting = AppSettings::Hidden)]
+    SandboxWorker(SandboxWorker),
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct Verify {
+    /// Image file(s) path, or glob patterns (e.g. `'archive/**/*.jpeg'`).
+    #[structopt(required = true, min_values = 1)]
+    inputs: Vec<String>,
+
+    /// Print the results as JSON.
+    #[structopt(long)]
+    json: bool,
+
+    /// Only print corrupt files.
+    #[structopt(short, long)]
+    quiet: bool,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct VerifyMark {
+    /// Image file(s) path.
+    #[structopt(required = true, min_values = 1, parse(from_os_str))]
+    inputs: Vec<PathBuf>,
+
+    /// Fail unless every image is marked with this ID.
+    #[structopt(long)]
+    expect: Option<u32>,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct Montage {
+    /// Image file(s) path, in order.
+    #[structopt(required = true, min_values = 1, parse(from_os_str))]
+    inputs: Vec<PathBuf>,
+
+    /// The contact sheet file path; the format follows the extension.
+    #[structopt(short, long, parse(from_os_str))]
+    output: PathBuf,
+
+    /// The largest size of each image (e.g. `256x256`).
+    #[structopt(long, default_value = "256x256")]
+    cell: Resolution,
+
+    /// Images per row (default: a roughly square grid).
+    #[structopt(long)]
+    columns: Option<u32>,
+
+    /// Don’t label images with their file names.
+    #[structopt(long)]
+    no_labels: bool,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct Stitch {
+    /// The first (e.g. original) image file path.
+    #[structopt(parse(from_os_str))]
+    a: PathBuf,
+
+    /// The second (e.g. optimized) image file path; resized to the first’s
+    /// dimensions if they differ.
+    #[structopt(parse(from_os_str))]
+    b: PathBuf,
+
+    /// The comparison file path; the format follows the extension.
+    #[structopt(short, long, parse(from_os_str))]
+    output: PathBuf,
+
+    /// `side-by-side` or `top-bottom`.
+    #[structopt(long, default_value = "side-by-side")]
+    layout: crate::montage::Layout,
+
+    /// The labels of the images, comma separated (default: their file
+    /// names and sizes).
+    #[structopt(long, use_delimiter = true, number_of_values = 1)]
+    labels: Vec<String>,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct Diff {
+    /// The first image file path.
+    #[structopt(parse(from_os_str))]
+    a: PathBuf,
+
+    /// The second image file path, of the same dimensions.
+    #[structopt(parse(from_os_str))]
+    b: PathBuf,
+
+    /// The heatmap file path; the format follows the extension.
+    #[structopt(parse(from_os_str))]
+    output: PathBuf,
+
+    /// Multiply differences by this, so subtle ones become visible.
+    #[structopt(long, default_value = "1")]
+    amplify: f32,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct Planes {
+    /// Image file(s) path.
+    #[structopt(required = true, min_values = 1, parse(from_os_str))]
+    inputs: Vec<PathBuf>,
+
+    /// Output directory; planes are named like `<input>.y.png`.
+    #[structopt(short = "O", long, parse(from_os_str))]
+    out_dir: PathBuf,
+
+    /// `yuv`, `rgba` or `all`.
+    #[structopt(long, default_value = "all")]
+    planes: crate::planes::Planes,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct RdCurve {
+    /// Image file path.
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// The curve (CSV: format, quality or palette size, size, score) file
+    /// path.
+    #[structopt(short, long, parse(from_os_str))]
+    output: PathBuf,
+
+    /// Also plot the curve to this SVG file path.
+    #[structopt(long, parse(from_os_str))]
+    svg: Option<PathBuf>,
+
+    /// Output format(s).
+    #[structopt(short, long, default_value = "jpeg webp")]
+    formats: Vec<OutputFormats>,
+
+    /// `vmaf` (requires the `ffi` feature), `psnr`, `dssim` or
+    /// `butteraugli`.
+    #[structopt(long, default_value = "vmaf")]
+    metric: crate::rd::Metric,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct Bench {
+    /// Directory of images.
+    #[structopt(parse(from_os_str))]
+    dir: PathBuf,
+
+    /// Codecs, comma separated; the first is the anchor.
+    #[structopt(long, use_delimiter = true, default_value = "jpeg,webp")]
+    codecs: Vec<OutputFormat>,
+
+    /// The score to compare sizes at, e.g. `85vq` (VMAF; requires the
+    /// `ffi` feature) or `40db` (PSNR).
+    #[structopt(long, default_value = "85vq")]
+    target: crate::bench::Target,
+
+    /// Print the results (per image, and the summary) as JSON.
+    #[structopt(long)]
+    json: bool,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct Tune {
+    /// Directory of (representative) sample images.
+    #[structopt(parse(from_os_str))]
+    dir: PathBuf,
+
+    /// The profile file path; TOML if its extension is `.toml`, else JSON.
+    #[structopt(short, long, parse(from_os_str), default_value = "imager.toml")]
+    output: PathBuf,
+
+    /// Tune this profile, instead of the defaults.
+    #[structopt(long, parse(from_os_str))]
+    base: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct Pack {
+    /// Image file path.
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// The `.icns`, `.ico` or `.heic` (`.heif`) file path.
+    #[structopt(short, long, parse(from_os_str))]
+    output: PathBuf,
+
+    /// `icns`, `ico` or `heif`, if not the output’s extension.
+    #[structopt(long)]
+    container: Option<crate::multires::Container>,
+
+    /// Icon sizes (or, for HEIF, thumbnail sizes) in pixels, comma
+    /// separated; the platform’s usual sizes if not given.
+    #[structopt(long, use_delimiter = true, number_of_values = 1)]
+    sizes: Vec<u32>,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct Book {
+    /// The `.epub` or `.cbz` file path.
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// The output file path.
+    #[structopt(short, long, parse(from_os_str), required_unless = "replace")]
+    output: Option<PathBuf>,
+
+    /// Overwrite the input.
+    #[structopt(long, conflicts_with = "output")]
+    replace: bool,
+
+    /// Spend more time searching for smaller images.
+    #[structopt(long)]
+    extreme: bool,
+
+    /// Print the report (with every image) as JSON.
+    #[structopt(long)]
+    json: bool,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct Srcset {
+    /// Image file path.
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// Where the variants (e.g. `photo-480w.webp`) are written.
+    #[structopt(short, long, parse(from_os_str))]
+    output_dir: PathBuf,
+
+    /// The max widths, comma separated, e.g. `480w,960w,1920w`.
+    #[structopt(long, required = true, use_delimiter = true, number_of_values = 1)]
+    widths: Vec<crate::srcset::Width>,
+
+    /// Output format(s), space separated.
+    #[structopt(long, default_value = "webp jpeg")]
+    formats: OutputFormats,
+
+    /// The manifest’s file path; `<input name>.srcset.json` in the output
+    /// directory if not given.
+    #[structopt(long, parse(from_os_str))]
+    manifest: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct Replan {
+    /// The directory of JSON reports (`--log-file`s), read in file name
+    /// order, so later entries of an output win.
+    #[structopt(long, parse(from_os_str))]
+    reports: PathBuf,
+
+    /// Outputs of imager releases older than this, e.g. `0.4`.
+    #[structopt(long)]
+    since_version: Option<crate::replan::Version>,
+
+    /// Outputs of a codec (or encoder) release, e.g. `avif<0.5`,
+    /// `libwebp<=1.2.4` or `mozjpeg=4.0.3`; repeatable.
+    #[structopt(long, number_of_values = 1)]
+    codec: Vec<crate::replan::CodecFilter>,
+
+    /// Outputs of a lower VMAF score than this.
+    #[structopt(long)]
+    min_vmaf: Option<f64>,
+
+    /// The job manifest’s file path; stdout if not given.
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct LongExposure {
+    /// The directory of frames (images, sorted by file name).
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// The output file path; the format follows the extension.
+    #[structopt(short, long, parse(from_os_str))]
+    output: PathBuf,
+
+    /// Align each frame to the first (e.g. of handheld clips) before
+    /// averaging.
+    #[structopt(long)]
+    align: bool,
+
+    /// Of `--align`: the largest shift to look for, in pixels.
+    #[structopt(long, default_value = "32")]
+    max_shift: u32,
+
+    /// How frames of other sizes than the canvas are fitted to it: off
+    /// (fail), pad, crop, or stretch.
+    #[structopt(long, default_value = "off")]
+    normalize: crate::data::Normalization,
+
+    /// Of `--normalize`: the canvas (e.g. 1920x1080), by default the first
+    /// frame’s size.
+    #[structopt(long)]
+    canvas: Option<Resolution>,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct Burst {
+    /// The stills’ file paths.
+    #[structopt(parse(from_os_str), required = true, min_values = 1)]
+    inputs: Vec<PathBuf>,
+
+    /// The output file path; the format follows the extension.
+    #[structopt(short, long, parse(from_os_str))]
+    output: PathBuf,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct Panorama {
+    /// The images’ file paths, left to right (or top to bottom).
+    #[structopt(parse(from_os_str), required = true, min_values = 2)]
+    inputs: Vec<PathBuf>,
+
+    /// The output file path; the format follows the extension.
+    #[structopt(short, long, parse(from_os_str))]
+    output: PathBuf,
+
+    /// The images are top to bottom, rather than left to right.
+    #[structopt(long)]
+    vertical: bool,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct Vectorize {
+    /// The image file path.
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// The SVG file path; by default, the input’s with an `.svg` extension.
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// The most colors, excluding transparency.
+    #[structopt(long, default_value = "8")]
+    colors: usize,
+
+    /// How far (in pixels) outlines may be simplified.
+    #[structopt(long, default_value = "1.0")]
+    tolerance: f64,
+
+    /// Regions of fewer pixels are merged into their surroundings.
+    #[structopt(long, default_value = "4")]
+    min_area: usize,
+
+    /// Straight outlines only, without curves.
+    #[structopt(long)]
+    polygons: bool,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct ConvertGif {
+    /// The GIF (or APNG) file path.
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// The output file path: `.webp`, `.png` (APNG), `.mp4` or `.webm`.
+    #[structopt(short, long, parse(from_os_str))]
+    output: PathBuf,
+
+    /// Of lossy WebP outputs (0-100).
+    #[structopt(long, default_value = "75")]
+    quality: f32,
+
+    /// Lossless WebP outputs, e.g. of pixel art.
+    #[structopt(long)]
+    lossless: bool,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct Show {
+    /// The image file path.
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// `kitty`, `sixel`, `truecolor` or `256`; by default, detected from the
+    /// environment.
+    #[structopt(long)]
+    protocol: Option<crate::preview::Protocol>,
+
+    /// The most columns to fill; by default, the terminal’s width.
+    #[structopt(long)]
+    width: Option<u32>,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct RunPipeline {
+    /// The pipeline (JSON) file path; see `--print-schema` for the format.
+    #[structopt(parse(from_os_str), required_unless = "print-schema")]
+    pipeline: Option<PathBuf>,
+
+    /// The pipeline file is a list of sharp (or libvips) operations, e.g.
+    /// `[{"resize": {"width": 800}}, {"webp": {"quality": 80}}]`, to
+    /// translate; what imager only approximates is printed as warnings.
+    #[structopt(long)]
+    sharp: bool,
+
+    /// Print the (translated) pipeline and exit.
+    #[structopt(long)]
+    translate: bool,
+
+    /// Image file(s) path.
+    #[structopt(short, long, min_values = 1, parse(from_os_str))]
+    inputs: Vec<PathBuf>,
+
+    /// Output directory.
+    #[structopt(short = "O", long, parse(from_os_str), required_unless_one = &["print-schema", "translate"])]
+    output_dir: Option<PathBuf>,
+
+    /// Print the pipeline JSON Schema and exit.
+    #[structopt(long)]
+    print_schema: bool,
+
+    /// As the main command’s, with the pipeline as the parameters.
+    #[structopt(long)]
+    provenance: Option<ProvenanceMode>,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct RunBatch {
+    /// The input directory; files are images by their content, whatever
+    /// their extension.
+    #[structopt(parse(from_os_str))]
+    input_dir: PathBuf,
+
+    /// Output directory.
+    #[structopt(short = "O", long, parse(from_os_str))]
+    output_dir: PathBuf,
+
+    /// Output format(s), space separated.
+    #[structopt(short, long, default_value = "webp")]
+    formats: OutputFormats,
+
+    /// Resize or downscale images if their resolution exceeds the given size.
+    #[structopt(long)]
+    max_size: Option<Resolution>,
+
+    /// The number of threads (default: the number of CPUs).
+    #[structopt(long)]
+    threads: Option<usize>,
+
+    /// Spend more time searching for smaller outputs.
+    #[structopt(long)]
+    extreme: bool,
+
+    /// Write a JSON report of every output, and of every file that
+    /// failed, to the given path.
+    #[structopt(long, parse(from_os_str))]
+    log_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct Serve {
+    /// The address to listen on.
+    #[structopt(short, long, default_value = "127.0.0.1:3000")]
+    address: String,
+
+    /// The number of async job workers (default: the number of CPUs).
+    #[structopt(long)]
+    workers: Option<usize>,
+
+    /// How long (in seconds) finished async jobs, and their outputs, are
+    /// kept.
+    #[structopt(long, default_value = "3600")]
+    job_retention: u64,
+
+    /// The largest accepted request body, in bytes.
+    #[structopt(long, default_value = "67108864")]
+    max_body_size: usize,
+
+    /// The most connections handled at once; more get `503`s.
+    #[structopt(long, default_value = "256")]
+    max_connections: usize,
+
+    /// A host job callbacks may be POSTed to (repeatable); without any,
+    /// callbacks may only go to public addresses.
+    #[structopt(long)]
+    callback_host: Vec<String>,
+
+    /// Require API keys, from this JSON file, with per-key rate limits,
+    /// max input sizes and concurrency caps: `{"keys": [{"name", "key",
+    /// "rate_limit" (per minute), "max_input_size", "max_concurrency"}]}`.
+    #[structopt(long, parse(from_os_str))]
+    keys: Option<PathBuf>,
+
+    /// On SIGTERM, how long (in seconds) to wait for in-flight encodes
+    /// before exiting.
+    #[structopt(long, default_value = "30")]
+    shutdown_timeout: u64,
+
+    /// The optimization profile (JSON, or TOML if `.toml`) of requests,
+    /// i.e. their default format and max size, and every other option.
+    /// Reloaded on SIGHUP, and when the file changes.
+    #[structopt(long, parse(from_os_str))]
+    profile: Option<PathBuf>,
+
+    /// Decode and encode in a subprocess confined with seccomp and
+    /// rlimits (Linux only), so malicious sources can’t compromise the
+    /// server.
+    #[structopt(long)]
+    sandbox: bool,
+
+    /// The address space limit of sandboxed jobs, in MiB.
+    #[structopt(long, default_value = "4096")]
+    sandbox_memory: u64,
+
+    /// The CPU time limit of sandboxed jobs, in seconds.
+    #[structopt(long, default_value = "120")]
+    sandbox_cpu_seconds: u64,
+
+    /// The time limit of sandboxed jobs, in seconds, e.g. of ones blocked
+    /// (using no CPU time).
+    #[structopt(long, default_value = "300")]
+    sandbox_wall_seconds: u64,
+
+    /// Also serve `imgproxy` or `thumbor` URLs (signed with `IMGPROXY_KEY`
+    /// and `IMGPROXY_SALT`, or `THUMBOR_SECURITY_KEY`, if set), of sources
+    /// at the `--origin`.
+    #[structopt(long, requires = "origin")]
+    compat: Option<crate::server::compat::Syntax>,
+
+    /// The URL prefix of `--compat` sources, e.g.
+    /// `https://images.example.com/`.
+    #[structopt(long)]
+    origin: Option<String>,
+
+    /// The memory (in MiB) to cache `--origin` sources in, so repeated
+    /// transforms don’t fetch them again; `0` to disable. Freshness is the
+    /// origin’s (`Cache-Control`, `Expires`).
+    #[structopt(long, default_value = "256")]
+    origin_cache_size: usize,
+
+    /// How long (in seconds) past their freshness cached sources are still
+    /// served: while revalidated in the background, or while the origin
+    /// fails; unless the origin says otherwise (`stale-while-revalidate`,
+    /// `stale-if-error`, `must-revalidate`).
+    #[structopt(long, default_value = "86400")]
+    origin_max_stale: u64,
+
+    /// How long (in seconds) CDNs and browsers may cache `--compat`
+    /// outputs, whose URLs name the transform, so are `immutable`.
+    #[structopt(long, default_value = "31536000")]
+    cache_max_age: u64,
+
+    /// Size outputs by the `Sec-CH-DPR` and `Sec-CH-Width` client hints,
+    /// and cap their quality on `Save-Data`; responses ask browsers to send
+    /// them (`Accept-CH`).
+    #[structopt(long)]
+    client_hints: bool,
+
+    /// The widths `Sec-CH-Width` hints round up to, e.g. `320,640,1280`;
+    /// fewer make for fewer cached variants.
+    #[structopt(long, use_delimiter = true, default_value = "320,640,960,1280,1920,2560,3840")]
+    width_buckets: Vec<u32>,
+
+    /// The highest `Sec-CH-DPR` honored.
+    #[structopt(long, default_value = "3")]
+    max_dpr: f64,
+
+    /// The max quality of `Save-Data` requests.
+    #[structopt(long, default_value = "60")]
+    save_data_quality: u8,
+
+    /// Named transforms, from this JSON file, that requests use by name
+    /// (`?template=thumb`): `{"templates": {"thumb": {"format", "size",
+    /// "preset"}}, "only"}`; with `only`, every other transform is
+    /// rejected.
+    #[structopt(long, parse(from_os_str))]
+    templates: Option<PathBuf>,
+
+    /// Encode with the encoder plugin (a shared library implementing
+    /// `include/imager_plugin.h`) in place of the built-in encoder of its
+    /// format; requires the `plugins` feature.
+    #[structopt(long, parse(from_os_str))]
+    plugin: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct SandboxWorker {
+    #[structopt(long)]
+    memory: u64,
+
+    #[structopt(long)]
+    cpu_seconds: u64,
+
+    #[structopt(long, parse(from_os_str))]
+    plugin: Vec<PathBuf>,
 }
 
 impl Command {
+    /// Contradictory options, each with a fix; checked before any input
+    /// is read.
+    fn problems(&self, inputs: &[PathBuf]) -> Vec<String> {
+        let mut problems = Vec::new();
+        let output_types = [self.output_file.is_some(), self.output_dir.is_some(), self.replace];
+        if !self.explain && output_types.iter().filter(|x| **x).count() > 1 {
+            problems.push(String::from(
+                "`--output-file`, `--output-dir` and `--replace` are exclusive; choose one",
+            ));
+        }
+        if inputs.len() > 1 && self.output_file.is_some() {
+            problems.push(format!(
+                "`--output-file` takes a single input, but {} were given; use `--output-dir`",
+                inputs.len()
+            ));
+        }
+        if self.report_format != ReportFormat::Json && self.log_file.is_none() {
+            problems.push(String::from("`--report` requires `--log-file`; set one"));
+        }
+        if self.no_palette_dither && self.palette.is_none() {
+            problems.push(String::from(
+                "`--no-palette-dither` has no effect without `--palette`; set a palette, or drop it",
+            ));
+        }
+        let archive_inputs = inputs.iter().filter(|x| ArchiveFormat::from_path(x).is_some()).count();
+        match &self.output_file {
+            Some(path) if archive_inputs > 0 && ArchiveFormat::from_path(path).is_none() => problems.push(format!(
+                "`--output-file` {} of an archive input isn’t a `.zip`, `.tar` or `.tar.gz`; rename it",
+                path.display()
+            )),
+            _ => (),
+        }
+        let outputs = self.output_file.iter().chain(&self.output_dir);
+        for output in outputs.filter_map(|x| x.to_str()).filter(|x| crate::sftp::is_url(x)) {
+            if let Err(message) = output.parse::<crate::sftp::SftpUrl>() {
+                problems.push(message);
+            }
+        }
+        match self.metric_coverage {
+            Some(x) if !(x > 0.0 && x <= 1.0) => problems.push(format!(
+                "invalid `--metric-coverage` {}; it’s a share of the tiles, above 0 and at most 1",
+                x
+            )),
+            _ => (),
+        }
+        let formats = self.formats.iter().flat_map(|x| x.0.clone()).collect::<Vec<_>>();
+        let fallbacks = self.fallback.iter().flat_map(|x| x.0.clone());
+        for format in fallbacks.filter(|x| formats.contains(x)) {
+            problems.push(format!(
+                "`--fallback` {:?} is also in `--formats`, so its output would be written twice; drop one",
+                format
+            ));
+        }
+        let (max_size, tuning) = self.settings();
+        let options = imager_core::validate::Options {
+            formats: &formats,
+            max_size: max_size.as_ref(),
+            allow_upscale: self.allow_upscale,
+            upscaler: self.upscaler,
+            remove_background: self.remove_background.is_some(),
+            palette: self.palette.is_some(),
+            color_mode: self.color_mode,
+            exif_thumbnail: self.exif_thumbnail,
+            text_protect: self.text_protect,
+            tuning: &tuning,
+        };
+        problems.extend(options.problems());
+        problems
+    }
+    /// The `--max-size`, and the tuning of the `--max-distance`, with the
+    /// `--preset` applied.
+    fn settings(&self) -> (Option<Resolution>, crate::data::Tuning) {
+        let tuning = crate::data::Tuning {
+            max_distance: self.max_distance,
+            vmaf: self.vmaf_target.unwrap_or_default(),
+            ..crate::data::Tuning::default()
+        };
+        match self.preset {
+            Some(preset) => (preset.max_size(self.max_size.clone()), preset.tuning(tuning)),
+            None => (self.max_size.clone(), tuning),
+        }
+    }
+    /// Where the archive of the outputs of an archive input goes.
+    fn archive_output(&self, input_path: &Path) -> PathBuf {
+        match (&self.output_file, &self.output_dir) {
+            (Some(path), _) => path.clone(),
+            (None, Some(dir)) => dir.join(input_path.file_name().expect("file name")),
+            (None, None) => input_path.to_owned(),
+        }
+    }
+    /// Whether any input or output is on a remote server.
+    fn uses_sftp(&self) -> bool {
+        let outputs = self.output_file.iter().chain(&self.output_dir);
+        self.inputs.iter().any(|x| crate::sftp::is_url(x))
+            || outputs.filter_map(|x| x.to_str()).any(crate::sftp::is_url)
+    }
     pub fn run(&self) {
+        let pool = self
+            .uses_sftp()
+            .then(|| crate::sftp::Pool::new().expect("failed to create the SFTP pool"));
+        let mut problems = Vec::new();
         let inputs = self
             .inputs
             .clone()
             .into_iter()
-            .filter_map(|x| glob::glob(&x).ok())
-            .map(|x| x.collect::<Vec<_>>())
-            .flatten()
-            .filter_map(Result::ok)
+            .flat_map(|x| match pool.as_ref().filter(|_| crate::sftp::is_url(&x)) {
+                // REMOTE PATHS STAND FOR THEIR URLS
+                Some(pool) => match x.parse().and_then(|url| pool.glob(&url).map_err(String::from)) {
+                    Ok(urls) => urls.iter().map(|x| PathBuf::from(x.to_string())).collect(),
+                    Err(message) => {
+                        problems.push(message);
+                        Vec::new()
+                    }
+                },
+                None => glob::glob(&x)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Result::ok)
+                    .collect::<Vec<_>>(),
+            })
             .collect::<Vec<_>>();
-        if inputs.len() > 1 && self.output_file.is_some() {
-            panic!(
-                "Output file isn’t valid for multiple input file paths, maybe use `--output-dir`?"
-            );
+        problems.extend(self.problems(&inputs));
+        if !problems.is_empty() {
+            for problem in problems {
+                eprintln!("[error] {}", problem);
+            }
+            std::process::exit(1);
         }
-        let output = match (
-            self.output_file.clone(),
-            self.output_dir.clone(),
-            self.replace,
-        ) {
-            (Some(x), None, false) => OutputType::File(x),
-            (None, Some(x), false) => OutputType::Dir(x),
-            (None, None, true) => OutputType::Replace,
-            _ => panic!("invalid output type"),
-        };
-        if output.is_replace() {
-            eprintln!("[warning] replacing input files");
-            eprintln!(
-                "[note] imager only works for original images, i.e. your highest quality versions"
-            )
+        load_plugins(&self.plugin);
+        if self.allow_upscale {
+            crate::upscale::check_available(self.upscaler).expect("invalid `--upscaler`");
         }
+        ///////////////////////////////////////////////////////////////////////
+        // ARCHIVES: THEIR IMAGES ARE EXTRACTED, AND PROCESSED AS FILES
+        ///////////////////////////////////////////////////////////////////////
+        let mut archives = Vec::<ArchiveInput>::new();
+        let mut archive_members = HashMap::<PathBuf, (usize, String)>::new();
+        let mut extracted = Vec::new();
+        for input_path in inputs {
+            let format = match ArchiveFormat::from_path(&input_path) {
+                Some(format) => format,
+                None => {
+                    extracted.push(input_path);
+                    continue;
+                }
+            };
+            let archive = ArchiveInput::extract(&input_path, format, self.archive_output(&input_path), pool.as_ref())
+                .unwrap_or_else(|message| {
+                    eprintln!("[error] {}: {}", input_path.display(), message);
+                    std::process::exit(1);
+                });
+            for member in archive.members.iter().filter(|x| ::image::guess_format(&x.data).is_ok()) {
+                let staged = archive.input_dir.join(&member.path);
+                archive_members.insert(staged.clone(), (archives.len(), member.path.clone()));
+                extracted.push(staged);
+            }
+            archives.push(archive);
+        }
+        let inputs = extracted;
+        // STAGED PATHS, AS THEIR ARCHIVE’S
+        let shown = |path: &Path| -> PathBuf {
+            for archive in &archives {
+                if let Ok(relative) = path.strip_prefix(archive.input_dir.path()) {
+                    return archive.path.join(relative);
+                }
+                if let Ok(relative) = path.strip_prefix(archive.output_dir.path()) {
+                    return archive.output.join(relative);
+                }
+            }
+            path.to_owned()
+        };
+        let archive_outputs = Mutex::new(Vec::<(usize, String, PathBuf)>::new());
         let entries = inputs
             .clone()
             .into_iter()
@@ -165,14 +1262,194 @@ impl Command {
         if entries.is_empty() {
             eprintln!("[warning] no (or missing) input files given");
         }
-        let entries_len = entries.len();
-        let process = |input_path: PathBuf, output_format: OutputFormat| -> api::OutMeda {
-            let mut opt_job = crate::api::OptJob::open(&input_path).expect("open input file path");
-            opt_job.output_format(output_format.clone());
-            if let Some(max_size) = self.max_size.clone() {
-                opt_job.max_size(max_size);
+        let entries_len = entries.len();
+        let background = self.remove_background.as_ref().map(|path| {
+            let remover = crate::background::BackgroundRemover::open(path)
+                .expect("invalid `--remove-background` model");
+            Arc::new(remover)
+        });
+        let attribution = Attribution {
+            creator: self.creator.clone(),
+            copyright: self.copyright.clone(),
+            license_url: self.license_url.clone(),
+        };
+        let manifest_entries = Mutex::new(Vec::new());
+        let c2pa_signer = self.c2pa_signer.as_ref().map(|path| {
+            crate::meta::c2pa::C2paSigner::open(path).expect("invalid `--c2pa-signer` config")
+        });
+        let fallbacks = self.fallback.iter().flat_map(|x| x.0.clone()).collect::<Vec<_>>();
+        let prepare = |input_path: &PathBuf,
+                       output_format: &OutputFormat|
+         -> Result<(crate::input::InputBuffer, ::image::ImageFormat, api::OptJob), FileError> {
+            let fail = |kind: FileErrorKind, message: String| FileError {
+                input_path: input_path.clone(),
+                output_format: output_format.clone(),
+                kind,
+                message,
+            };
+            // A DISABLED FORMAT FALLS BACK TOO
+            let encoder = match crate::codec::registry::encoder(output_format) {
+                Err(_) if fallbacks.iter().any(|x| crate::codec::registry::encoder(x).is_ok()) => Ok(()),
+                result => result.map(drop),
+            };
+            encoder
+                .and_then(|_| crate::codec::registry::decoders(&self.decoders))
+                .map_err(|e| fail(FileErrorKind::Unsupported, e.to_string()))?;
+            let source = match crate::sftp::SftpUrl::from_path(input_path) {
+                Some(url) => pool
+                    .as_ref()
+                    .expect("sftp pool")
+                    .read(&url)
+                    .map(crate::input::InputBuffer::Heap)
+                    .map_err(String::from),
+                None => crate::input::InputBuffer::open(input_path, self.read_mode).map_err(|e| e.to_string()),
+            }
+            .map_err(|e| fail(FileErrorKind::Io, e))?;
+            let source_format = ::image::guess_format(&source).map_err(|_| {
+                let message = String::from("unrecognized image format");
+                fail(FileErrorKind::Unsupported, message)
+            })?;
+            let (max_size, tuning) = self.settings();
+            let decode_options = DecodeOptions {
+                chain: self.decoders.clone(),
+                tolerate_truncated: self.tolerate_truncated,
+                max_size,
+                fit: self.fit,
+                auto_orient: self.auto_orient,
+            };
+            let mut opt_job = crate::api::OptJob::new_with_options(&source, &decode_options)
+                .map_err(|error| fail(FileErrorKind::Decode, error.to_string()))?;
+            opt_job.output_format(output_format.clone());
+            opt_job.resize_filter(self.resize_filter);
+            opt_job.crop(self.crop);
+            opt_job.privacy_policy(self.exif.clone());
+            opt_job.exif_thumbnail(self.exif_thumbnail);
+            opt_job.attribution(attribution.clone());
+            opt_job.seed(self.seed);
+            opt_job.color_mode(self.color_mode, self.threshold);
+            opt_job.text_protect(self.text_protect);
+            opt_job.fast_search(self.fast_search);
+            if let Some(coverage) = self.metric_coverage {
+                opt_job.metric_coverage(coverage);
+            }
+            if let Some(matte) = self.matte {
+                opt_job.matte(matte);
+            }
+            opt_job.tuning(tuning);
+            if let Some(palette) = &self.palette {
+                opt_job.brand_palette(palette.clone(), !self.no_palette_dither);
+            }
+            if let Some(remover) = &background {
+                opt_job.remove_background(remover.clone());
+            }
+            if self.allow_upscale {
+                opt_job.allow_upscale(self.upscaler);
+            }
+            if let Some(id) = self.watermark {
+                opt_job.watermark(id);
+            }
+            let (width, height) = opt_job.output_dimensions();
+            let fits = |format: &OutputFormat| width.max(height) <= format.max_dimension();
+            if !fits(output_format) && !fallbacks.iter().any(fits) {
+                let max_dimension = output_format.max_dimension();
+                let message = format!(
+                    "{}x{} exceeds the {:?} limit of {}",
+                    width, height, output_format, max_dimension
+                );
+                return Err(fail(FileErrorKind::TooLarge, message));
+            }
+            Ok((source, source_format, opt_job))
+        };
+        if self.explain {
+            for (input_path, output_format) in entries {
+                match prepare(&input_path, &output_format) {
+                    Ok((_, _, opt_job)) => println!(
+                        "{} ({:?}):\n{}",
+                        input_path.display(),
+                        output_format,
+                        opt_job.plan(self.extreme)
+                    ),
+                    Err(error) => eprintln!("[error] {}", error),
+                }
+            }
+            return;
+        }
+        let output = match (
+            self.output_file.clone(),
+            self.output_dir.clone(),
+            self.replace,
+        ) {
+            (Some(x), None, false) => OutputType::File(x),
+            (None, Some(x), false) => OutputType::Dir(x),
+            (None, None, true) => OutputType::Replace,
+            _ => panic!("invalid output type"),
+        };
+        if output.is_replace() {
+            eprintln!("[warning] replacing input files");
+            eprintln!(
+                "[note] imager only works for original images, i.e. your highest quality versions"
+            )
+        }
+        let process = |input_path: PathBuf,
+                       output_format: OutputFormat|
+         -> Result<api::OutMeda, FileError> {
+            let fail = |kind: FileErrorKind, message: String| FileError {
+                input_path: input_path.clone(),
+                output_format: output_format.clone(),
+                kind,
+                message,
+            };
+            let start = std::time::Instant::now();
+            let (source, source_format, opt_job) = prepare(&input_path, &output_format)?;
+            let dimensions = opt_job.output_dimensions();
+            let stages = self.provenance.map(|_| {
+                serde_json::to_value(opt_job.plan(self.extreme).stages).expect("to json failed")
+            });
+            // ENCODER PANICS ARE CAUGHT, AND FALL BACK
+            let (encoded, mut out_meta) = opt_job
+                .run_with_fallbacks(self.extreme, &fallbacks)
+                .map_err(|error| fail(FileErrorKind::Encode, error.to_string()))?;
+            let output_format = match &out_meta.fallback {
+                Some(fallback) => fallback.output.clone(),
+                None => output_format.clone(),
+            };
+            // PROVENANCE, BEFORE SIGNING
+            let provenance = stages.map(|stages| {
+                Provenance::new(&source, stages, output_format.clone(), out_meta.versions.clone())
+            });
+            let (encoded, sidecar) = match (self.provenance, provenance) {
+                (Some(ProvenanceMode::Xmp), Some(provenance)) => match provenance.embed(&encoded, dimensions) {
+                    Some(embedded) => (embedded, None),
+                    None => (encoded, Some(provenance)),
+                },
+                (_, provenance) => (encoded, provenance),
+            };
+            // CONTENT CREDENTIALS
+            let source_manifests = crate::meta::c2pa::extract_manifest_store(&source, source_format)
+                .map(|store| crate::meta::c2pa::manifest_labels(&store));
+            let encoded = match &c2pa_signer {
+                Some(signer) => crate::meta::c2pa::sign(
+                    &encoded,
+                    &output_format,
+                    &source,
+                    source_format,
+                    signer,
+                )
+                .map_err(|e| fail(FileErrorKind::Encode, format!("c2pa signing failed: {}", e)))?,
+                None => encoded,
+            };
+            if source_manifests.is_some() && c2pa_signer.is_none() {
+                progress_bar.println(format!(
+                    "[warning] {}: content credentials (C2PA) are lost without `--c2pa-signer`",
+                    input_path.display()
+                ));
+            }
+            if source_manifests.is_some() || c2pa_signer.is_some() {
+                out_meta.c2pa = Some(crate::meta::c2pa::C2paReport {
+                    source_manifests: source_manifests.unwrap_or_default(),
+                    signed: c2pa_signer.is_some(),
+                });
             }
-            let (encoded, mut out_meta) = opt_job.run(self.extreme).expect("opt job failed");
             out_meta.input_path = Some(input_path.clone());
             out_meta.output_path = None;
             let different_format = {
@@ -185,61 +1462,1052 @@ impl Command {
                 .expect("file name")
                 .to_str()
                 .expect("OsStr to str");
-            let output_ext = match output_format {
-                OutputFormat::Jpeg => "jpeg",
-                OutputFormat::Png => "png",
-                OutputFormat::Webp => "webp",
-            };
-            match output.clone() {
-                OutputType::Dir(path) => {
-                    if !path.exists() {
-                        std::fs::create_dir_all(&path).expect("create parent dir");
+            let output_ext = output_format.extension();
+            let member = archive_members.get(&input_path);
+            let output_path = match output.clone() {
+                // PRESERVING THE MEMBER’S PATH
+                _ if member.is_some() => {
+                    let (index, path) = member.expect("archive member");
+                    let mut output_path = archives[*index].output_dir.join(path);
+                    if different_format {
+                        output_path.set_extension(output_ext);
                     }
+                    output_path
+                }
+                OutputType::Dir(path) => {
                     let mut output_path = path.join(file_name);
                     if different_format {
                         output_path.set_extension(output_ext);
                     }
-                    out_meta.output_path = Some(output_path.clone());
-                    std::fs::write(output_path, encoded).expect("failed to write output file");
+                    output_path
                 }
                 OutputType::File(mut output_path) => {
-                    let parent_dir = output_path.parent().expect("get parent path");
-                    if !parent_dir.exists() {
-                        std::fs::create_dir_all(&parent_dir).expect("create parent dir");
-                    }
                     if different_format {
                         output_path.set_extension(output_ext);
                     }
-                    out_meta.output_path = Some(output_path.clone());
-                    std::fs::write(output_path, encoded).expect("failed to write output file");
+                    output_path
                 }
                 OutputType::Replace => {
                     let mut output_path = input_path.clone();
                     if different_format {
                         output_path.set_extension(output_ext);
                     }
-                    out_meta.output_path = Some(output_path.clone());
-                    std::fs::write(output_path, encoded).expect("failed to write output file");
+                    output_path
                 }
+            };
+            out_meta.input_size = Some(source.len() as u64);
+            out_meta.output_size = Some(encoded.len() as u64);
+            match crate::sftp::SftpUrl::from_path(&output_path) {
+                Some(url) => {
+                    let pool = pool.as_ref().expect("sftp pool");
+                    pool.write(&url, &encoded)
+                        .map_err(|e| fail(FileErrorKind::Io, e.to_string()))?;
+                    if let Some(provenance) = sidecar {
+                        let sidecar_url = url.with_path(format!("{}.provenance.json", url.path));
+                        pool.write(&sidecar_url, provenance.to_json().as_bytes())
+                            .map_err(|e| fail(FileErrorKind::Io, e.to_string()))?;
+                    }
+                }
+                None => {
+                    if let Some(parent_dir) = output_path.parent().filter(|x| !x.exists()) {
+                        std::fs::create_dir_all(parent_dir)
+                            .map_err(|e| fail(FileErrorKind::Io, e.to_string()))?;
+                    }
+                    std::fs::write(&output_path, &encoded)
+                        .map_err(|e| fail(FileErrorKind::Io, e.to_string()))?;
+                    if let Some(provenance) = sidecar {
+                        provenance
+                            .write_sidecar(&output_path)
+                            .map_err(|e| fail(FileErrorKind::Io, e))?;
+                    }
+                }
+            }
+            if let Some((index, path)) = member {
+                let entry = (*index, path.clone(), output_path.clone());
+                archive_outputs.lock().expect("archive outputs lock").push(entry);
+            }
+            if self.manifest.is_some() {
+                let entry = crate::manifest::Entry {
+                    output: crate::manifest::FileDigest::new(shown(&output_path), &encoded),
+                    format: output_format.clone(),
+                    source: crate::manifest::FileDigest::new(shown(&input_path), &source),
+                };
+                manifest_entries.lock().expect("manifest lock").push(entry);
             }
-            out_meta
+            out_meta.output_path = Some(output_path);
+            out_meta.duration_ms = Some(start.elapsed().as_millis() as u64);
+            Ok(out_meta)
         };
-        let output_log = entries
+        let results = entries
             .into_par_iter()
             .map(|(input_path, output_format)| {
-                let out_meta = process(input_path, output_format);
+                let mut result = process(input_path.clone(), output_format.clone());
+                match &mut result {
+                    Ok(meta) => {
+                        meta.input_path = meta.input_path.as_deref().map(shown);
+                        meta.output_path = meta.output_path.as_deref().map(shown);
+                    }
+                    Err(error) => error.input_path = shown(&error.input_path),
+                }
+                match &result {
+                    Ok(meta) => {
+                        for warning in &meta.warnings {
+                            progress_bar.println(format!(
+                                "[warning] {} ({:?}): {}",
+                                shown(&input_path).display(),
+                                output_format,
+                                warning
+                            ));
+                        }
+                    }
+                    Err(error) => progress_bar.println(format!("[error] {}", error)),
+                }
                 // DONE
                 progress_bar.inc(1);
-                out_meta
+                result
             })
-            .collect::<Vec<api::OutMeda>>();
+            .collect::<Vec<_>>();
+        let report = Report::from_results(results);
+        ///////////////////////////////////////////////////////////////////////
+        // OUTPUT ARCHIVES
+        ///////////////////////////////////////////////////////////////////////
+        let archive_outputs = archive_outputs.into_inner().expect("archive outputs lock");
+        let mut archived = true;
+        for (index, archive) in archives.iter().enumerate() {
+            let outputs = archive_outputs
+                .iter()
+                .filter(|x| x.0 == index)
+                .map(|x| (x.1.as_str(), &x.2))
+                .collect::<Vec<_>>();
+            if let Err(message) = archive.pack(&outputs, pool.as_ref()) {
+                eprintln!("[error] {}: {}", archive.output.display(), message);
+                archived = false;
+            }
+        }
         // SAVE LOG FILE
         if let Some(log_path) = self.log_file.clone() {
-            let output_log = serde_json::to_string_pretty(&output_log).expect("to json str failed");
-            std::fs::write(log_path, output_log);
+            let output_log = report.render(self.report_format, &log_path);
+            std::fs::write(log_path, output_log).expect("failed to write log file");
+        }
+        if let Some(manifest_path) = self.manifest.as_ref() {
+            let entries = manifest_entries.into_inner().expect("manifest lock");
+            let manifest = crate::manifest::Manifest::new(entries);
+            std::fs::write(manifest_path, manifest.to_json()).expect("failed to write manifest");
         }
         // DONE
         progress_bar.finish();
+        if !report.outputs.is_empty() {
+            eprint!("{}", report.summary());
+        }
+        let mut notified = true;
+        if let Some(url) = self.webhook.as_ref() {
+            let body = serde_json::to_vec(&report).expect("to json failed");
+            let mut webhook = crate::webhook::Webhook::from_env(url);
+            // E.G. SET BY CI SYSTEMS
+            let trace = std::env::var("TRACEPARENT").ok().and_then(|x| {
+                crate::trace::TraceContext::parse(&x, std::env::var("TRACESTATE").ok().as_deref())
+            });
+            if let Some(trace) = trace {
+                webhook = webhook.traced(&trace.child());
+            }
+            if let Err(message) = webhook.post(&body) {
+                eprintln!("[error] {}", message);
+                notified = false;
+            }
+        }
+        if !report.is_success() {
+            eprintln!(
+                "[error] {} of {} outputs failed",
+                report.errors.len(),
+                entries_len
+            );
+            std::process::exit(1);
+        }
+        if !notified || !archived {
+            std::process::exit(1);
+        }
+        if self.deny_warnings && report.warning_count() > 0 {
+            eprintln!("[error] {} quality warnings", report.warning_count());
+            std::process::exit(2);
+        }
+    }
+}
+
+/// An archive input: its members, with its images extracted (to be
+/// processed as files), and the directory of their outputs.
+struct ArchiveInput {
+    path: PathBuf,
+    format: ArchiveFormat,
+    /// Where the archive of the outputs goes.
+    output: PathBuf,
+    members: Vec<crate::archive::Member>,
+    input_dir: JobDir,
+    output_dir: JobDir,
+}
+
+impl ArchiveInput {
+    fn extract(
+        path: &Path,
+        format: ArchiveFormat,
+        output: PathBuf,
+        pool: Option<&crate::sftp::Pool>,
+    ) -> Result<Self, String> {
+        let source = match crate::sftp::SftpUrl::from_path(path) {
+            Some(url) => pool.expect("sftp pool").read(&url)?,
+            None => std::fs::read(path).map_err(|e| e.to_string())?,
+        };
+        let members = crate::archive::read(&source, format)?;
+        let input_dir = JobDir::new("archive-in")?;
+        for member in members.iter().filter(|x| ::image::guess_format(&x.data).is_ok()) {
+            let staged = input_dir.join(&member.path);
+            if let Some(parent) = staged.parent() {
+                std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            std::fs::write(&staged, &member.data).map_err(|e| e.to_string())?;
+        }
+        Ok(ArchiveInput {
+            path: path.to_owned(),
+            format,
+            output,
+            members,
+            input_dir,
+            output_dir: JobDir::new("archive-out")?,
+        })
+    }
+    /// Writes the archive of the `outputs` (by member path), with their
+    /// provenance sidecars, in the order of the members, which are kept
+    /// as they were if without one (e.g. not images, or failed).
+    fn pack(&self, outputs: &[(&str, &PathBuf)], pool: Option<&crate::sftp::Pool>) -> Result<(), String> {
+        let mut packed = Vec::new();
+        for member in &self.members {
+            let mut paths = outputs
+                .iter()
+                .filter(|x| x.0 == member.path)
+                .flat_map(|x| [x.1.clone(), crate::provenance::sidecar_path(x.1)])
+                .filter(|x| x.exists())
+                .collect::<Vec<_>>();
+            if paths.is_empty() {
+                packed.push(member.clone());
+                continue;
+            }
+            paths.sort();
+            paths.dedup();
+            for path in paths {
+                let relative = path.strip_prefix(self.output_dir.path()).expect("output in the archive directory");
+                packed.push(crate::archive::Member {
+                    path: relative.components().map(|x| x.as_os_str().to_string_lossy()).join("/"),
+                    data: std::fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?,
+                });
+            }
+        }
+        let format = ArchiveFormat::from_path(&self.output).unwrap_or(self.format);
+        let archive = crate::archive::write(&packed, format)?;
+        match crate::sftp::SftpUrl::from_path(&self.output) {
+            Some(url) => Ok(pool.expect("sftp pool").write(&url, &archive)?),
+            None => {
+                if let Some(parent) = self.output.parent().filter(|x| !x.as_os_str().is_empty() && !x.exists()) {
+                    std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+                }
+                std::fs::write(&self.output, archive).map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+impl Verify {
+    pub fn run(&self) {
+        let inputs = self
+            .inputs
+            .iter()
+            .filter_map(|x| glob::glob(x).ok())
+            .flatten()
+            .filter_map(Result::ok)
+            .filter(|x| x.is_file())
+            .collect::<Vec<_>>();
+        let results = inputs
+            .par_iter()
+            .map(|input_path| {
+                let verification = match std::fs::read(input_path) {
+                    Ok(source) => crate::verify::verify(&source),
+                    Err(error) => crate::verify::Verification {
+                        format: None,
+                        problems: vec![format!("failed to read: {}", error)],
+                    },
+                };
+                (input_path, verification)
+            })
+            .collect::<Vec<_>>();
+        let corrupt = results.iter().filter(|(_, x)| !x.is_valid()).count();
+        if self.json {
+            let results = results
+                .iter()
+                .filter(|(_, x)| !self.quiet || !x.is_valid())
+                .map(|(path, x)| {
+                    serde_json::json!({
+                        "path": path,
+                        "valid": x.is_valid(),
+                        "format": x.format,
+                        "problems": x.problems,
+                    })
+                })
+                .collect::<Vec<_>>();
+            println!("{}", serde_json::to_string_pretty(&results).expect("to json failed"));
+        } else {
+            for (path, verification) in &results {
+                if verification.is_valid() && !self.quiet {
+                    println!("{}: ok", path.display());
+                } else if !verification.is_valid() {
+                    println!("{}: corrupt ({})", path.display(), verification.problems.join("; "));
+                }
+            }
+            eprintln!("[note] {} of {} files corrupt", corrupt, results.len());
+        }
+        if corrupt > 0 {
+            std::process::exit(1);
+        }
+    }
+}
+
+impl VerifyMark {
+    pub fn run(&self) {
+        let mut failed = false;
+        for input_path in &self.inputs {
+            let image = std::fs::read(input_path).ok().and_then(|source| {
+                let format = ::image::guess_format(&source).ok()?;
+                crate::decode::decode(&source, format, &DecodeOptions::default()).ok()
+            });
+            let detection = match image {
+                Some((image, _)) => crate::watermark::detect(&image),
+                None => {
+                    eprintln!("[error] {}: failed to decode", input_path.display());
+                    failed = true;
+                    continue;
+                }
+            };
+            match detection {
+                Some(mark) => println!(
+                    "{}: {} (confidence {:.2})",
+                    input_path.display(),
+                    mark.id,
+                    mark.confidence
+                ),
+                None => println!("{}: no watermark", input_path.display()),
+            }
+            if self.expect.is_some() && detection.map(|x| x.id) != self.expect {
+                failed = true;
+            }
+        }
+        if failed {
+            std::process::exit(1);
+        }
+    }
+}
+
+impl Montage {
+    pub fn run(&self) {
+        let images: Vec<_> = self
+            .inputs
+            .iter()
+            .filter_map(|input_path| {
+                let image = std::fs::read(input_path).ok().and_then(|source| {
+                    let format = ::image::guess_format(&source).ok()?;
+                    crate::decode::decode(&source, format, &DecodeOptions::default()).ok()
+                });
+                match image {
+                    Some((image, _)) => {
+                        let label = input_path.file_name().unwrap_or(input_path.as_os_str());
+                        Some((label.to_string_lossy().into_owned(), image))
+                    }
+                    None => {
+                        eprintln!("[warning] {}: failed to decode; skipped", input_path.display());
+                        None
+                    }
+                }
+            })
+            .collect();
+        let montage = crate::montage::Montage {
+            cell: self.cell.clone(),
+            columns: self.columns,
+            labels: !self.no_labels,
+        };
+        let sheet = montage.render(&images).expect("failed to render contact sheet");
+        sheet.save(&self.output).expect("failed to write contact sheet");
+    }
+}
+
+/// Decodes the image, and returns it with its size (in bytes); panics on
+/// failure.
+fn open_image(input_path: &PathBuf) -> (::image::DynamicImage, usize) {
+    let source = std::fs::read(input_path).expect("failed to read input");
+    let format = ::image::guess_format(&source).expect("unknown image format");
+    let (image, _) = crate::decode::decode(&source, format, &DecodeOptions::default())
+        .unwrap_or_else(|_| panic!("{}: failed to decode", input_path.display()));
+    (image, source.len())
+}
+
+impl Stitch {
+    pub fn run(&self) {
+        let open = |input_path: &PathBuf| {
+            let (image, size) = open_image(input_path);
+            let name = input_path.file_name().unwrap_or(input_path.as_os_str());
+            let size = crate::gallery::file_size(Some(size as u64));
+            (format!("{} ({})", name.to_string_lossy(), size), image)
+        };
+        let (mut label_a, a) = open(&self.a);
+        let (mut label_b, b) = open(&self.b);
+        match self.labels.as_slice() {
+            [] => (),
+            [x, y] => {
+                label_a = x.clone();
+                label_b = y.clone();
+            }
+            _ => panic!("expected two `--labels`"),
+        }
+        let sheet = crate::montage::stitch((&label_a, &a), (&label_b, &b), self.layout);
+        sheet.save(&self.output).expect("failed to write comparison");
+    }
+}
+
+impl Diff {
+    pub fn run(&self) {
+        let (a, _) = open_image(&self.a);
+        let (b, _) = open_image(&self.b);
+        let diff = crate::diff::diff(&a, &b, self.amplify).expect("failed to diff");
+        diff.heatmap.save(&self.output).expect("failed to write heatmap");
+        println!("max ΔE {:.2}, mean ΔE {:.3}", diff.max_delta_e, diff.mean_delta_e);
+    }
+}
+
+impl Planes {
+    pub fn run(&self) {
+        std::fs::create_dir_all(&self.out_dir).expect("failed to create output dir");
+        for input_path in &self.inputs {
+            let (image, _) = open_image(input_path);
+            let stem = input_path.file_stem().unwrap_or(input_path.as_os_str());
+            for (name, plane) in crate::planes::extract(&image, self.planes) {
+                let output_path = self
+                    .out_dir
+                    .join(format!("{}.{}.png", stem.to_string_lossy(), name));
+                plane.save(&output_path).expect("failed to write plane");
+                println!("{}", output_path.display());
+            }
+        }
+    }
+}
+
+impl RdCurve {
+    pub fn run(&self) {
+        let (image, _) = open_image(&self.input);
+        let mut formats = Vec::new();
+        for format in self.formats.iter().flat_map(|x| x.0.clone()) {
+            if !formats.contains(&format) {
+                formats.push(format);
+            }
+        }
+        let points = crate::rd::sweep(&image, &formats, self.metric).expect("failed to sweep");
+        std::fs::write(&self.output, crate::rd::to_csv(&points, self.metric))
+            .expect("failed to write curve");
+        if let Some(svg_path) = self.svg.as_ref() {
+            std::fs::write(svg_path, crate::rd::to_svg(&points, self.metric))
+                .expect("failed to write plot");
+        }
+    }
+}
+
+impl Bench {
+    pub fn run(&self) {
+        let mut codecs = Vec::new();
+        for codec in self.codecs.iter() {
+            if !codecs.contains(codec) {
+                codecs.push(codec.clone());
+            }
+        }
+        let mut samples = Vec::new();
+        for input_path in corpus_paths(&self.dir) {
+            let image = match try_open_image(&input_path) {
+                Some(x) => x,
+                None => continue,
+            };
+            let sample = crate::bench::measure(input_path, &image, &codecs, self.target)
+                .expect("failed to sweep");
+            samples.push(sample);
+        }
+        let summary = crate::bench::summarize(&samples, &codecs);
+        if self.json {
+            let results = serde_json::json!({
+                "target": self.target.to_string(),
+                "anchor": codecs[0],
+                "images": samples,
+                "summary": summary,
+            });
+            println!("{}", serde_json::to_string_pretty(&results).expect("to json failed"));
+            return;
+        }
+        let anchor = format!("{:?}", codecs[0]);
+        let percent = |x: Option<f64>| x.map_or(String::from("-"), |x| format!("{:+.1}%", x * 100.0));
+        println!(
+            "{} images, target {}, anchor {}",
+            samples.len(),
+            self.target,
+            anchor
+        );
+        println!(
+            "{:<8} {:>8} {:>14} {:>10} {:>10}",
+            "codec", "reached", "size@target", "vs anchor", "BD-rate"
+        );
+        for row in summary {
+            println!(
+                "{:<8} {:>8} {:>14} {:>10} {:>10}",
+                format!("{:?}", row.codec),
+                format!("{}/{}", row.reached, samples.len()),
+                crate::gallery::file_size(row.mean_size_at_target.map(|x| x as u64)),
+                percent(row.size_vs_anchor),
+                percent(row.bd_rate),
+            );
+        }
+    }
+}
+
+impl Tune {
+    pub fn run(&self) {
+        let mut profile = match self.base.as_ref() {
+            Some(path) => crate::profile::OptProfile::open(path).expect("invalid `--base` profile"),
+            None => crate::profile::OptProfile::default(),
+        };
+        // FAILED SAMPLES (E.G. TOO SMALL FOR AN ENCODER) ARE SKIPPED
+        let samples = corpus_paths(&self.dir)
+            .par_iter()
+            .filter_map(|path| {
+                let image = try_open_image(path)?;
+                let search = || crate::tune::search(&image, &profile.formats);
+                let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(search))
+                    .unwrap_or_else(|panic| Err(crate::api::panic_message(panic)));
+                if let Err(message) = &result {
+                    eprintln!("[warning] skipping {}: {}", path.display(), message);
+                }
+                result.ok()
+            })
+            .collect::<Vec<_>>();
+        if samples.is_empty() {
+            eprintln!("[error] no sample images in {} could be searched", self.dir.display());
+            std::process::exit(1);
+        }
+        profile.tuning = crate::data::Tuning {
+            max_distance: profile.tuning.max_distance,
+            vmaf: profile.tuning.vmaf,
+            ..crate::tune::tune(&samples)
+        };
+        profile.save(&self.output).expect("failed to write profile");
+        let crate::data::Tuning { jpeg, webp, avif, jpeg_subsampling, webp_effort, .. } = profile.tuning;
+        println!(
+            "{} samples: JPEG q{}-q{} ({:?}), WebP q{}-q{} (effort {}), AVIF q{}-q{}",
+            samples.len(),
+            jpeg.min,
+            jpeg.max,
+            jpeg_subsampling,
+            webp.min,
+            webp.max,
+            webp_effort,
+            avif.min,
+            avif.max
+        );
+    }
+}
+
+impl Pack {
+    pub fn run(&self) {
+        let container = self
+            .container
+            .or_else(|| crate::multires::Container::infer_from_path(&self.output))
+            .expect("unknown container; set `--container`");
+        let sizes = match self.sizes.as_slice() {
+            [] => container.default_sizes(),
+            sizes => sizes,
+        };
+        let (image, _) = open_image(&self.input);
+        let output = crate::multires::write(&image, container, sizes).unwrap_or_else(|message| {
+            eprintln!("[error] {}", message);
+            std::process::exit(1)
+        });
+        std::fs::write(&self.output, output).expect("failed to write output");
+    }
+}
+
+impl Book {
+    pub fn run(&self) {
+        let fail = |message: String| -> ! {
+            eprintln!("[error] {}", message);
+            std::process::exit(1)
+        };
+        let format = crate::book::BookFormat::from_path(&self.input)
+            .unwrap_or_else(|| fail(String::from("unknown book format; use an .epub or .cbz file path")));
+        let source = std::fs::read(&self.input).unwrap_or_else(|e| fail(e.to_string()));
+        let (output, report) = crate::book::optimize(&source, format, self.extreme)
+            .unwrap_or_else(|message| fail(format!("{}: {}", self.input.display(), message)));
+        let output_path = self.output.as_ref().unwrap_or(&self.input);
+        std::fs::write(output_path, output).expect("failed to write output");
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&report).expect("to json"));
+            return;
+        }
+        for image in &report.images {
+            if let Some(message) = &image.error {
+                eprintln!("[warning] {}: {}", image.path, message);
+            }
+        }
+        println!(
+            "{}: {} → {} ({:+.1}%), {} of {} images smaller",
+            output_path.display(),
+            crate::gallery::file_size(Some(report.before as u64)),
+            crate::gallery::file_size(Some(report.after as u64)),
+            -report.reduction() * 100.0,
+            report.images.iter().filter(|x| x.after < x.before).count(),
+            report.images.len(),
+        );
+    }
+}
+
+impl Srcset {
+    pub fn run(&self) {
+        let fail = |message: String| -> ! {
+            eprintln!("[error] {}", message);
+            std::process::exit(1)
+        };
+        let source = std::fs::read(&self.input).unwrap_or_else(|e| fail(e.to_string()));
+        let outputs = api::Job::new(&source)
+            .formats(self.formats.0.clone())
+            .sizes(self.widths.iter().map(crate::srcset::Width::size))
+            .run()
+            .unwrap_or_else(|e| fail(e.to_string()));
+        std::fs::create_dir_all(&self.output_dir).expect("create output dir");
+        let manifest = crate::srcset::write(&self.input, &outputs, &self.output_dir).unwrap_or_else(|e| fail(e));
+        let manifest_path = self.manifest.clone().unwrap_or_else(|| {
+            let stem = self.input.file_stem().and_then(|x| x.to_str()).unwrap_or("image");
+            self.output_dir.join(format!("{}.srcset.json", stem))
+        });
+        std::fs::write(&manifest_path, manifest.to_json()).expect("failed to write manifest");
+        for (mime_type, srcset) in &manifest.srcset {
+            println!("{}: {}", mime_type, srcset);
+        }
+    }
+}
+
+impl Replan {
+    pub fn run(&self) {
+        let fail = |message: String| -> ! {
+            eprintln!("[error] {}", message);
+            std::process::exit(1)
+        };
+        let criteria = crate::replan::Criteria {
+            since_version: self.since_version.clone(),
+            codecs: self.codec.clone(),
+            min_vmaf: self.min_vmaf,
+        };
+        if criteria.is_empty() {
+            fail(String::from("give --since-version, --codec or --min-vmaf"));
+        }
+        let reports = crate::replan::open_reports(&self.reports).unwrap_or_else(|e| fail(e));
+        let plan = crate::replan::Plan::new(&reports, &criteria);
+        match &self.output {
+            Some(path) => {
+                std::fs::write(path, plan.to_json()).expect("failed to write job manifest");
+                eprintln!("{} outputs to re-process", plan.jobs.len());
+            }
+            None => println!("{}", plan.to_json()),
+        }
+    }
+}
+
+impl LongExposure {
+    pub fn run(&self) {
+        let output_format = OutputFormat::infer_from_path(&self.output)
+            .expect("unknown output format; use a .jpeg, .png, .webp, .tiff, .avif or .jxl file path");
+        let canvas = self.canvas.as_ref().map(|x| (x.width, x.height));
+        let frames = crate::data::VideoBuffer::open_image_dir_normalized(&self.input, self.normalize, canvas)
+            .unwrap_or_else(|error| {
+                eprintln!("[error] {}", error);
+                std::process::exit(1)
+            });
+        let max_shift = Some(self.max_shift).filter(|_| self.align);
+        let still = crate::exposure::average(&frames, max_shift).unwrap_or_else(|message| {
+            eprintln!("[error] {}", message);
+            std::process::exit(1)
+        });
+        // LOSSLESSLY, INTO THE USUAL OPTIMIZATION
+        let mut source = std::io::Cursor::new(Vec::new());
+        ::image::DynamicImage::ImageRgb8(still.to_rgba_image().to_rgb8())
+            .write_to(&mut source, ::image::ImageFormat::Png)
+            .expect("failed to encode still");
+        let mut opt_job = crate::api::OptJob::new(source.get_ref()).expect("failed to decode still");
+        opt_job.output_format(output_format);
+        let (output, _) = opt_job.run(false).expect("failed to optimize still");
+        std::fs::write(&self.output, output).expect("failed to write output");
+    }
+}
+
+impl ConvertGif {
+    pub fn run(&self) {
+        let source = std::fs::read(&self.input).expect("failed to read input");
+        let frames = match crate::codec::png::apng::is_apng(&source) {
+            true => crate::codec::png::apng::decode_frames(&source).expect("failed to decode APNG"),
+            false => crate::codec::gif::decode_frames(&source).expect("failed to decode GIF"),
+        };
+        let extension = self.output.extension().and_then(|x| x.to_str()).unwrap_or_default();
+        let output = match extension.parse::<crate::video::VideoFormat>() {
+            Ok(format) => crate::video::encode(&frames, format).expect("failed to encode video"),
+            Err(_) => match OutputFormat::infer_from_path(&self.output) {
+                Some(OutputFormat::Webp) => self.encode_webp(&frames),
+                Some(OutputFormat::Png) => crate::codec::png::apng::encode(&frames).expect("failed to encode APNG"),
+                _ => panic!("unknown output format; use a .webp, .png, .mp4 or .webm file path"),
+            },
+        };
+        std::fs::write(&self.output, &output).expect("failed to write output");
+        println!(
+            "{} frames ({:.2}s): {} → {} bytes",
+            frames.len(),
+            crate::codec::gif::duration(&frames).as_secs_f64(),
+            source.len(),
+            output.len()
+        );
+    }
+    #[cfg(not(feature = "pure-rust"))]
+    fn encode_webp(&self, frames: &[crate::codec::gif::Frame]) -> Vec<u8> {
+        let quality = (!self.lossless).then_some(self.quality);
+        crate::codec::webp::encode::anim::encode(frames, quality).expect("failed to encode animated WebP")
+    }
+    #[cfg(feature = "pure-rust")]
+    fn encode_webp(&self, _: &[crate::codec::gif::Frame]) -> Vec<u8> {
+        eprintln!("[error] {}", crate::codec::registry::FeatureDisabled::WEBP_ENCODING);
+        std::process::exit(1)
+    }
+}
+
+impl Burst {
+    pub fn run(&self) {
+        let output_format = OutputFormat::infer_from_path(&self.output)
+            .expect("unknown output format; use a .jpeg, .png, .webp, .tiff, .avif or .jxl file path");
+        let frames = self.inputs.iter().map(|x| open_image(x).0).collect::<Vec<_>>();
+        let (best, scores) = crate::burst::select(&frames).expect("no stills");
+        for (input_path, score) in self.inputs.iter().zip(&scores) {
+            println!(
+                "{} {}: sharpness {:.1}, exposure {:.3}, total {:.3}",
+                if input_path == &self.inputs[best] { "*" } else { " " },
+                input_path.display(),
+                score.sharpness,
+                score.exposure,
+                score.total
+            );
+        }
+        let mut opt_job = crate::api::OptJob::open(&self.inputs[best]).expect("failed to decode best still");
+        opt_job.output_format(output_format);
+        let (output, _) = opt_job.run(false).expect("failed to optimize best still");
+        std::fs::write(&self.output, output).expect("failed to write output");
+        println!("chose {}", self.inputs[best].display());
+    }
+}
+
+impl Panorama {
+    #[cfg(feature = "panorama")]
+    pub fn run(&self) {
+        use crate::panorama::Direction;
+        let output_format = OutputFormat::infer_from_path(&self.output)
+            .expect("unknown output format; use a .jpeg, .png, .webp, .tiff, .avif or .jxl file path");
+        let images = self.inputs.iter().map(|x| open_image(x).0).collect::<Vec<_>>();
+        let direction = if self.vertical { Direction::TopToBottom } else { Direction::LeftToRight };
+        let panorama = crate::panorama::stitch(&images, direction).unwrap_or_else(|message| {
+            eprintln!("[error] {}", message);
+            std::process::exit(1)
+        });
+        // LOSSLESSLY, INTO THE USUAL OPTIMIZATION
+        let mut source = std::io::Cursor::new(Vec::new());
+        ::image::DynamicImage::ImageRgb8(panorama)
+            .write_to(&mut source, ::image::ImageFormat::Png)
+            .expect("failed to encode panorama");
+        let mut opt_job = crate::api::OptJob::new(source.get_ref()).expect("failed to decode panorama");
+        opt_job.output_format(output_format);
+        let (output, _) = opt_job.run(false).expect("failed to optimize panorama");
+        std::fs::write(&self.output, output).expect("failed to write output");
+    }
+    #[cfg(not(feature = "panorama"))]
+    pub fn run(&self) {
+        eprintln!("[error] {}", crate::codec::registry::FeatureDisabled::PANORAMA);
+        std::process::exit(1)
+    }
+}
+
+impl Vectorize {
+    #[cfg(feature = "vectorize")]
+    pub fn run(&self) {
+        let (source, size) = open_image(&self.input);
+        let options = crate::vectorize::Options {
+            colors: self.colors,
+            tolerance: self.tolerance,
+            min_area: self.min_area,
+            curves: !self.polygons,
+        };
+        let vector = crate::vectorize::vectorize(&source, &options).unwrap_or_else(|message| {
+            eprintln!("[error] {}", message);
+            std::process::exit(1)
+        });
+        // SOURCES OFF BY MORE ARE GRADIENTS OR PHOTOS
+        if vector.error > 4.0 {
+            eprintln!(
+                "[warning] {} isn’t flat colored (mean error {:.1}); the SVG only approximates it",
+                self.input.display(),
+                vector.error
+            );
+        }
+        let output = self.output.clone().unwrap_or_else(|| self.input.with_extension("svg"));
+        std::fs::write(&output, &vector.svg).expect("failed to write output");
+        println!(
+            "{} ({} bytes) → {} ({} bytes, {} colors)",
+            self.input.display(),
+            size,
+            output.display(),
+            vector.svg.len(),
+            vector.colors
+        );
+    }
+    #[cfg(not(feature = "vectorize"))]
+    pub fn run(&self) {
+        eprintln!("[error] {}", crate::codec::registry::FeatureDisabled::VECTORIZE);
+        std::process::exit(1)
+    }
+}
+
+impl Show {
+    pub fn run(&self) {
+        let source = std::fs::read(&self.input).expect("failed to read input");
+        // OUTPUTS MAY BE JPEG XL, WHICH THE DECODERS DON’T HANDLE
+        let image = if crate::codec::jxl::is_jxl(&source) {
+            crate::codec::jxl::decode(&source).unwrap_or_else(|message| {
+                eprintln!("[error] {}", message);
+                std::process::exit(1)
+            })
+        } else {
+            open_image(&self.input).0
+        };
+        let mut terminal = crate::preview::Terminal::size();
+        terminal.columns = self.width.unwrap_or(terminal.columns);
+        // ROOM FOR THE CAPTION AND PROMPT
+        terminal.rows = terminal.rows.saturating_sub(2).max(1);
+        let protocol = self.protocol.unwrap_or_else(crate::preview::Protocol::detect);
+        print!("{}", crate::preview::render(&image, protocol, &terminal));
+        let (width, height) = (image.width(), image.height());
+        println!("{}: {}x{}, {} bytes", self.input.display(), width, height, source.len());
+    }
+}
+
+/// The files of the directory (not recursively), sorted.
+fn corpus_paths(dir: &PathBuf) -> Vec<PathBuf> {
+    let mut paths = std::fs::read_dir(dir)
+        .expect("failed to read dir")
+        .filter_map(Result::ok)
+        .map(|x| x.path())
+        .filter(|x| x.is_file())
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths
+}
+
+/// Like `open_image`, but skips (with a warning) files that aren’t images.
+fn try_open_image(input_path: &PathBuf) -> Option<::image::DynamicImage> {
+    let image = std::fs::read(input_path).ok().and_then(|source| {
+        let format = ::image::guess_format(&source).ok()?;
+        crate::decode::decode(&source, format, &DecodeOptions::default()).ok()
+    });
+    if image.is_none() {
+        eprintln!("[warning] skipping {}: not an image", input_path.display());
+    }
+    image.map(|(image, _)| image)
+}
+
+impl Serve {
+    pub fn run(&self) {
+        load_plugins(&self.plugin);
+        let config = crate::server::ServerConfig {
+            address: self.address.clone(),
+            workers: self.workers.unwrap_or_else(rayon::current_num_threads),
+            job_retention: std::time::Duration::from_secs(self.job_retention),
+            max_body_size: self.max_body_size,
+            max_connections: self.max_connections,
+            callback_hosts: self.callback_host.clone(),
+            shutdown_timeout: std::time::Duration::from_secs(self.shutdown_timeout),
+            sandbox: Some(crate::sandbox::Limits {
+                memory: self.sandbox_memory << 20,
+                cpu_seconds: self.sandbox_cpu_seconds,
+                wall_seconds: self.sandbox_wall_seconds,
+            })
+            .filter(|_| self.sandbox),
+            compat: self.compat.map(|syntax| {
+                let origin = self.origin.as_deref().expect("`--origin`");
+                crate::server::compat::Compat::from_env(syntax, origin).expect("invalid signing key")
+            }),
+            origin_cache: Some(crate::server::origin::OriginCache::new(
+                self.origin_cache_size << 20,
+                std::time::Duration::from_secs(self.origin_max_stale),
+            ))
+            .filter(|_| self.compat.is_some() && self.origin_cache_size > 0)
+            .map(Arc::new),
+            cache_max_age: std::time::Duration::from_secs(self.cache_max_age),
+            client_hints: Some(crate::server::hints::ClientHints {
+                widths: {
+                    let mut widths = self.width_buckets.clone();
+                    widths.sort_unstable();
+                    widths
+                },
+                max_dpr: self.max_dpr,
+                save_data_quality: self.save_data_quality,
+            })
+            .filter(|_| self.client_hints),
+            templates: self.templates.as_ref().map(|path| {
+                crate::server::templates::Templates::open(path).expect("invalid `--templates` file")
+            }),
+        };
+        if config.sandbox.is_some() && !crate::sandbox::SUPPORTED {
+            panic!("`--sandbox` requires Linux on x86_64 or aarch64");
+        }
+        let keys = self.keys.as_ref().map(|path| {
+            crate::server::keys::Keys::open(path).expect("invalid `--keys` file")
+        });
+        let profile = match self.profile.as_ref() {
+            Some(path) => crate::server::reload::LiveProfile::open(path).expect("invalid `--profile`"),
+            None => crate::server::reload::LiveProfile::fixed(Default::default()),
+        };
+        crate::server::serve(config, keys, profile).expect("failed to serve");
+    }
+}
+
+impl SandboxWorker {
+    pub fn run(&self) {
+        let limits = crate::sandbox::Limits {
+            memory: self.memory,
+            cpu_seconds: self.cpu_seconds,
+            ..Default::default()
+        };
+        std::process::exit(crate::sandbox::worker_main(&limits, &self.plugin));
+    }
+}
+
+impl RunPipeline {
+    pub fn run(&self) {
+        if self.print_schema {
+            println!("{}", crate::pipeline::PIPELINE_JSON_SCHEMA);
+            return;
+        }
+        let pipeline_path = self.pipeline.as_ref().expect("pipeline file path");
+        let pipeline = if self.sharp {
+            let source = std::fs::read_to_string(pipeline_path).expect("read pipeline file");
+            let translation = crate::sharp::translate(&source).expect("invalid sharp operations");
+            for note in &translation.notes {
+                eprintln!("[warning] {}", note);
+            }
+            translation.pipeline
+        } else {
+            crate::pipeline::Pipeline::open(pipeline_path).expect("invalid pipeline")
+        };
+        if self.translate {
+            println!("{}", pipeline.to_json());
+            return;
+        }
+        let output_dir = self.output_dir.as_ref().expect("output dir");
+        let output_ext = pipeline.format().expect("validated").extension();
+        std::fs::create_dir_all(output_dir).expect("create output dir");
+        let failed = self
+            .inputs
+            .par_iter()
+            .filter(|input_path| {
+                let output_path = output_dir
+                    .join(input_path.file_name().expect("file name"))
+                    .with_extension(output_ext);
+                let result = std::fs::read(input_path).map_err(|e| e.to_string()).and_then(|source| {
+                    let (out, meta) = crate::pipeline::run(&pipeline, &source)?;
+                    for warning in &meta.warnings {
+                        eprintln!("[warning] {}: {}", input_path.display(), warning);
+                    }
+                    let mode = match self.provenance {
+                        Some(mode) => mode,
+                        None => return std::fs::write(&output_path, out).map_err(|e| e.to_string()),
+                    };
+                    let format = match &meta.fallback {
+                        Some(fallback) => fallback.output.clone(),
+                        None => pipeline.format().expect("validated").clone(),
+                    };
+                    let parameters = serde_json::to_value(&pipeline).expect("to json failed");
+                    let provenance = Provenance::new(&source, parameters, format, meta.versions.clone());
+                    let dimensions = ::image::io::Reader::new(std::io::Cursor::new(&out))
+                        .with_guessed_format()
+                        .ok()
+                        .and_then(|x| x.into_dimensions().ok());
+                    let embedded = dimensions
+                        .filter(|_| mode == ProvenanceMode::Xmp)
+                        .and_then(|dimensions| provenance.embed(&out, dimensions));
+                    match embedded {
+                        Some(embedded) => std::fs::write(&output_path, embedded).map_err(|e| e.to_string()),
+                        None => {
+                            std::fs::write(&output_path, out).map_err(|e| e.to_string())?;
+                            provenance.write_sidecar(&output_path)
+                        }
+                    }
+                });
+                if let Err(message) = &result {
+                    eprintln!("[error] {}: {}", input_path.display(), message);
+                }
+                result.is_err()
+            })
+            .count();
+        if failed > 0 {
+            eprintln!("[error] {} of {} inputs failed", failed, self.inputs.len());
+            std::process::exit(1);
+        }
+    }
+}
+
+impl RunBatch {
+    pub fn run(&self) {
+        let max_size = self.max_size.clone();
+        let extreme = self.extreme;
+        let mut batch = crate::batch::Batch::new(&self.input_dir, &self.output_dir)
+            .formats(self.formats.0.clone())
+            .configure(move |job| match &max_size {
+                Some(max_size) => job.max_size(crate::data::OutputSize::Px(max_size.clone())).extreme(extreme),
+                None => job.extreme(extreme),
+            });
+        if let Some(threads) = self.threads {
+            batch = batch.threads(threads);
+        }
+        let progress_bar = ProgressBar::new(0);
+        let report = batch
+            .run(|progress| {
+                progress_bar.set_length(progress.total as u64);
+                if let Err(message) = &progress.result {
+                    progress_bar.println(format!("[error] {}: {}", progress.input_path.display(), message));
+                }
+                progress_bar.inc(1);
+            })
+            .unwrap_or_else(|e| {
+                eprintln!("[error] {}", e);
+                std::process::exit(1)
+            });
+        progress_bar.finish();
+        if let Some(log_path) = &self.log_file {
+            let output_log = report.render(ReportFormat::Json, log_path);
+            std::fs::write(log_path, output_log).expect("failed to write log file");
+        }
+        if !report.outputs.is_empty() {
+            eprint!("{}", report.summary());
+        }
+        if !report.errors.is_empty() {
+            std::process::exit(1);
+        }
+    }
+}
+
+fn load_plugins(paths: &[PathBuf]) {
+    for path in paths {
+        let encoder = crate::codec::registry::load_plugin(path)
+            .unwrap_or_else(|e| panic!("invalid `--plugin`: {}", e));
+        eprintln!("[note] encoding {:?} with the {} plugin", encoder.format, encoder.name);
     }
 }
 
@@ -249,5 +2517,33 @@ impl Command {
 
 fn main() {
     let cmd = Command::from_args();
-    cmd.run();
+    if let Some(temp_dir) = &cmd.temp_dir {
+        crate::workspace::set_root(temp_dir);
+    }
+    match &cmd.tool {
+        Some(Tool::Verify(tool)) => tool.run(),
+        Some(Tool::VerifyMark(tool)) => tool.run(),
+        Some(Tool::Pipeline(tool)) => tool.run(),
+        Some(Tool::Batch(tool)) => tool.run(),
+        Some(Tool::Montage(tool)) => tool.run(),
+        Some(Tool::Stitch(tool)) => tool.run(),
+        Some(Tool::Diff(tool)) => tool.run(),
+        Some(Tool::Planes(tool)) => tool.run(),
+        Some(Tool::RdCurve(tool)) => tool.run(),
+        Some(Tool::Bench(tool)) => tool.run(),
+        Some(Tool::Tune(tool)) => tool.run(),
+        Some(Tool::Pack(tool)) => tool.run(),
+        Some(Tool::Book(tool)) => tool.run(),
+        Some(Tool::Srcset(tool)) => tool.run(),
+        Some(Tool::Replan(tool)) => tool.run(),
+        Some(Tool::Gif(tool)) => tool.run(),
+        Some(Tool::LongExposure(tool)) => tool.run(),
+        Some(Tool::Burst(tool)) => tool.run(),
+        Some(Tool::Panorama(tool)) => tool.run(),
+        Some(Tool::Vectorize(tool)) => tool.run(),
+        Some(Tool::Show(tool)) => tool.run(),
+        Some(Tool::Serve(tool)) => tool.run(),
+        Some(Tool::SandboxWorker(tool)) => tool.run(),
+        None => cmd.run(),
+    }
 }
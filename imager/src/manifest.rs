@@ -0,0 +1,89 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Integrity manifests (`--manifest`): the SHA-256 of every output, and of
+//! the source it was generated from, e.g. as the subjects of a
+//! supply-chain attestation. Outputs are sorted by path, so manifests of
+//! reproducible batches (see `--seed`) are identical.
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::data::OutputFormat;
+
+pub const MANIFEST_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileDigest {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Lowercase hex.
+    pub sha256: String,
+}
+
+impl FileDigest {
+    pub fn new(path: PathBuf, contents: &[u8]) -> Self {
+        FileDigest {
+            path,
+            size: contents.len() as u64,
+            sha256: imager_core::digest::hex(&imager_core::digest::sha256(contents)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    #[serde(flatten)]
+    pub output: FileDigest,
+    pub format: OutputFormat,
+    pub source: FileDigest,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub schema_version: u32,
+    /// E.g. `imager 0.3.3`.
+    pub generator: String,
+    pub outputs: Vec<Entry>,
+}
+
+impl Manifest {
+    pub fn new(mut outputs: Vec<Entry>) -> Self {
+        outputs.sort_by(|a, b| a.output.path.cmp(&b.output.path));
+        Manifest {
+            schema_version: MANIFEST_SCHEMA_VERSION,
+            generator: format!("imager {}", env!("CARGO_PKG_VERSION")),
+            outputs,
+        }
+    }
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("to json str failed")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let entry = |name: &str, contents: &[u8]| Entry {
+            output: FileDigest::new(PathBuf::from(name), contents),
+            format: OutputFormat::Webp,
+            source: FileDigest::new(PathBuf::from("in.png"), b""),
+        };
+        let manifest = Manifest::new(vec![entry("b.webp", b"abc"), entry("a.webp", b"")]);
+        assert_eq!(manifest.outputs[0].output.path, PathBuf::from("a.webp"));
+        let json: serde_json::Value = serde_json::from_str(&manifest.to_json()).expect("json");
+        let output = &json["outputs"][1];
+        assert_eq!(output["path"], "b.webp");
+        assert_eq!(output["size"], 3);
+        assert_eq!(
+            output["sha256"],
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            output["source"]["sha256"],
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}
@@ -0,0 +1,270 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! C2PA (content credentials) manifest stores.
+//!
+//! Re-encoding invalidates the hard binding of a source’s manifests, so
+//! they can’t simply be copied. Instead outputs are re-signed (with the
+//! source as parent ingredient, preserving the chain) by the reference
+//! `c2patool` executable, which must be on the `PATH` (or configured).
+use image::ImageFormat;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::data::OutputFormat;
+
+///////////////////////////////////////////////////////////////////////////////
+// READING
+///////////////////////////////////////////////////////////////////////////////
+
+/// The JUMBF manifest store embedded in a source, if any.
+pub fn extract_manifest_store(source: &[u8], format: ImageFormat) -> Option<Vec<u8>> {
+    match format {
+        ImageFormat::Jpeg => jpeg_manifest_store(source),
+        ImageFormat::Png => super::container::png_chunks(source)
+            .find(|(kind, _, _)| kind == b"caBX")
+            .map(|(_, data, _)| data.to_vec()),
+        ImageFormat::WebP => super::container::webp_chunks(source)
+            .find(|(kind, _)| kind == b"C2PA")
+            .map(|(_, payload)| payload.to_vec()),
+        _ => None,
+    }
+}
+
+/// JUMBF in JPEG is split over APP11 segments, each prefixed with the
+/// `JP` common identifier, a box instance number and a sequence number;
+/// continuation segments repeat the 8 byte superbox header.
+fn jpeg_manifest_store(source: &[u8]) -> Option<Vec<u8>> {
+    let mut stores: Vec<(u16, Vec<u8>)> = Vec::new();
+    for (marker, payload) in super::container::jpeg_segments(source) {
+        if marker != 0xEB || payload.len() < 16 || &payload[..2] != b"JP" {
+            continue;
+        }
+        let instance = u16::from_be_bytes([payload[2], payload[3]]);
+        match stores.iter_mut().find(|(x, _)| *x == instance) {
+            Some((_, store)) => store.extend_from_slice(&payload[16..]),
+            None => stores.push((instance, payload[8..].to_vec())),
+        }
+    }
+    stores
+        .into_iter()
+        .map(|(_, store)| store)
+        .find(|store| box_label(store).as_deref() == Some("c2pa"))
+}
+
+/// The `(type, content)` boxes within a JUMBF (ISO BMFF style) buffer.
+fn boxes(source: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut at = 0;
+    std::iter::from_fn(move || {
+        let len = u32::from_be_bytes(source.get(at..at + 4)?.try_into().ok()?) as usize;
+        let kind: [u8; 4] = source.get(at + 4..at + 8)?.try_into().ok()?;
+        // ZERO MEANS ‘TO THE END’
+        let end = if len == 0 { source.len() } else { at.checked_add(len)? };
+        let content = source.get(at + 8..end)?;
+        at = end;
+        Some((kind, content))
+    })
+}
+
+/// The label of a `jumb` superbox.
+fn box_label(superbox: &[u8]) -> Option<String> {
+    match boxes(superbox).next()? {
+        (kind, content) if &kind == b"jumb" => superbox_label(content),
+        _ => None,
+    }
+}
+
+/// The label of a superbox’s content, from its `jumd` description box.
+fn superbox_label(content: &[u8]) -> Option<String> {
+    let (kind, description) = boxes(content).next()?;
+    // 16 BYTE CONTENT TYPE UUID, TOGGLES, THEN THE (OPTIONAL) LABEL
+    let toggles = *description.get(16)?;
+    if &kind != b"jumd" || toggles & 0x02 == 0 {
+        return None;
+    }
+    let label = description.get(17..)?.split(|x| *x == 0).next()?;
+    String::from_utf8(label.to_vec()).ok()
+}
+
+/// The labels of the manifests in a store, oldest first (the active
+/// manifest is the last).
+pub fn manifest_labels(store: &[u8]) -> Vec<String> {
+    let content = match boxes(store).next() {
+        Some((kind, content)) if &kind == b"jumb" => content,
+        _ => return Vec::new(),
+    };
+    boxes(content)
+        .skip(1)
+        .filter(|(kind, _)| kind == b"jumb")
+        .filter_map(|(_, content)| superbox_label(content))
+        .collect()
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// SIGNING
+///////////////////////////////////////////////////////////////////////////////
+
+/// Signing configuration, usually read from a JSON file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct C2paSigner {
+    /// The `c2patool` executable; by default, from the `PATH`.
+    #[serde(default)]
+    pub tool: Option<PathBuf>,
+    /// E.g. `es256`, `ps256` or `ed25519`.
+    pub alg: String,
+    /// PEM private key.
+    pub private_key: PathBuf,
+    /// PEM certificate chain for the key.
+    pub sign_cert: PathBuf,
+    /// RFC 3161 timestamp authority.
+    #[serde(default)]
+    pub ta_url: Option<String>,
+}
+
+impl C2paSigner {
+    /// Relative key and certificate paths are resolved against the
+    /// directory of the config file.
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path.as_ref()).map_err(|e| e.to_string())?;
+        let mut signer: C2paSigner = serde_json::from_str(&source).map_err(|e| e.to_string())?;
+        let base = path
+            .as_ref()
+            .parent()
+            .map(|x| x.to_path_buf())
+            .unwrap_or_default();
+        let base = std::fs::canonicalize(&base).unwrap_or(base);
+        signer.private_key = base.join(&signer.private_key);
+        signer.sign_cert = base.join(&signer.sign_cert);
+        Ok(signer)
+    }
+}
+
+/// What became of a source’s content credentials.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct C2paReport {
+    /// Manifest labels of the source, oldest first.
+    pub source_manifests: Vec<String>,
+    /// Whether the output carries a (new) signed manifest.
+    pub signed: bool,
+}
+
+/// Signs an output with a manifest recording the (`c2pa.transcoded`)
+/// action, with the source as parent ingredient.
+pub fn sign(
+    output: &[u8],
+    format: &OutputFormat,
+    source: &[u8],
+    source_format: ImageFormat,
+    signer: &C2paSigner,
+) -> Result<Vec<u8>, String> {
+    let dir = crate::workspace::JobDir::new("c2pa")?;
+    sign_in(dir.path(), output, format, source, source_format, signer)
+}
+
+fn sign_in(
+    dir: &std::path::Path,
+    output: &[u8],
+    format: &OutputFormat,
+    source: &[u8],
+    source_format: ImageFormat,
+    signer: &C2paSigner,
+) -> Result<Vec<u8>, String> {
+    let ext = |format: &OutputFormat| match format {
+        OutputFormat::Jpeg => "jpg",
+        OutputFormat::Png => "png",
+        OutputFormat::Webp => "webp",
+        OutputFormat::Tiff => "tiff",
+        OutputFormat::Avif => "avif",
+        OutputFormat::Jxl => "jxl",
+    };
+    let source_ext = match source_format {
+        ImageFormat::Png => "png",
+        ImageFormat::WebP => "webp",
+        _ => "jpg",
+    };
+    let unsigned_path = dir.join(format!("unsigned.{}", ext(format)));
+    let signed_path = dir.join(format!("signed.{}", ext(format)));
+    let parent_path = dir.join(format!("parent.{}", source_ext));
+    let manifest_path = dir.join("manifest.json");
+    let mut manifest = serde_json::json!({
+        "alg": signer.alg,
+        "private_key": signer.private_key,
+        "sign_cert": signer.sign_cert,
+        "claim_generator": format!("imager/{}", env!("CARGO_PKG_VERSION")),
+        "assertions": [{
+            "label": "c2pa.actions",
+            "data": {"actions": [{"action": "c2pa.transcoded"}]}
+        }]
+    });
+    if let Some(ta_url) = &signer.ta_url {
+        manifest["ta_url"] = serde_json::Value::from(ta_url.as_str());
+    }
+    let write = |path: &std::path::Path, data: &[u8]| {
+        std::fs::write(path, data).map_err(|e| e.to_string())
+    };
+    write(&unsigned_path, output)?;
+    write(&parent_path, source)?;
+    write(&manifest_path, manifest.to_string().as_bytes())?;
+    let tool = signer.tool.clone().unwrap_or_else(|| PathBuf::from("c2patool"));
+    let result = Command::new(&tool)
+        .arg(&unsigned_path)
+        .arg("--manifest")
+        .arg(&manifest_path)
+        .arg("--parent")
+        .arg(&parent_path)
+        .arg("--output")
+        .arg(&signed_path)
+        .arg("--force")
+        .output()
+        .map_err(|e| format!("failed to run {}: {}", tool.display(), e))?;
+    if !result.status.success() {
+        return Err(String::from_utf8_lossy(&result.stderr).trim().to_string());
+    }
+    std::fs::read(&signed_path).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn jumbf(label: &str, children: &[u8]) -> Vec<u8> {
+        let mut description = vec![0u8; 16];
+        description.push(0x03);
+        description.extend_from_slice(label.as_bytes());
+        description.push(0);
+        let mut content = ((description.len() + 8) as u32).to_be_bytes().to_vec();
+        content.extend_from_slice(b"jumd");
+        content.extend_from_slice(&description);
+        content.extend_from_slice(children);
+        let mut output = ((content.len() + 8) as u32).to_be_bytes().to_vec();
+        output.extend_from_slice(b"jumb");
+        output.extend_from_slice(&content);
+        output
+    }
+
+    #[test]
+    fn test_jpeg_manifest_store() {
+        let mut manifests = jumbf("urn:uuid:first", &[]);
+        manifests.extend(jumbf("urn:uuid:second", &[]));
+        let store = jumbf("c2pa", &manifests);
+        // SPLIT OVER TWO APP11 SEGMENTS
+        let (head, tail) = store.split_at(20);
+        let mut jpeg = vec![0xFF, 0xD8];
+        for (sequence, chunk) in [head, tail].iter().enumerate() {
+            let mut payload = b"JP\0\x01".to_vec();
+            payload.extend_from_slice(&(sequence as u32 + 1).to_be_bytes());
+            if sequence > 0 {
+                payload.extend_from_slice(&store[..8]);
+            }
+            payload.extend_from_slice(chunk);
+            jpeg.extend_from_slice(&[0xFF, 0xEB]);
+            jpeg.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+            jpeg.extend_from_slice(&payload);
+        }
+        jpeg.extend_from_slice(&[0xFF, 0xDA]);
+        let extracted = extract_manifest_store(&jpeg, ImageFormat::Jpeg).expect("manifest store");
+        assert_eq!(extracted, store);
+        assert_eq!(manifest_labels(&extracted), vec!["urn:uuid:first", "urn:uuid:second"]);
+    }
+}
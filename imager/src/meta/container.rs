@@ -0,0 +1,408 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Locating and inserting metadata payloads in JPEG, PNG and WebP files (and
+//! stripping them from JPEG ones).
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use image::ImageFormat;
+use std::io::{Read, Write};
+
+use crate::data::OutputFormat;
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+/// The iTXt keyword of XMP in PNG.
+const XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+
+///////////////////////////////////////////////////////////////////////////////
+// JPEG
+///////////////////////////////////////////////////////////////////////////////
+
+/// The `(marker, payload)` segments before the image data.
+pub(super) fn jpeg_segments(source: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let mut at = 2;
+    std::iter::from_fn(move || {
+        if source.get(at) != Some(&0xFF) {
+            return None;
+        }
+        let marker = *source.get(at + 1)?;
+        // START OF SCAN: NO MORE METADATA
+        if marker == 0xDA {
+            return None;
+        }
+        let len = u16::from_be_bytes([*source.get(at + 2)?, *source.get(at + 3)?]) as usize;
+        let payload = source.get(at + 4..at + 2 + len)?;
+        at += 2 + len;
+        Some((marker, payload))
+    })
+}
+
+fn jpeg_insert_app1(encoded: &[u8], header: &[u8], payload: &[u8]) -> Option<Vec<u8>> {
+    jpeg_insert_segment(encoded, 0xE1, header, payload)
+}
+
+fn jpeg_insert_segment(encoded: &[u8], marker: u8, header: &[u8], payload: &[u8]) -> Option<Vec<u8>> {
+    let len = u16::try_from(2 + header.len() + payload.len()).ok()?;
+    // AFTER SOI, A JFIF APP0, AN EXIF APP1 SEGMENT AND ICC APP2 SEGMENTS, IF
+    // ANY; EXIF MUST BE THE FIRST APP1
+    let mut at = 2;
+    let leading = jpeg_segments(encoded).take_while(|(marker, payload)| {
+        *marker == 0xE0
+            || (*marker == 0xE1 && payload.starts_with(EXIF_HEADER))
+            || (*marker == 0xE2 && payload.starts_with(ICC_HEADER))
+    });
+    for (_, payload) in leading {
+        at += 4 + payload.len();
+    }
+    let mut output = Vec::with_capacity(encoded.len() + len as usize + 2);
+    output.extend_from_slice(&encoded[..at]);
+    output.extend_from_slice(&[0xFF, marker]);
+    output.extend_from_slice(&len.to_be_bytes());
+    output.extend_from_slice(header);
+    output.extend_from_slice(payload);
+    output.extend_from_slice(&encoded[at..]);
+    Some(output)
+}
+
+/// Without the APPn segments (other than JFIF and Adobe ones, which affect
+/// decoding) and comments, e.g. of EXIF, XMP and ICC profiles; none if the
+/// source isn’t a well-formed JPEG.
+pub fn jpeg_strip_metadata(source: &[u8]) -> Option<Vec<u8>> {
+    if !source.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut output = Vec::with_capacity(source.len());
+    output.extend_from_slice(&source[..2]);
+    let mut at = 2;
+    for (marker, payload) in jpeg_segments(source) {
+        let end = at + 4 + payload.len();
+        let metadata = ((0xE1..=0xEF).contains(&marker) && marker != 0xEE) || marker == 0xFE;
+        if !metadata {
+            output.extend_from_slice(&source[at..end]);
+        }
+        at = end;
+    }
+    // THE SEGMENTS MUST END AT THE FIRST SCAN
+    if source.get(at..at + 2) != Some(&[0xFF, 0xDA]) {
+        return None;
+    }
+    output.extend_from_slice(&source[at..]);
+    Some(output)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PNG
+///////////////////////////////////////////////////////////////////////////////
+
+/// The `(type, data, chunk start)` chunks of a PNG file.
+pub(crate) fn png_chunks(source: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8], usize)> {
+    let mut at = 8;
+    std::iter::from_fn(move || {
+        let len = u32::from_be_bytes(source.get(at..at + 4)?.try_into().ok()?) as usize;
+        let kind: [u8; 4] = source.get(at + 4..at + 8)?.try_into().ok()?;
+        let data = source.get(at + 8..(at + 8).checked_add(len)?)?;
+        let start = at;
+        at += 12 + len;
+        Some((kind, data, start))
+    })
+}
+
+fn png_insert_chunk(encoded: &[u8], kind: &[u8; 4], data: &[u8]) -> Option<Vec<u8>> {
+    // BEFORE THE PALETTE (AS `iCCP` MUST BE) AND THE IMAGE DATA
+    let (_, _, at) = png_chunks(encoded).find(|(kind, _, _)| kind == b"PLTE" || kind == b"IDAT")?;
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    let mut output = Vec::with_capacity(encoded.len() + data.len() + 12);
+    output.extend_from_slice(&encoded[..at]);
+    output.extend_from_slice(&u32::try_from(data.len()).ok()?.to_be_bytes());
+    output.extend_from_slice(kind);
+    output.extend_from_slice(data);
+    output.extend_from_slice(&crc.finalize().to_be_bytes());
+    output.extend_from_slice(&encoded[at..]);
+    Some(output)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// WEBP
+///////////////////////////////////////////////////////////////////////////////
+
+/// The `(fourcc, payload)` chunks of a WebP (RIFF) file.
+pub(crate) fn webp_chunks(source: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut at = 12;
+    std::iter::from_fn(move || {
+        let kind: [u8; 4] = source.get(at..at + 4)?.try_into().ok()?;
+        let len = u32::from_le_bytes(source.get(at + 4..at + 8)?.try_into().ok()?) as usize;
+        let payload = source.get(at + 8..(at + 8).checked_add(len)?)?;
+        at += 8 + len + len % 2;
+        Some((kind, payload))
+    })
+}
+
+const VP8X_ICC: u8 = 0x20;
+const VP8X_ALPHA: u8 = 0x10;
+const VP8X_EXIF: u8 = 0x08;
+const VP8X_XMP: u8 = 0x04;
+
+/// Appends a chunk (or inserts it after the `VP8X`, for `ICCP`), converting
+/// simple (`VP8 `/`VP8L`) files to the extended (`VP8X`) layout as
+/// required.
+fn webp_insert_chunk(
+    encoded: &[u8],
+    kind: &[u8; 4],
+    flag: u8,
+    payload: &[u8],
+    (width, height): (u32, u32),
+) -> Option<Vec<u8>> {
+    let mut chunks = webp_chunks(encoded)
+        .map(|(kind, payload)| (kind, payload.to_vec()))
+        .collect::<Vec<_>>();
+    if chunks.first()?.0 != *b"VP8X" {
+        // VP8L HEADERS CARRY AN ALPHA HINT; LOSSY WITH ALPHA IS ALREADY VP8X
+        let (first, data) = chunks.first()?;
+        let alpha = first == b"VP8L" && data.get(4).map(|x| x & 0x10 != 0)?;
+        let mut vp8x = vec![if alpha { VP8X_ALPHA } else { 0 }, 0, 0, 0];
+        vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        chunks.insert(0, (*b"VP8X", vp8x));
+    }
+    chunks[0].1[0] |= flag;
+    if kind == b"ICCP" {
+        chunks.insert(1, (*kind, payload.to_vec()));
+    } else {
+        chunks.push((*kind, payload.to_vec()));
+    }
+    let mut body = b"WEBP".to_vec();
+    for (kind, payload) in chunks {
+        body.extend_from_slice(&kind);
+        body.extend_from_slice(&u32::try_from(payload.len()).ok()?.to_le_bytes());
+        body.extend_from_slice(&payload);
+        if payload.len() % 2 == 1 {
+            body.push(0);
+        }
+    }
+    let mut output = b"RIFF".to_vec();
+    output.extend_from_slice(&u32::try_from(body.len()).ok()?.to_le_bytes());
+    output.extend_from_slice(&body);
+    Some(output)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// EXIF
+///////////////////////////////////////////////////////////////////////////////
+
+/// The EXIF (TIFF) payload of a source image, if any.
+pub fn extract_exif(source: &[u8], format: ImageFormat) -> Option<Vec<u8>> {
+    let payload = match format {
+        ImageFormat::Jpeg => jpeg_segments(source)
+            .find(|(marker, payload)| *marker == 0xE1 && payload.starts_with(EXIF_HEADER))
+            .map(|(_, payload)| &payload[EXIF_HEADER.len()..])?,
+        ImageFormat::Png => png_chunks(source)
+            .find(|(kind, _, _)| kind == b"eXIf")
+            .map(|(_, data, _)| data)?,
+        ImageFormat::WebP => webp_chunks(source)
+            .find(|(kind, _)| kind == b"EXIF")
+            .map(|(_, payload)| payload.strip_prefix(EXIF_HEADER).unwrap_or(payload))?,
+        _ => return None,
+    };
+    Some(payload.to_vec())
+}
+
+/// Embeds an EXIF (TIFF) payload. Returns the output unchanged when the
+/// container can’t hold it (e.g. above 64K for JPEG).
+pub fn insert_exif(
+    encoded: Vec<u8>,
+    format: &OutputFormat,
+    tiff: &[u8],
+    dimensions: (u32, u32),
+) -> Vec<u8> {
+    let output = match format {
+        OutputFormat::Jpeg => jpeg_insert_app1(&encoded, EXIF_HEADER, tiff),
+        OutputFormat::Png => png_insert_chunk(&encoded, b"eXIf", tiff),
+        OutputFormat::Webp => webp_insert_chunk(&encoded, b"EXIF", VP8X_EXIF, tiff, dimensions),
+        // TIFF, AVIF AND JPEG XL OUTPUTS ARE WRITTEN WITHOUT METADATA
+        OutputFormat::Tiff | OutputFormat::Avif | OutputFormat::Jxl => None,
+    };
+    output.unwrap_or(encoded)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// XMP
+///////////////////////////////////////////////////////////////////////////////
+
+/// The XMP packet of a source image, if any.
+pub fn extract_xmp(source: &[u8], format: ImageFormat) -> Option<Vec<u8>> {
+    let payload = match format {
+        ImageFormat::Jpeg => jpeg_segments(source)
+            .find(|(marker, payload)| *marker == 0xE1 && payload.starts_with(XMP_HEADER))
+            .map(|(_, payload)| &payload[XMP_HEADER.len()..])?,
+        // UNCOMPRESSED, WITH EMPTY LANGUAGE AND TRANSLATED KEYWORD
+        ImageFormat::Png => png_chunks(source)
+            .find(|(kind, data, _)| kind == b"iTXt" && data.starts_with(XMP_KEYWORD))
+            .and_then(|(_, data, _)| data.get(XMP_KEYWORD.len() + 5..))?,
+        ImageFormat::WebP => webp_chunks(source)
+            .find(|(kind, _)| kind == b"XMP ")
+            .map(|(_, payload)| payload)?,
+        _ => return None,
+    };
+    Some(payload.to_vec())
+}
+
+/// Embeds an XMP packet, after any EXIF. Returns the output unchanged
+/// when the container can’t hold it.
+pub fn insert_xmp(
+    encoded: Vec<u8>,
+    format: &OutputFormat,
+    packet: &[u8],
+    dimensions: (u32, u32),
+) -> Vec<u8> {
+    let output = match format {
+        OutputFormat::Jpeg => jpeg_insert_app1(&encoded, XMP_HEADER, packet),
+        OutputFormat::Png => {
+            let mut data = XMP_KEYWORD.to_vec();
+            data.extend_from_slice(&[0, 0, 0, 0, 0]);
+            data.extend_from_slice(packet);
+            png_insert_chunk(&encoded, b"iTXt", &data)
+        }
+        OutputFormat::Webp => webp_insert_chunk(&encoded, b"XMP ", VP8X_XMP, packet, dimensions),
+        OutputFormat::Tiff | OutputFormat::Avif | OutputFormat::Jxl => None,
+    };
+    output.unwrap_or(encoded)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ICC
+///////////////////////////////////////////////////////////////////////////////
+
+const ICC_HEADER: &[u8] = b"ICC_PROFILE\0";
+/// Of JPEG APP2 segments, after the header and the sequence number and
+/// count.
+const MAX_ICC_CHUNK: usize = 65535 - 2 - ICC_HEADER.len() - 2;
+/// Of PNG `iCCP` chunks.
+const ICC_NAME: &[u8] = b"ICC profile";
+
+/// The ICC profile of a source image, if any; JPEG profiles may be split
+/// across segments, PNG ones are compressed.
+pub fn extract_icc(source: &[u8], format: ImageFormat) -> Option<Vec<u8>> {
+    match format {
+        ImageFormat::Jpeg => {
+            let mut chunks = jpeg_segments(source)
+                .filter(|(marker, payload)| *marker == 0xE2 && payload.starts_with(ICC_HEADER))
+                .filter_map(|(_, payload)| payload.get(ICC_HEADER.len()..))
+                .filter_map(|x| Some((*x.first()?, x.get(2..)?)))
+                .collect::<Vec<_>>();
+            chunks.sort_by_key(|(sequence, _)| *sequence);
+            let profile = chunks.into_iter().flat_map(|(_, chunk)| chunk.iter().copied()).collect::<Vec<_>>();
+            Some(profile).filter(|x| !x.is_empty())
+        }
+        ImageFormat::Png => {
+            let (_, data, _) = png_chunks(source).find(|(kind, _, _)| kind == b"iCCP")?;
+            // A NAME, THEN THE COMPRESSION METHOD (ZLIB)
+            let name_len = data.iter().position(|x| *x == 0)?;
+            let mut profile = Vec::new();
+            ZlibDecoder::new(data.get(name_len + 2..)?)
+                .read_to_end(&mut profile)
+                .ok()?;
+            Some(profile)
+        }
+        ImageFormat::WebP => webp_chunks(source)
+            .find(|(kind, _)| kind == b"ICCP")
+            .map(|(_, payload)| payload.to_vec()),
+        _ => None,
+    }
+}
+
+/// Embeds an ICC profile. Returns the output unchanged when the container
+/// can’t hold it (e.g. above 255 segments for JPEG).
+pub fn insert_icc(encoded: Vec<u8>, format: &OutputFormat, profile: &[u8], dimensions: (u32, u32)) -> Vec<u8> {
+    let output = match format {
+        OutputFormat::Jpeg => {
+            let chunks = profile.chunks(MAX_ICC_CHUNK).collect::<Vec<_>>();
+            let count = u8::try_from(chunks.len()).ok();
+            // IN ORDER, AS EACH GOES AFTER THE PREVIOUS
+            count.and_then(|count| {
+                chunks.iter().enumerate().try_fold(encoded.clone(), |output, (index, chunk)| {
+                    let header = [ICC_HEADER, &[index as u8 + 1, count]].concat();
+                    jpeg_insert_segment(&output, 0xE2, &header, chunk)
+                })
+            })
+        }
+        OutputFormat::Png => {
+            let mut compressed = ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+            compressed.write_all(profile).expect("compress icc profile");
+            let data = [ICC_NAME, &[0, 0], &compressed.finish().expect("compress icc profile")].concat();
+            png_insert_chunk(&encoded, b"iCCP", &data)
+        }
+        OutputFormat::Webp => webp_insert_chunk(&encoded, b"ICCP", VP8X_ICC, profile, dimensions),
+        OutputFormat::Tiff | OutputFormat::Avif | OutputFormat::Jxl => None,
+    };
+    output.unwrap_or(encoded)
+}
+
+/// Whether the source embeds an ICC profile.
+pub fn has_icc_profile(source: &[u8], format: ImageFormat) -> bool {
+    match format {
+        ImageFormat::Jpeg => jpeg_segments(source)
+            .any(|(marker, payload)| marker == 0xE2 && payload.starts_with(ICC_HEADER)),
+        ImageFormat::Png => png_chunks(source).any(|(kind, _, _)| &kind == b"iCCP"),
+        ImageFormat::WebP => webp_chunks(source).any(|(kind, _)| &kind == b"ICCP"),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::GenericImageView;
+
+    #[test]
+    fn test_exif_roundtrip() {
+        let source = image::load_from_memory(include_bytes!("../../assets/test/1.jpeg"))
+            .expect("decode test image")
+            .thumbnail(64, 64);
+        let tiff = b"II*\0\x08\0\0\0\0\0\0\0\0\0".to_vec();
+        let xmp = b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>".to_vec();
+        let encoded = [
+            (OutputFormat::Jpeg, ImageFormat::Jpeg),
+            (OutputFormat::Png, ImageFormat::Png),
+            #[cfg(not(feature = "pure-rust"))]
+            (OutputFormat::Webp, ImageFormat::WebP),
+        ];
+        for (output_format, format) in encoded {
+            let output = match output_format {
+                #[cfg(not(feature = "pure-rust"))]
+                OutputFormat::Webp => crate::codec::webp::encode::lossy::encode(&source, 75.0),
+                _ => {
+                    let mut output = std::io::Cursor::new(Vec::new());
+                    source
+                        .write_to(&mut output, format)
+                        .expect("encode test image");
+                    output.into_inner()
+                }
+            };
+            let output = insert_exif(output, &output_format, &tiff, source.dimensions());
+            let output = insert_xmp(output, &output_format, &xmp, source.dimensions());
+            assert_eq!(extract_exif(&output, format), Some(tiff.clone()));
+            assert_eq!(extract_xmp(&output, format), Some(xmp.clone()));
+            assert!(!has_icc_profile(&output, format));
+            // IN TWO APP2 SEGMENTS OF JPEG
+            let icc = (0..70_000).map(|x| (x * 7 % 251) as u8).collect::<Vec<_>>();
+            let output = insert_icc(output, &output_format, &icc, source.dimensions());
+            assert_eq!(extract_icc(&output, format), Some(icc));
+            assert_eq!(extract_exif(&output, format), Some(tiff.clone()));
+            let decoded = match format {
+                ImageFormat::WebP => crate::codec::webp::decode::decode(&output).expect("decode webp"),
+                _ => image::load_from_memory_with_format(&output, format).expect("decode"),
+            };
+            assert_eq!(decoded.dimensions(), source.dimensions());
+            if format == ImageFormat::Jpeg {
+                let stripped = jpeg_strip_metadata(&output).expect("strip jpeg");
+                assert_eq!((extract_exif(&stripped, format), extract_xmp(&stripped, format)), (None, None));
+                let pixels = image::load_from_memory(&stripped).expect("decode stripped");
+                assert_eq!(pixels.to_rgb8(), decoded.to_rgb8());
+            }
+        }
+        assert_eq!(jpeg_strip_metadata(b"\xFF\xD8\xFF"), None);
+    }
+}
@@ -0,0 +1,473 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Reading and rewriting EXIF (TIFF structured) payloads.
+use image::{DynamicImage, GenericImageView};
+
+use super::{ExifIfd, PrivacyPolicy};
+use crate::data::Resolution;
+
+const EXIF_IFD_POINTER: u16 = 0x8769;
+const JPEG_INTERCHANGE_FORMAT: u16 = 0x0201;
+const ORIENTATION: u16 = 0x0112;
+
+/// The thumbnail fits this, as in DCF (the camera file system standard).
+pub const THUMBNAIL_SIZE: (u32, u32) = (160, 120);
+const THUMBNAIL_QUALITY: u8 = 75;
+/// Keeps the EXIF within one JPEG APP1 segment (64 KiB).
+const MAX_THUMBNAIL_LEN: usize = 48 * 1024;
+
+///////////////////////////////////////////////////////////////////////////////
+// BYTE ORDER
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ByteOrder {
+    Little,
+    Big,
+}
+
+impl ByteOrder {
+    fn u16(self, bytes: &[u8], offset: usize) -> Option<u16> {
+        let bytes: [u8; 2] = bytes.get(offset..offset + 2)?.try_into().ok()?;
+        Some(match self {
+            Self::Little => u16::from_le_bytes(bytes),
+            Self::Big => u16::from_be_bytes(bytes),
+        })
+    }
+    fn u32(self, bytes: &[u8], offset: usize) -> Option<u32> {
+        let bytes: [u8; 4] = bytes.get(offset..offset + 4)?.try_into().ok()?;
+        Some(match self {
+            Self::Little => u32::from_le_bytes(bytes),
+            Self::Big => u32::from_be_bytes(bytes),
+        })
+    }
+    fn put_u16(self, output: &mut Vec<u8>, value: u16) {
+        match self {
+            Self::Little => output.extend_from_slice(&value.to_le_bytes()),
+            Self::Big => output.extend_from_slice(&value.to_be_bytes()),
+        }
+    }
+    fn put_u32(self, output: &mut Vec<u8>, value: u32) {
+        match self {
+            Self::Little => output.extend_from_slice(&value.to_le_bytes()),
+            Self::Big => output.extend_from_slice(&value.to_be_bytes()),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ENTRIES
+///////////////////////////////////////////////////////////////////////////////
+
+/// An IFD entry; `data` is the raw value, in the payload’s byte order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub tag: u16,
+    pub kind: u16,
+    pub count: u32,
+    pub data: Vec<u8>,
+}
+
+fn type_size(kind: u16) -> Option<usize> {
+    match kind {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 => Some(4),
+        5 | 10 | 12 => Some(8),
+        _ => None,
+    }
+}
+
+/// The IFD0 and Exif sub-IFD entries of a TIFF payload. Other IFDs (GPS,
+/// interoperability, the thumbnail’s IFD1) are never read; a thumbnail
+/// can be written, though.
+#[derive(Debug, Clone)]
+pub struct Exif {
+    order: ByteOrder,
+    pub primary: Vec<Entry>,
+    pub exif: Vec<Entry>,
+    /// A JPEG, written as IFD1.
+    pub thumbnail: Option<Vec<u8>>,
+}
+
+impl Exif {
+    pub fn parse(tiff: &[u8]) -> Option<Self> {
+        let order = match tiff.get(0..4)? {
+            b"II*\0" => ByteOrder::Little,
+            b"MM\0*" => ByteOrder::Big,
+            _ => return None,
+        };
+        let primary = read_ifd(tiff, order, order.u32(tiff, 4)? as usize)?;
+        let exif = primary
+            .iter()
+            .find(|x| x.tag == EXIF_IFD_POINTER && x.data.len() == 4)
+            .and_then(|x| read_ifd(tiff, order, order.u32(&x.data, 0)? as usize))
+            .unwrap_or_default();
+        Some(Exif {
+            order,
+            primary,
+            exif,
+            thumbnail: None,
+        })
+    }
+    /// Without entries, in little endian order.
+    pub fn empty() -> Self {
+        Exif {
+            order: ByteOrder::Little,
+            primary: Vec::new(),
+            exif: Vec::new(),
+            thumbnail: None,
+        }
+    }
+    pub fn get(&self, ifd: ExifIfd, tag: u16) -> Option<&Entry> {
+        let entries = match ifd {
+            ExifIfd::Primary => &self.primary,
+            ExifIfd::Exif => &self.exif,
+        };
+        entries.iter().find(|x| x.tag == tag)
+    }
+    pub fn retain<F: Fn(ExifIfd, u16) -> bool>(&mut self, keep: F) {
+        self.primary.retain(|x| keep(ExifIfd::Primary, x.tag));
+        self.exif.retain(|x| keep(ExifIfd::Exif, x.tag));
+    }
+    pub fn is_empty(&self) -> bool {
+        self.primary.is_empty() && self.exif.is_empty()
+    }
+    /// Serializes a fresh TIFF payload (IFD0 and, if needed, the Exif IFD
+    /// and the thumbnail’s IFD1).
+    pub fn to_tiff(&self) -> Vec<u8> {
+        let order = self.order;
+        let mut primary = self
+            .primary
+            .iter()
+            .filter(|x| x.tag != EXIF_IFD_POINTER)
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut exif = self.exif.clone();
+        exif.sort_by_key(|x| x.tag);
+        if !exif.is_empty() {
+            // PATCHED ONCE THE EXIF IFD IS PLACED
+            primary.push(Entry {
+                tag: EXIF_IFD_POINTER,
+                kind: 4,
+                count: 1,
+                data: vec![0; 4],
+            });
+        }
+        primary.sort_by_key(|x| x.tag);
+        let mut output = match order {
+            ByteOrder::Little => b"II*\0".to_vec(),
+            ByteOrder::Big => b"MM\0*".to_vec(),
+        };
+        order.put_u32(&mut output, 8);
+        let pointer_at = write_ifd(&mut output, order, &primary, EXIF_IFD_POINTER);
+        if let (false, Some(pointer_at)) = (exif.is_empty(), pointer_at) {
+            let offset = output.len() as u32;
+            let mut patch = Vec::new();
+            order.put_u32(&mut patch, offset);
+            output[pointer_at..pointer_at + 4].copy_from_slice(&patch);
+            write_ifd(&mut output, order, &exif, 0);
+        }
+        if let Some(thumbnail) = &self.thumbnail {
+            // IFD0’S NEXT IFD OFFSET, AFTER ITS ENTRIES
+            let next_at = 8 + 2 + primary.len() * 12;
+            let mut patch = Vec::new();
+            order.put_u32(&mut patch, output.len() as u32);
+            output[next_at..next_at + 4].copy_from_slice(&patch);
+            let entries = thumbnail_entries(order, thumbnail.len());
+            let pointer_at = write_ifd(&mut output, order, &entries, JPEG_INTERCHANGE_FORMAT)
+                .expect("thumbnail offset entry");
+            let mut patch = Vec::new();
+            order.put_u32(&mut patch, output.len() as u32);
+            output[pointer_at..pointer_at + 4].copy_from_slice(&patch);
+            output.extend_from_slice(thumbnail);
+        }
+        output
+    }
+}
+
+fn read_ifd(tiff: &[u8], order: ByteOrder, offset: usize) -> Option<Vec<Entry>> {
+    let count = order.u16(tiff, offset)? as usize;
+    let mut entries = Vec::with_capacity(count);
+    for ix in 0..count {
+        let at = offset + 2 + ix * 12;
+        let tag = order.u16(tiff, at)?;
+        let kind = order.u16(tiff, at + 2)?;
+        let count = order.u32(tiff, at + 4)?;
+        // SKIP UNKNOWN TYPES, RATHER THAN GUESSING THEIR SIZE
+        let size = match type_size(kind).and_then(|x| x.checked_mul(count as usize)) {
+            Some(x) => x,
+            None => continue,
+        };
+        let data = if size <= 4 {
+            tiff.get(at + 8..at + 8 + size)?
+        } else {
+            let value_at = order.u32(tiff, at + 8)? as usize;
+            match tiff.get(value_at..value_at.saturating_add(size)) {
+                Some(x) => x,
+                None => continue,
+            }
+        };
+        entries.push(Entry {
+            tag,
+            kind,
+            count,
+            data: data.to_vec(),
+        });
+    }
+    Some(entries)
+}
+
+/// IFD1: JPEG compressed, at 72 DPI, with the offset patched once placed.
+fn thumbnail_entries(order: ByteOrder, len: usize) -> Vec<Entry> {
+    let value = |tag: u16, kind: u16, values: &[u32]| {
+        let mut data = Vec::new();
+        for value in values {
+            match kind {
+                3 => order.put_u16(&mut data, *value as u16),
+                _ => order.put_u32(&mut data, *value),
+            }
+        }
+        let count = if kind == 5 { values.len() / 2 } else { values.len() };
+        Entry {
+            tag,
+            kind,
+            count: count as u32,
+            data,
+        }
+    };
+    vec![
+        // COMPRESSION: JPEG
+        value(0x0103, 3, &[6]),
+        value(0x011A, 5, &[72, 1]),
+        value(0x011B, 5, &[72, 1]),
+        // RESOLUTION UNIT: INCHES
+        value(0x0128, 3, &[2]),
+        value(JPEG_INTERCHANGE_FORMAT, 4, &[0]),
+        value(0x0202, 4, &[len as u32]),
+    ]
+}
+
+/// Appends an IFD (at the current, even, position) followed by its
+/// out-of-line values. Returns the position of the `pointer_tag` value.
+fn write_ifd(output: &mut Vec<u8>, order: ByteOrder, entries: &[Entry], pointer_tag: u16) -> Option<usize> {
+    let start = output.len();
+    let mut data_at = start + 2 + entries.len() * 12 + 4;
+    let mut pointer_at = None;
+    let mut values = Vec::new();
+    order.put_u16(output, entries.len() as u16);
+    for entry in entries {
+        order.put_u16(output, entry.tag);
+        order.put_u16(output, entry.kind);
+        order.put_u32(output, entry.count);
+        if entry.tag == pointer_tag {
+            pointer_at = Some(output.len());
+        }
+        if entry.data.len() <= 4 {
+            let mut inline = entry.data.clone();
+            inline.resize(4, 0);
+            output.extend_from_slice(&inline);
+        } else {
+            order.put_u32(output, data_at as u32);
+            values.extend_from_slice(&entry.data);
+            if entry.data.len() % 2 == 1 {
+                values.push(0);
+            }
+            data_at += entry.data.len() + entry.data.len() % 2;
+        }
+    }
+    // NO NEXT IFD, UNLESS PATCHED
+    order.put_u32(output, 0);
+    output.extend_from_slice(&values);
+    pointer_at
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// SANITIZE
+///////////////////////////////////////////////////////////////////////////////
+
+/// Rewrites a TIFF payload keeping only what the policy allows. Returns
+/// `None` when nothing is left (or the payload is malformed).
+pub fn sanitize(tiff: &[u8], policy: &PrivacyPolicy) -> Option<Vec<u8>> {
+    let mut exif = Exif::parse(tiff)?;
+    exif.retain(|ifd, tag| policy.allows(ifd, tag));
+    if exif.is_empty() {
+        return None;
+    }
+    Some(exif.to_tiff())
+}
+
+/// Just the orientation, unless it’s the default (1), which sources keep
+/// under every policy: their pixels don’t have it applied.
+pub fn orientation_only(tiff: &[u8]) -> Option<Vec<u8>> {
+    let mut exif = Exif::parse(tiff)?;
+    if orientation(tiff)? == 1 {
+        return None;
+    }
+    exif.retain(|ifd, tag| ifd == ExifIfd::Primary && tag == ORIENTATION);
+    Some(exif.to_tiff())
+}
+
+/// Without the orientation, i.e. with the default one, for pixels that have
+/// it applied; `None` when nothing is left.
+pub fn without_orientation(tiff: &[u8]) -> Option<Vec<u8>> {
+    let mut exif = Exif::parse(tiff)?;
+    exif.retain(|ifd, tag| !(ifd == ExifIfd::Primary && tag == ORIENTATION));
+    if exif.is_empty() {
+        return None;
+    }
+    Some(exif.to_tiff())
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ORIENTATION
+///////////////////////////////////////////////////////////////////////////////
+
+/// The orientation (1-8) of the payload; none if missing or invalid.
+pub fn orientation(tiff: &[u8]) -> Option<u16> {
+    let exif = Exif::parse(tiff)?;
+    let orientation = exif.order.u16(&exif.get(ExifIfd::Primary, ORIENTATION)?.data, 0)?;
+    Some(orientation).filter(|x| (1..=8).contains(x))
+}
+
+/// The image as displayed with the orientation; orientations 5 to 8 swap
+/// the width and height.
+pub fn orient(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        // TRANSPOSE
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        // TRANSVERSE
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// Of `orient`, i.e. orienting with it undoes the orientation.
+pub fn inverse_orientation(orientation: u16) -> u16 {
+    match orientation {
+        6 => 8,
+        8 => 6,
+        x => x,
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// THUMBNAILS
+///////////////////////////////////////////////////////////////////////////////
+
+/// A JPEG of the image fitting `THUMBNAIL_SIZE`; none if it wouldn’t fit in
+/// the EXIF.
+pub fn encode_thumbnail(image: &DynamicImage) -> Option<Vec<u8>> {
+    let (max_width, max_height) = THUMBNAIL_SIZE;
+    let (width, height) = image.dimensions();
+    let thumbnail = if width <= max_width && height <= max_height {
+        image.clone()
+    } else {
+        let target = crate::api::resize_dimensions((width, height), &Resolution::new(max_width, max_height));
+        crate::thumbnail::downscale(image, target)
+    };
+    #[cfg(not(feature = "pure-rust"))]
+    let output = unsafe { crate::codec::jpeg::encode(&thumbnail, THUMBNAIL_QUALITY) };
+    #[cfg(feature = "pure-rust")]
+    let output = crate::codec::jpeg::encode_in_rust(&thumbnail, THUMBNAIL_QUALITY);
+    Some(output).filter(|x| x.len() <= MAX_THUMBNAIL_LEN)
+}
+
+/// The (sanitized) payload with the thumbnail; a payload of just the
+/// thumbnail if none.
+pub fn with_thumbnail(tiff: Option<&[u8]>, thumbnail: Vec<u8>) -> Vec<u8> {
+    let mut exif = tiff.and_then(Exif::parse).unwrap_or_else(Exif::empty);
+    exif.thumbnail = Some(thumbnail);
+    exif.to_tiff()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    fn ascii(tag: u16, value: &str) -> Entry {
+        let mut data = value.as_bytes().to_vec();
+        data.push(0);
+        Entry {
+            tag,
+            kind: 2,
+            count: data.len() as u32,
+            data,
+        }
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let mut gps_pointer = ascii(0x8825, "");
+        gps_pointer.kind = 4;
+        gps_pointer.count = 1;
+        gps_pointer.data = vec![8, 0, 0, 0];
+        let source = Exif {
+            order: ByteOrder::Little,
+            primary: vec![
+                ascii(0x0110, "Camera Model"),
+                Entry {
+                    tag: 0x0112,
+                    kind: 3,
+                    count: 1,
+                    data: vec![6, 0],
+                },
+                gps_pointer,
+            ],
+            exif: vec![ascii(0xA431, "SERIAL-1234"), ascii(0x9003, "2019:11:02 10:00:00")],
+            thumbnail: None,
+        }
+        .to_tiff();
+        let policy = PrivacyPolicy::from_str("camera").expect("policy");
+        let output = Exif::parse(&sanitize(&source, &policy).expect("sanitize")).expect("parse");
+        assert_eq!(output.get(ExifIfd::Primary, 0x0110), Some(&ascii(0x0110, "Camera Model")));
+        assert_eq!(output.get(ExifIfd::Primary, 0x0112).map(|x| x.data.clone()), Some(vec![6, 0]));
+        assert!(output.get(ExifIfd::Primary, 0x8825).is_none());
+        assert!(output.exif.is_empty());
+        assert!(sanitize(&source, &PrivacyPolicy::default()).is_none());
+        let rotated = Exif::parse(&orientation_only(&source).expect("orientation")).expect("parse");
+        assert_eq!((rotated.primary.len(), rotated.primary[0].data.clone()), (1, vec![6, 0]));
+        assert!(orientation_only(&Exif::empty().to_tiff()).is_none());
+        assert_eq!(orientation(&source), Some(6));
+        let upright = without_orientation(&source).expect("without orientation");
+        assert_eq!(orientation(&upright), None);
+        assert!(without_orientation(&orientation_only(&source).expect("orientation")).is_none());
+        // 2x1: RED, THEN BLUE
+        let mut pixels = image::RgbImage::new(2, 1);
+        pixels.put_pixel(1, 0, image::Rgb([0, 0, 255]));
+        pixels.put_pixel(0, 0, image::Rgb([255, 0, 0]));
+        let pixels = DynamicImage::ImageRgb8(pixels);
+        for orientation in 1..=8 {
+            let oriented = orient(pixels.clone(), orientation);
+            let swapped = orientation >= 5;
+            assert_eq!(oriented.dimensions(), if swapped { (1, 2) } else { (2, 1) });
+            let restored = orient(oriented, inverse_orientation(orientation));
+            assert_eq!(restored.to_rgb8(), pixels.to_rgb8());
+        }
+        // ROTATED CLOCKWISE, RED ON TOP
+        assert_eq!(orient(pixels.clone(), 6).to_rgb8().get_pixel(0, 0), &image::Rgb([255, 0, 0]));
+        assert_eq!(orient(pixels.clone(), 5).to_rgb8().get_pixel(0, 0), &image::Rgb([255, 0, 0]));
+        assert_eq!(orient(pixels.clone(), 7).to_rgb8().get_pixel(0, 0), &image::Rgb([0, 0, 255]));
+        // IFD1 POINTS AT THE THUMBNAIL, AFTER IFD0 (KEPT AS IS)
+        let image = DynamicImage::new_rgb8(640, 360);
+        let thumbnail = encode_thumbnail(&image).expect("thumbnail");
+        let decoded = image::load_from_memory(&thumbnail).expect("decode thumbnail");
+        assert_eq!(decoded.dimensions(), (160, 90));
+        let tiff = with_thumbnail(Some(&source), thumbnail.clone());
+        // INCLUDING THE GPS AND EXIF IFD POINTERS
+        assert_eq!(Exif::parse(&tiff).expect("parse").primary.len(), 4);
+        let order = ByteOrder::Little;
+        let ifd1 = order.u32(&tiff, 8 + 2 + 4 * 12).unwrap() as usize;
+        let entries = read_ifd(&tiff, order, ifd1).expect("ifd1");
+        let value = |tag| order.u32(&entries.iter().find(|x| x.tag == tag).unwrap().data, 0).unwrap() as usize;
+        let (offset, len) = (value(JPEG_INTERCHANGE_FORMAT), value(0x0202));
+        assert_eq!(&tiff[offset..offset + len], &thumbnail[..]);
+    }
+}
@@ -0,0 +1,85 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Source metadata, and what of it reaches the outputs.
+//!
+//! Outputs are encoded from decoded pixels, so nothing is carried over
+//! unless a `PrivacyPolicy` allows it (but for a non-default orientation,
+//! unless the pixels have it applied); see `imager_core::meta`. An
+//! `Attribution` may be added to every output regardless.
+pub mod c2pa;
+pub mod container;
+pub mod exif;
+pub mod xmp;
+
+pub use imager_core::meta::{Attribution, ExifIfd, PrivacyPolicy};
+
+use image::ImageFormat;
+
+use crate::data::OutputFormat;
+
+/// The metadata payloads of a source.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMetadata {
+    /// TIFF structured.
+    pub exif: Option<Vec<u8>>,
+    pub icc_profile: Option<Vec<u8>>,
+    pub xmp: Option<Vec<u8>>,
+}
+
+impl SourceMetadata {
+    pub fn extract(source: &[u8], format: ImageFormat) -> Self {
+        SourceMetadata {
+            exif: container::extract_exif(source, format),
+            icc_profile: container::extract_icc(source, format),
+            xmp: container::extract_xmp(source, format),
+        }
+    }
+}
+
+/// Copies the policy-approved part of the source metadata into the output,
+/// with the `thumbnail` (see `exif::encode_thumbnail`) in the EXIF if
+/// given.
+pub fn apply_privacy_policy(
+    encoded: Vec<u8>,
+    format: &OutputFormat,
+    source: &SourceMetadata,
+    policy: &PrivacyPolicy,
+    dimensions: (u32, u32),
+    thumbnail: Option<Vec<u8>>,
+) -> Vec<u8> {
+    let tiff = source
+        .exif
+        .as_deref()
+        .and_then(|x| exif::sanitize(x, policy).or_else(|| exif::orientation_only(x)));
+    let tiff = match thumbnail {
+        Some(thumbnail) => Some(exif::with_thumbnail(tiff.as_deref(), thumbnail)),
+        None => tiff,
+    };
+    let encoded = match tiff {
+        Some(tiff) => container::insert_exif(encoded, format, &tiff, dimensions),
+        None => encoded,
+    };
+    let encoded = match source.icc_profile.as_deref().filter(|_| policy.keep_icc) {
+        Some(profile) => container::insert_icc(encoded, format, profile, dimensions),
+        None => encoded,
+    };
+    match source.xmp.as_deref().filter(|_| policy.keep_xmp) {
+        Some(packet) => container::insert_xmp(encoded, format, packet, dimensions),
+        None => encoded,
+    }
+}
+
+/// Writes the attribution (if any) into the output, as XMP.
+pub fn apply_attribution(
+    encoded: Vec<u8>,
+    format: &OutputFormat,
+    attribution: &Attribution,
+    dimensions: (u32, u32),
+) -> Vec<u8> {
+    if attribution.is_empty() {
+        return encoded;
+    }
+    let packet = xmp::attribution_packet(attribution);
+    container::insert_xmp(encoded, format, packet.as_bytes(), dimensions)
+}
@@ -0,0 +1,99 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Writing XMP packets.
+use super::Attribution;
+use crate::provenance::Provenance;
+
+/// Of imager’s own properties (see `provenance_packet`).
+pub const IMAGER_NAMESPACE: &str = "https://github.com/sycured/imager/ns/1.0/";
+
+fn escape(value: &str) -> String {
+    let mut output = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            _ => output.push(c),
+        }
+    }
+    output
+}
+
+/// A standalone XMP packet with the (IPTC Core) attribution properties.
+pub fn attribution_packet(attribution: &Attribution) -> String {
+    let mut properties = String::new();
+    if let Some(creator) = &attribution.creator {
+        properties.push_str(&format!(
+            "   <dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>\n",
+            escape(creator)
+        ));
+    }
+    if let Some(copyright) = &attribution.copyright {
+        properties.push_str(&format!(
+            "   <dc:rights><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:rights>\n",
+            escape(copyright)
+        ));
+        properties.push_str("   <xmpRights:Marked>True</xmpRights:Marked>\n");
+    }
+    if let Some(license_url) = &attribution.license_url {
+        properties.push_str(&format!(
+            "   <xmpRights:WebStatement>{}</xmpRights:WebStatement>\n",
+            escape(license_url)
+        ));
+        properties.push_str(&format!(
+            "   <cc:license rdf:resource=\"{}\"/>\n",
+            escape(license_url)
+        ));
+    }
+    packet(
+        concat!(
+            "    xmlns:dc=\"http://purl.org/dc/elements/1.1/\"\n",
+            "    xmlns:xmpRights=\"http://ns.adobe.com/xap/1.0/rights/\"\n",
+            "    xmlns:cc=\"http://creativecommons.org/ns#\"",
+        ),
+        &properties,
+    )
+}
+
+/// A standalone XMP packet with the provenance’s digests and versions (the
+/// parameters themselves are only in the sidecar).
+pub fn provenance_packet(provenance: &Provenance) -> String {
+    let mut properties = String::new();
+    let mut push = |name: &str, value: &str| {
+        properties.push_str(&format!("   <imager:{}>{}</imager:{}>\n", name, escape(value), name));
+    };
+    push("SourceSHA256", &provenance.source_sha256);
+    push("PipelineSHA256", &provenance.pipeline_sha256);
+    if let Some(versions) = &provenance.versions {
+        push("Version", &versions.imager);
+        let encoder = match &versions.encoder_version {
+            Some(version) => format!("{} {}", versions.encoder, version),
+            None => versions.encoder.clone(),
+        };
+        push("Encoder", &encoder);
+    }
+    packet(&format!("    xmlns:imager=\"{}\"", IMAGER_NAMESPACE), &properties)
+}
+
+/// Of the namespace declarations (attributes of the description) and the
+/// properties.
+fn packet(namespaces: &str, properties: &str) -> String {
+    format!(
+        concat!(
+            "<?xpacket begin=\"\u{FEFF}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n",
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n",
+            " <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n",
+            "  <rdf:Description rdf:about=\"\"\n",
+            "{}>\n",
+            "{}",
+            "  </rdf:Description>\n",
+            " </rdf:RDF>\n",
+            "</x:xmpmeta>\n",
+            "<?xpacket end=\"r\"?>",
+        ),
+        namespaces, properties
+    )
+}
@@ -0,0 +1,207 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Contact sheets (`imager montage`): a grid of images, each fit to the
+//! cell size (downscaled like outputs are, never enlarged) and labeled,
+//! e.g. for reviewing a batch, or the frames of a video at a glance.
+//!
+//! Also A/B comparisons (`imager stitch`): two images next to each other,
+//! or one above the other, at full size and labeled.
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use std::str::FromStr;
+
+use crate::data::Resolution;
+
+/// Around and between cells.
+const PADDING: u32 = 8;
+const BACKGROUND: Rgba<u8> = Rgba([244, 244, 244, 255]);
+const LABEL_COLOR: Rgba<u8> = Rgba([34, 34, 34, 255]);
+
+#[derive(Debug, Clone)]
+pub struct Montage {
+    /// The largest size of each image.
+    pub cell: Resolution,
+    /// Defaults to a (roughly) square grid.
+    pub columns: Option<u32>,
+    /// Label each image (e.g. with its file name).
+    pub labels: bool,
+}
+
+impl Default for Montage {
+    fn default() -> Self {
+        Montage {
+            cell: Resolution::new(256, 256),
+            columns: None,
+            labels: true,
+        }
+    }
+}
+
+/// Larger labels below wider images.
+fn label_scale(width: u32) -> u32 {
+    (width / 128).clamp(1, 4)
+}
+
+fn label_height(width: u32) -> u32 {
+    crate::text::GLYPH_HEIGHT * label_scale(width) + PADDING / 2
+}
+
+/// Centered below the image, at `left`, `top`, of `width`.
+fn draw_label(sheet: &mut RgbaImage, (left, top): (u32, u32), width: u32, label: &str) {
+    let scale = label_scale(width);
+    let label = crate::text::fit(label, width, scale);
+    let x = left + (width - crate::text::width(&label, scale)) / 2;
+    crate::text::draw(sheet, (x, top + PADDING / 2), &label, scale, LABEL_COLOR);
+}
+
+impl Montage {
+    fn label_height(&self) -> u32 {
+        if self.labels {
+            label_height(self.cell.width)
+        } else {
+            0
+        }
+    }
+    /// The sheet, of the images in order, with their labels.
+    pub fn render(&self, images: &[(String, DynamicImage)]) -> Result<DynamicImage, String> {
+        if images.is_empty() {
+            return Err(String::from("no images"));
+        }
+        if self.cell.width == 0 || self.cell.height == 0 {
+            return Err(String::from("empty cell size"));
+        }
+        let count = images.len() as u32;
+        let columns = self
+            .columns
+            .unwrap_or_else(|| (count as f64).sqrt().ceil() as u32)
+            .clamp(1, count);
+        let rows = count.div_ceil(columns);
+        let (cell_width, cell_height) = (self.cell.width, self.cell.height + self.label_height());
+        let mut sheet = RgbaImage::from_pixel(
+            PADDING + columns * (cell_width + PADDING),
+            PADDING + rows * (cell_height + PADDING),
+            BACKGROUND,
+        );
+        for (ix, (label, image)) in images.iter().enumerate() {
+            let (column, row) = (ix as u32 % columns, ix as u32 / columns);
+            let (left, top) = (
+                PADDING + column * (cell_width + PADDING),
+                PADDING + row * (cell_height + PADDING),
+            );
+            let fitted = fit(image, &self.cell);
+            let (width, height) = fitted.dimensions();
+            // CENTERED, WITH TRANSPARENCY OVER THE BACKGROUND
+            image::imageops::overlay(
+                &mut sheet,
+                &fitted.to_rgba8(),
+                (left + (self.cell.width - width) / 2) as i64,
+                (top + (self.cell.height - height) / 2) as i64,
+            );
+            if self.labels {
+                draw_label(&mut sheet, (left, top + self.cell.height), self.cell.width, label);
+            }
+        }
+        Ok(DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(sheet).to_rgb8()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    SideBySide,
+    TopBottom,
+}
+
+impl FromStr for Layout {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "side-by-side" => Ok(Layout::SideBySide),
+            "top-bottom" => Ok(Layout::TopBottom),
+            _ => Err(format!("Unknown layout {}", s)),
+        }
+    }
+}
+
+/// The two images, labeled, for comparison. The second is resized to the
+/// first’s dimensions (if they differ, e.g. for downscaled outputs), so
+/// details line up.
+pub fn stitch(
+    (label_a, a): (&str, &DynamicImage),
+    (label_b, b): (&str, &DynamicImage),
+    layout: Layout,
+) -> DynamicImage {
+    let (width, height) = a.dimensions();
+    let b = if b.dimensions() == (width, height) {
+        b.clone()
+    } else {
+        b.resize_exact(width, height, image::imageops::FilterType::Lanczos3)
+    };
+    let panel_height = height + label_height(width);
+    let (sheet_width, sheet_height, offset) = match layout {
+        Layout::SideBySide => (3 * PADDING + 2 * width, 2 * PADDING + panel_height, (width + PADDING, 0)),
+        Layout::TopBottom => (2 * PADDING + width, 3 * PADDING + 2 * panel_height, (0, panel_height + PADDING)),
+    };
+    let mut sheet = RgbaImage::from_pixel(sheet_width, sheet_height, BACKGROUND);
+    for (ix, (label, image)) in [(label_a, a), (label_b, &b)].into_iter().enumerate() {
+        let (left, top) = (PADDING + ix as u32 * offset.0, PADDING + ix as u32 * offset.1);
+        image::imageops::overlay(&mut sheet, &image.to_rgba8(), left as i64, top as i64);
+        draw_label(&mut sheet, (left, top + height), width, label);
+    }
+    DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(sheet).to_rgb8())
+}
+
+fn fit(image: &DynamicImage, cell: &Resolution) -> DynamicImage {
+    let dimensions = image.dimensions();
+    if dimensions.0 <= cell.width && dimensions.1 <= cell.height {
+        return image.clone();
+    }
+    let target = crate::api::resize_dimensions(dimensions, cell);
+    if crate::thumbnail::is_tiny(target) {
+        crate::thumbnail::downscale(image, target)
+    } else {
+        image.resize(cell.width, cell.height, image::imageops::FilterType::Lanczos3)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_montage() {
+        let source = image::load_from_memory(include_bytes!("../assets/test/1.jpeg")).expect("decode");
+        let small = DynamicImage::ImageRgba8(RgbaImage::new(10, 20));
+        let images: Vec<_> = vec![("1.jpeg", source.clone()), ("small", small), ("2.jpeg", source)]
+            .into_iter()
+            .map(|(label, image)| (label.to_owned(), image))
+            .collect();
+        let montage = Montage {
+            cell: Resolution::new(100, 80),
+            columns: None,
+            labels: true,
+        };
+        let sheet = montage.render(&images).expect("render");
+        // 2x2, LABELS 7PX HIGH, PLUS 4PX SPACING
+        assert_eq!(sheet.dimensions(), (8 + 2 * 108, 8 + 2 * (80 + 11 + 8)));
+        let unlabeled = Montage {
+            columns: Some(3),
+            labels: false,
+            ..montage
+        };
+        assert_eq!(unlabeled.render(&images).expect("render").dimensions(), (8 + 3 * 108, 8 + 88));
+        assert!(unlabeled.render(&[]).is_err());
+    }
+
+    #[test]
+    fn test_stitch() {
+        let a = DynamicImage::ImageRgba8(RgbaImage::from_pixel(200, 100, Rgba([255, 0, 0, 255])));
+        let b = DynamicImage::ImageRgba8(RgbaImage::new(100, 50));
+        let sheet = stitch(("a", &a), ("b", &b), Layout::SideBySide);
+        // LABELS 7PX HIGH, PLUS 4PX SPACING
+        assert_eq!(sheet.dimensions(), (8 + 2 * 208, 16 + 111));
+        assert_eq!(sheet.get_pixel(8, 8), Rgba([255, 0, 0, 255]));
+        let sheet = stitch(("a", &a), ("b", &b), Layout::TopBottom);
+        assert_eq!(sheet.dimensions(), (216, 8 + 2 * 119));
+        assert_eq!("top-bottom".parse(), Ok(Layout::TopBottom));
+    }
+}
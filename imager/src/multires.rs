@@ -0,0 +1,193 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Multi-resolution containers (`imager pack`), for app packaging: one
+//! source, resized to every size a platform expects, in one file.
+//!
+//! - ICNS (macOS): PNG entries from 16 to 1024 pixels, each also stored as
+//!   the `@2x` (Retina) entry of half its size.
+//! - ICO (Windows): PNG entries up to 256 pixels.
+//! - HEIF: the source at full size, with embedded thumbnails (see
+//!   `codec::heif`).
+//!
+//! Icons are square; other sources are fit, centered, on transparency.
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::error::{ImagerError, Result};
+
+/// Of HEIF primary images and thumbnails; there’s no quality search.
+pub const HEIF_QUALITY: u8 = 85;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Container {
+    Icns,
+    Ico,
+    Heif,
+}
+
+impl FromStr for Container {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "icns" => Ok(Container::Icns),
+            "ico" => Ok(Container::Ico),
+            "heif" | "heic" => Ok(Container::Heif),
+            _ => Err(format!("Unknown container {}", s)),
+        }
+    }
+}
+
+impl Container {
+    pub fn infer_from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let ext = path.as_ref().extension()?.to_str()?;
+        Self::from_str(ext).ok()
+    }
+    /// Icon sizes, or (for HEIF) thumbnail sizes, in pixels.
+    pub fn default_sizes(&self) -> &'static [u32] {
+        match self {
+            Container::Icns => &[16, 32, 64, 128, 256, 512, 1024],
+            Container::Ico => &[16, 24, 32, 48, 64, 128, 256],
+            Container::Heif => &[320],
+        }
+    }
+}
+
+pub fn write(source: &DynamicImage, container: Container, sizes: &[u32]) -> Result<Vec<u8>> {
+    if sizes.is_empty() {
+        return Err(ImagerError::InvalidInput(String::from("no sizes given")));
+    }
+    match container {
+        Container::Icns => icns(source, sizes),
+        Container::Ico => ico(source, sizes),
+        Container::Heif => crate::codec::heif::encode(source, sizes, HEIF_QUALITY),
+    }
+}
+
+/// The source fit in a `size` square, centered on transparency.
+fn square(source: &DynamicImage, size: u32) -> RgbaImage {
+    let resized = source.resize(size, size, FilterType::Lanczos3);
+    let (width, height) = resized.dimensions();
+    let mut output = RgbaImage::new(size, size);
+    image::imageops::overlay(
+        &mut output,
+        &resized.to_rgba8(),
+        i64::from((size - width) / 2),
+        i64::from((size - height) / 2),
+    );
+    output
+}
+
+fn png(source: &RgbaImage) -> Vec<u8> {
+    let mut output = std::io::Cursor::new(Vec::new());
+    source
+        .write_to(&mut output, image::ImageFormat::Png)
+        .expect("encode png");
+    output.into_inner()
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ICNS
+///////////////////////////////////////////////////////////////////////////////
+
+/// The entry types of a size, and of its `@2x` alias (if any).
+fn icns_types(size: u32) -> Option<(&'static [u8; 4], Option<&'static [u8; 4]>)> {
+    match size {
+        16 => Some((b"icp4", None)),
+        32 => Some((b"icp5", Some(b"ic11"))),
+        64 => Some((b"icp6", Some(b"ic12"))),
+        128 => Some((b"ic07", None)),
+        256 => Some((b"ic08", Some(b"ic13"))),
+        512 => Some((b"ic09", Some(b"ic14"))),
+        1024 => Some((b"ic10", None)),
+        _ => None,
+    }
+}
+
+fn icns(source: &DynamicImage, sizes: &[u32]) -> Result<Vec<u8>> {
+    let mut entries = Vec::new();
+    for size in sizes {
+        let (kind, retina) = icns_types(*size).ok_or_else(|| {
+            let message = format!("ICNS has no {}px icons; use 16, 32, 64, 128, 256, 512 or 1024", size);
+            ImagerError::InvalidInput(message)
+        })?;
+        let data = png(&square(source, *size));
+        if let Some(retina) = retina {
+            entries.push((retina, data.clone()));
+        }
+        entries.push((kind, data));
+    }
+    // LENGTHS INCLUDE THE 8 BYTE (TYPE AND LENGTH) HEADERS, BIG ENDIAN
+    let length = 8 + entries.iter().map(|(_, data)| 8 + data.len()).sum::<usize>();
+    let mut output = Vec::with_capacity(length);
+    output.extend_from_slice(b"icns");
+    output.extend_from_slice(&(length as u32).to_be_bytes());
+    for (kind, data) in entries {
+        output.extend_from_slice(kind);
+        output.extend_from_slice(&(8 + data.len() as u32).to_be_bytes());
+        output.extend_from_slice(&data);
+    }
+    Ok(output)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ICO
+///////////////////////////////////////////////////////////////////////////////
+
+fn ico(source: &DynamicImage, sizes: &[u32]) -> Result<Vec<u8>> {
+    if let Some(size) = sizes.iter().find(|x| **x == 0 || **x > 256) {
+        let message = format!("ICO icons are 1 to 256 pixels, not {}", size);
+        return Err(ImagerError::InvalidInput(message));
+    }
+    let images = sizes.iter().map(|size| png(&square(source, *size))).collect::<Vec<_>>();
+    // HEADER, THEN A 16 BYTE DIRECTORY ENTRY PER IMAGE, THEN THE IMAGES
+    let mut output = Vec::new();
+    output.extend_from_slice(&[0, 0, 1, 0]);
+    output.extend_from_slice(&(sizes.len() as u16).to_le_bytes());
+    let mut offset = 6 + 16 * sizes.len();
+    for (size, data) in sizes.iter().zip(&images) {
+        // 0 IS 256
+        let dimension = (*size % 256) as u8;
+        output.extend_from_slice(&[dimension, dimension, 0, 0]);
+        // COLOR PLANES, BITS PER PIXEL
+        output.extend_from_slice(&1u16.to_le_bytes());
+        output.extend_from_slice(&32u16.to_le_bytes());
+        output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        output.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += data.len();
+    }
+    for data in images {
+        output.extend_from_slice(&data);
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let source = DynamicImage::ImageRgba8(RgbaImage::from_pixel(40, 20, image::Rgba([255, 0, 0, 255])));
+        assert_eq!(Container::infer_from_path("app.ICNS"), Some(Container::Icns));
+        let ico = write(&source, Container::Ico, &[16, 256]).expect("ico");
+        assert_eq!(&ico[..6], &[0, 0, 1, 0, 2, 0]);
+        // THE 256 PIXEL ENTRY, AS 0
+        assert_eq!(ico[6 + 16], 0);
+        let second = u32::from_le_bytes(ico[6 + 16 + 12..6 + 32].try_into().unwrap()) as usize;
+        let icon = image::load_from_memory(&ico[second..]).expect("decode entry");
+        assert_eq!(icon.dimensions(), (256, 256));
+        // CENTERED, SO TRANSPARENT ABOVE AND BELOW
+        assert_eq!(icon.get_pixel(128, 0).0[3], 0);
+        assert_eq!(icon.get_pixel(128, 128).0, [255, 0, 0, 255]);
+        assert!(matches!(write(&source, Container::Ico, &[512]), Err(ImagerError::InvalidInput(_))));
+        let icns = write(&source, Container::Icns, &[16, 32]).expect("icns");
+        assert_eq!(u32::from_be_bytes(icns[4..8].try_into().unwrap()) as usize, icns.len());
+        assert_eq!(&icns[8..12], b"icp4");
+        let error = write(&source, Container::Icns, &[48]).unwrap_err();
+        assert!(error.to_string().starts_with("ICNS has no 48px"), "{}", error);
+    }
+}
@@ -0,0 +1,247 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Panorama stitching (`imager panorama`, with the `panorama` feature):
+//! overlapping images, in order (left to right, or top to bottom), combined
+//! into one before the usual optimization.
+//!
+//! Each image is registered against the previous one by a translation,
+//! found by the mean absolute difference of the overlap (at a reduced
+//! scale, then refined), so handheld drift is followed but lens distortion
+//! and rotation aren’t corrected; shots panned on a tripod, or scans, stitch
+//! best. Exposure differences are evened out by a gain per image, seams are
+//! feathered, and the result is cropped to the band every image covers.
+use image::{DynamicImage, GrayImage, Rgb, RgbImage};
+use rayon::prelude::*;
+
+/// The least overlap of neighbors, as a fraction of the width.
+const MIN_OVERLAP: f64 = 0.1;
+/// The most vertical drift of neighbors, as a fraction of the height.
+const MAX_DRIFT: f64 = 0.15;
+/// The coarse search’s images are at most this wide.
+const COARSE_WIDTH: u32 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    LeftToRight,
+    TopToBottom,
+}
+
+/// The images, stitched.
+pub fn stitch(images: &[DynamicImage], direction: Direction) -> Result<RgbImage, String> {
+    if images.len() < 2 {
+        return Err(String::from("a panorama needs at least two images"));
+    }
+    // TOP TO BOTTOM IS LEFT TO RIGHT, ROTATED
+    let images = images
+        .iter()
+        .map(|x| match direction {
+            Direction::LeftToRight => x.to_rgb8(),
+            Direction::TopToBottom => image::imageops::rotate270(&x.to_rgb8()),
+        })
+        .collect::<Vec<_>>();
+    let lumas = images
+        .iter()
+        .map(|x| DynamicImage::ImageRgb8(x.clone()).to_luma8())
+        .collect::<Vec<_>>();
+    let shifts = lumas
+        .par_windows(2)
+        .map(|pair| register(&pair[0], &pair[1]))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut positions = vec![(0, 0)];
+    for (dx, dy) in shifts {
+        let (x, y) = *positions.last().expect("positions");
+        positions.push((x + dx, y + dy));
+    }
+    let gains = gains(&lumas, &positions);
+    let output = blend(&images, &positions, &gains)?;
+    Ok(match direction {
+        Direction::LeftToRight => output,
+        Direction::TopToBottom => image::imageops::rotate90(&output),
+    })
+}
+
+/// The `(dx, dy)` of `next` relative to `previous`, i.e. `next(x, y)` best
+/// matches `previous(x + dx, y + dy)`.
+pub fn register(previous: &GrayImage, next: &GrayImage) -> Result<(i32, i32), String> {
+    let scale = previous.width().max(next.width()).div_ceil(COARSE_WIDTH);
+    let coarse = |x: &GrayImage| {
+        let (width, height) = ((x.width() / scale).max(1), (x.height() / scale).max(1));
+        image::imageops::resize(x, width, height, image::imageops::FilterType::Triangle)
+    };
+    let (small_previous, small_next) = (coarse(previous), coarse(next));
+    let (small_width, small_height) = small_previous.dimensions();
+    let max_dx = (f64::from(small_width) * (1.0 - MIN_OVERLAP)) as i32;
+    let max_dy = (f64::from(small_height.min(small_next.height())) * MAX_DRIFT).ceil() as i32;
+    let candidates = (-max_dy..=max_dy).flat_map(|dy| (1..=max_dx).map(move |dx| (dx, dy)));
+    let (dx, dy) = best_shift(&small_previous, &small_next, candidates, 1)
+        .ok_or("the images don’t overlap enough; they must be in order, and overlap by at least a tenth")?;
+    let scale = scale as i32;
+    let center = (dx * scale, dy * scale);
+    let refined = (-scale..=scale)
+        .flat_map(|dy| (-scale..=scale).map(move |dx| (center.0 + dx, center.1 + dy)))
+        .filter(|(dx, _)| *dx > 0);
+    Ok(best_shift(previous, next, refined, 2).unwrap_or(center))
+}
+
+/// Of the `candidates`, the shift of the lowest mean absolute difference,
+/// less the overlaps’ mean difference (so exposures needn’t match), sampling
+/// every `step`th pixel.
+fn best_shift(
+    previous: &GrayImage,
+    next: &GrayImage,
+    candidates: impl Iterator<Item = (i32, i32)>,
+    step: usize,
+) -> Option<(i32, i32)> {
+    let candidates = candidates.collect::<Vec<_>>();
+    candidates
+        .into_par_iter()
+        .filter_map(|shift| Some((cost(previous, next, shift, step)?, shift)))
+        .min_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(_, shift)| shift)
+}
+
+fn cost(previous: &GrayImage, next: &GrayImage, (dx, dy): (i32, i32), step: usize) -> Option<f64> {
+    let (x0, x1, y0, y1) = overlap(previous, next, (dx, dy))?;
+    if f64::from(x1 - x0) < f64::from(previous.width().min(next.width())) * MIN_OVERLAP
+        || f64::from(y1 - y0) < f64::from(previous.height().min(next.height())) * (1.0 - 2.0 * MAX_DRIFT)
+    {
+        return None;
+    }
+    // IN `next` COORDINATES
+    let pairs = || {
+        (y0..y1).step_by(step).flat_map(move |y| {
+            (x0..x1).step_by(step).map(move |x| {
+                let a = previous.get_pixel((x + dx) as u32, (y + dy) as u32).0[0];
+                let b = next.get_pixel(x as u32, y as u32).0[0];
+                (f64::from(a), f64::from(b))
+            })
+        })
+    };
+    let count = pairs().count() as f64;
+    let offset = pairs().map(|(a, b)| a - b).sum::<f64>() / count;
+    Some(pairs().map(|(a, b)| (a - b - offset).abs()).sum::<f64>() / count)
+}
+
+/// The overlap, as `x0..x1` and `y0..y1` in `next` coordinates.
+fn overlap<P: image::Pixel>(
+    previous: &image::ImageBuffer<P, Vec<P::Subpixel>>,
+    next: &image::ImageBuffer<P, Vec<P::Subpixel>>,
+    (dx, dy): (i32, i32),
+) -> Option<(i32, i32, i32, i32)> {
+    let (x0, x1) = (0.max(-dx), (next.width() as i32).min(previous.width() as i32 - dx));
+    let (y0, y1) = (0.max(-dy), (next.height() as i32).min(previous.height() as i32 - dy));
+    Some((x0, x1, y0, y1)).filter(|_| x0 < x1 && y0 < y1)
+}
+
+/// Per image, evening out the mean luma of overlaps, relative to their
+/// average (so the panorama is about as bright as its images).
+fn gains(lumas: &[GrayImage], positions: &[(i32, i32)]) -> Vec<f64> {
+    let mut gains = vec![1.0];
+    for (pair, position) in lumas.windows(2).zip(positions.windows(2)) {
+        let shift = (position[1].0 - position[0].0, position[1].1 - position[0].1);
+        let ratio = overlap(&pair[0], &pair[1], shift).map_or(1.0, |(x0, x1, y0, y1)| {
+            let (mut a, mut b) = (0.0, 0.0);
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    a += f64::from(pair[0].get_pixel((x + shift.0) as u32, (y + shift.1) as u32).0[0]);
+                    b += f64::from(pair[1].get_pixel(x as u32, y as u32).0[0]);
+                }
+            }
+            if b > 0.0 {
+                a / b
+            } else {
+                1.0
+            }
+        });
+        let last = *gains.last().expect("gains");
+        gains.push(last * ratio);
+    }
+    let mean = gains.iter().sum::<f64>() / gains.len() as f64;
+    gains.into_iter().map(|x| x / mean).collect()
+}
+
+/// Every image at its position, each pixel weighted by its distance to the
+/// left or right edge of its image (so seams fade); cropped to the rows
+/// every image covers.
+fn blend(images: &[RgbImage], positions: &[(i32, i32)], gains: &[f64]) -> Result<RgbImage, String> {
+    let top = positions.iter().map(|x| x.1).max().expect("positions");
+    let bottom = images
+        .iter()
+        .zip(positions)
+        .map(|(image, (_, y))| y + image.height() as i32)
+        .min()
+        .expect("positions");
+    let right = images
+        .iter()
+        .zip(positions)
+        .map(|(image, (x, _))| x + image.width() as i32)
+        .max()
+        .expect("positions");
+    if bottom <= top {
+        return Err(String::from("the images drift too far apart vertically to share any rows"));
+    }
+    let (width, height) = (right as u32, (bottom - top) as u32);
+    let rows = (0..height)
+        .into_par_iter()
+        .map(|row| {
+            let y = top + row as i32;
+            (0..width as i32)
+                .map(|x| {
+                    let mut sum = [0.0; 3];
+                    let mut total = 0.0;
+                    for ((image, (left, image_top)), gain) in images.iter().zip(positions).zip(gains) {
+                        let (local_x, local_y) = (x - left, y - image_top);
+                        if local_x < 0 || local_x >= image.width() as i32 {
+                            continue;
+                        }
+                        let weight = f64::from(local_x.min(image.width() as i32 - 1 - local_x) + 1);
+                        let pixel = image.get_pixel(local_x as u32, local_y as u32);
+                        for (sum, channel) in sum.iter_mut().zip(pixel.0) {
+                            *sum += weight * gain * f64::from(channel);
+                        }
+                        total += weight;
+                    }
+                    let channel = |x: f64| (x / total.max(f64::EPSILON)).round().clamp(0.0, 255.0) as u8;
+                    Rgb([channel(sum[0]), channel(sum[1]), channel(sum[2])])
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    Ok(RgbImage::from_fn(width, height, |x, y| rows[y as usize][x as usize]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stitch() {
+        // A TEXTURED SCENE, AND THREE OVERLAPPING SHOTS OF IT, HANDHELD
+        let scene = RgbImage::from_fn(400, 120, |x, y| {
+            let (x, y) = (x as i32 / 3, y as i32 / 3);
+            let value = (x.wrapping_mul(73_856_093) ^ y.wrapping_mul(19_349_663)).rem_euclid(200) as u8 + 20;
+            Rgb([value, value / 2, 255 - value])
+        });
+        let shot = |x: u32, y: u32, gain: f64| {
+            let mut shot = image::imageops::crop_imm(&scene, x, y, 160, 100).to_image();
+            for pixel in shot.pixels_mut() {
+                pixel.0 = pixel.0.map(|x| (f64::from(x) * gain).round() as u8);
+            }
+            DynamicImage::ImageRgb8(shot)
+        };
+        let shots = [shot(0, 10, 1.0), shot(110, 4, 1.0), shot(230, 16, 1.0)];
+        assert_eq!(register(&shots[0].to_luma8(), &shots[1].to_luma8()), Ok((110, -6)));
+        let panorama = stitch(&shots, Direction::LeftToRight).expect("stitch");
+        // ROWS 16 TO 104 ARE IN EVERY SHOT
+        assert_eq!(panorama.dimensions(), (390, 88));
+        assert_eq!(panorama, image::imageops::crop_imm(&scene, 0, 16, 390, 88).to_image());
+        // A DARKER SHOT STILL REGISTERS, AND IS EVENED OUT
+        let shots = [shot(0, 10, 1.0), shot(110, 4, 0.9)];
+        let panorama = stitch(&shots, Direction::LeftToRight).expect("stitch");
+        assert_eq!(panorama.width(), 270);
+        let vertical = shots.iter().map(|x| x.rotate90()).collect::<Vec<_>>();
+        assert_eq!(stitch(&vertical, Direction::TopToBottom).expect("stitch").height(), 270);
+        assert!(stitch(&shots[..1], Direction::LeftToRight).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Running pipelines described as data (see `imager_core::pipeline`).
+use std::sync::Arc;
+
+pub use imager_core::pipeline::*;
+
+use crate::api::{OptJob, OutMeda};
+use crate::background::BackgroundRemover;
+
+/// Validates and runs the pipeline on an encoded source, falling back to
+/// the `encode` stage’s `fallbacks` when its encoder fails.
+///
+/// A `remove-background` stage loads its model on every call; for
+/// batches, build the `OptJob`s with a shared `BackgroundRemover` instead.
+pub fn run(pipeline: &Pipeline, source: &[u8]) -> Result<(Vec<u8>, OutMeda), String> {
+    pipeline.validate()?;
+    crate::codec::registry::check_pipeline(pipeline)?;
+    let decode_options = pipeline.decode_options();
+    let mut job = OptJob::new_with_options(source, &decode_options)?;
+    job.seed(pipeline.seed);
+    let mut extreme_mode = false;
+    for stage in &pipeline.stages {
+        match stage {
+            // APPLIED BY `OptJob::new_with_options`
+            Stage::Decode { .. } => (),
+            Stage::Resize {
+                max_size,
+                upscaler,
+                fit,
+                filter,
+                crop,
+            } => {
+                job.max_size(max_size.clone());
+                job.fit(*fit);
+                job.resize_filter(*filter);
+                job.crop(*crop);
+                if let Some(upscaler) = upscaler {
+                    crate::upscale::check_available(*upscaler)?;
+                    job.allow_upscale(*upscaler);
+                }
+            }
+            Stage::RemoveBackground { model } => {
+                job.remove_background(Arc::new(BackgroundRemover::open(model)?));
+            }
+            Stage::Watermark { id } => job.watermark(*id),
+            Stage::Palette { palette, dither } => job.brand_palette(palette.clone(), *dither),
+            Stage::Encode {
+                format,
+                fallbacks: _,
+                extreme,
+                color_mode,
+                threshold,
+                text_protect,
+                tuning,
+            } => {
+                job.output_format(format.clone());
+                job.color_mode(*color_mode, *threshold);
+                job.text_protect(*text_protect);
+                job.tuning(*tuning);
+                extreme_mode = *extreme;
+            }
+            Stage::Metadata {
+                privacy,
+                attribution,
+                exif_thumbnail,
+            } => {
+                job.privacy_policy(privacy.clone());
+                job.exif_thumbnail(*exif_thumbnail);
+                job.attribution(attribution.clone());
+            }
+        }
+    }
+    Ok(job.run_with_fallbacks(extreme_mode, pipeline.fallbacks())?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::GenericImageView;
+
+    #[test]
+    fn test_run_pipeline() {
+        let source = r#"{
+            "stages": [
+                {"stage": "resize", "max_size": {"width": 240, "height": 240}},
+                {"stage": "watermark", "id": 42},
+                {"stage": "encode", "format": "Png"}
+            ]
+        }"#;
+        let pipeline = Pipeline::from_json(source).expect("parse pipeline");
+        let (output, _) =
+            run(&pipeline, include_bytes!("../assets/test/1.jpeg")).expect("run pipeline");
+        let output = image::load_from_memory(&output).expect("decode output");
+        assert!(output.width().max(output.height()) <= 240);
+        assert!(run(&pipeline, b"not an image").is_err());
+    }
+}
@@ -0,0 +1,85 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Planes and channels as grayscale images (`imager planes`), for debugging
+//! chroma subsampling and color shifts: the Y, U and V planes as encoders
+//! see them (4:2:0, so U and V are half size), and the R, G, B (and A)
+//! channels.
+use image::{DynamicImage, GenericImageView, GrayImage};
+use std::str::FromStr;
+
+use crate::data::Yuv420P;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Planes {
+    Yuv,
+    Rgba,
+    All,
+}
+
+impl FromStr for Planes {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "yuv" => Ok(Planes::Yuv),
+            "rgba" | "rgb" => Ok(Planes::Rgba),
+            "all" => Ok(Planes::All),
+            _ => Err(format!("Unknown planes {}", s)),
+        }
+    }
+}
+
+/// The planes, named (`y`, `u`, …); alpha only for images with an alpha
+/// channel. Odd dimensions are cropped to even ones for YUV.
+pub fn extract(source: &DynamicImage, planes: Planes) -> Vec<(&'static str, GrayImage)> {
+    let mut output = Vec::new();
+    if planes != Planes::Rgba {
+        let even = crate::data::ensure_even_reslution(source);
+        let yuv = Yuv420P::from_image(&even).expect("to yuv");
+        let (width, height) = yuv.dimensions();
+        let plane = |data: &[u8], width, height| {
+            GrayImage::from_raw(width, height, data.to_vec()).expect("plane size")
+        };
+        output.push(("y", plane(yuv.y(), width, height)));
+        output.push(("u", plane(yuv.u(), width / 2, height / 2)));
+        output.push(("v", plane(yuv.v(), width / 2, height / 2)));
+    }
+    if planes != Planes::Yuv {
+        let rgba = source.to_rgba8();
+        let channels: &[(&'static str, usize)] = if source.color().has_alpha() {
+            &[("r", 0), ("g", 1), ("b", 2), ("a", 3)]
+        } else {
+            &[("r", 0), ("g", 1), ("b", 2)]
+        };
+        for (name, channel) in channels {
+            let (width, height) = source.dimensions();
+            let image = GrayImage::from_fn(width, height, |x, y| {
+                image::Luma([rgba.get_pixel(x, y).0[*channel]])
+            });
+            output.push((*name, image));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_extract() {
+        let source = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(5, 4, image::Rgb([255, 0, 0])));
+        let planes = extract(&source, Planes::All);
+        let names: Vec<_> = planes.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["y", "u", "v", "r", "g", "b"]);
+        let (_, y) = &planes[0];
+        let (_, u) = &planes[1];
+        assert_eq!((y.dimensions(), u.dimensions()), ((4, 4), (2, 2)));
+        // RED IS LOW IN U, HIGH IN V
+        assert!(u.get_pixel(0, 0).0[0] < 128 && planes[2].1.get_pixel(0, 0).0[0] > 128);
+        assert_eq!(planes[3].1.get_pixel(4, 3).0[0], 255);
+        assert_eq!(planes[4].1.get_pixel(4, 3).0[0], 0);
+        let transparent = DynamicImage::ImageRgba8(image::RgbaImage::new(2, 2));
+        assert_eq!(extract(&transparent, Planes::Rgba).len(), 4);
+    }
+}
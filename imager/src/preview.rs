@@ -0,0 +1,327 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Terminal previews (`imager show`), so outputs can be checked over SSH:
+//! the image, downscaled to fit the terminal, as
+//!
+//! - kitty graphics protocol PNGs (kitty, WezTerm, Ghostty),
+//! - sixels (e.g. foot, mlterm, iTerm2), of at most 256 colors,
+//! - or else ANSI half blocks, two pixels per cell, in truecolor if the
+//!   terminal advertises it (`COLORTERM`), else the 256 color palette.
+//!
+//! The protocol is picked by the environment (`TERM`, `TERM_PROGRAM`),
+//! since querying the terminal needs raw mode; `--protocol` overrides it.
+use image::{imageops::FilterType, DynamicImage, GenericImageView, Rgba, RgbaImage};
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::codec::png::{self, ImageMode};
+
+/// Alpha below which pixels show the terminal’s background.
+const ALPHA_THRESHOLD: u8 = 128;
+/// Of the base64 payload of each kitty escape.
+const KITTY_CHUNK: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Kitty,
+    Sixel,
+    Truecolor,
+    Ansi256,
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kitty" => Ok(Protocol::Kitty),
+            "sixel" => Ok(Protocol::Sixel),
+            "truecolor" => Ok(Protocol::Truecolor),
+            "256" => Ok(Protocol::Ansi256),
+            _ => Err(format!("unknown protocol {}; use kitty, sixel, truecolor or 256", s)),
+        }
+    }
+}
+
+impl Protocol {
+    pub fn detect() -> Self {
+        Protocol::from_env(|name| std::env::var(name).ok())
+    }
+    fn from_env(var: impl Fn(&str) -> Option<String>) -> Self {
+        let term = var("TERM").unwrap_or_default();
+        let program = var("TERM_PROGRAM").unwrap_or_default();
+        let colorterm = var("COLORTERM").unwrap_or_default();
+        if var("KITTY_WINDOW_ID").is_some()
+            || matches!(term.as_str(), "xterm-kitty" | "xterm-ghostty")
+            || matches!(program.as_str(), "WezTerm" | "ghostty")
+        {
+            Protocol::Kitty
+        } else if term.contains("sixel") || term.starts_with("foot") || term.starts_with("mlterm") || program == "iTerm.app" {
+            Protocol::Sixel
+        } else if matches!(colorterm.as_str(), "truecolor" | "24bit") {
+            Protocol::Truecolor
+        } else {
+            Protocol::Ansi256
+        }
+    }
+}
+
+/// The space to preview in.
+#[derive(Debug, Clone, Copy)]
+pub struct Terminal {
+    pub columns: u32,
+    pub rows: u32,
+    /// Of a cell, in pixels.
+    pub cell: (u32, u32),
+}
+
+impl Terminal {
+    /// Of stdout, else `COLUMNS` and `LINES`, else 80x24; cells of terminals
+    /// that don’t report their pixel size are assumed to be 8x16.
+    pub fn size() -> Self {
+        let from_env = |name: &str, default: u32| {
+            std::env::var(name).ok().and_then(|x| x.parse().ok()).filter(|x| *x > 0).unwrap_or(default)
+        };
+        let mut terminal = Terminal {
+            columns: from_env("COLUMNS", 80),
+            rows: from_env("LINES", 24),
+            cell: (8, 16),
+        };
+        #[cfg(unix)]
+        unsafe {
+            let mut size: libc::winsize = std::mem::zeroed();
+            if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0 && size.ws_col > 0 {
+                terminal.columns = u32::from(size.ws_col);
+                terminal.rows = u32::from(size.ws_row);
+                if size.ws_xpixel > 0 && size.ws_ypixel > 0 && size.ws_row > 0 {
+                    terminal.cell = (
+                        u32::from(size.ws_xpixel) / terminal.columns,
+                        u32::from(size.ws_ypixel) / terminal.rows,
+                    );
+                }
+            }
+        }
+        terminal
+    }
+}
+
+/// The escape sequences previewing the image within `columns` and `rows`
+/// (never enlarged), ending on a new line.
+pub fn render(source: &DynamicImage, protocol: Protocol, terminal: &Terminal) -> String {
+    let (cell_width, cell_height) = (terminal.cell.0.max(1), terminal.cell.1.max(1));
+    let fit = |width: u32, height: u32| {
+        let (source_width, source_height) = source.dimensions();
+        if source_width <= width && source_height <= height {
+            source.to_rgba8()
+        } else {
+            source.resize(width.max(1), height.max(1), FilterType::Triangle).to_rgba8()
+        }
+    };
+    match protocol {
+        Protocol::Kitty => {
+            let image = fit(terminal.columns * cell_width, terminal.rows * cell_height);
+            kitty(&image, image.width().div_ceil(cell_width))
+        }
+        Protocol::Sixel => sixel(&fit(terminal.columns * cell_width, terminal.rows * cell_height)),
+        Protocol::Truecolor | Protocol::Ansi256 => {
+            half_blocks(&fit(terminal.columns, terminal.rows * 2), protocol == Protocol::Truecolor)
+        }
+    }
+}
+
+fn kitty(image: &RgbaImage, columns: u32) -> String {
+    let mut encoded = std::io::Cursor::new(Vec::new());
+    DynamicImage::ImageRgba8(image.clone())
+        .write_to(&mut encoded, image::ImageFormat::Png)
+        .expect("encode preview png");
+    let payload = base64(encoded.get_ref());
+    let chunks = payload.as_bytes().chunks(KITTY_CHUNK).collect::<Vec<_>>();
+    let mut output = String::new();
+    for (ix, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(ix + 1 < chunks.len());
+        let chunk = std::str::from_utf8(chunk).expect("base64 is ascii");
+        if ix == 0 {
+            let _ = write!(output, "\x1b_Ga=T,f=100,c={},m={};{}\x1b\\", columns, more, chunk);
+        } else {
+            let _ = write!(output, "\x1b_Gm={};{}\x1b\\", more, chunk);
+        }
+    }
+    output.push('\n');
+    output
+}
+
+fn sixel(image: &RgbaImage) -> String {
+    let (width, height) = image.dimensions();
+    let (palette, indices) = png::quantize(&DynamicImage::ImageRgba8(image.clone()), ImageMode::Text, 255);
+    let visible = |x: u32, y: u32| image.get_pixel(x, y).0[3] >= ALPHA_THRESHOLD;
+    let index = |x: u32, y: u32| indices[(y * width + x) as usize];
+    // TRANSPARENT BACKGROUND, SQUARE PIXELS
+    let mut output = format!("\x1bP0;1;0q\"1;1;{};{}", width, height);
+    for (ix, color) in palette.iter().enumerate() {
+        let percent = |x: u8| u32::from(x) * 100 / 255;
+        let _ = write!(output, "#{};2;{};{};{}", ix, percent(color.r), percent(color.g), percent(color.b));
+    }
+    for band in (0..height).step_by(6) {
+        let rows = band..(band + 6).min(height);
+        let mut used = rows
+            .clone()
+            .flat_map(|y| (0..width).filter(move |x| visible(*x, y)).map(move |x| index(x, y)))
+            .collect::<Vec<_>>();
+        used.sort_unstable();
+        used.dedup();
+        for color in used {
+            let _ = write!(output, "#{}", color);
+            let sixels = (0..width).map(|x| {
+                let bits = rows
+                    .clone()
+                    .filter(|y| visible(x, *y) && index(x, *y) == color)
+                    .fold(0, |bits, y| bits | (1 << (y - band)));
+                char::from(63 + bits as u8)
+            });
+            run_length(&mut output, sixels);
+            output.push('$');
+        }
+        output.push('-');
+    }
+    output.push_str("\x1b\\\n");
+    output
+}
+
+/// As sixel repeat introducers, for runs of more than three.
+fn run_length(output: &mut String, sixels: impl Iterator<Item = char>) {
+    let flush = |output: &mut String, sixel: char, count: usize| match count {
+        0 => (),
+        1..=3 => output.extend(std::iter::repeat_n(sixel, count)),
+        _ => {
+            let _ = write!(output, "!{}{}", count, sixel);
+        }
+    };
+    let mut run = (' ', 0);
+    for sixel in sixels {
+        if sixel == run.0 {
+            run.1 += 1;
+        } else {
+            flush(output, run.0, run.1);
+            run = (sixel, 1);
+        }
+    }
+    flush(output, run.0, run.1);
+}
+
+/// Upper half blocks, the foreground the upper pixel and the background
+/// the lower one.
+fn half_blocks(image: &RgbaImage, truecolor: bool) -> String {
+    let (width, height) = image.dimensions();
+    let color = |pixel: &Rgba<u8>, layer: u8| {
+        if truecolor {
+            format!("\x1b[{}8;2;{};{};{}m", layer, pixel.0[0], pixel.0[1], pixel.0[2])
+        } else {
+            format!("\x1b[{}8;5;{}m", layer, ansi256(pixel.0))
+        }
+    };
+    let mut output = String::new();
+    for y in (0..height).step_by(2) {
+        let mut previous = None;
+        for x in 0..width {
+            let upper = image.get_pixel(x, y);
+            let lower = (y + 1 < height).then(|| image.get_pixel(x, y + 1));
+            let visible = |pixel: &&Rgba<u8>| pixel.0[3] >= ALPHA_THRESHOLD;
+            let cell = match (Some(upper).filter(visible), lower.filter(visible)) {
+                (Some(upper), Some(lower)) => format!("{}{}▀", color(upper, 3), color(lower, 4)),
+                (Some(upper), None) => format!("\x1b[49m{}▀", color(upper, 3)),
+                (None, Some(lower)) => format!("\x1b[49m{}▄", color(lower, 3)),
+                (None, None) => String::from("\x1b[0m "),
+            };
+            // REPEATED COLORS ARE LEFT AS THEY ARE
+            let glyph = cell.chars().last().expect("glyph");
+            let escapes = &cell[..cell.len() - glyph.len_utf8()];
+            if previous.as_deref() != Some(escapes) {
+                output.push_str(escapes);
+                previous = Some(escapes.to_owned());
+            }
+            output.push(glyph);
+        }
+        output.push_str("\x1b[0m\n");
+    }
+    output
+}
+
+/// The nearest of the 6x6x6 color cube and the gray ramp.
+fn ansi256([r, g, b, _]: [u8; 4]) -> u8 {
+    const LEVELS: [i32; 6] = [0, 95, 135, 175, 215, 255];
+    let nearest = |x: u8| {
+        (0..6)
+            .min_by_key(|ix| (LEVELS[*ix] - i32::from(x)).abs())
+            .expect("levels")
+    };
+    let (r6, g6, b6) = (nearest(r), nearest(g), nearest(b));
+    let distance = |(x, y, z): (i32, i32, i32)| {
+        (x - i32::from(r)).pow(2) + (y - i32::from(g)).pow(2) + (z - i32::from(b)).pow(2)
+    };
+    let cube = distance((LEVELS[r6], LEVELS[g6], LEVELS[b6]));
+    let mean = (i32::from(r) + i32::from(g) + i32::from(b)) / 3;
+    let gray = ((mean - 8).max(0) / 10).min(23);
+    let level = 8 + gray * 10;
+    if distance((level, level, level)) < cube {
+        232 + gray as u8
+    } else {
+        16 + (36 * r6 + 6 * g6 + b6) as u8
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        for ix in 0..4 {
+            if ix <= chunk.len() {
+                output.push(char::from(ALPHABET[(n >> (18 - 6 * ix) & 63) as usize]));
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        fn env<'a>(pairs: &'a [(&'static str, &'static str)]) -> impl Fn(&str) -> Option<String> + 'a {
+            move |name| pairs.iter().find(|x| x.0 == name).map(|x| x.1.to_owned())
+        }
+        assert_eq!(Protocol::from_env(env(&[("TERM", "xterm-kitty")])), Protocol::Kitty);
+        assert_eq!(Protocol::from_env(env(&[("TERM", "foot")])), Protocol::Sixel);
+        let ssh = [("TERM", "xterm-256color"), ("COLORTERM", "truecolor")];
+        assert_eq!(Protocol::from_env(env(&ssh)), Protocol::Truecolor);
+        assert_eq!(Protocol::from_env(env(&[])), Protocol::Ansi256);
+        assert_eq!(base64(b"imager"), "aW1hZ2Vy");
+        assert_eq!(base64(b"png"), "cG5n");
+        assert_eq!(base64(b"pn"), "cG4=");
+        assert_eq!((ansi256([255, 0, 0, 255]), ansi256([128, 128, 128, 255])), (196, 244));
+        // RED OVER BLUE, THEN A TRANSPARENT ROW
+        let mut image = RgbaImage::from_pixel(2, 3, Rgba([255, 0, 0, 255]));
+        for x in 0..2 {
+            image.put_pixel(x, 1, Rgba([0, 0, 255, 255]));
+            image.put_pixel(x, 2, Rgba([0, 0, 0, 0]));
+        }
+        let terminal = Terminal {
+            columns: 80,
+            rows: 24,
+            cell: (8, 16),
+        };
+        let source = DynamicImage::ImageRgba8(image);
+        let blocks = render(&source, Protocol::Truecolor, &terminal);
+        assert_eq!(blocks, "\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m▀▀\x1b[0m\n\x1b[0m  \x1b[0m\n");
+        let sixels = render(&source, Protocol::Sixel, &terminal);
+        assert!(sixels.starts_with("\x1bP0;1;0q\"1;1;2;3#0;2;"), "{:?}", sixels);
+        assert!(sixels.ends_with("$-\x1b\\\n"));
+        let kitty = render(&source, Protocol::Kitty, &terminal);
+        assert!(kitty.starts_with("\x1b_Ga=T,f=100,c=1,m=0;iVBORw0KGgo"), "{:?}", kitty);
+    }
+}
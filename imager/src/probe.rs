@@ -0,0 +1,454 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Metadata only probing of images and videos, from their headers, so
+//! batch planners can decide (e.g. skip, or pick the formats of) files
+//! before committing to full decodes.
+//!
+//! JPEG, PNG, GIF, WebP, TIFF and AVIF headers are parsed in process;
+//! anything else (videos, and e.g. JPEG XL) by an `ffprobe` subprocess.
+//! Files are memory-mapped when large (see `InputBuffer`), so probing a
+//! video doesn’t read it; bytes that need `ffprobe` are first written to
+//! a `JobDir`.
+use image::codecs::{gif::GifDecoder, jpeg::JpegDecoder, png::PngDecoder, tiff::TiffDecoder};
+use image::{ImageDecoder, ImageFormat};
+use serde::Serialize;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use crate::error::{ImagerError, Result};
+use crate::input::{InputBuffer, ReadMode};
+use crate::workspace::JobDir;
+
+/// What to probe.
+#[derive(Debug, Clone, Copy)]
+pub enum MediaSource<'a> {
+    Path(&'a Path),
+    Bytes(&'a [u8]),
+}
+
+impl<'a> From<&'a Path> for MediaSource<'a> {
+    fn from(path: &'a Path) -> Self {
+        Self::Path(path)
+    }
+}
+
+impl<'a> From<&'a PathBuf> for MediaSource<'a> {
+    fn from(path: &'a PathBuf) -> Self {
+        Self::Path(path)
+    }
+}
+
+impl<'a> From<&'a str> for MediaSource<'a> {
+    fn from(path: &'a str) -> Self {
+        Self::Path(Path::new(path))
+    }
+}
+
+impl<'a> From<&'a [u8]> for MediaSource<'a> {
+    fn from(source: &'a [u8]) -> Self {
+        Self::Bytes(source)
+    }
+}
+
+impl<'a> From<&'a Vec<u8>> for MediaSource<'a> {
+    fn from(source: &'a Vec<u8>) -> Self {
+        Self::Bytes(source)
+    }
+}
+
+impl<'a, const N: usize> From<&'a [u8; N]> for MediaSource<'a> {
+    fn from(source: &'a [u8; N]) -> Self {
+        Self::Bytes(source)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Image,
+    Video,
+}
+
+/// As far as the headers tell; unknowns are none.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ColorInfo {
+    /// E.g. `Rgb8` (of `image::ColorType`) for images, or `yuv420p` (of
+    /// ffprobe) for videos.
+    pub pixel_format: Option<String>,
+    /// Per channel.
+    pub bit_depth: Option<u8>,
+    pub alpha: Option<bool>,
+    pub icc_profile: bool,
+    /// Of ffprobe, e.g. `bt709`.
+    pub primaries: Option<String>,
+    pub transfer: Option<String>,
+    pub matrix: Option<String>,
+    /// Full (PC), rather than limited (TV) range.
+    pub full_range: Option<bool>,
+}
+
+/// Of the image, or the first video stream.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MediaInfo {
+    pub kind: MediaKind,
+    /// E.g. `jpeg`, or ffprobe’s `mov,mp4,m4a,3gp,3g2,mj2`.
+    pub container: String,
+    /// E.g. `vp8l` (lossless WebP), `av1` (AVIF) or `h264`.
+    pub codec: String,
+    /// As stored, i.e. before the rotation.
+    pub width: u32,
+    pub height: u32,
+    pub duration: Option<Duration>,
+    /// Frames per second, as a ratio, e.g. `(30000, 1001)`.
+    pub frame_rate: Option<(u64, u64)>,
+    /// E.g. of APNGs and animated WebPs; 1 for stills.
+    pub frame_count: Option<u64>,
+    /// To display, rotate clockwise by this (0, 90, 180 or 270 degrees)…
+    pub rotation: u16,
+    /// …then mirror horizontally if this (e.g. EXIF orientation 2).
+    pub mirrored: bool,
+    pub color: ColorInfo,
+}
+
+impl MediaInfo {
+    /// Of a path, or of bytes.
+    pub fn probe<'a>(source: impl Into<MediaSource<'a>>) -> Result<Self> {
+        match source.into() {
+            MediaSource::Path(path) => {
+                let buffer = InputBuffer::open(path, ReadMode::Auto).map_err(ImagerError::io(path))?;
+                match probe_image(&buffer) {
+                    Some(info) => info,
+                    None => probe_ffprobe(path),
+                }
+            }
+            MediaSource::Bytes(source) => match probe_image(source) {
+                Some(info) => info,
+                None => {
+                    let job_dir = JobDir::new("probe")?;
+                    let path = job_dir.join("source");
+                    std::fs::write(&path, source).map_err(ImagerError::io(&path))?;
+                    probe_ffprobe(&path)
+                }
+            },
+        }
+    }
+    /// With the rotation applied.
+    pub fn display_dimensions(&self) -> (u32, u32) {
+        match self.rotation {
+            90 | 270 => (self.height, self.width),
+            _ => (self.width, self.height),
+        }
+    }
+    fn still(format: &str, codec: &str, (width, height): (u32, u32), color: ColorInfo) -> Self {
+        MediaInfo {
+            kind: MediaKind::Image,
+            container: String::from(format),
+            codec: String::from(codec),
+            width,
+            height,
+            duration: None,
+            frame_rate: None,
+            frame_count: Some(1),
+            rotation: 0,
+            mirrored: false,
+            color,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// IMAGES
+///////////////////////////////////////////////////////////////////////////////
+
+/// None if the format isn’t parsed in process.
+fn probe_image(source: &[u8]) -> Option<Result<MediaInfo>> {
+    let format = image::guess_format(source).ok()?;
+    let mut info = match format {
+        ImageFormat::Jpeg => header(JpegDecoder::new(Cursor::new(source)), "jpeg"),
+        ImageFormat::Png => header(PngDecoder::new(Cursor::new(source)), "png").map(|mut info| {
+            // APNG
+            if let Some(frames) = crate::codec::png::apng::frame_count(source) {
+                info.frame_count = Some(frames);
+            }
+            info
+        }),
+        ImageFormat::Gif => header(GifDecoder::new(Cursor::new(source)), "gif").map(|mut info| {
+            // WITHOUT SKIPPING THROUGH EVERY (COMPRESSED) FRAME
+            info.frame_count = None;
+            info
+        }),
+        ImageFormat::Tiff => header(TiffDecoder::new(Cursor::new(source)), "tiff"),
+        // THE DECODER OF IMAGE DECODES IN ITS CONSTRUCTOR
+        ImageFormat::WebP => webp_header(source),
+        ImageFormat::Avif => avif_header(source),
+        _ => return None,
+    };
+    if let Ok(info) = info.as_mut() {
+        info.color.icc_profile = crate::meta::container::has_icc_profile(source, format);
+        let orientation = crate::meta::container::extract_exif(source, format)
+            .and_then(|x| crate::meta::exif::orientation(&x))
+            .unwrap_or(1);
+        if orientation != 1 {
+            (info.rotation, info.mirrored) = from_orientation(orientation);
+        }
+    }
+    Some(info)
+}
+
+/// Of a decoder, which (but for WebP) reads only the headers when created.
+fn header<'a, D: ImageDecoder<'a>>(decoder: image::ImageResult<D>, format: &str) -> Result<MediaInfo> {
+    let decoder = decoder?;
+    let color_type = decoder.color_type();
+    let color = ColorInfo {
+        pixel_format: Some(format!("{:?}", color_type)),
+        bit_depth: Some((color_type.bits_per_pixel() / u16::from(color_type.channel_count())) as u8),
+        alpha: Some(color_type.has_alpha()),
+        ..ColorInfo::default()
+    };
+    Ok(MediaInfo::still(format, format, decoder.dimensions(), color))
+}
+
+fn webp_header(source: &[u8]) -> Result<MediaInfo> {
+    let invalid = || ImagerError::decode("invalid WebP header");
+    let u24 = |x: &[u8]| u32::from(x[0]) | u32::from(x[1]) << 8 | u32::from(x[2]) << 16;
+    let mut chunks = Vec::new();
+    let mut offset = 12;
+    while let Some(chunk) = source.get(offset..offset + 8) {
+        let len = u32::from_le_bytes(chunk[4..].try_into().unwrap()) as usize;
+        let data = &source[offset + 8..source.len().min(offset + 8 + len)];
+        chunks.push((&chunk[..4], data));
+        // PADDED TO EVEN LENGTHS
+        offset += 8 + len + len % 2;
+    }
+    let frame = |kind: &[u8], data: &[u8]| -> Option<(&'static str, (u32, u32), bool)> {
+        match kind {
+            b"VP8 " if data.len() >= 10 => {
+                let dimension = |x: &[u8]| u32::from(u16::from_le_bytes([x[0], x[1]]) & 0x3FFF);
+                Some(("vp8", (dimension(&data[6..]), dimension(&data[8..])), false))
+            }
+            b"VP8L" if data.len() >= 5 && data[0] == 0x2F => {
+                let bits = u32::from_le_bytes(data[1..5].try_into().unwrap());
+                Some((
+                    "vp8l",
+                    ((bits & 0x3FFF) + 1, (bits >> 14 & 0x3FFF) + 1),
+                    bits >> 28 & 1 == 1,
+                ))
+            }
+            _ => None,
+        }
+    };
+    let (kind, data) = chunks.first().copied().ok_or_else(invalid)?;
+    let mut info = if kind == b"VP8X" && data.len() >= 10 {
+        let flags = data[0];
+        let dimensions = (u24(&data[4..]) + 1, u24(&data[7..]) + 1);
+        let frames = chunks.iter().filter(|(kind, _)| *kind == b"ANMF");
+        // OF THE FIRST FRAME, BY ITS BITSTREAM
+        let codec = match frames.clone().next() {
+            Some((_, anmf)) => anmf.get(16..).and_then(|x| frame(x.get(..4)?, x.get(8..)?)),
+            None => chunks.iter().find_map(|(kind, data)| frame(kind, data)),
+        }
+        .map_or("vp8", |x| x.0);
+        let mut info = MediaInfo::still("webp", codec, dimensions, ColorInfo::default());
+        info.color.alpha = Some(flags & 0x10 != 0);
+        if flags & 0x02 != 0 {
+            info.frame_count = Some(frames.count() as u64);
+        }
+        info
+    } else {
+        let (codec, dimensions, alpha) = frame(kind, data).ok_or_else(invalid)?;
+        let mut info = MediaInfo::still("webp", codec, dimensions, ColorInfo::default());
+        info.color.alpha = Some(alpha);
+        info
+    };
+    info.color.bit_depth = Some(8);
+    Ok(info)
+}
+
+/// The `auxC` (auxiliary type) of alpha planes.
+const AVIF_ALPHA_URN: &[u8] = b"urn:mpeg:mpegB:cicp:systems:auxiliary:alpha";
+
+/// Of the `ispe` (dimensions), `pixi` (depth), `auxC` (alpha) and `irot`
+/// (rotation) item properties, by a scan of the `meta` box (which precedes
+/// the data).
+fn avif_header(source: &[u8]) -> Result<MediaInfo> {
+    // THE FIRST (OF THE PRIMARY IMAGE, NOT AN ALPHA OR THUMBNAIL ITEM), WHICH ENCODERS WRITE FIRST
+    let find = |kind: &[u8], len: usize| {
+        let at = source.windows(4).position(|x| x == kind)?;
+        source.get(at + 4..at + 4 + len)
+    };
+    let ispe = find(b"ispe", 12).ok_or_else(|| ImagerError::decode("AVIF without an ispe property"))?;
+    let u32_at = |at: usize| u32::from_be_bytes(ispe[at..at + 4].try_into().unwrap());
+    let mut info = MediaInfo::still("avif", "av1", (u32_at(4), u32_at(8)), ColorInfo::default());
+    info.color.bit_depth = find(b"pixi", 6).filter(|x| x[4] > 0).map(|x| x[5]);
+    info.color.alpha = Some(source.windows(AVIF_ALPHA_URN.len()).any(|x| x == AVIF_ALPHA_URN));
+    // ANTICLOCKWISE, IN QUARTER TURNS
+    info.rotation = find(b"irot", 1).map_or(0, |x| (4 - u16::from(x[0] & 3)) % 4 * 90);
+    Ok(info)
+}
+
+/// Of an EXIF orientation (1-8).
+fn from_orientation(orientation: u16) -> (u16, bool) {
+    match orientation {
+        2 => (0, true),
+        3 => (180, false),
+        4 => (180, true),
+        5 => (90, true),
+        6 => (90, false),
+        7 => (270, true),
+        8 => (270, false),
+        _ => (0, false),
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// FFPROBE
+///////////////////////////////////////////////////////////////////////////////
+
+fn probe_ffprobe(path: &Path) -> Result<MediaInfo> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", "v:0", "-of", "json", "-show_entries"])
+        .arg(concat!(
+            "stream=codec_name,width,height,avg_frame_rate,r_frame_rate,nb_frames,duration,pix_fmt,",
+            "bits_per_raw_sample,color_range,color_space,color_primaries,color_transfer",
+            ":stream_tags=rotate:stream_side_data=rotation:format=format_name,duration"
+        ))
+        .arg(path)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|x| ImagerError::decode(format!("failed to run ffprobe: {}", x)))?;
+    if !output.status.success() {
+        return Err(ImagerError::decode(format!("ffprobe failed ({})", output.status)));
+    }
+    let probe = serde_json::from_slice(&output.stdout).map_err(ImagerError::decode)?;
+    from_probe(path, &probe)
+}
+
+/// Of `ffprobe -of json` output.
+fn from_probe(path: &Path, probe: &serde_json::Value) -> Result<MediaInfo> {
+    let stream = &probe["streams"][0];
+    let dimension = |key: &str| stream[key].as_u64().filter(|x| *x > 0).map(|x| x as u32);
+    let (Some(width), Some(height)) = (dimension("width"), dimension("height")) else {
+        return Err(ImagerError::decode(format!(
+            "{}: no image or video stream",
+            path.display()
+        )));
+    };
+    let string = |value: &serde_json::Value| value.as_str().filter(|x| *x != "unknown").map(String::from);
+    let frame_rate = crate::video::parse_rate(&stream["avg_frame_rate"])
+        .or_else(|| crate::video::parse_rate(&stream["r_frame_rate"]));
+    let duration = crate::video::parse_seconds(&stream["duration"])
+        .or_else(|| crate::video::parse_seconds(&probe["format"]["duration"]));
+    let frame_count = stream["nb_frames"].as_str().and_then(|x| x.parse().ok());
+    let pixel_format = string(&stream["pix_fmt"]);
+    Ok(MediaInfo {
+        // E.G. JPEG XL, OR A STILL IN A VIDEO CONTAINER
+        kind: if frame_count.unwrap_or(1) <= 1 && duration.is_none_or(|x| x.is_zero()) {
+            MediaKind::Image
+        } else {
+            MediaKind::Video
+        },
+        container: string(&probe["format"]["format_name"]).unwrap_or_default(),
+        codec: string(&stream["codec_name"]).unwrap_or_default(),
+        width,
+        height,
+        duration,
+        frame_rate,
+        frame_count,
+        rotation: crate::video::parse_rotation(stream),
+        mirrored: false,
+        color: ColorInfo {
+            bit_depth: stream["bits_per_raw_sample"].as_str().and_then(|x| x.parse().ok()),
+            alpha: pixel_format
+                .as_deref()
+                .map(|x| x.starts_with("yuva") || x.contains("rgba") || x.contains("argb")),
+            pixel_format,
+            icc_profile: false,
+            primaries: string(&stream["color_primaries"]),
+            transfer: string(&stream["color_transfer"]),
+            matrix: string(&stream["color_space"]),
+            full_range: string(&stream["color_range"]).map(|x| x == "pc"),
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_probe() {
+        let jpeg = MediaInfo::probe("assets/test/1.jpeg").expect("probe jpeg");
+        let decoded = image::open("assets/test/1.jpeg").expect("decode");
+        assert_eq!((jpeg.kind, jpeg.codec.as_str()), (MediaKind::Image, "jpeg"));
+        assert_eq!((jpeg.width, jpeg.height), (decoded.width(), decoded.height()));
+        assert_eq!(jpeg.color.bit_depth, Some(8));
+        // BY THE (HEADER ONLY) PARSERS, OF ENCODED BYTES
+        let image = image::DynamicImage::ImageRgba8(image::RgbaImage::new(30, 20));
+        for (format, codec) in [
+            (ImageFormat::Png, "png"),
+            (ImageFormat::Gif, "gif"),
+            (ImageFormat::Tiff, "tiff"),
+        ] {
+            let mut encoded = Cursor::new(Vec::new());
+            image.write_to(&mut encoded, format).expect("encode");
+            let info = MediaInfo::probe(encoded.get_ref()).expect("probe");
+            assert_eq!((info.codec.as_str(), info.width, info.height), (codec, 30, 20));
+        }
+        let webp = imager_core::vp8l::encode_rgba(30, 20, image.as_bytes()).expect("encode webp");
+        let info = MediaInfo::probe(&webp).expect("probe webp");
+        assert_eq!((info.codec.as_str(), info.width, info.height), ("vp8l", 30, 20));
+        let gif = crate::codec::gif::test::animated_gif();
+        assert_eq!(MediaInfo::probe(&gif).expect("probe gif").width, 20);
+        assert_eq!(from_orientation(6), (90, false));
+    }
+
+    #[test]
+    fn test_avif_header() {
+        let boxed = |kind: &[u8], payload: &[u8]| {
+            let mut output = ((8 + payload.len()) as u32).to_be_bytes().to_vec();
+            output.extend_from_slice(kind);
+            output.extend_from_slice(payload);
+            output
+        };
+        // FTYP, THEN THE PROPERTIES (FULL BOXES) OF A 30X20 IMAGE, WITH AN ALPHA ITEM OR NOT
+        let avif = |auxiliary: &[u8]| {
+            let mut ispe = vec![0; 4];
+            ispe.extend_from_slice(&30u32.to_be_bytes());
+            ispe.extend_from_slice(&20u32.to_be_bytes());
+            let mut source = boxed(b"ftyp", b"avif\0\0\0\0avifmif1miaf");
+            source.extend(boxed(b"ispe", &ispe));
+            source.extend(boxed(b"auxC", &[&[0; 4], auxiliary, b"\0"].concat()));
+            source
+        };
+        let info = MediaInfo::probe(&avif(AVIF_ALPHA_URN)).expect("probe avif");
+        assert_eq!((info.codec.as_str(), info.width, info.height), ("av1", 30, 20));
+        assert_eq!(info.color.alpha, Some(true));
+        let depth = avif(b"urn:mpeg:mpegB:cicp:systems:auxiliary:depth");
+        assert_eq!(MediaInfo::probe(&depth).expect("probe avif").color.alpha, Some(false));
+    }
+
+    #[test]
+    fn test_from_probe() {
+        let probe = serde_json::json!({
+            "streams": [{
+                "codec_name": "h264", "width": 1920, "height": 1080, "pix_fmt": "yuv420p",
+                "avg_frame_rate": "30000/1001", "nb_frames": "300", "bits_per_raw_sample": "8",
+                "color_range": "tv", "color_primaries": "bt709", "color_space": "unknown",
+                "side_data_list": [{"rotation": -90}],
+            }],
+            "format": {"format_name": "mov,mp4,m4a,3gp,3g2,mj2", "duration": "10.010000"},
+        });
+        let info = from_probe(Path::new("phone.mp4"), &probe).expect("parse");
+        assert_eq!((info.kind, info.codec.as_str()), (MediaKind::Video, "h264"));
+        assert_eq!((info.frame_rate, info.frame_count), (Some((30000, 1001)), Some(300)));
+        assert_eq!(info.duration, Some(Duration::from_millis(10010)));
+        assert_eq!((info.rotation, info.display_dimensions()), (90, (1080, 1920)));
+        assert_eq!((info.color.full_range, info.color.matrix), (Some(false), None));
+        assert_eq!(info.color.alpha, Some(false));
+        assert!(from_probe(Path::new("audio.mp3"), &serde_json::json!({"streams": []})).is_err());
+    }
+}
@@ -0,0 +1,137 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Provenance records (`--provenance`): of each output, the SHA-256 of its
+//! source, the parameters it was generated with (and their SHA-256), and
+//! the imager and encoder versions, so any derivative (e.g. found in a
+//! bucket) can be traced back to how it was made.
+//!
+//! Records are written as a sidecar (`photo.webp.provenance.json`), or
+//! embedded as XMP (see `meta::xmp::provenance_packet`), of the digests and
+//! versions only. Outputs that can’t hold XMP (TIFF, AVIF and JPEG XL) get
+//! the sidecar instead.
+//!
+//! The parameters are the pipeline (of `imager pipeline`), or else the
+//! stages of the job’s plan (see `OptJob::plan`, i.e. `--explain`); their
+//! digest is of their compact JSON, so equal parameters hash equally.
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use imager_core::digest::{hex, sha256};
+
+use crate::data::OutputFormat;
+use crate::report::Versions;
+
+pub const PROVENANCE_SCHEMA_VERSION: u32 = 1;
+
+/// Where records are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProvenanceMode {
+    Sidecar,
+    Xmp,
+}
+
+impl std::str::FromStr for ProvenanceMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sidecar" => Ok(ProvenanceMode::Sidecar),
+            "xmp" => Ok(ProvenanceMode::Xmp),
+            _ => Err(format!("Unknown provenance mode {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Provenance {
+    pub schema_version: u32,
+    /// Lowercase hex.
+    pub source_sha256: String,
+    /// Of the `pipeline`, as compact JSON; lowercase hex.
+    pub pipeline_sha256: String,
+    pub pipeline: serde_json::Value,
+    /// Of the output, e.g. a fallback format.
+    pub format: OutputFormat,
+    pub versions: Option<Versions>,
+}
+
+impl Provenance {
+    pub fn new(source: &[u8], pipeline: serde_json::Value, format: OutputFormat, versions: Option<Versions>) -> Self {
+        let compact = serde_json::to_string(&pipeline).expect("to json failed");
+        Provenance {
+            schema_version: PROVENANCE_SCHEMA_VERSION,
+            source_sha256: hex(&sha256(source)),
+            pipeline_sha256: hex(&sha256(compact.as_bytes())),
+            pipeline,
+            format,
+            versions,
+        }
+    }
+    pub fn from_json(source: &str) -> Result<Self, String> {
+        serde_json::from_str(source).map_err(|e| e.to_string())
+    }
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("to json str failed")
+    }
+    /// Embeds the record (as XMP) in the output, unless the container
+    /// can’t hold it.
+    pub fn embed(&self, encoded: &[u8], dimensions: (u32, u32)) -> Option<Vec<u8>> {
+        let packet = crate::meta::xmp::provenance_packet(self);
+        let output = crate::meta::container::insert_xmp(encoded.to_vec(), &self.format, packet.as_bytes(), dimensions);
+        (output.len() != encoded.len()).then_some(output)
+    }
+    /// Writes the record next to the output (see `sidecar_path`).
+    pub fn write_sidecar(&self, output_path: &Path) -> Result<(), String> {
+        let path = sidecar_path(output_path);
+        std::fs::write(&path, self.to_json()).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// E.g. `photo.webp.provenance.json`, of `photo.webp`.
+pub fn sidecar_path(output_path: &Path) -> PathBuf {
+    let mut path = output_path.as_os_str().to_owned();
+    path.push(".provenance.json");
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_provenance() {
+        let versions = Versions {
+            imager: String::from("0.4.1"),
+            encoder: String::from("libwebp"),
+            encoder_version: Some(String::from("1.3.2")),
+        };
+        let stages = serde_json::json!([{"stage": "resize", "detail": "480x320, lanczos3"}]);
+        let provenance = Provenance::new(b"abc", stages.clone(), OutputFormat::Png, Some(versions));
+        assert_eq!(
+            provenance.source_sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let same = Provenance::new(b"", stages, OutputFormat::Png, None);
+        assert_eq!(provenance.pipeline_sha256, same.pipeline_sha256);
+        assert_eq!(Provenance::from_json(&provenance.to_json()), Ok(provenance.clone()));
+        assert_eq!(
+            sidecar_path(Path::new("out/photo.webp")),
+            PathBuf::from("out/photo.webp.provenance.json")
+        );
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::DynamicImage::new_rgb8(4, 4)
+            .write_to(&mut png, image::ImageFormat::Png)
+            .expect("encode");
+        let embedded = provenance.embed(png.get_ref(), (4, 4)).expect("embed");
+        let packet = crate::meta::container::extract_xmp(&embedded, image::ImageFormat::Png).expect("xmp");
+        let packet = String::from_utf8(packet).expect("utf-8");
+        assert!(packet.contains(&format!("<imager:PipelineSHA256>{}<", provenance.pipeline_sha256)));
+        assert!(packet.contains("<imager:Encoder>libwebp 1.3.2<"));
+        // NO XMP IN AVIF OUTPUTS, SO THE SIDECAR
+        let avif = Provenance {
+            format: OutputFormat::Avif,
+            ..provenance
+        };
+        assert_eq!(avif.embed(b"", (4, 4)), None);
+    }
+}
@@ -0,0 +1,288 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Rate-distortion curves (`imager rd-curve`): one source encoded across a
+//! sweep of settings per codec, with the size and score of every encode,
+//! to evaluate codecs on one’s own content.
+//!
+//! The setting is the quality for JPEG and WebP, and the palette size for
+//! PNG. Scores are VMAF (as the searches use; requires `ffi`), the PSNR
+//! of the RGB channels, or DSSIM (see `eval::ssim`) or butteraugli
+//! distances (see `eval::butteraugli`), where lower is better.
+use image::{DynamicImage, GenericImageView};
+use rayon::prelude::*;
+use std::str::FromStr;
+
+use crate::codec::png;
+use crate::codec::registry::FeatureDisabled;
+use crate::data::OutputFormat;
+use crate::decode::DecodeOptions;
+
+pub const QUALITIES: [u32; 11] = [10, 20, 30, 40, 50, 60, 70, 80, 85, 90, 95];
+/// Up to 255, as `png::compress` may add a color.
+pub const PALETTE_SIZES: [u32; 7] = [4, 8, 16, 32, 64, 128, 255];
+/// The PSNR of identical images.
+pub const MAX_PSNR: f64 = 100.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    Vmaf,
+    Psnr,
+    Dssim,
+    Butteraugli,
+}
+
+impl FromStr for Metric {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "vmaf" => Ok(Metric::Vmaf),
+            "psnr" => Ok(Metric::Psnr),
+            "dssim" => Ok(Metric::Dssim),
+            "butteraugli" => Ok(Metric::Butteraugli),
+            _ => Err(format!("Unknown metric {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Metric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Metric::Vmaf => write!(f, "vmaf"),
+            Metric::Psnr => write!(f, "psnr"),
+            Metric::Dssim => write!(f, "dssim"),
+            Metric::Butteraugli => write!(f, "butteraugli"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    pub format: OutputFormat,
+    /// The quality, or palette size.
+    pub setting: u32,
+    pub size: usize,
+    pub score: f64,
+}
+
+/// The curve of each format, in order of `settings`.
+pub fn sweep(
+    source: &DynamicImage,
+    formats: &[OutputFormat],
+    metric: Metric,
+) -> Result<Vec<Point>, String> {
+    if metric == Metric::Vmaf && cfg!(feature = "pure-rust") {
+        return Err(FeatureDisabled::VMAF.into());
+    }
+    let runs = formats
+        .iter()
+        .flat_map(|format| settings(format).iter().map(move |x| (format.clone(), *x)))
+        .collect::<Vec<_>>();
+    runs.into_par_iter()
+        .map(|(format, setting)| {
+            let encoded = encode(source, &format, setting)?;
+            let score = score(source, &encoded, &format, metric)?;
+            Ok(Point {
+                format,
+                setting,
+                size: encoded.len(),
+                score,
+            })
+        })
+        .collect()
+}
+
+pub fn settings(format: &OutputFormat) -> &'static [u32] {
+    match format {
+        OutputFormat::Png => &PALETTE_SIZES,
+        // LOSSLESS, SO A SINGLE POINT
+        OutputFormat::Tiff => &[0],
+        _ => &QUALITIES,
+    }
+}
+
+pub(crate) fn encode(source: &DynamicImage, format: &OutputFormat, setting: u32) -> Result<Vec<u8>, String> {
+    match format {
+        #[cfg(not(feature = "pure-rust"))]
+        OutputFormat::Jpeg => Ok(unsafe { crate::codec::jpeg::encode(source, setting as u8) }),
+        #[cfg(feature = "pure-rust")]
+        OutputFormat::Jpeg => Ok(crate::codec::jpeg::encode_in_rust(source, setting as u8)),
+        #[cfg(not(feature = "pure-rust"))]
+        OutputFormat::Webp => Ok(crate::codec::webp::encode::lossy::encode(source, setting as f32)),
+        #[cfg(feature = "pure-rust")]
+        OutputFormat::Webp => {
+            let rgba = source.to_rgba8();
+            Ok(imager_core::vp8::encode_rgba(rgba.width(), rgba.height(), rgba.as_raw(), setting as u8)?)
+        }
+        OutputFormat::Png => png::compress(source, png::ImageMode::Text, setting as usize),
+        OutputFormat::Tiff => Ok(crate::codec::tiff::encode(source)),
+        OutputFormat::Avif => Ok(crate::codec::avif::encode(source, setting as u8)?),
+        OutputFormat::Jxl => Ok(crate::codec::jxl::encode(source, setting as u8)?),
+    }
+}
+
+fn score(
+    source: &DynamicImage,
+    encoded: &[u8],
+    format: &OutputFormat,
+    metric: Metric,
+) -> Result<f64, String> {
+    score_decoded(source, &decode(encoded, format)?, metric)
+}
+
+pub(crate) fn decode(encoded: &[u8], format: &OutputFormat) -> Result<DynamicImage, String> {
+    let image_format = match format {
+        OutputFormat::Jpeg => image::ImageFormat::Jpeg,
+        OutputFormat::Png => image::ImageFormat::Png,
+        OutputFormat::Webp => image::ImageFormat::WebP,
+        OutputFormat::Tiff => image::ImageFormat::Tiff,
+        OutputFormat::Avif => image::ImageFormat::Avif,
+        // THE IMAGE CRATE HAS NO JPEG XL DECODER
+        OutputFormat::Jxl => return Ok(crate::codec::jxl::decode(encoded)?),
+    };
+    let (decoded, _) = crate::decode::decode(encoded, image_format, &DecodeOptions::default())
+        .map_err(|error| format!("failed to decode the {:?} output: {}", format, error))?;
+    Ok(decoded)
+}
+
+fn score_decoded(source: &DynamicImage, decoded: &DynamicImage, metric: Metric) -> Result<f64, String> {
+    match metric {
+        #[cfg(not(feature = "pure-rust"))]
+        Metric::Vmaf => {
+            use crate::data::VideoBuffer;
+            let source = VideoBuffer::from_image(source).expect("to VideoBuffer");
+            let decoded = VideoBuffer::from_image(decoded).expect("to VideoBuffer");
+            Ok(crate::vmaf::get_report(&source, &decoded))
+        }
+        #[cfg(feature = "pure-rust")]
+        Metric::Vmaf => Err(FeatureDisabled::VMAF.into()),
+        Metric::Psnr => Ok(psnr(source, decoded)),
+        Metric::Dssim => Ok(crate::eval::ssim::dssim(source, decoded)),
+        Metric::Butteraugli => Ok(crate::eval::butteraugli::distance(source, decoded)),
+    }
+}
+
+/// Of the RGB channels, up to `MAX_PSNR`.
+pub fn psnr(a: &DynamicImage, b: &DynamicImage) -> f64 {
+    assert_eq!(a.dimensions(), b.dimensions());
+    let (a, b) = (a.to_rgb8(), b.to_rgb8());
+    let squared_error: f64 = a
+        .as_raw()
+        .iter()
+        .zip(b.as_raw())
+        .map(|(x, y)| (*x as f64 - *y as f64).powi(2))
+        .sum();
+    let mse = squared_error / a.as_raw().len().max(1) as f64;
+    if mse == 0.0 {
+        return MAX_PSNR;
+    }
+    (10.0 * (255.0 * 255.0 / mse).log10()).min(MAX_PSNR)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// OUTPUT
+///////////////////////////////////////////////////////////////////////////////
+
+pub fn to_csv(points: &[Point], metric: Metric) -> String {
+    let mut csv = format!("format,setting,size,{}\n", metric);
+    for point in points {
+        csv.push_str(&format!(
+            "{:?},{},{},{:.3}\n",
+            point.format, point.setting, point.size, point.score
+        ));
+    }
+    csv
+}
+
+const SVG_WIDTH: f64 = 640.0;
+const SVG_HEIGHT: f64 = 400.0;
+const SVG_MARGIN: f64 = 56.0;
+
+/// A line chart of the score by size (in KiB), one line per format.
+pub fn to_svg(points: &[Point], metric: Metric) -> String {
+    let max_size = points.iter().map(|x| x.size).max().unwrap_or(1).max(1) as f64 / 1024.0;
+    let min_score = points.iter().map(|x| x.score).fold(f64::INFINITY, f64::min);
+    let max_score = points.iter().map(|x| x.score).fold(f64::NEG_INFINITY, f64::max);
+    let (min_score, max_score) = if min_score < max_score {
+        (min_score, max_score)
+    } else {
+        (min_score.min(0.0), max_score.max(min_score + 1.0))
+    };
+    let (plot_width, plot_height) = (SVG_WIDTH - 2.0 * SVG_MARGIN, SVG_HEIGHT - 2.0 * SVG_MARGIN);
+    let x = |size: usize| SVG_MARGIN + size as f64 / 1024.0 / max_size * plot_width;
+    let y = |score: f64| SVG_MARGIN + (max_score - score) / (max_score - min_score) * plot_height;
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" font-family=\"sans-serif\" font-size=\"12\">\n\
+         <rect width=\"{w}\" height=\"{h}\" fill=\"white\"/>\n\
+         <path d=\"M{m} {m} V{b} H{r}\" fill=\"none\" stroke=\"#222\"/>\n\
+         <text x=\"{l}\" y=\"{t}\" text-anchor=\"end\">{max_score:.1}</text>\n\
+         <text x=\"{l}\" y=\"{b}\" text-anchor=\"end\">{min_score:.1}</text>\n\
+         <text x=\"{r}\" y=\"{bt}\" text-anchor=\"end\">{max_size:.1} KiB</text>\n\
+         <text x=\"{cx}\" y=\"{bt}\" text-anchor=\"middle\">size</text>\n\
+         <text x=\"{m}\" y=\"{lt}\">{metric}</text>\n",
+        w = SVG_WIDTH,
+        h = SVG_HEIGHT,
+        m = SVG_MARGIN,
+        b = SVG_HEIGHT - SVG_MARGIN,
+        r = SVG_WIDTH - SVG_MARGIN,
+        l = SVG_MARGIN - 6.0,
+        t = SVG_MARGIN + 4.0,
+        bt = SVG_HEIGHT - SVG_MARGIN + 18.0,
+        lt = SVG_MARGIN - 24.0,
+        cx = SVG_WIDTH / 2.0,
+        max_score = max_score,
+        min_score = min_score,
+        max_size = max_size,
+        metric = metric,
+    );
+    const COLORS: [&str; 3] = ["#d62728", "#1f77b4", "#2ca02c"];
+    let mut formats: Vec<&OutputFormat> = Vec::new();
+    for point in points {
+        if !formats.contains(&&point.format) {
+            formats.push(&point.format);
+        }
+    }
+    for (ix, format) in formats.into_iter().enumerate() {
+        let color = COLORS[ix % COLORS.len()];
+        let mut line = points.iter().filter(|x| &x.format == format).collect::<Vec<_>>();
+        line.sort_by_key(|x| x.size);
+        let path = line
+            .iter()
+            .map(|p| format!("{:.1},{:.1}", x(p.size), y(p.score)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        svg.push_str(&format!(
+            "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\"/>\n",
+            path, color
+        ));
+        svg.push_str(&format!(
+            "<text x=\"{}\" y=\"{}\" fill=\"{}\" text-anchor=\"end\">{:?}</text>\n",
+            SVG_WIDTH - SVG_MARGIN,
+            SVG_MARGIN + 16.0 * (ix as f64 + 1.0),
+            color,
+            format
+        ));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sweep() {
+        let source = image::load_from_memory(include_bytes!("../assets/test/1.jpeg"))
+            .expect("decode")
+            .thumbnail(96, 96);
+        let points = sweep(&source, &[OutputFormat::Jpeg], Metric::Psnr).expect("sweep");
+        assert_eq!(points.len(), QUALITIES.len());
+        let (first, last) = (&points[0], &points[points.len() - 1]);
+        assert!(first.size < last.size && first.score < last.score, "{:?}", points);
+        let csv = to_csv(&points, Metric::Psnr);
+        assert!(csv.starts_with("format,setting,size,psnr\nJpeg,10,"));
+        assert_eq!(to_svg(&points, Metric::Psnr).matches("<polyline").count(), 1);
+        assert_eq!(psnr(&source, &source), MAX_PSNR);
+    }
+}
@@ -0,0 +1,313 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Re-optimization plans (`imager replan`): which outputs of past batches,
+//! by their JSON reports (`--log-file`), are worth re-processing, i.e.
+//! those of an imager older than `--since-version`, of a codec release
+//! matched by `--codec` (e.g. `avif<0.5`, or `libwebp<=1.2.4`), or of
+//! a VMAF score below `--min-vmaf`.
+//!
+//! Reports are read in file name order (e.g. of dated log files), and only
+//! the latest entry of each output counts, so re-processed outputs drop out
+//! of later plans. Entries of reports predating the `versions` field match
+//! every version criterion, since they can’t be ruled out.
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::api::OutMeda;
+use crate::data::{InferOutputFormat, OutputFormat};
+use crate::report::Report;
+
+///////////////////////////////////////////////////////////////////////////////
+// VERSIONS
+///////////////////////////////////////////////////////////////////////////////
+
+/// Dotted numbers, e.g. `0.4` or `1.2.4`; trailing zeros don’t count (so
+/// `0.4` equals `0.4.0`).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version(Vec<u32>);
+
+impl std::str::FromStr for Version {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s
+            .trim_start_matches('v')
+            .split('.')
+            .map(|x| x.parse::<u32>().map_err(|_| format!("Unknown version {}", s)))
+            .collect::<Result<Vec<_>, _>>()?;
+        while parts.len() > 1 && parts.last() == Some(&0) {
+            parts.pop();
+        }
+        Ok(Version(parts))
+    }
+}
+
+/// Of `name`, in a reported version string: the version following it (e.g.
+/// `rav1e 0.6.6` in `1.16.2 (rav1e 0.6.6)`), else the first one.
+fn find_version(reported: &str, name: &str) -> Option<Version> {
+    let tokens = reported
+        .split(|x: char| x.is_whitespace() || x == '(' || x == ')' || x == ',')
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>();
+    let named = tokens
+        .windows(2)
+        .find(|pair| pair[0].eq_ignore_ascii_case(name))
+        .and_then(|pair| pair[1].parse().ok());
+    named.or_else(|| tokens.iter().find_map(|x| x.parse().ok()))
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// CRITERIA
+///////////////////////////////////////////////////////////////////////////////
+
+/// Of a codec’s version: `<`, `<=` or `=`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+}
+
+/// E.g. `avif<0.5`: outputs of the format (or encoder, or a library it
+/// reports, e.g. `rav1e`) at matching versions, i.e. of the library, if
+/// named, else of the encoder (e.g. libheif’s, of AVIF outputs).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodecFilter {
+    pub name: String,
+    pub comparison: Comparison,
+    pub version: Version,
+}
+
+impl std::str::FromStr for CodecFilter {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ix = s
+            .find(['<', '='])
+            .ok_or_else(|| format!("Unknown codec filter {}", s))?;
+        let (name, rest) = s.split_at(ix);
+        let (comparison, version) = match rest {
+            _ if rest.starts_with("<=") => (Comparison::LessOrEqual, &rest[2..]),
+            _ if rest.starts_with('<') => (Comparison::Less, &rest[1..]),
+            _ => (Comparison::Equal, rest.trim_start_matches('=')),
+        };
+        if name.is_empty() {
+            return Err(format!("Unknown codec filter {}", s));
+        }
+        Ok(CodecFilter {
+            name: name.to_lowercase(),
+            comparison,
+            version: version.parse()?,
+        })
+    }
+}
+
+impl CodecFilter {
+    /// Why the output matches, if it does.
+    fn matches(&self, output: &OutMeda, format: Option<&OutputFormat>) -> Option<String> {
+        let format_name = format.map(|x| x.extension());
+        let versions = match &output.versions {
+            Some(versions) => versions,
+            None if format_name == Some(self.name.as_str()) => {
+                return Some(format!("{}: unknown encoder version", self.name))
+            }
+            None => return None,
+        };
+        let reported = versions.encoder_version.as_deref().unwrap_or_default();
+        let named = format_name == Some(self.name.as_str())
+            || versions.encoder.eq_ignore_ascii_case(&self.name)
+            || reported.to_lowercase().contains(&self.name);
+        if !named {
+            return None;
+        }
+        let version = match find_version(reported, &self.name) {
+            Some(version) => version,
+            None => return Some(format!("{}: unknown encoder version", versions.encoder)),
+        };
+        let matched = matches!(
+            (version.cmp(&self.version), self.comparison),
+            (Ordering::Less, Comparison::Less | Comparison::LessOrEqual)
+                | (Ordering::Equal, Comparison::LessOrEqual | Comparison::Equal)
+        );
+        matched.then(|| format!("{} {}", versions.encoder, reported))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Criteria {
+    /// Outputs of older imager releases.
+    pub since_version: Option<Version>,
+    pub codecs: Vec<CodecFilter>,
+    pub min_vmaf: Option<f64>,
+}
+
+impl Criteria {
+    pub fn is_empty(&self) -> bool {
+        self.since_version.is_none() && self.codecs.is_empty() && self.min_vmaf.is_none()
+    }
+    /// Why the output is worth re-processing, if it is.
+    fn reasons(&self, output: &OutMeda) -> Vec<String> {
+        let mut reasons = Vec::new();
+        if let Some(since) = &self.since_version {
+            let imager = output.versions.as_ref().map(|x| x.imager.as_str());
+            match imager.map(str::parse::<Version>) {
+                Some(Ok(version)) if version >= *since => (),
+                Some(_) => reasons.push(format!("imager {}", imager.unwrap_or_default())),
+                None => reasons.push(String::from("unknown imager version")),
+            }
+        }
+        let format = output_format(output);
+        reasons.extend(self.codecs.iter().filter_map(|x| x.matches(output, format.as_ref())));
+        if let (Some(min), Some(score)) = (self.min_vmaf, output.vmaf_score) {
+            if score < min {
+                reasons.push(format!("VMAF {:.2}", score));
+            }
+        }
+        reasons
+    }
+}
+
+fn output_format(output: &OutMeda) -> Option<OutputFormat> {
+    match &output.fallback {
+        Some(fallback) => Some(fallback.output.clone()),
+        None => OutputFormat::infer_from_path(output.output_path.as_ref()?),
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PLANS
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlannedJob {
+    pub input_path: Option<PathBuf>,
+    pub output_path: Option<PathBuf>,
+    pub format: Option<OutputFormat>,
+    /// E.g. `libwebp 1.2.4`, per matched criterion.
+    pub reasons: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Plan {
+    /// By output path.
+    pub jobs: Vec<PlannedJob>,
+}
+
+impl Plan {
+    /// Of the reports, oldest first.
+    pub fn new(reports: &[Report], criteria: &Criteria) -> Self {
+        // THE LATEST ENTRY OF EACH OUTPUT
+        let mut latest = BTreeMap::new();
+        for output in reports.iter().flat_map(|x| &x.outputs) {
+            let key = output.output_path.clone().or_else(|| output.input_path.clone());
+            latest.insert(key, output);
+        }
+        let jobs = latest
+            .into_values()
+            .filter_map(|output| {
+                let reasons = criteria.reasons(output);
+                (!reasons.is_empty()).then(|| PlannedJob {
+                    input_path: output.input_path.clone(),
+                    output_path: output.output_path.clone(),
+                    format: output_format(output),
+                    reasons,
+                })
+            })
+            .collect();
+        Plan { jobs }
+    }
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("to json str failed")
+    }
+}
+
+/// The JSON reports (`*.json`) of the directory, by file name.
+pub fn open_reports(dir: &Path) -> Result<Vec<Report>, String> {
+    let mut paths = std::fs::read_dir(dir)
+        .map_err(|e| format!("{}: {}", dir.display(), e))?
+        .filter_map(|x| Some(x.ok()?.path()))
+        .filter(|x| x.extension().is_some_and(|x| x == "json"))
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths
+        .iter()
+        .map(|path| {
+            let source = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            Report::from_json(&source).map_err(|e| format!("{}: {}", path.display(), e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_plan() {
+        assert!("0.4".parse::<Version>() == "0.4.0".parse());
+        assert!("0.3.3".parse::<Version>() < "0.4".parse());
+        let filter = "rav1e<0.7".parse::<CodecFilter>().expect("filter");
+        assert_eq!((filter.name.as_str(), filter.comparison), ("rav1e", Comparison::Less));
+        assert!("<0.5".parse::<CodecFilter>().is_err());
+        let report = |outputs: &str| {
+            let json = format!(r#"{{"schema_version": 1, "outputs": [{}], "errors": []}}"#, outputs);
+            Report::from_json(&json).expect("report")
+        };
+        let output = |path: &str, imager: &str, encoder: &str, version: &str| {
+            format!(
+                r#"{{"input_class": "M1", "input_path": "in/{}.png", "output_path": "out/{}",
+                    "vmaf_score": 93.0, "extreme_mode": false,
+                    "versions": {{"imager": "{}", "encoder": "{}", "encoder_version": "{}"}}}}"#,
+                path, path, imager, encoder, version
+            )
+        };
+        let old = report(
+            &[
+                output("a.avif", "0.3.3", "libheif", "1.16.2 (rav1e 0.6.6)"),
+                output("b.webp", "0.3.3", "libwebp", "1.2.4"),
+            ]
+            .join(","),
+        );
+        // B WAS RE-PROCESSED SINCE
+        let new = report(&output("b.webp", "0.4.1", "libwebp", "1.3.2"));
+        let reports = [old, new];
+        let criteria = Criteria {
+            codecs: vec![filter, "libwebp<=1.2.4".parse().expect("filter")],
+            ..Criteria::default()
+        };
+        let plan = Plan::new(&reports, &criteria);
+        assert_eq!(plan.jobs.len(), 1);
+        // OF THE FORMAT, LIBHEIF’S VERSION
+        let avif = Criteria {
+            codecs: vec!["avif<1.17".parse().expect("filter")],
+            ..Criteria::default()
+        };
+        assert_eq!(Plan::new(&reports, &avif).jobs.len(), 1);
+        let avif = Criteria {
+            codecs: vec!["avif<0.5".parse().expect("filter")],
+            ..Criteria::default()
+        };
+        assert!(Plan::new(&reports, &avif).jobs.is_empty());
+        assert_eq!(plan.jobs[0].format, Some(OutputFormat::Avif));
+        assert_eq!(plan.jobs[0].reasons, ["libheif 1.16.2 (rav1e 0.6.6)"]);
+        let criteria = Criteria {
+            since_version: "0.4".parse().ok(),
+            min_vmaf: Some(95.0),
+            ..Criteria::default()
+        };
+        let plan = Plan::new(&reports, &criteria);
+        assert_eq!(plan.jobs[0].reasons, ["imager 0.3.3", "VMAF 93.00"]);
+        assert_eq!(plan.jobs[1].reasons, ["VMAF 93.00"]);
+        // UNVERSIONED ENTRIES CAN’T BE RULED OUT
+        let unversioned = report(r#"{"input_class": "M1", "output_path": "out/c.avif"}"#);
+        let plan = Plan::new(
+            &[unversioned],
+            &Criteria {
+                since_version: "0.4".parse().ok(),
+                ..Criteria::default()
+            },
+        );
+        assert_eq!(plan.jobs[0].reasons, ["unknown imager version"]);
+    }
+}
@@ -0,0 +1,420 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Batch reports, as written by `--log-file`. See `imager_core::report`
+//! for the schema and its versioning rules.
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::api::OutMeda;
+use crate::data::OutputFormat;
+
+pub use imager_core::report::{
+    check_schema_version, unversioned_schema, DistanceCheck, Fallback, FileErrorKind, Versions, Warning,
+    WarningKind, REPORT_JSON_SCHEMA, REPORT_SCHEMA_VERSION,
+};
+
+///////////////////////////////////////////////////////////////////////////////
+// FILE ERRORS
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileError {
+    pub input_path: PathBuf,
+    pub output_format: OutputFormat,
+    pub kind: FileErrorKind,
+    pub message: String,
+}
+
+impl std::fmt::Display for FileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({:?}): {} error: {}",
+            self.input_path.display(),
+            self.output_format,
+            self.kind,
+            self.message
+        )
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// REPORT
+///////////////////////////////////////////////////////////////////////////////
+
+/// How reports are written: JSON (per the schema), CSV (one row per
+/// output or error), or an HTML gallery (see `crate::gallery`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+    Html,
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(ReportFormat::Json),
+            "csv" => Ok(ReportFormat::Csv),
+            "html" => Ok(ReportFormat::Html),
+            _ => Err(format!("Unknown report format {}", s)),
+        }
+    }
+}
+
+/// The outcome of a batch: one entry per input file and output format,
+/// either in `outputs` or in `errors`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    #[serde(default = "unversioned_schema")]
+    pub schema_version: u32,
+    pub outputs: Vec<OutMeda>,
+    pub errors: Vec<FileError>,
+}
+
+impl Default for Report {
+    fn default() -> Self {
+        Report {
+            schema_version: REPORT_SCHEMA_VERSION,
+            outputs: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+}
+
+impl Report {
+    pub fn from_json(source: &str) -> Result<Self, String> {
+        let value: serde_json::Value = serde_json::from_str(source).map_err(|e| e.to_string())?;
+        check_schema_version(&value, REPORT_SCHEMA_VERSION)?;
+        serde_json::from_value(value).map_err(|e| e.to_string())
+    }
+    pub fn from_results<I: IntoIterator<Item = Result<OutMeda, FileError>>>(results: I) -> Self {
+        let mut report = Report::default();
+        for result in results {
+            match result {
+                Ok(x) => report.outputs.push(x),
+                Err(x) => report.errors.push(x),
+            }
+        }
+        report
+    }
+    pub fn is_success(&self) -> bool {
+        self.errors.is_empty()
+    }
+    pub fn warning_count(&self) -> usize {
+        self.outputs.iter().map(|x| x.warnings.len()).sum()
+    }
+    /// The report as written to `path`, in the given format.
+    pub fn render(&self, format: ReportFormat, path: &std::path::Path) -> String {
+        match format {
+            ReportFormat::Json => serde_json::to_string_pretty(self).expect("to json str failed"),
+            ReportFormat::Csv => self.to_csv(),
+            ReportFormat::Html => {
+                let dir = path.parent().unwrap_or_else(|| std::path::Path::new(""));
+                crate::gallery::render(self, dir)
+            }
+        }
+    }
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(CSV_HEADER);
+        csv.push('\n');
+        let path = |x: &Option<PathBuf>| {
+            x.as_ref()
+                .map(|x| x.display().to_string())
+                .unwrap_or_default()
+        };
+        let number = |x: Option<u64>| x.map(|x| x.to_string()).unwrap_or_default();
+        for output in &self.outputs {
+            let row = [
+                path(&output.input_path),
+                path(&output.output_path),
+                format!("{:?}", output.input_class),
+                output
+                    .decoder
+                    .as_ref()
+                    .map(|x| format!("{:?}", x))
+                    .unwrap_or_default(),
+                number(output.input_size),
+                number(output.output_size),
+                output
+                    .savings()
+                    .map(|x| format!("{:.4}", x))
+                    .unwrap_or_default(),
+                output
+                    .vmaf_score
+                    .map(|x| format!("{:.2}", x))
+                    .unwrap_or_default(),
+                number(output.duration_ms),
+                output
+                    .warnings
+                    .iter()
+                    .map(|x| x.kind.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                String::new(),
+                String::new(),
+            ];
+            csv_row(&mut csv, &row);
+        }
+        for error in &self.errors {
+            let mut row: [String; 12] = Default::default();
+            row[0] = error.input_path.display().to_string();
+            row[10] = error.kind.to_string();
+            row[11] = error.message.clone();
+            csv_row(&mut csv, &row);
+        }
+        csv
+    }
+    pub fn summary(&self) -> Summary {
+        let mut savings = self
+            .outputs
+            .iter()
+            .filter_map(OutMeda::savings)
+            .collect::<Vec<_>>();
+        savings.sort_by(f64::total_cmp);
+        let percentile = |p: usize| {
+            // NEAREST RANK
+            let rank = (p * savings.len()).div_ceil(100);
+            savings.get(rank.max(1) - 1).copied()
+        };
+        let mut slowest = self
+            .outputs
+            .iter()
+            .filter_map(|x| Some((x.input_path.clone()?, x.duration_ms?)))
+            .collect::<Vec<_>>();
+        slowest.sort_by_key(|x| std::cmp::Reverse(x.1));
+        slowest.truncate(SLOWEST_FILES);
+        Summary {
+            outputs: self.outputs.len(),
+            errors: self.errors.len(),
+            warnings: self.warning_count(),
+            input_bytes: self.outputs.iter().filter_map(|x| x.input_size).sum(),
+            output_bytes: self.outputs.iter().filter_map(|x| x.output_size).sum(),
+            savings_percentiles: [percentile(10), percentile(50), percentile(90)],
+            slowest,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// CSV
+///////////////////////////////////////////////////////////////////////////////
+
+const CSV_HEADER: &str = "input_path,output_path,input_class,decoder,input_size,output_size,\
+                          savings,vmaf_score,duration_ms,warnings,error_kind,error_message";
+
+/// Appends the RFC 4180 row.
+fn csv_row(csv: &mut String, fields: &[String]) {
+    for (ix, field) in fields.iter().enumerate() {
+        if ix > 0 {
+            csv.push(',');
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            csv.push('"');
+            csv.push_str(&field.replace('"', "\"\""));
+            csv.push('"');
+        } else {
+            csv.push_str(field);
+        }
+    }
+    csv.push('\n');
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// SUMMARY
+///////////////////////////////////////////////////////////////////////////////
+
+/// The number of files in `Summary::slowest`.
+pub const SLOWEST_FILES: usize = 5;
+
+/// End of run statistics of a report.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub outputs: usize,
+    pub errors: usize,
+    pub warnings: usize,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    /// The 10th, 50th and 90th percentile of `OutMeda::savings`.
+    pub savings_percentiles: [Option<f64>; 3],
+    /// The inputs that took longest (in milliseconds), slowest first.
+    pub slowest: Vec<(PathBuf, u64)>,
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} outputs, {} errors, {} warnings",
+            self.outputs, self.errors, self.warnings
+        )?;
+        let total = if self.input_bytes > 0 {
+            1.0 - self.output_bytes as f64 / self.input_bytes as f64
+        } else {
+            0.0
+        };
+        writeln!(
+            f,
+            "{} → {} bytes ({:.1}% saved)",
+            self.input_bytes,
+            self.output_bytes,
+            total * 100.0
+        )?;
+        if let [Some(p10), Some(p50), Some(p90)] = self.savings_percentiles {
+            writeln!(
+                f,
+                "savings: p10 {:.1}%, p50 {:.1}%, p90 {:.1}%",
+                p10 * 100.0,
+                p50 * 100.0,
+                p90 * 100.0
+            )?;
+        }
+        for (path, ms) in &self.slowest {
+            writeln!(f, "slow: {} ({} ms)", path.display(), ms)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_error_kind_json() {
+        let error = FileError {
+            input_path: PathBuf::from("a.png"),
+            output_format: OutputFormat::Webp,
+            kind: FileErrorKind::TooLarge,
+            message: String::from("20000x100 exceeds 16382"),
+        };
+        let report = Report::from_results(vec![Err(error)]);
+        let json = serde_json::to_value(&report).expect("to json");
+        assert_eq!(json["errors"][0]["kind"], "too-large");
+        assert!(!report.is_success());
+    }
+
+    /// Every serialized key must be declared in the published schema.
+    #[test]
+    fn test_report_matches_schema() {
+        let schema: serde_json::Value = serde_json::from_str(REPORT_JSON_SCHEMA).expect("schema");
+        assert_eq!(schema["properties"]["schema_version"]["const"], REPORT_SCHEMA_VERSION);
+        let output = OutMeda {
+            input_class: crate::classifier::Class::L1,
+            input_path: Some(PathBuf::from("a.jpeg")),
+            output_path: Some(PathBuf::from("out/a.webp")),
+            vmaf_score: Some(90.0),
+            distance: Some(DistanceCheck {
+                distance: 1.2,
+                max_distance: 1.5,
+                passed: true,
+            }),
+            extreme_mode: Some(false),
+            decoder: Some(crate::decode::Decoder::Image),
+            c2pa: Some(crate::meta::c2pa::C2paReport {
+                source_manifests: vec![String::from("urn:uuid:0")],
+                signed: false,
+            }),
+            warnings: vec![Warning::new(WarningKind::Upscaled, "enlarged")],
+            fallback: Some(Fallback {
+                requested: OutputFormat::Avif,
+                output: OutputFormat::Webp,
+                errors: vec![String::from("Avif: encoder panicked")],
+            }),
+            versions: Some(Versions {
+                imager: String::from("0.3.3"),
+                encoder: String::from("libwebp"),
+                encoder_version: Some(String::from("1.2.4")),
+            }),
+            input_size: Some(2048),
+            output_size: Some(512),
+            duration_ms: Some(1200),
+        };
+        let error = FileError {
+            input_path: PathBuf::from("b.jpeg"),
+            output_format: OutputFormat::Jpeg,
+            kind: FileErrorKind::Decode,
+            message: String::new(),
+        };
+        let report = Report::from_results(vec![Ok(output), Err(error)]);
+        let json = serde_json::to_value(&report).expect("to json");
+        let check = |value: &serde_json::Value, schema: &serde_json::Value| {
+            let keys = value.as_object().expect("object").keys();
+            for key in keys {
+                assert!(schema["properties"].get(key).is_some(), "undeclared key {}", key);
+            }
+            for key in schema["required"].as_array().expect("required") {
+                assert!(value.get(key.as_str().expect("key")).is_some());
+            }
+        };
+        let definitions = &schema["definitions"];
+        check(&json, &schema);
+        check(&json["outputs"][0], &definitions["output"]);
+        check(&json["errors"][0], &definitions["file_error"]);
+        check(&json["outputs"][0]["warnings"][0], &definitions["warning"]);
+        check(&json["outputs"][0]["fallback"], &definitions["output"]["properties"]["fallback"]);
+        check(&json["outputs"][0]["versions"], &definitions["output"]["properties"]["versions"]);
+        check(&json["outputs"][0]["distance"], &definitions["output"]["properties"]["distance"]);
+        assert_eq!(report.warning_count(), 1);
+    }
+
+    #[test]
+    fn test_csv_and_summary() {
+        let output = |name: &str, input_size, output_size, duration_ms| OutMeda {
+            input_class: crate::classifier::Class::M1,
+            input_path: Some(PathBuf::from(name)),
+            output_path: None,
+            vmaf_score: None,
+            distance: None,
+            extreme_mode: None,
+            decoder: None,
+            c2pa: None,
+            warnings: Vec::new(),
+            fallback: None,
+            versions: None,
+            input_size: Some(input_size),
+            output_size: Some(output_size),
+            duration_ms: Some(duration_ms),
+        };
+        let error = FileError {
+            input_path: PathBuf::from("c.png"),
+            output_format: OutputFormat::Png,
+            kind: FileErrorKind::Io,
+            message: String::from("denied, \"twice\""),
+        };
+        let report = Report::from_results(vec![
+            Ok(output("a,1.png", 1000, 500, 20)),
+            Ok(output("b.png", 1000, 900, 40)),
+            Err(error),
+        ]);
+        let csv = report.to_csv();
+        let rows = csv.lines().collect::<Vec<_>>();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0].split(',').count(), 12);
+        assert!(rows[1].starts_with("\"a,1.png\",,M1,,1000,500,0.5000,,20,"));
+        assert!(rows[3].ends_with(",io,\"denied, \"\"twice\"\"\""));
+        let summary = report.summary();
+        assert_eq!((summary.input_bytes, summary.output_bytes), (2000, 1400));
+        assert_eq!(summary.savings_percentiles[1].map(|x| (x * 100.0).round()), Some(10.0));
+        assert_eq!(summary.slowest[0], (PathBuf::from("b.png"), 40));
+    }
+
+    #[test]
+    fn test_report_compat() {
+        // AS WRITTEN BY v1
+        let v1 = r#"{
+            "schema_version": 1,
+            "outputs": [{"input_class": "H1", "input_path": "a.png", "output_path": "b.png",
+                         "vmaf_score": null, "extreme_mode": false, "decoder": "Turbo"}],
+            "errors": [{"input_path": "c.png", "output_format": "Png", "kind": "io",
+                        "message": "denied"}]
+        }"#;
+        let report = Report::from_json(v1).expect("parse v1 report");
+        assert_eq!(report.outputs.len(), 1);
+        assert_eq!(report.errors[0].kind, FileErrorKind::Io);
+        let v2 = r#"{"schema_version": 2, "outputs": [], "errors": []}"#;
+        assert!(Report::from_json(v2).is_err());
+    }
+}
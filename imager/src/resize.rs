@@ -0,0 +1,168 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Resizing to the `max_size` (the box), by fit mode and filter, before
+//! encoding (for every output format). With a box of `W`x`H`:
+//!
+//! - `inside` (the default): as large as fits in the box.
+//! - `outside`: as small as covers the box.
+//! - `contain`: `inside`, then padded (centered) to `W`x`H`, transparent
+//!   for sources with alpha, else black.
+//! - `cover`: `outside`, then cropped to `W`x`H`, at the center, or by
+//!   the content (see `crop`).
+//! - `fill`: stretched to `W`x`H`.
+//!
+//! Sources are only enlarged given an upscaler (by at most `MAX_UPSCALE`),
+//! so that without one, `contain` pads smaller sources to the box, and
+//! `cover` and `fill` crop or stretch no more than the source allows (i.e.
+//! their outputs are never larger than the source, on either side).
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, RgbImage, RgbaImage};
+
+pub use imager_core::data::{Fit, ResizeFilter};
+
+use crate::crop::Crop;
+use crate::data::Resolution;
+use crate::upscale::{Upscaler, MAX_UPSCALE};
+
+/// Of a resize: the source is scaled to `scaled`, then padded or cropped
+/// to `output`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Geometry {
+    pub scaled: (u32, u32),
+    pub output: (u32, u32),
+}
+
+impl Geometry {
+    /// Of the fit of `source` to the box; `upscale` allows enlarging.
+    pub fn new((width, height): (u32, u32), max_size: &Resolution, fit: Fit, upscale: bool) -> Self {
+        let limit = if upscale { MAX_UPSCALE as f64 } else { 1.0 };
+        let ratio_x = (max_size.width as f64 / width as f64).min(limit);
+        let ratio_y = (max_size.height as f64 / height as f64).min(limit);
+        let scale = |ratio: f64, side: u32| ((side as f64 * ratio).round() as u32).max(1);
+        let uniform = |ratio: f64| (scale(ratio, width), scale(ratio, height));
+        let box_size = (max_size.width, max_size.height);
+        let (scaled, output) = match fit {
+            Fit::Inside => (uniform(ratio_x.min(ratio_y)), None),
+            Fit::Outside => (uniform(ratio_x.max(ratio_y)), None),
+            Fit::Contain => (uniform(ratio_x.min(ratio_y)), Some(box_size)),
+            Fit::Cover => {
+                let scaled = uniform(ratio_x.max(ratio_y));
+                (scaled, Some((scaled.0.min(box_size.0), scaled.1.min(box_size.1))))
+            }
+            Fit::Fill => ((scale(ratio_x, width), scale(ratio_y, height)), None),
+        };
+        Geometry {
+            scaled,
+            output: output.unwrap_or(scaled),
+        }
+    }
+    /// Whether the source is enlarged, on either side.
+    pub fn enlarges(&self, (width, height): (u32, u32)) -> bool {
+        self.scaled.0 > width || self.scaled.1 > height
+    }
+}
+
+pub fn filter_type(filter: ResizeFilter) -> FilterType {
+    match filter {
+        ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        ResizeFilter::CatmullRom => FilterType::CatmullRom,
+        ResizeFilter::Triangle => FilterType::Triangle,
+    }
+}
+
+/// The source, scaled by the filter (or enlarged by the upscaler, if given
+/// and the geometry enlarges), then padded or cropped (by the crop).
+pub fn resize(
+    source: &DynamicImage,
+    geometry: &Geometry,
+    filter: ResizeFilter,
+    upscaler: Option<Upscaler>,
+    crop: Crop,
+) -> Result<DynamicImage, String> {
+    let (width, height) = geometry.scaled;
+    let scaled = match upscaler {
+        _ if source.dimensions() == geometry.scaled => source.clone(),
+        Some(upscaler) if geometry.enlarges(source.dimensions()) => {
+            crate::upscale::upscale(source, geometry.scaled, upscaler)?
+        }
+        _ => source.resize_exact(width, height, filter_type(filter)),
+    };
+    Ok(place(scaled, geometry.output, crop))
+}
+
+/// Crops (the window of the crop) or pads (centered, transparent for
+/// images with alpha, else black) the image to the given size, as needed.
+pub fn place(image: DynamicImage, (width, height): (u32, u32), crop: Crop) -> DynamicImage {
+    if image.dimensions() == (width, height) {
+        return image;
+    }
+    let offset = |side: u32, canvas: u32| side.saturating_sub(canvas) / 2;
+    let window = (image.width().min(width), image.height().min(height));
+    let (left, top) = crate::crop::window(&image, window, crop);
+    let cropped = image.crop_imm(left, top, window.0, window.1);
+    if cropped.dimensions() == (width, height) {
+        return cropped;
+    }
+    let (x, y) = (offset(width, cropped.width()), offset(height, cropped.height()));
+    if image.color().has_alpha() {
+        let mut canvas = RgbaImage::new(width, height);
+        image::imageops::overlay(&mut canvas, &cropped.to_rgba8(), x.into(), y.into());
+        DynamicImage::ImageRgba8(canvas)
+    } else {
+        let mut canvas = RgbImage::new(width, height);
+        image::imageops::overlay(&mut canvas, &cropped.to_rgb8(), x.into(), y.into());
+        DynamicImage::ImageRgb8(canvas)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fits() {
+        let target = Resolution::new(300, 300);
+        let geometry = |fit: Fit, upscale: bool| Geometry::new((1200, 600), &target, fit, upscale);
+        assert_eq!(geometry(Fit::Inside, false).output, (300, 150));
+        assert_eq!(geometry(Fit::Outside, false).output, (600, 300));
+        assert_eq!(
+            geometry(Fit::Contain, false),
+            Geometry {
+                scaled: (300, 150),
+                output: (300, 300)
+            }
+        );
+        assert_eq!(
+            geometry(Fit::Cover, false),
+            Geometry {
+                scaled: (600, 300),
+                output: (300, 300)
+            }
+        );
+        assert_eq!(geometry(Fit::Fill, false).output, (300, 300));
+        // SMALLER SOURCES ARE ONLY ENLARGED GIVEN AN UPSCALER
+        let small = |fit: Fit, upscale: bool| Geometry::new((200, 100), &target, fit, upscale).output;
+        assert_eq!(small(Fit::Cover, false), (200, 100));
+        assert_eq!(small(Fit::Fill, false), (200, 100));
+        assert_eq!(small(Fit::Contain, false), (300, 300));
+        assert_eq!(small(Fit::Cover, true), (300, 300));
+        assert!(Geometry::new((200, 100), &target, Fit::Fill, true).enlarges((200, 100)));
+        // PADDED, AND CROPPED, CENTERED
+        let source = DynamicImage::ImageRgb8(RgbImage::from_pixel(1200, 600, image::Rgb([200, 10, 10])));
+        let contain = geometry(Fit::Contain, false);
+        let contained = resize(&source, &contain, ResizeFilter::Triangle, None, Crop::Centre).expect("resize");
+        assert_eq!(contained.dimensions(), (300, 300));
+        assert_eq!(contained.get_pixel(150, 10).0, [0, 0, 0, 255]);
+        assert_eq!(contained.get_pixel(150, 150).0, [200, 10, 10, 255]);
+        let cover = geometry(Fit::Cover, false);
+        let covered = resize(&source, &cover, ResizeFilter::CatmullRom, None, Crop::Centre).expect("resize");
+        assert_eq!(covered.dimensions(), (300, 300));
+        let translucent = DynamicImage::ImageRgba8(RgbaImage::new(100, 50));
+        assert_eq!(place(translucent, (100, 100), Crop::Entropy).get_pixel(50, 5).0, [0, 0, 0, 0]);
+        assert_eq!(
+            "catmull-rom".parse::<ResizeFilter>().map(filter_type),
+            Ok(FilterType::CatmullRom)
+        );
+    }
+}
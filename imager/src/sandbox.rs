@@ -0,0 +1,449 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Sandboxed jobs: decoding (and encoding) untrusted sources in a
+//! subprocess, so a codec exploit can’t compromise the calling service.
+//!
+//! The worker is the current executable, run as `imager sandbox-worker`
+//! (other executables must call `worker_main` for that subcommand). It
+//! gets the pipeline and the source over stdin, and returns the output
+//! over stdout; its environment is cleared, and before reading the source
+//! it confines itself:
+//!
+//! - rlimits: address space, CPU time, no file writes, few descriptors;
+//!   and a wall-clock timeout, since a worker blocked in a syscall uses
+//!   no CPU time.
+//! - seccomp: no `execve`, forks, sockets, file creation or writes,
+//!   renames, deletes, ptrace, signals to other processes, or namespace,
+//!   module and mount changes. (Files can still be read, e.g. by libvmaf
+//!   loading the parent’s model, see `vmaf::MODEL_VAR`; the worker has
+//!   nowhere to send them but its own output.)
+//!
+//! Seccomp requires Linux on x86_64 or aarch64; elsewhere `run` fails
+//! rather than run unconfined.
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::api::OutMeda;
+use crate::pipeline::Pipeline;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    /// Address space, in bytes.
+    pub memory: u64,
+    pub cpu_seconds: u64,
+    /// After which the parent kills the worker.
+    pub wall_seconds: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            memory: 4 << 30,
+            cpu_seconds: 120,
+            wall_seconds: 300,
+        }
+    }
+}
+
+pub const SUPPORTED: bool = cfg!(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+));
+
+///////////////////////////////////////////////////////////////////////////////
+// PARENT
+///////////////////////////////////////////////////////////////////////////////
+
+/// Like `pipeline::run`, in a confined worker process.
+pub fn run(pipeline: &Pipeline, source: &[u8], limits: &Limits) -> Result<(Vec<u8>, OutMeda), String> {
+    if !SUPPORTED {
+        return Err(String::from("sandboxing requires Linux on x86_64 or aarch64"));
+    }
+    pipeline.validate()?;
+    let executable = std::env::current_exe().map_err(|e| e.to_string())?;
+    let mut command = Command::new(executable);
+    command.env_clear();
+    #[cfg(not(feature = "pure-rust"))]
+    command.env(crate::vmaf::MODEL_VAR, crate::vmaf::model_path());
+    let mut child = command
+        .arg("sandbox-worker")
+        .arg("--memory")
+        .arg(limits.memory.to_string())
+        .arg("--cpu-seconds")
+        .arg(limits.cpu_seconds.to_string())
+        .args(
+            crate::codec::registry::plugin_paths()
+                .iter()
+                .flat_map(|path| [std::ffi::OsStr::new("--plugin"), path.as_os_str()]),
+        )
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to start the sandbox: {}", e))?;
+    let request = pipeline.to_json();
+    let deadline = Instant::now() + Duration::from_secs(limits.wall_seconds);
+    let (status, stdout, stderr) = std::thread::scope(|scope| {
+        let mut stdin = child.stdin.take().expect("piped stdin");
+        // A WORKER THAT DIES EARLY BREAKS THE PIPE; ITS STATUS SAYS WHY
+        scope.spawn(move || {
+            let _ = stdin
+                .write_all(&(request.len() as u32).to_le_bytes())
+                .and_then(|()| stdin.write_all(request.as_bytes()))
+                .and_then(|()| stdin.write_all(source));
+        });
+        let read = |mut pipe: Box<dyn Read + Send>| {
+            scope.spawn(move || {
+                let mut data = Vec::new();
+                let _ = pipe.read_to_end(&mut data);
+                data
+            })
+        };
+        let stdout = read(Box::new(child.stdout.take().expect("piped stdout")));
+        let stderr = read(Box::new(child.stderr.take().expect("piped stderr")));
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break Ok(Some(status)),
+                Ok(None) if Instant::now() >= deadline => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    break Ok(None);
+                }
+                Ok(None) => std::thread::sleep(Duration::from_millis(5)),
+                Err(error) => break Err(error.to_string()),
+            }
+        };
+        let join = |x: std::thread::ScopedJoinHandle<Vec<u8>>| x.join().unwrap_or_default();
+        (status, join(stdout), join(stderr))
+    });
+    let Some(status) = status? else {
+        return Err(format!("the sandboxed worker timed out ({}s)", limits.wall_seconds));
+    };
+    if !status.success() {
+        let stderr = String::from_utf8_lossy(&stderr).trim().to_string();
+        return Err(match (stderr.is_empty(), signal(&status)) {
+            (true, Some(signal)) => format!("the sandboxed worker was killed (signal {})", signal),
+            (true, None) => format!("the sandboxed worker failed ({})", status),
+            (false, _) => stderr,
+        });
+    }
+    let (meta, output) = split_frame(&stdout).ok_or("invalid sandbox response")?;
+    let meta: OutMeda = serde_json::from_slice(meta).map_err(|e| e.to_string())?;
+    Ok((output.to_vec(), meta))
+}
+
+#[cfg(unix)]
+fn signal(status: &std::process::ExitStatus) -> Option<i32> {
+    std::os::unix::process::ExitStatusExt::signal(status)
+}
+
+#[cfg(not(unix))]
+fn signal(_: &std::process::ExitStatus) -> Option<i32> {
+    None
+}
+
+/// A length prefixed (u32 LE) header, and the rest.
+fn split_frame(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let length = u32::from_le_bytes(data.get(..4)?.try_into().ok()?) as usize;
+    let rest = &data[4..];
+    Some((rest.get(..length)?, &rest[length..]))
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// WORKER
+///////////////////////////////////////////////////////////////////////////////
+
+/// The `sandbox-worker` subcommand; returns the exit code. Plugins are
+/// loaded before confining.
+pub fn worker_main(limits: &Limits, plugins: &[PathBuf]) -> i32 {
+    let result = plugins
+        .iter()
+        .try_for_each(|path| crate::codec::registry::load_plugin(path).map(drop))
+        .and_then(|()| confine(limits))
+        .and_then(|()| {
+            let mut request = Vec::new();
+            std::io::stdin()
+                .read_to_end(&mut request)
+                .map_err(|e| e.to_string())?;
+            let (pipeline, source) = split_frame(&request).ok_or("invalid sandbox request")?;
+            let pipeline = std::str::from_utf8(pipeline).map_err(|e| e.to_string())?;
+            let pipeline = Pipeline::from_json(pipeline)?;
+            let (output, meta) = crate::pipeline::run(&pipeline, source)?;
+            let meta = serde_json::to_vec(&meta).map_err(|e| e.to_string())?;
+            let mut stdout = std::io::stdout();
+            stdout
+                .write_all(&(meta.len() as u32).to_le_bytes())
+                .and_then(|()| stdout.write_all(&meta))
+                .and_then(|()| stdout.write_all(&output))
+                .and_then(|()| stdout.flush())
+                .map_err(|e| e.to_string())
+        });
+    match result {
+        Ok(()) => 0,
+        Err(message) => {
+            eprintln!("{}", message);
+            1
+        }
+    }
+}
+
+/// Applies the limits and the seccomp filter to the current process, for
+/// good.
+pub fn confine(limits: &Limits) -> Result<(), String> {
+    #[cfg(unix)]
+    {
+        let set = |resource, value: u64| {
+            let limit = libc::rlimit {
+                rlim_cur: value as libc::rlim_t,
+                rlim_max: value as libc::rlim_t,
+            };
+            match unsafe { libc::setrlimit(resource, &limit) } {
+                0 => Ok(()),
+                _ => Err(format!("setrlimit failed: {}", std::io::Error::last_os_error())),
+            }
+        };
+        set(libc::RLIMIT_AS, limits.memory)?;
+        set(libc::RLIMIT_CPU, limits.cpu_seconds)?;
+        set(libc::RLIMIT_FSIZE, 0)?;
+        set(libc::RLIMIT_NOFILE, 64)?;
+        set(libc::RLIMIT_CORE, 0)?;
+    }
+    #[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+    seccomp::install()?;
+    Ok(())
+}
+
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod seccomp {
+    use libc::{sock_filter, sock_fprog, BPF_ABS, BPF_JEQ, BPF_JMP, BPF_JSET, BPF_K, BPF_LD, BPF_RET, BPF_W};
+    #[cfg(target_arch = "x86_64")]
+    use libc::BPF_JGE;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000_003e;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+    /// Of x32 syscall numbers, which share x86_64’s `AUDIT_ARCH`.
+    #[cfg(target_arch = "x86_64")]
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    // OFFSETS INTO `struct seccomp_data`
+    const NR: u32 = 0;
+    const ARCH: u32 = 4;
+    const fn arg(ix: u32) -> u32 {
+        16 + ix * 8
+    }
+
+    const DENIED: &[libc::c_long] = &[
+        libc::SYS_execve,
+        libc::SYS_execveat,
+        libc::SYS_socket,
+        libc::SYS_socketpair,
+        libc::SYS_connect,
+        libc::SYS_bind,
+        libc::SYS_listen,
+        libc::SYS_accept,
+        libc::SYS_accept4,
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_kill,
+        libc::SYS_unlinkat,
+        libc::SYS_renameat,
+        libc::SYS_renameat2,
+        libc::SYS_mkdirat,
+        libc::SYS_linkat,
+        libc::SYS_symlinkat,
+        libc::SYS_fchmod,
+        libc::SYS_fchmodat,
+        libc::SYS_fchown,
+        libc::SYS_fchownat,
+        libc::SYS_truncate,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_chroot,
+        libc::SYS_pivot_root,
+        libc::SYS_setns,
+        libc::SYS_unshare,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+        libc::SYS_io_uring_setup,
+        libc::SYS_personality,
+        libc::SYS_openat2,
+        libc::SYS_setuid,
+        libc::SYS_setgid,
+        libc::SYS_reboot,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_fork,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_vfork,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_creat,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_unlink,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_rename,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_mkdir,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_rmdir,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_link,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_symlink,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_chmod,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_chown,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_lchown,
+    ];
+
+    const WRITE_FLAGS: u32 = (libc::O_WRONLY | libc::O_RDWR | libc::O_CREAT | libc::O_TRUNC) as u32;
+
+    fn statement(code: u32, k: u32) -> sock_filter {
+        sock_filter {
+            code: code as u16,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    fn jump(code: u32, k: u32, jt: u8, jf: u8) -> sock_filter {
+        sock_filter {
+            code: code as u16,
+            jt,
+            jf,
+            k,
+        }
+    }
+
+    fn errno(code: i32) -> sock_filter {
+        statement(BPF_RET | BPF_K, libc::SECCOMP_RET_ERRNO | code as u32)
+    }
+
+    fn allow() -> sock_filter {
+        statement(BPF_RET | BPF_K, libc::SECCOMP_RET_ALLOW)
+    }
+
+    /// Denies `nr` when its `arg` has any (`deny_set`) or none
+    /// (`!deny_set`) of the `mask` bits.
+    fn check_flags(filter: &mut Vec<sock_filter>, nr: libc::c_long, ix: u32, mask: u32, deny_set: bool) {
+        filter.push(jump(BPF_JMP | BPF_JEQ | BPF_K, nr as u32, 0, 4));
+        filter.push(statement(BPF_LD | BPF_W | BPF_ABS, arg(ix)));
+        let (jt, jf) = if deny_set { (0, 1) } else { (1, 0) };
+        filter.push(jump(BPF_JMP | BPF_JSET | BPF_K, mask, jt, jf));
+        filter.push(errno(libc::EPERM));
+        filter.push(allow());
+    }
+
+    pub fn filter() -> Vec<sock_filter> {
+        let mut filter = vec![
+            statement(BPF_LD | BPF_W | BPF_ABS, ARCH),
+            jump(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH, 1, 0),
+            statement(BPF_RET | BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+            statement(BPF_LD | BPF_W | BPF_ABS, NR),
+        ];
+        // OTHERWISE EVERY DENIED SYSCALL IS ALLOWED BY ITS x32 ALIAS
+        #[cfg(target_arch = "x86_64")]
+        filter.extend([
+            jump(BPF_JMP | BPF_JGE | BPF_K, X32_SYSCALL_BIT, 0, 1),
+            statement(BPF_RET | BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        ]);
+        for nr in DENIED {
+            filter.push(jump(BPF_JMP | BPF_JEQ | BPF_K, *nr as u32, 0, 1));
+            filter.push(errno(libc::EPERM));
+        }
+        // SO GLIBC FALLS BACK TO `clone`, WHERE THE FLAGS CAN BE CHECKED
+        filter.push(jump(BPF_JMP | BPF_JEQ | BPF_K, libc::SYS_clone3 as u32, 0, 1));
+        filter.push(errno(libc::ENOSYS));
+        // THREADS, BUT NOT PROCESSES
+        check_flags(&mut filter, libc::SYS_clone, 0, libc::CLONE_THREAD as u32, false);
+        // READ-ONLY OPENS
+        check_flags(&mut filter, libc::SYS_openat, 2, WRITE_FLAGS, true);
+        #[cfg(target_arch = "x86_64")]
+        check_flags(&mut filter, libc::SYS_open, 1, WRITE_FLAGS, true);
+        filter.push(allow());
+        filter
+    }
+
+    pub fn install() -> Result<(), String> {
+        let mut filter = filter();
+        let program = sock_fprog {
+            len: filter.len() as u16,
+            filter: filter.as_mut_ptr(),
+        };
+        let error = || Err(format!("seccomp failed: {}", std::io::Error::last_os_error()));
+        unsafe {
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return error();
+            }
+            let program = &program as *const sock_fprog;
+            if libc::prctl(libc::PR_SET_SECCOMP, libc::SECCOMP_MODE_FILTER, program) != 0 {
+                return error();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Confines a copy of the test process (as that can’t be undone); its
+    /// output is piped, since it can’t write to files (e.g. `cargo test >
+    /// log`).
+    #[test]
+    fn test_confine() {
+        if !SUPPORTED {
+            return;
+        }
+        let confined = |mode: &str| {
+            Command::new(std::env::current_exe().expect("test executable"))
+                .args(["--exact", "sandbox::test::test_confine", "--test-threads", "1"])
+                .env("IMAGER_TEST_CONFINED", mode)
+                .output()
+                .expect("run confined test")
+        };
+        match std::env::var("IMAGER_TEST_CONFINED").as_deref() {
+            Err(_) => {
+                let output = confined("1");
+                assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+                #[cfg(target_arch = "x86_64")]
+                assert_eq!(signal(&confined("x32").status), Some(libc::SIGSYS));
+                return;
+            }
+            Ok("x32") => {
+                confine(&Limits::default()).expect("confine");
+                unsafe { libc::syscall(libc::SYS_getpid | 0x4000_0000) };
+                unreachable!("x32 syscall allowed");
+            }
+            Ok(_) => (),
+        }
+        confine(&Limits::default()).expect("confine");
+        let readable = std::fs::read(std::env::current_exe().expect("test executable"));
+        assert!(readable.is_ok());
+        let denied = |result: std::io::Result<()>| {
+            result.expect_err("denied").raw_os_error() == Some(libc::EPERM)
+        };
+        let path = std::env::temp_dir().join(format!("imager-sandbox-{}", std::process::id()));
+        assert!(denied(std::fs::write(&path, b"x")));
+        assert!(denied(std::net::TcpListener::bind("127.0.0.1:0").map(drop)));
+        assert!(denied(Command::new("true").status().map(drop)));
+        assert_eq!(std::thread::spawn(|| 1 + 1).join().expect("thread"), 2);
+        let (meta, rest) = split_frame(b"\x02\0\0\0{}png").expect("frame");
+        assert_eq!((meta, rest), (&b"{}"[..], &b"png"[..]));
+    }
+}
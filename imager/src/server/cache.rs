@@ -0,0 +1,195 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! HTTP caching of `GET` outputs, for CDNs (and browsers) in front of the
+//! server: strong ETags, and `304`s for conditional requests.
+//!
+//! - ETags of `--compat` outputs hash the source (its SHA-256), the
+//!   transform (the pipeline, as JSON) and the imager version, so they’re
+//!   known before encoding, and change with anything that changes the
+//!   output. Their URLs name the transform, so they’re also `immutable`,
+//!   for the `--cache-max-age`.
+//! - ETags of job results hash the output.
+//!
+//! `If-None-Match` takes precedence over `If-Modified-Since`, which is only
+//! checked against the origin’s `Last-Modified` (RFC 9110, 13.2.2).
+use imager_core::digest::{hex, sha256};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::http::{Request, Response};
+use crate::pipeline::Pipeline;
+
+/// A year, the most RFC 9111 suggests.
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Of the validators of a representation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validators {
+    /// Quoted, e.g. `"3f…"`.
+    pub etag: String,
+    /// An HTTP date.
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    /// Of a transform of a source.
+    pub fn of_transform(source: &[u8], pipeline: &Pipeline) -> Self {
+        let transform = serde_json::to_string(pipeline).expect("to json failed");
+        let identity = [
+            &sha256(source)[..],
+            env!("CARGO_PKG_VERSION").as_bytes(),
+            b"\n",
+            transform.as_bytes(),
+        ]
+        .concat();
+        Validators {
+            etag: format!("\"{}\"", &hex(&sha256(&identity))[..32]),
+            last_modified: None,
+        }
+    }
+    pub fn of_output(output: &[u8]) -> Self {
+        Validators {
+            etag: format!("\"{}\"", &hex(&sha256(output))[..32]),
+            last_modified: None,
+        }
+    }
+    /// Whether the client’s copy is current, i.e. the request is answered
+    /// with a `304`.
+    pub fn not_modified(&self, request: &Request) -> bool {
+        if let Some(tags) = request.header("if-none-match") {
+            // WEAK COMPARISON, AS FOR `GET`S
+            let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_owned();
+            return tags.trim() == "*" || tags.split(',').any(|tag| opaque(tag) == opaque(&self.etag));
+        }
+        let since = request.header("if-modified-since").and_then(parse_http_date);
+        let modified = self.last_modified.as_deref().and_then(parse_http_date);
+        matches!((since, modified), (Some(since), Some(modified)) if modified <= since)
+    }
+    /// Adds the validators (e.g. to the `200`, or the `304`).
+    pub fn apply(&self, response: Response) -> Response {
+        let response = response.header("ETag", &self.etag);
+        match &self.last_modified {
+            Some(date) => response.header("Last-Modified", date),
+            None => response,
+        }
+    }
+}
+
+/// Of responses to immutable URLs.
+pub fn immutable(max_age: Duration) -> String {
+    format!("public, max-age={}, immutable", max_age.as_secs())
+}
+
+/// A `304` (without a body), with the headers the `200` would have.
+pub fn not_modified(validators: &Validators, headers: &[(&str, &str)]) -> Response {
+    let response = Response {
+        status: 304,
+        headers: Vec::new(),
+        body: Vec::new(),
+    };
+    let response = headers
+        .iter()
+        .fold(response, |response, (name, value)| response.header(name, value));
+    validators.apply(response)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// HTTP DATES
+///////////////////////////////////////////////////////////////////////////////
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+/// The IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn http_date(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |x| x.as_secs());
+    let (days, seconds) = ((seconds / 86400) as i64, seconds % 86400);
+    // HOWARD HINNANT’S `civil_from_days`
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[days.rem_euclid(7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Of IMF-fixdates only; the obsolete formats never reach `If-Modified-Since`
+/// from CDNs and current browsers.
+pub fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let parts = date.split_whitespace().collect::<Vec<_>>();
+    let [_, day, month, year, time, "GMT"] = parts.as_slice() else {
+        return None;
+    };
+    let day = day.parse::<i64>().ok()?;
+    let month = MONTHS.iter().position(|x| x == month)? as i64 + 1;
+    let year = year.parse::<i64>().ok()?;
+    let time = time
+        .split(':')
+        .map(|x| x.parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let [hours, minutes, seconds] = time.as_slice() else {
+        return None;
+    };
+    // HOWARD HINNANT’S `days_from_civil`
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = u64::try_from(era * 146_097 + doe - 719_468).ok()?;
+    let seconds = days * 86400 + hours * 3600 + minutes * 60 + seconds;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validators() {
+        let date = "Sun, 06 Nov 1994 08:49:37 GMT";
+        let time = parse_http_date(date).expect("date");
+        assert_eq!(time, UNIX_EPOCH + Duration::from_secs(784_111_777));
+        assert_eq!(http_date(time), date);
+        assert_eq!(http_date(UNIX_EPOCH + Duration::from_secs(951_782_400)), "Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+        let pipeline = Pipeline::new(Vec::new());
+        let validators = Validators {
+            last_modified: Some(String::from(date)),
+            ..Validators::of_transform(b"source", &pipeline)
+        };
+        assert_eq!(validators.etag.len(), 34);
+        assert_ne!(validators, Validators::of_transform(b"other source", &pipeline));
+        let request = |headers: &[(&str, &str)]| Request {
+            headers: headers.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            ..Request::default()
+        };
+        let etag = validators.etag.as_str();
+        assert!(validators.not_modified(&request(&[("if-none-match", &format!("\"x\", W/{}", etag))])));
+        assert!(!validators.not_modified(&request(&[("if-none-match", "\"x\"")])));
+        // IF-NONE-MATCH TAKES PRECEDENCE
+        let later = "Mon, 07 Nov 1994 08:49:37 GMT";
+        assert!(validators.not_modified(&request(&[("if-modified-since", later)])));
+        assert!(!validators.not_modified(&request(&[("if-none-match", "\"x\""), ("if-modified-since", later)])));
+        assert!(!validators.not_modified(&request(&[("if-modified-since", "Sat, 05 Nov 1994 08:49:37 GMT")])));
+        assert!(!validators.not_modified(&request(&[])));
+        let response = not_modified(&validators, &[("Cache-Control", &immutable(DEFAULT_MAX_AGE))]);
+        assert_eq!(response.status, 304);
+        assert!(response.body.is_empty());
+        assert_eq!(response.headers.len(), 3);
+    }
+}
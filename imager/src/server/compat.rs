@@ -0,0 +1,587 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! imgproxy and thumbor URLs (`imager serve --compat`), so imager can stand
+//! in for either behind a CDN, without rewriting the cached URLs:
+//!
+//! - imgproxy: `/<signature>/<options>/plain/<source URL>[@<format>]` or
+//!   `/<signature>/<options>/<base64 source URL>[.<format>]`, with the
+//!   options `resize` (`rs`), `size` (`s`), `resizing_type` (`rt`),
+//!   `width` (`w`), `height` (`h`), `enlarge` (`el`), `dpr`, `quality`
+//!   (`q`) and `format` (`f`, `ext`); and the basic
+//!   `/<signature>/<type>/<width>/<height>/<gravity>/<enlarge>/<source
+//!   URL>` form. Signatures are those of `IMGPROXY_KEY` and
+//!   `IMGPROXY_SALT` (hex), unchecked if unset.
+//! - thumbor: `/<signature>/[fit-in/]<width>x<height>/[<align>/][smart/]
+//!   [filters:<name>(<args>):…/]<source URL>`, with the filters
+//!   `format`, `quality`, `grayscale` and `upscale`. Signatures are those of
+//!   `THUMBOR_SECURITY_KEY`; `unsafe` URLs are only served without it.
+//!
+//! Sources are fetched (with `curl`) from the `--origin`: relative source
+//! URLs are resolved against it, and absolute ones must be within it (the
+//! same scheme, host and port, and under its path).
+//!
+//! imager never crops, pads or stretches, so other resizing types (e.g.
+//! `fill`, thumbor’s default crop) fit inside instead; these and options
+//! imager lacks (e.g. `blur`) are ignored, and listed in the
+//! `X-Imager-Ignored` response header.
+use imager_core::digest::{base64_url, constant_time_eq, from_base64_url, from_hex, hmac_sha1, hmac_sha256};
+use image::ImageFormat;
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use super::http::percent_decode;
+use super::OptParams;
+use crate::data::{ColorMode, OutputFormat, QualityRange, Resolution};
+use crate::profile::OptProfile;
+
+pub const IMGPROXY_KEY_VAR: &str = "IMGPROXY_KEY";
+pub const IMGPROXY_SALT_VAR: &str = "IMGPROXY_SALT";
+pub const THUMBOR_KEY_VAR: &str = "THUMBOR_SECURITY_KEY";
+
+pub const IGNORED_HEADER: &str = "X-Imager-Ignored";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    Imgproxy,
+    Thumbor,
+}
+
+impl FromStr for Syntax {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "imgproxy" => Ok(Syntax::Imgproxy),
+            "thumbor" => Ok(Syntax::Thumbor),
+            _ => Err(format!("Unknown URL syntax {}", s)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Compat {
+    pub syntax: Syntax,
+    /// The URL prefix of sources.
+    pub origin: String,
+    /// The signing key (and, for imgproxy, salt).
+    pub key: Option<Vec<u8>>,
+    pub salt: Vec<u8>,
+}
+
+/// Without the key.
+impl std::fmt::Debug for Compat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Compat")
+            .field("syntax", &self.syntax)
+            .field("origin", &self.origin)
+            .field("signed", &self.key.is_some())
+            .finish()
+    }
+}
+
+/// How to transform the source, as parsed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Transform {
+    /// Absolute, within the origin.
+    pub source: String,
+    /// The source’s, if none.
+    pub format: Option<OutputFormat>,
+    /// Unbounded, if `None`.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub enlarge: bool,
+    pub quality: Option<u8>,
+    pub grayscale: bool,
+    /// Options imager doesn’t (fully) apply.
+    pub ignored: Vec<String>,
+}
+
+/// A failed parse: the status (`400`, or `403` for bad signatures), and
+/// why.
+pub type Rejection = (u16, String);
+
+impl Compat {
+    /// With the key (and salt) of the syntax’s environment variables, if
+    /// set.
+    pub fn from_env(syntax: Syntax, origin: &str) -> Result<Self, String> {
+        let var = |name: &str| std::env::var(name).ok().filter(|x| !x.is_empty());
+        let (key, salt) = match syntax {
+            Syntax::Imgproxy => {
+                let hex = |name: &str| match var(name) {
+                    Some(x) => from_hex(&x).map(Some).ok_or_else(|| format!("{} isn’t hex", name)),
+                    None => Ok(None),
+                };
+                (hex(IMGPROXY_KEY_VAR)?, hex(IMGPROXY_SALT_VAR)?.unwrap_or_default())
+            }
+            Syntax::Thumbor => (var(THUMBOR_KEY_VAR).map(String::into_bytes), Vec::new()),
+        };
+        Ok(Compat {
+            syntax,
+            origin: origin.to_owned(),
+            key,
+            salt,
+        })
+    }
+    /// Parses a request target (as sent, i.e. percent-encoded).
+    pub fn parse(&self, target: &str) -> Result<Transform, Rejection> {
+        let bad = |message: String| (400, message);
+        let path = target.strip_prefix('/').ok_or_else(|| bad(String::from("invalid path")))?;
+        let (signature, signed) = path
+            .split_once('/')
+            .ok_or_else(|| bad(String::from("no signature, or source URL")))?;
+        self.verify(signature, signed)?;
+        let mut transform = match self.syntax {
+            Syntax::Imgproxy => imgproxy(signed),
+            Syntax::Thumbor => thumbor(signed),
+        }
+        .map_err(bad)?;
+        transform.source = self.resolve(&transform.source).map_err(|x| (403, x))?;
+        Ok(transform)
+    }
+    fn verify(&self, signature: &str, signed: &str) -> Result<(), Rejection> {
+        let forbidden = |message: &str| Err((403, message.to_owned()));
+        let Some(key) = &self.key else {
+            return match self.syntax {
+                Syntax::Thumbor if signature != "unsafe" => forbidden("thumbor URLs are unsigned (unsafe) here"),
+                _ => Ok(()),
+            };
+        };
+        let expected = match self.syntax {
+            Syntax::Imgproxy => {
+                let mut message = self.salt.clone();
+                message.extend_from_slice(format!("/{}", signed).as_bytes());
+                base64_url(&hmac_sha256(key, &message), false)
+            }
+            Syntax::Thumbor => base64_url(&hmac_sha1(key, signed.as_bytes()), true),
+        };
+        if constant_time_eq(signature.as_bytes(), expected.as_bytes()) {
+            Ok(())
+        } else {
+            forbidden("invalid signature")
+        }
+    }
+    /// Relative to the origin, or within it.
+    fn resolve(&self, source: &str) -> Result<String, String> {
+        // ENDING AT A PATH SEGMENT, SO `https://host` DOESN’T MATCH
+        // `https://host.evil.net` OR `https://host@evil.net`
+        let prefix = format!("{}/", self.origin.trim_end_matches('/'));
+        let url = if source.starts_with("http://") || source.starts_with("https://") {
+            if !source.starts_with(&prefix) {
+                return Err(format!("{} is outside the origin", source));
+            }
+            source.to_owned()
+        } else if source.contains("://") {
+            return Err(format!("unsupported source {}", source));
+        } else {
+            format!("{}{}", prefix, source.trim_start_matches('/'))
+        };
+        // NOR CAN DOT SEGMENTS (WHICH CURL RESOLVES) LEAVE IT
+        if url[prefix.len()..].split(['/', '?', '#']).any(|x| x == "..") {
+            return Err(format!("{} is outside the origin", source));
+        }
+        Ok(url)
+    }
+}
+
+/// A source, as fetched.
+#[derive(Debug, Clone, Default)]
+pub struct Fetched {
+    /// E.g. `304`, to a conditional request (without a body).
+    pub status: u16,
+    pub body: Vec<u8>,
+    /// The origin’s (an HTTP date), if any.
+    pub last_modified: Option<String>,
+    pub etag: Option<String>,
+    /// As sent, for caching the source (see `origin`).
+    pub cache_control: Option<String>,
+    pub expires: Option<String>,
+}
+
+/// GETs the source; without following redirects (so sources stay within
+/// the origin).
+pub fn fetch(url: &str, max_size: usize) -> Result<Fetched, String> {
+    fetch_with_headers(url, max_size, &[])
+}
+
+/// Like `fetch`, with request headers, e.g. the conditions of a
+/// revalidation.
+pub fn fetch_with_headers(url: &str, max_size: usize, headers: &[(String, String)]) -> Result<Fetched, String> {
+    let mut command = Command::new("curl");
+    command
+        .args(["--silent", "--show-error", "--fail", "--max-time", "30", "--dump-header", "-"])
+        .args(["--proto", "=http,https", "--max-filesize"])
+        .arg(max_size.to_string());
+    for (name, value) in headers {
+        command.arg("--header").arg(format!("{}: {}", name, value));
+    }
+    let output = command
+        .arg("--")
+        .arg(url)
+        .output()
+        .map_err(|e| format!("failed to run curl: {}", e))?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("failed to fetch {}: {}", url, message.trim()));
+    }
+    // THE HEADERS PRECEDE THE BODY; THE LAST BLOCK IS THE RESPONSE’S (E.G.
+    // AFTER A PROXY’S `CONNECT`)
+    let mut fetched = Fetched {
+        body: output.stdout,
+        ..Fetched::default()
+    };
+    while fetched.body.starts_with(b"HTTP/") {
+        let end = fetched
+            .body
+            .windows(4)
+            .position(|x| x == b"\r\n\r\n")
+            .ok_or_else(|| format!("failed to fetch {}: truncated headers", url))?;
+        let headers = String::from_utf8_lossy(&fetched.body[..end]).into_owned();
+        let header = |wanted: &str| {
+            headers.lines().find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                Some(value.trim().to_owned()).filter(|_| name.trim().eq_ignore_ascii_case(wanted))
+            })
+        };
+        fetched.status = headers
+            .split_whitespace()
+            .nth(1)
+            .and_then(|x| x.parse().ok())
+            .unwrap_or_default();
+        fetched.last_modified = header("last-modified");
+        fetched.etag = header("etag");
+        fetched.cache_control = header("cache-control");
+        fetched.expires = header("expires");
+        fetched.body.drain(..end + 4);
+    }
+    Ok(fetched)
+}
+
+fn format(name: &str) -> Result<OutputFormat, String> {
+    match name.to_lowercase().as_str() {
+        "jpg" => Ok(OutputFormat::Jpeg),
+        "tif" => Ok(OutputFormat::Tiff),
+        name => name.parse(),
+    }
+}
+
+fn number<T: FromStr>(value: &str, what: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid {} {}", what, value))
+}
+
+/// Where `0` is unbounded.
+fn side(x: u32) -> Option<u32> {
+    Some(x).filter(|x| *x > 0)
+}
+
+impl Transform {
+    /// Of the `profile`, overridden by the transform’s options.
+    pub fn params(&self, source: &[u8], profile: &OptProfile) -> OptParams {
+        let format = self.format.clone().unwrap_or_else(|| match ::image::guess_format(source) {
+            Ok(ImageFormat::Png) => OutputFormat::Png,
+            Ok(ImageFormat::WebP) => OutputFormat::Webp,
+            Ok(ImageFormat::Avif) => OutputFormat::Avif,
+            _ => OutputFormat::Jpeg,
+        });
+        let mut profile = profile.clone();
+        // AN UNBOUNDED SIDE IS BOUNDED BY THE FORMAT
+        let limit = format.max_dimension();
+        let max_size = match (self.width, self.height) {
+            (None, None) => profile.max_size.clone(),
+            (width, height) => Some(Resolution::new(width.unwrap_or(limit), height.unwrap_or(limit))),
+        };
+        profile.allow_upscale |= self.enlarge;
+        if self.grayscale {
+            profile.color_mode = ColorMode::Grayscale;
+        }
+        if let Some(quality) = self.quality {
+            let range = QualityRange {
+                min: quality,
+                max: quality,
+            };
+            match format {
+                OutputFormat::Jpeg => profile.tuning.jpeg = range,
+                OutputFormat::Webp => profile.tuning.webp = range,
+                OutputFormat::Avif => profile.tuning.avif = range,
+                _ => (),
+            }
+        }
+        OptParams {
+            output_format: format,
+            max_size,
+            profile: Arc::new(profile),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// IMGPROXY
+///////////////////////////////////////////////////////////////////////////////
+
+const RESIZING_TYPES: [&str; 5] = ["fit", "fill", "fill-down", "force", "auto"];
+
+fn imgproxy(path: &str) -> Result<Transform, String> {
+    let segments = path.split('/').collect::<Vec<_>>();
+    let mut transform = Transform::default();
+    let (mut width, mut height, mut dpr) = (0, 0, 1.0);
+    let mut resizing_type = |transform: &mut Transform, value: &str| {
+        if !RESIZING_TYPES.contains(&value) {
+            return Err(format!("unknown resizing type {}", value));
+        }
+        if !matches!(value, "fit" | "auto") {
+            transform.ignored.push(format!("resizing_type:{}", value));
+        }
+        Ok(())
+    };
+    let flag = |x: &str| matches!(x, "1" | "t" | "true");
+    // THE BASIC FORM: TYPE, WIDTH, HEIGHT, GRAVITY, ENLARGE
+    let is_basic = segments.len() > 5
+        && RESIZING_TYPES.contains(&segments[0])
+        && segments[1].parse::<u32>().is_ok()
+        && segments[2].parse::<u32>().is_ok();
+    let url = if is_basic {
+        resizing_type(&mut transform, segments[0])?;
+        width = number(segments[1], "width")?;
+        height = number(segments[2], "height")?;
+        transform.enlarge = flag(segments[4]);
+        &segments[5..]
+    } else {
+        let start = segments
+            .iter()
+            .position(|x| !x.contains(':'))
+            .ok_or("no source URL")?;
+        for option in &segments[..start] {
+            let mut args = option.split(':');
+            let name = args.next().expect("split");
+            let args = args.collect::<Vec<_>>();
+            let arg = |ix: usize| args.get(ix).copied().filter(|x| !x.is_empty());
+            match name {
+                "resize" | "rs" | "size" | "s" => {
+                    let args = match name {
+                        "resize" | "rs" => {
+                            if let Some(value) = arg(0) {
+                                resizing_type(&mut transform, value)?;
+                            }
+                            1
+                        }
+                        _ => 0,
+                    };
+                    if let Some(value) = arg(args) {
+                        width = number(value, "width")?;
+                    }
+                    if let Some(value) = arg(args + 1) {
+                        height = number(value, "height")?;
+                    }
+                    if let Some(value) = arg(args + 2) {
+                        transform.enlarge = flag(value);
+                    }
+                    if arg(args + 3).is_some_and(flag) {
+                        transform.ignored.push(String::from("extend"));
+                    }
+                }
+                "resizing_type" | "rt" => resizing_type(&mut transform, arg(0).unwrap_or_default())?,
+                "width" | "w" => width = number(arg(0).unwrap_or_default(), "width")?,
+                "height" | "h" => height = number(arg(0).unwrap_or_default(), "height")?,
+                "enlarge" | "el" => transform.enlarge = arg(0).is_some_and(flag),
+                "dpr" => dpr = number(arg(0).unwrap_or_default(), "dpr")?,
+                "quality" | "q" => transform.quality = Some(number(arg(0).unwrap_or_default(), "quality")?),
+                "format" | "f" | "ext" => transform.format = Some(format(arg(0).unwrap_or_default())?),
+                // IMAGER STRIPS METADATA ANYWAY (AS ITS PRIVACY POLICY ALLOWS)
+                "strip_metadata" | "sm" => (),
+                name => transform.ignored.push(name.to_owned()),
+            }
+        }
+        &segments[start..]
+    };
+    let url = match url.split_first() {
+        Some((&"plain", url)) => {
+            let url = url.join("/");
+            let (url, extension) = match url.rsplit_once('@') {
+                Some((url, extension)) if !extension.contains('/') => (url, Some(extension)),
+                _ => (url.as_str(), None),
+            };
+            if let Some(extension) = extension {
+                transform.format = Some(format(extension)?);
+            }
+            percent_decode(url)
+        }
+        Some((&"enc", _)) => return Err(String::from("encrypted source URLs are unsupported")),
+        _ => {
+            let encoded = url.concat();
+            let (encoded, extension) = match encoded.split_once('.') {
+                Some((encoded, extension)) => (encoded, Some(extension)),
+                None => (encoded.as_str(), None),
+            };
+            if let Some(extension) = extension {
+                transform.format = Some(format(extension)?);
+            }
+            from_base64_url(encoded)
+                .and_then(|x| String::from_utf8(x).ok())
+                .ok_or("invalid base64 source URL")?
+        }
+    };
+    if url.is_empty() {
+        return Err(String::from("no source URL"));
+    }
+    let scale = |x: u32| (f64::from(x) * dpr).round() as u32;
+    transform.width = side(scale(width));
+    transform.height = side(scale(height));
+    transform.source = url;
+    Ok(transform)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// THUMBOR
+///////////////////////////////////////////////////////////////////////////////
+
+fn thumbor(path: &str) -> Result<Transform, String> {
+    let mut transform = Transform::default();
+    let mut segments = path.split('/').peekable();
+    let mut fit_in = false;
+    let mut size = None;
+    while let Some(segment) = segments.peek().copied() {
+        let is_size = |x: &str| {
+            x.split_once('x')
+                .is_some_and(|(w, h)| [w, h].iter().all(|x| x.trim_start_matches('-').chars().all(|c| c.is_ascii_digit())))
+        };
+        let is_crop = |x: &str| x.split_once(':').is_some_and(|(a, b)| is_size(a) && is_size(b));
+        match segment {
+            "meta" => return Err(String::from("metadata (meta) URLs are unsupported")),
+            "fit-in" | "adaptive-fit-in" => fit_in = true,
+            "full-fit-in" | "adaptive-full-fit-in" => {
+                fit_in = true;
+                transform.ignored.push(segment.to_owned());
+            }
+            "left" | "center" | "right" | "top" | "middle" | "bottom" | "smart" => (),
+            x if x == "trim" || x.starts_with("trim:") => transform.ignored.push(String::from("trim")),
+            x if is_crop(x) => transform.ignored.push(String::from("crop")),
+            x if is_size(x) && size.is_none() => size = Some(x.to_owned()),
+            x if x.starts_with("filters:") => thumbor_filters(&mut transform, &x["filters:".len()..])?,
+            _ => break,
+        }
+        segments.next();
+    }
+    let url = segments.collect::<Vec<_>>().join("/");
+    if url.is_empty() {
+        return Err(String::from("no source URL"));
+    }
+    if let Some(size) = size {
+        let (width, height) = size.split_once('x').expect("a size");
+        if width.starts_with('-') || height.starts_with('-') {
+            transform.ignored.push(String::from("flip"));
+        }
+        let parse = |x: &str| match x.trim_start_matches('-') {
+            "" => Ok(0),
+            x => number::<u32>(x, "size"),
+        };
+        let (width, height) = (parse(width)?, parse(height)?);
+        if !fit_in && width > 0 && height > 0 {
+            transform.ignored.push(String::from("crop"));
+        }
+        transform.width = side(width);
+        transform.height = side(height);
+    }
+    transform.source = percent_decode(&url);
+    Ok(transform)
+}
+
+/// E.g. `format(webp):quality(80)`.
+fn thumbor_filters(transform: &mut Transform, filters: &str) -> Result<(), String> {
+    for filter in filters.split("):").filter(|x| !x.is_empty()) {
+        let (name, args) = filter
+            .trim_end_matches(')')
+            .split_once('(')
+            .ok_or_else(|| format!("invalid filter {}", filter))?;
+        match name {
+            "format" => transform.format = Some(format(args)?),
+            "quality" => transform.quality = Some(number(args, "quality")?),
+            "grayscale" => transform.grayscale = true,
+            "upscale" => transform.enlarge = true,
+            "strip_exif" | "strip_icc" => (),
+            name => transform.ignored.push(name.to_owned()),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let origin = "https://images.example.com";
+        let imgproxy = Compat {
+            key: Some(from_hex("943b421c9eb07c830af81030552c86009268de4e532ba2ee2eab8247c6da0881").unwrap()),
+            salt: from_hex("520f986b998545b4785e0defbc4f3c1203f22de2374a3d53cb7a7fe9fea309c5").unwrap(),
+            ..Compat::from_env(Syntax::Imgproxy, origin).unwrap()
+        };
+        // A BASE64 SOURCE URL, SPLIT INTO SEGMENTS
+        let target = "/90UxdwGRAI2bpLSHKkZculJau5ahfxfS0h3fMuQAf40/rs:fill:300:400:0/g:sm/\
+                      aHR0cDovL2V4YW1w/bGUuY29tL2ltYWdl/cy9jdXJpb3NpdHku/anBn.png";
+        let transform = imgproxy.parse(target);
+        // AN ABSOLUTE SOURCE, OUTSIDE THE ORIGIN
+        assert_eq!(transform, Err((403, String::from("http://example.com/images/curiosity.jpg is outside the origin"))));
+        let mut tampered = String::from(target);
+        tampered.replace_range(target.find("300").unwrap()..target.find("300").unwrap() + 3, "301");
+        assert_eq!(imgproxy.parse(&tampered).unwrap_err().0, 403);
+        let unsigned = Compat {
+            key: None,
+            ..imgproxy.clone()
+        };
+        let transform = unsigned.parse("/insecure/rs:fit:300:0/q:70/blur:2/plain/photos/a%20b.jpg@webp").unwrap();
+        assert_eq!(transform.source, "https://images.example.com/photos/a b.jpg");
+        assert_eq!((transform.width, transform.height), (Some(300), None));
+        assert_eq!(transform.format, Some(OutputFormat::Webp));
+        assert_eq!(transform.quality, Some(70));
+        assert_eq!(transform.ignored, ["blur"]);
+        let params = transform.params(&[], &OptProfile::default());
+        assert_eq!(params.max_size, Some(Resolution::new(300, OutputFormat::Webp.max_dimension())));
+        assert_eq!(params.profile.tuning.webp, QualityRange { min: 70, max: 70 });
+        let transform = unsigned.parse("/_/fill/100/100/ce/1/plain/photos/a.jpg").unwrap();
+        assert_eq!((transform.width, transform.height, transform.enlarge), (Some(100), Some(100), true));
+        assert_eq!(transform.ignored, ["resizing_type:fill"]);
+        // THUMBOR
+        let thumbor = Compat {
+            key: Some(b"MY_SECURE_KEY".to_vec()),
+            ..Compat::from_env(Syntax::Thumbor, origin).unwrap()
+        };
+        let signed = "fit-in/300x200/filters:format(webp):quality(80):blur(3)/photos/a.jpg";
+        let signature = base64_url(&hmac_sha1(b"MY_SECURE_KEY", signed.as_bytes()), true);
+        let transform = thumbor.parse(&format!("/{}/{}", signature, signed)).unwrap();
+        assert_eq!(transform.source, "https://images.example.com/photos/a.jpg");
+        assert_eq!((transform.width, transform.height), (Some(300), Some(200)));
+        assert_eq!((transform.format, transform.quality), (Some(OutputFormat::Webp), Some(80)));
+        assert_eq!(transform.ignored, ["blur"]);
+        assert_eq!(thumbor.parse(&format!("/unsafe/{}", signed)).unwrap_err().0, 403);
+        let unsigned = Compat { key: None, ..thumbor };
+        let transform = unsigned.parse("/unsafe/300x200/smart/https://images.example.com/b.png").unwrap();
+        assert_eq!(transform.ignored, ["crop"]);
+        assert!(unsigned.parse("/unsafe/300x200/").is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let compat = Compat::from_env(Syntax::Imgproxy, "https://images.example.com").unwrap();
+        assert_eq!(compat.resolve("/a.jpg").unwrap(), "https://images.example.com/a.jpg");
+        assert_eq!(
+            compat.resolve("https://images.example.com/a.jpg").unwrap(),
+            "https://images.example.com/a.jpg"
+        );
+        for source in [
+            "https://images.example.com.evil.net/a.jpg",
+            "https://images.example.com@evil.net/a.jpg",
+            "https://images.example.com:8080/a.jpg",
+            "http://images.example.com/a.jpg",
+            "https://images.example.com/../a.jpg",
+            "../a.jpg",
+            "file:///etc/passwd",
+        ] {
+            assert!(compat.resolve(source).is_err(), "{}", source);
+        }
+        let compat = Compat::from_env(Syntax::Imgproxy, "https://example.com/photos/").unwrap();
+        assert_eq!(compat.resolve("a.jpg").unwrap(), "https://example.com/photos/a.jpg");
+        assert!(compat.resolve("https://example.com/photos-private/a.jpg").is_err());
+        assert!(compat.resolve("a/../../private/a.jpg").is_err());
+    }
+}
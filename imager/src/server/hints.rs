@@ -0,0 +1,117 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Client hints (`imager serve --client-hints`): responsive outputs, sized
+//! and compressed for the requesting device, from one URL.
+//!
+//! - `Sec-CH-DPR` (or `DPR`) scales the requested max size (in CSS pixels)
+//!   to device pixels; rounded up to a multiple of 0.5, up to the
+//!   `max_dpr`.
+//! - `Sec-CH-Width` (or `Width`), the layout width in device pixels, caps
+//!   the width; rounded up to the next of the `widths` (else down to the
+//!   largest).
+//! - `Save-Data: on` caps the quality of every format.
+//!
+//! The rounding keeps the number of variants per URL (so in a cache in
+//! front of the server) bounded.
+use std::sync::Arc;
+
+use super::http::Request;
+use super::OptParams;
+use crate::data::{QualityRange, Resolution};
+
+/// For browsers to send the hints.
+pub const ACCEPT_CH: &str = "Sec-CH-Width, Sec-CH-DPR, Save-Data";
+/// Of responses that honored them.
+pub const VARY: &str = "Sec-CH-Width, Width, Sec-CH-DPR, DPR, Save-Data";
+
+pub const DEFAULT_WIDTHS: [u32; 7] = [320, 640, 960, 1280, 1920, 2560, 3840];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientHints {
+    /// The buckets, ascending.
+    pub widths: Vec<u32>,
+    pub max_dpr: f64,
+    /// The max quality of `Save-Data` requests.
+    pub save_data_quality: u8,
+}
+
+impl Default for ClientHints {
+    fn default() -> Self {
+        ClientHints {
+            widths: DEFAULT_WIDTHS.to_vec(),
+            max_dpr: 3.0,
+            save_data_quality: 60,
+        }
+    }
+}
+
+impl ClientHints {
+    /// Adjusts the `params` to the request’s hints.
+    pub fn apply(&self, request: &Request, params: &mut OptParams) {
+        let hint = |names: [&str; 2]| names.iter().find_map(|x| request.header(x));
+        let dpr = hint(["sec-ch-dpr", "dpr"])
+            .and_then(|x| x.parse::<f64>().ok())
+            .filter(|x| x.is_finite() && *x > 0.0)
+            .map_or(1.0, |x| ((x * 2.0).ceil() / 2.0).min(self.max_dpr));
+        let width = hint(["sec-ch-width", "width"]).and_then(|x| x.parse::<u32>().ok()).filter(|x| *x > 0);
+        let limit = params.output_format.max_dimension();
+        let scale = |x: u32| ((f64::from(x) * dpr).round() as u32).min(limit);
+        let max_size = params.max_size.as_ref().map(|x| Resolution::new(scale(x.width), scale(x.height)));
+        params.max_size = match (max_size, width.map(|x| self.bucket(x))) {
+            (Some(max_size), Some(width)) => Some(Resolution::new(max_size.width.min(width), max_size.height)),
+            (None, Some(width)) => Some(Resolution::new(width, limit)),
+            (max_size, None) => max_size,
+        };
+        let save_data = request.header("save-data").is_some_and(|x| x.eq_ignore_ascii_case("on"));
+        if save_data {
+            let mut profile = (*params.profile).clone();
+            let cap = |range: QualityRange| QualityRange {
+                min: range.min.min(self.save_data_quality),
+                max: range.max.min(self.save_data_quality),
+            };
+            let tuning = &mut profile.tuning;
+            (tuning.jpeg, tuning.webp, tuning.avif) = (cap(tuning.jpeg), cap(tuning.webp), cap(tuning.avif));
+            params.profile = Arc::new(profile);
+        }
+    }
+    /// The smallest bucket at least `width`, else the largest.
+    fn bucket(&self, width: u32) -> u32 {
+        let largest = self.widths.last().copied().unwrap_or(width);
+        self.widths.iter().copied().find(|x| *x >= width).unwrap_or(largest)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::OutputFormat;
+
+    #[test]
+    fn test_apply() {
+        let hints = ClientHints::default();
+        let request = |headers: &[(&str, &str)]| Request {
+            headers: headers.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            ..Request::default()
+        };
+        let params = |max_size: Option<Resolution>| OptParams::new(OutputFormat::Jpeg, max_size);
+        let apply = |headers: &[(&str, &str)], max_size: Option<Resolution>| {
+            let mut params = params(max_size);
+            hints.apply(&request(headers), &mut params);
+            params
+        };
+        let size = Some(Resolution::new(400, 300));
+        assert_eq!(apply(&[("sec-ch-dpr", "2")], size.clone()).max_size, Some(Resolution::new(800, 600)));
+        assert_eq!(apply(&[("dpr", "1.3")], size.clone()).max_size, Some(Resolution::new(600, 450)));
+        // CAPPED BY THE MAX DPR, THEN BY THE LAYOUT WIDTH (IN THE 960 BUCKET)
+        let applied = apply(&[("dpr", "5"), ("width", "700")], size.clone());
+        assert_eq!(applied.max_size, Some(Resolution::new(960, 900)));
+        let applied = apply(&[("sec-ch-width", "5000")], None);
+        assert_eq!(applied.max_size, Some(Resolution::new(3840, OutputFormat::Jpeg.max_dimension())));
+        assert_eq!(apply(&[("sec-ch-dpr", "2")], None).max_size, None);
+        assert_eq!(apply(&[("dpr", "abc")], size.clone()).max_size, size);
+        let applied = apply(&[("save-data", "on")], None);
+        assert_eq!(applied.profile.tuning.jpeg, QualityRange { min: 0, max: 60 });
+        assert_eq!(apply(&[], None).profile.tuning.jpeg.max, 98);
+    }
+}
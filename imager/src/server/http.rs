@@ -0,0 +1,244 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Just enough HTTP/1.1 for the server: one request per connection,
+//! bodies by `Content-Length` (no chunked uploads).
+use serde::Serialize;
+use std::io::{BufRead, Read, Write};
+
+/// The largest request line or header accepted.
+const MAX_LINE: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+
+#[derive(Debug, Clone, Default)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// As sent (percent-encoded, with the query), e.g. for signatures.
+    pub target: String,
+    pub query: Vec<(String, String)>,
+    /// With lowercase names.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Reads a request; failures are the response to send instead.
+    pub fn read<R: BufRead>(reader: &mut R, max_body_size: usize) -> Result<Request, Response> {
+        let bad_request = |message: &str| Response::text(400, message);
+        let request_line = read_line(reader).map_err(|e| bad_request(&e))?;
+        let mut parts = request_line.split(' ');
+        let (method, target) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => {
+                (method, target)
+            }
+            _ => return Err(bad_request("invalid request line")),
+        };
+        let (path, query) = match target.find('?') {
+            Some(ix) => (&target[..ix], parse_query(&target[ix + 1..])),
+            None => (target, Vec::new()),
+        };
+        let mut request = Request {
+            method: method.to_owned(),
+            path: percent_decode(path),
+            target: target.to_owned(),
+            query,
+            ..Request::default()
+        };
+        loop {
+            let line = read_line(reader).map_err(|e| bad_request(&e))?;
+            if line.is_empty() {
+                break;
+            }
+            if request.headers.len() == MAX_HEADERS {
+                return Err(Response::text(431, "too many headers"));
+            }
+            let ix = line.find(':').ok_or_else(|| bad_request("invalid header"))?;
+            request.headers.push((
+                line[..ix].trim().to_lowercase(),
+                line[ix + 1..].trim().to_owned(),
+            ));
+        }
+        if request.header("transfer-encoding").is_some() {
+            return Err(Response::text(411, "chunked requests aren’t supported"));
+        }
+        let length = match request.header("content-length") {
+            None => 0,
+            Some(x) => x
+                .parse::<usize>()
+                .map_err(|_| bad_request("invalid content-length"))?,
+        };
+        if length > max_body_size {
+            return Err(Response::text(
+                413,
+                &format!("bodies are limited to {} bytes", max_body_size),
+            ));
+        }
+        request.body = vec![0; length];
+        reader
+            .read_exact(&mut request.body)
+            .map_err(|_| bad_request("truncated body"))?;
+        Ok(request)
+    }
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+    /// The path segments, e.g. `["jobs", "<id>"]` for `/jobs/<id>`.
+    pub fn segments(&self) -> Vec<&str> {
+        self.path.split('/').filter(|x| !x.is_empty()).collect()
+    }
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String, String> {
+    let mut line = Vec::new();
+    reader
+        .take(MAX_LINE as u64)
+        .read_until(b'\n', &mut line)
+        .map_err(|e| e.to_string())?;
+    if line.last() != Some(&b'\n') {
+        return Err(String::from("line too long, or connection closed"));
+    }
+    let line = String::from_utf8(line).map_err(|_| String::from("invalid utf-8"))?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|x| !x.is_empty())
+        .map(|param| match param.find('=') {
+            Some(ix) => (query_decode(&param[..ix]), query_decode(&param[ix + 1..])),
+            None => (query_decode(param), String::new()),
+        })
+        .collect()
+}
+
+/// Of query strings’ form encoding, where `+` is a space.
+fn query_decode(input: &str) -> String {
+    percent_decode(&input.replace('+', " "))
+}
+
+/// Of paths, where `+` is itself.
+pub(crate) fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut output = Vec::with_capacity(bytes.len());
+    let mut ix = 0;
+    while ix < bytes.len() {
+        let hex = || std::str::from_utf8(bytes.get(ix + 1..ix + 3)?).ok();
+        match bytes[ix] {
+            b'%' => match hex().and_then(|x| u8::from_str_radix(x, 16).ok()) {
+                Some(byte) => {
+                    output.push(byte);
+                    ix += 2;
+                }
+                None => output.push(b'%'),
+            },
+            byte => output.push(byte),
+        }
+        ix += 1;
+    }
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: Vec<u8>) -> Self {
+        Response {
+            status,
+            headers: vec![(String::from("Content-Type"), content_type.to_owned())],
+            body,
+        }
+    }
+    pub fn text(status: u16, message: &str) -> Self {
+        Response::new(status, "text/plain; charset=utf-8", format!("{}\n", message).into_bytes())
+    }
+    pub fn json<T: Serialize>(status: u16, value: &T) -> Self {
+        let body = serde_json::to_vec_pretty(value).expect("to json failed");
+        Response::new(status, "application/json", body)
+    }
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        write!(writer, "HTTP/1.1 {} {}\r\n", self.status, reason(self.status))?;
+        for (name, value) in &self.headers {
+            write!(writer, "{}: {}\r\n", name, value)?;
+        }
+        // A `304`’S WOULD HAVE TO BE THE `200`’S
+        if self.status != 304 {
+            write!(writer, "Content-Length: {}\r\n", self.body.len())?;
+        }
+        write!(writer, "Connection: close\r\n\r\n")?;
+        writer.write_all(&self.body)?;
+        writer.flush()
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Entity",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_request() {
+        let source = b"POST /jobs/a+b%2Bc?format=webp&callback=https%3A%2F%2Fa.b%2Fc&q=a+b%2Bc HTTP/1.1\r\n\
+                       Host: localhost\r\nContent-Length: 4\r\n\r\nbodyEXTRA";
+        let request = Request::read(&mut &source[..], 1024).expect("parse request");
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.segments(), vec!["jobs", "a+b+c"]);
+        assert_eq!(request.param("callback"), Some("https://a.b/c"));
+        assert_eq!(request.param("q"), Some("a b+c"));
+        assert_eq!(request.header("host"), Some("localhost"));
+        assert_eq!(request.body, b"body");
+        let error = Request::read(&mut &source[..], 2).expect_err("too large");
+        assert_eq!(error.status, 413);
+        let mut output = Vec::new();
+        Response::text(404, "no such job").write_to(&mut output).expect("write");
+        assert!(output.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+        assert!(output.ends_with(b"\r\n\r\nno such job\n"));
+        for (status, line) in [(401, "401 Unauthorized"), (429, "429 Too Many Requests")] {
+            let mut output = Vec::new();
+            Response::text(status, "").write_to(&mut output).expect("write");
+            assert!(output.starts_with(format!("HTTP/1.1 {}\r\n", line).as_bytes()));
+        }
+    }
+}
@@ -0,0 +1,269 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Async jobs: accepted with an ID right away, optimized by a fixed pool
+//! of workers, and kept (results included) for a retention period after
+//! they finish (by default `RETENTION`), then `prune`d, or dropped when
+//! read. Jobs submitted with an
+//! API key are only visible with that key.
+use rand::RngCore;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::keys::Permit;
+use super::OptParams;
+use crate::api::OutMeda;
+use crate::data::OutputFormat;
+use crate::sandbox::Limits;
+use crate::trace::{Span, TraceContext};
+use crate::webhook::Webhook;
+
+/// How long finished jobs (and their outputs) are kept by default.
+pub const RETENTION: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+/// As returned by `GET /jobs/<id>`, and POSTed to the job’s callback.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStatus {
+    pub id: String,
+    pub state: JobState,
+    pub output_format: OutputFormat,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<OutMeda>,
+}
+
+struct Job {
+    /// The name of the API key that submitted the job.
+    owner: Option<String>,
+    status: JobStatus,
+    output: Option<Vec<u8>>,
+    finished: Option<Instant>,
+}
+
+impl Job {
+    fn expired(&self, retention: Duration) -> bool {
+        self.finished.is_some_and(|x| x.elapsed() >= retention)
+    }
+}
+
+pub struct Task {
+    pub source: Vec<u8>,
+    pub params: OptParams,
+    pub callback: Option<Webhook>,
+    /// Held until the job finishes.
+    pub permit: Option<Permit>,
+    /// Of the submitting request.
+    pub trace: TraceContext,
+}
+
+pub struct Jobs {
+    jobs: Mutex<HashMap<String, Job>>,
+    queue: Mutex<Sender<(String, Task)>>,
+    sandbox: Option<Limits>,
+    retention: Duration,
+}
+
+impl Jobs {
+    /// Starts `workers` worker threads, which optimize in a sandboxed
+    /// subprocess given `sandbox`; finished jobs are kept for `retention`.
+    pub fn start(workers: usize, sandbox: Option<Limits>, retention: Duration) -> Arc<Self> {
+        let (sender, receiver) = channel();
+        let jobs = Arc::new(Jobs {
+            jobs: Mutex::new(HashMap::new()),
+            queue: Mutex::new(sender),
+            sandbox,
+            retention,
+        });
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..workers.max(1) {
+            let jobs = jobs.clone();
+            let receiver = receiver.clone();
+            std::thread::spawn(move || jobs.work(&receiver));
+        }
+        jobs
+    }
+    /// Queues the job, and returns its ID.
+    pub fn submit(&self, task: Task) -> String {
+        let mut bytes = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let id = imager_core::digest::hex(&bytes);
+        let status = JobStatus {
+            id: id.clone(),
+            state: JobState::Queued,
+            output_format: task.params.output_format.clone(),
+            error: None,
+            meta: None,
+        };
+        {
+            let mut jobs = self.jobs.lock().expect("jobs lock");
+            jobs.insert(
+                id.clone(),
+                Job {
+                    owner: task.permit.as_ref().map(|x| x.name().to_owned()),
+                    status,
+                    output: None,
+                    finished: None,
+                },
+            );
+        }
+        self.queue
+            .lock()
+            .expect("queue lock")
+            .send((id.clone(), task))
+            .expect("workers are running");
+        id
+    }
+    /// Drops the jobs finished longer than the retention period ago.
+    pub fn prune(&self) {
+        let mut jobs = self.jobs.lock().expect("jobs lock");
+        jobs.retain(|_, job| !job.expired(self.retention));
+    }
+    /// The number of queued or running jobs.
+    pub fn unfinished(&self) -> usize {
+        let jobs = self.jobs.lock().expect("jobs lock");
+        jobs.values().filter(|x| x.finished.is_none()).count()
+    }
+    /// The status of the job, if it exists and belongs to `owner`.
+    pub fn status(&self, id: &str, owner: Option<&str>) -> Option<JobStatus> {
+        self.read(id, owner, |job| job.status.clone())
+    }
+    /// The output of a finished job; `Err` with the status otherwise.
+    pub fn output(
+        &self,
+        id: &str,
+        owner: Option<&str>,
+    ) -> Option<Result<(OutputFormat, Vec<u8>), JobStatus>> {
+        let (status, output) = self.read(id, owner, |job| (job.status.clone(), job.output.clone()))?;
+        Some(match output {
+            Some(output) => Ok((status.output_format, output)),
+            None => Err(status),
+        })
+    }
+    /// Of the job, if it exists and belongs to `owner`; expired ones are
+    /// dropped instead.
+    fn read<T, F: FnOnce(&Job) -> T>(&self, id: &str, owner: Option<&str>, f: F) -> Option<T> {
+        let mut jobs = self.jobs.lock().expect("jobs lock");
+        if jobs.get(id)?.expired(self.retention) {
+            jobs.remove(id);
+            return None;
+        }
+        jobs.get(id).filter(|x| x.owner.as_deref() == owner).map(f)
+    }
+    fn work(&self, receiver: &Mutex<Receiver<(String, Task)>>) {
+        loop {
+            let (id, task) = match receiver.lock().expect("queue lock").recv() {
+                Ok(x) => x,
+                Err(_) => return,
+            };
+            let span = Span::start("job", &task.trace);
+            self.update(&id, |job| job.status.state = JobState::Running);
+            let result = {
+                let _span = Span::start("optimize", &span.context);
+                super::optimize(&task.source, &task.params, self.sandbox.as_ref())
+            };
+            let status = self.update(&id, |job| {
+                match result {
+                    Ok((output, meta)) => {
+                        job.status.state = JobState::Done;
+                        job.status.output_format = super::output_format(&task.params, &meta).clone();
+                        job.status.meta = Some(meta);
+                        job.output = Some(output);
+                    }
+                    Err(message) => {
+                        job.status.state = JobState::Failed;
+                        job.status.error = Some(message);
+                    }
+                }
+                job.finished = Some(Instant::now());
+            });
+            if let (Some(callback), Some(status)) = (task.callback, status) {
+                let body = serde_json::to_vec(&status).expect("to json failed");
+                let span = Span::start("callback", &span.context);
+                if let Err(message) = callback.traced(&span.context).post(&body) {
+                    eprintln!("[warning] job {}: {}", id, message);
+                }
+            }
+        }
+    }
+    fn update<F: FnOnce(&mut Job)>(&self, id: &str, f: F) -> Option<JobStatus> {
+        let mut jobs = self.jobs.lock().expect("jobs lock");
+        let job = jobs.get_mut(id)?;
+        f(job);
+        Some(job.status.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_job_lifecycle() {
+        let jobs = Jobs::start(1, None, RETENTION);
+        let params = OptParams::new(OutputFormat::Png, Some(crate::data::Resolution::new(64, 64)));
+        let source = include_bytes!("../../assets/test/1.jpeg").to_vec();
+        let task = |source: Vec<u8>| Task {
+            source,
+            params: params.clone(),
+            callback: None,
+            permit: None,
+            trace: TraceContext::root(),
+        };
+        let id = jobs.submit(task(source));
+        let failing = jobs.submit(task(b"not an image".to_vec()));
+        assert!(jobs.status("unknown", None).is_none());
+        assert!(jobs.status(&id, Some("web")).is_none());
+        let finished = |id: &str| {
+            (0..600).find_map(|_| {
+                let status = jobs.status(id, None).expect("job status");
+                if matches!(status.state, JobState::Done | JobState::Failed) {
+                    return Some(status);
+                }
+                std::thread::sleep(Duration::from_millis(100));
+                None
+            })
+        };
+        assert_eq!(finished(&id).map(|x| x.state), Some(JobState::Done));
+        assert!(matches!(jobs.output(&id, None), Some(Ok((OutputFormat::Png, _)))));
+        let failed = finished(&failing).expect("job finished");
+        assert_eq!(failed.state, JobState::Failed);
+        assert!(failed.error.is_some());
+    }
+
+    #[test]
+    fn test_retention() {
+        let jobs = Jobs::start(1, None, Duration::ZERO);
+        let params = OptParams::new(OutputFormat::Png, None);
+        let task = || Task {
+            source: b"not an image".to_vec(),
+            params: params.clone(),
+            callback: None,
+            permit: None,
+            trace: TraceContext::root(),
+        };
+        let read = jobs.submit(task());
+        let pruned = jobs.submit(task());
+        let finished = (0..600).any(|_| {
+            std::thread::sleep(Duration::from_millis(100));
+            jobs.unfinished() == 0
+        });
+        assert!(finished);
+        assert!(jobs.status(&read, None).is_none());
+        assert_eq!(jobs.jobs.lock().expect("jobs lock").len(), 1);
+        jobs.prune();
+        assert!(jobs.jobs.lock().expect("jobs lock").get(&pruned).is_none());
+    }
+}
@@ -0,0 +1,193 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! API keys (`imager serve --keys`), with per-key limits, so one tenant
+//! can’t starve a shared server.
+//!
+//! The keys file is JSON, e.g.
+//!
+//! ```json
+//! {"keys": [{"name": "web", "key": "…", "rate_limit": 120,
+//!            "max_input_size": 20000000, "max_concurrency": 4}]}
+//! ```
+//!
+//! where `rate_limit` is in requests per minute (with bursts of up to that
+//! many), and `max_concurrency` counts sync requests and unfinished jobs.
+//! Every limit is optional. Clients send `Authorization: Bearer <key>` (or
+//! `X-Api-Key: <key>`). Requests refused for their concurrency don’t count
+//! toward the rate limit.
+//!
+//! `--compat` URLs don’t take keys, since they’re requested by browsers
+//! and CDNs, which can’t send them: they’re authorized by their signatures
+//! instead, and open without a signing key (as with imgproxy and thumbor
+//! themselves).
+use serde::Deserialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use super::http::{Request, Response};
+use imager_core::digest::sha256;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKey {
+    pub name: String,
+    pub key: String,
+    #[serde(default)]
+    pub rate_limit: Option<u32>,
+    #[serde(default)]
+    pub max_input_size: Option<usize>,
+    #[serde(default)]
+    pub max_concurrency: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct KeysFile {
+    keys: Vec<ApiKey>,
+}
+
+struct Tenant {
+    config: ApiKey,
+    digest: [u8; 32],
+    /// Available requests, and when they were last refilled.
+    tokens: Mutex<(f64, Instant)>,
+    in_flight: AtomicUsize,
+}
+
+pub struct Keys(Vec<Arc<Tenant>>);
+
+/// An authorized request’s hold on its key’s concurrency; released when
+/// dropped (i.e. when the request, or its job, is done).
+pub struct Permit(Arc<Tenant>);
+
+impl Permit {
+    pub fn name(&self) -> &str {
+        &self.0.config.name
+    }
+}
+
+impl std::fmt::Debug for Permit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Permit").field(&self.name()).finish()
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Keys {
+    pub fn new(keys: Vec<ApiKey>) -> Result<Self, String> {
+        let mut tenants: Vec<Arc<Tenant>> = Vec::new();
+        for config in keys {
+            if config.key.is_empty() {
+                return Err(format!("key {} is empty", config.name));
+            }
+            if tenants.iter().any(|x| x.config.name == config.name) {
+                return Err(format!("duplicate key name {}", config.name));
+            }
+            let burst = config.rate_limit.unwrap_or(0) as f64;
+            tenants.push(Arc::new(Tenant {
+                digest: sha256(config.key.as_bytes()),
+                tokens: Mutex::new((burst, Instant::now())),
+                in_flight: AtomicUsize::new(0),
+                config,
+            }));
+        }
+        Ok(Keys(tenants))
+    }
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let file: KeysFile = serde_json::from_str(&source).map_err(|e| e.to_string())?;
+        Keys::new(file.keys)
+    }
+    /// Checks the request’s key and limits; failures are the response to
+    /// send instead.
+    pub fn authorize(&self, request: &Request) -> Result<Permit, Response> {
+        let key = request
+            .header("authorization")
+            .and_then(|x| x.strip_prefix("Bearer "))
+            .or_else(|| request.header("x-api-key"))
+            .map(str::trim);
+        // COMPARING DIGESTS, SO THE COMPARISON TIME DOESN’T LEAK THE KEY
+        let tenant = key.and_then(|key| {
+            let digest = sha256(key.as_bytes());
+            self.0.iter().find(|x| x.digest == digest)
+        });
+        let tenant = match tenant {
+            Some(x) => x,
+            None => {
+                return Err(Response::text(401, "missing or invalid API key")
+                    .header("WWW-Authenticate", "Bearer"))
+            }
+        };
+        let config = &tenant.config;
+        if let Some(limit) = config.max_input_size.filter(|x| request.body.len() > *x) {
+            return Err(Response::text(
+                413,
+                &format!("inputs are limited to {} bytes for this key", limit),
+            ));
+        }
+        let in_flight = tenant.in_flight.fetch_add(1, Ordering::SeqCst);
+        let permit = Permit(tenant.clone());
+        if let Some(limit) = config.max_concurrency.filter(|x| in_flight >= *x) {
+            return Err(Response::text(
+                429,
+                &format!("at most {} concurrent requests for this key", limit),
+            ));
+        }
+        if let Some(rate_limit) = config.rate_limit {
+            let mut tokens = tenant.tokens.lock().expect("tokens lock");
+            let (available, refilled) = *tokens;
+            let per_second = rate_limit as f64 / 60.0;
+            let available =
+                (available + refilled.elapsed().as_secs_f64() * per_second).min(rate_limit as f64);
+            if available < 1.0 {
+                *tokens = (available, Instant::now());
+                let wait = ((1.0 - available) / per_second).ceil().max(1.0);
+                return Err(Response::text(429, "rate limit exceeded")
+                    .header("Retry-After", &format!("{}", wait)));
+            }
+            *tokens = (available - 1.0, Instant::now());
+        }
+        Ok(permit)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_authorize() {
+        let keys = Keys::new(vec![ApiKey {
+            name: String::from("web"),
+            key: String::from("secret"),
+            rate_limit: Some(3),
+            max_input_size: Some(4),
+            max_concurrency: Some(2),
+        }])
+        .expect("keys");
+        let request = |key: &str, body: &[u8]| Request {
+            headers: vec![(String::from("authorization"), format!("Bearer {}", key))],
+            body: body.to_vec(),
+            ..Request::default()
+        };
+        assert_eq!(keys.authorize(&request("wrong", b"")).err().map(|x| x.status), Some(401));
+        assert_eq!(keys.authorize(&request("secret", b"12345")).err().map(|x| x.status), Some(413));
+        let first = keys.authorize(&request("secret", b"")).expect("first");
+        assert_eq!(first.name(), "web");
+        let _second = keys.authorize(&request("secret", b"")).expect("second");
+        // OVER THE CONCURRENCY, WITHOUT SPENDING A REQUEST, THEN (AFTER
+        // RELEASES) THE RATE LIMIT
+        assert_eq!(keys.authorize(&request("secret", b"")).err().map(|x| x.status), Some(429));
+        drop(first);
+        let third = keys.authorize(&request("secret", b"")).expect("third");
+        drop(third);
+        let error = keys.authorize(&request("secret", b"")).expect_err("rate limited");
+        assert_eq!(error.status, 429);
+        assert!(error.headers.iter().any(|(name, _)| name == "Retry-After"));
+    }
+}
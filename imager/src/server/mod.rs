@@ -0,0 +1,674 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Server mode (`imager serve`): an HTTP API for optimizing images.
+//!
+//! - `POST /opt`: optimizes the body, and responds with the output.
+//! - `POST /jobs`: queues the body as an async job, and responds with its
+//!   status (`202`, with the ID), without waiting for the encode; with a
+//!   `callback` URL, the final status is POSTed there (see `webhook`), if
+//!   its host is a `--callback-host`, or public without any.
+//! - `GET /jobs/<id>`: the job’s status.
+//! - `GET /jobs/<id>/result`: the job’s output, once done.
+//! - `GET /healthz`: liveness; `GET /readyz`: readiness, i.e. `503` once
+//!   shutting down.
+//!
+//! Both take `format` (`jpeg`, `png` or `webp`, default `jpeg`) and `size`
+//! (the max resolution, e.g. `800x600`, or `full`) query parameters,
+//! defaulting to the profile’s first format and its max size, and a
+//! `preset` (e.g. `data-saver`, see `profile::Preset`) to apply over the
+//! profile. With `--keys`, they require an API key (see `keys`).
+//! Everything else comes from the `--profile`, which is reloaded on SIGHUP
+//! or when it changes (see `reload`). Formats and stages that this build
+//! lacks are `501`s (see `codec::registry`).
+//!
+//! With `--compat imgproxy` (or `thumbor`), other `GET`s are imgproxy (or
+//! thumbor) URLs, of sources at the `--origin` (see `compat`); these are
+//! authorized by their signatures, rather than API keys. Their sources are
+//! cached, and served stale while the origin is down (see `origin`).
+//!
+//! With `--client-hints`, outputs of `POST /opt` and `--compat` URLs are
+//! sized (and compressed) for the device, by its client hints (see
+//! `hints`).
+//!
+//! With `--templates`, requests may name a template of the transform
+//! parameters (`template`, e.g. `thumb`), and the server may be restricted
+//! to them (see `templates`).
+//!
+//! `GET` outputs have ETags, and conditional requests get `304`s (see
+//! `cache`).
+//!
+//! Requests continue the trace of their `traceparent` header, if any (see
+//! `trace`), into job callbacks.
+//!
+//! With `--sandbox`, sources are decoded and encoded in a confined
+//! subprocess (see `sandbox`), so a malicious one can at worst fail its
+//! own request.
+//!
+//! Connections time out after `SOCKET_TIMEOUT` without progress, and those
+//! beyond `--max-connections` get `503`s.
+//!
+//! On SIGTERM (or SIGINT), the server stops taking new work, closes the
+//! connections still waiting for their request, and exits once in-flight
+//! requests and jobs are done, or at the shutdown deadline.
+use std::collections::HashMap;
+use std::io::BufReader;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub mod cache;
+pub mod compat;
+pub mod hints;
+pub mod http;
+pub mod jobs;
+pub mod keys;
+pub mod origin;
+pub mod reload;
+pub mod templates;
+
+use self::cache::Validators;
+use self::compat::Compat;
+use self::hints::ClientHints;
+use self::http::{Request, Response};
+use self::jobs::{JobState, Jobs};
+use self::keys::Keys;
+use self::origin::OriginCache;
+use self::reload::LiveProfile;
+use self::templates::Templates;
+use crate::api::OutMeda;
+use crate::codec::registry::check_pipeline;
+use crate::data::{OutputFormat, OutputSize, Resolution};
+use crate::pipeline::{Pipeline, Stage};
+use crate::profile::{OptProfile, Preset};
+use crate::sandbox::Limits;
+use crate::trace::{Span, TraceContext};
+use crate::webhook::Webhook;
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub address: String,
+    /// The number of async job workers.
+    pub workers: usize,
+    /// How long finished jobs (and their outputs) are kept.
+    pub job_retention: Duration,
+    pub max_body_size: usize,
+    /// Of connections handled at once.
+    pub max_connections: usize,
+    /// The hosts job callbacks may be POSTed to; any public one if empty.
+    pub callback_hosts: Vec<String>,
+    /// How long to wait for in-flight work when shutting down.
+    pub shutdown_timeout: Duration,
+    /// Optimize in sandboxed subprocesses, with these limits.
+    pub sandbox: Option<Limits>,
+    /// Serve imgproxy or thumbor URLs.
+    pub compat: Option<Compat>,
+    /// Of the `compat` sources.
+    pub origin_cache: Option<Arc<OriginCache>>,
+    /// Of the (immutable) `compat` outputs.
+    pub cache_max_age: Duration,
+    /// Honor client hints, with these buckets.
+    pub client_hints: Option<ClientHints>,
+    /// Named transforms (and whether to allow only them).
+    pub templates: Option<Templates>,
+}
+
+struct State {
+    config: ServerConfig,
+    jobs: Arc<Jobs>,
+    keys: Option<Keys>,
+    profile: LiveProfile,
+    draining: AtomicBool,
+    /// Connections being handled.
+    connections: AtomicUsize,
+    /// Connections still reading their request, by ID, with when they were
+    /// accepted.
+    waiting: Mutex<HashMap<u64, (TcpStream, Instant)>>,
+    next_connection: AtomicU64,
+}
+
+impl State {
+    fn new(config: ServerConfig, keys: Option<Keys>, profile: LiveProfile) -> Self {
+        State {
+            jobs: Jobs::start(config.workers, config.sandbox.clone(), config.job_retention),
+            config,
+            keys,
+            profile,
+            draining: AtomicBool::new(false),
+            connections: AtomicUsize::new(0),
+            waiting: Mutex::new(HashMap::new()),
+            next_connection: AtomicU64::new(0),
+        }
+    }
+    fn busy(&self) -> usize {
+        self.connections.load(Ordering::SeqCst) + self.jobs.unfinished()
+    }
+    /// Closes the connections that have been waiting for their request for
+    /// `grace` or longer, e.g. idle ones.
+    fn close_waiting(&self, grace: Duration) {
+        let mut waiting = self.waiting.lock().expect("waiting lock");
+        waiting.retain(|_, (stream, accepted)| {
+            if accepted.elapsed() < grace {
+                return true;
+            }
+            let _ = stream.shutdown(Shutdown::Both);
+            false
+        });
+    }
+    /// Of `POST`s, with their template and hints; failures are the
+    /// response to send instead.
+    fn params(&self, request: &Request) -> Result<OptParams, Response> {
+        let expanded;
+        let request_params = match &self.config.templates {
+            Some(templates) => {
+                expanded = templates.expand(request)?;
+                &expanded
+            }
+            None => request,
+        };
+        match OptParams::from_request(request_params, self.profile.get()) {
+            Ok(x) => Ok(self.with_hints(request, x)),
+            Err(message) => Err(Response::text(400, &message)),
+        }
+    }
+    fn with_hints(&self, request: &Request, mut params: OptParams) -> OptParams {
+        if let Some(hints) = &self.config.client_hints {
+            hints.apply(request, &mut params);
+        }
+        params
+    }
+    /// Of responses to hinted requests.
+    fn hint_headers(&self) -> Vec<(&'static str, &'static str)> {
+        if self.config.client_hints.is_some() {
+            vec![("Accept-CH", hints::ACCEPT_CH), ("Vary", hints::VARY)]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// SHUTDOWN
+///////////////////////////////////////////////////////////////////////////////
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_signal(signal: libc::c_int) {
+    match signal {
+        libc::SIGHUP => RELOAD.store(true, Ordering::SeqCst),
+        _ => SHUTDOWN.store(true, Ordering::SeqCst),
+    }
+}
+
+fn install_signal_handlers() {
+    #[cfg(unix)]
+    unsafe {
+        let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGHUP, handler);
+    }
+}
+
+/// How often the profile file is checked for changes (and finished jobs
+/// pruned).
+const PROFILE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Of reads and writes of connections, e.g. of clients that stall.
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// While draining, how long connections may wait for their request before
+/// they’re closed, e.g. so health checks still get their `503`s.
+const DRAIN_WAITING_GRACE: Duration = Duration::from_secs(1);
+
+///////////////////////////////////////////////////////////////////////////////
+// SERVER
+///////////////////////////////////////////////////////////////////////////////
+
+/// Serves until shut down (see above); without `keys`, the API is open.
+pub fn serve(config: ServerConfig, keys: Option<Keys>, profile: LiveProfile) -> std::io::Result<()> {
+    let listener = TcpListener::bind(&config.address)?;
+    // POLLED, TO NOTICE SHUTDOWNS
+    listener.set_nonblocking(true)?;
+    install_signal_handlers();
+    eprintln!("[note] listening on {}", listener.local_addr()?);
+    let state = Arc::new(State::new(config, keys, profile));
+    let mut deadline = None;
+    let mut polled = Instant::now();
+    loop {
+        let reload = if RELOAD.swap(false, Ordering::SeqCst) {
+            Some(state.profile.reload())
+        } else if polled.elapsed() >= PROFILE_POLL_INTERVAL {
+            polled = Instant::now();
+            state.jobs.prune();
+            state.profile.poll()
+        } else {
+            None
+        };
+        match reload {
+            Some(Ok(())) => eprintln!("[note] reloaded the profile"),
+            Some(Err(message)) => eprintln!("[warning] kept the previous profile: {}", message),
+            None => (),
+        }
+        if deadline.is_none() && SHUTDOWN.load(Ordering::SeqCst) {
+            state.draining.store(true, Ordering::SeqCst);
+            deadline = Some(Instant::now() + state.config.shutdown_timeout);
+            eprintln!("[note] shutting down, draining {} requests and jobs", state.busy());
+        }
+        if let Some(deadline) = deadline {
+            state.close_waiting(DRAIN_WAITING_GRACE);
+            let busy = state.busy();
+            if busy == 0 {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                eprintln!("[warning] shutdown deadline passed, abandoning {} requests and jobs", busy);
+                return Ok(());
+            }
+        }
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(SOCKET_TIMEOUT))?;
+                stream.set_write_timeout(Some(SOCKET_TIMEOUT))?;
+                if state.connections.load(Ordering::SeqCst) >= state.config.max_connections {
+                    let response = Response::text(503, "too many connections").header("Retry-After", "1");
+                    let _ = response.write_to(&mut &stream);
+                    continue;
+                }
+                state.connections.fetch_add(1, Ordering::SeqCst);
+                let state = state.clone();
+                std::thread::spawn(move || {
+                    handle(&state, stream);
+                    state.connections.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            Err(error) => eprintln!("[warning] failed to accept connection: {}", error),
+        }
+    }
+}
+
+fn handle(state: &State, stream: TcpStream) {
+    let id = state.next_connection.fetch_add(1, Ordering::SeqCst);
+    let waiting = stream.try_clone().map(|clone| {
+        let mut waiting = state.waiting.lock().expect("waiting lock");
+        waiting.insert(id, (clone, Instant::now()));
+    });
+    let mut reader = BufReader::new(&stream);
+    let request = Request::read(&mut reader, state.config.max_body_size);
+    if waiting.is_ok() && state.waiting.lock().expect("waiting lock").remove(&id).is_none() {
+        // CLOSED WHILE DRAINING
+        return;
+    }
+    let response = match request {
+        Ok(request) => route(state, &request),
+        Err(response) => response,
+    };
+    let mut writer = &stream;
+    if let Err(error) = response.write_to(&mut writer) {
+        eprintln!("[warning] failed to respond: {}", error);
+    }
+}
+
+fn route(state: &State, request: &Request) -> Response {
+    let segments = request.segments();
+    if segments.is_empty() {
+        return match request.method.as_str() {
+            "GET" => Response::text(
+                200,
+                &format!("Imager server, version '{}'.", env!("CARGO_PKG_VERSION")),
+            ),
+            _ => Response::text(405, "method not allowed"),
+        };
+    }
+    let draining = state.draining.load(Ordering::SeqCst);
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["healthz"]) => return Response::text(200, "ok"),
+        ("GET", ["readyz"]) if draining => return Response::text(503, "shutting down"),
+        ("GET", ["readyz"]) => return Response::text(200, "ready"),
+        ("POST", _) if draining => return Response::text(503, "shutting down"),
+        ("GET", ["jobs", ..]) => (),
+        // AUTHORIZED BY THEIR SIGNATURES, NOT KEYS (SEE `keys`)
+        ("GET", _) if state.config.compat.is_some() => {
+            let compat = state.config.compat.as_ref().expect("compat");
+            return serve_compat(state, compat, request);
+        }
+        _ => (),
+    }
+    let permit = match state.keys.as_ref().map(|keys| keys.authorize(request)) {
+        Some(Ok(permit)) => Some(permit),
+        Some(Err(response)) => return response,
+        None => None,
+    };
+    let owner = permit.as_ref().map(|x| x.name().to_owned());
+    let owner = owner.as_deref();
+    let trace = request
+        .header("traceparent")
+        .and_then(|x| TraceContext::parse(x, request.header("tracestate")))
+        .unwrap_or_else(TraceContext::root);
+    let span = Span::start("request", &trace);
+    match (request.method.as_str(), segments.as_slice()) {
+        ("POST", ["opt"]) => {
+            let params = match state.params(request) {
+                Ok(x) => x,
+                Err(response) => return response,
+            };
+            if let Err(error) = check_pipeline(&params.pipeline()) {
+                return Response::text(501, &error.to_string());
+            }
+            let _span = Span::start("optimize", &span.context);
+            match optimize(&request.body, &params, state.config.sandbox.as_ref()) {
+                Ok((output, meta)) => {
+                    let response = Response::new(200, output_format(&params, &meta).mime_type(), output);
+                    let headers = state.hint_headers();
+                    headers.iter().fold(response, |response, (name, value)| response.header(name, value))
+                }
+                Err(message) => Response::text(422, &message),
+            }
+        }
+        ("POST", ["jobs"]) => {
+            let params = match state.params(request) {
+                Ok(x) => x,
+                Err(response) => return response,
+            };
+            if let Err(error) = check_pipeline(&params.pipeline()) {
+                return Response::text(501, &error.to_string());
+            }
+            let callback_hosts = &state.config.callback_hosts;
+            let callback = match request.param("callback").map(|x| Webhook::checked(x, callback_hosts)) {
+                Some(Ok(x)) => Some(x),
+                Some(Err(message)) => return Response::text(400, &message),
+                None => None,
+            };
+            let task = jobs::Task {
+                source: request.body.clone(),
+                params,
+                callback,
+                permit,
+                trace: span.context.clone(),
+            };
+            let id = state.jobs.submit(task);
+            let status = state.jobs.status(&id, owner).expect("submitted job");
+            Response::json(202, &status).header("Location", &format!("/jobs/{}", id))
+        }
+        ("GET", ["jobs", id]) => match state.jobs.status(id, owner) {
+            Some(status) => Response::json(200, &status),
+            None => Response::text(404, "no such job"),
+        },
+        ("GET", ["jobs", id, "result"]) => match state.jobs.output(id, owner) {
+            Some(Ok((format, output))) => {
+                let validators = Validators::of_output(&output);
+                if validators.not_modified(request) {
+                    return cache::not_modified(&validators, &[]);
+                }
+                validators.apply(Response::new(200, format.mime_type(), output))
+            }
+            Some(Err(status)) if status.state == JobState::Failed => Response::json(422, &status),
+            Some(Err(status)) => Response::json(409, &status),
+            None => Response::text(404, "no such job"),
+        },
+        (_, ["opt"]) | (_, ["jobs", ..]) => Response::text(405, "method not allowed"),
+        _ => Response::text(404, "not found"),
+    }
+}
+
+/// Fetches and optimizes the source of an imgproxy or thumbor URL.
+fn serve_compat(state: &State, compat: &Compat, request: &Request) -> Response {
+    let transform = match compat.parse(&request.target) {
+        Ok(x) => x,
+        Err((status, message)) => return Response::text(status, &message),
+    };
+    let max_size = state.config.max_body_size;
+    let source = match &state.config.origin_cache {
+        Some(cache) => cache.get(&transform.source, max_size),
+        None => compat::fetch(&transform.source, max_size).map(Arc::new),
+    };
+    let source = match source {
+        Ok(x) => x,
+        Err(message) => return Response::text(502, &message),
+    };
+    let profile = state.profile.get();
+    let params = transform.params(&source.body, &profile);
+    if state.config.templates.as_ref().is_some_and(|x| !x.allows(&params, &profile)) {
+        return Response::text(403, "only templates are allowed");
+    }
+    let params = state.with_hints(request, params);
+    let pipeline = params.pipeline();
+    if let Err(error) = check_pipeline(&pipeline) {
+        return Response::text(501, &error.to_string());
+    }
+    let validators = Validators {
+        last_modified: source.last_modified.clone(),
+        ..Validators::of_transform(&source.body, &pipeline)
+    };
+    let cache_control = cache::immutable(state.config.cache_max_age);
+    let ignored = transform.ignored.join(", ");
+    let mut headers = vec![("Cache-Control", cache_control.as_str())];
+    headers.extend(state.hint_headers());
+    if !ignored.is_empty() {
+        headers.push((compat::IGNORED_HEADER, &ignored));
+    }
+    if validators.not_modified(request) {
+        return cache::not_modified(&validators, &headers);
+    }
+    match optimize(&source.body, &params, state.config.sandbox.as_ref()) {
+        Ok((output, meta)) => {
+            let response = Response::new(200, output_format(&params, &meta).mime_type(), output);
+            let response = headers
+                .iter()
+                .fold(response, |response, (name, value)| response.header(name, value));
+            validators.apply(response)
+        }
+        Err(message) => Response::text(422, &message),
+    }
+}
+
+/// The optimization parameters of a request.
+#[derive(Debug, Clone)]
+pub struct OptParams {
+    pub output_format: OutputFormat,
+    pub max_size: Option<Resolution>,
+    /// As of when the request arrived.
+    pub profile: Arc<OptProfile>,
+}
+
+impl OptParams {
+    pub fn new(output_format: OutputFormat, max_size: Option<Resolution>) -> Self {
+        OptParams {
+            output_format,
+            max_size,
+            profile: Arc::new(OptProfile::default()),
+        }
+    }
+    pub fn from_request(request: &Request, profile: Arc<OptProfile>) -> Result<Self, String> {
+        let preset = request.param("preset").map(str::parse::<Preset>).transpose()?;
+        let profile = match preset {
+            Some(preset) => Arc::new(preset.apply(&profile)),
+            None => profile,
+        };
+        let output_format = match request.param("format") {
+            Some(x) => x.parse()?,
+            None => profile.formats.first().cloned().unwrap_or_default(),
+        };
+        let max_size = match request.param("size").map(str::parse::<OutputSize>) {
+            Some(Ok(OutputSize::Px(x))) => Some(x),
+            Some(Ok(OutputSize::Full)) => None,
+            Some(Err(message)) => return Err(format!("invalid size: {}", message)),
+            None => profile.max_size.clone(),
+        };
+        let max_size = match preset {
+            Some(preset) => preset.max_size(max_size),
+            None => max_size,
+        };
+        Ok(OptParams {
+            output_format,
+            max_size,
+            profile,
+        })
+    }
+    /// The formats to fall back to when the encoder fails: those after the
+    /// requested one in the profile’s `formats` (its preference list), then
+    /// its `fallbacks`.
+    pub fn fallbacks(&self) -> Vec<OutputFormat> {
+        let formats = &self.profile.formats;
+        let later = match formats.iter().position(|x| *x == self.output_format) {
+            Some(ix) => &formats[ix + 1..],
+            None => &[],
+        };
+        let mut fallbacks = Vec::new();
+        for format in later.iter().chain(&self.profile.fallbacks) {
+            if *format != self.output_format && !fallbacks.contains(format) {
+                fallbacks.push(format.clone());
+            }
+        }
+        fallbacks
+    }
+    /// The job, as a pipeline.
+    pub fn pipeline(&self) -> Pipeline {
+        let profile = &self.profile;
+        let mut stages = vec![Stage::Decode {
+            decoders: profile.decoders.clone(),
+            tolerate_truncated: profile.tolerate_truncated,
+            auto_orient: profile.auto_orient,
+        }];
+        if let Some(max_size) = self.max_size.clone() {
+            stages.push(Stage::Resize {
+                max_size,
+                upscaler: Some(profile.upscaler).filter(|_| profile.allow_upscale),
+                fit: profile.fit,
+                filter: profile.resize_filter,
+                crop: profile.crop,
+            });
+        }
+        if let Some(palette) = profile.palette.clone() {
+            stages.push(Stage::Palette {
+                palette,
+                dither: profile.dither,
+            });
+        }
+        stages.push(Stage::Encode {
+            format: self.output_format.clone(),
+            fallbacks: self.fallbacks(),
+            extreme: profile.extreme,
+            color_mode: profile.color_mode,
+            threshold: profile.threshold,
+            text_protect: profile.text_protect,
+            tuning: profile.tuning,
+        });
+        stages.push(Stage::Metadata {
+            privacy: profile.privacy.clone(),
+            attribution: profile.attribution.clone(),
+            exif_thumbnail: profile.exif_thumbnail,
+        });
+        Pipeline {
+            seed: profile.seed,
+            ..Pipeline::new(stages)
+        }
+    }
+}
+
+/// The format of the output, i.e. a fallback if the requested one failed.
+pub fn output_format<'a>(params: &'a OptParams, meta: &'a OutMeda) -> &'a OutputFormat {
+    meta.fallback.as_ref().map_or(&params.output_format, |x| &x.output)
+}
+
+/// Optimizes an encoded source (sandboxed, given limits); encoder panics
+/// (of every format) are errors.
+pub fn optimize(
+    source: &[u8],
+    params: &OptParams,
+    sandbox: Option<&Limits>,
+) -> Result<(Vec<u8>, OutMeda), String> {
+    let pipeline = params.pipeline();
+    let (output, mut meta) = match sandbox {
+        Some(limits) => crate::sandbox::run(&pipeline, source, limits)?,
+        None => std::panic::catch_unwind(|| crate::pipeline::run(&pipeline, source))
+            .unwrap_or_else(|_| Err(String::from("encoder panicked")))?,
+    };
+    meta.input_size = Some(source.len() as u64);
+    meta.output_size = Some(output.len() as u64);
+    Ok((output, meta))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config() -> ServerConfig {
+        ServerConfig {
+            address: String::from("127.0.0.1:0"),
+            workers: 1,
+            job_retention: jobs::RETENTION,
+            max_body_size: 1024,
+            max_connections: 1,
+            callback_hosts: Vec::new(),
+            shutdown_timeout: Duration::from_secs(1),
+            sandbox: None,
+            compat: None,
+            origin_cache: None,
+            cache_max_age: cache::DEFAULT_MAX_AGE,
+            client_hints: None,
+            templates: None,
+        }
+    }
+
+    #[test]
+    fn test_health_endpoints() {
+        let state = State::new(config(), None, LiveProfile::fixed(OptProfile::default()));
+        let request = |method: &str, path: &str| Request {
+            method: method.to_owned(),
+            path: path.to_owned(),
+            ..Request::default()
+        };
+        assert_eq!(route(&state, &request("GET", "/healthz")).status, 200);
+        assert_eq!(route(&state, &request("GET", "/readyz")).status, 200);
+        assert_eq!(route(&state, &request("POST", "/opt")).status, 422);
+        let preset = |preset: &str| Request {
+            query: vec![(String::from("preset"), preset.to_owned())],
+            ..request("POST", "/opt")
+        };
+        assert_eq!(route(&state, &preset("saver")).status, 400);
+        let params = OptParams::from_request(&preset("data-saver"), state.profile.get()).expect("params");
+        assert_eq!(params.max_size, Some(Resolution::new(1280, 1280)));
+        let callback = |url: &str| Request {
+            query: vec![(String::from("callback"), url.to_owned())],
+            ..request("POST", "/jobs")
+        };
+        assert_eq!(route(&state, &callback("http://169.254.169.254/")).status, 400);
+        state.draining.store(true, Ordering::SeqCst);
+        assert_eq!(route(&state, &request("GET", "/healthz")).status, 200);
+        assert_eq!(route(&state, &request("GET", "/readyz")).status, 503);
+        assert_eq!(route(&state, &request("POST", "/jobs")).status, 503);
+        assert_eq!(state.busy(), 0);
+    }
+
+    #[test]
+    fn test_close_waiting() {
+        use std::io::Read;
+        let state = Arc::new(State::new(config(), None, LiveProfile::fixed(OptProfile::default())));
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let mut client = TcpStream::connect(listener.local_addr().expect("address")).expect("connect");
+        let (stream, _) = listener.accept().expect("accept");
+        let handler = {
+            let state = state.clone();
+            std::thread::spawn(move || handle(&state, stream))
+        };
+        while state.waiting.lock().expect("waiting lock").is_empty() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        state.close_waiting(Duration::from_secs(60));
+        assert_eq!(state.waiting.lock().expect("waiting lock").len(), 1);
+        state.close_waiting(Duration::ZERO);
+        handler.join().expect("handler");
+        assert!(state.waiting.lock().expect("waiting lock").is_empty());
+        let mut response = Vec::new();
+        client.set_read_timeout(Some(Duration::from_secs(5))).expect("timeout");
+        assert_eq!(client.read_to_end(&mut response).expect("closed"), 0);
+    }
+}
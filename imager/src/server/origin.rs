@@ -0,0 +1,435 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! A cache of `--compat` sources (`imager serve --origin-cache-size`), so
+//! repeated transforms of a source don’t fetch it again, and an origin
+//! outage doesn’t take down delivery of what’s cached.
+//!
+//! Freshness is the origin’s, as for a shared cache (RFC 9111): its
+//! `s-maxage` or `max-age`, else its `Expires`, else a tenth of the time
+//! since its `Last-Modified` (up to a day). `no-store` and `private`
+//! sources aren’t cached, and `no-cache` ones are revalidated every time.
+//! Stale sources are
+//!
+//! - served within `stale-while-revalidate` (RFC 5861), while revalidated
+//!   in the background, and
+//! - served within `stale-if-error` when revalidating them fails (e.g. a
+//!   timeout, or a `5xx`),
+//!
+//! which default to the `--origin-max-stale`. Revalidations are
+//! conditional (`If-None-Match`, `If-Modified-Since`), so `304`s keep the
+//! cached body. Concurrent requests of a source share one fetch, and the
+//! least recently used sources are evicted first.
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use super::cache::parse_http_date;
+use super::compat::{self, Fetched};
+
+/// GETs a source, with request headers (see `compat::fetch_with_headers`).
+pub type Fetch = fn(&str, usize, &[(String, String)]) -> Result<Fetched, String>;
+
+/// A day, as heuristic freshness is limited to.
+const MAX_HEURISTIC_FRESHNESS: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long a source may be served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Lifetime {
+    fresh: Duration,
+    stale_while_revalidate: Duration,
+    stale_if_error: Duration,
+}
+
+impl Lifetime {
+    /// Of a response, as of `now`; none if it may not be cached.
+    fn of(fetched: &Fetched, max_stale: Duration, now: SystemTime) -> Option<Self> {
+        let cache_control = fetched.cache_control.as_deref().unwrap_or_default();
+        let directives = cache_control
+            .split(',')
+            .map(|x| match x.split_once('=') {
+                Some((name, value)) => (
+                    name.trim().to_lowercase(),
+                    value.trim().trim_matches('"').parse::<u64>().ok(),
+                ),
+                None => (x.trim().to_lowercase(), None),
+            })
+            .collect::<Vec<_>>();
+        let has = |wanted: &str| directives.iter().any(|(name, _)| name == wanted);
+        let seconds = |wanted: &str| {
+            directives
+                .iter()
+                .find(|(name, _)| name == wanted)
+                .and_then(|(_, x)| *x)
+                .map(Duration::from_secs)
+        };
+        if has("no-store") || has("private") {
+            return None;
+        }
+        let since = |date: &Option<String>| {
+            let date = date.as_deref().and_then(parse_http_date)?;
+            Some(now.duration_since(date).unwrap_or_default())
+        };
+        let until = |date: &Option<String>| {
+            let date = date.as_deref().and_then(parse_http_date)?;
+            Some(date.duration_since(now).unwrap_or_default())
+        };
+        let fresh = if has("no-cache") {
+            Duration::ZERO
+        } else {
+            seconds("s-maxage")
+                .or_else(|| seconds("max-age"))
+                .or_else(|| until(&fetched.expires))
+                .or_else(|| since(&fetched.last_modified).map(|x| (x / 10).min(MAX_HEURISTIC_FRESHNESS)))
+                .unwrap_or_default()
+        };
+        // STALENESS IS FORBIDDEN BY THESE
+        let revalidate = has("must-revalidate") || has("proxy-revalidate") || has("no-cache");
+        let stale = |wanted: &str| match revalidate {
+            true => Duration::ZERO,
+            false => seconds(wanted).unwrap_or(max_stale),
+        };
+        Some(Lifetime {
+            fresh,
+            stale_while_revalidate: stale("stale-while-revalidate"),
+            stale_if_error: stale("stale-if-error"),
+        })
+    }
+}
+
+struct Entry {
+    fetched: Arc<Fetched>,
+    stored: Instant,
+    used: Instant,
+    lifetime: Lifetime,
+    revalidating: bool,
+}
+
+#[derive(Default)]
+struct Entries {
+    by_url: HashMap<String, Entry>,
+    /// Of the cached bodies, in bytes.
+    size: usize,
+}
+
+impl Entries {
+    fn insert(&mut self, url: &str, entry: Entry) {
+        self.size += entry.fetched.body.len();
+        if let Some(previous) = self.by_url.insert(url.to_owned(), entry) {
+            self.size -= previous.fetched.body.len();
+        }
+    }
+    fn remove(&mut self, url: &str) {
+        if let Some(previous) = self.by_url.remove(url) {
+            self.size -= previous.fetched.body.len();
+        }
+    }
+}
+
+/// A fetch in progress: its result, once done, for the requests waiting
+/// on it.
+type Flight = Arc<(Mutex<Option<Result<Arc<Fetched>, String>>>, Condvar)>;
+
+pub struct OriginCache {
+    /// Of the cached bodies, in bytes.
+    capacity: usize,
+    /// Of sources whose origin doesn’t say.
+    max_stale: Duration,
+    fetch: Fetch,
+    entries: Mutex<Entries>,
+    /// By URL.
+    flights: Mutex<HashMap<String, Flight>>,
+}
+
+impl std::fmt::Debug for OriginCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OriginCache")
+            .field("capacity", &self.capacity)
+            .field("max_stale", &self.max_stale)
+            .finish()
+    }
+}
+
+impl OriginCache {
+    pub fn new(capacity: usize, max_stale: Duration) -> Self {
+        OriginCache::with_fetch(capacity, max_stale, compat::fetch_with_headers)
+    }
+    pub fn with_fetch(capacity: usize, max_stale: Duration, fetch: Fetch) -> Self {
+        OriginCache {
+            capacity,
+            max_stale,
+            fetch,
+            entries: Mutex::new(Entries::default()),
+            flights: Mutex::new(HashMap::new()),
+        }
+    }
+    /// The source, cached, or fetched (see above).
+    pub fn get(self: &Arc<Self>, url: &str, max_size: usize) -> Result<Arc<Fetched>, String> {
+        let cached = {
+            let mut entries = self.entries.lock().expect("origin cache lock");
+            entries.by_url.get_mut(url).map(|entry| {
+                entry.used = Instant::now();
+                let revalidating = entry.revalidating;
+                let age = entry.stored.elapsed();
+                let lifetime = entry.lifetime;
+                if age >= lifetime.fresh && age < lifetime.fresh + lifetime.stale_while_revalidate {
+                    entry.revalidating = true;
+                }
+                (entry.fetched.clone(), age, lifetime, revalidating)
+            })
+        };
+        match &cached {
+            Some((fetched, age, lifetime, _)) if *age < lifetime.fresh => return Ok(fetched.clone()),
+            Some((fetched, age, lifetime, revalidating)) if *age < lifetime.fresh + lifetime.stale_while_revalidate => {
+                if !revalidating {
+                    let (cache, url) = (self.clone(), url.to_owned());
+                    std::thread::spawn(move || {
+                        if let Err(message) = cache.revalidate(&url, max_size) {
+                            eprintln!("[warning] kept a stale source: {}", message);
+                        }
+                    });
+                }
+                return Ok(fetched.clone());
+            }
+            _ => (),
+        }
+        match self.revalidate(url, max_size) {
+            Ok(fetched) => Ok(fetched),
+            Err(message) => match cached {
+                Some((fetched, age, lifetime, _)) if age < lifetime.fresh + lifetime.stale_if_error => {
+                    eprintln!("[warning] served a stale source: {}", message);
+                    Ok(fetched)
+                }
+                _ => Err(message),
+            },
+        }
+    }
+    /// See `fetch_and_store`; or, if the source is already being fetched,
+    /// that fetch’s result.
+    fn revalidate(&self, url: &str, max_size: usize) -> Result<Arc<Fetched>, String> {
+        let (flight, leader) = {
+            let mut flights = self.flights.lock().expect("origin cache lock");
+            match flights.get(url) {
+                Some(flight) => (flight.clone(), false),
+                None => {
+                    let flight = Flight::default();
+                    flights.insert(url.to_owned(), flight.clone());
+                    (flight, true)
+                }
+            }
+        };
+        let (result, done) = &*flight;
+        if !leader {
+            let result = done
+                .wait_while(result.lock().expect("origin cache lock"), |x| x.is_none())
+                .expect("origin cache lock");
+            return result.clone().expect("a result");
+        }
+        let fetched = self.fetch_and_store(url, max_size);
+        self.flights.lock().expect("origin cache lock").remove(url);
+        *result.lock().expect("origin cache lock") = Some(fetched.clone());
+        done.notify_all();
+        fetched
+    }
+    /// Fetches the source, conditionally if cached, and caches it.
+    fn fetch_and_store(&self, url: &str, max_size: usize) -> Result<Arc<Fetched>, String> {
+        let previous = {
+            let entries = self.entries.lock().expect("origin cache lock");
+            entries.by_url.get(url).map(|x| x.fetched.clone())
+        };
+        let mut conditions = Vec::new();
+        if let Some(previous) = &previous {
+            if let Some(etag) = &previous.etag {
+                conditions.push((String::from("If-None-Match"), etag.clone()));
+            }
+            if let Some(date) = &previous.last_modified {
+                conditions.push((String::from("If-Modified-Since"), date.clone()));
+            }
+        }
+        let fetched = match ((self.fetch)(url, max_size, &conditions), previous) {
+            // THE CACHED BODY, WITH THE UPDATED HEADERS (RFC 9111, 4.3.4)
+            (Ok(fetched), Some(previous)) if fetched.status == 304 => Fetched {
+                status: previous.status,
+                body: previous.body.clone(),
+                last_modified: fetched.last_modified.or_else(|| previous.last_modified.clone()),
+                etag: fetched.etag.or_else(|| previous.etag.clone()),
+                cache_control: fetched.cache_control.or_else(|| previous.cache_control.clone()),
+                expires: fetched.expires.or_else(|| previous.expires.clone()),
+            },
+            (Ok(fetched), _) => fetched,
+            (Err(message), _) => {
+                let mut entries = self.entries.lock().expect("origin cache lock");
+                if let Some(entry) = entries.by_url.get_mut(url) {
+                    entry.revalidating = false;
+                }
+                return Err(message);
+            }
+        };
+        let fetched = Arc::new(fetched);
+        self.store(url, fetched.clone());
+        Ok(fetched)
+    }
+    fn store(&self, url: &str, fetched: Arc<Fetched>) {
+        let mut entries = self.entries.lock().expect("origin cache lock");
+        let lifetime = Lifetime::of(&fetched, self.max_stale, SystemTime::now());
+        let lifetime = match lifetime.filter(|_| fetched.body.len() <= self.capacity) {
+            Some(x) => x,
+            None => {
+                entries.remove(url);
+                return;
+            }
+        };
+        let now = Instant::now();
+        let entry = Entry {
+            fetched,
+            stored: now,
+            used: now,
+            lifetime,
+            revalidating: false,
+        };
+        entries.insert(url, entry);
+        while entries.size > self.capacity {
+            let oldest = entries
+                .by_url
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(url, _)| url.clone())
+                .expect("an entry");
+            entries.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static FETCHES: AtomicUsize = AtomicUsize::new(0);
+    /// Whether the origin is up.
+    static UP: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(true);
+
+    fn origin(url: &str, _: usize, conditions: &[(String, String)]) -> Result<Fetched, String> {
+        FETCHES.fetch_add(1, Ordering::SeqCst);
+        if !UP.load(Ordering::SeqCst) {
+            return Err(String::from("origin down"));
+        }
+        // E.G. `…/max-age:60+stale-if-error:0`
+        let cache_control = url.rsplit('/').next().map(|x| x.replace('+', ", ").replace(':', "="));
+        let etag = Some(String::from("\"v1\""));
+        if conditions
+            .iter()
+            .any(|(name, value)| name == "If-None-Match" && etag.as_ref() == Some(value))
+        {
+            return Ok(Fetched {
+                status: 304,
+                etag,
+                ..Fetched::default()
+            });
+        }
+        Ok(Fetched {
+            status: 200,
+            body: url.as_bytes().to_vec(),
+            etag,
+            cache_control,
+            ..Fetched::default()
+        })
+    }
+
+    #[test]
+    fn test_origin_cache() {
+        let max_stale = Duration::from_secs(60);
+        let lifetime = |cache_control: &str, last_modified: Option<&str>| {
+            let fetched = Fetched {
+                cache_control: Some(cache_control.to_owned()),
+                last_modified: last_modified.map(str::to_owned),
+                ..Fetched::default()
+            };
+            let now = parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT").expect("date");
+            Lifetime::of(&fetched, max_stale, now)
+        };
+        let of = |fresh, stale_while_revalidate, stale_if_error| {
+            Some(Lifetime {
+                fresh: Duration::from_secs(fresh),
+                stale_while_revalidate: Duration::from_secs(stale_while_revalidate),
+                stale_if_error: Duration::from_secs(stale_if_error),
+            })
+        };
+        assert_eq!(
+            lifetime("public, max-age=10, s-maxage=20, stale-if-error=5", None),
+            of(20, 60, 5)
+        );
+        assert_eq!(lifetime("max-age=10, must-revalidate", None), of(10, 0, 0));
+        assert_eq!(lifetime("no-store", None), None);
+        assert_eq!(lifetime("private, max-age=10", None), None);
+        assert_eq!(lifetime("", Some("Sun, 06 Nov 1994 07:49:37 GMT")), of(360, 60, 60));
+        // FETCHED ONCE WHILE FRESH, REVALIDATED (WITH A `304`) ONCE STALE
+        let cache = Arc::new(OriginCache::with_fetch(1024, max_stale, origin));
+        let get = |url: &str| cache.get(url, 1024).map(|x| x.body.clone());
+        let fresh = "https://origin/max-age:60";
+        assert_eq!(get(fresh), Ok(fresh.as_bytes().to_vec()));
+        assert_eq!(get(fresh), Ok(fresh.as_bytes().to_vec()));
+        assert_eq!(FETCHES.load(Ordering::SeqCst), 1);
+        let stale = "https://origin/max-age:0+stale-while-revalidate:60";
+        assert_eq!(get(stale), Ok(stale.as_bytes().to_vec()));
+        assert_eq!(get(stale), Ok(stale.as_bytes().to_vec()));
+        for _ in 0..200 {
+            if FETCHES.load(Ordering::SeqCst) == 3 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(FETCHES.load(Ordering::SeqCst), 3);
+        // THE ORIGIN IS DOWN: STALE WITHIN `stale-if-error`, ELSE FAILED
+        let tolerant = "https://origin/max-age:0+stale-while-revalidate:0";
+        let strict = "https://origin/max-age:0+stale-while-revalidate:0+stale-if-error:0";
+        assert!(get(tolerant).is_ok() && get(strict).is_ok());
+        UP.store(false, Ordering::SeqCst);
+        assert_eq!(get(tolerant), Ok(tolerant.as_bytes().to_vec()));
+        assert!(get(strict).is_err());
+        assert!(get("https://origin/uncached").is_err());
+        UP.store(true, Ordering::SeqCst);
+        // EVICTED, LEAST RECENTLY USED FIRST
+        let small = Arc::new(OriginCache::with_fetch(40, max_stale, origin));
+        let (first, second) = ("https://origin/1/max-age:60", "https://origin/2/max-age:60");
+        small.get(first, 1024).expect("first");
+        small.get(second, 1024).expect("second");
+        let entries = small.entries.lock().expect("lock");
+        assert_eq!(entries.by_url.keys().collect::<Vec<_>>(), [second]);
+        assert_eq!(entries.size, second.len());
+    }
+
+    static SLOW_FETCHES: AtomicUsize = AtomicUsize::new(0);
+
+    fn slow_origin(url: &str, _: usize, _: &[(String, String)]) -> Result<Fetched, String> {
+        SLOW_FETCHES.fetch_add(1, Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(200));
+        Ok(Fetched {
+            status: 200,
+            body: url.as_bytes().to_vec(),
+            cache_control: Some(String::from("max-age=60")),
+            ..Fetched::default()
+        })
+    }
+
+    #[test]
+    fn test_single_flight() {
+        // CONCURRENT MISSES OF A SOURCE SHARE ONE FETCH
+        let cache = Arc::new(OriginCache::with_fetch(1024, Duration::from_secs(60), slow_origin));
+        let url = "https://origin/shared";
+        let barrier = std::sync::Barrier::new(4);
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    barrier.wait();
+                    assert_eq!(cache.get(url, 1024).map(|x| x.body.clone()), Ok(url.as_bytes().to_vec()));
+                });
+            }
+        });
+        assert_eq!(SLOW_FETCHES.load(Ordering::SeqCst), 1);
+        assert!(cache.flights.lock().expect("lock").is_empty());
+        // AND ARE CACHED
+        cache.get(url, 1024).expect("cached");
+        assert_eq!(SLOW_FETCHES.load(Ordering::SeqCst), 1);
+    }
+}
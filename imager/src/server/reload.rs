@@ -0,0 +1,95 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Hot-reloadable profiles: re-read on SIGHUP, or when the file changes,
+//! so quality targets and allowed transforms can be tuned without a
+//! restart. A profile that fails to parse or validate is reported, and
+//! the previous one stays in effect.
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+use crate::profile::OptProfile;
+
+pub struct LiveProfile {
+    /// `None` for the (fixed) default profile.
+    path: Option<PathBuf>,
+    current: RwLock<Arc<OptProfile>>,
+    modified: Mutex<Option<SystemTime>>,
+}
+
+impl LiveProfile {
+    pub fn fixed(profile: OptProfile) -> Self {
+        LiveProfile {
+            path: None,
+            current: RwLock::new(Arc::new(profile)),
+            modified: Mutex::new(None),
+        }
+    }
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<Self, String> {
+        let path = path.into();
+        let modified = modified(&path);
+        let profile = OptProfile::open(&path)?;
+        Ok(LiveProfile {
+            path: Some(path),
+            current: RwLock::new(Arc::new(profile)),
+            modified: Mutex::new(modified),
+        })
+    }
+    /// The profile in effect; requests keep theirs across reloads.
+    pub fn get(&self) -> Arc<OptProfile> {
+        self.current.read().expect("profile lock").clone()
+    }
+    /// Re-reads the profile.
+    pub fn reload(&self) -> Result<(), String> {
+        let path = match self.path.as_ref() {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        *self.modified.lock().expect("profile lock") = modified(path);
+        let profile = OptProfile::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        *self.current.write().expect("profile lock") = Arc::new(profile);
+        Ok(())
+    }
+    /// Reloads if the file changed since the last (re)load; `None` if it
+    /// didn’t.
+    pub fn poll(&self) -> Option<Result<(), String>> {
+        let changed = {
+            let path = self.path.as_ref()?;
+            modified(path) != *self.modified.lock().expect("profile lock")
+        };
+        if changed {
+            Some(self.reload())
+        } else {
+            None
+        }
+    }
+}
+
+fn modified(path: &std::path::Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::OutputFormat;
+
+    #[test]
+    fn test_reload_profile() {
+        let path = std::env::temp_dir().join(format!("imager-profile-{}.json", std::process::id()));
+        std::fs::write(&path, r#"{"formats": ["Png"]}"#).expect("write profile");
+        let live = LiveProfile::open(&path).expect("open profile");
+        assert_eq!(live.get().formats, vec![OutputFormat::Png]);
+        assert!(live.poll().is_none());
+        std::fs::write(&path, r#"{"formats": ["Webp"], "seed": 3}"#).expect("write profile");
+        live.reload().expect("reload");
+        let profile = live.get();
+        assert_eq!(profile.formats, vec![OutputFormat::Webp]);
+        // INVALID PROFILES ARE REJECTED, AND THE LAST GOOD ONE STAYS
+        std::fs::write(&path, r#"{"formats": []}"#).expect("write profile");
+        assert!(live.reload().is_err());
+        assert_eq!(live.get(), profile);
+        std::fs::remove_file(&path).expect("remove profile");
+    }
+}
@@ -0,0 +1,197 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Transform templates (`imager serve --templates`): named sets of the
+//! transform parameters, that requests use by name (`?template=thumb`).
+//!
+//! The templates file is JSON, e.g.
+//!
+//! ```json
+//! {"templates": {"thumb": {"format": "webp", "size": "320x320"},
+//!                "hero": {"size": "1920x1080", "preset": "data-saver"}},
+//!  "only": true}
+//! ```
+//!
+//! where every parameter is optional, and parsed as the query parameter of
+//! the same name. Parameters of the request override the template’s,
+//! unless `only`, which restricts the server to the templates: requests
+//! must name one, and can’t add transform parameters, and `--compat` URLs
+//! must be exactly the transform of one (e.g. `rs:fit:320:320/…@webp`), so
+//! the outputs of each source (i.e. the cache in front of the server, and
+//! the encodes that a client can ask for) are bounded by the templates.
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use super::http::{Request, Response};
+use super::OptParams;
+use crate::data::{OutputFormat, OutputSize};
+use crate::profile::{OptProfile, Preset};
+
+/// The query parameters of transforms.
+pub const TRANSFORM_PARAMS: [&str; 3] = ["format", "size", "preset"];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    #[serde(default)]
+    pub format: Option<String>,
+    #[serde(default)]
+    pub size: Option<String>,
+    #[serde(default)]
+    pub preset: Option<String>,
+}
+
+impl Template {
+    fn params(&self) -> Vec<(String, String)> {
+        let values = [&self.format, &self.size, &self.preset];
+        TRANSFORM_PARAMS
+            .iter()
+            .zip(values)
+            .filter_map(|(name, value)| Some((name.to_string(), value.clone()?)))
+            .collect()
+    }
+    fn validate(&self) -> Result<(), String> {
+        if let Some(format) = &self.format {
+            format.parse::<OutputFormat>()?;
+        }
+        if let Some(size) = &self.size {
+            size.parse::<OutputSize>()?;
+        }
+        if let Some(preset) = &self.preset {
+            preset.parse::<Preset>()?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TemplatesFile {
+    templates: BTreeMap<String, Template>,
+    #[serde(default)]
+    only: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Templates {
+    templates: BTreeMap<String, Template>,
+    /// Reject everything else.
+    pub only: bool,
+}
+
+impl Templates {
+    pub fn new(templates: BTreeMap<String, Template>, only: bool) -> Result<Self, String> {
+        for (name, template) in &templates {
+            template
+                .validate()
+                .map_err(|message| format!("template {}: {}", name, message))?;
+        }
+        if only && templates.is_empty() {
+            return Err(String::from("`only` without templates"));
+        }
+        Ok(Templates { templates, only })
+    }
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let file: TemplatesFile = serde_json::from_str(&source).map_err(|e| e.to_string())?;
+        Templates::new(file.templates, file.only)
+    }
+    /// The request, with the parameters of its template; failures are the
+    /// response to send instead.
+    pub fn expand(&self, request: &Request) -> Result<Request, Response> {
+        let name = match request.param("template") {
+            Some(name) => name,
+            None if self.only => return Err(Response::text(403, "only templates are allowed")),
+            None => return Ok(request.clone()),
+        };
+        let template = match self.templates.get(name) {
+            Some(x) => x,
+            None => return Err(Response::text(400, &format!("unknown template {}", name))),
+        };
+        let overridden = |name: &str| request.param(name).is_some();
+        if let Some(name) = TRANSFORM_PARAMS.iter().find(|x| overridden(x)).filter(|_| self.only) {
+            return Err(Response::text(
+                403,
+                &format!("only templates are allowed, without {}", name),
+            ));
+        }
+        let mut query = template.params();
+        query.retain(|(name, _)| !overridden(name));
+        query.extend(request.query.iter().filter(|(name, _)| name != "template").cloned());
+        Ok(Request {
+            query,
+            ..request.clone()
+        })
+    }
+    /// Whether (with `only`) the params (e.g. of a `--compat` URL) are
+    /// those of a template.
+    pub fn allows(&self, params: &OptParams, profile: &Arc<OptProfile>) -> bool {
+        !self.only
+            || self.templates.values().any(|template| {
+                let request = Request {
+                    query: template.params(),
+                    ..Request::default()
+                };
+                match OptParams::from_request(&request, profile.clone()) {
+                    Ok(x) => {
+                        x.output_format == params.output_format
+                            && x.max_size == params.max_size
+                            && x.profile == params.profile
+                    }
+                    Err(_) => false,
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::Resolution;
+
+    #[test]
+    fn test_expand() {
+        let thumb = Template {
+            format: Some(String::from("webp")),
+            size: Some(String::from("320x320")),
+            preset: None,
+        };
+        let templates = BTreeMap::from([(String::from("thumb"), thumb)]);
+        let invalid = Template {
+            size: Some(String::from("huge")),
+            ..Template::default()
+        };
+        assert!(Templates::new(BTreeMap::from([(String::from("hero"), invalid)]), false).is_err());
+        let request = |query: &[(&str, &str)]| Request {
+            query: query.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            ..Request::default()
+        };
+        let profile = Arc::new(OptProfile::default());
+        let params = |request: &Request| OptParams::from_request(request, profile.clone()).expect("params");
+        let open = Templates::new(templates.clone(), false).expect("templates");
+        let expanded = open
+            .expand(&request(&[("template", "thumb"), ("size", "100x100")]))
+            .expect("expand");
+        let expanded = params(&expanded);
+        assert_eq!(expanded.output_format, OutputFormat::Webp);
+        assert_eq!(expanded.max_size, Some(Resolution::new(100, 100)));
+        assert_eq!(
+            open.expand(&request(&[("size", "full")])).expect("expand").query.len(),
+            1
+        );
+        assert_eq!(
+            open.expand(&request(&[("template", "hero")])).err().map(|x| x.status),
+            Some(400)
+        );
+        let only = Templates::new(templates, true).expect("templates");
+        let status = |query: &[(&str, &str)]| only.expand(&request(query)).err().map(|x| x.status);
+        assert_eq!(status(&[]), Some(403));
+        assert_eq!(status(&[("template", "thumb"), ("format", "png")]), Some(403));
+        assert_eq!(status(&[("template", "thumb")]), None);
+        // E.G. COMPAT URLS
+        let thumb = params(&request(&[("format", "webp"), ("size", "320x320")]));
+        assert!(only.allows(&thumb, &profile));
+        assert!(!only.allows(&params(&request(&[("format", "webp")])), &profile));
+        assert!(open.allows(&params(&request(&[])), &profile));
+    }
+}
@@ -0,0 +1,376 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Inputs and outputs on remote servers, as `sftp://[user@]host[:port]/path`
+//! URLs, e.g. to optimize assets in place without mounting filesystems:
+//!
+//! ```no_run
+//! # use imager::sftp::{Pool, SftpUrl};
+//! let pool = Pool::new()?;
+//! let url: SftpUrl = "sftp://deploy@assets.example.com/srv/www/hero.jpg".parse()?;
+//! let source = pool.read(&url)?;
+//! pool.write(&url, &source)?;
+//! # Ok::<(), String>(())
+//! ```
+//!
+//! Transfers run the OpenSSH `sftp` client, so authentication (keys, the
+//! agent, `~/.ssh/config` hosts) is the user’s own. The pool opens one
+//! master connection per server (an OpenSSH control socket), on its first
+//! transfer, which every later transfer (e.g. of a batch) multiplexes, and
+//! closes them all when dropped.
+//!
+//! Paths are taken literally (not percent-decoded); `sftp://host/~/x.jpg`
+//! is relative to the login directory. Writes go to a temporary file
+//! beside the output, renamed over it, so readers never see a partial one.
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::error::{ImagerError, Result};
+use crate::workspace::JobDir;
+
+pub const SCHEME: &str = "sftp://";
+
+///////////////////////////////////////////////////////////////////////////////
+// URLS
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SftpUrl {
+    pub user: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+    /// Absolute, or relative to the login directory.
+    pub path: String,
+}
+
+impl SftpUrl {
+    /// Of paths standing for URLs, e.g. those of `--inputs`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.to_str().filter(|x| is_url(x))?.parse().ok()
+    }
+    /// The same server, at another path.
+    pub fn with_path(&self, path: impl Into<String>) -> Self {
+        SftpUrl {
+            path: path.into(),
+            ..self.clone()
+        }
+    }
+    /// E.g. `deploy@assets.example.com`, for `ssh`.
+    fn destination(&self) -> String {
+        match &self.user {
+            Some(user) => format!("{}@{}", user, self.host),
+            None => self.host.clone(),
+        }
+    }
+    /// Of the server’s pooled connection.
+    fn key(&self) -> String {
+        format!("{}:{}", self.destination(), self.port.unwrap_or(22))
+    }
+}
+
+pub fn is_url(value: &str) -> bool {
+    value.starts_with(SCHEME)
+}
+
+impl std::str::FromStr for SftpUrl {
+    type Err = String;
+    fn from_str(value: &str) -> std::result::Result<Self, String> {
+        let invalid = |reason: &str| format!("invalid SFTP URL {:?}: {}", value, reason);
+        let rest = value
+            .strip_prefix(SCHEME)
+            .ok_or_else(|| invalid("not sftp://"))?;
+        let (authority, path) = rest.split_at(rest.find('/').ok_or_else(|| invalid("no path"))?);
+        let (user, host_port) = match authority.rsplit_once('@') {
+            Some((user, host_port)) if !user.is_empty() => (Some(user.to_owned()), host_port),
+            Some(_) => return Err(invalid("empty user")),
+            None => (None, authority),
+        };
+        // E.G. `[::1]:2222`
+        let (host, port) = match host_port.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, port) = bracketed
+                    .split_once(']')
+                    .ok_or_else(|| invalid("unclosed IPv6 address"))?;
+                (host, port.strip_prefix(':'))
+            }
+            None => match host_port.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (host_port, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(invalid("no host"));
+        }
+        let port = port
+            .map(|x| x.parse::<u16>().map_err(|_| invalid("bad port")))
+            .transpose()?;
+        let path = match path.strip_prefix("/~/") {
+            Some(relative) => relative.to_owned(),
+            None => path.to_owned(),
+        };
+        if path.is_empty() || path == "/" {
+            return Err(invalid("no path"));
+        }
+        Ok(SftpUrl {
+            user,
+            host: host.to_owned(),
+            port,
+            path,
+        })
+    }
+}
+
+impl std::fmt::Display for SftpUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", SCHEME)?;
+        if let Some(user) = &self.user {
+            write!(f, "{}@", user)?;
+        }
+        match self.host.contains(':') {
+            true => write!(f, "[{}]", self.host)?,
+            false => write!(f, "{}", self.host)?,
+        }
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        match self.path.starts_with('/') {
+            true => write!(f, "{}", self.path),
+            false => write!(f, "/~/{}", self.path),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// BATCH COMMANDS
+///////////////////////////////////////////////////////////////////////////////
+
+/// An argument of `sftp`’s batch commands, which are split on spaces and
+/// unquoted like a shell’s; remote paths are globbed, unless `literal`.
+fn quote(argument: &str, literal: bool) -> String {
+    let mut output = String::from("\"");
+    for char in argument.chars() {
+        if matches!(char, '"' | '\\') || (literal && matches!(char, '*' | '?' | '[' | ']')) {
+            output.push('\\');
+        }
+        output.push(char);
+    }
+    output.push('"');
+    output
+}
+
+fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// The parent directory of a remote path, if any.
+fn parent(path: &str) -> Option<&str> {
+    path.rsplit_once('/').map(|x| x.0).filter(|x| !x.is_empty())
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// CONNECTION POOL
+///////////////////////////////////////////////////////////////////////////////
+
+/// Of a server: the `ssh` destination of its master connection, or why
+/// connecting failed (which later transfers fail with too).
+type Master = std::result::Result<String, String>;
+
+/// By server (its `key`): the index its control socket is named after,
+/// and its master connection, once connecting.
+type Masters = HashMap<String, (usize, Arc<OnceLock<Master>>)>;
+
+/// Connections to servers, shared by every transfer (and thread).
+pub struct Pool {
+    /// Of the control sockets.
+    dir: JobDir,
+    masters: Mutex<Masters>,
+}
+
+impl Pool {
+    pub fn new() -> Result<Self> {
+        Ok(Pool {
+            dir: JobDir::new("sftp")?,
+            masters: Mutex::new(HashMap::new()),
+        })
+    }
+    /// The server’s control socket; connecting on first use, while other
+    /// transfers to the server wait (so there’s only ever the one).
+    fn master(&self, url: &SftpUrl) -> std::result::Result<PathBuf, String> {
+        let (index, master) = {
+            let mut masters = self.masters.lock().expect("sftp pool lock");
+            let index = masters.len();
+            masters
+                .entry(url.key())
+                .or_insert_with(|| (index, Arc::new(OnceLock::new())))
+                .clone()
+        };
+        let socket = self.dir.join(index.to_string());
+        master
+            .get_or_init(|| connect(url, &socket))
+            .clone()
+            .map(|_| socket)
+    }
+    /// Runs the batch `commands` (one per line), returning the output.
+    fn run(&self, url: &SftpUrl, commands: &str) -> std::result::Result<Vec<u8>, String> {
+        let socket = self.master(url)?;
+        let mut command = Command::new("sftp");
+        command
+            .args(["-q", "-b", "-", "-o", "ControlMaster=no", "-o", "BatchMode=yes", "-o"])
+            .arg(format!("ControlPath={}", socket.display()));
+        if let Some(port) = url.port {
+            command.arg("-P").arg(port.to_string());
+        }
+        let mut child = command
+            .arg("--")
+            .arg(url.destination())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("failed to run sftp: {}", e))?;
+        child
+            .stdin
+            .take()
+            .expect("piped stdin")
+            .write_all(commands.as_bytes())
+            .map_err(|e| e.to_string())?;
+        let output = child.wait_with_output().map_err(|e| e.to_string())?;
+        if !output.status.success() {
+            return Err(format!("{}: {}", url, String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        Ok(output.stdout)
+    }
+    pub fn read(&self, url: &SftpUrl) -> Result<Vec<u8>> {
+        let dir = JobDir::new("sftp-get")?;
+        let local = dir.join("file");
+        let local_str = local.to_str().ok_or_else(non_utf8)?;
+        let command = format!("get {} {}\n", quote(&url.path, true), quote(local_str, false));
+        self.run(url, &command).map_err(ImagerError::Transfer)?;
+        std::fs::read(&local).map_err(ImagerError::io(&local))
+    }
+    /// Writes the file (creating its directory, but not the directory’s
+    /// parents); atomically where the server supports POSIX renames
+    /// (OpenSSH’s do).
+    pub fn write(&self, url: &SftpUrl, data: &[u8]) -> Result<()> {
+        let dir = JobDir::new("sftp-put")?;
+        let local = dir.join("file");
+        std::fs::write(&local, data).map_err(ImagerError::io(&local))?;
+        let local_str = local.to_str().ok_or_else(non_utf8)?;
+        let temporary = format!("{}.imager-{}.tmp", url.path, std::process::id());
+        let mut commands = String::new();
+        if let Some(parent) = parent(&url.path) {
+            // `-` IGNORES THE FAILURE, E.G. OF AN EXISTING DIRECTORY
+            commands.push_str(&format!("-mkdir {}\n", quote(parent, true)));
+        }
+        commands.push_str(&format!("put {} {}\n", quote(local_str, false), quote(&temporary, true)));
+        commands.push_str(&format!("rename {} {}\n", quote(&temporary, true), quote(&url.path, true)));
+        let result = self.run(url, &commands).map(drop);
+        if result.is_err() {
+            let _ = self.run(url, &format!("-rm {}\n", quote(&temporary, true)));
+        }
+        result.map_err(ImagerError::Transfer)
+    }
+    /// The files matching the URL’s path, if a glob pattern (e.g.
+    /// `sftp://host/photos/*.jpg`), in order; else the URL.
+    pub fn glob(&self, url: &SftpUrl) -> Result<Vec<SftpUrl>> {
+        if !is_glob(&url.path) {
+            return Ok(vec![url.clone()]);
+        }
+        // `@` DOESN’T ECHO THE COMMAND
+        let output = self
+            .run(url, &format!("@ls -1 {}\n", quote(&url.path, false)))
+            .map_err(ImagerError::Transfer)?;
+        let mut paths = String::from_utf8_lossy(&output)
+            .lines()
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+            .map(|x| url.with_path(x))
+            .collect::<Vec<_>>();
+        paths.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(paths)
+    }
+}
+
+fn non_utf8() -> ImagerError {
+    ImagerError::InvalidInput(String::from("non UTF-8 temp path"))
+}
+
+/// Opens the master connection of the server, returning once
+/// authenticated (which may prompt, e.g. for a passphrase).
+fn connect(url: &SftpUrl, socket: &Path) -> Master {
+    let mut command = Command::new("ssh");
+    command
+        .args(["-M", "-N", "-f", "-o", "ControlPersist=yes", "-S"])
+        .arg(socket);
+    if let Some(port) = url.port {
+        command.arg("-p").arg(port.to_string());
+    }
+    let output = command
+        .arg("--")
+        .arg(url.destination())
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|e| format!("failed to run ssh: {}", e))?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("failed to connect to {}: {}", url.key(), message.trim()));
+    }
+    Ok(url.destination())
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        let masters = self.masters.get_mut().expect("sftp pool lock");
+        for (index, master) in masters.values() {
+            if let Some(Ok(destination)) = master.get() {
+                let _ = Command::new("ssh")
+                    .arg("-S")
+                    .arg(self.dir.join(index.to_string()))
+                    .args(["-O", "exit", "--"])
+                    .arg(destination)
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_urls() {
+        let url: SftpUrl = "sftp://deploy@assets.example.com:2222/srv/www/hero.jpg".parse().expect("url");
+        assert_eq!(url.user.as_deref(), Some("deploy"));
+        assert_eq!(url.host, "assets.example.com");
+        assert_eq!(url.port, Some(2222));
+        assert_eq!(url.path, "/srv/www/hero.jpg");
+        assert_eq!(url.to_string(), "sftp://deploy@assets.example.com:2222/srv/www/hero.jpg");
+        let home: SftpUrl = "sftp://[::1]/~/photos/a b.jpg".parse().expect("url");
+        assert_eq!((home.host.as_str(), home.path.as_str()), ("::1", "photos/a b.jpg"));
+        assert_eq!(home.to_string(), "sftp://[::1]/~/photos/a b.jpg");
+        // AS OUTPUT PATHS ARE DERIVED
+        let mut path = PathBuf::from("sftp://host/out").join("hero.jpg");
+        path.set_extension("webp");
+        assert_eq!(SftpUrl::from_path(&path).map(|x| x.path).as_deref(), Some("/out/hero.webp"));
+        for invalid in ["sftp://host", "sftp://host/", "sftp://@host/x", "sftp://host:ssh/x", "https://host/x"] {
+            assert!(invalid.parse::<SftpUrl>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote(r#"/a "b"/c*.jpg"#, true), r#""/a \"b\"/c\*.jpg""#);
+        assert_eq!(quote("/photos/*.jpg", false), r#""/photos/*.jpg""#);
+        assert_eq!(parent("/srv/hero.jpg"), Some("/srv"));
+        assert_eq!(parent("/hero.jpg"), None);
+        assert_eq!(parent("hero.jpg"), None);
+    }
+}
@@ -0,0 +1,151 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Responsive images (`imager srcset`): the variants of a source at several
+//! widths (e.g. `480w,960w,1920w`) and formats, from a single decode (see
+//! `Job::sizes`), named by their width (e.g. `photo-480w.webp`), with a
+//! manifest of the variants and of the `srcset` attribute of each format,
+//! e.g. for a `<picture>` element’s `<source type="image/webp" srcset="…">`.
+//!
+//! Sources smaller than a width aren’t enlarged, so widths can yield the
+//! same variant; it’s listed once, at its actual width.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::api::JobOutput;
+use crate::data::{OutputFormat, OutputSize, Resolution};
+
+/// A variant’s max width, written as `480w` (as `srcset` descriptors) or
+/// `480`; the height is unbounded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Width(pub u32);
+
+impl Width {
+    pub fn size(&self) -> OutputSize {
+        OutputSize::Px(Resolution::new(self.0, u32::MAX))
+    }
+}
+
+impl std::str::FromStr for Width {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim_end_matches('w').parse::<u32>() {
+            Ok(0) | Err(_) => Err(format!("Unknown width {}", s)),
+            Ok(width) => Ok(Width(width)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Variant {
+    pub path: PathBuf,
+    pub format: OutputFormat,
+    pub width: u32,
+    pub height: u32,
+    /// In bytes.
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub source: PathBuf,
+    /// By format, then width.
+    pub variants: Vec<Variant>,
+    /// Of each format (by MIME type), with the variants’ file names as the
+    /// URLs, e.g. `photo-480w.webp 480w, photo-960w.webp 960w`.
+    pub srcset: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("to json str failed")
+    }
+}
+
+/// E.g. `photo-480w.webp`, of `photo.jpeg`.
+pub fn file_name(source: &Path, width: u32, format: &OutputFormat) -> String {
+    let stem = source.file_stem().and_then(|x| x.to_str()).unwrap_or("image");
+    format!("{}-{}w.{}", stem, width, format.extension())
+}
+
+/// Writes the outputs (of a `Job` with several sizes) to the directory,
+/// dropping duplicates (e.g. of widths larger than the source).
+pub fn write(source: &Path, outputs: &[JobOutput], output_dir: &Path) -> Result<Manifest, String> {
+    let mut variants = Vec::<Variant>::new();
+    for output in outputs {
+        let (width, height) = output.dimensions;
+        let duplicate = variants.iter().any(|x| x.format == output.format && x.width == width);
+        if duplicate {
+            continue;
+        }
+        let path = output_dir.join(file_name(source, width, &output.format));
+        std::fs::write(&path, &output.data).map_err(|e| format!("{}: {}", path.display(), e))?;
+        variants.push(Variant {
+            path,
+            format: output.format.clone(),
+            width,
+            height,
+            size: output.data.len() as u64,
+        });
+    }
+    variants.sort_by_key(|x| (x.format.mime_type(), x.width));
+    Ok(Manifest {
+        source: source.to_owned(),
+        srcset: srcsets(&variants),
+        variants,
+    })
+}
+
+fn srcsets(variants: &[Variant]) -> BTreeMap<String, String> {
+    let mut srcsets = BTreeMap::<String, Vec<String>>::new();
+    for variant in variants {
+        let name = variant.path.file_name().and_then(|x| x.to_str()).unwrap_or_default();
+        let candidate = format!("{} {}w", name, variant.width);
+        srcsets
+            .entry(variant.format.mime_type().to_owned())
+            .or_default()
+            .push(candidate);
+    }
+    srcsets
+        .into_iter()
+        .map(|(mime, candidates)| (mime, candidates.join(", ")))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::Job;
+
+    #[test]
+    fn test_srcset() {
+        assert_eq!("480w".parse(), Ok(Width(480)));
+        assert!("0w".parse::<Width>().is_err());
+        let source = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(200, 100, |x, y| {
+            image::Rgb([x as u8, y as u8, 128])
+        }));
+        let mut png = std::io::Cursor::new(Vec::new());
+        source
+            .write_to(&mut png, image::ImageFormat::Png)
+            .expect("encode source");
+        let widths = [Width(64), Width(128), Width(400), Width(800)];
+        let outputs = Job::new(png.get_ref())
+            .formats([OutputFormat::Png])
+            .sizes(widths.iter().map(Width::size))
+            .run()
+            .expect("run job");
+        let dimensions = outputs.iter().map(|x| x.dimensions).collect::<Vec<_>>();
+        assert_eq!(dimensions, [(64, 32), (128, 64), (200, 100), (200, 100)]);
+        let output_dir = std::env::temp_dir().join(format!("imager-srcset-{}", std::process::id()));
+        std::fs::create_dir_all(&output_dir).expect("output dir");
+        let manifest = write(Path::new("in/photo.jpeg"), &outputs, &output_dir).expect("write");
+        std::fs::remove_dir_all(&output_dir).expect("remove output dir");
+        // THE SOURCE ISN’T ENLARGED, SO 400W AND 800W ARE THE SAME VARIANT
+        assert_eq!(manifest.variants.len(), 3);
+        assert_eq!(
+            manifest.srcset["image/png"],
+            "photo-64w.png 64w, photo-128w.png 128w, photo-200w.png 200w"
+        );
+    }
+}
@@ -0,0 +1,257 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Text protection (`--text-protect`), for UI screenshots: text blocks are
+//! detected, and only there is the output corrected, instead of encoding
+//! everything without chroma subsampling (4:4:4).
+//!
+//! Text blocks (16x16, one JPEG 4:2:0 MCU) are high contrast, mostly two
+//! tone, with dense edges and thin strokes; photos fail at least one of
+//! these. In them,
+//!
+//! - the luma absorbs the chroma error: with 4:2:0, decoders smear chroma
+//!   across edges, so the luma is recomputed for the colors the decoder
+//!   will actually show (chroma from luma, like sharp YUV), and
+//! - the quality search doesn’t stop until they pass `TEXT_PSNR`, since
+//!   JPEG can’t raise the quality of a single block.
+//!
+//! WebP conversions already use sharp YUV over the whole image.
+use image::{DynamicImage, GrayImage, RgbImage};
+
+pub const BLOCK: u32 = 16;
+
+/// The luma PSNR text blocks need, in dB.
+pub const TEXT_PSNR: f64 = 32.0;
+
+// HEURISTICS
+const MIN_CONTRAST: u8 = 80;
+const EDGE_GRADIENT: i32 = 64;
+const EDGE_DENSITY: (f64, f64) = (0.08, 0.6);
+/// The share of pixels near either tone.
+const MIN_TWO_TONE: f64 = 0.7;
+/// The median length of ink runs, along rows.
+const MAX_STROKE_WIDTH: usize = 6;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextMask {
+    pub columns: u32,
+    pub rows: u32,
+    blocks: Vec<bool>,
+}
+
+impl TextMask {
+    pub fn detect(source: &DynamicImage) -> Self {
+        let luma = source.to_luma8();
+        let (width, height) = luma.dimensions();
+        let (columns, rows) = (width.div_ceil(BLOCK), height.div_ceil(BLOCK));
+        let blocks = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .map(|(column, row)| is_text(&luma, column * BLOCK, row * BLOCK))
+            .collect();
+        TextMask {
+            columns,
+            rows,
+            blocks,
+        }
+    }
+    /// Whether the pixel is in a text block.
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        let (column, row) = (x / BLOCK, y / BLOCK);
+        column < self.columns && row < self.rows && self.blocks[(row * self.columns + column) as usize]
+    }
+    /// The share of text blocks.
+    pub fn coverage(&self) -> f64 {
+        let text = self.blocks.iter().filter(|x| **x).count();
+        text as f64 / self.blocks.len().max(1) as f64
+    }
+    pub fn is_empty(&self) -> bool {
+        !self.blocks.contains(&true)
+    }
+}
+
+fn is_text(luma: &GrayImage, x0: u32, y0: u32) -> bool {
+    let (width, height) = luma.dimensions();
+    let (x1, y1) = ((x0 + BLOCK).min(width), (y0 + BLOCK).min(height));
+    let pixel = |x: u32, y: u32| luma.get_pixel(x, y).0[0];
+    let values = (y0..y1).flat_map(|y| (x0..x1).map(move |x| pixel(x, y))).collect::<Vec<_>>();
+    let (min, max) = (*values.iter().min().unwrap(), *values.iter().max().unwrap());
+    if max - min < MIN_CONTRAST {
+        return false;
+    }
+    let quarter = (max - min) / 4;
+    let two_tone = values.iter().filter(|x| **x <= min + quarter || **x >= max - quarter).count();
+    if (two_tone as f64) < MIN_TWO_TONE * values.len() as f64 {
+        return false;
+    }
+    let edges = (y0..y1)
+        .flat_map(|y| (x0..x1).map(move |x| (x, y)))
+        .filter(|(x, y)| {
+            let dx = i32::from(pixel((*x + 1).min(width - 1), *y)) - i32::from(pixel(*x, *y));
+            let dy = i32::from(pixel(*x, (*y + 1).min(height - 1))) - i32::from(pixel(*x, *y));
+            dx.abs() + dy.abs() >= EDGE_GRADIENT
+        })
+        .count();
+    let density = edges as f64 / values.len() as f64;
+    if density < EDGE_DENSITY.0 || density > EDGE_DENSITY.1 {
+        return false;
+    }
+    // INK IS THE MINORITY TONE
+    let middle = min / 2 + max / 2;
+    let dark = values.iter().filter(|x| **x < middle).count();
+    let is_ink = |x: u8| (x < middle) == (dark * 2 <= values.len());
+    let mut runs = Vec::new();
+    for row in values.chunks((x1 - x0) as usize) {
+        let mut run = 0;
+        for value in row {
+            if is_ink(*value) {
+                run += 1;
+            } else if run > 0 {
+                runs.push(run);
+                run = 0;
+            }
+        }
+        // RUNS CUT BY THE BLOCK’S EDGE ARE UNKNOWN
+    }
+    runs.sort_unstable();
+    runs.get(runs.len() / 2).is_some_and(|x| *x <= MAX_STROKE_WIDTH)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// CHROMA FROM LUMA
+///////////////////////////////////////////////////////////////////////////////
+
+/// JFIF full range YCbCr, interleaved, of every pixel (for the encoder to
+/// subsample); in text blocks, the luma is the least squares fit of the
+/// source RGB given the chroma decoders will show (box downsampled, then
+/// triangle upsampled as libjpeg’s “fancy” upsampling), and their clamping.
+pub fn ycbcr(source: &DynamicImage, mask: &TextMask) -> Vec<u8> {
+    let rgb = source.to_rgb8();
+    let (width, height) = rgb.dimensions();
+    let mut output = Vec::with_capacity(width as usize * height as usize * 3);
+    let mut chroma = vec![(0.0, 0.0); (width * height) as usize];
+    for (x, y, pixel) in rgb.enumerate_pixels() {
+        let [r, g, b] = pixel.0.map(f64::from);
+        let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+        let cb = -0.168_736 * r - 0.331_264 * g + 0.5 * b;
+        let cr = 0.5 * r - 0.418_688 * g - 0.081_312 * b;
+        chroma[(y * width + x) as usize] = (cb, cr);
+        output.extend_from_slice(&[to_u8(luma), to_u8(cb + 128.0), to_u8(cr + 128.0)]);
+    }
+    if mask.is_empty() {
+        return output;
+    }
+    // 2x2 AVERAGES
+    let (half_width, half_height) = (width.div_ceil(2), height.div_ceil(2));
+    let mut averages = vec![(0.0, 0.0); (half_width * half_height) as usize];
+    for y in 0..half_height {
+        for x in 0..half_width {
+            let (mut sum, mut count) = ((0.0, 0.0), 0.0);
+            for (sx, sy) in [(2 * x, 2 * y), (2 * x + 1, 2 * y), (2 * x, 2 * y + 1), (2 * x + 1, 2 * y + 1)] {
+                if sx < width && sy < height {
+                    let (cb, cr) = chroma[(sy * width + sx) as usize];
+                    sum = (sum.0 + cb, sum.1 + cr);
+                    count += 1.0;
+                }
+            }
+            averages[(y * half_width + x) as usize] = (sum.0 / count, sum.1 / count);
+        }
+    }
+    let average = |x: i64, y: i64| {
+        let x = x.clamp(0, i64::from(half_width) - 1) as u32;
+        let y = y.clamp(0, i64::from(half_height) - 1) as u32;
+        averages[(y * half_width + x) as usize]
+    };
+    for (x, y, pixel) in rgb.enumerate_pixels() {
+        if !mask.contains(x, y) {
+            continue;
+        }
+        // 9:3:3:1 OF THE NEAREST SAMPLE AND ITS NEIGHBORS TOWARD THE PIXEL
+        let (cx, cy) = (i64::from(x / 2), i64::from(y / 2));
+        let (nx, ny) = (cx + if x % 2 == 0 { -1 } else { 1 }, cy + if y % 2 == 0 { -1 } else { 1 });
+        let samples = [(average(cx, cy), 9.0), (average(nx, cy), 3.0), (average(cx, ny), 3.0), (average(nx, ny), 1.0)];
+        let (cb, cr) = samples.iter().fold((0.0, 0.0), |(cb, cr), ((x, y), weight)| {
+            (cb + x * weight / 16.0, cr + y * weight / 16.0)
+        });
+        let offsets = [1.402 * cr, -0.344_136 * cb - 0.714_136 * cr, 1.772 * cb];
+        output[(y * width + x) as usize * 3] = fit_luma(pixel.0.map(f64::from), offsets);
+    }
+    output
+}
+
+/// The luma minimizing the squared error of `clamp(luma + offset)` to the
+/// target channels. It’s piecewise quadratic, so the minimum is the least
+/// squares fit of some subset of (unclamped) channels, or a breakpoint.
+fn fit_luma(target: [f64; 3], offsets: [f64; 3]) -> u8 {
+    let error = |luma: f64| -> f64 {
+        (0..3)
+            .map(|x| (luma + offsets[x]).clamp(0.0, 255.0) - target[x])
+            .map(|x| x * x)
+            .sum()
+    };
+    let fits = (1..8u8).map(|subset| {
+        let channels = (0..3).filter(|x| subset & (1 << x) != 0);
+        let sum: f64 = channels.clone().map(|x| target[x] - offsets[x]).sum();
+        sum / f64::from(subset.count_ones())
+    });
+    let breakpoints = offsets.into_iter().flat_map(|x| [-x, 255.0 - x]);
+    let best = fits
+        .chain(breakpoints)
+        .map(|x| x.round().clamp(0.0, 255.0))
+        .min_by(|a, b| error(*a).total_cmp(&error(*b)))
+        .unwrap_or_default();
+    best as u8
+}
+
+fn to_u8(x: f64) -> u8 {
+    x.round().clamp(0.0, 255.0) as u8
+}
+
+/// The luma PSNR of the text blocks of `decoded`, to the `ycbcr` luma (i.e.
+/// of the quantization alone, which blurs strokes); none without any.
+pub fn text_psnr(ycbcr: &[u8], decoded: &RgbImage, mask: &TextMask) -> Option<f64> {
+    let (mut sum, mut count) = (0.0, 0usize);
+    for (x, y, pixel) in decoded.enumerate_pixels() {
+        if mask.contains(x, y) {
+            let [r, g, b] = pixel.0.map(f64::from);
+            let target = ycbcr[(y * decoded.width() + x) as usize * 3];
+            let difference = f64::from(target) - (0.299 * r + 0.587 * g + 0.114 * b);
+            sum += difference * difference;
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return None;
+    }
+    Some(10.0 * (255.0 * 255.0 / (sum / count as f64)).log10())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_text_protect() {
+        // RED 2 PIXEL STROKES ON WHITE, BESIDE A SMOOTH GRADIENT
+        let source = image::RgbImage::from_fn(64, 32, |x, y| match x {
+            0..=31 if x % 6 < 2 && y % 16 < 12 => image::Rgb([200, 0, 0]),
+            0..=31 => image::Rgb([255, 255, 255]),
+            _ => image::Rgb([(x * 4) as u8, (y * 4) as u8, 128]),
+        });
+        let source = DynamicImage::ImageRgb8(source);
+        let mask = TextMask::detect(&source);
+        assert_eq!((mask.columns, mask.rows), (4, 2));
+        assert!(mask.contains(0, 0) && mask.contains(20, 20));
+        assert!(!mask.contains(40, 0) && !mask.contains(63, 31));
+        assert_eq!(mask.coverage(), 0.5);
+        let protected = ycbcr(&source, &mask);
+        let plain = ycbcr(&source, &TextMask::detect(&DynamicImage::new_rgb8(64, 32)));
+        // THE GRADIENT IS UNTOUCHED; THE STROKES’ EDGES AREN’T
+        for (ix, (x, y)) in (0..32).flat_map(|y| (0..64).map(move |x| (x, y))).enumerate() {
+            let (protected, plain) = (&protected[ix * 3..ix * 3 + 3], &plain[ix * 3..ix * 3 + 3]);
+            assert!(x < 32 || protected == plain, "{} {}", x, y);
+        }
+        assert_ne!(protected[..64 * 3], plain[..64 * 3]);
+        let gray = RgbImage::from_fn(64, 32, |x, y| image::Rgb([protected[(y * 64 + x) as usize * 3]; 3]));
+        assert!(text_psnr(&protected, &gray, &mask).is_some_and(|x| x > 100.0));
+    }
+}
@@ -0,0 +1,169 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Downscaling to tiny (icon sized) outputs.
+//!
+//! At extreme ratios Lanczos either aliases or, with its support scaled
+//! to the ratio, blurs fine detail away. Here every source pixel instead
+//! contributes by the area it covers (with premultiplied alpha, so
+//! transparent pixels don’t bleed), followed by an unsharp mask that’s
+//! stronger for larger ratios and backs off where there’s already
+//! contrast, to restore edges without ringing.
+use image::{DynamicImage, GenericImageView, RgbaImage};
+
+/// Outputs whose larger side is below this use `downscale`.
+pub const TINY_THUMBNAIL: u32 = 128;
+
+pub fn is_tiny((width, height): (u32, u32)) -> bool {
+    width.max(height) < TINY_THUMBNAIL
+}
+
+pub fn downscale(source: &DynamicImage, (width, height): (u32, u32)) -> DynamicImage {
+    let ratio = source.width().max(source.height()) as f32 / width.max(height) as f32;
+    let averaged = area_average(&source.to_rgba8(), (width, height));
+    let sharpened = sharpen(&averaged, (0.3 + 0.1 * ratio.log2()).clamp(0.3, 0.8));
+    if source.color().has_alpha() {
+        DynamicImage::ImageRgba8(sharpened)
+    } else {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(sharpened).to_rgb8())
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// AREA AVERAGE
+///////////////////////////////////////////////////////////////////////////////
+
+/// `(source index, weight)` pairs of each output sample, from the
+/// overlap of its footprint with every source sample.
+fn coverage(source_len: u32, output_len: u32) -> Vec<Vec<(usize, f32)>> {
+    let scale = source_len as f32 / output_len as f32;
+    (0..output_len)
+        .map(|ix| {
+            let start = ix as f32 * scale;
+            let end = (start + scale).min(source_len as f32);
+            let mut weights = Vec::new();
+            let mut at = start.floor() as usize;
+            while (at as f32) < end {
+                let overlap = (end.min(at as f32 + 1.0) - start.max(at as f32)).max(0.0);
+                if overlap > 0.0 {
+                    weights.push((at, overlap / scale));
+                }
+                at += 1;
+            }
+            weights
+        })
+        .collect()
+}
+
+fn area_average(source: &RgbaImage, (width, height): (u32, u32)) -> RgbaImage {
+    let (source_width, source_height) = source.dimensions();
+    let columns = coverage(source_width, width);
+    let rows = coverage(source_height, height);
+    // HORIZONTAL PASS, PREMULTIPLIED
+    let mut horizontal = vec![[0.0f32; 4]; (width * source_height) as usize];
+    for y in 0..source_height {
+        for (x, weights) in columns.iter().enumerate() {
+            let sum = &mut horizontal[(y * width) as usize + x];
+            for (sx, weight) in weights {
+                let [r, g, b, a] = source.get_pixel(*sx as u32, y).0;
+                let alpha = a as f32 / 255.0;
+                sum[0] += weight * r as f32 * alpha;
+                sum[1] += weight * g as f32 * alpha;
+                sum[2] += weight * b as f32 * alpha;
+                sum[3] += weight * a as f32;
+            }
+        }
+    }
+    let mut output = RgbaImage::new(width, height);
+    for (y, weights) in rows.iter().enumerate() {
+        for x in 0..width {
+            let mut sum = [0.0f32; 4];
+            for (sy, weight) in weights {
+                let value = horizontal[*sy * width as usize + x as usize];
+                for channel in 0..4 {
+                    sum[channel] += weight * value[channel];
+                }
+            }
+            let alpha = sum[3] / 255.0;
+            let unpremultiply = |c: f32| if alpha > 0.0 { c / alpha } else { 0.0 };
+            let to_u8 = |c: f32| c.round().clamp(0.0, 255.0) as u8;
+            output.put_pixel(
+                x,
+                y as u32,
+                image::Rgba([
+                    to_u8(unpremultiply(sum[0])),
+                    to_u8(unpremultiply(sum[1])),
+                    to_u8(unpremultiply(sum[2])),
+                    to_u8(sum[3]),
+                ]),
+            );
+        }
+    }
+    output
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ADAPTIVE SHARPEN
+///////////////////////////////////////////////////////////////////////////////
+
+fn luma([r, g, b, _]: [u8; 4]) -> f32 {
+    0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32
+}
+
+/// Unsharp mask (3x3 binomial blur), with the gain reduced by the local
+/// luma range. Alpha is left untouched.
+fn sharpen(source: &RgbaImage, amount: f32) -> RgbaImage {
+    const KERNEL: [f32; 3] = [0.25, 0.5, 0.25];
+    let (width, height) = source.dimensions();
+    let at = |x: i64, y: i64| {
+        let x = x.clamp(0, width as i64 - 1) as u32;
+        let y = y.clamp(0, height as i64 - 1) as u32;
+        source.get_pixel(x, y).0
+    };
+    let mut output = source.clone();
+    for y in 0..height as i64 {
+        for x in 0..width as i64 {
+            let mut blurred = [0.0f32; 3];
+            let (mut min, mut max) = (f32::MAX, f32::MIN);
+            for (dy, ky) in KERNEL.iter().enumerate() {
+                for (dx, kx) in KERNEL.iter().enumerate() {
+                    let pixel = at(x + dx as i64 - 1, y + dy as i64 - 1);
+                    for (sum, value) in blurred.iter_mut().zip(pixel) {
+                        *sum += ky * kx * value as f32;
+                    }
+                    min = min.min(luma(pixel));
+                    max = max.max(luma(pixel));
+                }
+            }
+            let gain = amount / (1.0 + (max - min) / 64.0);
+            let pixel = output.get_pixel_mut(x as u32, y as u32);
+            for (value, blurred) in pixel.0.iter_mut().zip(blurred) {
+                let sharpened = *value as f32 + gain * (*value as f32 - blurred);
+                *value = sharpened.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_area_average() {
+        // A 2x2 GRID OF FOUR COLORS, ONE TRANSPARENT
+        let colors = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255], [9, 9, 9, 0]];
+        let source =
+            RgbaImage::from_fn(6, 6, |x, y| image::Rgba(colors[(x / 3 + 2 * (y / 3)) as usize]));
+        let output = area_average(&source, (2, 2));
+        assert_eq!(output.get_pixel(0, 0).0, colors[0]);
+        assert_eq!(output.get_pixel(1, 1).0, [0, 0, 0, 0]);
+        // EDGES WITH TRANSPARENT PIXELS KEEP THEIR COLOR
+        let output = area_average(&source, (3, 3));
+        assert_eq!(output.get_pixel(1, 2).0, [0, 0, 255, 128]);
+        assert_eq!(output.get_pixel(2, 1).0, [0, 255, 0, 128]);
+        let thumbnail = downscale(&DynamicImage::ImageRgba8(source), (4, 4));
+        assert_eq!(thumbnail.dimensions(), (4, 4));
+    }
+}
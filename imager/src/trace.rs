@@ -0,0 +1,169 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! W3C Trace Context (`traceparent`/`tracestate`) propagation, so the
+//! optimization shows up in distributed traces: incoming contexts are
+//! continued by imager’s spans, and passed on to webhooks and callbacks.
+//!
+//! Spans of sampled traces are logged to stderr when they end, as
+//! `[trace] trace_id=… span_id=… parent_id=… name=… duration_ms=…`.
+use rand::RngCore;
+use std::time::Instant;
+
+use imager_core::digest::hex;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    /// The ID of the current span.
+    pub span_id: [u8; 8],
+    pub flags: u8,
+    /// Vendor specific, passed on as is.
+    pub state: Option<String>,
+}
+
+impl TraceContext {
+    /// A new (unsampled) trace.
+    pub fn root() -> Self {
+        let mut trace_id = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut trace_id);
+        TraceContext {
+            trace_id,
+            span_id: new_span_id(),
+            flags: 0,
+            state: None,
+        }
+    }
+    /// Parses a `traceparent` header; invalid ones are ignored, per the
+    /// spec. Later versions are parsed as version `00`.
+    pub fn parse(traceparent: &str, tracestate: Option<&str>) -> Option<Self> {
+        let parts = traceparent.trim().split('-').collect::<Vec<_>>();
+        let (version, trace_id, span_id, flags) = match parts.as_slice() {
+            [version, trace_id, span_id, flags] => (*version, *trace_id, *span_id, *flags),
+            [version, trace_id, span_id, flags, ..] if *version != "00" => {
+                (*version, *trace_id, *span_id, *flags)
+            }
+            _ => return None,
+        };
+        if version.len() != 2 || version == "ff" || flags.len() != 2 {
+            return None;
+        }
+        let version = parse_hex::<1>(version)?;
+        let trace_id = parse_hex::<16>(trace_id)?;
+        let span_id = parse_hex::<8>(span_id)?;
+        let flags = parse_hex::<1>(flags)?[0];
+        if version[0] == 0xff || trace_id == [0; 16] || span_id == [0; 8] {
+            return None;
+        }
+        Some(TraceContext {
+            trace_id,
+            span_id,
+            flags,
+            state: tracestate.map(str::trim).filter(|x| !x.is_empty()).map(str::to_owned),
+        })
+    }
+    /// A context for a child span.
+    pub fn child(&self) -> Self {
+        TraceContext {
+            span_id: new_span_id(),
+            ..self.clone()
+        }
+    }
+    pub fn sampled(&self) -> bool {
+        self.flags & 0x01 != 0
+    }
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-{:02x}", hex(&self.trace_id), hex(&self.span_id), self.flags)
+    }
+    /// The `traceparent` (and `tracestate`) headers to send.
+    pub fn headers(&self) -> Vec<(String, String)> {
+        let mut headers = vec![(String::from("traceparent"), self.traceparent())];
+        if let Some(state) = self.state.as_ref() {
+            headers.push((String::from("tracestate"), state.clone()));
+        }
+        headers
+    }
+}
+
+fn new_span_id() -> [u8; 8] {
+    let mut span_id = [0u8; 8];
+    while span_id == [0; 8] {
+        rand::thread_rng().fill_bytes(&mut span_id);
+    }
+    span_id
+}
+
+fn parse_hex<const N: usize>(input: &str) -> Option<[u8; N]> {
+    // LOWERCASE ONLY
+    if input.len() != N * 2 || !input.bytes().all(|x| matches!(x, b'0'..=b'9' | b'a'..=b'f')) {
+        return None;
+    }
+    let mut output = [0u8; N];
+    for (ix, byte) in output.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&input[ix * 2..ix * 2 + 2], 16).ok()?;
+    }
+    Some(output)
+}
+
+/// A unit of work in a trace; logged when dropped.
+pub struct Span {
+    pub context: TraceContext,
+    parent_id: [u8; 8],
+    name: &'static str,
+    start: Instant,
+}
+
+impl Span {
+    pub fn start(name: &'static str, parent: &TraceContext) -> Self {
+        Span {
+            context: parent.child(),
+            parent_id: parent.span_id,
+            name,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if self.context.sampled() {
+            eprintln!(
+                "[trace] trace_id={} span_id={} parent_id={} name={} duration_ms={}",
+                hex(&self.context.trace_id),
+                hex(&self.context.span_id),
+                hex(&self.parent_id),
+                self.name,
+                self.start.elapsed().as_millis()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_traceparent() {
+        let header = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        let context = TraceContext::parse(header, Some("congo=t61rcWkgMzE")).expect("parse");
+        assert!(context.sampled());
+        assert_eq!(context.traceparent(), header);
+        let span = Span::start("optimize", &context);
+        assert_eq!(span.context.trace_id, context.trace_id);
+        assert_ne!(span.context.span_id, context.span_id);
+        assert_eq!(span.context.headers()[1].1, "congo=t61rcWkgMzE");
+        // INVALID: UPPERCASE, ZERO IDS, VERSION ff, EXTRA FIELDS IN VERSION 00
+        for header in [
+            "00-0AF7651916CD43DD8448EB211C80319C-B7AD6B7169203331-01",
+            "00-00000000000000000000000000000000-b7ad6b7169203331-01",
+            "00-0af7651916cd43dd8448eb211c80319c-0000000000000000-01",
+            "ff-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-xx",
+        ] {
+            assert!(TraceContext::parse(header, None).is_none(), "{}", header);
+        }
+        let future = "01-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00-extra";
+        assert!(TraceContext::parse(future, None).is_some());
+    }
+}
@@ -0,0 +1,161 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Profile tuning (`imager tune`): runs the quality searches on a sample of
+//! a site’s images, and narrows the profile’s quality ranges (see
+//! `Tuning`) to the qualities the sample needed, from the 10th to the 90th
+//! percentile. Outliers then encode at the bounds, instead of the search
+//! drifting to extremes, and the searches start closer to their result.
+//!
+//! The JPEG searches run at both chroma subsamplings, and the one with the
+//! smaller outputs (at the qualities each needed) wins. WebP outputs are
+//! encoded at the searched quality at every effort, and the lowest effort
+//! within `EFFORT_TOLERANCE` of the highest’s total size wins.
+use image::DynamicImage;
+
+use crate::codec::registry::FeatureDisabled;
+use crate::data::{OutputFormat, QualityRange, Subsampling, Tuning, MAX_WEBP_EFFORT};
+
+pub const LOW_PERCENTILE: usize = 10;
+pub const HIGH_PERCENTILE: usize = 90;
+
+/// How much larger (in total) the outputs of a lower WebP effort may be.
+pub const EFFORT_TOLERANCE: f64 = 0.01;
+
+pub const SUBSAMPLINGS: [Subsampling; 2] = [Subsampling::Yuv420, Subsampling::Yuv444];
+
+/// The quality a search picked, and the size of its output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Searched {
+    pub quality: u8,
+    pub size: usize,
+}
+
+/// The searches of each format, for JPEG (of each of the `SUBSAMPLINGS`),
+/// WebP (and the output sizes at its quality, of each effort) and AVIF.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sample {
+    pub jpeg: Option<[Searched; 2]>,
+    pub webp: Option<(u8, [usize; MAX_WEBP_EFFORT as usize + 1])>,
+    pub avif: Option<u8>,
+}
+
+/// Runs the default (untuned) searches.
+pub fn search(source: &DynamicImage, formats: &[OutputFormat]) -> Result<Sample, String> {
+    if cfg!(feature = "pure-rust") {
+        return Err(FeatureDisabled::VMAF.into());
+    }
+    let mut sample = Sample::default();
+    for format in formats {
+        match format {
+            OutputFormat::Jpeg => {
+                let search = |subsampling: Subsampling| {
+                    let mut context = crate::codec::jpeg::OptContext::from_image(source.clone());
+                    context.subsampling(subsampling);
+                    let (output, report) = context.run_search(false);
+                    Searched {
+                        quality: report.end_q,
+                        size: output.len(),
+                    }
+                };
+                sample.jpeg = Some(SUBSAMPLINGS.map(search));
+            }
+            #[cfg(not(feature = "pure-rust"))]
+            OutputFormat::Webp => {
+                use crate::codec::webp::encode::lossy::encode_yuva;
+                let (_, meta) = crate::codec::webp::opt::opt(source, Tuning::default().webp);
+                let quality = meta.end_q.min(100) as u8;
+                let picture = crate::data::Yuva420P::from_image(source).map_err(|e| e.to_string())?;
+                let sizes =
+                    std::array::from_fn(|effort| encode_yuva(&picture, f32::from(quality), effort as u8).len());
+                sample.webp = Some((quality, sizes));
+            }
+            OutputFormat::Avif => {
+                let (_, meta) = crate::codec::avif::opt(source, Tuning::default().avif, None, false, None)?;
+                sample.avif = Some(meta.end_q);
+            }
+            _ => (),
+        }
+    }
+    Ok(sample)
+}
+
+/// The tuning of the sampled searches; formats without samples keep the
+/// defaults.
+pub fn tune(samples: &[Sample]) -> Tuning {
+    let default = Tuning::default();
+    let jpeg = samples.iter().filter_map(|x| x.jpeg).collect::<Vec<_>>();
+    let webp = samples.iter().filter_map(|x| x.webp).collect::<Vec<_>>();
+    let avif = samples.iter().filter_map(|x| x.avif).collect::<Vec<_>>();
+    // THE SUBSAMPLING WITH THE SMALLER OUTPUTS
+    let subsampling = (0..SUBSAMPLINGS.len())
+        .min_by_key(|&index| jpeg.iter().map(|x| x[index].size).sum::<usize>())
+        .filter(|_| !jpeg.is_empty());
+    // THE LOWEST EFFORT WITHIN THE TOLERANCE OF THE HIGHEST
+    let total = |effort: usize| webp.iter().map(|(_, sizes)| sizes[effort]).sum::<usize>();
+    let webp_effort = (0..=MAX_WEBP_EFFORT)
+        .find(|&effort| {
+            total(effort as usize) as f64 <= total(MAX_WEBP_EFFORT as usize) as f64 * (1.0 + EFFORT_TOLERANCE)
+        })
+        .filter(|_| !webp.is_empty());
+    Tuning {
+        jpeg: subsampling
+            .and_then(|index| range(jpeg.iter().map(|x| x[index].quality).collect()))
+            .unwrap_or(default.jpeg),
+        jpeg_subsampling: subsampling.map_or(default.jpeg_subsampling, |index| SUBSAMPLINGS[index]),
+        webp: range(webp.iter().map(|(quality, _)| *quality).collect()).unwrap_or(default.webp),
+        webp_effort: webp_effort.unwrap_or(default.webp_effort),
+        avif: range(avif).unwrap_or(default.avif),
+        ..default
+    }
+}
+
+fn range(mut qualities: Vec<u8>) -> Option<QualityRange> {
+    qualities.sort_unstable();
+    let last = qualities.len().checked_sub(1)?;
+    Some(QualityRange {
+        min: qualities[last * LOW_PERCENTILE / 100],
+        max: qualities[(last * HIGH_PERCENTILE).div_ceil(100)],
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tune() {
+        // 4:4:4 NEEDS LOWER QUALITIES, BUT ITS OUTPUTS ARE LARGER
+        let samples = (1..=20)
+            .map(|x| Sample {
+                jpeg: Some([
+                    Searched { quality: x * 5, size: 1000 },
+                    Searched { quality: x * 4, size: 1100 },
+                ]),
+                webp: None,
+                avif: None,
+            })
+            .collect::<Vec<_>>();
+        let tuning = tune(&samples);
+        assert_eq!(tuning.jpeg, QualityRange { min: 10, max: 95 });
+        assert_eq!(tuning.jpeg_subsampling, Subsampling::Yuv420);
+        assert_eq!((tuning.webp, tuning.webp_effort), (Tuning::default().webp, MAX_WEBP_EFFORT));
+        let smaller = |mut sample: Sample| {
+            sample.jpeg.as_mut().expect("jpeg")[1].size = 900;
+            sample
+        };
+        let tuning = tune(&samples.into_iter().map(smaller).collect::<Vec<_>>());
+        assert_eq!(tuning.jpeg, QualityRange { min: 8, max: 76 });
+        assert_eq!(tuning.jpeg_subsampling, Subsampling::Yuv444);
+        // EFFORT 4 IS WITHIN 1% OF 6
+        let webp = |quality| Sample {
+            webp: Some((quality, [2000, 1500, 1200, 1100, 1008, 1002, 1000])),
+            ..Sample::default()
+        };
+        let tuning = tune(&[webp(70), webp(80)]);
+        assert_eq!((tuning.webp, tuning.webp_effort), (QualityRange { min: 70, max: 80 }, 4));
+        assert_eq!(tuning.jpeg_subsampling, Subsampling::Yuv420);
+        assert_eq!(range(vec![70]), Some(QualityRange { min: 70, max: 70 }));
+        assert_eq!(range(Vec::new()), None);
+    }
+}
@@ -0,0 +1,107 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Explicit (opt-in) upscaling, e.g. for print sized assets from small
+//! sources. Enlargement is capped at `MAX_UPSCALE` per side, since beyond
+//! that no algorithm recovers detail that isn’t there.
+use image::{DynamicImage, GenericImageView};
+
+pub use imager_core::data::{Upscaler, MAX_UPSCALE};
+
+use crate::data::Resolution;
+
+/// The aspect ratio preserving fit of `source` within `target`, if that’s
+/// larger than the source (capped at `MAX_UPSCALE`).
+pub fn upscale_dimensions((width, height): (u32, u32), target: &Resolution) -> Option<(u32, u32)> {
+    let ratio = f64::min(
+        target.width as f64 / width as f64,
+        target.height as f64 / height as f64,
+    );
+    if ratio <= 1.0 {
+        return None;
+    }
+    let ratio = ratio.min(MAX_UPSCALE as f64);
+    let width = (width as f64 * ratio).round() as u32;
+    let height = (height as f64 * ratio).round() as u32;
+    Some((width, height))
+}
+
+/// Fails for upscalers this build can’t run.
+pub fn check_available(upscaler: Upscaler) -> Result<(), String> {
+    Ok(crate::codec::registry::upscaler(upscaler)?)
+}
+
+pub fn upscale(
+    source: &DynamicImage,
+    (width, height): (u32, u32),
+    upscaler: Upscaler,
+) -> Result<DynamicImage, String> {
+    check_available(upscaler)?;
+    let filter = image::imageops::FilterType::Lanczos3;
+    match upscaler {
+        Upscaler::Lanczos => Ok(source.resize_exact(width, height, filter)),
+        // ESRGAN ONLY RUNS AT FIXED SCALES; DOWNSCALE THE REST OF THE WAY
+        Upscaler::Esrgan => {
+            let enlarged = esrgan::upscale(source)?;
+            if enlarged.dimensions() == (width, height) {
+                return Ok(enlarged);
+            }
+            Ok(enlarged.resize_exact(width, height, filter))
+        }
+    }
+}
+
+#[cfg(feature = "esrgan")]
+mod esrgan {
+    use image::DynamicImage;
+    use std::process::Command;
+
+    use crate::workspace::JobDir;
+
+    /// Upscales 4x via a `realesrgan-ncnn-vulkan` subprocess.
+    pub fn upscale(source: &DynamicImage) -> Result<DynamicImage, String> {
+        let dir = JobDir::new("esrgan")?;
+        upscale_in(dir.path(), source)
+    }
+
+    fn upscale_in(dir: &std::path::Path, source: &DynamicImage) -> Result<DynamicImage, String> {
+        let input_path = dir.join("input.png");
+        let output_path = dir.join("output.png");
+        source.save(&input_path).map_err(|e| e.to_string())?;
+        let result = Command::new("realesrgan-ncnn-vulkan")
+            .arg("-i")
+            .arg(&input_path)
+            .arg("-o")
+            .arg(&output_path)
+            .arg("-s")
+            .arg(super::MAX_UPSCALE.to_string())
+            .output()
+            .map_err(|e| format!("failed to run realesrgan-ncnn-vulkan: {}", e))?;
+        if !result.status.success() {
+            return Err(String::from_utf8_lossy(&result.stderr).trim().to_string());
+        }
+        image::open(&output_path).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(not(feature = "esrgan"))]
+mod esrgan {
+    use image::DynamicImage;
+
+    pub fn upscale(_: &DynamicImage) -> Result<DynamicImage, String> {
+        unreachable!("checked by `check_available`")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_upscale_dimensions() {
+        let target = Resolution::new(2000, 2000);
+        assert_eq!(upscale_dimensions((400, 300), &target), Some((1600, 1200)));
+        assert_eq!(upscale_dimensions((1000, 500), &target), Some((2000, 1000)));
+        assert_eq!(upscale_dimensions((3000, 500), &target), None);
+    }
+}
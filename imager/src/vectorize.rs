@@ -0,0 +1,415 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Vectorization (`imager vectorize`, with the `vectorize` feature) of
+//! bilevel and flat colored rasters, e.g. logos that arrive as PNGs, into
+//! compact SVGs, potrace style:
+//!
+//! 1. the pixels are reduced to a few colors (exactly, if there are few
+//!    enough), and specks are merged into their surroundings,
+//! 2. each color’s region is traced along the pixel edges, into closed
+//!    loops (holes run the other way, so the nonzero fill rule cuts them
+//!    out),
+//! 3. the loops are simplified (Douglas-Peucker), and gentle corners are
+//!    rounded into quadratic curves.
+//!
+//! The most common color becomes the background (unless there’s
+//! transparency), so most colors’ seams are hidden under it. Photos don’t
+//! vectorize well; `Vector::error` tells.
+use image::{DynamicImage, GenericImageView};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::codec::png::{self, ImageMode};
+
+/// Alpha below which pixels are transparent.
+const ALPHA_THRESHOLD: u8 = 128;
+/// Turns sharper than this (in degrees) stay corners.
+const CORNER_ANGLE: f64 = 60.0;
+const TRANSPARENT: u16 = u16::MAX;
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// At most, excluding transparency.
+    pub colors: usize,
+    /// How far (in pixels) simplified outlines may stray from the traced
+    /// ones.
+    pub tolerance: f64,
+    /// Regions of fewer pixels are merged into their surroundings.
+    pub min_area: usize,
+    /// Round gentle corners into curves, rather than polygons only.
+    pub curves: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            colors: 8,
+            tolerance: 1.0,
+            min_area: 4,
+            curves: true,
+        }
+    }
+}
+
+pub struct Vector {
+    pub svg: String,
+    /// Including the background, excluding transparency.
+    pub colors: usize,
+    /// The mean absolute difference (per RGB channel, of opaque pixels) of
+    /// the source and its reduced colors; above a few, the source isn’t
+    /// flat colored.
+    pub error: f64,
+}
+
+pub fn vectorize(source: &DynamicImage, options: &Options) -> Result<Vector, String> {
+    let (width, height) = source.dimensions();
+    if width == 0 || height == 0 {
+        return Err(String::from("the image is empty"));
+    }
+    if options.colors == 0 || options.colors > 256 {
+        return Err(format!("{} colors; 1 to 256 are supported", options.colors));
+    }
+    let rgba = source.to_rgba8();
+    let (palette, mut labels) = reduce(source, options.colors);
+    let opaque = rgba.pixels().filter(|x| x.0[3] >= ALPHA_THRESHOLD).count().max(1);
+    let error = rgba
+        .pixels()
+        .zip(&labels)
+        .filter(|(_, label)| **label != TRANSPARENT)
+        .map(|(pixel, label)| {
+            let color = palette[*label as usize];
+            (0..3).map(|c| f64::from(pixel.0[c].abs_diff(color[c]))).sum::<f64>() / 3.0
+        })
+        .sum::<f64>()
+        / opaque as f64;
+    merge_specks(&mut labels, (width, height), options.min_area);
+    // BY AREA, SO THE BACKGROUND IS FIRST
+    let mut areas = vec![0usize; palette.len()];
+    for label in labels.iter().filter(|x| **x != TRANSPARENT) {
+        areas[*label as usize] += 1;
+    }
+    let mut order = (0..palette.len()).filter(|x| areas[*x] > 0).collect::<Vec<_>>();
+    order.sort_by_key(|x| std::cmp::Reverse(areas[*x]));
+    let background = order.first().copied().filter(|_| !labels.contains(&TRANSPARENT));
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">",
+        width, height
+    );
+    if let Some(background) = background {
+        svg.push_str(&format!(
+            "<rect width=\"{}\" height=\"{}\"{}/>",
+            width,
+            height,
+            fill(palette[background])
+        ));
+    }
+    for label in order.iter().filter(|x| Some(**x) != background) {
+        let mask = labels.iter().map(|x| *x as usize == *label).collect::<Vec<_>>();
+        let mut data = String::new();
+        for outline in trace(&mask, (width, height)) {
+            let outline = simplify(&corners(&outline), options.tolerance);
+            data.push_str(&path_data(&outline, options.curves));
+        }
+        svg.push_str(&format!("<path{} d=\"{}\"/>", fill(palette[*label]), data));
+    }
+    svg.push_str("</svg>");
+    Ok(Vector {
+        svg,
+        colors: order.len(),
+        error,
+    })
+}
+
+/// The palette, and every pixel’s index into it (or `TRANSPARENT`).
+fn reduce(source: &DynamicImage, colors: usize) -> (Vec<[u8; 4]>, Vec<u16>) {
+    let rgba = source.to_rgba8();
+    let mut exact = BTreeMap::new();
+    for pixel in rgba.pixels().filter(|x| x.0[3] >= ALPHA_THRESHOLD) {
+        let next = exact.len();
+        exact.entry(pixel.0).or_insert(next);
+        if exact.len() > colors {
+            break;
+        }
+    }
+    if exact.len() <= colors {
+        let labels = rgba
+            .pixels()
+            .map(|x| match exact.get(&x.0) {
+                Some(ix) if x.0[3] >= ALPHA_THRESHOLD => *ix as u16,
+                _ => TRANSPARENT,
+            })
+            .collect();
+        let mut palette = vec![[0; 4]; exact.len()];
+        for (color, ix) in exact {
+            palette[ix] = color;
+        }
+        return (palette, labels);
+    }
+    // THE QUANTIZER SPLITS ONE COLOR INTO `steps + 1`; ONE MORE FOR
+    // TRANSPARENCY
+    let transparent = rgba.pixels().any(|x| x.0[3] < ALPHA_THRESHOLD);
+    let steps = (colors + usize::from(transparent) - 1).min(255);
+    let (palette, indices) = png::quantize(source, ImageMode::Text, steps);
+    let palette = palette.iter().map(|x| [x.r, x.g, x.b, x.a]).collect::<Vec<_>>();
+    let labels = indices
+        .iter()
+        .map(|ix| match palette[*ix as usize][3] {
+            alpha if alpha >= ALPHA_THRESHOLD => u16::from(*ix),
+            _ => TRANSPARENT,
+        })
+        .collect();
+    (palette, labels)
+}
+
+/// Relabels regions (4-connected) smaller than `min_area` as the label
+/// most common around them.
+fn merge_specks(labels: &mut [u16], (width, height): (u32, u32), min_area: usize) {
+    let (width, height) = (width as usize, height as usize);
+    let mut seen = vec![false; labels.len()];
+    let neighbors = |ix: usize| {
+        let (x, y) = (ix % width, ix / width);
+        [
+            (x > 0).then(|| ix - 1),
+            (x + 1 < width).then(|| ix + 1),
+            (y > 0).then(|| ix - width),
+            (y + 1 < height).then(|| ix + width),
+        ]
+        .into_iter()
+        .flatten()
+    };
+    for start in 0..labels.len() {
+        if seen[start] {
+            continue;
+        }
+        let label = labels[start];
+        let mut region = vec![start];
+        let mut around = HashMap::<u16, usize>::new();
+        seen[start] = true;
+        let mut next = 0;
+        while next < region.len() {
+            for neighbor in neighbors(region[next]) {
+                if labels[neighbor] != label {
+                    *around.entry(labels[neighbor]).or_default() += 1;
+                } else if !seen[neighbor] {
+                    seen[neighbor] = true;
+                    region.push(neighbor);
+                }
+            }
+            next += 1;
+        }
+        let surrounding = around.into_iter().max_by_key(|(label, count)| (*count, *label));
+        if let Some((surrounding, _)) = surrounding.filter(|_| region.len() < min_area) {
+            for ix in region {
+                labels[ix] = surrounding;
+            }
+        }
+    }
+}
+
+type Point = (i64, i64);
+
+/// The closed outlines of the mask, as pixel corners, clockwise (in image
+/// coordinates) around the mask; so holes are counterclockwise.
+fn trace(mask: &[bool], (width, height): (u32, u32)) -> Vec<Vec<Point>> {
+    let (width, height) = (i64::from(width), i64::from(height));
+    let inside = |x: i64, y: i64| x >= 0 && y >= 0 && x < width && y < height && mask[(y * width + x) as usize];
+    // EACH EDGE WITH THE MASK ON ITS RIGHT
+    let mut outgoing = HashMap::<Point, Vec<Point>>::new();
+    for y in 0..height {
+        for x in (0..width).filter(|x| inside(*x, y)) {
+            let mut edge = |from: Point, to: Point| outgoing.entry(from).or_default().push(to);
+            if !inside(x, y - 1) {
+                edge((x, y), (x + 1, y));
+            }
+            if !inside(x + 1, y) {
+                edge((x + 1, y), (x + 1, y + 1));
+            }
+            if !inside(x, y + 1) {
+                edge((x + 1, y + 1), (x, y + 1));
+            }
+            if !inside(x - 1, y) {
+                edge((x, y + 1), (x, y));
+            }
+        }
+    }
+    let mut starts = outgoing.keys().copied().collect::<Vec<_>>();
+    starts.sort_unstable_by_key(|(x, y)| (*y, *x));
+    let mut outlines = Vec::new();
+    for start in starts {
+        while let Some(mut to) = outgoing.get_mut(&start).and_then(|x| x.pop()) {
+            let mut outline = vec![start];
+            let mut from = start;
+            while to != start {
+                outline.push(to);
+                // WHERE TWO PIXELS TOUCH DIAGONALLY, TURN RIGHT, SO THEY’RE
+                // OUTLINED SEPARATELY
+                let direction = (to.0 - from.0, to.1 - from.1);
+                let right = (to.0 - direction.1, to.1 + direction.0);
+                let candidates = outgoing.get_mut(&to).expect("closed outlines");
+                let ix = candidates.iter().position(|x| *x == right).unwrap_or(0);
+                let next = candidates.swap_remove(ix);
+                from = to;
+                to = next;
+            }
+            outlines.push(outline);
+        }
+    }
+    outlines
+}
+
+/// The points where the outline turns.
+fn corners(outline: &[Point]) -> Vec<Point> {
+    let n = outline.len();
+    (0..n)
+        .filter(|ix| {
+            let (previous, point, next) = (outline[(ix + n - 1) % n], outline[*ix], outline[(ix + 1) % n]);
+            (point.0 - previous.0, point.1 - previous.1) != (next.0 - point.0, next.1 - point.1)
+        })
+        .map(|ix| outline[ix])
+        .collect()
+}
+
+/// Douglas-Peucker, of the closed outline split at its first point and the
+/// point farthest from it.
+fn simplify(outline: &[Point], tolerance: f64) -> Vec<Point> {
+    if outline.len() <= 4 || tolerance <= 0.0 {
+        return outline.to_vec();
+    }
+    let distance = |a: Point, b: Point| (((a.0 - b.0).pow(2) + (a.1 - b.1).pow(2)) as f64).sqrt();
+    let far = (1..outline.len())
+        .max_by(|a, b| distance(outline[0], outline[*a]).total_cmp(&distance(outline[0], outline[*b])))
+        .expect("points");
+    let mut closed = outline.to_vec();
+    closed.push(outline[0]);
+    let mut keep = vec![false; closed.len()];
+    keep[0] = true;
+    keep[far] = true;
+    douglas_peucker(&closed[..=far], tolerance, &mut keep[..=far]);
+    douglas_peucker(&closed[far..], tolerance, &mut keep[far..]);
+    let simplified = outline
+        .iter()
+        .zip(&keep)
+        .filter(|(_, keep)| **keep)
+        .map(|(point, _)| *point)
+        .collect::<Vec<_>>();
+    // TOO FEW POINTS WOULD COLLAPSE THE SHAPE
+    if simplified.len() < 3 {
+        outline.to_vec()
+    } else {
+        simplified
+    }
+}
+
+fn douglas_peucker(points: &[Point], tolerance: f64, keep: &mut [bool]) {
+    let (first, last) = (points[0], points[points.len() - 1]);
+    let (dx, dy) = ((last.0 - first.0) as f64, (last.1 - first.1) as f64);
+    let length = (dx * dx + dy * dy).sqrt();
+    let offset = |point: &Point| {
+        let (px, py) = ((point.0 - first.0) as f64, (point.1 - first.1) as f64);
+        if length == 0.0 {
+            (px * px + py * py).sqrt()
+        } else {
+            (px * dy - py * dx).abs() / length
+        }
+    };
+    let farthest = (1..points.len().saturating_sub(1))
+        .map(|ix| (ix, offset(&points[ix])))
+        .max_by(|a, b| a.1.total_cmp(&b.1));
+    if let Some((ix, _)) = farthest.filter(|(_, offset)| *offset > tolerance) {
+        keep[ix] = true;
+        douglas_peucker(&points[..=ix], tolerance, &mut keep[..=ix]);
+        douglas_peucker(&points[ix..], tolerance, &mut keep[ix..]);
+    }
+}
+
+/// SVG path commands of the closed outline: with `curves`, from the middle
+/// of each side to the next, through gentle corners as control points.
+fn path_data(outline: &[Point], curves: bool) -> String {
+    let n = outline.len();
+    if !curves {
+        let points = outline.iter().map(|(x, y)| format!("{} {}", x, y)).collect::<Vec<_>>();
+        return format!("M{}Z", points.join("L"));
+    }
+    let middle = |a: Point, b: Point| {
+        let half = |x: i64| if x % 2 == 0 { format!("{}", x / 2) } else { format!("{}.5", x.div_euclid(2)) };
+        format!("{} {}", half(a.0 + b.0), half(a.1 + b.1))
+    };
+    let corner = |ix: usize| {
+        let (previous, point, next) = (outline[(ix + n - 1) % n], outline[ix % n], outline[(ix + 1) % n]);
+        let (a, b) = ((point.0 - previous.0, point.1 - previous.1), (next.0 - point.0, next.1 - point.1));
+        let lengths = (((a.0 * a.0 + a.1 * a.1) * (b.0 * b.0 + b.1 * b.1)) as f64).sqrt();
+        ((a.0 * b.0 + a.1 * b.1) as f64) < CORNER_ANGLE.to_radians().cos() * lengths
+    };
+    let mut data = format!("M{}", middle(outline[n - 1], outline[0]));
+    for ix in 0..n {
+        let (point, next) = (outline[ix], outline[(ix + 1) % n]);
+        if !corner(ix) {
+            data.push_str(&format!("Q{} {} {}", point.0, point.1, middle(point, next)));
+            continue;
+        }
+        data.push_str(&format!("L{} {}", point.0, point.1));
+        // ON TO THE NEXT SIDE’S MIDDLE ONLY IF A CURVE STARTS THERE
+        if !corner(ix + 1) {
+            data.push_str(&format!("L{}", middle(point, next)));
+        }
+    }
+    data.push('Z');
+    data
+}
+
+/// The `fill` (and `fill-opacity`) attributes, in the shortest hex form.
+fn fill([r, g, b, a]: [u8; 4]) -> String {
+    let short = [r, g, b].iter().all(|x| x % 17 == 0);
+    let color = if short {
+        format!("#{:x}{:x}{:x}", r / 17, g / 17, b / 17)
+    } else {
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    };
+    match a {
+        255 => format!(" fill=\"{}\"", color),
+        _ => format!(" fill=\"{}\" fill-opacity=\"{:.2}\"", color, f64::from(a) / 255.0),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn test_vectorize() {
+        // A SQUARE WITH A HOLE, A DISC, AND A SPECK, ON WHITE
+        let (white, black, red) = (Rgba([255, 255, 255, 255]), Rgba([0, 0, 0, 255]), Rgba([200, 16, 46, 255]));
+        let source = RgbaImage::from_fn(64, 48, |x, y| {
+            let (dx, dy) = (x as i32 - 44, y as i32 - 24);
+            if (4..24).contains(&x) && (4..24).contains(&y) && !((10..16).contains(&x) && (10..16).contains(&y)) {
+                black
+            } else if dx * dx + dy * dy < 100 || (x, y) == (2, 40) {
+                red
+            } else {
+                white
+            }
+        });
+        let mask = source.pixels().map(|x| *x == black).collect::<Vec<_>>();
+        let outlines = trace(&mask, source.dimensions());
+        assert_eq!(outlines.len(), 2);
+        // CLOCKWISE OUTLINES, LESS COUNTERCLOCKWISE HOLES, IS THE AREA
+        let area = |outline: &Vec<Point>| {
+            let n = outline.len();
+            (0..n)
+                .map(|ix| outline[ix].0 * outline[(ix + 1) % n].1 - outline[(ix + 1) % n].0 * outline[ix].1)
+                .sum::<i64>()
+                / 2
+        };
+        assert_eq!(outlines.iter().map(area).sum::<i64>(), 20 * 20 - 6 * 6);
+        assert!(outlines.iter().all(|x| corners(x).len() == 4));
+        let vector = vectorize(&DynamicImage::ImageRgba8(source), &Options::default()).expect("vectorize");
+        assert_eq!((vector.colors, vector.error), (3, 0.0));
+        assert!(vector.svg.starts_with("<svg") && vector.svg.contains("<rect width=\"64\" height=\"48\" fill=\"#fff\"/>"));
+        assert!(vector.svg.contains("fill=\"#000\" d=\"M4 14L4 4L24 4L24 24L4 24Z"), "{}", vector.svg);
+        // THE SPECK IS MERGED, SO THE RED PATH IS THE DISC ONLY
+        let red = vector.svg.split("fill=\"#c8102e\"").nth(1).expect("red path");
+        assert_eq!(red.matches('M').count(), 1);
+        assert!(red.contains('Q'));
+    }
+}
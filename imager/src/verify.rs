@@ -0,0 +1,150 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Corruption checks (`imager verify`), e.g. for archives: the container
+//! structure (truncation, PNG chunk CRCs, RIFF sizes, end markers), then a
+//! full, strict decode (unlike `decode::decode`, which may tolerate
+//! truncated JPEGs).
+use image::ImageFormat;
+use serde::Serialize;
+
+use crate::meta::container::{png_chunks, webp_chunks};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Verification {
+    /// As detected, e.g. `Png`; none for unknown formats.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    /// Empty for valid files.
+    pub problems: Vec<String>,
+}
+
+impl Verification {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+pub fn verify(source: &[u8]) -> Verification {
+    let format = match ::image::guess_format(source) {
+        Ok(format) => format,
+        Err(_) => {
+            return Verification {
+                format: None,
+                problems: vec![String::from("unknown format")],
+            }
+        }
+    };
+    let mut problems = match format {
+        ImageFormat::Png => check_png(source),
+        ImageFormat::Jpeg => check_jpeg(source),
+        ImageFormat::WebP => check_webp(source),
+        ImageFormat::Gif if source.last() != Some(&0x3B) => vec![String::from("no trailer (truncated)")],
+        _ => Vec::new(),
+    };
+    // DECODERS MAY PANIC ON MALFORMED STREAMS
+    let decoded = std::panic::catch_unwind(|| ::image::load_from_memory_with_format(source, format));
+    match decoded {
+        Ok(Ok(_)) => (),
+        Ok(Err(error)) => problems.push(format!("failed to decode: {}", error)),
+        Err(_) => problems.push(String::from("failed to decode: decoder panicked")),
+    }
+    Verification {
+        format: Some(format!("{:?}", format)),
+        problems,
+    }
+}
+
+fn check_png(source: &[u8]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut end = 8;
+    let mut last = None;
+    for (ix, (kind, data, start)) in png_chunks(source).enumerate() {
+        let name = String::from_utf8_lossy(&kind).into_owned();
+        if ix == 0 && &kind != b"IHDR" {
+            problems.push(format!("first chunk is {}, not IHDR", name));
+        }
+        end = start + 12 + data.len();
+        let stored = match source.get(end - 4..end) {
+            Some(x) => u32::from_be_bytes(x.try_into().expect("4 bytes")),
+            None => {
+                problems.push(format!("{} chunk at {} truncated", name, start));
+                return problems;
+            }
+        };
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(&kind);
+        crc.update(data);
+        if crc.finalize() != stored {
+            problems.push(format!("{} chunk at {}: CRC mismatch", name, start));
+        }
+        last = Some(kind);
+        if &kind == b"IEND" {
+            break;
+        }
+    }
+    if last.as_ref() != Some(b"IEND") && end < source.len() {
+        problems.push(format!("no IEND chunk (the chunk at {} is truncated)", end));
+    } else if last.as_ref() != Some(b"IEND") {
+        problems.push(String::from("no IEND chunk (truncated)"));
+    } else if end < source.len() {
+        problems.push(format!("{} bytes of trailing data after IEND", source.len() - end));
+    }
+    problems
+}
+
+fn check_jpeg(source: &[u8]) -> Vec<String> {
+    // SOME WRITERS PAD PAST THE END OF IMAGE MARKER
+    let trimmed = source.iter().rposition(|x| *x != 0).map_or(&[][..], |x| &source[..=x]);
+    if trimmed.ends_with(&[0xFF, 0xD9]) {
+        Vec::new()
+    } else {
+        vec![String::from("no end of image marker (truncated)")]
+    }
+}
+
+fn check_webp(source: &[u8]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let riff_len = match source.get(4..8) {
+        Some(x) => u32::from_le_bytes(x.try_into().expect("4 bytes")) as usize + 8,
+        None => return vec![String::from("no RIFF header (truncated)")],
+    };
+    if riff_len > source.len() {
+        problems.push(format!("RIFF size {} exceeds the file ({} bytes; truncated)", riff_len, source.len()));
+    } else if riff_len < source.len() {
+        problems.push(format!("{} bytes of trailing data after the RIFF", source.len() - riff_len));
+    }
+    let body = &source[..riff_len.min(source.len())];
+    let covered = webp_chunks(body).fold(12, |at, (_, payload)| at + 8 + payload.len() + payload.len() % 2);
+    if covered < body.len() {
+        problems.push(format!("chunk at {} exceeds the RIFF (truncated)", covered));
+    } else if covered == 12 {
+        problems.push(String::from("no chunks"));
+    }
+    problems
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let jpeg = include_bytes!("../assets/test/1.jpeg");
+        assert!(verify(jpeg).is_valid());
+        assert!(!verify(&jpeg[..jpeg.len() / 2]).is_valid());
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::new(8, 8))
+            .write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png)
+            .expect("encode png");
+        assert!(verify(&png).is_valid(), "{:?}", verify(&png));
+        // A FLIPPED BIT IN IHDR
+        let mut flipped = png.clone();
+        flipped[20] ^= 1;
+        let problems = verify(&flipped).problems;
+        assert!(problems.iter().any(|x| x.contains("CRC mismatch")), "{:?}", problems);
+        let truncated = verify(&png[..png.len() - 6]);
+        assert!(truncated.problems[0].starts_with("no IEND chunk"), "{:?}", truncated);
+        assert_eq!(verify(b"not an image").format, None);
+    }
+}
@@ -0,0 +1,421 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Frame accurate seeking in video files, via `ffprobe` and `ffmpeg`
+//! subprocesses (as the `ffmpeg` decoder), e.g. for thumbnails at any
+//! timestamp of a long video, without decoding (or holding) every frame
+//! as a `VideoBuffer` would.
+//!
+//! Seeks use the container’s index to find the keyframe at or before the
+//! timestamp, then decode (and drop) the frames from it to the one asked
+//! for. Frames are numbered by the stream’s average frame rate, which is
+//! exact for constant frame rate streams only.
+//!
+//! Phone videos are typically stored sideways, with a display rotation
+//! (matrix) in the container; by the `RotationPolicy`, frames are either
+//! rotated upright, or kept as stored, with the rotation carried over to
+//! outputs (see `encode_rotated`), or dropped.
+//!
+//! Frames (e.g. of GIFs) are encoded to MP4 (H.264) or WebM (VP9) by
+//! `encode`, keeping each frame’s delay.
+use image::{DynamicImage, ImageFormat};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use crate::codec::gif::Frame;
+use crate::data::{VideoBuffer, Yuv420P};
+use crate::error::{ImagerError, Result};
+use crate::workspace::JobDir;
+
+/// Of an ffprobe rate, e.g. `"30000/1001"`; ffprobe gives numbers as
+/// strings, and `"0/0"` for unknown rates.
+pub(crate) fn parse_rate(value: &serde_json::Value) -> Option<(u64, u64)> {
+    let (num, den) = value.as_str()?.split_once('/')?;
+    let rate = (num.parse::<u64>().ok()?, den.parse::<u64>().ok()?);
+    (rate.0 > 0 && rate.1 > 0).then_some(rate)
+}
+
+/// Of an ffprobe duration, e.g. `"10.010000"`.
+pub(crate) fn parse_seconds(value: &serde_json::Value) -> Option<Duration> {
+    let seconds = value.as_str()?.parse::<f64>().ok().filter(|x| *x >= 0.0)?;
+    Some(Duration::from_secs_f64(seconds))
+}
+
+/// Of a stream’s display matrix, or legacy `rotate` tag: clockwise, to the
+/// nearest quarter turn (0, 90, 180 or 270 degrees).
+pub(crate) fn parse_rotation(stream: &serde_json::Value) -> u16 {
+    // THE DISPLAY MATRIX’S IS ANTICLOCKWISE; THE LEGACY TAG’S CLOCKWISE
+    let rotation = stream["side_data_list"]
+        .as_array()
+        .and_then(|x| x.iter().find_map(|x| x["rotation"].as_f64()))
+        .map(|x| -x)
+        .or_else(|| stream["tags"]["rotate"].as_str()?.parse().ok())
+        .unwrap_or(0.0);
+    ((rotation / 90.0).round() as i64 * 90).rem_euclid(360) as u16
+}
+
+/// What to do with a stream’s display rotation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RotationPolicy {
+    /// Rotate the frames upright, as players display them.
+    #[default]
+    Apply,
+    /// Keep the frames as stored, and carry the rotation over to outputs
+    /// (see `VideoFile::output_rotation`), e.g. to re-encode losslessly.
+    Propagate,
+    /// Keep the frames as stored, and drop the rotation.
+    Ignore,
+}
+
+impl std::str::FromStr for RotationPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "apply" => Ok(RotationPolicy::Apply),
+            "propagate" => Ok(RotationPolicy::Propagate),
+            "ignore" => Ok(RotationPolicy::Ignore),
+            _ => Err(format!("Unknown rotation policy {}", s)),
+        }
+    }
+}
+
+/// Of the first video stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoFile {
+    path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    /// Frames per second, as a ratio, e.g. `(30000, 1001)`.
+    pub frame_rate: (u64, u64),
+    pub duration: Option<Duration>,
+    /// As the container reports it, if it does.
+    pub frame_count: Option<u64>,
+    /// Clockwise, to display the frames as stored (see `parse_rotation`).
+    pub rotation: u16,
+    policy: RotationPolicy,
+}
+
+impl VideoFile {
+    /// Probes the file’s first video stream.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let output = Command::new("ffprobe")
+            .args(["-v", "error", "-select_streams", "v:0", "-of", "json", "-show_entries"])
+            .arg(concat!(
+                "stream=width,height,avg_frame_rate,r_frame_rate,nb_frames,duration",
+                ":stream_tags=rotate:stream_side_data=rotation:format=duration"
+            ))
+            .arg(path)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .map_err(|x| ImagerError::decode(format!("failed to run ffprobe: {}", x)))?;
+        if !output.status.success() {
+            return Err(ImagerError::decode(format!("ffprobe failed ({})", output.status)));
+        }
+        let probe = serde_json::from_slice(&output.stdout).map_err(ImagerError::decode)?;
+        Self::from_probe(path, &probe)
+    }
+    /// Of `ffprobe -of json` output.
+    fn from_probe(path: &Path, probe: &serde_json::Value) -> Result<Self> {
+        let stream = &probe["streams"][0];
+        let dimension = |key: &str| stream[key].as_u64().filter(|x| *x > 0).map(|x| x as u32);
+        let (Some(width), Some(height)) = (dimension("width"), dimension("height")) else {
+            return Err(ImagerError::decode(format!("{}: no video stream", path.display())));
+        };
+        let frame_rate = parse_rate(&stream["avg_frame_rate"])
+            .or_else(|| parse_rate(&stream["r_frame_rate"]))
+            .ok_or_else(|| ImagerError::decode(format!("{}: unknown frame rate", path.display())))?;
+        let duration = parse_seconds(&stream["duration"]).or_else(|| parse_seconds(&probe["format"]["duration"]));
+        Ok(VideoFile {
+            path: path.to_owned(),
+            width,
+            height,
+            frame_rate,
+            duration,
+            frame_count: stream["nb_frames"].as_str().and_then(|x| x.parse().ok()),
+            rotation: parse_rotation(stream),
+            policy: RotationPolicy::default(),
+        })
+    }
+    /// By default, `RotationPolicy::Apply`.
+    pub fn rotation_policy(mut self, policy: RotationPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// Of the decoded frames, i.e. swapped if they’re rotated by a quarter
+    /// turn.
+    pub fn frame_dimensions(&self) -> (u32, u32) {
+        match (self.policy, self.rotation) {
+            (RotationPolicy::Apply, 90 | 270) => (self.height, self.width),
+            _ => (self.width, self.height),
+        }
+    }
+    /// For `encode_rotated`, of the decoded frames: the stream’s, if
+    /// `RotationPolicy::Propagate`, else none.
+    pub fn output_rotation(&self) -> u16 {
+        match self.policy {
+            RotationPolicy::Propagate => self.rotation,
+            RotationPolicy::Apply | RotationPolicy::Ignore => 0,
+        }
+    }
+    /// Of the frame, from the start of the file.
+    pub fn frame_time(&self, frame: u64) -> Duration {
+        let (num, den) = self.frame_rate;
+        Duration::from_secs_f64(frame as f64 * den as f64 / num as f64)
+    }
+    /// The frame at or after the timestamp.
+    pub fn seek_time(&self, timestamp: Duration) -> Result<DynamicImage> {
+        if let Some(duration) = self.duration.filter(|x| timestamp > *x) {
+            return Err(ImagerError::InvalidInput(format!(
+                "{:?} is past the end of the video ({:?})",
+                timestamp, duration
+            )));
+        }
+        self.decode_at(timestamp)
+    }
+    /// The frame of the number (from 0).
+    pub fn seek_frame(&self, frame: u64) -> Result<DynamicImage> {
+        if let Some(count) = self.frame_count.filter(|x| frame >= *x) {
+            return Err(ImagerError::InvalidInput(format!(
+                "frame {} is past the end of the video ({} frames)",
+                frame, count
+            )));
+        }
+        // A QUARTER FRAME EARLY, SO ROUNDING CAN’T SKIP TO THE NEXT ONE
+        let (num, den) = self.frame_rate;
+        let early = Duration::from_secs_f64(0.25 * den as f64 / num as f64);
+        self.decode_at(self.frame_time(frame).saturating_sub(early))
+    }
+    /// The frames of the numbers, e.g. for VMAF, each by a seek.
+    pub fn frames(&self, frames: &[u64]) -> Result<VideoBuffer> {
+        let frames = frames
+            .iter()
+            .map(|frame| Yuv420P::from_image(&self.seek_frame(*frame)?))
+            .collect::<Result<Vec<_>>>()?;
+        VideoBuffer::from_frames(frames)
+    }
+    /// Of the input; ffmpeg rotates upright by default.
+    fn rotation_args(&self) -> &'static [&'static str] {
+        match self.policy {
+            RotationPolicy::Apply => &[],
+            RotationPolicy::Propagate | RotationPolicy::Ignore => &["-noautorotate"],
+        }
+    }
+    fn decode_at(&self, timestamp: Duration) -> Result<DynamicImage> {
+        // -ss BEFORE -i SEEKS THE INPUT, BY THE INDEX, THEN DECODES UP TO THE TIMESTAMP
+        let output = Command::new("ffmpeg")
+            .args(["-nostdin", "-v", "error", "-ss"])
+            .arg(format!("{:.6}", timestamp.as_secs_f64()))
+            .args(self.rotation_args())
+            .arg("-i")
+            .arg(&self.path)
+            .args(["-frames:v", "1", "-f", "image2pipe", "-vcodec", "png", "pipe:1"])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .map_err(|x| ImagerError::decode(format!("failed to run ffmpeg: {}", x)))?;
+        if !output.status.success() {
+            return Err(ImagerError::decode(format!("ffmpeg failed ({})", output.status)));
+        }
+        if output.stdout.is_empty() {
+            return Err(ImagerError::decode(format!("no frame at {:?}", timestamp)));
+        }
+        Ok(::image::load_from_memory_with_format(&output.stdout, ImageFormat::Png)?)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// ENCODING
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoFormat {
+    /// H.264, for every browser.
+    Mp4,
+    /// VP9, smaller.
+    Webm,
+}
+
+impl VideoFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            VideoFormat::Mp4 => "mp4",
+            VideoFormat::Webm => "webm",
+        }
+    }
+    /// Of the codec, for ffmpeg.
+    fn codec_args(&self) -> &'static [&'static str] {
+        match self {
+            VideoFormat::Mp4 => &[
+                "-c:v",
+                "libx264",
+                "-crf",
+                "23",
+                "-preset",
+                "slow",
+                "-movflags",
+                "+faststart",
+            ],
+            VideoFormat::Webm => &["-c:v", "libvpx-vp9", "-crf", "33", "-b:v", "0", "-row-mt", "1"],
+        }
+    }
+}
+
+impl std::str::FromStr for VideoFormat {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mp4" => Ok(VideoFormat::Mp4),
+            "webm" => Ok(VideoFormat::Webm),
+            _ => Err(format!("Unknown video format {}", s)),
+        }
+    }
+}
+
+/// An `ffconcat` list of the frames (as `frame-<index>.png`), by their
+/// delays.
+fn concat_list(frames: &[Frame]) -> String {
+    let mut list = String::from("ffconcat version 1.0\n");
+    for (ix, frame) in frames.iter().enumerate() {
+        list.push_str(&format!(
+            "file 'frame-{}.png'\nduration {:.3}\n",
+            ix,
+            frame.delay.as_secs_f64()
+        ));
+    }
+    // THE LAST DURATION ONLY COUNTS IF ITS FILE IS REPEATED
+    list.push_str(&format!("file 'frame-{}.png'\n", frames.len().saturating_sub(1)));
+    list
+}
+
+/// Of the frames (of the same size, without alpha), via an `ffmpeg`
+/// subprocess, at a variable frame rate.
+pub fn encode(frames: &[Frame], format: VideoFormat) -> Result<Vec<u8>> {
+    encode_rotated(frames, format, 0)
+}
+
+/// Of the input, for the output’s display matrix (anticlockwise); needs
+/// ffmpeg 6.1 or later.
+fn display_rotation_args(rotation: u16) -> Vec<String> {
+    match rotation % 360 {
+        0 => Vec::new(),
+        rotation => vec![String::from("-display_rotation:v:0"), format!("{}", -i32::from(rotation))],
+    }
+}
+
+/// Like `encode`, with a display rotation (clockwise) in the output, e.g.
+/// of a `VideoFile`’s frames (see `VideoFile::output_rotation`).
+pub fn encode_rotated(frames: &[Frame], format: VideoFormat, rotation: u16) -> Result<Vec<u8>> {
+    if frames.is_empty() {
+        return Err(ImagerError::InvalidInput(String::from("no frames")));
+    }
+    let job_dir = JobDir::new("video")?;
+    for (ix, frame) in frames.iter().enumerate() {
+        let path = job_dir.join(format!("frame-{}.png", ix));
+        frame
+            .image
+            .save_with_format(&path, ImageFormat::Png)
+            .map_err(|x| ImagerError::Encode(x.to_string()))?;
+    }
+    let list_path = job_dir.join("frames.ffconcat");
+    std::fs::write(&list_path, concat_list(frames)).map_err(ImagerError::io(&list_path))?;
+    let output_path = job_dir.join(format!("output.{}", format.extension()));
+    let status = Command::new("ffmpeg")
+        .args(["-nostdin", "-v", "error"])
+        .args(display_rotation_args(rotation))
+        .args(["-f", "concat", "-safe", "0", "-i"])
+        .arg(&list_path)
+        // YUV 4:2:0 NEEDS EVEN DIMENSIONS
+        .args([
+            "-vf",
+            "pad=ceil(iw/2)*2:ceil(ih/2)*2,format=yuv420p",
+            "-fps_mode",
+            "vfr",
+        ])
+        .args(format.codec_args())
+        .arg(&output_path)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|x| ImagerError::Encode(format!("failed to run ffmpeg: {}", x)))?;
+    if !status.success() {
+        return Err(ImagerError::Encode(format!("ffmpeg failed ({})", status)));
+    }
+    std::fs::read(&output_path).map_err(ImagerError::io(&output_path))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_probe() {
+        let probe = serde_json::json!({
+            "streams": [{
+                "width": 1920,
+                "height": 1080,
+                "avg_frame_rate": "30000/1001",
+                "r_frame_rate": "30000/1001",
+                "nb_frames": "300"
+            }],
+            "format": {"duration": "10.010000"}
+        });
+        let video = VideoFile::from_probe(Path::new("clip.mp4"), &probe).expect("probe");
+        assert_eq!(
+            (video.width, video.height, video.frame_rate),
+            (1920, 1080, (30000, 1001))
+        );
+        assert_eq!(video.duration, Some(Duration::from_millis(10010)));
+        assert_eq!(video.frame_time(30), Duration::from_millis(1001));
+        // PAST THE END, BEFORE RUNNING FFMPEG
+        assert!(matches!(video.seek_frame(300), Err(ImagerError::InvalidInput(_))));
+        assert!(matches!(
+            video.seek_time(Duration::from_secs(11)),
+            Err(ImagerError::InvalidInput(_))
+        ));
+        let unknown = serde_json::json!({"streams": [{"width": 64, "height": 64, "avg_frame_rate": "0/0"}]});
+        assert!(VideoFile::from_probe(Path::new("x.mkv"), &unknown).is_err());
+        let audio = serde_json::json!({"streams": []});
+        assert!(VideoFile::from_probe(Path::new("x.m4a"), &audio).is_err());
+        let frame = |delay| Frame {
+            image: image::RgbaImage::new(2, 2),
+            delay: Duration::from_millis(delay),
+        };
+        assert_eq!(
+            concat_list(&[frame(50), frame(120)]),
+            "ffconcat version 1.0\nfile 'frame-0.png'\nduration 0.050\nfile 'frame-1.png'\nduration 0.120\n\
+             file 'frame-1.png'\n"
+        );
+        assert_eq!("WebM".parse(), Ok(VideoFormat::Webm));
+    }
+
+    #[test]
+    fn test_rotation() {
+        let probe = serde_json::json!({
+            "streams": [{
+                "width": 1920,
+                "height": 1080,
+                "avg_frame_rate": "30/1",
+                "side_data_list": [{"side_data_type": "Display Matrix", "rotation": -90}]
+            }]
+        });
+        let video = VideoFile::from_probe(Path::new("phone.mp4"), &probe).expect("probe");
+        assert_eq!((video.rotation, video.frame_dimensions(), video.output_rotation()), (90, (1080, 1920), 0));
+        assert!(video.rotation_args().is_empty());
+        let video = video.rotation_policy(RotationPolicy::Propagate);
+        assert_eq!((video.frame_dimensions(), video.output_rotation()), ((1920, 1080), 90));
+        assert_eq!(video.rotation_args(), ["-noautorotate"]);
+        let video = video.rotation_policy("ignore".parse().expect("policy"));
+        assert_eq!((video.frame_dimensions(), video.output_rotation()), ((1920, 1080), 0));
+        let legacy = serde_json::json!({"tags": {"rotate": "270"}});
+        assert_eq!(parse_rotation(&legacy), 270);
+        assert_eq!(parse_rotation(&serde_json::json!({"side_data_list": [{"rotation": 180.0}]})), 180);
+        assert_eq!(display_rotation_args(90), ["-display_rotation:v:0", "-90"]);
+        assert!(display_rotation_args(0).is_empty());
+    }
+}
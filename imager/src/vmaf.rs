@@ -24,6 +24,18 @@ lazy_static! {
     static ref VMAF_LOCK: Mutex<()> = { Mutex::new(()) };
 }
 
+/// Overrides the model path, e.g. so sandboxed workers (that can’t write
+/// the model out) share their parent’s.
+pub const MODEL_VAR: &str = "IMAGER_VMAF_MODEL";
+
+/// The 4K model file, written to a temporary directory on first use.
+pub fn model_path() -> PathBuf {
+    match std::env::var_os(MODEL_VAR) {
+        Some(path) => PathBuf::from(path),
+        None => vmaf_sys::extras::get_4k_model_path(),
+    }
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 // VMAF CALLBACK
 ///////////////////////////////////////////////////////////////////////////////
@@ -100,7 +112,7 @@ pub unsafe fn vmaf_controller<'a>(
 
     // SETTINGS
     let mut vmaf_score = 0.0;
-    let model_path = vmaf_sys::extras::get_4k_model_path()
+    let model_path = model_path()
         .to_str()
         .expect("PathBuf to str failed")
         .to_owned();
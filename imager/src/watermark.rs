@@ -0,0 +1,210 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Robust invisible watermarks, for tracking where outputs end up.
+//!
+//! A 32 bit ID (plus a 16 bit check) is embedded in the luma of every 8x8
+//! block, in the order of two low-frequency AC coefficients of its DCT:
+//! these survive JPEG and WebP quantization, while marked images stay
+//! around 40dB PSNR from their source.
+//! Each bit is repeated over many blocks and recovered by a (soft)
+//! majority vote. Blocks are aligned to the top left corner, so marks
+//! survive re-encoding but not cropping or resizing.
+use image::{DynamicImage, GenericImageView, Rgb, Rgba};
+
+const BLOCK: usize = 8;
+type Block = [[f32; BLOCK]; BLOCK];
+const ID_BITS: usize = 32;
+const PAYLOAD_BITS: usize = ID_BITS + 16;
+/// The coefficient pair, as `(row, column)` DCT indices.
+const PAIR: [(usize, usize); 2] = [(1, 1), (0, 2)];
+/// The minimum gap enforced between the pair.
+const STRENGTH: f32 = 20.0;
+/// The fraction of blocks that must agree with the decoded payload.
+const MIN_CONFIDENCE: f64 = 0.6;
+
+/// Sources need at least this many 8x8 blocks (e.g. 128x96 pixels) for
+/// marks to be reliably detected.
+pub const MIN_BLOCKS: usize = PAYLOAD_BITS * 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Detection {
+    pub id: u32,
+    /// The fraction of blocks agreeing with the payload, from `0.6` to `1`.
+    pub confidence: f64,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// DCT
+///////////////////////////////////////////////////////////////////////////////
+
+/// Orthonormal 8 point DCT-II basis, `[frequency][sample]`.
+fn basis() -> Block {
+    let mut output = [[0.0; BLOCK]; BLOCK];
+    for (k, row) in output.iter_mut().enumerate() {
+        let scale = if k == 0 { (1.0 / 8.0f32).sqrt() } else { 0.5 };
+        for (n, x) in row.iter_mut().enumerate() {
+            let angle = std::f32::consts::PI * (2 * n + 1) as f32 * k as f32 / 16.0;
+            *x = scale * angle.cos();
+        }
+    }
+    output
+}
+
+/// A single DCT coefficient of a block.
+fn coefficient(block: &Block, basis: &Block, (u, v): (usize, usize)) -> f32 {
+    let mut sum = 0.0;
+    for (y, row) in block.iter().enumerate() {
+        for (x, value) in row.iter().enumerate() {
+            sum += basis[u][y] * basis[v][x] * value;
+        }
+    }
+    sum
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PAYLOAD
+///////////////////////////////////////////////////////////////////////////////
+
+fn check(id: u32) -> u16 {
+    (crc32fast::hash(&id.to_le_bytes()) & 0xFFFF) as u16
+}
+
+fn payload(id: u32) -> [bool; PAYLOAD_BITS] {
+    let word = (u64::from(check(id)) << ID_BITS) | u64::from(id);
+    let mut bits = [false; PAYLOAD_BITS];
+    for (ix, bit) in bits.iter_mut().enumerate() {
+        *bit = (word >> ix) & 1 == 1;
+    }
+    bits
+}
+
+/// The luma blocks of an image, with their top left corners.
+fn blocks(image: &DynamicImage) -> Vec<((u32, u32), Block)> {
+    let rgb = image.to_rgb8();
+    let (width, height) = rgb.dimensions();
+    let mut output = Vec::new();
+    for by in (0..height / BLOCK as u32).map(|x| x * BLOCK as u32) {
+        for bx in (0..width / BLOCK as u32).map(|x| x * BLOCK as u32) {
+            let mut block = [[0.0; BLOCK]; BLOCK];
+            for (y, row) in block.iter_mut().enumerate() {
+                for (x, value) in row.iter_mut().enumerate() {
+                    let Rgb([r, g, b]) = *rgb.get_pixel(bx + x as u32, by + y as u32);
+                    *value = 0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32;
+                }
+            }
+            output.push(((bx, by), block));
+        }
+    }
+    output
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// EMBED & DETECT
+///////////////////////////////////////////////////////////////////////////////
+
+/// Marks an image with the given ID. Alpha is left untouched.
+pub fn embed(image: &DynamicImage, id: u32) -> DynamicImage {
+    let basis = basis();
+    let bits = payload(id);
+    let mut output = image.to_rgba8();
+    for (ix, ((bx, by), block)) in blocks(image).into_iter().enumerate() {
+        let gap = coefficient(&block, &basis, PAIR[0]) - coefficient(&block, &basis, PAIR[1]);
+        // ONES: FIRST ABOVE SECOND; ZEROS: THE OTHER WAY ROUND
+        let target = if bits[ix % PAYLOAD_BITS] { STRENGTH } else { -STRENGTH };
+        let delta = if target > 0.0 {
+            (target - gap).max(0.0)
+        } else {
+            (target - gap).min(0.0)
+        };
+        if delta == 0.0 {
+            continue;
+        }
+        for y in 0..BLOCK {
+            for x in 0..BLOCK {
+                let [(u1, v1), (u2, v2)] = PAIR;
+                let pattern = basis[u1][y] * basis[v1][x] - basis[u2][y] * basis[v2][x];
+                // THE SAME OFFSET ON EVERY CHANNEL SHIFTS LUMA ALONE
+                let offset = delta / 2.0 * pattern;
+                let Rgba([r, g, b, a]) = *output.get_pixel(bx + x as u32, by + y as u32);
+                let shift = |c: u8| (c as f32 + offset).round().clamp(0.0, 255.0) as u8;
+                output.put_pixel(bx + x as u32, by + y as u32, Rgba([shift(r), shift(g), shift(b), a]));
+            }
+        }
+    }
+    if image.color().has_alpha() {
+        DynamicImage::ImageRgba8(output)
+    } else {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(output).to_rgb8())
+    }
+}
+
+/// Recovers the ID of a marked image, if any.
+pub fn detect(image: &DynamicImage) -> Option<Detection> {
+    let basis = basis();
+    let gaps = blocks(image)
+        .into_iter()
+        .map(|(_, block)| coefficient(&block, &basis, PAIR[0]) - coefficient(&block, &basis, PAIR[1]))
+        .collect::<Vec<_>>();
+    if gaps.len() < PAYLOAD_BITS {
+        return None;
+    }
+    // SOFT VOTES, CLAMPED SO A FEW STRONG EDGES CAN’T OUTVOTE THE REST
+    let mut votes = [0.0f32; PAYLOAD_BITS];
+    for (ix, gap) in gaps.iter().enumerate() {
+        votes[ix % PAYLOAD_BITS] += gap.clamp(-STRENGTH, STRENGTH);
+    }
+    let word = votes
+        .iter()
+        .enumerate()
+        .filter(|(_, vote)| **vote > 0.0)
+        .fold(0u64, |word, (ix, _)| word | (1 << ix));
+    let id = (word & 0xFFFF_FFFF) as u32;
+    if (word >> ID_BITS) as u16 != check(id) {
+        return None;
+    }
+    let bits = payload(id);
+    let agreeing = gaps
+        .iter()
+        .enumerate()
+        .filter(|(ix, gap)| (**gap > 0.0) == bits[ix % PAYLOAD_BITS])
+        .count();
+    let confidence = agreeing as f64 / gaps.len() as f64;
+    if confidence < MIN_CONFIDENCE {
+        return None;
+    }
+    Some(Detection { id, confidence })
+}
+
+/// Whether an image of the given resolution is large enough to be
+/// reliably marked.
+pub fn can_embed((width, height): (u32, u32)) -> bool {
+    (width as usize / BLOCK) * (height as usize / BLOCK) >= MIN_BLOCKS
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_survives_lossy_encode() {
+        let source = image::load_from_memory(include_bytes!("../assets/test/1.jpeg"))
+            .expect("decode test image")
+            .thumbnail(480, 480);
+        assert!(detect(&source).is_none());
+        let marked = embed(&source, 0xC0FFEE);
+        let mut jpeg = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 50)
+            .encode_image(&marked)
+            .expect("encode jpeg");
+        let decoded = [
+            image::load_from_memory(&jpeg).expect("decode jpeg"),
+            #[cfg(not(feature = "pure-rust"))]
+            crate::codec::webp::decode::decode(&crate::codec::webp::encode::lossy::encode(&marked, 75.0))
+                .expect("decode webp"),
+        ];
+        for image in decoded.iter() {
+            assert_eq!(detect(image).map(|x| x.id), Some(0xC0FFEE));
+        }
+    }
+}
@@ -0,0 +1,232 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Completion notifications: the final (JSON) report is POSTed to a
+//! webhook URL, e.g. of an asset management system, via `curl`.
+//!
+//! With a secret (`IMAGER_WEBHOOK_SECRET`), requests carry the
+//! HMAC-SHA256 of the body as `X-Imager-Signature-256: sha256=<hex>`,
+//! which receivers should check (in constant time) before trusting it.
+//!
+//! Webhooks of untrusted URLs (e.g. server job callbacks) are `checked`:
+//! their host must be allowed, or resolve to public addresses only, which
+//! `curl` is then pinned to, so they can’t reach internal services.
+use std::io::Write;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::process::{Command, Stdio};
+
+use crate::trace::TraceContext;
+use imager_core::digest::{hex, hmac_sha256};
+
+/// The environment variable of the signing secret.
+pub const SECRET_VAR: &str = "IMAGER_WEBHOOK_SECRET";
+
+pub const SIGNATURE_HEADER: &str = "X-Imager-Signature-256";
+
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    pub url: String,
+    pub secret: Option<Vec<u8>>,
+    /// Extra request headers.
+    pub headers: Vec<(String, String)>,
+    /// Pins the URL’s host to an address, as `host:port:address` (see
+    /// `curl --resolve`).
+    pub resolve: Option<String>,
+}
+
+impl Webhook {
+    /// A webhook signed with the secret of `IMAGER_WEBHOOK_SECRET`, if set.
+    pub fn from_env(url: &str) -> Self {
+        Webhook {
+            url: url.to_owned(),
+            secret: std::env::var_os(SECRET_VAR)
+                .filter(|x| !x.is_empty())
+                .map(|x| x.to_string_lossy().into_owned().into_bytes()),
+            headers: Vec::new(),
+            resolve: None,
+        }
+    }
+    /// A webhook of an untrusted URL: its host must be one of
+    /// `allowed_hosts` or, without any, resolve to public addresses only.
+    pub fn checked(url: &str, allowed_hosts: &[String]) -> Result<Self, String> {
+        let invalid = || format!("invalid callback {}", url);
+        let (host, port) = authority(url).ok_or_else(invalid)?;
+        if !allowed_hosts.is_empty() {
+            if !allowed_hosts.iter().any(|x| x.eq_ignore_ascii_case(host)) {
+                return Err(format!("callback host {} isn’t allowed", host));
+            }
+            return Ok(Webhook::from_env(url));
+        }
+        let addresses = (host.trim_start_matches('[').trim_end_matches(']'), port)
+            .to_socket_addrs()
+            .map_err(|e| format!("failed to resolve {}: {}", host, e))?
+            .map(|x| x.ip())
+            .collect::<Vec<_>>();
+        let address = match addresses.first() {
+            Some(x) if addresses.iter().all(|x| is_public(*x)) => *x,
+            _ => return Err(format!("callback host {} isn’t public", host)),
+        };
+        let address = match address {
+            IpAddr::V4(x) => x.to_string(),
+            IpAddr::V6(x) => format!("[{}]", x),
+        };
+        Ok(Webhook {
+            resolve: Some(format!("{}:{}:{}", host, port, address)),
+            ..Webhook::from_env(url)
+        })
+    }
+    /// Passes the trace context on, as `traceparent` (and `tracestate`).
+    pub fn traced(mut self, context: &TraceContext) -> Self {
+        self.headers.extend(context.headers());
+        self
+    }
+    /// The value of the signature header for `body`.
+    pub fn signature(&self, body: &[u8]) -> Option<String> {
+        let secret = self.secret.as_ref()?;
+        Some(format!("sha256={}", hex(&hmac_sha256(secret, body))))
+    }
+    /// POSTs the JSON `body`, retrying transient failures a few times.
+    pub fn post(&self, body: &[u8]) -> Result<(), String> {
+        let mut command = Command::new("curl");
+        command
+            .args(["--silent", "--show-error", "--fail", "--retry", "3"])
+            .args(["--max-time", "30", "--output", "/dev/null"])
+            .args(["-X", "POST", "-H", "Content-Type: application/json"])
+            .args(["--data-binary", "@-"]);
+        if let Some(signature) = self.signature(body) {
+            command
+                .arg("-H")
+                .arg(format!("{}: {}", SIGNATURE_HEADER, signature));
+        }
+        for (name, value) in &self.headers {
+            command.arg("-H").arg(format!("{}: {}", name, value));
+        }
+        if let Some(resolve) = &self.resolve {
+            command.args(["--proto", "=http,https", "--resolve"]).arg(resolve);
+        }
+        let mut child = command
+            .arg("--")
+            .arg(&self.url)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("failed to run curl: {}", e))?;
+        child
+            .stdin
+            .take()
+            .expect("piped stdin")
+            .write_all(body)
+            .map_err(|e| e.to_string())?;
+        let result = child.wait_with_output().map_err(|e| e.to_string())?;
+        if !result.status.success() {
+            return Err(format!(
+                "webhook {} failed: {}",
+                self.url,
+                String::from_utf8_lossy(&result.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The host (IPv6 addresses in brackets) and port of an HTTP(S) URL;
+/// none with credentials, which `curl` would take as the host otherwise.
+fn authority(url: &str) -> Option<(&str, u16)> {
+    let (rest, default_port) = match url.split_once("://")? {
+        ("http", rest) => (rest, 80),
+        ("https", rest) => (rest, 443),
+        _ => return None,
+    };
+    let authority = &rest[..rest.find(['/', '?', '#']).unwrap_or(rest.len())];
+    let (host, port) = match authority.rfind(':') {
+        Some(ix) if !authority[ix..].contains(']') => (&authority[..ix], Some(&authority[ix + 1..])),
+        _ => (authority, None),
+    };
+    let name = |x: &str| x.bytes().all(|x| x.is_ascii_alphanumeric() || x == b'-' || x == b'.');
+    let valid = match host.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+        Some(address) => address.parse::<std::net::Ipv6Addr>().is_ok(),
+        None => !host.is_empty() && name(host),
+    };
+    let port = match port {
+        Some(x) => x.parse().ok()?,
+        None => default_port,
+    };
+    Some((host, port)).filter(|_| valid)
+}
+
+/// Whether the address is globally routable, i.e. not loopback, private,
+/// link-local (e.g. cloud metadata services), shared, or reserved.
+fn is_public(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(x) => {
+            let [a, b, ..] = x.octets();
+            !(x.is_private()
+                || x.is_loopback()
+                || x.is_link_local()
+                || x.is_unspecified()
+                || x.is_broadcast()
+                || x.is_multicast()
+                || x.is_documentation()
+                || a == 0
+                || a >= 240
+                || (a == 100 && b & 0xc0 == 64))
+        }
+        IpAddr::V6(x) => match x.to_ipv4_mapped() {
+            Some(x) => is_public(IpAddr::V4(x)),
+            None => !(x.is_loopback()
+                || x.is_unspecified()
+                || x.is_multicast()
+                || x.is_unique_local()
+                || x.is_unicast_link_local()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_signature() {
+        let webhook = Webhook {
+            url: String::from("https://example.com/hook"),
+            secret: Some(b"Jefe".to_vec()),
+            headers: Vec::new(),
+            resolve: None,
+        };
+        assert_eq!(
+            webhook.signature(b"what do ya want for nothing?").as_deref(),
+            Some("sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843")
+        );
+        let unsigned = Webhook { secret: None, ..webhook };
+        assert_eq!(unsigned.signature(b"{}"), None);
+    }
+
+    #[test]
+    fn test_checked() {
+        let allowed = [String::from("hooks.example.com")];
+        let resolve = |url: &str| Webhook::checked(url, &[]).map(|x| x.resolve);
+        assert_eq!(resolve("https://93.184.216.34/hook"), Ok(Some(String::from("93.184.216.34:443:93.184.216.34"))));
+        assert_eq!(resolve("http://[2606:4700::1]:8080/"), Ok(Some(String::from("[2606:4700::1]:8080:[2606:4700::1]"))));
+        for url in [
+            "http://127.0.0.1/",
+            "http://localhost:8080/",
+            "http://10.0.0.1/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://100.64.0.1/",
+            "http://[::1]/",
+            "http://[::ffff:192.168.0.1]/",
+            "http://[fd00::1]/",
+            "http://93.184.216.34@127.0.0.1/",
+            "ftp://93.184.216.34/",
+            "http://93.184.216.34:99999/",
+        ] {
+            assert!(resolve(url).is_err(), "{}", url);
+        }
+        let webhook = Webhook::checked("https://Hooks.Example.com:8443/done", &allowed).expect("allowed");
+        assert_eq!(webhook.resolve, None);
+        assert!(Webhook::checked("https://hooks.example.com.evil.net/", &allowed).is_err());
+        assert!(Webhook::checked("http://127.0.0.1/", &allowed).is_err());
+    }
+}
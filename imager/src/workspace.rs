@@ -0,0 +1,170 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+//! Scratch space on disk, for the stages that need it (e.g. the files of
+//! external tools, such as `c2patool`, or of multi-pass encodes).
+//!
+//! Every job gets its own directory (a `JobDir`), under the root, with a
+//! random name, created exclusively and only accessible to the user, so
+//! concurrent jobs (and processes, e.g. server workers) sharing a root
+//! never collide, and other users can neither guess nor read them. Job
+//! directories are removed when dropped, whether or not the job succeeded.
+//!
+//! The root is that given to `set_root` (i.e. `--temp-dir`), else the
+//! `IMAGER_TMPDIR` environment variable, else `imager-<uid>` in the
+//! system’s temp directory. On Unix, it’s created with mode 0700, and a
+//! root that isn’t a directory of the user’s only (e.g. one another user
+//! created first in a shared `/tmp`, or a symlink) is refused.
+use lazy_static::lazy_static;
+use rand::Rng;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use crate::error::{ImagerError, Result};
+
+lazy_static! {
+    static ref ROOT: RwLock<Option<PathBuf>> = RwLock::new(None);
+}
+
+/// For every job directory created after.
+pub fn set_root(root: impl Into<PathBuf>) {
+    *ROOT.write().expect("workspace lock") = Some(root.into());
+}
+
+pub fn root() -> PathBuf {
+    let configured = ROOT.read().expect("workspace lock").clone();
+    configured
+        .or_else(|| std::env::var_os("IMAGER_TMPDIR").map(PathBuf::from))
+        .unwrap_or_else(|| std::env::temp_dir().join(default_root_name()))
+}
+
+/// Per user, so users don’t share (and contend for) one root.
+#[cfg(unix)]
+fn default_root_name() -> String {
+    format!("imager-{}", unsafe { libc::getuid() })
+}
+
+#[cfg(not(unix))]
+fn default_root_name() -> String {
+    String::from("imager")
+}
+
+/// Creates the root (and its parents) if need be, then checks it’s a
+/// directory of the user’s only.
+#[cfg(unix)]
+fn prepare_root(root: &Path) -> Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(root)
+        .map_err(ImagerError::io(root))?;
+    // NOT FOLLOWING SYMLINKS, WHICH ANOTHER USER MAY HAVE PLANTED
+    let meta = std::fs::symlink_metadata(root).map_err(ImagerError::io(root))?;
+    let unsafe_root = |problem: String| Err(ImagerError::InvalidInput(format!("{}: {}", root.display(), problem)));
+    if !meta.is_dir() {
+        return unsafe_root(String::from("the temp dir is not a directory (or is a symlink)"));
+    }
+    if meta.uid() != unsafe { libc::getuid() } {
+        return unsafe_root(String::from("the temp dir is owned by another user"));
+    }
+    let mode = meta.permissions().mode() & 0o777;
+    if mode & 0o077 != 0 {
+        return unsafe_root(format!(
+            "the temp dir is accessible to other users (mode {:o}); make it 700",
+            mode
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn prepare_root(root: &Path) -> Result<()> {
+    std::fs::create_dir_all(root).map_err(ImagerError::io(root))
+}
+
+/// A directory of the root, removed (with its contents) when dropped.
+#[derive(Debug)]
+pub struct JobDir {
+    path: PathBuf,
+}
+
+impl JobDir {
+    /// E.g. `c2pa-5f0c1d9e3a7b2468`, of the `label` and a random suffix.
+    pub fn new(label: &str) -> Result<Self> {
+        let root = root();
+        prepare_root(&root)?;
+        loop {
+            let path = root.join(format!("{}-{:016x}", label, rand::thread_rng().gen::<u64>()));
+            // EXCLUSIVELY, SO NEVER ONE ANOTHER PROCESS CREATED
+            let mut builder = std::fs::DirBuilder::new();
+            #[cfg(unix)]
+            std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+            match builder.create(&path) {
+                Ok(()) => return Ok(JobDir { path }),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(ImagerError::io(path)(e)),
+            }
+        }
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// Of a file of the directory.
+    pub fn join(&self, name: impl AsRef<Path>) -> PathBuf {
+        self.path.join(name)
+    }
+}
+
+impl Drop for JobDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rayon::prelude::*;
+
+    #[test]
+    fn test_job_dirs() {
+        let dirs = (0..16)
+            .into_par_iter()
+            .map(|_| JobDir::new("test").expect("job dir"))
+            .collect::<Vec<_>>();
+        let mut paths = dirs.iter().map(|x| x.path().to_owned()).collect::<Vec<_>>();
+        paths.sort();
+        paths.dedup();
+        assert_eq!(paths.len(), 16);
+        std::fs::write(dirs[0].join("stats.log"), "pass 1").expect("write");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |path: &Path| std::fs::metadata(path).expect("metadata").permissions().mode() & 0o777;
+            assert_eq!(mode(dirs[0].path()), 0o700);
+            assert_eq!(mode(&root()), 0o700);
+        }
+        drop(dirs);
+        assert!(paths.iter().all(|x| !x.exists()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unsafe_roots() {
+        use std::os::unix::fs::PermissionsExt;
+        let shared = std::env::temp_dir().join(format!("imager-test-shared-{}", std::process::id()));
+        std::fs::create_dir_all(&shared).expect("create dir");
+        std::fs::set_permissions(&shared, std::fs::Permissions::from_mode(0o777)).expect("chmod");
+        let error = prepare_root(&shared).unwrap_err();
+        assert!(matches!(&error, ImagerError::InvalidInput(x) if x.contains("accessible to other users")));
+        let link = shared.with_extension("link");
+        let _ = std::fs::remove_file(&link);
+        std::os::unix::fs::symlink(&shared, &link).expect("symlink");
+        assert!(prepare_root(&link).unwrap_err().to_string().contains("symlink"));
+        std::fs::set_permissions(&shared, std::fs::Permissions::from_mode(0o700)).expect("chmod");
+        assert!(prepare_root(&shared).is_ok());
+        std::fs::remove_file(&link).expect("remove link");
+        std::fs::remove_dir(&shared).expect("remove dir");
+    }
+}